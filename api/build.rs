@@ -38,17 +38,28 @@ async fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=../third_party/deno/core/lib.deno_core.d.ts");
 
     compile("api").await?;
+    compile("blob").await?;
     compile("builtin_root").await?;
+    compile("context").await?;
     compile("crud").await?;
     compile("datastore").await?;
     compile("filter").await?;
+    compile("geo").await?;
     compile("http").await?;
+    compile("ingest").await?;
     compile("kafka").await?;
+    compile("kv").await?;
+    compile("raw_sql").await?;
+    compile("rejections").await?;
     compile("request").await?;
     compile("routing").await?;
     compile("run").await?;
+    compile("seed").await?;
+    compile("socket").await?;
     compile("special").await?;
+    compile("testing").await?;
     compile("type_system").await?;
+    compile("upload").await?;
     compile("utils").await?;
     compile("policies").await?;
 
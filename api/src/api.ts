@@ -1,28 +1,78 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 export { crud } from "./crud.ts";
-export type { ChiselEntityClass } from "./crud.ts";
+export { crudPageToPage } from "./crud.ts";
+export type { ChiselEntityClass, CrudPage } from "./crud.ts";
+export { ingest, IngestError } from "./ingest.ts";
 export {
+    aggregate,
     AuthUser,
+    avgOf,
     ChiselCursor,
     ChiselEntity,
     chiselIterator,
+    collate,
+    ConflictError,
+    count,
+    countOf,
     labels,
+    locked,
     loggedInUser,
+    maxBytes,
+    maxOf,
+    minOf,
+    onDelete,
+    ownedBy,
+    renamedFrom,
+    sumOf,
+    timestamps,
     unique,
+    ValidationError,
+    version,
 } from "./datastore.ts";
-export type { Id } from "./datastore.ts";
-export type { ChiselEvent, EventHandler } from "./kafka.ts";
+export type { FixtureBundle } from "./testing.ts";
+export type { GeoPoint, NearOptions } from "./geo.ts";
+export type { KvEntry } from "./kv.ts";
+export type { StoredBlob } from "./blob.ts";
+export type { RawSqlParam } from "./raw_sql.ts";
+export type {
+    AggregateOptions,
+    BatchResults,
+    CacheOptions,
+    CachedQueries,
+    Decimal,
+    FieldNames,
+    Id,
+    Page,
+    PageParams,
+    RelationSelection,
+} from "./datastore.ts";
+export type {
+    ChiselEvent,
+    EventFilter,
+    EventHandler,
+    TopicOptions,
+    TopicSchema,
+} from "./kafka.ts";
 export { publishEvent } from "./kafka.ts";
-export { ChiselRequest, Params, Query } from "./request.ts";
+export type { AuthHook, AuthPrincipal } from "./http.ts";
+export { ChiselContext } from "./context.ts";
+export { ChiselRequest, MultipartPart, Params, Query } from "./request.ts";
 export { RouteMap } from "./routing.ts";
 export type {
     Handler,
     MiddlewareHandler,
     MiddlewareNext,
     ResponseLike,
+    RouteExampleInit,
+    SecurityHeaderOverrides,
 } from "./routing.ts";
+export type { SeedHandler } from "./seed.ts";
+export { upload } from "./upload.ts";
+export type { UploadHandler } from "./upload.ts";
+export { Chisel, ChiselSocket } from "./socket.ts";
+export type { SocketHandler } from "./socket.ts";
 export { getSecret, responseFromJson } from "./utils.ts";
 export type { JSONValue } from "./utils.ts";
 export type { ReqContext } from "./policies.ts";
-export { Action } from "./policies.ts";
+export { Action, Mask } from "./policies.ts";
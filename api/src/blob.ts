@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { requestContext } from "./datastore.ts";
+import { opAsync, opSync } from "./utils.ts";
+
+/** Blob returned by `Chisel.blob.get()`. */
+export type StoredBlob = {
+    data: Uint8Array;
+    /** Content type that the blob was stored with, if any. */
+    contentType: string | undefined;
+};
+
+type BlobJson = {
+    data: Uint8Array;
+    contentType: string | null;
+};
+
+/**
+ * Storage of binary objects ("blobs") in buckets, backed by a directory of
+ * the server (`--blob-dir`) or by an S3-compatible object store
+ * (`--blob-s3-endpoint`):
+ *
+ * ```typescript
+ * await Chisel.blob.put("avatars", "alice.png", bytes, {
+ *     contentType: "image/png",
+ * });
+ * const avatar = await Chisel.blob.get("avatars", "alice.png");
+ * const url = await Chisel.blob.signedUrl("avatars", "alice.png", {
+ *     expiresInS: 300,
+ * });
+ * ```
+ *
+ * Bucket names have 3 to 63 lowercase letters, digits and hyphens; keys are
+ * paths separated by slashes. Unlike `Chisel.kv`, the blobs are not part of
+ * the transaction of the request, so they are stored even if the request
+ * fails later. Sandboxed requests do not change the stored blobs: their puts
+ * and deletes have no effect.
+ *
+ * The `blobs` section of the policies restricts the buckets to some users,
+ * the same way as the `users` of a route:
+ *
+ * ```yaml
+ * blobs:
+ *   - bucket: avatars
+ *     users: ^admin@
+ * ```
+ */
+export const blob = {
+    /** Stores `data` (a string is encoded as UTF-8) under `key` of `bucket`,
+     * replacing the blob that was stored there. */
+    async put(
+        bucket: string,
+        key: string,
+        data: Uint8Array | string,
+        options?: { contentType?: string },
+    ): Promise<void> {
+        const bytes = typeof data === "string"
+            ? new TextEncoder().encode(data)
+            : data;
+        await opAsync(
+            "op_chisel_blob_put",
+            requestContext.rid,
+            bucket,
+            key,
+            bytes,
+            options?.contentType ?? null,
+        );
+    },
+
+    /** Returns the blob under `key` of `bucket`, or `undefined` if there is no
+     * such blob. */
+    async get(bucket: string, key: string): Promise<StoredBlob | undefined> {
+        const blob = await opAsync(
+            "op_chisel_blob_get",
+            requestContext.rid,
+            bucket,
+            key,
+        ) as BlobJson | null;
+        if (blob === null) {
+            return undefined;
+        }
+        return { data: blob.data, contentType: blob.contentType ?? undefined };
+    },
+
+    /** Deletes the blob under `key` of `bucket`. Returns false if there was no
+     * such blob. */
+    async delete(bucket: string, key: string): Promise<boolean> {
+        return await opAsync(
+            "op_chisel_blob_delete",
+            requestContext.rid,
+            bucket,
+            key,
+        ) as boolean;
+    },
+
+    /** Returns a URL that lets anyone who has it download the blob under `key`
+     * of `bucket` for `expiresInS` seconds (one hour by default, at most a
+     * week). With `--blob-dir`, the URL is a path of this server and it is
+     * invalidated when the server restarts. */
+    // deno-lint-ignore require-await
+    async signedUrl(
+        bucket: string,
+        key: string,
+        options?: { expiresInS?: number },
+    ): Promise<string> {
+        return opSync(
+            "op_chisel_blob_signed_url",
+            requestContext.rid,
+            bucket,
+            key,
+            options?.expiresInS ?? 60 * 60,
+        ) as string;
+    },
+};
@@ -6,7 +6,7 @@ import { ChiselEntity } from "./datastore.ts";
 import { TopicMap } from "./kafka.ts";
 import { ChiselRequest } from "./request.ts";
 import { MiddlewareNext, RouteMap } from "./routing.ts";
-import { getSecret, opSync } from "./utils.ts";
+import { getSecret, opSync, responseFromJson } from "./utils.ts";
 
 class AuthUser extends ChiselEntity {}
 class AuthSession extends ChiselEntity {}
@@ -24,6 +24,12 @@ export const routeMap = new RouteMap()
             .prefix("/tokens", AuthToken.crud())
             .prefix("/accounts", AuthAccount.crud())
             .middleware(authMiddleware),
+    )
+    .prefix(
+        "/metrics",
+        new RouteMap()
+            .get("/", metrics)
+            .middleware(authMiddleware),
     );
 
 export const topicMap = new TopicMap();
@@ -54,6 +60,10 @@ async function authMiddleware(
     return next(request);
 }
 
+function metrics(): Response {
+    return responseFromJson(opSync("op_chisel_get_metrics"), 200);
+}
+
 function forbidden(msg: string): Response {
     return new Response(msg, { status: 403 });
 }
@@ -0,0 +1,116 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import type { AuthUser } from "./datastore.ts";
+
+// Context of an HTTP request that we receive from Rust
+export type RequestContextJson = {
+    versionId: string;
+    requestId: string;
+    traceparent: string | undefined;
+    clientIp: string;
+    userId: string | undefined;
+    tenantId: string | undefined;
+    claims: Record<string, unknown> | undefined;
+    roles: string[];
+};
+
+/**
+ * Context of the HTTP request that is being handled. Route handlers receive it
+ * as their second argument (middlewares as their third), and it is also
+ * available as `req.ctx`.
+ *
+ * @example
+ * ```typescript
+ * import { ChiselContext, ChiselRequest } from "@chiselstrike/api";
+ *
+ * export default async function (req: ChiselRequest, ctx: ChiselContext) {
+ *     if (!ctx.hasRole("admin")) {
+ *         return new Response("Forbidden", { status: 403 });
+ *     }
+ *     // propagates the trace of this request to the other service
+ *     return await fetch("https://example.com", { headers: ctx.traceHeaders() });
+ * }
+ * ```
+ */
+export class ChiselContext {
+    /** Id of the API version that handles the request. */
+    readonly versionId: string;
+    /** Id of the request, which is attached to its log records and returned in the `x-trace-id` header. */
+    readonly requestId: string;
+    /** W3C `traceparent` of the span of the request, `undefined` if the request is not traced. */
+    readonly traceparent: string | undefined;
+    /** IP address of the client that sent the request. */
+    readonly clientIp: string;
+    /** Id of the logged-in user, `undefined` if the request is anonymous. */
+    readonly userId: string | undefined;
+    /** The logged-in user, `undefined` if the request is anonymous. */
+    readonly user: AuthUser | undefined;
+    /**
+     * Tenant of the request when chiseld isolates the data per tenant
+     * (`--tenant-claim` or `--tenant-header`), `undefined` otherwise. The
+     * queries and writes of the request only see the data of this tenant.
+     */
+    readonly tenantId: string | undefined;
+    /** Claims about the user, from the JWT or from the authentication hook. */
+    readonly claims: Record<string, unknown>;
+    /** Roles of the user, which are the strings in the `roles` claim. */
+    readonly roles: string[];
+    /** Headers of the request. */
+    readonly headers: Headers;
+    /**
+     * Trailers of the response, which can be set until the body of the
+     * response is complete, for example from a `ReadableStream` body once the
+     * last chunk has been produced.
+     *
+     * HTTP/2 clients receive them as HTTP trailers. HTTP/1.1 clients receive
+     * them as ordinary response headers instead: the response is sent only
+     * after its body is complete, so the headers are not committed before the
+     * trailers are known.
+     */
+    readonly trailers: Headers;
+
+    constructor(
+        json: RequestContextJson,
+        user: AuthUser | undefined,
+        headers: Headers,
+    ) {
+        this.versionId = json.versionId;
+        this.requestId = json.requestId;
+        this.traceparent = json.traceparent ?? undefined;
+        this.clientIp = json.clientIp;
+        this.userId = json.userId ?? undefined;
+        this.user = user;
+        this.tenantId = json.tenantId ?? undefined;
+        this.claims = json.claims ?? {};
+        this.roles = json.roles;
+        this.headers = headers;
+        this.trailers = new Headers();
+    }
+
+    /** Returns true if the user has the given role. */
+    hasRole(role: string): boolean {
+        return this.roles.includes(role);
+    }
+
+    /** Returns the headers that propagate the trace of this request to outbound requests. */
+    traceHeaders(): Record<string, string> {
+        const headers: Record<string, string> = {
+            "x-trace-id": this.requestId,
+        };
+        if (this.traceparent !== undefined) {
+            headers["traceparent"] = this.traceparent;
+        }
+        return headers;
+    }
+}
+
+/** Returns the roles in the `roles` claim of `claims`. */
+export function claimedRoles(
+    claims: Record<string, unknown> | undefined,
+): string[] {
+    const roles = claims?.roles;
+    if (!Array.isArray(roles)) {
+        return [];
+    }
+    return roles.filter((role): role is string => typeof role === "string");
+}
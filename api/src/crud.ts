@@ -1,16 +1,54 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 import { opAsync, responseFromJson } from "./utils.ts";
-import { ChiselEntity, mergeIntoEntity, requestContext } from "./datastore.ts";
+import { ChiselEntity, requestContext } from "./datastore.ts";
+import type { Page } from "./datastore.ts";
 import { ChiselRequest } from "./request.ts";
 import { RouteMap } from "./routing.ts";
 import { ClientMetadata, CrudHandler } from "./routing.ts";
 
+/**
+ * Body of the response of the `GET /` route of `crud()`. The `results` and the
+ * `next_cursor` are the `items` and the `nextCursor` of a `Page`: the cursors
+ * of the route and of `ChiselEntity.page()` are interchangeable.
+ */
+export type CrudPage<T> = {
+    results: T[];
+    /** Cursor of the next page, omitted when the page is not full. */
+    next_cursor?: string;
+    /** Relative URL of the next page. */
+    next_page?: string;
+    /** Relative URL of the previous page. */
+    prev_page?: string;
+    pagination: {
+        /** Null if the whole result set was returned at once with `full_scan=true`. */
+        page_size: number | null;
+        max_page_size: number;
+        count: number;
+        full_scan: boolean;
+    };
+};
+
+/** Thrown by the `GET /` route of `crud()` for a query that the client is not
+ * allowed to make, such as `full_scan=true` without `allowFullScan`. The
+ * route responds with 400 Bad Request. */
+export class CrudQueryError extends Error {
+    constructor(msg: string) {
+        super(msg);
+    }
+}
+
+/** Converts the body of the response of the `GET /` route of `crud()` to a `Page`. */
+export function crudPageToPage<T>(crudPage: CrudPage<T>): Page<T> {
+    return { items: crudPage.results, nextCursor: crudPage.next_cursor };
+}
+
 export type ChiselEntityClass<T extends ChiselEntity> = {
     new (): T;
     findOne: (_: { id: string }) => Promise<T | undefined>;
     findMany: (_: Partial<T>) => Promise<T[]>;
     build: (...properties: Record<string, unknown>[]) => T;
     delete: (_: { id: string }) => Promise<void>;
+    update: (id: string, fields: Partial<T>) => Promise<T | undefined>;
 };
 
 /**
@@ -29,6 +67,12 @@ export type ChiselEntityClass<T extends ChiselEntity> = {
  *    `responseFromJson()`.
  *  - `getAll`: should we generate `GET /` route that returns all entities according to filters in the URL
  *    query parameters? Defaults to true.
+ *  - `pageSize`: number of entities returned by `GET /` when the request does not specify `page_size`.
+ *    Defaults to the `--crud-default-page-size` of the server.
+ *  - `maxPageSize`: larger `page_size` requested by the client are clamped to this value. Defaults to the
+ *    `--crud-max-page-size` of the server.
+ *  - `allowFullScan`: should `GET /?full_scan=true` return all matching entities at once, ignoring the page
+ *    size limits? Meant for internal tooling, defaults to false.
  *  - `getOne`: should we generate `GET /:id` route that returns one entity by id? Defaults to true.
  *  - `write`: should we generate the routes that write to the database? Defaults to true, and it can be
  *    overriden on a per-route basis.
@@ -50,6 +94,9 @@ export function crud<T extends ChiselEntity, E extends ChiselEntityClass<T>>(
             status: number,
         ) => Promise<Response> | Response;
         getAll?: boolean;
+        pageSize?: number;
+        maxPageSize?: number;
+        allowFullScan?: boolean;
         getOne?: boolean;
         write?: boolean;
         post?: boolean;
@@ -70,10 +117,19 @@ export function crud<T extends ChiselEntity, E extends ChiselEntityClass<T>>(
         };
     };
 
-    // Returns all entities matching the filter in the `filter` URL parameter.
+    // Returns a page of entities matching the filter in the `filter` URL parameter.
     async function getAll(req: ChiselRequest): Promise<Response> {
         return createResponse(
-            await fetchEntitiesCrud(entity, req.path, Array.from(req.query)),
+            await fetchEntitiesCrud(
+                entity,
+                req.path,
+                Array.from(req.query),
+                {
+                    pageSize: config?.pageSize,
+                    maxPageSize: config?.maxPageSize,
+                    allowFullScan: config?.allowFullScan ?? false,
+                },
+            ),
             200,
         );
     }
@@ -106,7 +162,9 @@ export function crud<T extends ChiselEntity, E extends ChiselEntityClass<T>>(
         routeMap.route("POST", "/", post, clientMetadata("PostOne"));
     }
 
-    // Updates and returns the entity matching :id from the `req` payload.
+    // Updates and returns the entity matching :id from the `req` payload. If the entity has a
+    // `@version` field, the version in the payload must match the stored version, otherwise the
+    // save throws a `ConflictError` and the request fails with 409 Conflict.
     async function put(req: ChiselRequest): Promise<Response> {
         const u = entity.build(await req.json());
         u.id = req.params.get("id");
@@ -117,19 +175,18 @@ export function crud<T extends ChiselEntity, E extends ChiselEntityClass<T>>(
         routeMap.route("PUT", "/:id", put, clientMetadata("PutOne"));
     }
 
-    // Modifies an entity matching :id from the `req` payload.
+    // Writes the fields in the `req` payload into the entity matching :id, leaving the other
+    // fields untouched. The `@version` field (if any) is checked like in `put()`; if the payload
+    // does not contain it, the update is not checked against the stored version.
     async function patch(req: ChiselRequest): Promise<Response> {
-        const orig = await entity.findOne({ id: req.params.get("id") });
-        if (!orig) {
-            return createResponse("object does not exist, cannot PATCH", 404);
-        }
-        mergeIntoEntity(
-            entity.name,
-            orig as Record<string, unknown>,
+        const updated = await entity.update(
+            req.params.get("id"),
             await req.json(),
         );
-        await orig.save();
-        return createResponse(orig, 200);
+        if (!updated) {
+            return createResponse("object does not exist, cannot PATCH", 404);
+        }
+        return createResponse(updated, 200);
     }
     if (config?.patch ?? config?.write ?? true) {
         routeMap.route("PATCH", "/:id", patch, clientMetadata("PatchOne"));
@@ -169,17 +226,23 @@ async function fetchEntitiesCrud<T extends ChiselEntity>(
     type: { new (): T },
     urlPath: string,
     urlQuery: [string, string][],
-): Promise<T[]> {
+    pageLimits: {
+        pageSize?: number;
+        maxPageSize?: number;
+        allowFullScan: boolean;
+    },
+): Promise<CrudPage<T>> {
     const results = await opAsync(
         "op_chisel_crud_query",
         {
             typeName: type.name,
             urlPath,
             urlQuery,
+            ...pageLimits,
         },
         requestContext.rid,
     );
-    return results as T[];
+    return results as CrudPage<T>;
 }
 
 async function deleteEntitiesCrud<T extends ChiselEntity>(
@@ -3,9 +3,15 @@
 import { crud } from "./crud.ts";
 import { evalFilter } from "./filter.ts";
 import type { FilterExpr } from "./filter.ts";
+import { boundingBoxFilter, distanceMeters } from "./geo.ts";
+import type { GeoPoint, NearOptions } from "./geo.ts";
 import type { RouteMap } from "./routing.ts";
-import { opAsync, opSync } from "./utils.ts";
+import { opAsync, opSync, valueToJson } from "./utils.ts";
+import type { JSONValue } from "./utils.ts";
 import { typeSystem } from "./type_system.ts";
+/** Number of rows that are fetched from the database at once when a cursor is iterated. */
+const FETCH_ROWS = 100;
+
 /**
  * Base class for various Operators applicable on `ChiselCursor`. An
  * implementation of Operator<T> processes an AsyncIterable<T> and
@@ -40,12 +46,49 @@ abstract class Operator<Input, Output> {
     }
 
     public runChiselQuery(): AsyncIterable<Output> {
+        const pages = this.runChiselQueryPages(FETCH_ROWS);
+        return {
+            [Symbol.asyncIterator]: async function* () {
+                for await (const page of pages) {
+                    yield* page;
+                }
+            },
+        };
+    }
+
+    /**
+     * Runs the query in the database and returns its results in pages of at
+     * most `pageSize` elements, which are fetched with one op call each.
+     */
+    public runChiselQueryPages(pageSize: number): AsyncIterable<Output[]> {
         const getRid = () =>
             opAsync(
                 "op_chisel_relational_query_create",
                 this,
                 requestContext.rid,
+                this.queryCacheTtlMs(),
             ) as Promise<number>;
+        return this.readQueryPages(getRid, pageSize);
+    }
+
+    /** Time for which the results of the query are cached, see `ChiselEntity.cached()`. */
+    public queryCacheTtlMs(): number | undefined {
+        // deno-lint-ignore no-this-alias
+        let base: Operator<unknown, unknown> = this;
+        while (base.inner !== undefined) {
+            base = base.inner;
+        }
+        return base instanceof BaseEntity ? base.cacheTtlMs : undefined;
+    }
+
+    /**
+     * Reads the results of the query stream resource returned by `getRid` in
+     * pages of at most `pageSize` elements, and closes the resource.
+     */
+    public readQueryPages(
+        getRid: () => Promise<number>,
+        pageSize: number,
+    ): AsyncIterable<Output[]> {
         const recordToOutput = (rawRecord: unknown) => {
             return this.recordToOutput(rawRecord);
         };
@@ -53,18 +96,21 @@ abstract class Operator<Input, Output> {
             [Symbol.asyncIterator]: async function* () {
                 const rid = await getRid();
                 try {
-                    while (true) {
-                        await opAsync("op_chisel_query_next", rid);
-                        const properties = opSync(
-                            "op_chisel_query_get_value",
+                    let more = true;
+                    while (more) {
+                        more = await opAsync(
+                            "op_chisel_query_fetch",
+                            rid,
+                            pageSize,
+                        ) as boolean;
+                        const rows = opSync(
+                            "op_chisel_query_take_rows",
                             rid,
                             requestContext.rid,
-                        );
-
-                        if (properties === null) {
-                            break;
+                        ) as unknown[];
+                        if (rows.length > 0) {
+                            yield rows.map(recordToOutput);
                         }
-                        yield recordToOutput(properties);
                     }
                 } finally {
                     Deno.core.close(rid);
@@ -81,6 +127,8 @@ class BaseEntity<T> extends Operator<never, T> {
     constructor(
         public name: string,
         private baseConstructor: { new (): T },
+        /** If set, results of the query are cached for this long (see `ChiselEntity.cached()`). */
+        public cacheTtlMs?: number,
     ) {
         super(undefined);
     }
@@ -250,6 +298,41 @@ class ColumnsSelect<T, C extends (keyof T)[]>
     }
 }
 
+/**
+ * Relations to load together with the elements of a cursor, see
+ * `ChiselCursor.with()`. The keys are fields of type `Id<Entity>` (or nested
+ * entities) and the values are either `true` or the relations to load from the
+ * related entity.
+ */
+export type RelationSelection<T> = {
+    [K in keyof T]?: boolean | RelationSelection<Record<string, unknown>>;
+};
+
+/**
+ * With operator loads the related entities in `selection` in the same
+ * database query as the elements. It can only be evaluated by the database.
+ */
+class With<T> extends Operator<T, T> {
+    constructor(
+        inner: Operator<unknown, T>,
+        public selection: RelationSelection<T>,
+    ) {
+        super(inner);
+    }
+
+    apply(
+        _iter: AsyncIterable<T>,
+    ): AsyncIterable<T> {
+        throw new Error(
+            "with() must be called before map() and before filter() with a predicate function",
+        );
+    }
+
+    recordToOutput(rawRecord: unknown): T {
+        return this.inner!.recordToOutput(rawRecord);
+    }
+}
+
 /**
  * PredicateFilter operator applies `predicate` on each element and keeps
  * only those for which the `predicate` returns true.
@@ -447,7 +530,7 @@ class Count extends Operator<unknown, number> {
     }
 
     recordToOutput(rawRecord: unknown): number {
-        return (rawRecord as { count: number }).count;
+        return Number((rawRecord as { count: bigint }).count);
     }
 }
 
@@ -532,16 +615,31 @@ class MaxBy<Input, K extends keyof Input>
     }
 }
 
+/** Names of the properties of `T` that are not methods. */
+export type FieldNames<T> = {
+    // deno-lint-ignore no-explicit-any
+    [K in keyof T]: T[K] extends (...args: any[]) => unknown ? never : K;
+}[keyof T] & keyof T;
+
+/** Arrays of the elements of the cursors passed to `ChiselCursor.batch()`. */
+export type BatchResults<C extends readonly ChiselCursor<unknown>[]> = {
+    [K in keyof C]: C[K] extends ChiselCursor<infer T> ? T[] : never;
+};
+
 /** ChiselCursor is a lazy iterator that will be used by ChiselStrike to construct an optimized query. */
 export class ChiselCursor<T> {
     constructor(private inner: Operator<unknown, T>) {}
 
-    // FIXME: The typing of Select operator is wrong because Pick allows to select
-    // not only properties, but methods as well. Which means `Person.cursor().select("save");`
-    // will pass the compiler, but it's obviously not what we want to allow.
-
-    /** Force ChiselStrike to fetch just the `...columns` that are part of the colums list. */
-    select<C extends (keyof T)[]>(
+    /**
+     * Fetches just the `...columns` of the elements: the database query reads
+     * only these columns, and the elements are plain objects with just these
+     * properties.
+     *
+     * ```typescript
+     * const names = await Person.cursor().select("firstName", "lastName").toArray();
+     * ```
+     */
+    select<C extends FieldNames<T>[]>(
         ...columns: C
     ): ChiselCursor<Pick<T, C[number]>> {
         return new ChiselCursor(
@@ -552,6 +650,30 @@ export class ChiselCursor<T> {
         );
     }
 
+    /**
+     * Loads the related entities together with the elements, instead of
+     * returning just their ids. The related entities are joined in the same
+     * database query, so this avoids a `findById()` call for every element:
+     *
+     * ```typescript
+     * const posts = await Post.cursor()
+     *     .with({ author: { company: true } })
+     *     .toArray();
+     * // `post.author` now holds the `Author` object instead of its id
+     * ```
+     *
+     * The `Id<>` fields in `selection` hold the related objects (or are
+     * undefined if the related object does not exist). Relations can be
+     * nested at most 5 levels deep, which bounds the loading of
+     * self-referential entities. Saving an object with loaded relations stores
+     * their ids.
+     */
+    with(selection: RelationSelection<T>): ChiselCursor<T> {
+        return new ChiselCursor(
+            new With(this.inner, selection),
+        );
+    }
+
     /** Restricts this cursor to contain only at most `count` elements */
     take(count: number): ChiselCursor<T> {
         return new ChiselCursor(
@@ -622,6 +744,29 @@ export class ChiselCursor<T> {
         return new ChiselCursor(op);
     }
 
+    /**
+     * Restricts this cursor to the elements whose `GeoPoint` at `key` is at
+     * most `radiusMeters` meters from the point `{ lat, lng }`:
+     *
+     * ```typescript
+     * const shops = await Shop.cursor()
+     *     .near("location", { lat: 50.08, lng: 14.42, radiusMeters: 2000 })
+     *     .toArray();
+     * ```
+     *
+     * The database only returns the elements in the bounding box of the
+     * circle, and the distances are checked afterwards.
+     */
+    near(key: keyof T & string, options: NearOptions): ChiselCursor<T> {
+        const { lat, lng, radiusMeters } = options;
+        return this.filter(boundingBoxFilter(key, options) as FilterExpr<T>)
+            .filter((arg: T) => {
+                const point = arg[key] as unknown as GeoPoint | undefined;
+                return point !== undefined && point !== null &&
+                    distanceMeters(point, { lat, lng }) <= radiusMeters;
+            });
+    }
+
     /**
      * Sorts cursor elements.
      *
@@ -719,6 +864,107 @@ export class ChiselCursor<T> {
         );
     }
 
+    /**
+     * Iterates the elements in pages (arrays) of at most `pageSize` elements.
+     * Each page is fetched from the database at once, so this is an efficient
+     * way to process large tables:
+     *
+     * ```typescript
+     * for await (const page of Person.cursor().pages(500)) {
+     *     await exportPeople(page);
+     * }
+     * ```
+     */
+    pages(pageSize: number): AsyncIterable<T[]> {
+        if (!Number.isSafeInteger(pageSize) || pageSize <= 0) {
+            throw new Error(
+                `pageSize must be a positive integer, got ${pageSize}`,
+            );
+        }
+        const iter = this.inner.eval();
+        if (iter === undefined) {
+            return this.inner.runChiselQueryPages(pageSize);
+        }
+        // the cursor is (partially) evaluated in JavaScript, so its elements
+        // are grouped into pages here
+        return {
+            [Symbol.asyncIterator]: async function* () {
+                let page: T[] = [];
+                for await (const element of iter) {
+                    page.push(element);
+                    if (page.length >= pageSize) {
+                        yield page;
+                        page = [];
+                    }
+                }
+                if (page.length > 0) {
+                    yield page;
+                }
+            },
+        };
+    }
+
+    /**
+     * Fetches the elements of all `cursors` at once, which is faster than
+     * awaiting `toArray()` of every cursor in turn. On Postgres, the queries
+     * are sent to the database together, each on its own connection, unless
+     * the request has already changed the entities they read:
+     *
+     * ```typescript
+     * const [posts, authors] = await ChiselCursor.batch(
+     *     Post.cursor().take(10),
+     *     Author.cursor().filter({ active: true }),
+     * );
+     * ```
+     *
+     * Cursors that are (partially) evaluated in TypeScript and cursors of
+     * cached queries are fetched one by one.
+     */
+    static async batch<C extends readonly ChiselCursor<unknown>[]>(
+        ...cursors: C
+    ): Promise<BatchResults<C>> {
+        const batched = cursors.filter((cursor) =>
+            cursor.inner.eval() === undefined &&
+            cursor.inner.queryCacheTtlMs() === undefined
+        );
+        const rids = batched.length > 0
+            ? await opAsync(
+                "op_chisel_execute_batch",
+                batched.map((cursor) => cursor.inner),
+                requestContext.rid,
+            ) as number[]
+            : [];
+        const results = [];
+        let idx = 0;
+        try {
+            for (const cursor of cursors) {
+                if (cursor !== batched[idx]) {
+                    results.push(await cursor.toArray());
+                    continue;
+                }
+                const rid = rids[idx];
+                // the resource is closed when its pages are read
+                rids[idx++] = -1;
+                const pages = cursor.inner.readQueryPages(
+                    () => Promise.resolve(rid),
+                    FETCH_ROWS,
+                );
+                const elements = [];
+                for await (const page of pages) {
+                    elements.push(...page);
+                }
+                results.push(elements);
+            }
+        } finally {
+            for (const rid of rids) {
+                if (rid !== -1) {
+                    Deno.core.close(rid);
+                }
+            }
+        }
+        return results as BatchResults<C>;
+    }
+
     /** ChiselCursor implements asyncIterator, meaning you can use it in any asynchronous context. */
     [Symbol.asyncIterator](): AsyncIterator<T> {
         let iter = this.inner.eval();
@@ -731,17 +977,166 @@ export class ChiselCursor<T> {
 
 export function chiselIterator<T extends ChiselEntity>(
     type: { new (): T },
+    cacheTtlMs?: number,
 ) {
-    const b = new BaseEntity<T>(type.name, type);
+    const b = new BaseEntity<T>(type.name, type, cacheTtlMs);
     return new ChiselCursor(b);
 }
 
-export type UpsertArgs<T> = {
-    restrictions: Partial<T> | FilterExpr<T>;
-    create: Partial<T>;
-    update: Partial<T>;
+export type CacheOptions = {
+    /** How long the results of a query are cached, in milliseconds. Defaults to one minute. */
+    ttlMs?: number;
 };
 
+/**
+ * Queries of an entity whose results are cached in the server, returned by
+ * `ChiselEntity.cached()`.
+ *
+ * Results of a query are cached until the TTL expires or until a transaction
+ * that changes the entity (or any entity that the query reads) is committed.
+ * Queries made after the current transaction changed the entity are not
+ * cached, so that they see the uncommitted changes.
+ *
+ * Only the database query is cached: predicates that are passed as
+ * functions (and cannot be converted to database filters) are evaluated
+ * every time.
+ */
+export class CachedQueries<T extends ChiselEntity> {
+    constructor(
+        private type: { new (): T },
+        private ttlMs: number,
+    ) {}
+
+    /** Returns a cursor with cached results, see `ChiselEntity.cursor()`. */
+    cursor(): ChiselCursor<T> {
+        return chiselIterator<T>(this.type, this.ttlMs);
+    }
+
+    /** Cached variant of `ChiselEntity.findAll()`. */
+    async findAll(take?: number): Promise<T[]> {
+        let it = this.cursor();
+        if (take) {
+            it = it.take(take);
+        }
+        return await it.toArray();
+    }
+
+    /** Cached variant of `ChiselEntity.findMany()`. */
+    async findMany(
+        arg1: ((arg: T) => boolean) | Partial<T> | FilterExpr<T>,
+        take?: number,
+    ): Promise<T[]> {
+        let it = undefined;
+        if (typeof arg1 == "function") {
+            it = this.cursor().filter(arg1);
+        } else {
+            it = this.cursor().filter(arg1 as FilterExpr<T>);
+        }
+        if (take !== undefined) {
+            it = it.take(take);
+        }
+        return await it.toArray();
+    }
+
+    // FindMany function used by Chisel Compiler. Not intended for direct usage.
+    async __findMany(
+        exprPredicate: (arg: T) => boolean,
+        expression: Record<string, unknown>,
+        postPredicate?: (arg: T) => boolean,
+        take?: number,
+    ): Promise<T[]> {
+        let it = this.cursor().__filter(
+            exprPredicate,
+            expression,
+            postPredicate,
+        );
+        if (take !== undefined) {
+            it = it.take(take);
+        }
+        return await it.toArray();
+    }
+
+    /** Cached variant of `ChiselEntity.findOne()`. */
+    async findOne(
+        arg1: ((arg: T) => boolean) | Partial<T> | FilterExpr<T>,
+    ): Promise<T | undefined> {
+        let it = undefined;
+        if (typeof arg1 == "function") {
+            it = this.cursor().filter(arg1);
+        } else {
+            it = this.cursor().filter(arg1 as FilterExpr<T>);
+        }
+        for await (const value of it) {
+            return value;
+        }
+        return undefined;
+    }
+
+    // findOne function used by Chisel Compiler. Not intended for direct usage.
+    async __findOne(
+        exprPredicate: (arg: T) => boolean,
+        expression: Record<string, unknown>,
+        postPredicate?: (arg: T) => boolean,
+    ): Promise<T | undefined> {
+        const it = this.cursor().__filter(
+            exprPredicate,
+            expression,
+            postPredicate,
+        );
+        for await (const value of it) {
+            return value;
+        }
+        return undefined;
+    }
+}
+
+/** Parameters of `ChiselEntity.page()`. */
+export type PageParams<T> = {
+    /** The `nextCursor` of the previous page. The first page is returned if not set. */
+    cursor?: string;
+    /** Maximum number of items in the page. Defaults to 100. */
+    size?: number;
+    /** Restricts the items to the entities that match the filter. */
+    filter?: Partial<T> | FilterExpr<T>;
+    /**
+     * Sort order of the items, ties are broken by `id`. Defaults to sorting by
+     * `id`. It is ignored when `cursor` is set, because the cursor carries
+     * the sort order of the first page.
+     */
+    sort?: { key: FieldNames<T>; ascending?: boolean };
+    /**
+     * Should the page report the total number of matching entities? This costs
+     * another database query.
+     */
+    total?: boolean;
+};
+
+/**
+ * A page of entities returned by `ChiselEntity.page()`. The cursors are
+ * interchangeable with the `next_cursor` of the `GET` route of `crud()`.
+ */
+export type Page<T> = {
+    items: T[];
+    /** Cursor of the next page, undefined if this is the last page. */
+    nextCursor?: string;
+    /** Total number of matching entities, if requested with `total: true`. */
+    total?: number;
+};
+
+export type UpsertArgs<T> =
+    | {
+        /** The id or a `@unique` field, with the value of the row to update. */
+        find: Partial<T>;
+        create: Partial<T>;
+        update: Partial<T>;
+    }
+    | {
+        /** @deprecated Use `find`, which writes the row without racing with other requests. */
+        restrictions: Partial<T> | FilterExpr<T>;
+        create: Partial<T>;
+        update: Partial<T>;
+    };
+
 /** ChiselEntity is a class that ChiselStrike user-defined entities are expected to extend.
  *
  * It provides properties that are inherent to a ChiselStrike entity, like an id, and static
@@ -793,13 +1188,53 @@ export class ChiselEntity {
     /** saves the current object into the backend */
     async save() {
         ensureNotGet();
-        type IdsJson = { id: string; children: Record<string, IdsJson> };
+        type IdsJson = {
+            id: string;
+            version?: number;
+            writtenAt?: number;
+            owner?: string;
+            children: Record<string, IdsJson>;
+        };
         const idTree = await opAsync("op_chisel_store", {
             name: this.constructor.name,
             value: this,
         }, requestContext.rid) as IdsJson;
         function backfillIds(this_: ChiselEntity, jsonIds: IdsJson) {
+            // objects without an id are always inserted
+            const inserted = this_.id === undefined;
             this_.id = jsonIds.id;
+            if (jsonIds.version !== undefined) {
+                const versionField = typeSystem.findEntity(this_.constructor.name)
+                    ?.fields.find((field) => field.isVersion);
+                if (versionField !== undefined) {
+                    (this_ as unknown as Record<string, unknown>)[
+                        versionField.name
+                    ] = jsonIds.version;
+                }
+            }
+            if (jsonIds.writtenAt !== undefined) {
+                const fields = typeSystem.findEntity(this_.constructor.name)
+                    ?.fields ?? [];
+                const record = this_ as unknown as Record<string, unknown>;
+                for (const field of fields) {
+                    // an updated object keeps the creation time that it was loaded with
+                    if (
+                        field.timestamp === "updated" ||
+                        (field.timestamp === "created" &&
+                            (inserted || record[field.name] === undefined))
+                    ) {
+                        record[field.name] = new Date(jsonIds.writtenAt);
+                    }
+                }
+            }
+            if (jsonIds.owner !== undefined) {
+                const ownerField = typeSystem.findEntity(this_.constructor.name)
+                    ?.ownedBy;
+                if (ownerField !== undefined) {
+                    (this_ as unknown as Record<string, unknown>)[ownerField] =
+                        jsonIds.owner;
+                }
+            }
             for (const [fieldName, value] of Object.entries(jsonIds.children)) {
                 const child = (this_ as unknown as Record<string, unknown>)[
                     fieldName
@@ -820,6 +1255,18 @@ export class ChiselEntity {
         return chiselIterator<T>(this);
     }
 
+    /**
+     * Returns queries of this entity whose results are cached in the server,
+     * for example `Person.cached({ ttlMs: 5000 }).findMany({ country: "CZ" })`.
+     * See `CachedQueries` for the details.
+     */
+    static cached<T extends ChiselEntity>(
+        this: { new (): T },
+        options?: CacheOptions,
+    ): CachedQueries<T> {
+        return new CachedQueries<T>(this, options?.ttlMs ?? 60 * 1000);
+    }
+
     /**
      * Return all entities of type T.
      */
@@ -903,6 +1350,70 @@ export class ChiselEntity {
         return await it.toArray();
     }
 
+    /**
+     * Returns a page of entities of type T, sorted by `sort` and matching
+     * `filter`. The following page is fetched by passing the `nextCursor` of
+     * the page:
+     *
+     * ```typescript
+     * let page = await Post.page({ size: 20, sort: { key: "createdAt", ascending: false } });
+     * while (page.nextCursor !== undefined) {
+     *     page = await Post.page({ cursor: page.nextCursor, size: 20 });
+     * }
+     * ```
+     *
+     * Unlike `skip()`, the cursor selects the next page by the sort keys of
+     * the last item, so pages stay consistent when entities are inserted or
+     * deleted in the meantime, and late pages are as fast as the first one.
+     * The `filter` must be the same for all pages.
+     */
+    static async page<T extends ChiselEntity>(
+        this: { new (): T },
+        params: PageParams<T> = {},
+    ): Promise<Page<T>> {
+        const size = params.size ?? 100;
+        if (!Number.isSafeInteger(size) || size <= 0) {
+            throw new Error(`size must be a positive integer, got ${size}`);
+        }
+        const cursor = params.cursor !== undefined
+            ? decodePageCursor(params.cursor)
+            : newPageCursor(params.sort);
+
+        let filtered = chiselIterator<T>(this);
+        if (params.filter !== undefined) {
+            filtered = filtered.filter(params.filter as FilterExpr<T>);
+        }
+        const total = params.total ? await filtered.count() : undefined;
+
+        const keys = cursor.axes.map((axis) =>
+            new SortKey<T>(axis.key.fieldName as keyof T, axis.key.ascending)
+        );
+        let it = new ChiselCursor<T>(
+            new SortBy(new BaseEntity<T>(this.name, this), keys),
+        );
+        if (params.filter !== undefined) {
+            it = it.filter(params.filter as FilterExpr<T>);
+        }
+        if (cursor.axes[0].value !== undefined) {
+            it = it.filter(pageCursorFilter(cursor) as FilterExpr<T>);
+        }
+        // one more item tells whether there is a next page
+        const items = await it.take(size + 1).toArray();
+        let nextCursor = undefined;
+        if (items.length > size) {
+            items.length = size;
+            const last = items[size - 1] as unknown as Record<string, unknown>;
+            nextCursor = encodePageCursor({
+                ...cursor,
+                axes: cursor.axes.map((axis) => ({
+                    key: axis.key,
+                    value: valueToJson(last[axis.key.fieldName]),
+                })),
+            });
+        }
+        return { items, nextCursor, total };
+    }
+
     /**
      * Returns a single object of type T for which the given `predicate` returns true.
      *
@@ -1092,20 +1603,28 @@ export class ChiselEntity {
     /**
      * Update an object or create it if it doesn't exist.
      *
+     * `find` names a single field, which must be the id or a `@unique` field. If a row with that
+     * value exists, the fields in `update` are written into it, otherwise a row with the fields in
+     * `create` (and `find`) is created. This is a single database statement, so concurrent upserts
+     * of the same row don't race.
+     *
      * @example
      * ```typescript
      * export class User extends ChiselEntity {
-     *   username: string,
+     *   @unique username: string,
      *   email: string,
      * }
      * const user = await User.upsert({
-     *     restrictions: { username: "alice", email: "alice@example.com" },
-     *     create: { username: "alice", email: "alice@example.com" },
+     *     find: { username: "alice" },
+     *     create: { email: "alice@example.com" },
      *     update: { email: "alice@chiselstrike.com" }
      * });
      * ```
      *
-     * Please note that upsert only updates a single row it matches on.
+     * Related entities must already exist, they are not saved by `upsert()`.
+     *
+     * With the deprecated `restrictions` instead of `find`, the first matching row is loaded and
+     * saved, which races with other requests.
      *
      * @version experimental
      */
@@ -1113,6 +1632,23 @@ export class ChiselEntity {
         this: { new (): T },
         args: UpsertArgs<T>,
     ): Promise<T> {
+        ensureNotGet();
+        if ("find" in args) {
+            const create = buildEntity(this, args.create, args.find);
+            const id = await opAsync("op_chisel_upsert", {
+                name: this.name,
+                find: args.find,
+                create,
+                update: args.update,
+            }, requestContext.rid) as Id<T>;
+            const it = chiselIterator<T>(this).filter(
+                { id } as Partial<T>,
+            ).take(1);
+            for await (const value of it) {
+                return value;
+            }
+            throw new Error(`upsert(): the written ${this.name} cannot be read`);
+        }
         const it = chiselIterator<T>(this).filter(
             args.restrictions as FilterExpr<T>,
         );
@@ -1138,6 +1674,124 @@ export class ChiselEntity {
             return entity;
         }
     }
+
+    /**
+     * Writes the given fields of the object with the given id, leaving the other fields untouched.
+     *
+     * Unlike loading the object and saving it, only the given fields are written, in a single
+     * database statement. If the entity has a `@version` field and `fields` contains it, the
+     * stored version must match, otherwise a `ConflictError` is thrown.
+     *
+     * @example
+     * ```typescript
+     * const user = await User.update(id, { email: "alice@chiselstrike.com" });
+     * ```
+     *
+     * Related entities must already exist, they are not saved by `update()`.
+     *
+     * @returns the updated object, or `undefined` if there is no object with the given id.
+     */
+    static async update<T extends ChiselEntity>(
+        this: { new (): T },
+        id: Id<T>,
+        fields: Partial<T>,
+    ): Promise<T | undefined> {
+        ensureNotGet();
+        const values = {};
+        mergeIntoEntity(this.name, values, fields as Record<string, unknown>);
+        const found = await opAsync("op_chisel_update", {
+            name: this.name,
+            id,
+            fields: values,
+        }, requestContext.rid) as boolean;
+        if (!found) {
+            return undefined;
+        }
+        const it = chiselIterator<T>(this).filter({ id } as Partial<T>).take(1);
+        for await (const value of it) {
+            return value;
+        }
+        return undefined;
+    }
+}
+
+/**
+ * Cursor of `ChiselEntity.page()`, in the format of the cursors of the CRUD
+ * routes: the sort keys of the pages, with the values of the last item of the
+ * previous page.
+ */
+type PageCursor = {
+    axes: {
+        key: { fieldName: string; ascending: boolean };
+        /** Undefined for the first page. */
+        value?: JSONValue;
+    }[];
+    forward: boolean;
+    inclusive: boolean;
+};
+
+function newPageCursor<T>(
+    sort?: { key: FieldNames<T>; ascending?: boolean },
+): PageCursor {
+    const axes = [];
+    if (sort !== undefined && sort.key !== "id") {
+        axes.push({
+            key: {
+                fieldName: sort.key as string,
+                ascending: sort.ascending ?? true,
+            },
+        });
+    }
+    axes.push({ key: { fieldName: "id", ascending: true } });
+    return { axes, forward: true, inclusive: false };
+}
+
+function encodePageCursor(cursor: PageCursor): string {
+    const bytes = new TextEncoder().encode(JSON.stringify(cursor));
+    let binary = "";
+    for (const byte of bytes) {
+        binary += String.fromCharCode(byte);
+    }
+    return btoa(binary);
+}
+
+function decodePageCursor(encoded: string): PageCursor {
+    let cursor;
+    try {
+        const binary = atob(encoded);
+        const bytes = Uint8Array.from(binary, (c) => c.charCodeAt(0));
+        cursor = JSON.parse(new TextDecoder().decode(bytes));
+    } catch (e) {
+        throw new Error(`invalid page cursor: ${e}`);
+    }
+    if (!Array.isArray(cursor?.axes) || cursor.axes.length == 0) {
+        throw new Error("invalid page cursor: it has no sort axes");
+    }
+    if (!cursor.forward) {
+        throw new Error(
+            "page cursors can only go forward, use the next_page link of the CRUD route to go back",
+        );
+    }
+    return cursor;
+}
+
+/**
+ * Returns a filter that matches the items that come after the values in
+ * `cursor` in the lexicographic order of its axes.
+ */
+function pageCursorFilter(cursor: PageCursor): Record<string, unknown> {
+    const alternatives = cursor.axes.map((axis, idx) => {
+        const alternative: Record<string, unknown> = {};
+        for (const prev of cursor.axes.slice(0, idx)) {
+            alternative[prev.key.fieldName] = prev.value;
+        }
+        const op = axis.key.ascending
+            ? (cursor.inclusive ? "$gte" : "$gt")
+            : (cursor.inclusive ? "$lte" : "$lt");
+        alternative[axis.key.fieldName] = { [op]: axis.value };
+        return alternative;
+    });
+    return { "$or": alternatives };
 }
 
 function restrictionsToFilterExpr<T extends ChiselEntity>(
@@ -1154,6 +1808,10 @@ function restrictionsToFilterExpr<T extends ChiselEntity>(
         } else if (value instanceof Date) {
             // Dirty hack until we process filtering expressions via v8.
             value = value.getTime();
+        } else if (typeof value === "bigint") {
+            // The expression is sent as JSON, whose numbers can't hold all bigints; the database
+            // converts the string back to an integer.
+            value = value.toString();
         }
 
         const cmpExpr = {
@@ -1193,6 +1851,238 @@ export function unique(_target: unknown, _name: string): void {
     // chisel-decorator, no content
 }
 
+/**
+ * Limits the size of an `ArrayBuffer` field to `bytes` bytes. Writing a larger
+ * value throws a `ValidationError` (CRUD endpoints respond with 422).
+ */
+export function maxBytes(_bytes: number) {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Decides what happens to this entity when the entity that the field refers
+ * to is deleted: `"cascade"` deletes it, too, `"restrict"` makes the deletion
+ * throw a `ConflictError` (CRUD endpoints respond with 409) and `"setNull"`
+ * clears the field, which must be optional.
+ */
+export function onDelete(_action: "cascade" | "restrict" | "setNull") {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Sets how the values of a `string` field are compared. With `"nocase"`, the
+ * uniqueness of the field, the filters that compare it and the sorting by it
+ * ignore the case, so `@unique @collate("nocase") email: string` rejects
+ * `Alice@example.com` when `alice@example.com` is stored. `"binary"` is the
+ * default.
+ */
+export function collate(_collation: "nocase" | "binary") {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Renames a field or an entity without losing its data:
+ * `@renamedFrom("fullName") name: string` tells `chisel apply` that `name` is
+ * the field that used to be called `fullName`, so its column is renamed
+ * instead of being dropped and added again. On a class,
+ * `@renamedFrom("Person") class User` keeps the table and the rows of
+ * `Person` for `User`. The decorator has no effect once the old field or
+ * entity is gone, so it can be removed after the apply.
+ */
+export function renamedFrom(_oldName: string) {
+    return (_target: unknown, _propertyName?: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Marks a `number` field that is used for optimistic concurrency control.
+ *
+ * The field is incremented every time the entity is saved, and `save()` throws a
+ * `ConflictError` if the entity was saved by somebody else since it was read
+ * (that is, if the stored version differs from the version of the saved object).
+ */
+export function version(_target: unknown, _name: string): void {
+    // chisel-decorator, no content
+}
+
+/**
+ * Marks a `number` field that holds the number of instances of `entity` whose `field` refers to
+ * this instance (the field can be a string with the id, an `Id<>` or a nested entity).
+ *
+ * The count is maintained by the server in the same transaction as the instances of `entity`
+ * are saved and deleted, and it is computed from the existing data when the field is applied.
+ * Values assigned to the field are ignored by `save()`.
+ *
+ * @example
+ * ```typescript
+ * export class Post extends ChiselEntity {
+ *     @count(Comment, "postId") commentCount = 0;
+ * }
+ * ```
+ */
+export function count<T extends ChiselEntity>(
+    _entity: { new (): T },
+    _field: keyof T & string,
+) {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/** Options of a materialized aggregate. */
+export type AggregateOptions = {
+    /**
+     * Recompute the aggregate every `refreshEvery` seconds. If not given, the aggregate is
+     * updated in the same transaction as the aggregated entity is written.
+     */
+    refreshEvery?: number;
+};
+
+/**
+ * Marks an entity as a materialized aggregate of `entity`: a read-only rollup with one instance
+ * for every group of instances of `entity`.
+ *
+ * Fields of the aggregate that are not decorated with an aggregate function (`@countOf()`,
+ * `@sumOf()`, `@avgOf()`, `@minOf()` or `@maxOf()`) are the fields that `entity` is grouped by.
+ * The aggregate is computed by the server when it is applied, and it cannot be saved or deleted.
+ *
+ * @example
+ * ```typescript
+ * @aggregate(Order, { refreshEvery: 60 })
+ * export class SalesByRegion extends ChiselEntity {
+ *     region: string;
+ *     @countOf() orders: number;
+ *     @sumOf("total") revenue: number;
+ * }
+ * ```
+ */
+export function aggregate<T extends ChiselEntity>(
+    _entity: { new (): T },
+    _options?: AggregateOptions,
+) {
+    return (_target: unknown) => {
+        // chisel-decorator, no content
+    };
+}
+
+/** Marks a `number` field of an aggregate that holds the number of instances in the group. */
+export function countOf() {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/** Marks a `number` field of an aggregate that holds the sum of `field` over the group. */
+export function sumOf(_field: string) {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/** Marks a `number` field of an aggregate that holds the average of `field` over the group. */
+export function avgOf(_field: string) {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/** Marks a field of an aggregate that holds the minimum of `field` over the group. */
+export function minOf(_field: string) {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/** Marks a field of an aggregate that holds the maximum of `field` over the group. */
+export function maxOf(_field: string) {
+    return (_target: unknown, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Marks an entity that must not be changed by accident.
+ *
+ * `chisel apply` refuses to change the fields of a locked entity (or to remove it, or to remove
+ * `@locked`) unless it is passed `--unlock EntityName`. Every such change (and every rejected
+ * attempt) is recorded in the audit log, see `chisel audit-log`.
+ */
+export function locked(_target: unknown): void {
+    // chisel-decorator, no content
+}
+
+/**
+ * Adds the `createdAt` and `updatedAt` fields to an entity.
+ *
+ * Both are set by the server when the entity is saved: `createdAt` when it is inserted and
+ * `updatedAt` on every write, so the values in the saved object are ignored. When the decorator
+ * is added to an entity with data, the existing rows get the time of the apply. To access the
+ * fields in the code (for example to filter or sort by them), declare them as read-only:
+ *
+ * @example
+ * ```typescript
+ * @timestamps
+ * export class Post extends ChiselEntity {
+ *     title: string;
+ *     readonly createdAt: Date;
+ *     readonly updatedAt: Date;
+ * }
+ * ```
+ */
+export function timestamps(_target: unknown): void {
+    // chisel-decorator, no content
+}
+
+/**
+ * Restricts the rows of an entity to the users that own them.
+ *
+ * `field` must be a string field that holds the id of the owner. The server only returns (and
+ * deletes) the rows whose owner is the user of the request, and when an entity is saved, `field`
+ * is set to that user; updating a row of another user fails. Anonymous users can neither read nor
+ * write any row. Requests with the `X-Chisel-Admin-Secret` header access all rows and can set the
+ * owner freely.
+ *
+ * @example
+ * ```typescript
+ * @ownedBy("userId")
+ * export class Note extends ChiselEntity {
+ *     userId: string;
+ *     text: string;
+ * }
+ * ```
+ */
+export function ownedBy(_field: string) {
+    return (_target: unknown) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Thrown by `save()` when the `@version` field of the saved entity is out of
+ * date, and by `delete()` when an `@onDelete("restrict")` reference still
+ * refers to a deleted entity.
+ */
+export class ConflictError extends Error {
+    constructor(msg: string) {
+        super(msg);
+    }
+}
+
+/** Thrown by `save()` when a value of the saved entity is out of the range of its field, such as
+ * a value of an enum field that is not one of the values of the enum. */
+export class ValidationError extends Error {
+    constructor(msg: string) {
+        super(msg);
+    }
+}
+
 export const requestContext: {
     rid: number | undefined;
     method: string;
@@ -1291,7 +2181,16 @@ function mergeSourceIntoEntity(
             );
         };
         const typeName = field.type.name;
-        if (typeName == "string" || typeName == "entityId") {
+        if (field.type.name == "entityId" && typeof fieldValue == "object") {
+            // the related entity was loaded by `ChiselCursor.with()`
+            const related = new ChiselEntity();
+            mergeIntoEntity(
+                field.type.entityName,
+                related as unknown as Record<string, unknown>,
+                fieldValue as Record<string, unknown>,
+            );
+            target[field.name] = related;
+        } else if (typeName == "string" || typeName == "entityId") {
             if (typeof fieldValue == "string") {
                 target[field.name] = fieldValue;
             } else {
@@ -1303,6 +2202,26 @@ function mergeSourceIntoEntity(
             } else {
                 throw err("number");
             }
+        } else if (typeName == "bigint") {
+            // This covers the CRUD path where we get the value as a string (or as a number, if it
+            // is small enough).
+            if (typeof fieldValue == "bigint") {
+                target[field.name] = fieldValue;
+            } else if (
+                typeof fieldValue == "string" ||
+                (typeof fieldValue == "number" &&
+                    Number.isSafeInteger(fieldValue))
+            ) {
+                try {
+                    target[field.name] = BigInt(fieldValue);
+                } catch {
+                    throw new Error(
+                        `failed to convert value '${fieldValue}' to bigint for field ${field.name}`,
+                    );
+                }
+            } else {
+                throw err("bigint");
+            }
         } else if (typeName == "boolean") {
             if (typeof fieldValue == "boolean") {
                 target[field.name] = fieldValue;
@@ -1339,6 +2258,8 @@ function mergeSourceIntoEntity(
                     `field ${field.name} of entity ${entityName} is Date, but provided value ${fieldValue} is not an instance of Date`,
                 );
             }
+        } else if (typeName == "json" || typeName == "geoPoint") {
+            target[field.name] = fieldValue;
         } else if (typeName == "array") {
             if (Array.isArray(fieldValue)) {
                 target[field.name] = fieldValue;
@@ -1377,3 +2298,12 @@ function assertNever(_: never) {
 }
 
 export type Id<Entity extends ChiselEntity> = Entity["id"];
+
+/**
+ * Fixed-precision decimal number with at most `Precision` digits (up to 18),
+ * `Scale` of which are after the decimal point, such as `Decimal<10, 2>` for
+ * amounts of money. The values are strings, such as `"12.50"`, and the database
+ * compares, sorts and sums them exactly. Writing a value that does not fit
+ * throws a `ValidationError` (CRUD endpoints respond with 422).
+ */
+export type Decimal<_Precision extends number, _Scale extends number> = string;
@@ -18,7 +18,10 @@ export type FilterExpr<T> = {
     "$and"?: FilterExpr<T>[];
     "$or"?: FilterExpr<T>[];
     "$not"?: FilterExpr<T>;
-} & { [key in keyof T]?: FieldFilter<T[key]> };
+} & { [key in keyof T]?: FieldFilter<T[key]> } & {
+    /** Filters by a value nested in a field, such as `"meta.path"` for the `path` of `meta`. */
+    [path: `${string}.${string}`]: unknown;
+};
 
 type PrimitiveValue = string | number | boolean | Date | null | undefined;
 type EntityValue = PrimitiveValue | { [key: string]: EntityValue };
@@ -43,12 +46,26 @@ export function evalFilter<T>(
         } else {
             return evalFieldFilter(
                 filter,
-                v[key] as EntityValue,
+                getPath(v, key) as EntityValue,
             );
         }
     });
 }
 
+/** Returns the value of `key` in `v`, where dots in `key` separate the keys of nested values. */
+function getPath(v: Record<string, unknown>, key: string): unknown {
+    if (key in v) {
+        return v[key];
+    }
+    return key.split(".").reduce(
+        (value: unknown, part) =>
+            value !== null && typeof value === "object"
+                ? (value as Record<string, unknown>)[part]
+                : undefined,
+        v,
+    );
+}
+
 function evalFieldFilter(
     filter: FieldFilter<unknown>,
     v: EntityValue,
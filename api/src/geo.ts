@@ -0,0 +1,74 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+/** Geographic location, with the latitude and longitude in degrees. */
+export type GeoPoint = {
+    lat: number;
+    lng: number;
+};
+
+/** Circle around a point, as given to `ChiselCursor.near()`. */
+export type NearOptions = GeoPoint & {
+    radiusMeters: number;
+};
+
+/** Mean radius of the Earth, in meters. */
+const EARTH_RADIUS_METERS = 6371008.8;
+
+function toRadians(degrees: number): number {
+    return degrees * Math.PI / 180;
+}
+
+function toDegrees(radians: number): number {
+    return radians * 180 / Math.PI;
+}
+
+/** Returns the great-circle distance between `a` and `b` in meters (the haversine formula). */
+export function distanceMeters(a: GeoPoint, b: GeoPoint): number {
+    const dLat = toRadians(b.lat - a.lat);
+    const dLng = toRadians(b.lng - a.lng);
+    const h = Math.sin(dLat / 2) ** 2 +
+        Math.cos(toRadians(a.lat)) * Math.cos(toRadians(b.lat)) *
+            Math.sin(dLng / 2) ** 2;
+    return 2 * EARTH_RADIUS_METERS * Math.asin(Math.min(1, Math.sqrt(h)));
+}
+
+/**
+ * Returns a filter expression that matches the points of `key` in the bounding
+ * box of the circle `near`. The expression is evaluated by the database, so it
+ * only needs to be refined by `distanceMeters()` afterwards.
+ */
+export function boundingBoxFilter(
+    key: string,
+    near: NearOptions,
+): Record<string, unknown> {
+    const dLat = toDegrees(near.radiusMeters / EARTH_RADIUS_METERS);
+    const minLat = near.lat - dLat;
+    const maxLat = near.lat + dLat;
+    const filter: Record<string, unknown> = {
+        [`${key}.lat`]: { "$gte": minLat, "$lte": maxLat },
+    };
+    // close to the poles, the circle covers all longitudes
+    if (minLat <= -90 || maxLat >= 90) {
+        return filter;
+    }
+    const sinDLng = Math.sin(toRadians(dLat)) / Math.cos(toRadians(near.lat));
+    const dLng = toDegrees(Math.asin(Math.min(1, sinDLng)));
+    const minLng = near.lng - dLng;
+    const maxLng = near.lng + dLng;
+    const lngKey = `${key}.lng`;
+    if (minLng < -180) {
+        // the box crosses the antimeridian
+        filter["$or"] = [
+            { [lngKey]: { "$gte": minLng + 360 } },
+            { [lngKey]: { "$lte": maxLng } },
+        ];
+    } else if (maxLng > 180) {
+        filter["$or"] = [
+            { [lngKey]: { "$gte": minLng } },
+            { [lngKey]: { "$lte": maxLng - 360 } },
+        ];
+    } else {
+        filter[lngKey] = { "$gte": minLng, "$lte": maxLng };
+    }
+    return filter;
+}
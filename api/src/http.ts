@@ -1,7 +1,17 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-import { loggedInUser, requestContext } from "./datastore.ts";
+import { claimedRoles } from "./context.ts";
+import type { RequestContextJson } from "./context.ts";
+import { CrudQueryError } from "./crud.ts";
+import {
+    ConflictError,
+    loggedInUser,
+    requestContext,
+    ValidationError,
+} from "./datastore.ts";
+import { IngestError } from "./ingest.ts";
 import { PermissionDeniedError } from "./policies.ts";
+import { takeUnhandledRejection } from "./rejections.ts";
 import { ChiselRequest } from "./request.ts";
 import { Router, RouterMatch } from "./routing.ts";
 import {
@@ -19,7 +29,7 @@ export type HttpRequest = {
     headers: [string, string][];
     body: Uint8Array;
     routingPath: string;
-    userId: string | undefined;
+    ctx: RequestContextJson;
 };
 
 // HTTP response that we give to Rust
@@ -27,16 +37,118 @@ export type HttpResponse = {
     status: number;
     headers: [string, string][];
     body: Uint8Array;
+    // overrides of the security headers of the version (see `RouteMap.securityHeaders()`)
+    securityHeaders?: [string, string | null][];
+    // trailers that the handler set in `ctx.trailers`
+    trailers?: [string, string][];
+};
+
+/** User principal that is resolved by an `AuthHook`. */
+export type AuthPrincipal = {
+    /** Id of the user, which the policies see as `ctx.userId`. */
+    userId: string;
+    /** Arbitrary claims about the user, which the policies see as `ctx.token`. */
+    claims?: Record<string, unknown>;
 };
 
+/**
+ * Authentication hook, which resolves the user that performs a request in
+ * authentication systems that ChiselStrike doesn't support out of the box.
+ *
+ * The hook is the default export of the file named by `auth_hook` in
+ * `Chisel.toml`. It is called with the raw request before routing, at most once
+ * per request, and returns the principal of the user (or `null` if the request
+ * is anonymous). If it throws, the request is rejected with status 401. The
+ * hook runs outside of the transaction of the request, so it cannot access
+ * entities.
+ */
+export type AuthHook = (
+    req: Request,
+) => Promise<AuthPrincipal | null | undefined> | AuthPrincipal | null | undefined;
+
 const versionId = opSync("op_chisel_get_version_id") as string;
 const isDebug = opSync("op_chisel_is_debug") as boolean;
 
+// Signal that aborts the outbound fetches of the HTTP request that is being handled, see
+// `installCancellableFetch()`
+let requestSignal: AbortSignal | undefined;
+
+// Replaces the global `fetch()` with a version that aborts the outbound requests when the HTTP
+// request that is being handled is cancelled (because the client disconnected or the deadline of
+// the request expired).
+export function installCancellableFetch() {
+    const originalFetch = globalThis.fetch;
+    globalThis.fetch = (input: RequestInfo | URL, init?: RequestInit) => {
+        if (requestSignal === undefined) {
+            return originalFetch(input, init);
+        }
+        const ownSignal = init?.signal ??
+            (input instanceof Request ? input.signal : undefined);
+        const signal = ownSignal
+            ? anySignal(requestSignal, ownSignal)
+            : requestSignal;
+        return originalFetch(input, { ...init, signal });
+    };
+}
+
+// Returns a signal that is aborted when any of the `signals` is aborted.
+function anySignal(...signals: AbortSignal[]): AbortSignal {
+    const controller = new AbortController();
+    for (const signal of signals) {
+        if (signal.aborted) {
+            controller.abort(signal.reason);
+            break;
+        }
+        signal.addEventListener(
+            "abort",
+            () => controller.abort(signal.reason),
+            { once: true },
+        );
+    }
+    return controller.signal;
+}
+
 // Handle an HTTP request. This should only be called from `run.ts`, see the `run()` function from details.
 export async function handleHttpRequest(
     router: Router,
     httpRequest: HttpRequest,
+    authHook: AuthHook | undefined,
 ): Promise<HttpResponse> {
+    const abortController = new AbortController();
+    opAsync("op_chisel_job_cancelled", requestContext.rid).then(
+        (cancelled) => {
+            if (cancelled) {
+                abortController.abort(
+                    new DOMException("Request was cancelled", "AbortError"),
+                );
+            }
+        },
+        () => {},
+    );
+    requestSignal = abortController.signal;
+    try {
+        return await handleRequest(router, httpRequest, authHook);
+    } finally {
+        requestSignal = undefined;
+    }
+}
+
+async function handleRequest(
+    router: Router,
+    httpRequest: HttpRequest,
+    authHook: AuthHook | undefined,
+): Promise<HttpResponse> {
+    if (authHook !== undefined) {
+        const unauthorized = await resolveUser(authHook, httpRequest);
+        if (unauthorized !== undefined) {
+            return unauthorized;
+        }
+    }
+    const forbidden = resolveTenant(httpRequest);
+    if (forbidden !== undefined) {
+        return forbidden;
+    }
+
     const routerMatch = router.lookup(
         httpRequest.method,
         httpRequest.routingPath,
@@ -64,7 +176,7 @@ export async function handleHttpRequest(
     // initialize the legacy global request context
     // note that this means that we can only handle a single request at a time!
     requestContext.method = httpRequest.method;
-    requestContext.userId = httpRequest.userId;
+    requestContext.userId = httpRequest.ctx.userId;
 
     // we must start the transaction before reading the logged-in user
     await opAsync("op_chisel_begin_transaction", requestContext.rid);
@@ -80,6 +192,8 @@ export async function handleHttpRequest(
             body: httpRequest.method == "GET" || httpRequest.method == "HEAD"
                 ? undefined
                 : httpRequest.body,
+            // aborted when the client disconnects or the deadline of the request expires
+            signal: requestSignal,
         },
         url.pathname,
         versionId,
@@ -87,6 +201,7 @@ export async function handleHttpRequest(
         url.searchParams,
         routerMatch.params,
         routerMatch.legacyFileName,
+        httpRequest.ctx,
     );
 
     try {
@@ -96,12 +211,34 @@ export async function handleHttpRequest(
         // code might still be running while the response is streaming
         const responseBody = await response.arrayBuffer();
 
+        // a promise rejected without a handler fails the request, even if the handler returned
+        // a response, so that the failure is not lost
+        const rejection = takeUnhandledRejection(requestContext.rid);
+        if (rejection !== undefined) {
+            throw rejection;
+        }
+
         await opAsync("op_chisel_commit_transaction", requestContext.rid);
 
+        if (
+            response.status >= 200 && response.status < 300 &&
+            opSync("op_chisel_sample_example")
+        ) {
+            recordExample(
+                httpRequest,
+                url,
+                routerMatch,
+                response,
+                responseBody,
+            );
+        }
+
         return {
             status: response.status,
             headers: Array.from(response.headers.entries()),
             body: new Uint8Array(responseBody),
+            securityHeaders: routerMatch.securityHeaders,
+            trailers: Array.from(chiselRequest.ctx.trailers.entries()),
         };
     } catch (e) {
         let description = "";
@@ -109,6 +246,15 @@ export async function handleHttpRequest(
 
         if (e instanceof PermissionDeniedError) {
             code = HTTP_STATUS.FORBIDDEN;
+        } else if (e instanceof ConflictError) {
+            code = HTTP_STATUS.CONFLICT;
+            description += `${e.message}\n`;
+        } else if (e instanceof IngestError || e instanceof CrudQueryError) {
+            code = HTTP_STATUS.BAD_REQUEST;
+            description += `${e.message}\n`;
+        } else if (e instanceof ValidationError) {
+            code = HTTP_STATUS.UNPROCESSABLE_ENTITY;
+            description += `${e.message}\n`;
         } else if (e instanceof ChiselError) {
             code = e.httpErrorCode;
             if (e.message !== undefined) {
@@ -141,6 +287,88 @@ export async function handleHttpRequest(
     }
 }
 
+// Records a successful request as the example of its route, see `--record-examples-rate`. The
+// example is stored in the background, so that recording never delays the response.
+function recordExample(
+    httpRequest: HttpRequest,
+    url: URL,
+    routerMatch: RouterMatch,
+    response: Response,
+    responseBody: ArrayBuffer,
+) {
+    const decoder = new TextDecoder();
+    const contentType = httpRequest.headers.find(([name]) =>
+        name.toLowerCase() === "content-type"
+    )?.[1];
+    opAsync("op_chisel_record_example", {
+        name: `${httpRequest.method} ${routerMatch.pathPattern}`,
+        method: httpRequest.method,
+        path: httpRequest.routingPath + url.search,
+        contentType,
+        body: httpRequest.body.length > 0
+            ? decoder.decode(httpRequest.body)
+            : undefined,
+        status: response.status,
+        response: response.headers.get("content-type")?.includes("json")
+            ? decoder.decode(responseBody)
+            : undefined,
+    }).catch((e) => console.warn(`Could not record route example: ${e}`));
+}
+
+// Calls the authentication hook and replaces the user of the request with the user that the hook
+// resolved. Returns a response if the hook rejected the request.
+async function resolveUser(
+    authHook: AuthHook,
+    httpRequest: HttpRequest,
+): Promise<HttpResponse | undefined> {
+    const url = new URL(httpRequest.uri, location.href);
+    const request = new Request(url.toString(), {
+        method: httpRequest.method,
+        headers: httpRequest.headers,
+        body: httpRequest.method == "GET" || httpRequest.method == "HEAD"
+            ? undefined
+            : httpRequest.body,
+    });
+
+    let principal;
+    try {
+        principal = await authHook(request) ?? null;
+    } catch (e) {
+        const message =
+            `Authentication hook rejected ${httpRequest.method} ${httpRequest.uri}: ${e}`;
+        console.error(message);
+        return isDebug
+            ? textResponse(HTTP_STATUS.UNAUTHORIZED, message)
+            : emptyResponse(HTTP_STATUS.UNAUTHORIZED);
+    }
+
+    opSync("op_chisel_set_authentication", requestContext.rid, principal);
+    httpRequest.ctx.userId = principal?.userId;
+    httpRequest.ctx.claims = principal?.claims;
+    httpRequest.ctx.roles = claimedRoles(principal?.claims);
+    return undefined;
+}
+
+// Resolves the tenant of the request when chiseld isolates the data per tenant. This happens after
+// the authentication hook, because the tenant may be a claim of the user that the hook resolved.
+// Returns a response if the tenant header of the request names another tenant than the user.
+function resolveTenant(httpRequest: HttpRequest): HttpResponse | undefined {
+    let tenantId;
+    try {
+        tenantId = opSync("op_chisel_resolve_tenant", requestContext.rid) as
+            | string
+            | null;
+    } catch (e) {
+        const message =
+            `Rejected ${httpRequest.method} ${httpRequest.uri}: ${e}`;
+        return isDebug
+            ? textResponse(HTTP_STATUS.FORBIDDEN, message)
+            : emptyResponse(HTTP_STATUS.FORBIDDEN);
+    }
+    httpRequest.ctx.tenantId = tenantId ?? undefined;
+    return undefined;
+}
+
 function handleRouterMatch(
     routerMatch: RouterMatch,
     request: ChiselRequest,
@@ -161,7 +389,11 @@ async function handleMiddlewareChain(
             request.loadReflection(match.reflection.handler.request);
         }
         // call the handler function
-        const responseLike = await match.handler.call(undefined, request);
+        const responseLike = await match.handler.call(
+            undefined,
+            request,
+            request.ctx,
+        );
         if (responseLike instanceof Response) {
             return responseLike;
         } else if (typeof responseLike === "string") {
@@ -177,6 +409,7 @@ async function handleMiddlewareChain(
             undefined,
             request,
             next,
+            request.ctx,
         );
     }
 }
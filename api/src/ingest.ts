@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+import { opAsync, responseFromJson } from "./utils.ts";
+import { ChiselEntity, requestContext } from "./datastore.ts";
+import type { ChiselEntityClass } from "./crud.ts";
+import { ChiselRequest } from "./request.ts";
+import { RouteMap } from "./routing.ts";
+
+/** Thrown when a row of an upload to an `ingest()` route cannot be parsed or does not match the
+ * entity type. No rows of such upload are inserted. */
+export class IngestError extends Error {
+    constructor(msg: string) {
+        super(msg);
+    }
+}
+
+const ingestContentTypes = [
+    "text/csv",
+    "application/x-ndjson",
+    "application/ndjson",
+    "application/jsonl",
+    "application/x-jsonlines",
+];
+
+/**
+ * Generates a route map with a `POST /` route that inserts the entities uploaded as CSV or NDJSON
+ * (newline-delimited JSON). The format is selected by the `content-type` of the request; the first
+ * line of a CSV upload is a header with the names of the fields.
+ *
+ * The upload is streamed to the server, which parses and validates the rows incrementally and
+ * inserts them in batches, so even large uploads are not buffered in memory. All rows are inserted
+ * in the transaction of the request: if any row is invalid, the request fails with status 400 and
+ * nothing is inserted.
+ * @example
+ * Put this in the file 'routes/comments/import.ts':
+ * ```typescript
+ * import { Comment } from "../../models/comment";
+ * export default ingest(Comment);
+ * ```
+ * @param entity Entity type
+ * @param config Configure the ingestion.
+ *  - `createResponse`: function to create response from the result `{ inserted }`. Defaults to
+ *    `responseFromJson()`.
+ *  - `batchSize`: number of rows that are inserted at once. Defaults to 1000.
+ * @returns A route map suitable as a default export in a route file.
+ */
+export function ingest<T extends ChiselEntity, E extends ChiselEntityClass<T>>(
+    entity: E,
+    config?: {
+        createResponse?: (
+            body: unknown,
+            status: number,
+        ) => Promise<Response> | Response;
+        batchSize?: number;
+    },
+): RouteMap {
+    const createResponse = config?.createResponse ?? responseFromJson;
+
+    async function post(req: ChiselRequest): Promise<Response> {
+        const contentType = req.headers.get("content-type") ?? "";
+        const mime = contentType.split(";")[0].trim().toLowerCase();
+        if (!ingestContentTypes.includes(mime)) {
+            return createResponse(
+                `Unsupported content type '${contentType}', expected one of: ${
+                    ingestContentTypes.join(", ")
+                }`,
+                415,
+            );
+        }
+
+        // when the route was recognized by the server, the body is streamed directly to
+        // `op_chisel_ingest` and `req` has an empty body
+        const inserted = await opAsync(
+            "op_chisel_ingest",
+            {
+                typeName: entity.name,
+                contentType,
+                batchSize: config?.batchSize ?? 1000,
+            },
+            new Uint8Array(await req.arrayBuffer()),
+            requestContext.rid,
+        ) as number;
+        return createResponse({ inserted }, 200);
+    }
+
+    return new RouteMap().route("POST", "/", post, {
+        handler: { kind: "ingest", entityName: entity.name },
+    });
+}
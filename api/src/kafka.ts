@@ -5,8 +5,13 @@ import { opAsync, opSync } from "./utils.ts";
 
 export type KafkaEvent = {
     topic: string;
+    partition: number;
+    offset: number;
     key: Uint8Array;
     value: Uint8Array;
+    decodedValue?: unknown;
+    /** Indices of the handlers that accept the event; all handlers if not given. */
+    handlers?: number[];
 };
 
 export class ChiselOutbox extends ChiselEntity {
@@ -18,20 +23,113 @@ export class ChiselOutbox extends ChiselEntity {
 }
 
 export class TopicMap {
-    topics: Record<string, EventHandler>;
+    topics: Record<string, TopicHandler[]>;
+    options: Record<string, TopicOptions>;
 
     constructor() {
         this.topics = {};
+        this.options = {};
     }
 
-    topic(topic: string, handler: EventHandler) {
-        this.topics[topic] = handler;
+    topic(
+        topic: string,
+        handler: EventHandler,
+        options?: TopicOptions,
+        filter?: EventFilter,
+    ) {
+        (this.topics[topic] ??= []).push({ handler, filter });
+        if (options !== undefined) {
+            if (this.options[topic] !== undefined) {
+                throw new Error(
+                    `Options of Kafka topic ${topic} are exported by multiple event handlers`,
+                );
+            }
+            this.options[topic] = options;
+        }
     }
 }
 
+type TopicHandler = {
+    handler: EventHandler;
+    filter?: EventFilter;
+};
+
+/**
+ * Filter of an event handler, exported as `filter` from the event handler
+ * module. The handler is called only for events that match all the
+ * conditions. Filters are evaluated before the event reaches JavaScript, so
+ * a topic can be split between multiple handlers (in `events/<topic>/*.ts`)
+ * without any overhead for the events that are filtered out.
+ */
+export type EventFilter = {
+    /** The key of the event must start with this prefix. */
+    keyPrefix?: string;
+    /** Headers of the event that must have exactly these values. */
+    headers?: Record<string, string>;
+    /**
+     * Fields of the value that must be equal to these values. The value is
+     * parsed as JSON (or decoded with the schema registry, see
+     * `TopicOptions.schema`). Nested fields are separated by dots, such as
+     * `"customer.country"`.
+     */
+    fields?: Record<string, unknown>;
+};
+
+/**
+ * Consumer settings for a topic, exported as `options` from the event handler module.
+ */
+export type TopicOptions = {
+    /**
+     * Where to start consuming the topic if the consumer group has not
+     * committed any offset for it yet. Defaults to the `--kafka-start-offset`
+     * option of `chiseld`.
+     */
+    startOffset?: "earliest" | "latest" | number;
+    /**
+     * If true, the offset of an event is committed only when the handler
+     * calls `event.commit()`, in the same transaction as the other changes
+     * made by the handler. Events that are not committed are delivered
+     * again, which gives at-least-once processing. An event that is still
+     * not committed after 3 redeliveries is skipped.
+     */
+    manualCommit?: boolean;
+    /**
+     * Decode the values of the topic with the schema registry given by the
+     * `--kafka-schema-registry` option of `chiseld`. Values in the Avro or
+     * JSON Schema formats are passed to the handler in
+     * `event.decodedValue`, and objects published on the topic with
+     * `publishEvent()` are encoded.
+     */
+    schema?: TopicSchema;
+};
+
+/**
+ * Schema registry settings of a topic.
+ */
+export type TopicSchema = {
+    /**
+     * Subject whose latest schema is used to encode the values published on
+     * the topic. Defaults to `<topic>-value`.
+     */
+    valueSubject?: string;
+};
+
 export type ChiselEvent = {
     key: Blob;
     value: Blob;
+    /**
+     * The value decoded with the schema registry. Only present if the topic
+     * has a `schema` and the value could be decoded.
+     */
+    decodedValue?: unknown;
+    partition: number;
+    offset: number;
+    /**
+     * Commits the offset of this event in the current transaction. Only
+     * needed for topics with `manualCommit`, other events are committed
+     * automatically.
+     */
+    commit: () => Promise<void>;
 };
 
 export type EventHandler = (event: ChiselEvent) => Promise<void>;
@@ -41,11 +139,14 @@ export async function handleKafkaEvent(
     topicMap: TopicMap,
     event: KafkaEvent,
 ): Promise<void> {
-    const handler = topicMap.topics[event.topic];
-    if (handler === undefined) {
+    const topicHandlers = topicMap.topics[event.topic];
+    if (topicHandlers === undefined) {
         // just ignore events on unknown topics
         return;
     }
+    const handlers = event.handlers === undefined
+        ? topicHandlers
+        : event.handlers.map((idx) => topicHandlers[idx]);
 
     // fake a global request context, so that the datastore operations work in event handler
     requestContext.method = "POST";
@@ -55,11 +156,20 @@ export async function handleKafkaEvent(
     const chiselEvent = {
         key: new Blob([event.key]),
         value: new Blob([event.value]),
+        decodedValue: event.decodedValue,
+        partition: event.partition,
+        offset: event.offset,
+        commit: async () => {
+            await opAsync("op_chisel_kafka_commit", requestContext.rid);
+        },
     };
 
     await opAsync("op_chisel_begin_transaction", requestContext.rid);
     try {
-        await handler(chiselEvent);
+        // all handlers that accept the event run in the same transaction
+        for (const { handler } of handlers) {
+            await handler(chiselEvent);
+        }
         await opAsync("op_chisel_commit_transaction", requestContext.rid);
     } catch (e) {
         let description = "";
@@ -83,7 +193,11 @@ export async function handleKafkaEvent(
 export type PublishEventArgs = {
     topic: string;
     key?: string | ArrayBuffer;
-    value?: string | ArrayBuffer;
+    /**
+     * Strings and buffers are published as they are, other objects are
+     * encoded with the latest schema of the topic in the schema registry.
+     */
+    value?: string | ArrayBuffer | Record<string, unknown>;
 };
 
 /**
@@ -104,17 +218,26 @@ export async function publishEvent(args: PublishEventArgs): Promise<void> {
     const seqNo = await ChiselOutbox.cursor().count();
     const topic = args.topic;
     const encoder = new TextEncoder();
-    const convert = (value?: string | ArrayBuffer) => {
+    const convert = async (
+        value?: string | ArrayBuffer | Record<string, unknown>,
+    ) => {
         if (!value) {
             return undefined;
         }
         if (typeof value === "string") {
             return encoder.encode(value);
         }
-        return value;
+        if (value instanceof ArrayBuffer) {
+            return value;
+        }
+        return await opAsync(
+            "op_chisel_kafka_encode",
+            topic,
+            value,
+        ) as Uint8Array;
     };
-    const key = convert(args.key);
-    const value = convert(args.value);
+    const key = await convert(args.key);
+    const value = await convert(args.value);
     await ChiselOutbox.create({
         timestamp,
         seqNo,
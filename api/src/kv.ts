@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { requestContext } from "./datastore.ts";
+import { opAsync } from "./utils.ts";
+
+/** Entry of the key-value store, as returned by `Chisel.kv.list()`. */
+export type KvEntry<T = unknown> = {
+    key: string;
+    value: T;
+    /** Time when the entry expires (in milliseconds since the UNIX epoch),
+     * `undefined` if it never expires. */
+    expiresAt: number | undefined;
+};
+
+type KvEntryJson = {
+    key: string;
+    value: unknown;
+    expiresAt: number | null;
+};
+
+/**
+ * Ordered key-value store of this version. Values are stored as JSON and the
+ * store is accessed in the transaction of the current request, so the changes
+ * are committed (or rolled back) together with the changes to the entities:
+ *
+ * ```typescript
+ * await Chisel.kv.set("sessions/alice", { cart: [] }, { ttlMs: 60 * 60 * 1000 });
+ * const session = await Chisel.kv.get<Session>("sessions/alice");
+ * for (const { key, value } of await Chisel.kv.list("sessions/")) {
+ *     // ...
+ * }
+ * ```
+ *
+ * The `kv` section of the policies restricts the keys under a prefix to some
+ * users, the same way as the `users` of a route:
+ *
+ * ```yaml
+ * kv:
+ *   - prefix: sessions
+ *     users: ^admin@
+ * ```
+ */
+export const kv = {
+    /** Returns the value of `key`, or `undefined` if there is no such key or
+     * it has expired. */
+    async get<T = unknown>(key: string): Promise<T | undefined> {
+        const entry = await opAsync(
+            "op_chisel_kv_get",
+            requestContext.rid,
+            key,
+        ) as KvEntryJson | null;
+        return entry === null ? undefined : entry.value as T;
+    },
+
+    /** Sets the value of `key`. If `ttlMs` is given, the entry expires after
+     * this many milliseconds. */
+    async set(
+        key: string,
+        value: unknown,
+        options?: { ttlMs?: number },
+    ): Promise<void> {
+        await opAsync(
+            "op_chisel_kv_set",
+            requestContext.rid,
+            key,
+            value,
+            options?.ttlMs ?? null,
+        );
+    },
+
+    /** Deletes `key`. Returns false if there was no such key. */
+    async delete(key: string): Promise<boolean> {
+        return await opAsync(
+            "op_chisel_kv_delete",
+            requestContext.rid,
+            key,
+        ) as boolean;
+    },
+
+    /** Returns the entries whose keys start with `prefix`, ordered by their
+     * keys (compared byte-wise). At most `limit` entries are returned. */
+    async list<T = unknown>(
+        prefix: string,
+        options?: { limit?: number },
+    ): Promise<KvEntry<T>[]> {
+        const entries = await opAsync(
+            "op_chisel_kv_list",
+            requestContext.rid,
+            prefix,
+            options?.limit ?? null,
+        ) as KvEntryJson[];
+        return entries.map((entry) => ({
+            key: entry.key,
+            value: entry.value as T,
+            expiresAt: entry.expiresAt ?? undefined,
+        }));
+    },
+};
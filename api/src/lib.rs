@@ -24,17 +24,28 @@ macro_rules! source_d_ts {
 lazy_static! {
     pub static ref SOURCES_JS: HashMap<&'static str, &'static str> = vec![
         source_js!("api"),
+        source_js!("blob"),
         source_js!("builtin_root"),
+        source_js!("context"),
         source_js!("crud"),
         source_js!("datastore"),
         source_js!("filter"),
+        source_js!("geo"),
         source_js!("http"),
+        source_js!("ingest"),
         source_js!("kafka"),
+        source_js!("kv"),
+        source_js!("raw_sql"),
+        source_js!("rejections"),
         source_js!("request"),
         source_js!("routing"),
         source_js!("run"),
+        source_js!("seed"),
+        source_js!("socket"),
         source_js!("special"),
+        source_js!("testing"),
         source_js!("type_system"),
+        source_js!("upload"),
         source_js!("utils"),
         source_js!("policies"),
         ("main.js", include_str!("main.js")),
@@ -43,17 +54,28 @@ lazy_static! {
     .collect();
     pub static ref SOURCES_D_TS: HashMap<&'static str, &'static str> = vec![
         source_d_ts!("api"),
+        source_d_ts!("blob"),
         source_d_ts!("builtin_root"),
+        source_d_ts!("context"),
         source_d_ts!("crud"),
         source_d_ts!("datastore"),
         source_d_ts!("filter"),
+        source_d_ts!("geo"),
         source_d_ts!("http"),
+        source_d_ts!("ingest"),
         source_d_ts!("kafka"),
+        source_d_ts!("kv"),
+        source_d_ts!("raw_sql"),
+        source_d_ts!("rejections"),
         source_d_ts!("request"),
         source_d_ts!("routing"),
         source_d_ts!("run"),
+        source_d_ts!("seed"),
+        source_d_ts!("socket"),
         source_d_ts!("special"),
+        source_d_ts!("testing"),
         source_d_ts!("type_system"),
+        source_d_ts!("upload"),
         source_d_ts!("utils"),
         source_d_ts!("policies"),
     ]
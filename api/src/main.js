@@ -4,8 +4,10 @@
 
 // Import the user-defined code from a special module prepared by `chisel
 // apply`. This transitively loads all user code.
-import { routeMap, topicMap } from "file:///__root.ts";
+// (We import the whole module, because versions applied by older `chisel` don't
+// export `authHook` and `seedMap`.)
+import * as root from "file:///__root.ts";
 
 // Continue in TypeScript.
 import run from "chisel://api/run.ts";
-await run(routeMap, topicMap);
+await run(root.routeMap, root.topicMap, root.authHook, root.seedMap);
@@ -4,6 +4,22 @@ export type ReqContext = {
     headers: Record<string, string>;
     apiVersion: string;
     userId: string;
+    /**
+     * Tenant of the request when chiseld isolates the data per tenant
+     * (`--tenant-claim` or `--tenant-header`), so that a policy can treat
+     * tenants differently.
+     */
+    tenantId: string | null;
+    /** Claims about the user, from the JWT or from the authentication hook. */
+    token: Record<string, unknown> | null;
+    /** Roles of the user, which are the strings in the `roles` claim. */
+    roles: string[];
+    /** Id of the API version that handles the request. */
+    versionId?: string;
+    /** Id of the request, see `ChiselContext.requestId`. */
+    requestId?: string;
+    /** IP address of the client that sent the request. */
+    clientIp?: string;
 };
 
 export const Action = {
@@ -13,6 +29,21 @@ export const Action = {
     Skip: 3,
 };
 
+/**
+ * Masks that an `onRead` policy can return to hide fields of an entity from
+ * the reader, e.g. `return Mask.fields({ email: Mask.Redact })`. The masked
+ * fields are also hidden from the responses of CRUD endpoints.
+ */
+export const Mask = {
+    /** Removes the field from the entity. */
+    Redact: "redact",
+    /** Replaces the value of the field with its hex SHA-256 hash. */
+    Hash: "hash",
+    fields: (fields: Record<string, "redact" | "hash">) => ({
+        __chiselFieldMask: fields,
+    }),
+} as const;
+
 export class PermissionDeniedError extends Error {
     constructor(msg: string) {
         super(msg);
@@ -0,0 +1,50 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { ChiselEntity, requestContext } from "./datastore.ts";
+import { opAsync, opSync } from "./utils.ts";
+
+/** Value that can be passed as a parameter of `Chisel.rawQuery()`. */
+export type RawSqlParam = string | number | boolean | null;
+
+/**
+ * Runs a raw SQL statement in the transaction of the current request and
+ * returns its rows as plain objects, keyed by the names of the columns. This is
+ * available only when chiseld runs with `--allow-raw-sql`, and not when the
+ * data is isolated per tenant.
+ *
+ * The parameters are referred to as `$1`, `$2`, ... in the statement, and the
+ * tables of the entities are named by `Chisel.table()`:
+ *
+ * ```typescript
+ * const rows = await Chisel.rawQuery<{ author: string; books: number }>(
+ *     `SELECT author, count(*) AS books FROM ${Chisel.table(Book)}
+ *      WHERE pages > $1 GROUP BY author`,
+ *     [100],
+ * );
+ * ```
+ *
+ * Policies, `@ownedBy` and the other checks of ChiselStrike are not applied to
+ * raw queries. The statement can only read and write data, and only in the
+ * tables of the entities of its version and of its key-value store. String
+ * literals must be plain `'...'` strings (pass other values as parameters).
+ * The type parameter is not checked, the values are
+ * returned as the database returns them: for example, dates are numbers of
+ * milliseconds and `ArrayBuffer`s are base64 strings.
+ *
+ * @version experimental
+ */
+export async function rawQuery<T = Record<string, unknown>>(
+    sql: string,
+    params: RawSqlParam[] = [],
+): Promise<T[]> {
+    return await opAsync("op_chisel_raw_query", {
+        sql,
+        params,
+    }, requestContext.rid) as T[];
+}
+
+/** Returns the quoted name of the table that stores the instances of
+ * `entity`, to be used in `Chisel.rawQuery()`. */
+export function table(entity: typeof ChiselEntity): string {
+    return opSync("op_chisel_raw_table_name", entity.name) as string;
+}
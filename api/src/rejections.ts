@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { requestContext } from "./datastore.ts";
+import { opSync } from "./utils.ts";
+
+// deno_core reports promises that were rejected without a handler, and promises that got a handler
+// after they had been rejected, through the callback installed by
+// `installUnhandledRejectionTracking()`
+const PROMISE_REJECT_WITH_NO_HANDLER = 0;
+const PROMISE_HANDLER_ADDED_AFTER_REJECT = 1;
+
+type Rejection = { rid: number | undefined; reason: unknown };
+
+// Promises that are rejected and still have no handler, with the job that was running when they
+// were rejected.
+const unhandledRejections = new Map<unknown, Rejection>();
+
+/** Error that fails a request in which a promise was rejected without a handler. */
+export class UnhandledRejectionError extends Error {
+    constructor(public reason: unknown) {
+        super(`Promise rejected without a handler: ${reason}`);
+        this.name = "UnhandledRejectionError";
+        if (reason instanceof Error && reason.stack !== undefined) {
+            this.stack = `${this.name}: ${reason.stack}`;
+        }
+    }
+}
+
+export function installUnhandledRejectionTracking() {
+    Deno.core.opSync(
+        "op_set_promise_reject_callback",
+        (type: number, promise: unknown, reason: unknown) => {
+            // we must not throw in here: deno would then keep the exception as pending, which
+            // eventually fails an unlucky user of the event loop instead of the job that
+            // rejected the promise
+            if (type == PROMISE_REJECT_WITH_NO_HANDLER) {
+                unhandledRejections.set(promise, {
+                    rid: requestContext.rid,
+                    reason,
+                });
+            } else if (type == PROMISE_HANDLER_ADDED_AFTER_REJECT) {
+                unhandledRejections.delete(promise);
+            }
+        },
+    );
+}
+
+/**
+ * Removes the unhandled rejections of the job `rid` and counts them in the metrics. Returns an
+ * error for the first of them, or `undefined` if there are none.
+ */
+export function takeUnhandledRejection(
+    rid: number | undefined,
+): UnhandledRejectionError | undefined {
+    let error: UnhandledRejectionError | undefined;
+    for (const [promise, rejection] of unhandledRejections) {
+        if (rejection.rid !== rid) {
+            continue;
+        }
+        unhandledRejections.delete(promise);
+        opSync("op_chisel_count_unhandled_rejection");
+        error ??= new UnhandledRejectionError(rejection.reason);
+    }
+    return error;
+}
+
+/**
+ * Logs and counts all unhandled rejections that are left after a job, including the ones that
+ * happened outside of any job. They are logged with the trace id of the current job.
+ */
+export function reportUnhandledRejections() {
+    for (const [promise, { reason }] of unhandledRejections) {
+        unhandledRejections.delete(promise);
+        opSync("op_chisel_count_unhandled_rejection");
+        console.error(new UnhandledRejectionError(reason).stack);
+    }
+}
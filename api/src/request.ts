@@ -1,5 +1,7 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+import { ChiselContext } from "./context.ts";
+import type { RequestContextJson } from "./context.ts";
 import type { AuthUser } from "./datastore.ts";
 import {
     ChiselError,
@@ -25,6 +27,8 @@ export type QueryParamsGeneric = Record<
  * @property {AuthUser} user - The currently logged in user. `undefined` if there isn't one.
  * @property {Query} query - Helper class containing parsed query string from the URL.
  * @property {Params} params - Helper class containing parameters from the URL path.
+ * @property {ChiselContext} ctx - Context of the request, which is also passed to the handler.
+ * @property {AbortSignal} signal - Aborted when the client disconnects or the deadline of the request expires.
  */
 export class ChiselRequest<
     TypedQuery extends QueryParamsGeneric = Record<string, string>,
@@ -35,6 +39,7 @@ export class ChiselRequest<
     public readonly user: AuthUser | undefined;
     public readonly query: Query;
     public readonly params: Params;
+    public readonly ctx: ChiselContext;
 
     private typedQueryParams: TypedQuery;
     private reflection: RequestReflection | undefined;
@@ -49,6 +54,7 @@ export class ChiselRequest<
         query: URLSearchParams,
         params: Record<string, string>,
         legacyFileName: string | undefined,
+        ctx: RequestContextJson,
     ) {
         super(input, init);
         this.path = path;
@@ -57,6 +63,7 @@ export class ChiselRequest<
         this.query = new Query(query);
         this.params = new Params(params);
         this.legacyFileName = legacyFileName;
+        this.ctx = new ChiselContext(ctx, user, this.headers);
         this.loadReflection(undefined);
     }
 
@@ -126,6 +133,23 @@ export class ChiselRequest<
         }
     }
 
+    /**
+     * Parses a `multipart/form-data` body, such as the body of an HTML form with file inputs.
+     * The parts are yielded as they are read from the body and their content is read chunk by
+     * chunk, see `MultipartPart`. Throws a `ChiselError` with status 400 if the body is not
+     * `multipart/form-data`.
+     */
+    multipart(): AsyncGenerator<MultipartPart> {
+        const boundary = multipartBoundary(this.headers.get("content-type"));
+        if (boundary === undefined) {
+            throw new ChiselError(
+                HTTP_STATUS.BAD_REQUEST,
+                "expected a multipart/form-data body",
+            );
+        }
+        return parseMultipart(this.body ?? emptyBody(), boundary);
+    }
+
     private queryToTyped(fields: Record<string, ReflectionType>): TypedQuery {
         const bad = (msg: string) => {
             return new ChiselError(HTTP_STATUS.BAD_REQUEST, msg);
@@ -465,3 +489,278 @@ function getBool(value: string | undefined): boolean | undefined {
             return true;
     }
 }
+
+/** A part of a `multipart/form-data` body, which is either a form field or an uploaded file.
+ *
+ * The content is read from the body as it is consumed, so it must be read before the next part
+ * is requested; the content of the parts that are skipped is discarded.
+ */
+export class MultipartPart {
+    /** Name of the form field. */
+    public readonly name: string;
+    /** Name of the uploaded file, `undefined` for parts that are not files. */
+    public readonly filename: string | undefined;
+    /** Content type of the part, `undefined` if it was not specified. */
+    public readonly contentType: string | undefined;
+    private consumed = false;
+
+    constructor(
+        public readonly headers: Headers,
+        private readonly content: AsyncIterable<Uint8Array>,
+    ) {
+        const disposition = headers.get("content-disposition") ?? "";
+        this.name = dispositionParam(disposition, "name") ?? "";
+        this.filename = dispositionParam(disposition, "filename");
+        this.contentType = headers.get("content-type") ?? undefined;
+    }
+
+    /** Returns the chunks of the content. The content can only be read once. */
+    chunks(): AsyncIterable<Uint8Array> {
+        if (this.consumed) {
+            throw new Error(`content of part '${this.name}' was already read`);
+        }
+        this.consumed = true;
+        return this.content;
+    }
+
+    /** Reads the whole content. */
+    async bytes(): Promise<Uint8Array> {
+        const chunks = [];
+        for await (const chunk of this.chunks()) {
+            chunks.push(chunk);
+        }
+        return concatBytes(chunks);
+    }
+
+    /** Reads the whole content as UTF-8 text. */
+    async text(): Promise<string> {
+        return new TextDecoder().decode(await this.bytes());
+    }
+}
+
+/** Returns the boundary of a `multipart/form-data` content type, `undefined` for other content
+ * types. */
+export function multipartBoundary(
+    contentType: string | null,
+): string | undefined {
+    const [mime, ...params] = (contentType ?? "").split(";");
+    if (mime.trim().toLowerCase() !== "multipart/form-data") {
+        return undefined;
+    }
+    for (const param of params) {
+        const match = param.trim().match(/^boundary=(?:"([^"]+)"|(.+))$/i);
+        if (match) {
+            return match[1] ?? match[2];
+        }
+    }
+    return undefined;
+}
+
+/** Parses a `multipart/form-data` body with the given boundary, yielding its parts as they are
+ * read. */
+export function parseMultipart(
+    body: AsyncIterable<Uint8Array>,
+    boundary: string,
+): AsyncGenerator<MultipartPart> {
+    return new MultipartReader(body, boundary).parts();
+}
+
+// deno-lint-ignore require-yield
+async function* emptyBody(): AsyncGenerator<Uint8Array> {
+    return;
+}
+
+function dispositionParam(
+    disposition: string,
+    param: string,
+): string | undefined {
+    const match = disposition.match(
+        new RegExp(`;\\s*${param}=(?:"([^"]*)"|([^;]*))`, "i"),
+    );
+    return match ? (match[1] ?? match[2].trim()) : undefined;
+}
+
+const encoder = new TextEncoder();
+const CRLF = encoder.encode("\r\n");
+const HEADERS_END = encoder.encode("\r\n\r\n");
+
+class MultipartReader {
+    private readonly body: AsyncIterator<Uint8Array>;
+    private readonly delimiter: Uint8Array;
+    // the body is prefixed with CRLF, so that the first boundary matches the delimiter too
+    private buf = CRLF;
+    private eof = false;
+    // index of the part whose content is being read, and whether the content ended
+    private partIdx = -1;
+    private contentDone = false;
+
+    constructor(body: AsyncIterable<Uint8Array>, boundary: string) {
+        this.body = body[Symbol.asyncIterator]();
+        this.delimiter = encoder.encode(`\r\n--${boundary}`);
+    }
+
+    async *parts(): AsyncGenerator<MultipartPart> {
+        // skip the preamble
+        await this.skipContent();
+        for (;;) {
+            await this.fillTo(2);
+            if (this.buf[0] === 0x2d && this.buf[1] === 0x2d) {
+                // "--" after the delimiter ends the body, the epilogue is ignored
+                return;
+            }
+            if (indexOf(this.buf.subarray(0, 2), CRLF) !== 0) {
+                throw malformed();
+            }
+            this.buf = this.buf.subarray(2);
+
+            const headers = new Headers();
+            let headersEnd = await this.find(HEADERS_END, CRLF);
+            if (headersEnd > 0) {
+                const lines = new TextDecoder().decode(
+                    this.buf.subarray(0, headersEnd),
+                );
+                for (const line of lines.split("\r\n")) {
+                    const colon = line.indexOf(":");
+                    if (colon <= 0) {
+                        throw malformed();
+                    }
+                    headers.append(
+                        line.slice(0, colon).trim(),
+                        line.slice(colon + 1).trim(),
+                    );
+                }
+                headersEnd += HEADERS_END.length;
+            } else {
+                // a part without headers
+                headersEnd = CRLF.length;
+            }
+            this.buf = this.buf.subarray(headersEnd);
+
+            this.partIdx += 1;
+            this.contentDone = false;
+            yield new MultipartPart(headers, this.content(this.partIdx));
+            await this.skipContent();
+        }
+    }
+
+    private async *content(partIdx: number): AsyncGenerator<Uint8Array> {
+        while (partIdx === this.partIdx) {
+            const chunk = await this.nextContentChunk();
+            if (chunk === undefined) {
+                return;
+            }
+            yield chunk;
+        }
+    }
+
+    private async skipContent() {
+        while (await this.nextContentChunk() !== undefined) {
+            // discard the content
+        }
+    }
+
+    // Returns the next chunk of the content of the current part, `undefined` after the delimiter
+    // that ends the content was consumed.
+    private async nextContentChunk(): Promise<Uint8Array | undefined> {
+        while (!this.contentDone) {
+            const idx = indexOf(this.buf, this.delimiter);
+            if (idx >= 0) {
+                const chunk = this.buf.subarray(0, idx);
+                this.buf = this.buf.subarray(idx + this.delimiter.length);
+                this.contentDone = true;
+                if (chunk.length > 0) {
+                    return chunk;
+                }
+                break;
+            }
+            // keep the bytes that may be the start of a delimiter
+            const safe = this.buf.length - (this.delimiter.length - 1);
+            if (safe > 0) {
+                const chunk = this.buf.subarray(0, safe);
+                this.buf = this.buf.subarray(safe);
+                return chunk;
+            }
+            if (!await this.fill()) {
+                throw malformed();
+            }
+        }
+        return undefined;
+    }
+
+    // Returns the index of `needle` in the buffer, reading more of the body as needed, or 0 if
+    // the buffer starts with `prefix`.
+    private async find(
+        needle: Uint8Array,
+        prefix: Uint8Array,
+    ): Promise<number> {
+        await this.fillTo(prefix.length);
+        if (indexOf(this.buf.subarray(0, prefix.length), prefix) === 0) {
+            return 0;
+        }
+        for (;;) {
+            const idx = indexOf(this.buf, needle);
+            if (idx >= 0) {
+                return idx;
+            }
+            if (!await this.fill()) {
+                throw malformed();
+            }
+        }
+    }
+
+    private async fillTo(length: number) {
+        while (this.buf.length < length) {
+            if (!await this.fill()) {
+                throw malformed();
+            }
+        }
+    }
+
+    private async fill(): Promise<boolean> {
+        if (this.eof) {
+            return false;
+        }
+        const next = await this.body.next();
+        if (next.done) {
+            this.eof = true;
+            return false;
+        }
+        this.buf = concatBytes([this.buf, next.value]);
+        return true;
+    }
+}
+
+function malformed(): ChiselError {
+    return new ChiselError(
+        HTTP_STATUS.BAD_REQUEST,
+        "malformed multipart/form-data body",
+    );
+}
+
+function indexOf(haystack: Uint8Array, needle: Uint8Array): number {
+    const last = haystack.length - needle.length;
+    let i = haystack.indexOf(needle[0]);
+    while (i >= 0 && i <= last) {
+        let j = 1;
+        while (j < needle.length && haystack[i + j] === needle[j]) {
+            j++;
+        }
+        if (j === needle.length) {
+            return i;
+        }
+        i = haystack.indexOf(needle[0], i + 1);
+    }
+    return -1;
+}
+
+function concatBytes(chunks: Uint8Array[]): Uint8Array {
+    const result = new Uint8Array(
+        chunks.reduce((len, chunk) => len + chunk.length, 0),
+    );
+    let offset = 0;
+    for (const chunk of chunks) {
+        result.set(chunk, offset);
+        offset += chunk.length;
+    }
+    return result;
+}
@@ -1,10 +1,12 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+import type { ChiselContext } from "./context.ts";
 import {
     ChiselRequest,
     QueryParamsGeneric,
     RequestReflection,
 } from "./request.ts";
+import type { SocketHandler } from "./socket.ts";
 import { JSONValue, ReflectionType } from "./utils.ts";
 
 /** Container for HTTP routes and their handlers.
@@ -43,12 +45,20 @@ import { JSONValue, ReflectionType } from "./utils.ts";
  */
 export class RouteMap {
     routes: Route[];
+    sockets: SocketRoute[];
+    examples: RouteExample[];
     middlewares: Middleware[];
+    headerOverrides: SecurityHeaderOverrides;
+    timeoutS: number | undefined;
 
     /** Creates an empty `RouteMap`. */
     constructor() {
         this.routes = [];
+        this.sockets = [];
+        this.examples = [];
         this.middlewares = [];
+        this.headerOverrides = {};
+        this.timeoutS = undefined;
     }
 
     /** Adds a route to the route map.
@@ -121,8 +131,87 @@ export class RouteMap {
                 middlewares: route.middlewares.concat(routeMap.middlewares),
                 legacyFileName: route.legacyFileName,
                 clientMetadata: route.clientMetadata,
+                securityHeaders: mergeSecurityHeaders(
+                    routeMap.headerOverrides,
+                    route.securityHeaders,
+                ),
+                timeoutS: route.timeoutS ?? routeMap.timeoutS,
             });
         }
+        for (const socket of routeMap.sockets) {
+            this.sockets.push({
+                pathPattern: path + socket.pathPattern,
+                handler: socket.handler,
+            });
+        }
+        for (const example of routeMap.examples) {
+            this.examples.push({ ...example, path: path + example.path });
+        }
+        return this;
+    }
+
+    /** Registers an example request of the routes in this route map.
+     *
+     * `chisel contract verify` sends the example request to a deployment and
+     * fails if the status or the shape of the JSON response differs from the
+     * example: the types of the values must match and all fields of the
+     * example must be present, but the values themselves may differ.
+     *
+     * ```typescript
+     * export default new RouteMap()
+     *      .get("/:id", getBook)
+     *      .example("get a book", {
+     *          path: "/1",
+     *          response: { title: "Dune", pages: 412 },
+     *      });
+     * ```
+     *
+     * @param name Name of the example, which is reported by `chisel contract
+     * verify`.
+     *
+     * @param example The request (the `path` is relative to this route map and
+     * may include a query string) and the expected response. The `method`
+     * defaults to `GET` and the `status` to 200. A `body` that is not a string
+     * is sent as JSON.
+     */
+    example(name: string, example: RouteExampleInit): this {
+        const headers = Object.entries(example.headers ?? {});
+        let body: string | undefined;
+        if (typeof example.body === "string") {
+            body = example.body;
+        } else if (example.body !== undefined) {
+            body = JSON.stringify(example.body);
+            const hasContentType = headers.some(([name]) =>
+                name.toLowerCase() === "content-type"
+            );
+            if (!hasContentType) {
+                headers.push(["content-type", "application/json"]);
+            }
+        }
+        this.examples.push({
+            name,
+            method: (example.method ?? "GET").toUpperCase(),
+            path: example.path[0] !== "/" ? "/" + example.path : example.path,
+            headers,
+            body,
+            status: example.status ?? 200,
+            response: example.response,
+        });
+        return this;
+    }
+
+    /** Adds a WebSocket route to the route map.
+     *
+     * When a client opens a WebSocket connection on the given `path`, the
+     * events on the connection are handled by the `handler`. The `path` is a
+     * pattern, see the documentation for `route()`. See also
+     * `Chisel.socket()`.
+     *
+     * Middlewares do not apply to WebSocket routes.
+     */
+    socket(path: string, handler: SocketHandler): this {
+        const pathPattern = path[0] !== "/" ? "/" + path : path;
+        this.sockets.push({ pathPattern, handler });
         return this;
     }
 
@@ -187,6 +276,58 @@ export class RouteMap {
         return this;
     }
 
+    /** Overrides the security headers for all routes in this route map.
+     *
+     * The security headers (such as `Strict-Transport-Security` or
+     * `Content-Security-Policy`) are configured per version in the
+     * `security_headers` section of the YAML policies and they are added to
+     * every response. Here you can replace the value of a header for these
+     * routes, or remove the header by passing `null`:
+     *
+     * ```typescript
+     * export default new RouteMap()
+     *      .get("/embed", getEmbed)
+     *      .securityHeaders({ "X-Frame-Options": null });
+     * ```
+     *
+     * Headers that the handler sets in its `Response` are never replaced.
+     * Overrides of a nested route map take precedence over the overrides of the
+     * route map that contains it.
+     */
+    securityHeaders(headers: SecurityHeaderOverrides): this {
+        for (const [name, value] of Object.entries(headers)) {
+            this.headerOverrides[name.toLowerCase()] = value;
+        }
+        return this;
+    }
+
+    /** Sets the deadline of the requests to all routes in this route map.
+     *
+     * This replaces the deadline that chiseld sets with
+     * `--request-timeout-s`. When a request does not finish within `seconds`,
+     * it is answered with status 504, the `signal` of its `ChiselRequest` is
+     * aborted together with its pending queries and fetches, and if the
+     * handler still keeps running, its JavaScript execution is terminated.
+     *
+     * ```typescript
+     * export default new RouteMap()
+     *      .get("/report", buildReport)
+     *      .timeout(30);
+     * ```
+     *
+     * The deadline of a nested route map takes precedence over the deadline
+     * of the route map that contains it.
+     */
+    timeout(seconds: number): this {
+        if (!Number.isFinite(seconds) || seconds <= 0) {
+            throw new TypeError(
+                `Timeout must be a positive number of seconds, got ${seconds}`,
+            );
+        }
+        this.timeoutS = seconds;
+        return this;
+    }
+
     // Convert a default export from a file inside `/routes` into a `RouteMap`.
     // This is an internal, private API.
     // TODO: remove the `legacyFileName` when we no longer need the legacy properties in `ChiselRequest`.
@@ -224,6 +365,47 @@ export type Route = {
     // TODO: remove this when we no longer need the legacy properties in `ChiselRequest`
     legacyFileName: string | undefined;
     clientMetadata?: ClientMetadata;
+    securityHeaders?: SecurityHeaderOverrides;
+    // deadline of the requests in seconds, see `RouteMap.timeout()`
+    timeoutS?: number;
+};
+
+/** Overrides of security headers: maps header names to their values, or to
+ * `null` to remove the header. See `RouteMap.securityHeaders()`. */
+export type SecurityHeaderOverrides = Record<string, string | null>;
+
+// Merges the overrides of a route map with the overrides of its route, which take precedence.
+function mergeSecurityHeaders(
+    outer: SecurityHeaderOverrides,
+    inner: SecurityHeaderOverrides | undefined,
+): SecurityHeaderOverrides {
+    return { ...outer, ...inner };
+}
+
+/** An example request and its expected response, see `RouteMap.example()`. */
+export type RouteExampleInit = {
+    method?: string;
+    path: string;
+    headers?: Record<string, string>;
+    body?: JSONValue;
+    status?: number;
+    response?: JSONValue;
+};
+
+// An example in the form that is reported to Rust (see `contract.rs`).
+export type RouteExample = {
+    name: string;
+    method: string;
+    path: string;
+    headers: [string, string][];
+    body: string | undefined;
+    status: number;
+    response: JSONValue | undefined;
+};
+
+export type SocketRoute = {
+    pathPattern: string;
+    handler: SocketHandler;
 };
 
 export type CrudHandler =
@@ -251,12 +433,21 @@ export type ClientMetadata = {
         | {
             kind: "generic";
             request: RequestReflection;
+        }
+        | {
+            kind: "ingest";
+            /// Name of the entity that is inserted by the `ingest()` route.
+            entityName: string;
+        }
+        | {
+            kind: "upload";
         };
 };
 
 /** A request handler that maps HTTP request to an HTTP response. */
 export type Handler = (
     req: ChiselRequest,
+    ctx: ChiselContext,
 ) => ResponseLike | Promise<ResponseLike>;
 
 /** Anything that we can convert to a `Response`:
@@ -273,6 +464,7 @@ export type PostHandler<
     TypedJsonBody = JSONValue,
 > = (
     req: ChiselRequest<TypedQuery, TypedJsonBody>,
+    ctx: ChiselContext,
 ) => ResponseLike | Promise<ResponseLike>;
 
 /** Anything that we can convert to a `RouteMap`:
@@ -301,6 +493,7 @@ export type Middleware = {
 export type MiddlewareHandler = (
     request: ChiselRequest,
     next: MiddlewareNext,
+    ctx: ChiselContext,
 ) => Promise<Response>;
 
 export type MiddlewareNext = (request: ChiselRequest) => Promise<Response>;
@@ -312,13 +505,31 @@ export type EndpointReflection = {
 
 export class Router {
     private routes: RouterRoute[];
+    private sockets: RouterSocketRoute[];
 
     constructor(routeMap: RouteMap) {
         this.routes = routeMap.routes.map((route) =>
-            new RouterRoute(route, routeMap.middlewares)
+            new RouterRoute(
+                route,
+                routeMap.middlewares,
+                routeMap.headerOverrides,
+            )
+        );
+        this.sockets = routeMap.sockets.map((socket) =>
+            new RouterSocketRoute(socket)
         );
     }
 
+    lookupSocket(path: string): RouterSocketMatch | null {
+        for (const socket of this.sockets) {
+            const match = socket.match(path);
+            if (match !== null) {
+                return match;
+            }
+        }
+        return null;
+    }
+
     lookup(
         method: string,
         path: string,
@@ -342,21 +553,56 @@ export class Router {
 
 export type RouterMatch = {
     params: Record<string, string>;
+    pathPattern: string;
     handler: Handler;
     middlewares: Middleware[];
     legacyFileName: string | undefined;
     reflection?: ClientMetadata;
+    securityHeaders: [string, string | null][];
+};
+
+export type RouterSocketMatch = {
+    params: Record<string, string>;
+    handler: SocketHandler;
 };
 
+class RouterSocketRoute {
+    pattern: URLPattern;
+    handler: SocketHandler;
+
+    constructor(socket: SocketRoute) {
+        this.pattern = new URLPattern(`http://dummy-host${socket.pathPattern}`);
+        this.handler = socket.handler;
+    }
+
+    match(path: string): RouterSocketMatch | null {
+        const baseUrl = "http://dummy-host";
+        let match = this.pattern.exec(path, baseUrl);
+        if (match === null && path[path.length - 1] !== "/") {
+            match = this.pattern.exec(path + "/", baseUrl);
+        }
+        if (match === null) {
+            return null;
+        }
+        return { params: match.pathname.groups, handler: this.handler };
+    }
+}
+
 class RouterRoute {
     pattern: URLPattern;
     pathOnlyPattern: URLPattern;
+    pathPattern: string;
     handler: Handler;
     middlewares: Middleware[];
     legacyFileName: string | undefined;
     reflection?: ClientMetadata;
+    securityHeaders: [string, string | null][];
 
-    constructor(route: Route, routeMapMiddlewares: Middleware[]) {
+    constructor(
+        route: Route,
+        routeMapMiddlewares: Middleware[],
+        routeMapHeaderOverrides: SecurityHeaderOverrides,
+    ) {
         // HACK: we use the hostname part of the URL Pattern to match the method
         const methodPattern = route.methods
             .map((method) => (method == "*" ? ".*" : method.toLowerCase()))
@@ -367,10 +613,14 @@ class RouterRoute {
         this.pathOnlyPattern = new URLPattern(
             `http://dummy-host${route.pathPattern}`,
         );
+        this.pathPattern = route.pathPattern;
         this.handler = route.handler;
         this.middlewares = route.middlewares.concat(routeMapMiddlewares);
         this.legacyFileName = route.legacyFileName;
         this.reflection = route.clientMetadata;
+        this.securityHeaders = Object.entries(
+            mergeSecurityHeaders(routeMapHeaderOverrides, route.securityHeaders),
+        );
     }
 
     match(method: string, path: string): RouterMatch | null {
@@ -386,10 +636,12 @@ class RouterRoute {
 
         return {
             params: match.pathname.groups,
+            pathPattern: this.pathPattern,
             handler: this.handler,
             middlewares: this.middlewares,
             legacyFileName: this.legacyFileName,
             reflection: this.reflection,
+            securityHeaders: this.securityHeaders,
         };
     }
 
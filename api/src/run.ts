@@ -1,45 +1,102 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-import { handleHttpRequest } from "./http.ts";
-import type { HttpRequest } from "./http.ts";
+import { handleHttpRequest, installCancellableFetch } from "./http.ts";
+import type { AuthHook, HttpRequest } from "./http.ts";
 import { handleKafkaEvent, TopicMap } from "./kafka.ts";
 import type { KafkaEvent } from "./kafka.ts";
 import { Router } from "./routing.ts";
+import { handleSocketJob } from "./socket.ts";
+import type { SocketJob } from "./socket.ts";
 import { RouteMap } from "./routing.ts";
 import type { RouteMapLike } from "./routing.ts";
+import { handleSeedJob, SeedMap } from "./seed.ts";
+import type { SeedJob } from "./seed.ts";
 import { specialAfter, specialBefore } from "./special.ts";
 import { opAsync, opSync } from "./utils.ts";
-import { requestContext } from "./datastore.ts";
+import {
+    ConflictError,
+    requestContext,
+    ValidationError,
+} from "./datastore.ts";
 import { DirtyEntityError, PermissionDeniedError } from "./policies.ts";
+import { CrudQueryError } from "./crud.ts";
+import { IngestError } from "./ingest.ts";
+import {
+    installUnhandledRejectionTracking,
+    reportUnhandledRejections,
+} from "./rejections.ts";
+import { installFrozenDate } from "./testing.ts";
 
 // A generic job that we receive from Rust
 type AcceptedJob =
     | { type: "http"; request: HttpRequest; ctxRid: number }
     | { type: "kafka"; event: KafkaEvent; ctxRid: number }
-    | { type: "outbox"; ctxRid: number };
+    | { type: "outbox"; ctxRid: number }
+    | { type: "socket"; ctxRid: number } & SocketJob
+    | { type: "seed"; ctxRid: number } & SeedJob;
 
 // This is the entry point into the TypeScript runtime, called from `main.js`
 // with structures that describe the user-defined behavior (such as how to
-// handle HTTP requests).
+// handle HTTP requests, how to resolve the user of a request and how to seed
+// the data).
 //
 // The async function returns when there are no more jobs to handle.
 export default async function run(
     userRouteMap: RouteMapLike,
     userTopicMap: TopicMap | undefined,
+    authHook: AuthHook | undefined,
+    userSeedMap: SeedMap | undefined,
 ): Promise<void> {
     // build the root RouteMap from the map provided by the user and a few internal routes
+    const userRoutes = RouteMap.convert(userRouteMap);
     const routeMap = new RouteMap();
     specialBefore(routeMap);
-    routeMap.prefix("/", RouteMap.convert(userRouteMap));
+    routeMap.prefix("/", userRoutes);
     specialAfter(routeMap);
     const router = new Router(routeMap);
 
+    redirectConsoleToLog();
+    installCancellableFetch();
+    if (opSync("op_chisel_is_testing")) {
+        installFrozenDate();
+    }
+
+    // report the user routes to Rust, they are described in the OpenAPI document
+    opSync(
+        "op_chisel_set_routes",
+        userRoutes.routes.map((route) => {
+            const handler = route.clientMetadata?.handler;
+            return {
+                methods: route.methods,
+                pathPattern: route.pathPattern,
+                crud: handler?.kind === "crud" ? handler.handler : undefined,
+                ingest: handler?.kind === "ingest"
+                    ? { entityName: handler.entityName }
+                    : undefined,
+                upload: handler?.kind === "upload",
+                timeoutS: route.timeoutS ?? userRoutes.timeoutS,
+            };
+        }),
+    );
+
+    // report the route examples to Rust, they are replayed by `chisel contract verify`
+    opSync("op_chisel_set_examples", userRoutes.examples);
+
     // subscribe to all requested Kafka topics
     const topicMap = userTopicMap ?? new TopicMap();
     for (const topic in topicMap.topics) {
-        opSync("op_chisel_subscribe_topic", topic);
+        opSync(
+            "op_chisel_subscribe_topic",
+            topic,
+            topicMap.options[topic],
+            topicMap.topics[topic].map(({ filter }) => filter ?? null),
+        );
     }
 
+    // report the seeds to Rust, which decides which of them should run
+    const seedMap = userSeedMap ?? new SeedMap();
+    opSync("op_chisel_set_seeds", Object.keys(seedMap.seeds));
+
     const workerIdx = Deno.core.opSync("op_chisel_get_worker_idx");
 
     // signal to Rust that we are ready to handle jobs
@@ -53,6 +110,14 @@ export default async function run(
     );
     // @ts-ignore: Dynamic property
     Deno.core.registerErrorClass("DirtyEntityError", DirtyEntityError);
+    // @ts-ignore: Dynamic property
+    Deno.core.registerErrorClass("ConflictError", ConflictError);
+    // @ts-ignore: Dynamic property
+    Deno.core.registerErrorClass("IngestError", IngestError);
+    // @ts-ignore: Dynamic property
+    Deno.core.registerErrorClass("CrudQueryError", CrudQueryError);
+    // @ts-ignore: Dynamic property
+    Deno.core.registerErrorClass("ValidationError", ValidationError);
 
     for (;;) {
         const job = await opAsync(
@@ -70,11 +135,18 @@ export default async function run(
             const httpResponse = await handleHttpRequest(
                 router,
                 job.request,
+                authHook,
             );
             opSync("op_chisel_http_respond", requestContext.rid, httpResponse);
         } else if (job.type == "kafka") {
             requestContext.rid = job.ctxRid;
             await handleKafkaEvent(topicMap, job.event);
+        } else if (job.type == "socket") {
+            requestContext.rid = job.ctxRid;
+            await handleSocketJob(router, job);
+        } else if (job.type == "seed") {
+            requestContext.rid = job.ctxRid;
+            await handleSeedJob(seedMap, job);
         } else if (job.type == "outbox") {
             if (workerIdx == 0) {
                 await opAsync("op_chisel_poll_outbox", job.ctxRid);
@@ -82,6 +154,7 @@ export default async function run(
         } else {
             throw new Error("Unknown type of AcceptedJob");
         }
+        reportUnhandledRejections();
         if (requestContext.rid !== undefined) {
             Deno.core.close(requestContext.rid);
             requestContext.rid = undefined;
@@ -89,17 +162,32 @@ export default async function run(
     }
 }
 
-// TODO: explore what this does in more detail
-Deno.core.opSync(
-    "op_set_promise_reject_callback",
-    (type: number, _promise: unknown, reason: unknown) => {
-        if (type == 0) { // PromiseRejectWithNoHandler
-            // Without this function deno pushes the exception to
-            // pending_promise_exceptions, which eventually causes an unlucky
-            // user of poll_event_loop to get an error. Since user code can
-            // create and reject a promise that lacks a handler, we have to do
-            // this. Throwing in here causes deno to at least log the stack.
-            throw new Error("Promise rejected without a handler: " + reason);
+// Redirects the output of `console` to the log of chiseld, so that the messages logged by user code
+// are formatted like the rest of the log and carry the trace id of the request that is being handled.
+function redirectConsoleToLog() {
+    const format = (args: unknown[]) =>
+        args.map((arg) => typeof arg === "string" ? arg : Deno.inspect(arg))
+            .join(" ");
+    const levels = {
+        debug: ["debug", "trace"],
+        info: ["log", "info"],
+        warn: ["warn"],
+        error: ["error"],
+    } as const;
+    for (const [level, methods] of Object.entries(levels)) {
+        for (const method of methods) {
+            // @ts-ignore: Dynamic property
+            console[method] = (...args: unknown[]) => {
+                opSync(
+                    "op_chisel_log",
+                    { level, message: format(args) },
+                    requestContext.rid ?? null,
+                );
+            };
         }
-    },
-);
+    }
+}
+
+// installed when the module is loaded, so that we also track the rejections in the top-level code
+// of user modules
+installUnhandledRejectionTracking();
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { requestContext } from "./datastore.ts";
+import { opAsync, opSync } from "./utils.ts";
+
+/**
+ * A seed script, which is the default export of a file in the `seeds/`
+ * directory. Seeds populate the datastore with initial data: `chisel seed`
+ * runs every seed of a version exactly once, in the order of the file names,
+ * each in its own transaction.
+ */
+export type SeedHandler = () => Promise<void> | void;
+
+export class SeedMap {
+    seeds: Record<string, SeedHandler>;
+
+    constructor() {
+        this.seeds = {};
+    }
+
+    seed(name: string, handler: SeedHandler) {
+        if (this.seeds[name] !== undefined) {
+            throw new Error(`Seed ${name} is defined multiple times`);
+        }
+        this.seeds[name] = handler;
+    }
+}
+
+// Seed that we are asked to run by Rust
+export type SeedJob = {
+    name: string;
+};
+
+export async function handleSeedJob(
+    seedMap: SeedMap,
+    job: SeedJob,
+): Promise<void> {
+    const handler = seedMap.seeds[job.name];
+    if (handler === undefined) {
+        opSync(
+            "op_chisel_seed_done",
+            requestContext.rid,
+            `There is no seed ${JSON.stringify(job.name)}`,
+        );
+        return;
+    }
+
+    // fake a global request context, so that the datastore operations work in the seed
+    requestContext.method = "POST";
+    requestContext.userId = undefined;
+
+    await opAsync("op_chisel_begin_transaction", requestContext.rid);
+    let error: string | null = null;
+    try {
+        await handler();
+        await opAsync("op_chisel_commit_transaction", requestContext.rid);
+    } catch (e) {
+        if (e instanceof Error && e.stack !== undefined) {
+            error = e.stack;
+        } else {
+            error = "" + e;
+        }
+        console.error(`Error in seed ${job.name}: ${error}`);
+
+        try {
+            opSync("op_chisel_rollback_transaction", requestContext.rid);
+        } catch (e) {
+            console.error(`Error when rolling back transaction: ${e}`);
+        }
+    }
+    opSync("op_chisel_seed_done", requestContext.rid, error);
+}
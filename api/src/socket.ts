@@ -0,0 +1,182 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { requestContext } from "./datastore.ts";
+import { RouteMap } from "./routing.ts";
+import type { Router } from "./routing.ts";
+import { blob } from "./blob.ts";
+import { kv } from "./kv.ts";
+import { rawQuery, table } from "./raw_sql.ts";
+import { testing } from "./testing.ts";
+import { opAsync, opSync } from "./utils.ts";
+
+// Socket event that we receive from Rust
+export type SocketEvent =
+    | { type: "open" }
+    | { type: "text"; data: string }
+    | { type: "binary"; data: Uint8Array }
+    | { type: "close"; code: number | null; reason: string };
+
+// Socket job that we receive from Rust
+export type SocketJob = {
+    socketId: number;
+    routingPath: string;
+    userId: string | undefined;
+    event: SocketEvent;
+};
+
+/** A WebSocket connection to a client.
+ *
+ * A new `ChiselSocket` object is passed to every call of a `SocketHandler`,
+ * but all events of a connection have the same `id` and they are handled in
+ * the same JavaScript runtime, in the order in which they were received.
+ */
+export class ChiselSocket {
+    constructor(
+        /** Unique id of the connection. */
+        readonly id: number,
+        /** URL path that was used to open the connection. */
+        readonly path: string,
+        /** Parameters matched from the route pattern (see `RouteMap.route()`). */
+        readonly params: Record<string, string>,
+    ) {}
+
+    /** Sends a text (for strings) or binary message to the client.
+     *
+     * In sandboxed requests (such as mirrored requests), nothing is sent.
+     */
+    send(data: string | ArrayBuffer | Uint8Array): void {
+        const message = typeof data === "string"
+            ? { type: "text", data }
+            : {
+                type: "binary",
+                data: data instanceof Uint8Array ? data : new Uint8Array(data),
+            };
+        opSync("op_chisel_socket_send", requestContext.rid, this.id, message);
+    }
+
+    /** Closes the connection. In sandboxed requests, the connection is not
+     * closed. */
+    close(code?: number, reason?: string): void {
+        opSync(
+            "op_chisel_socket_close",
+            requestContext.rid,
+            this.id,
+            code,
+            reason,
+        );
+    }
+}
+
+/** Handler of events on WebSocket connections.
+ *
+ * Every callback is called in its own transaction, just like HTTP request
+ * handlers. All callbacks are optional.
+ */
+export type SocketHandler = {
+    /** Called when a client opens a new connection. */
+    open?: (socket: ChiselSocket) => void | Promise<void>;
+    /** Called for every message from the client. Text messages are passed as
+     * strings and binary messages as `Uint8Array`. */
+    message?: (
+        socket: ChiselSocket,
+        data: string | Uint8Array,
+    ) => void | Promise<void>;
+    /** Called when the connection is closed, either by the client or by the
+     * server. */
+    close?: (
+        socket: ChiselSocket,
+        code: number | undefined,
+        reason: string,
+    ) => void | Promise<void>;
+};
+
+export const Chisel = {
+    /** Creates a `RouteMap` that accepts WebSocket connections. For example,
+     * to handle WebSocket connections on `/chat`, you might create a file
+     * `routes/chat.ts` with this content:
+     *
+     * ```typescript
+     * export default Chisel.socket({
+     *     async message(socket, data) {
+     *         await Message.create({ text: String(data) });
+     *         socket.send("ok");
+     *     },
+     * });
+     * ```
+     */
+    socket(handler: SocketHandler): RouteMap {
+        return new RouteMap().socket("/", handler);
+    },
+
+    /** Ordered key-value store of this version. */
+    kv,
+
+    /** Storage of binary objects in buckets. */
+    blob,
+
+    /** Testing-only API, available when chiseld runs with `--testing`. */
+    testing,
+
+    /** Raw SQL queries, available when chiseld runs with `--allow-raw-sql`. */
+    rawQuery,
+
+    /** Name of the table of an entity, for `rawQuery()`. */
+    table,
+};
+
+// Handle a socket event. This should only be called from `run.ts`, see the `run()` function from details.
+export async function handleSocketJob(
+    router: Router,
+    job: SocketJob,
+): Promise<void> {
+    const match = router.lookupSocket(job.routingPath);
+    const event = job.event;
+    if (event.type === "open") {
+        opSync("op_chisel_socket_accept", requestContext.rid, match !== null);
+    }
+    if (match === null) {
+        return;
+    }
+
+    // fake a global request context, so that the datastore operations work in the socket handler
+    requestContext.method = "GET";
+    requestContext.userId = job.userId;
+
+    const socket = new ChiselSocket(job.socketId, job.routingPath, match.params);
+    const handler = match.handler;
+    await opAsync("op_chisel_begin_transaction", requestContext.rid);
+    try {
+        if (event.type === "open") {
+            await handler.open?.(socket);
+        } else if (event.type === "text" || event.type === "binary") {
+            await handler.message?.(socket, event.data);
+        } else if (event.type === "close") {
+            await handler.close?.(socket, event.code ?? undefined, event.reason);
+        }
+        await opAsync("op_chisel_commit_transaction", requestContext.rid);
+    } catch (e) {
+        let description = "";
+        if (e instanceof Error && e.stack !== undefined) {
+            description = e.stack;
+        } else {
+            description = "" + e;
+        }
+        console.error(
+            `Error in socket ${job.socketId} (${job.routingPath}) on ${event.type}: ${description}`,
+        );
+
+        try {
+            opSync("op_chisel_rollback_transaction", requestContext.rid);
+        } catch (e) {
+            console.error(`Error when rolling back transaction: ${e}`);
+        }
+        if (event.type === "open") {
+            // 1011 = the server encountered an unexpected condition
+            try {
+                socket.close(1011, "Error when opening the socket");
+            } catch (_) {
+                // the socket is already closed
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { requestContext } from "./datastore.ts";
+import { opAsync, opSync } from "./utils.ts";
+
+/** Objects that are stored by `Chisel.testing.seed()`, keyed by the name of
+ * their entity. */
+export type FixtureBundle = Record<string, Record<string, unknown>[]>;
+
+function ensureTesting() {
+    if (!opSync("op_chisel_is_testing")) {
+        throw new Error(
+            "Chisel.testing is available only when chiseld runs with --testing",
+        );
+    }
+}
+
+/**
+ * Testing-only API, which is available only when chiseld runs with
+ * `--testing`. It lets test suites isolate their tests without touching the
+ * database directly:
+ *
+ * ```typescript
+ * await Chisel.testing.reset();
+ * const ids = await Chisel.testing.seed({
+ *     Person: [{ name: "Alice" }, { name: "Bob" }],
+ * });
+ * Chisel.testing.freezeTime(new Date("2022-01-01T00:00:00Z"));
+ * ```
+ */
+export const testing = {
+    /** Deletes all instances of all entities of this version and all entries
+     * of its key-value store (`Chisel.kv`). */
+    async reset(): Promise<void> {
+        await opAsync("op_chisel_testing_reset", requestContext.rid);
+    },
+
+    /** Stores the objects in `fixtures` and returns their ids, keyed by the
+     * name of their entity (in the same order as the objects). Fields that are
+     * missing get their default values. */
+    async seed(fixtures: FixtureBundle): Promise<Record<string, string[]>> {
+        ensureTesting();
+        const ids: Record<string, string[]> = {};
+        for (const [name, objects] of Object.entries(fixtures)) {
+            ids[name] = [];
+            for (const value of objects) {
+                const idTree = await opAsync("op_chisel_store", {
+                    name,
+                    value,
+                }, requestContext.rid) as { id: string };
+                ids[name].push(idTree.id);
+            }
+        }
+        return ids;
+    },
+
+    /** Freezes the time that `Date` reports (in all workers) at `time`. */
+    freezeTime(time: Date | number): void {
+        const timeMs = typeof time === "number" ? time : time.getTime();
+        opSync("op_chisel_testing_freeze_time", timeMs);
+    },
+
+    /** Lets the time that `Date` reports flow again. */
+    unfreezeTime(): void {
+        opSync("op_chisel_testing_freeze_time", null);
+    },
+};
+
+/** Replaces the global `Date` with a class that respects the time frozen by
+ * `Chisel.testing.freezeTime()`. This should only be called from `run.ts` when
+ * chiseld runs with `--testing`. */
+export function installFrozenDate() {
+    const RealDate = Date;
+    const now = () =>
+        (opSync("op_chisel_testing_now") as number | null) ?? RealDate.now();
+    class FrozenDate extends RealDate {
+        constructor(...args: unknown[]) {
+            if (args.length === 0) {
+                super(now());
+            } else {
+                // @ts-ignore: the arguments are passed to the real constructor as they are
+                super(...args);
+            }
+        }
+
+        static now(): number {
+            return now();
+        }
+    }
+    globalThis.Date = FrozenDate as DateConstructor;
+}
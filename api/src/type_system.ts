@@ -8,9 +8,12 @@ export type SimpleTypeSystem = {
 export type Type =
     | { name: "string" }
     | { name: "number" }
+    | { name: "bigint" }
     | { name: "boolean" }
     | { name: "jsDate" }
     | { name: "arrayBuffer" }
+    | { name: "json" }
+    | { name: "geoPoint" }
     | { name: "array"; elementType: Type }
     | { name: "entity"; entityName: string }
     | { name: "entityId"; entityName: string };
@@ -20,11 +23,20 @@ export type Field = {
     type: Type;
     isOptional: boolean;
     isUnique: boolean;
+    isVersion: boolean;
+    /** Set for the fields added by `@timestamps`, which are maintained by the server. */
+    timestamp?: "created" | "updated";
+    /** Set for the fields whose type is a string enum: the values of the enum. */
+    enumValues?: string[];
+    /** Set for the `ArrayBuffer` fields marked with `@maxBytes`: the size limit in bytes. */
+    maxBytes?: number;
 };
 
 export type Entity = {
     name: string;
     fields: Field[];
+    /** Set for entities marked with `@ownedBy`: the field that holds the owner of the row. */
+    ownedBy?: string;
 };
 
 export class TypeSystem {
@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+import { opAsync, responseFromJson } from "./utils.ts";
+import { requestContext } from "./datastore.ts";
+import type { ChiselContext } from "./context.ts";
+import {
+    ChiselRequest,
+    multipartBoundary,
+    MultipartPart,
+    parseMultipart,
+} from "./request.ts";
+import { ResponseLike, RouteMap } from "./routing.ts";
+
+/** Handles the parts of a `multipart/form-data` upload, see `upload()`. */
+export type UploadHandler = (
+    parts: AsyncIterable<MultipartPart>,
+    req: ChiselRequest,
+    ctx: ChiselContext,
+) => ResponseLike | Promise<ResponseLike>;
+
+/**
+ * Generates a route map with a `POST /` route that handles `multipart/form-data` uploads, such as
+ * the submissions of HTML forms with file inputs.
+ *
+ * The upload is streamed to the handler, which receives the parts as they arrive and reads their
+ * content chunk by chunk, so even large files are not buffered in memory. The body of such routes
+ * is not limited by `--max-body-size`.
+ * @example
+ * Put this in the file 'routes/files.ts':
+ * ```typescript
+ * import { upload } from "@chiselstrike/api";
+ * export default upload(async (parts) => {
+ *     const sizes: Record<string, number> = {};
+ *     for await (const part of parts) {
+ *         sizes[part.filename ?? part.name] = 0;
+ *         for await (const chunk of part.chunks()) {
+ *             sizes[part.filename ?? part.name] += chunk.length;
+ *         }
+ *     }
+ *     return sizes;
+ * });
+ * ```
+ * @param handler Function that handles the parts of the upload and returns the response.
+ * @returns A route map suitable as a default export in a route file.
+ */
+export function upload(handler: UploadHandler): RouteMap {
+    async function post(
+        req: ChiselRequest,
+        ctx: ChiselContext,
+    ): Promise<ResponseLike> {
+        const contentType = req.headers.get("content-type");
+        const boundary = multipartBoundary(contentType);
+        if (boundary === undefined) {
+            return responseFromJson(
+                `Unsupported content type '${contentType}', expected multipart/form-data`,
+                415,
+            );
+        }
+        return await handler(
+            parseMultipart(bodyChunks(req), boundary),
+            req,
+            ctx,
+        );
+    }
+
+    return new RouteMap().route("POST", "/", post, {
+        handler: { kind: "upload" },
+    });
+}
+
+async function* bodyChunks(req: ChiselRequest): AsyncGenerator<Uint8Array> {
+    // when the route was recognized by the server, the body is streamed by
+    // `op_chisel_read_body_chunk` and `req` has an empty body
+    let streamed = false;
+    for (;;) {
+        const chunk = await opAsync(
+            "op_chisel_read_body_chunk",
+            requestContext.rid,
+        ) as Uint8Array | null;
+        if (chunk === null) {
+            break;
+        }
+        streamed = true;
+        yield chunk;
+    }
+    if (!streamed && req.body !== null) {
+        yield* req.body;
+    }
+}
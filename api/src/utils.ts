@@ -1,15 +1,11 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-export function opSync(opName: string, a?: unknown, b?: unknown): unknown {
-    return Deno.core.opSync(opName, a, b);
+export function opSync(opName: string, ...args: unknown[]): unknown {
+    return Deno.core.opSync(opName, ...args);
 }
 
-export function opAsync(
-    opName: string,
-    a?: unknown,
-    b?: unknown,
-): Promise<unknown> {
-    return Deno.core.opAsync(opName, a, b);
+export function opAsync(opName: string, ...args: unknown[]): Promise<unknown> {
+    return Deno.core.opAsync(opName, ...args);
 }
 
 export type JSONValue = string | number | boolean | null | {
@@ -53,13 +49,16 @@ function stringifyJson(value: unknown, space?: string | number): string {
 
 // This function is duplicated in client_lib.ts. If you happen to improve it,
 // don't forget to update the other one as well.
-function valueToJson(v: unknown): JSONValue {
+export function valueToJson(v: unknown): JSONValue {
     if (v === undefined || v === null) {
         return null;
     } else if (typeof v === "string" || v instanceof String) {
         return v as string;
     } else if (typeof v === "number" || v instanceof Number) {
         return v as number;
+    } else if (typeof v === "bigint") {
+        // JSON numbers can't hold all 64-bit integers
+        return v.toString();
     } else if (typeof v === "boolean" || v instanceof Boolean) {
         return v as boolean;
     } else if (v instanceof Date) {
@@ -105,10 +104,13 @@ function valueToJson(v: unknown): JSONValue {
 /** HTTP status codes */
 export const HTTP_STATUS = {
     BAD_REQUEST: 400,
+    CONFLICT: 409,
+    UNPROCESSABLE_ENTITY: 422,
     FORBIDDEN: 403,
     INTERNAL_SERVER_ERROR: 500,
     METHOD_NOT_ALLOWED: 405,
     NOT_FOUND: 404,
+    UNAUTHORIZED: 401,
 };
 
 export class ChiselError {
@@ -0,0 +1,484 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Filter expressions of queries.
+//!
+//! This is the one representation of the expressions that `chiselc` compiles from the predicates
+//! of `filter()` and `find{Many,One}()` calls and that the server translates to SQL. Both sides use
+//! these types, and the JSON form is what the TypeScript runtime sends to the server.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// An expression.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "exprType")]
+pub enum Expr {
+    /// A value expression.
+    Value {
+        value: Value,
+    },
+    /// Expression for addressing function parameters of the current expression
+    Parameter {
+        position: usize,
+    },
+    /// Expression for addressing entity property
+    Property(PropertyAccess),
+    /// A binary expression.
+    Binary(BinaryExpr),
+    Not {
+        expr: Box<Self>,
+    },
+    /// A variable of the TypeScript code that the expression was compiled from. `chiselc` emits
+    /// it as a `Value` that holds the variable, so the server never receives it.
+    Variable {
+        name: String,
+    },
+}
+
+impl Expr {
+    pub fn negate(expr: Expr) -> Self {
+        Expr::Not {
+            expr: Box::new(expr),
+        }
+    }
+
+    /// Returns the names of the properties that the expression accesses, in the order of
+    /// appearance. For nested accesses such as `person.company.name`, only the outermost
+    /// property (`name`) is returned.
+    pub fn properties(&self) -> Vec<&str> {
+        let mut props = vec![];
+        self.collect_properties(&mut props);
+        props
+    }
+
+    fn collect_properties<'a>(&'a self, props: &mut Vec<&'a str>) {
+        match self {
+            Expr::Binary(binary) => {
+                binary.left.collect_properties(props);
+                binary.right.collect_properties(props);
+            }
+            Expr::Not { expr } => expr.collect_properties(props),
+            Expr::Property(access) => props.push(&access.property),
+            Expr::Value { .. } | Expr::Parameter { .. } | Expr::Variable { .. } => {}
+        }
+    }
+}
+
+impl From<Value> for Expr {
+    fn from(value: Value) -> Self {
+        Expr::Value { value }
+    }
+}
+
+impl From<BinaryExpr> for Expr {
+    fn from(expr: BinaryExpr) -> Self {
+        Expr::Binary(expr)
+    }
+}
+
+impl From<PropertyAccess> for Expr {
+    fn from(prop_access: PropertyAccess) -> Self {
+        Expr::Property(prop_access)
+    }
+}
+
+/// Various Values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    String(String),
+    Null,
+}
+
+impl Value {
+    fn try_from(json: &JsonValue) -> anyhow::Result<Self> {
+        let v = match json {
+            JsonValue::Null => Value::Null,
+            JsonValue::Bool(b) => Value::Bool(*b),
+            JsonValue::Number(n) if n.is_i64() => Value::I64(n.as_i64().unwrap()),
+            JsonValue::Number(n) if n.is_u64() => Value::U64(n.as_u64().unwrap()),
+            JsonValue::Number(n) if n.is_f64() => Value::F64(n.as_f64().unwrap()),
+            JsonValue::String(s) => Value::String(s.clone()),
+            JsonValue::Array(_) => {
+                anyhow::bail!(
+                    "Trying to convert JSON Array {json} to Expression Value which is not supported"
+                )
+            }
+            JsonValue::Object(_) => {
+                anyhow::bail!(
+                    "Trying to convert JSON Object {json} to Expression Value which is not supported"
+                )
+            }
+            _ => {
+                anyhow::bail!(
+                    "Trying to convert JSON value {json} to Expression Value which is not supported"
+                )
+            }
+        };
+        Ok(v)
+    }
+}
+
+impl From<&JsonValue> for Value {
+    fn from(json: &JsonValue) -> Self {
+        Self::try_from(json).unwrap()
+    }
+}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Value::Bool(val)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(val: u64) -> Self {
+        Value::U64(val)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(val: i64) -> Self {
+        Value::I64(val)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::F64(val)
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        Value::String(val)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        Value::String(val.to_owned())
+    }
+}
+
+impl From<Option<Value>> for Value {
+    fn from(opt: Option<Value>) -> Value {
+        match opt {
+            Some(val) => val,
+            None => Value::Null,
+        }
+    }
+}
+
+/// Expression of a property access on an Entity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropertyAccess {
+    /// Name of a property that will be accessed.
+    pub property: String,
+    /// Expression whose property will be accessed. The expression
+    /// can be either another Property access or a Parameter representing
+    /// an entity.
+    pub object: Box<Expr>,
+}
+
+/// A binary operator.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BinaryOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Like,
+    NotLike,
+}
+
+impl BinaryOp {
+    pub fn to_sql_string(&self) -> &str {
+        match &self {
+            Self::Eq => "=",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+            Self::And => "AND",
+            Self::Or => "OR",
+            Self::Like => "LIKE",
+            Self::NotLike => "NOT LIKE",
+        }
+    }
+}
+
+/// A binary expression.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BinaryExpr {
+    pub left: Box<Expr>,
+    pub op: BinaryOp,
+    pub right: Box<Expr>,
+}
+
+macro_rules! make_op_method {
+    ($MethodName:ident, $EnumName:ident) => {
+        pub fn $MethodName(lhs: Expr, rhs: Expr) -> Expr {
+            Self::new(BinaryOp::$EnumName, lhs, rhs).into()
+        }
+    };
+}
+
+impl BinaryExpr {
+    pub fn new(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        BinaryExpr {
+            left: Box::new(lhs),
+            op,
+            right: Box::new(rhs),
+        }
+    }
+
+    make_op_method! {eq, Eq}
+    make_op_method! {not_eq, NotEq}
+    make_op_method! {lt, Lt}
+    make_op_method! {lt_eq, LtEq}
+    make_op_method! {gt, Gt}
+    make_op_method! {gt_eq, GtEq}
+    make_op_method! {and, And}
+    make_op_method! {or, Or}
+    make_op_method! {like, Like}
+    make_op_method! {not_like, NotLike}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_value_parsing_bool() {
+        let expr: Expr = serde_json::from_str(
+            r#"{
+            "exprType": "Value",
+            "value": true
+        }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            expr,
+            Expr::Value {
+                value: Value::Bool(true)
+            }
+        ));
+    }
+
+    #[test]
+    fn test_value_parsing_u64() {
+        let expr = serde_json::from_str(
+            r#"{
+            "exprType": "Value",
+            "value": 42
+        }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            expr,
+            Expr::Value {
+                value: Value::U64(42)
+            }
+        ));
+    }
+
+    #[test]
+    fn test_value_parsing_i64() {
+        let expr = serde_json::from_str(
+            r#"{
+            "exprType": "Value",
+            "value": -42
+        }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            expr,
+            Expr::Value {
+                value: Value::I64(-42)
+            }
+        ));
+    }
+
+    #[test]
+    fn test_value_parsing_f64() {
+        let expr = serde_json::from_str(
+            r#"{
+            "exprType": "Value",
+            "value": 42.0
+        }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            expr,
+            Expr::Value {
+                value: Value::F64(_)
+            }
+        ));
+        if let Expr::Value {
+            value: Value::F64(v),
+        } = expr
+        {
+            assert_eq!(v, 42.0);
+        } else {
+            panic!("failed to match the value");
+        }
+    }
+
+    #[test]
+    fn test_value_parsing_string() {
+        let expr: Expr = serde_json::from_str(
+            r#"{
+            "exprType": "Value",
+            "value": "I'm the best value"
+        }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            expr,
+            Expr::Value {
+                value: Value::String(_)
+            }
+        ));
+        if let Expr::Value {
+            value: Value::String(v),
+        } = expr
+        {
+            assert_eq!(v, "I'm the best value");
+        } else {
+            panic!("failed to match the value");
+        }
+    }
+
+    #[test]
+    fn test_value_parsing_null() {
+        let expr = serde_json::from_str(
+            r#"{
+            "exprType": "Value",
+            "value": null
+        }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(expr, Expr::Value { value: Value::Null }));
+    }
+
+    #[test]
+    #[should_panic(expected = "missing field `value`")]
+    fn test_value_parsing_value_missing_panic() {
+        let _expr: Expr = serde_json::from_str(
+            r#"{
+            "exprType": "Value"
+        }"#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn not_round_trip() {
+        let expr = Expr::negate(BinaryExpr::eq(
+            Expr::Parameter { position: 0 },
+            Value::from(true).into(),
+        ));
+        let json = serde_json::to_string(&expr).unwrap();
+        assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), expr);
+    }
+
+    #[test]
+    fn properties() {
+        let nested = PropertyAccess {
+            property: "name".into(),
+            object: Box::new(
+                PropertyAccess {
+                    property: "company".into(),
+                    object: Box::new(Expr::Parameter { position: 0 }),
+                }
+                .into(),
+            ),
+        };
+        let age = PropertyAccess {
+            property: "age".into(),
+            object: Box::new(Expr::Parameter { position: 0 }),
+        };
+        let expr = BinaryExpr::or(
+            BinaryExpr::eq(nested.into(), Value::from("x").into()),
+            Expr::negate(BinaryExpr::gt(
+                age.into(),
+                Expr::Variable { name: "min".into() },
+            )),
+        );
+        assert_eq!(expr.properties(), vec!["name", "age"]);
+    }
+
+    fn arb_value() -> impl Strategy<Value = Value> {
+        // non-negative integers are parsed as `U64` and serde_json only parses floats with a
+        // few decimal digits exactly, so these are the values that survive a round trip unchanged
+        prop_oneof![
+            any::<bool>().prop_map(Value::Bool),
+            any::<u64>().prop_map(Value::U64),
+            (i64::MIN..0).prop_map(Value::I64),
+            (-100_000_000i64..100_000_000).prop_map(|n| Value::F64(n as f64 / 100.0)),
+            ".*".prop_map(Value::String),
+            Just(Value::Null),
+        ]
+    }
+
+    fn arb_op() -> impl Strategy<Value = BinaryOp> {
+        prop_oneof![
+            Just(BinaryOp::Eq),
+            Just(BinaryOp::NotEq),
+            Just(BinaryOp::Lt),
+            Just(BinaryOp::LtEq),
+            Just(BinaryOp::Gt),
+            Just(BinaryOp::GtEq),
+            Just(BinaryOp::And),
+            Just(BinaryOp::Or),
+            Just(BinaryOp::Like),
+            Just(BinaryOp::NotLike),
+        ]
+    }
+
+    fn arb_expr() -> impl Strategy<Value = Expr> {
+        let leaf = prop_oneof![
+            arb_value().prop_map(Expr::from),
+            (0..4usize).prop_map(|position| Expr::Parameter { position }),
+            "[a-z]+".prop_map(|name| Expr::Variable { name }),
+        ];
+        leaf.prop_recursive(4, 32, 2, |inner| {
+            prop_oneof![
+                (inner.clone(), arb_op(), inner.clone())
+                    .prop_map(|(left, op, right)| Expr::from(BinaryExpr::new(op, left, right))),
+                (inner.clone(), "[a-zA-Z.]+").prop_map(|(object, property)| {
+                    Expr::from(PropertyAccess {
+                        property,
+                        object: Box::new(object),
+                    })
+                }),
+                inner.prop_map(Expr::negate),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn json_round_trip(expr in arb_expr()) {
+            let json = serde_json::to_string(&expr).unwrap();
+            prop_assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), expr);
+        }
+    }
+}
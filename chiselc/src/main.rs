@@ -26,10 +26,18 @@ struct Opt {
     /// Entity types
     #[arg(short, long, default_value = "js")]
     target: Target,
+    /// Parse the input as TSX (implied by an input file with the `.tsx` extension).
+    #[arg(long)]
+    tsx: bool,
 }
 
 fn main() -> Result<()> {
     let opt = Opt::parse();
+    let tsx = opt.tsx
+        || opt
+            .input
+            .as_ref()
+            .map_or(false, |path| path.extension() == Some("tsx".as_ref()));
     let mut input = match opt.input {
         Some(path) => {
             let file = File::open(path.clone())
@@ -46,7 +54,7 @@ fn main() -> Result<()> {
     }
 
     let mut output = Cursor::new(Vec::new());
-    compile(data, symbols, opt.target, &mut output)?;
+    compile(data, symbols, opt.target, tsx, &mut output)?;
 
     match opt.output {
         Some(path) => {
@@ -57,6 +57,8 @@ fn canonical_transforms(module: &mut Module) {
 pub struct ParserContext {
     pub sm: Lrc<SourceMap>,
     error_buffer: ErrorBuffer,
+    /// Parse the code as TSX, which allows JSX elements, but not the `<T>expr` type assertions.
+    pub tsx: bool,
 }
 
 impl ParserContext {
@@ -75,6 +77,7 @@ impl ParserContext {
         let fm = self.sm.new_source_file(FileName::Anon, code);
         let config = swc_ecmascript::parser::TsConfig {
             decorators: true,
+            tsx: self.tsx,
             ..Default::default()
         };
         let lexer = Lexer::new(
@@ -108,9 +111,13 @@ pub fn compile<W: Write>(
     code: String,
     symbols: Symbols,
     target: Target,
+    tsx: bool,
     mut output: W,
 ) -> Result<()> {
-    let ctx = ParserContext::new();
+    let ctx = ParserContext {
+        tsx,
+        ..Default::default()
+    };
     // FIXME: We probably need a name for better error messages.
     let module = ctx.parse(code, false)?;
 
@@ -6,58 +6,9 @@
 //! represent queries. The queries are then transformed into this query
 //! intermediate representation.
 
+use chisel_expr::Expr;
 use indexmap::IndexSet;
 
-/// An expression.
-#[derive(Debug)]
-#[allow(clippy::enum_variant_names)]
-pub enum Expr {
-    /// A binary expression.
-    BinaryExpr(BinaryExpr),
-    /// An entity property.
-    PropertyAccess(PropertyAccessExpr),
-    /// An identifier expression.
-    Identifier(String),
-    /// A value expression.
-    Value(Value),
-}
-
-/// A binary expression.
-#[derive(Debug)]
-pub struct BinaryExpr {
-    pub left: Box<Expr>,
-    pub op: BinaryOp,
-    pub right: Box<Expr>,
-}
-
-/// A property access expression.
-#[derive(Debug)]
-pub struct PropertyAccessExpr {
-    pub object: Box<Expr>,
-    pub property: String,
-}
-
-/// A binary operator.
-#[derive(Debug)]
-pub enum BinaryOp {
-    And,
-    Eq,
-    Gt,
-    GtEq,
-    Lt,
-    LtEq,
-    NotEq,
-    Or,
-}
-
-/// A value expression
-#[derive(Debug)]
-pub enum Value {
-    Bool(bool),
-    Num(f64),
-    Str(String),
-}
-
 /// A query operator.
 #[derive(Debug)]
 pub enum Operator {
@@ -92,8 +43,6 @@ pub struct Filter {
     /// The post filter predicate expression AST that is always evaluateed at
     /// runtime, which allows expression with side-effects, for example.
     pub post_expr: Option<Box<swc_ecmascript::ast::Expr>>,
-    /// The parameters to this filter.
-    pub parameters: Vec<String>,
     /// The predicate expression to filter by.
     pub predicate: Expr,
     /// The input query operator that is filtered.
@@ -102,21 +51,10 @@ pub struct Filter {
 
 impl Filter {
     pub fn properties(&self) -> IndexSet<String> {
-        let mut props = IndexSet::new();
-        expr_to_props(&self.predicate, &mut props);
-        props
-    }
-}
-
-fn expr_to_props(expr: &Expr, props: &mut IndexSet<String>) {
-    match expr {
-        Expr::BinaryExpr(binary_expr) => {
-            expr_to_props(&binary_expr.left, props);
-            expr_to_props(&binary_expr.right, props);
-        }
-        Expr::PropertyAccess(property_access) => {
-            props.insert(property_access.property.clone());
-        }
-        _ => { /* Nothing to do */ }
+        self.predicate
+            .properties()
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect()
     }
 }
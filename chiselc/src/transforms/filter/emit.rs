@@ -11,19 +11,19 @@
 //! impact on this module or the `__find{Many.One}` and `__filter` internal
 //! functions we emit calls to.
 
-use crate::query::BinaryExpr as QBinaryExpr;
-use crate::query::BinaryOp as QBinaryOp;
-use crate::query::Expr as QExpr;
 use crate::query::Filter;
 use crate::query::Operator;
-use crate::query::PropertyAccessExpr;
-use crate::query::Value as QValue;
+use chisel_expr::BinaryExpr as QBinaryExpr;
+use chisel_expr::BinaryOp as QBinaryOp;
+use chisel_expr::Expr as QExpr;
+use chisel_expr::PropertyAccess;
+use chisel_expr::Value as QValue;
 use swc_atoms::JsWord;
 use swc_common::Span;
 use swc_ecmascript::ast::Number;
 use swc_ecmascript::ast::{
-    Bool, CallExpr, Callee, Expr, ExprOrSpread, Ident, KeyValueProp, Lit, MemberProp, ObjectLit,
-    Prop, PropName, PropOrSpread, Str,
+    Bool, CallExpr, Callee, Expr, ExprOrSpread, Ident, KeyValueProp, Lit, MemberProp, Null,
+    ObjectLit, Prop, PropName, PropOrSpread, Str,
 };
 
 /// Emit AST expression from query expression `operator`.
@@ -96,52 +96,33 @@ fn rewrite_filter_callee(callee: &Callee, function: &str) -> Callee {
 }
 
 fn filter_to_ts(filter: &Filter, span: Span) -> Expr {
-    expr_to_ts(&filter.predicate, &filter.parameters, span)
+    expr_to_ts(&filter.predicate, span)
 }
 
-fn expr_to_ts(expr: &QExpr, params: &[String], span: Span) -> Expr {
+fn expr_to_ts(expr: &QExpr, span: Span) -> Expr {
     match expr {
-        QExpr::BinaryExpr(binary_expr) => binary_expr_to_ts(binary_expr, params, span),
-        QExpr::PropertyAccess(property_access_expr) => {
-            property_access_to_ts(property_access_expr, params, span)
-        }
-        QExpr::Identifier(ident) => identifier_to_ts(ident, params, span),
-        QExpr::Value(val) => value_to_ts(val, span),
+        QExpr::Binary(binary_expr) => binary_expr_to_ts(binary_expr, span),
+        QExpr::Property(property_access_expr) => property_access_to_ts(property_access_expr, span),
+        QExpr::Parameter { position } => parameter_to_ts(*position, span),
+        QExpr::Variable { name } => variable_to_ts(name, span),
+        QExpr::Value { value } => value_to_ts(value, span),
+        QExpr::Not { expr } => not_to_ts(expr, span),
     }
 }
 
-fn binary_expr_to_ts(binary_expr: &QBinaryExpr, params: &[String], span: Span) -> Expr {
+fn binary_expr_to_ts(binary_expr: &QBinaryExpr, span: Span) -> Expr {
     let mut props = vec![make_expr_type("Binary", span)];
-    let left = expr_to_ts(&binary_expr.left, params, span);
-    let left = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
-        key: PropName::Ident(Ident {
-            span,
-            sym: JsWord::from("left"),
-            optional: false,
-        }),
-        value: Box::new(left),
-    })));
-    props.push(left);
-    let op = binary_op_to_ts(&binary_expr.op, span);
-    let op = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
-        key: PropName::Ident(Ident {
-            span,
-            sym: JsWord::from("op"),
-            optional: false,
-        }),
-        value: Box::new(op),
-    })));
-    props.push(op);
-    let right = expr_to_ts(&binary_expr.right, params, span);
-    let right = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
-        key: PropName::Ident(Ident {
-            span,
-            sym: JsWord::from("right"),
-            optional: false,
-        }),
-        value: Box::new(right),
-    })));
-    props.push(right);
+    props.push(make_prop("left", expr_to_ts(&binary_expr.left, span), span));
+    props.push(make_prop(
+        "op",
+        binary_op_to_ts(&binary_expr.op, span),
+        span,
+    ));
+    props.push(make_prop(
+        "right",
+        expr_to_ts(&binary_expr.right, span),
+        span,
+    ));
     Expr::Object(ObjectLit { span, props })
 }
 
@@ -155,86 +136,74 @@ fn binary_op_to_ts(binary_op: &QBinaryOp, span: Span) -> Expr {
         QBinaryOp::LtEq => "LtEq",
         QBinaryOp::NotEq => "NotEq",
         QBinaryOp::Or => "Or",
+        QBinaryOp::Like => "Like",
+        QBinaryOp::NotLike => "NotLike",
     };
     make_str_lit(raw_op, span)
 }
 
-fn property_access_to_ts(
-    property_access_expr: &PropertyAccessExpr,
-    params: &[String],
-    span: Span,
-) -> Expr {
+fn property_access_to_ts(property_access_expr: &PropertyAccess, span: Span) -> Expr {
     let mut props = vec![make_expr_type("Property", span)];
-    let obj = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
-        key: PropName::Ident(Ident {
-            span,
-            sym: JsWord::from("object"),
-            optional: false,
-        }),
-        value: Box::new(expr_to_ts(&property_access_expr.object, params, span)),
-    })));
-    props.push(obj);
-    let prop = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
-        key: PropName::Ident(Ident {
-            span,
-            sym: JsWord::from("property"),
-            optional: false,
-        }),
-        value: Box::new(make_str_lit(&property_access_expr.property, span)),
-    })));
-    props.push(prop);
+    let obj = expr_to_ts(&property_access_expr.object, span);
+    props.push(make_prop("object", obj, span));
+    let prop = make_str_lit(&property_access_expr.property, span);
+    props.push(make_prop("property", prop, span));
     Expr::Object(ObjectLit { span, props })
 }
 
-fn identifier_to_ts(ident: &str, params: &[String], span: Span) -> Expr {
-    let mut props = vec![];
-    if let Some(pos) = params.iter().position(|param| param == ident) {
-        props.push(make_expr_type("Parameter", span));
-        let lit = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
-            key: PropName::Ident(Ident {
-                span,
-                sym: JsWord::from("position"),
-                optional: false,
-            }),
-            value: Box::new(make_num_lit(&(pos as f64), span)),
-        })));
-        props.push(lit);
-    } else {
-        props.push(make_expr_type("Value", span));
-        let lit = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
-            key: PropName::Ident(Ident {
-                span,
-                sym: JsWord::from("value"),
-                optional: false,
-            }),
-            value: Box::new(Expr::Ident(Ident {
-                span,
-                sym: JsWord::from(ident),
-                optional: false,
-            })),
-        })));
-        props.push(lit);
-    }
+fn parameter_to_ts(position: usize, span: Span) -> Expr {
+    let props = vec![
+        make_expr_type("Parameter", span),
+        make_prop("position", make_num_lit(&(position as f64), span), span),
+    ];
+    Expr::Object(ObjectLit { span, props })
+}
+
+/// Variables are evaluated by the runtime, so they are emitted as a `Value`
+/// that refers to the variable.
+fn variable_to_ts(name: &str, span: Span) -> Expr {
+    let ident = Expr::Ident(Ident {
+        span,
+        sym: JsWord::from(name),
+        optional: false,
+    });
+    let props = vec![
+        make_expr_type("Value", span),
+        make_prop("value", ident, span),
+    ];
     Expr::Object(ObjectLit { span, props })
 }
 
-fn value_to_ts(lit: &QValue, span: Span) -> Expr {
-    let mut props = vec![make_expr_type("Value", span)];
-    let lit = match lit {
+fn value_to_ts(value: &QValue, span: Span) -> Expr {
+    let lit = match value {
         QValue::Bool(v) => make_bool_lit(*v, span),
-        QValue::Str(s) => make_str_lit(s, span),
-        QValue::Num(n) => make_num_lit(n, span),
+        QValue::String(s) => make_str_lit(s, span),
+        QValue::U64(n) => make_num_lit(&(*n as f64), span),
+        QValue::I64(n) => make_num_lit(&(*n as f64), span),
+        QValue::F64(n) => make_num_lit(n, span),
+        QValue::Null => Expr::Lit(Lit::Null(Null { span })),
     };
-    let lit = PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
+    let props = vec![make_expr_type("Value", span), make_prop("value", lit, span)];
+    Expr::Object(ObjectLit { span, props })
+}
+
+fn not_to_ts(expr: &QExpr, span: Span) -> Expr {
+    let props = vec![
+        make_expr_type("Not", span),
+        make_prop("expr", expr_to_ts(expr, span), span),
+    ];
+    Expr::Object(ObjectLit { span, props })
+}
+
+fn make_prop(key: &str, value: Expr, span: Span) -> PropOrSpread {
+    PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
         key: PropName::Ident(Ident {
             span,
-            sym: JsWord::from("value"),
+            sym: JsWord::from(key),
             optional: false,
         }),
-        value: Box::new(lit),
-    })));
-    props.push(lit);
-    Expr::Object(ObjectLit { span, props })
+        value: Box::new(value),
+    })))
 }
 
 fn make_expr_type(expr_type: &str, span: Span) -> PropOrSpread {
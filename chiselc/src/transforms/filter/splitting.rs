@@ -24,8 +24,8 @@
 //! which then allows the compiler to transform the first `filter()` call's
 //! predicate function into a query expression.
 
-use crate::query::Expr as QExpr;
 use crate::transforms::utils::convert_filter_expr;
+use chisel_expr::Expr as QExpr;
 use swc_ecmascript::ast::{ArrowExpr, BinExpr, BinaryOp, BlockStmtOrExpr, Expr, Stmt};
 
 /// Splits an expression if needed and then converts it to a query expression.
@@ -33,8 +33,11 @@ use swc_ecmascript::ast::{ArrowExpr, BinExpr, BinaryOp, BlockStmtOrExpr, Expr, S
 /// The function returns a tuple of `Option<(Expr, QExpr)>` that represents the
 /// converted query in AST and IR form and `Option<Expr>` that represents the
 /// unconvertable post expression generated by the split.
-pub fn split_and_convert_expr(expr: &Expr) -> (Option<(Expr, QExpr)>, Option<Expr>) {
-    if let Some(qexpr) = convert_filter_expr(expr) {
+pub fn split_and_convert_expr(
+    expr: &Expr,
+    params: &[String],
+) -> (Option<(Expr, QExpr)>, Option<Expr>) {
+    if let Some(qexpr) = convert_filter_expr(expr, params) {
         return (Some((expr.clone(), qexpr)), None);
     }
     match expr {
@@ -44,7 +47,7 @@ pub fn split_and_convert_expr(expr: &Expr) -> (Option<(Expr, QExpr)>, Option<Exp
             right,
             span: _,
         }) => {
-            if let Some(qleft) = convert_filter_expr(left) {
+            if let Some(qleft) = convert_filter_expr(left, params) {
                 (Some((*left.to_owned(), qleft)), Some(*right.to_owned()))
             } else {
                 (None, None)
@@ -1,17 +1,17 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 use crate::filtering::FilterProperties;
-use crate::query::BinaryExpr as QBinaryExpr;
-use crate::query::BinaryOp as QBinaryOp;
-use crate::query::Expr as QExpr;
 use crate::query::Filter as QFilter;
 use crate::query::Operator as QOperator;
-use crate::query::PropertyAccessExpr as QPropertyAccessExpr;
 use crate::query::Scan as QScan;
-use crate::query::Value as QValue;
 use crate::transforms::filter::splitting::{rewrite_filter_arrow, split_and_convert_expr};
 use crate::utils::pat_to_string;
 use anyhow::Result;
+use chisel_expr::BinaryExpr as QBinaryExpr;
+use chisel_expr::BinaryOp as QBinaryOp;
+use chisel_expr::Expr as QExpr;
+use chisel_expr::PropertyAccess as QPropertyAccess;
+use chisel_expr::Value as QValue;
 
 use swc_ecmascript::ast::{
     BinExpr, BinaryOp, BlockStmtOrExpr, CallExpr, Callee, Expr, Ident, Lit, MemberExpr, MemberProp,
@@ -69,6 +69,7 @@ pub fn extract_filter(
     }
     let param = &params[0];
     let param = pat_to_string(param).unwrap();
+    let params = [param.clone()];
     let (query, post_expr) = match &arrow.body {
         BlockStmtOrExpr::BlockStmt(block_stmt) => {
             if block_stmt.stmts.len() != 1 {
@@ -81,13 +82,13 @@ pub fn extract_filter(
                 }
             };
             match &return_stmt.arg {
-                Some(expr) => split_and_convert_expr(expr),
+                Some(expr) => split_and_convert_expr(expr, &params),
                 None => {
                     todo!();
                 }
             }
         }
-        BlockStmtOrExpr::Expr(expr) => split_and_convert_expr(expr),
+        BlockStmtOrExpr::Expr(expr) => split_and_convert_expr(expr, &params),
     };
     let (query_expr, predicate) = match query {
         Some(query) => query,
@@ -100,7 +101,6 @@ pub fn extract_filter(
         call_expr: call_expr.to_owned(),
         query_expr,
         post_expr,
-        parameters: vec![param.clone()],
         input: Box::new(QOperator::Scan(QScan {
             entity_type: entity_type.clone(),
             alias: param,
@@ -111,49 +111,57 @@ pub fn extract_filter(
     (Some(Box::new(QOperator::Filter(filter))), props)
 }
 
-pub fn convert_filter_expr(expr: &Expr) -> Option<QExpr> {
+/// Converts a filter predicate to a query expression. Identifiers that name
+/// one of the arrow function `params` become parameters of the expression,
+/// other identifiers are variables of the surrounding code.
+pub fn convert_filter_expr(expr: &Expr, params: &[String]) -> Option<QExpr> {
     match expr {
-        Expr::Bin(bin_expr) => convert_bin_expr(bin_expr),
-        Expr::Lit(Lit::Bool(value)) => Some(QExpr::Value(QValue::Bool(value.value))),
+        Expr::Bin(bin_expr) => convert_bin_expr(bin_expr, params),
+        Expr::Lit(Lit::Bool(value)) => Some(QValue::Bool(value.value).into()),
         _ => None,
     }
 }
 
-pub fn convert_bin_expr(expr: &BinExpr) -> Option<QExpr> {
-    let left = convert_expr(&expr.left);
+pub fn convert_bin_expr(expr: &BinExpr, params: &[String]) -> Option<QExpr> {
+    let left = convert_expr(&expr.left, params);
     let op = convert_binary_op(&expr.op);
-    let right = convert_expr(&expr.right);
+    let right = convert_expr(&expr.right, params);
     if let (Some(left), Some(op), Some(right)) = (left, op, right) {
-        Some(QExpr::BinaryExpr(QBinaryExpr {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        }))
+        Some(QBinaryExpr::new(op, left, right).into())
     } else {
         None
     }
 }
 
-fn convert_expr(expr: &Expr) -> Option<QExpr> {
+fn convert_expr(expr: &Expr, params: &[String]) -> Option<QExpr> {
     match expr {
-        Expr::Bin(bin_expr) => convert_bin_expr(bin_expr),
-        Expr::Paren(paren_expr) => convert_expr(&paren_expr.expr),
-        Expr::Lit(Lit::Num(number)) => Some(QExpr::Value(QValue::Num(number.value))),
-        Expr::Lit(Lit::Str(s)) => Some(QExpr::Value(QValue::Str(format!("{}", s.value)))),
+        Expr::Bin(bin_expr) => convert_bin_expr(bin_expr, params),
+        Expr::Paren(paren_expr) => convert_expr(&paren_expr.expr, params),
+        Expr::Lit(Lit::Num(number)) => Some(QValue::F64(number.value).into()),
+        Expr::Lit(Lit::Str(s)) => Some(QValue::String(format!("{}", s.value)).into()),
         Expr::Member(member_expr) => {
-            let obj = convert_expr(&member_expr.obj)?;
+            let obj = convert_expr(&member_expr.obj, params)?;
             let prop = match &member_expr.prop {
                 MemberProp::Ident(ident) => ident.sym.to_string(),
                 _ => {
                     todo!();
                 }
             };
-            Some(QExpr::PropertyAccess(QPropertyAccessExpr {
-                object: Box::new(obj),
-                property: prop,
-            }))
+            Some(
+                QPropertyAccess {
+                    object: Box::new(obj),
+                    property: prop,
+                }
+                .into(),
+            )
+        }
+        Expr::Ident(ident) => {
+            let name = ident.sym.to_string();
+            match params.iter().position(|param| *param == name) {
+                Some(position) => Some(QExpr::Parameter { position }),
+                None => Some(QExpr::Variable { name }),
+            }
         }
-        Expr::Ident(ident) => Some(QExpr::Identifier(ident.sym.to_string())),
         _ => None,
     }
 }
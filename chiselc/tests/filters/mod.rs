@@ -39,6 +39,7 @@ macro_rules! compile {
                 code.clone(),
                 symbols.clone(),
                 $target,
+                false,
                 &mut out
             ).unwrap();
 
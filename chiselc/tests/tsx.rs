@@ -0,0 +1,32 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use chiselc::parse::compile;
+use chiselc::rewrite::Target;
+use chiselc::symbols::Symbols;
+
+fn compile_js(code: &str, tsx: bool) -> anyhow::Result<String> {
+    let mut out = Vec::new();
+    compile(
+        code.to_string(),
+        Symbols::new(),
+        Target::JavaScript,
+        tsx,
+        &mut out,
+    )?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn jsx_elements() {
+    let code = r#"
+    export default function Greeting(props: { name: string }) {
+        return <h1>Hello {props.name}</h1>;
+    }
+    "#;
+    let out = compile_js(code, true).unwrap();
+    assert!(out.contains("<h1>"), "{}", out);
+    assert!(!out.contains("string"), "{}", out);
+
+    // JSX is a syntax error in plain TypeScript
+    assert!(compile_js(code, false).is_err());
+}
@@ -9,5 +9,15 @@ macro_rules! execute {
 }
 
 pub(crate) mod apply;
+pub(crate) mod contract;
+pub(crate) mod data;
 pub(crate) mod dev;
+pub(crate) mod diff;
 pub(crate) mod generate;
+pub(crate) mod lint;
+pub(crate) mod meta;
+pub(crate) mod modules;
+pub(crate) mod restore;
+pub(crate) mod secrets;
+pub(crate) mod seed;
+pub(crate) mod transfer;
@@ -4,16 +4,22 @@ pub mod common;
 pub mod deno;
 pub mod node;
 
+use crate::codegen::RootSources;
 use crate::project::{read_manifest, read_to_string, AutoIndex, Module, Optimize};
 use crate::proto::chisel_rpc_client::ChiselRpcClient;
-use crate::proto::{ApplyRequest, IndexCandidate, PolicyUpdateRequest};
+use crate::proto::{
+    AddTypeRequest, ApplyPolicyOnlyRequest, ApplyRequest, ApplyResponse, IndexCandidate,
+    Module as ProtoModule, ModuleRef, PolicyUpdateRequest, StaticFile, WorkerPoolSettings,
+};
 use anyhow::{anyhow, Context, Result};
 use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ffi::OsStr;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use uuid::Uuid;
 
 static DEFAULT_APP_NAME: &str = "ChiselStrike Application";
 
@@ -64,18 +70,120 @@ impl From<bool> for TypeChecking {
     }
 }
 
-pub(crate) async fn apply(
-    server_url: String,
-    version_id: String,
-    allow_type_deletion: AllowTypeDeletion,
+/// Changes with data or of locked models that `chisel apply` was explicitly allowed to make.
+#[derive(Default)]
+pub(crate) struct AllowedChanges {
+    /// Models (`Name`) and fields (`Name.field`) with data that may be dropped.
+    pub drops: Vec<String>,
+    /// Models marked with `@locked` that may be changed.
+    pub unlocked: Vec<String>,
+}
+
+/// How `chisel apply` changes the server.
+#[derive(Default)]
+pub(crate) struct ApplyOptions {
+    /// Archive the tables of removed models that still have data, instead of dropping them.
+    pub archive_removed: bool,
+    /// Only print the plan of the apply, without changing anything.
+    pub dry_run: bool,
+    /// Print the plan of a dry run as JSON.
+    pub json_plan: bool,
+    /// Only compile the modules that changed since the last incremental apply, and do not send
+    /// the code of the other modules again (used by `chisel dev`).
+    pub incremental: bool,
+    /// Fetch the remote imports again, instead of taking them from the disk cache.
+    pub reload: bool,
+    /// Only take the remote imports from the disk cache, never fetch them.
+    pub offline: bool,
+    /// How many compilers may compile the sources in parallel (1 compiles them all in a single
+    /// compilation).
+    pub compile_jobs: usize,
+}
+
+/// Version that `chisel apply` applies the project to.
+pub(crate) struct ApplyTarget {
+    pub version_id: String,
+    /// Route a part of the requests to another version of the app to the applied version.
+    pub canary: Option<Canary>,
+}
+
+impl From<String> for ApplyTarget {
+    fn from(version_id: String) -> Self {
+        Self {
+            version_id,
+            canary: None,
+        }
+    }
+}
+
+pub(crate) struct Canary {
+    /// Percentage of the requests that are routed to the applied version (0 stops routing).
+    pub percentage: f64,
+    /// Version whose requests are split; if `None`, the server picks the only other version of the
+    /// app.
+    pub of: Option<String>,
+}
+
+/// How `chisel apply` deals with the lock that prevents concurrent applies.
+pub(crate) struct ApplyLock {
+    /// How long to wait (in seconds) for a concurrent apply to finish.
+    pub timeout_s: u32,
+    /// Remove the lock before applying, in case it was left behind by a crashed server.
+    pub force_unlock: bool,
+}
+
+impl Default for ApplyLock {
+    fn default() -> Self {
+        Self {
+            timeout_s: 60,
+            force_unlock: false,
+        }
+    }
+}
+
+/// The project in the current directory, compiled to the form in which it is applied.
+pub(crate) struct CompiledProject {
+    pub types: Vec<AddTypeRequest>,
+    pub modules: Vec<ProtoModule>,
+    /// URLs of the modules whose compilation was reused from the last incremental apply.
+    pub reused_modules: HashSet<String>,
+    pub index_candidates: Vec<IndexCandidate>,
+    pub policies: Vec<PolicyUpdateRequest>,
+    /// Path patterns of the file-based routes.
+    pub route_patterns: Vec<String>,
+    /// Files of the static directory, which are served by the version.
+    pub static_files: Vec<StaticFile>,
+    /// Hosts that the version may connect to.
+    pub allowed_hosts: Vec<String>,
+    /// Worker pool of the version, from the `[workers]` section of the manifest.
+    pub worker_pool: WorkerPoolSettings,
+    /// Import map that resolves the bare specifiers of the modules at runtime.
+    pub import_map: HashMap<String, String>,
+}
+
+/// Compiles the project in the current directory, as configured by the `options` of the apply.
+pub(crate) async fn compile_project(
     type_check: TypeChecking,
-) -> Result<()> {
+    options: &ApplyOptions,
+) -> Result<CompiledProject> {
     let cwd = env::current_dir()?;
     let manifest = read_manifest(&cwd).context("Could not read manifest file")?;
     let models = manifest.models(&cwd)?;
     let route_map = manifest.route_map(&cwd)?;
+    let route_patterns = route_map
+        .routes
+        .iter()
+        .map(|route| route.path_pattern.clone())
+        .collect();
     let topic_map = manifest.topic_map(&cwd)?;
-    let policies = manifest.policies(&cwd)?;
+    let seed_map = manifest.seed_map(&cwd)?;
+    let auth_hook = manifest.auth_hook(&cwd)?;
+    // the auth hook may live next to the policies, but it is applied as code
+    let policies: Vec<PathBuf> = manifest
+        .policies(&cwd)?
+        .into_iter()
+        .filter(|p| Some(p) != auth_hook.as_ref())
+        .collect();
 
     let types_req = crate::ts::parse_types(&models)?;
     let mut policy_req = vec![];
@@ -92,28 +200,71 @@ pub(crate) async fn apply(
     }
     let optimize = chiselc_available && manifest.optimize == Optimize::Yes;
     let auto_index = chiselc_available && manifest.auto_index == AutoIndex::Yes;
-    let (modules, index_candidates) = match manifest.modules {
+    let sources = RootSources {
+        route_map,
+        topic_map,
+        auth_hook,
+        seed_map,
+    };
+    let (modules, index_candidates, reused_modules) = match manifest.modules {
         Module::Node => {
-            node::apply(
-                route_map,
-                topic_map,
-                &entities,
-                optimize,
-                auto_index,
-                &type_check,
-            )
-            .await?
+            let (modules, index_candidates) =
+                node::apply(sources, &entities, optimize, auto_index, &type_check).await?;
+            (modules, index_candidates, HashSet::new())
         }
-        Module::Deno => deno::apply(route_map, topic_map, &entities, optimize, auto_index).await?,
+        Module::Deno => deno::apply(sources, &entities, optimize, auto_index, options).await?,
+    };
+    // node modules are bundled, so they do not import anything by a bare specifier
+    let import_map = match manifest.modules {
+        Module::Node => HashMap::new(),
+        Module::Deno => deno::runtime_import_map(&cwd)?,
     };
 
     for p in &policies {
-        policy_req.push(PolicyUpdateRequest {
-            policy_config: read_to_string(p)?,
-            path: p.display().to_string(),
-        });
+        policy_req.push(policy_update(p)?);
     }
 
+    let static_files = manifest
+        .static_files(&cwd)?
+        .into_iter()
+        .map(|(path, file)| {
+            let content = std::fs::read(&file)
+                .with_context(|| format!("Could not read static file {}", file.display()))?;
+            Ok(StaticFile { path, content })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(CompiledProject {
+        types: types_req,
+        modules,
+        reused_modules,
+        index_candidates,
+        policies: policy_req,
+        route_patterns,
+        static_files,
+        allowed_hosts: manifest.allowed_hosts,
+        worker_pool: WorkerPoolSettings {
+            min_workers: manifest.workers.min,
+            max_workers: manifest.workers.max,
+            scale_up_queue_depth: manifest.workers.scale_up_queue_depth,
+            scale_up_latency_ms: manifest.workers.scale_up_latency_ms,
+            scale_down_idle_s: manifest.workers.scale_down_idle_s,
+        },
+        import_map,
+    })
+}
+
+pub(crate) async fn apply(
+    server_url: String,
+    target: ApplyTarget,
+    allow_type_deletion: AllowTypeDeletion,
+    allowed: AllowedChanges,
+    options: ApplyOptions,
+    lock: ApplyLock,
+    type_check: TypeChecking,
+) -> Result<()> {
+    let project = compile_project(type_check, &options).await?;
+
     let package = match read_to_string("./package.json") {
         Ok(x) => {
             let val: serde_json::Result<serde_json::Value> = serde_json::from_str(&x);
@@ -145,19 +296,172 @@ pub(crate) async fn apply(
     };
 
     let mut client = ChiselRpcClient::connect(server_url.clone()).await?;
+    let apply_id = Uuid::new_v4().to_string();
+    let (modules, reused_modules) = if options.incremental {
+        split_reused_modules(&project.modules, &project.reused_modules)
+    } else {
+        (project.modules.clone(), vec![])
+    };
     let req = ApplyRequest {
-        types: types_req,
+        types: project.types,
         modules,
-        index_candidates,
-        policies: policy_req,
+        reused_modules,
+        static_files: project.static_files,
+        allowed_hosts: project.allowed_hosts,
+        worker_pool: Some(project.worker_pool),
+        import_map: project.import_map,
+        index_candidates: project.index_candidates,
+        policies: project.policies,
         allow_type_deletion: allow_type_deletion.into(),
-        version_id,
+        allowed_drops: allowed.drops,
+        unlocked: allowed.unlocked,
+        archive_removed: options.archive_removed,
+        dry_run: options.dry_run,
+        version_id: target.version_id,
+        canary_percentage: target.canary.as_ref().map(|canary| canary.percentage),
+        canary_of: target
+            .canary
+            .and_then(|canary| canary.of)
+            .unwrap_or_default(),
         version_tag,
         app_name,
+        apply_id: apply_id.clone(),
+        resume: false,
+        lock_holder: lock_holder(),
+        lock_timeout_s: lock.timeout_s,
+        force_unlock: lock.force_unlock,
     };
 
+    let retry_req = (!req.reused_modules.is_empty()).then(|| ApplyRequest {
+        modules: project.modules,
+        reused_modules: vec![],
+        ..req.clone()
+    });
+    let mut response = client.apply(tonic::Request::new(req)).await;
+    if let Some(retry_req) = retry_req {
+        // the server did not have some of the reused modules (for example, because it was
+        // restarted), so they must all be sent
+        if matches!(&response, Err(status) if status.code() == tonic::Code::FailedPrecondition) {
+            response = client.apply(tonic::Request::new(retry_req)).await;
+        }
+    }
+    if options.dry_run {
+        let msg = execute!(response);
+        if options.json_plan {
+            println!("{}", msg.plan_json);
+        } else {
+            print_plan(&msg);
+        }
+        return Ok(());
+    }
+    if let Err(ref status) = response {
+        // the server keeps applying even if we are disconnected, so the apply can be resumed
+        if matches!(
+            status.code(),
+            tonic::Code::Unavailable | tonic::Code::Unknown | tonic::Code::Cancelled
+        ) {
+            eprintln!(
+                "The connection to the server was interrupted, you can finish the apply with `chisel apply --resume {}`",
+                apply_id
+            );
+        }
+    }
+    let msg = execute!(response);
+    print_applied(&msg);
+    Ok(())
+}
+
+/// Splits `modules` into the modules whose code is sent to the server and references to the
+/// `reused` modules, which are unchanged since the last incremental apply, so the server already
+/// has them.
+fn split_reused_modules(
+    modules: &[ProtoModule],
+    reused: &HashSet<String>,
+) -> (Vec<ProtoModule>, Vec<ModuleRef>) {
+    let mut sent = vec![];
+    let mut refs = vec![];
+    for module in modules {
+        if reused.contains(&module.url) {
+            refs.push(ModuleRef {
+                url: module.url.clone(),
+                hash: chisel_server::module_hash(&module.code),
+            });
+        } else {
+            sent.push(module.clone());
+        }
+    }
+    (sent, refs)
+}
+
+/// Resumes an apply that was interrupted, or reports the outcome of an apply that has already
+/// finished.
+pub(crate) async fn resume_apply(server_url: String, apply_id: String) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let req = ApplyRequest {
+        apply_id,
+        resume: true,
+        lock_holder: lock_holder(),
+        lock_timeout_s: ApplyLock::default().timeout_s,
+        ..Default::default()
+    };
     let msg = execute!(client.apply(tonic::Request::new(req)).await);
+    print_applied(&msg);
+    Ok(())
+}
 
+/// Applies only the policies of the current project to a running version, without compiling and
+/// applying the models and routes.
+pub(crate) async fn apply_policies(
+    server_url: String,
+    version_id: String,
+    lock: ApplyLock,
+) -> Result<()> {
+    let cwd = env::current_dir()?;
+    let manifest = read_manifest(&cwd).context("Could not read manifest file")?;
+    let policies = manifest
+        .policies(&cwd)?
+        .iter()
+        .map(|p| policy_update(p))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let req = ApplyPolicyOnlyRequest {
+        version_id,
+        policies,
+        lock_holder: lock_holder(),
+        lock_timeout_s: lock.timeout_s,
+        force_unlock: lock.force_unlock,
+    };
+    let msg = execute!(client.apply_policy_only(tonic::Request::new(req)).await);
+    println!("Applied policies:");
+    println!("  {} labels", msg.labels.len());
+    Ok(())
+}
+
+fn policy_update(path: &Path) -> Result<PolicyUpdateRequest> {
+    Ok(PolicyUpdateRequest {
+        policy_config: read_to_string(path)?,
+        path: path.display().to_string(),
+    })
+}
+
+/// Identifies this client to concurrent applies.
+fn lock_holder() -> String {
+    format!("{}@{}", whoami::username(), whoami::hostname())
+}
+
+fn print_plan(msg: &ApplyResponse) {
+    if msg.plan.is_empty() {
+        println!("Dry run: the apply would not change the database");
+        return;
+    }
+    println!("Dry run: the apply would");
+    for step in msg.plan.iter() {
+        println!("  {}", step);
+    }
+}
+
+fn print_applied(msg: &ApplyResponse) {
     println!("Applied:");
     if !msg.types.is_empty() {
         println!("  {} models", msg.types.len());
@@ -168,13 +472,25 @@ pub(crate) async fn apply(
     if !msg.labels.is_empty() {
         println!("  {} labels", msg.labels.len());
     }
-
-    Ok(())
+    if !msg.dropped.is_empty() {
+        println!("  dropped {}", msg.dropped.join(", "));
+    }
+    if !msg.archived.is_empty() {
+        println!("  archived {}", msg.archived.join(", "));
+    }
+    if !msg.canary_of.is_empty() {
+        println!(
+            "  routing {}% of the requests to {} to this version",
+            msg.canary_percentage, msg.canary_of
+        );
+    }
 }
 
-fn parse_indexes(code: String, entities: &[String]) -> Result<Vec<IndexCandidate>> {
+/// Finds the index candidates in `code`, which is TSX if `tsx` (otherwise TypeScript or
+/// JavaScript).
+fn parse_indexes(code: String, entities: &[String], tsx: bool) -> Result<Vec<IndexCandidate>> {
     let mut index_candidates = vec![];
-    let indexes = chiselc_output(code, "filter-properties", entities)?;
+    let indexes = chiselc_output(code, "filter-properties", entities, tsx)?;
     let indexes: Value = serde_json::from_str(&indexes)?;
     if let Some(indexes) = indexes.as_array() {
         for index in indexes {
@@ -250,8 +566,11 @@ fn chiselc_spawn(
 }
 
 /// Spawn `chiselc`, wait for the process to complete, and return its output.
-fn chiselc_output(code: String, target: &str, entities: &[String]) -> Result<String> {
+fn chiselc_output(code: String, target: &str, entities: &[String], tsx: bool) -> Result<String> {
     let mut args: Vec<&str> = vec!["--target", target];
+    if tsx {
+        args.push("--tsx");
+    }
     if !entities.is_empty() {
         for entity in entities.iter() {
             args.push("-e");
@@ -3,7 +3,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::{events::FileTopicMap, routes::FileRouteMap};
+use crate::{events::FileTopicMap, routes::FileRouteMap, seeds::FileSeedMap};
 use anyhow::{anyhow, Context, Result};
 
 pub(crate) fn create_tmp_route_files(
@@ -28,6 +28,17 @@ pub(crate) fn create_tmp_topic_files(
     Ok(file_map)
 }
 
+pub(crate) fn create_tmp_seed_files(
+    mut file_map: FileSeedMap,
+    gen_dir: &Path,
+) -> Result<FileSeedMap> {
+    let cwd = env::current_dir()?;
+    for seed in file_map.seeds.iter_mut() {
+        copy_source(&cwd, &mut seed.file_path, gen_dir)?;
+    }
+    Ok(file_map)
+}
+
 fn copy_source(cwd: &PathBuf, file_path: &mut PathBuf, gen_dir: &Path) -> Result<()> {
     let file_rel_path = file_path
         .strip_prefix(cwd)
@@ -4,54 +4,92 @@
 
 use crate::cmd::apply::chiselc_output;
 use crate::cmd::apply::parse_indexes;
-use crate::codegen::codegen_root_module;
-use crate::events::FileTopicMap;
+use crate::cmd::apply::ApplyOptions;
+use crate::codegen::{codegen_root_module, RootSources};
 use crate::proto::{IndexCandidate, Module};
-use crate::routes::FileRouteMap;
 use anyhow::{anyhow, bail, Context, Result};
+use endpoint_tsc::tsc_compile::{load_import_map, CompileCache, FixedUrl, RemoteOptions, TsConfig};
 use endpoint_tsc::Compiler;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use url::Url;
 
+/// Cache of the TypeScript compilation of incremental applies, relative to the project.
+const COMPILE_CACHE_PATH: &str = ".chisel-cache/tsc.json";
+
+/// Lockfile with the hashes of the remote imports, relative to the project.
+const LOCKFILE_PATH: &str = "chisel.lock";
+
+/// Compiles the project to modules. If the apply is incremental, only the modules that changed
+/// since the last incremental apply are compiled, and the URLs of the other modules are returned
+/// as reused. Otherwise, the sources may be compiled in parallel (see `compile_parallel()`).
 pub(crate) async fn apply(
-    route_map: FileRouteMap,
-    topic_map: FileTopicMap,
+    sources: RootSources,
     entities: &[String],
     optimize: bool,
     auto_index: bool,
-) -> Result<(Vec<Module>, Vec<IndexCandidate>)> {
+    options: &ApplyOptions,
+) -> Result<(Vec<Module>, Vec<IndexCandidate>, HashSet<String>)> {
     let import_fn = |path: &Path| -> Result<String> {
         Url::from_file_path(path)
             .map(|url| url.to_string())
             .map_err(|_| anyhow!("Cannot convert file path {} to import URL", path.display()))
     };
 
-    let root_code = codegen_root_module(&route_map, &topic_map, &import_fn)
+    let root_code = codegen_root_module(&sources, &import_fn)
         .context("Could not generate code for file-based routing and event topics")?;
     let (_root_file, root_url) = temporary_source_file("__root.", &root_code)?;
 
     let mut compiler = Compiler::new(true);
-    let compiled = compiler
-        .compile(root_url.clone())
-        .await
-        .context("Could not compile routes (using deno-style modules)")?;
+    compiler.remote = RemoteOptions {
+        reload: options.reload,
+        offline: options.offline,
+        lockfile: Some(PathBuf::from(LOCKFILE_PATH)),
+    };
+    let cwd = std::env::current_dir()?;
+    compiler.tsconfig = TsConfig::load(&cwd)?;
+    compiler.import_map = load_import_map(&cwd)?;
+    let mut cache = options
+        .incremental
+        .then(|| CompileCache::load(Path::new(COMPILE_CACHE_PATH)));
+    let compiled = match cache.as_mut() {
+        Some(cache) => compiler.compile_incremental(root_url.clone(), cache).await,
+        None if options.compile_jobs > 1 => compile_parallel(
+            &compiler,
+            &sources,
+            &root_url,
+            &root_code,
+            options.compile_jobs,
+        ),
+        None => compiler.compile(root_url.clone()).await,
+    }
+    .context("Could not compile routes (using deno-style modules)")?;
+    if let Some(cache) = &cache {
+        cache.save(Path::new(COMPILE_CACHE_PATH))?;
+    }
 
     let mut modules = Vec::new();
     let mut index_candidates = Vec::new();
+    let mut reused = HashSet::new();
     for (url, mut code, _is_dts) in compiled.into_iter() {
         let mut url = Url::parse(url.as_str()).unwrap();
+        if cache
+            .as_ref()
+            .map_or(false, |cache| cache.is_reused(url.as_str()))
+        {
+            reused.insert(url.to_string());
+        }
         if url == root_url {
             url = Url::parse("file:///__root.ts").unwrap();
         }
 
         if optimize {
-            code = chiselc_output(code, "js", entities)?;
+            code = chiselc_output(code, "js", entities, false)?;
         }
 
         if auto_index {
-            let mut candidates = parse_indexes(code.clone(), entities)?;
+            let mut candidates = parse_indexes(code.clone(), entities, false)?;
             index_candidates.append(&mut candidates);
         }
 
@@ -61,7 +99,60 @@ pub(crate) async fn apply(
         });
     }
 
-    Ok((modules, index_candidates))
+    Ok((modules, index_candidates, reused))
+}
+
+/// Compiles the sources that the root module imports with up to `jobs` compilers in parallel. The
+/// root module itself is plain JavaScript, so it is used as it is; this skips only the check of
+/// the types of the values that the root module passes around.
+fn compile_parallel(
+    compiler: &Compiler,
+    sources: &RootSources,
+    root_url: &Url,
+    root_code: &str,
+    jobs: usize,
+) -> Result<Vec<(FixedUrl, String, bool)>> {
+    let paths = sources
+        .route_map
+        .routes
+        .iter()
+        .map(|route| &route.file_path)
+        .chain(
+            sources
+                .topic_map
+                .topics
+                .iter()
+                .map(|topic| &topic.file_path),
+        )
+        .chain(sources.seed_map.seeds.iter().map(|seed| &seed.file_path))
+        .chain(sources.auth_hook.iter());
+    let mut urls = vec![];
+    for path in paths {
+        let url = Url::from_file_path(path)
+            .map_err(|_| anyhow!("Cannot convert file path {} to URL", path.display()))?;
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    let mut compiled = compiler.compile_parallel(urls, jobs)?;
+    let root_url = FixedUrl::parse(root_url.as_str()).unwrap();
+    compiled.push((root_url, root_code.to_string(), false));
+    Ok(compiled)
+}
+
+/// Returns the import map that the server uses to resolve the bare specifiers that remain in the
+/// compiled modules: the imports of `import_map.json` and the path aliases of `tsconfig.json` in
+/// `dir`.
+pub(crate) fn runtime_import_map(dir: &Path) -> Result<HashMap<String, String>> {
+    let mut imports: HashMap<String, String> =
+        TsConfig::load(dir)?.alias_imports().into_iter().collect();
+    // like in the compilation, the import map takes precedence over the path aliases
+    let import_map = load_import_map(dir)?;
+    for (key, target) in import_map.imports() {
+        imports.insert(key.to_string(), target.to_string());
+    }
+    Ok(imports)
 }
 
 fn temporary_source_file(name_prefix: &str, code: &str) -> Result<(tempfile::NamedTempFile, Url)> {
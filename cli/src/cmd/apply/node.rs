@@ -3,22 +3,19 @@
 use crate::cmd::apply::chiselc_spawn;
 use crate::cmd::apply::parse_indexes;
 use crate::cmd::apply::TypeChecking;
-use crate::codegen::codegen_root_module;
-use crate::events::FileTopicMap;
+use crate::codegen::{codegen_root_module, RootSources};
 use crate::project::read_to_string;
 use crate::proto::{IndexCandidate, Module};
-use crate::routes::FileRouteMap;
 use anyhow::{bail, Context, Result};
 use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 use tsc_reflection;
 
-use super::common::{create_tmp_route_files, create_tmp_topic_files};
+use super::common::{create_tmp_route_files, create_tmp_seed_files, create_tmp_topic_files};
 
 pub(crate) async fn apply(
-    mut route_map: FileRouteMap,
-    mut topic_map: FileTopicMap,
+    mut sources: RootSources,
     entities: &[String],
     optimize: bool,
     auto_index: bool,
@@ -46,9 +43,11 @@ pub(crate) async fn apply(
 
     let route_gen_dir = cwd.join(".routegen");
     let event_gen_dir = cwd.join(".eventgen");
+    let seed_gen_dir = cwd.join(".seedgen");
 
-    route_map = create_tmp_route_files(route_map, &route_gen_dir)?;
-    topic_map = create_tmp_topic_files(topic_map, &event_gen_dir)?;
+    sources.route_map = create_tmp_route_files(sources.route_map, &route_gen_dir)?;
+    sources.topic_map = create_tmp_topic_files(sources.topic_map, &event_gen_dir)?;
+    sources.seed_map = create_tmp_seed_files(sources.seed_map, &seed_gen_dir)?;
     tsc_reflection::transform_in_place(&cwd, &route_gen_dir, false).await?;
 
     let mut index_candidates = vec![];
@@ -66,7 +65,8 @@ pub(crate) async fn apply(
         if auto_index {
             let code = read_to_string(file_path.clone())
                 .with_context(|| format!("Could not read file {}", file_path.display()))?;
-            let mut indexes = parse_indexes(code, entities).with_context(|| {
+            let tsx = file_path.extension() == Some("tsx".as_ref());
+            let mut indexes = parse_indexes(code, entities, tsx).with_context(|| {
                 format!(
                     "Could not parse auto-indexing information from file {}",
                     file_path.display()
@@ -78,13 +78,16 @@ pub(crate) async fn apply(
         Ok(())
     };
 
-    // TODO: we need to preprocess all source files with chiselc, not just routes and events
-    for route in route_map.routes.iter_mut() {
+    // TODO: we need to preprocess all source files with chiselc, not just routes, events and seeds
+    for route in sources.route_map.routes.iter_mut() {
         preprocess_source(&route.file_path)?;
     }
-    for topic in topic_map.topics.iter_mut() {
+    for topic in sources.topic_map.topics.iter_mut() {
         preprocess_source(&topic.file_path)?;
     }
+    for seed in sources.seed_map.seeds.iter_mut() {
+        preprocess_source(&seed.file_path)?;
+    }
 
     for proc in chiselc_procs.into_iter() {
         let chiselc_output = proc
@@ -104,7 +107,7 @@ pub(crate) async fn apply(
             .map(String::from)
             .context("Path is not valid UTF-8")
     };
-    let root_code = codegen_root_module(&route_map, &topic_map, &import_fn)
+    let root_code = codegen_root_module(&sources, &import_fn)
         .context("Could not generate code for file-based routing and event topics")?;
 
     let root_path = bundler_input_dir.path().join("__root.ts");
@@ -0,0 +1,233 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::{parse_version, DEFAULT_API_VERSION};
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use serde_derive::Deserialize;
+use serde_json::Value as JsonValue;
+
+/// Header that carries the `CHISEL_ADMIN_SECRET` secret.
+const ADMIN_SECRET_HEADER: &str = "X-Chisel-Admin-Secret";
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum ContractCommand {
+    /// Replay the route examples of a version against a deployment and fail if the status or the
+    /// shape of a response differs from the example.
+    ///
+    /// The examples are registered with `RouteMap.example()` or recorded from the traffic of the
+    /// server (see the `--record-examples-rate` option of chiseld).
+    Verify {
+        /// Version whose examples are replayed.
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+        /// Base URL of the deployment that the examples are replayed against, such as
+        /// `https://staging.example.com`. Defaults to the server that the examples come from.
+        #[arg(long)]
+        target: Option<String>,
+        /// Version of the target deployment. Defaults to `--version`.
+        #[arg(long)]
+        target_version: Option<String>,
+        /// The `CHISEL_ADMIN_SECRET` secret of the server, which is required when the server does
+        /// not run in debug mode. Defaults to the `CHISEL_ADMIN_SECRET` environment variable.
+        #[arg(long)]
+        admin_secret: Option<String>,
+    },
+}
+
+/// Route example as listed by the server.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RouteExample {
+    name: String,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    status: u16,
+    response: Option<JsonValue>,
+    recorded: bool,
+}
+
+pub(crate) async fn cmd_contract(api_listen_addr: String, cmd: ContractCommand) -> Result<()> {
+    match cmd {
+        ContractCommand::Verify {
+            version,
+            target,
+            target_version,
+            admin_secret,
+        } => {
+            let target = target.unwrap_or_else(|| format!("http://{}", api_listen_addr));
+            let target_version = target_version.unwrap_or_else(|| version.clone());
+            let admin_secret = admin_secret.or_else(|| std::env::var("CHISEL_ADMIN_SECRET").ok());
+            let examples = fetch_examples(&api_listen_addr, &version, admin_secret).await?;
+            verify(&examples, &target, &target_version).await
+        }
+    }
+}
+
+async fn fetch_examples(
+    api_listen_addr: &str,
+    version: &str,
+    admin_secret: Option<String>,
+) -> Result<Vec<RouteExample>> {
+    let url = format!(
+        "http://{}/{}/__chiselstrike/examples",
+        api_listen_addr, version
+    );
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(secret) = admin_secret.as_ref() {
+        request = request.header(ADMIN_SECRET_HEADER, secret);
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let message = response.text().await.unwrap_or_default();
+        bail!("Server responded with status {}: {}", status, message);
+    }
+    Ok(response.json().await?)
+}
+
+async fn verify(examples: &[RouteExample], target: &str, target_version: &str) -> Result<()> {
+    if examples.is_empty() {
+        println!("There are no route examples to verify");
+        return Ok(());
+    }
+
+    let client = reqwest::Client::new();
+    let base_url = format!("{}/{}", target.trim_end_matches('/'), target_version);
+    let mut failed = 0;
+    for example in examples.iter() {
+        let problems = replay(&client, &base_url, example)
+            .await
+            .unwrap_or_else(|e| vec![format!("{:#}", e)]);
+        let source = if example.recorded { " (recorded)" } else { "" };
+        if problems.is_empty() {
+            println!("PASS {}{}", example.name, source);
+        } else {
+            failed += 1;
+            println!("FAIL {}{}", example.name, source);
+            for problem in problems.iter() {
+                println!("    {}", problem);
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} route examples failed", failed, examples.len());
+    }
+    println!("All {} route examples passed", examples.len());
+    Ok(())
+}
+
+/// Sends the request of the example and returns the differences between the actual and the
+/// expected response.
+async fn replay(
+    client: &reqwest::Client,
+    base_url: &str,
+    example: &RouteExample,
+) -> Result<Vec<String>> {
+    let method = reqwest::Method::from_bytes(example.method.as_bytes())
+        .with_context(|| format!("Invalid method {:?}", example.method))?;
+    let mut request = client.request(method, format!("{}{}", base_url, example.path));
+    for (name, value) in example.headers.iter() {
+        request = request.header(name, value);
+    }
+    if let Some(body) = example.body.as_ref() {
+        request = request.body(body.clone());
+    }
+    let response = request.send().await?;
+
+    let status = response.status().as_u16();
+    if status != example.status {
+        return Ok(vec![format!(
+            "expected status {}, got {}",
+            example.status, status
+        )]);
+    }
+    let expected = match example.response.as_ref() {
+        Some(expected) => expected,
+        None => return Ok(vec![]),
+    };
+    let text = response.text().await?;
+    match serde_json::from_str::<JsonValue>(&text) {
+        Ok(actual) => Ok(shape_diff(expected, &actual, "$")),
+        Err(_) => Ok(vec!["expected a JSON response".into()]),
+    }
+}
+
+/// Compares the shapes of two JSON values: the types must be the same, and the fields of an
+/// expected object must be present in the actual object (extra fields are fine). Elements of an
+/// actual array are compared with the first element of the expected array. An expected `null`
+/// matches any value.
+fn shape_diff(expected: &JsonValue, actual: &JsonValue, path: &str) -> Vec<String> {
+    match (expected, actual) {
+        (JsonValue::Null, _) => vec![],
+        (JsonValue::Object(expected), JsonValue::Object(actual)) => expected
+            .iter()
+            .flat_map(|(name, expected)| {
+                let path = format!("{}.{}", path, name);
+                match actual.get(name) {
+                    Some(actual) => shape_diff(expected, actual, &path),
+                    None => vec![format!("{}: missing", path)],
+                }
+            })
+            .collect(),
+        (JsonValue::Array(expected), JsonValue::Array(actual)) => match expected.first() {
+            Some(expected) => actual
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, actual)| {
+                    shape_diff(expected, actual, &format!("{}[{}]", path, idx))
+                })
+                .collect(),
+            None => vec![],
+        },
+        (expected, actual) if type_name(expected) == type_name(actual) => vec![],
+        (expected, actual) => vec![format!(
+            "{}: expected {}, got {}",
+            path,
+            type_name(expected),
+            type_name(actual)
+        )],
+    }
+}
+
+fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn same_shape() {
+        let expected = json!({"title": "Dune", "tags": ["scifi"], "author": null});
+        let actual = json!({"title": "Emma", "tags": [], "author": {"name": "x"}, "pages": 1});
+        assert!(shape_diff(&expected, &actual, "$").is_empty());
+    }
+
+    #[test]
+    fn changed_shape() {
+        let expected = json!({"title": "Dune", "pages": 412, "tags": ["scifi"]});
+        let actual = json!({"title": 1, "tags": ["x", 2]});
+        let mut diff = shape_diff(&expected, &actual, "$");
+        diff.sort();
+        assert_eq!(
+            diff,
+            vec![
+                "$.pages: missing",
+                "$.tags[1]: expected string, got number",
+                "$.title: expected string, got number",
+            ]
+        );
+    }
+}
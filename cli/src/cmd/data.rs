@@ -0,0 +1,216 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::proto::chisel_rpc_client::ChiselRpcClient;
+use crate::proto::{
+    data_diff_response, row_diff, CheckCountsRequest, DataDiffRequest, DataDiffSummary,
+    DataQueryRequest, ListArchivesRequest, RowDiff,
+};
+use crate::{parse_version, DEFAULT_API_VERSION};
+use anyhow::{anyhow, bail, Result};
+use clap::Subcommand;
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum DataCommand {
+    /// Compare the data of an entity between two versions.
+    Diff {
+        #[arg(value_parser = parse_version)]
+        from: String,
+        #[arg(value_parser = parse_version)]
+        to: String,
+        /// Name of the entity to compare.
+        #[arg(long)]
+        entity: String,
+        /// Only compare a percentage of the rows (selected by their id).
+        #[arg(long, default_value = "100")]
+        sample: f64,
+    },
+    /// Print the rows of an entity as JSON, one row per line.
+    Query {
+        /// Name of the entity to query.
+        entity: String,
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+        /// Query the table of the entity that was archived by `chisel apply --archive`.
+        #[arg(long)]
+        archived: bool,
+        /// Maximum number of rows to print (0 prints all rows).
+        #[arg(long, default_value = "100")]
+        limit: u32,
+    },
+    /// List the entities whose tables were archived by `chisel apply --archive`.
+    Archives,
+    /// Check that the `@count` fields match the data, for example after the data was modified
+    /// outside of ChiselStrike.
+    CheckCounts {
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+        /// Recompute the `@count` fields that do not match.
+        #[arg(long)]
+        fix: bool,
+    },
+}
+
+pub(crate) async fn cmd_data(server_url: String, cmd: DataCommand) -> Result<()> {
+    match cmd {
+        DataCommand::Diff {
+            from,
+            to,
+            entity,
+            sample,
+        } => diff(server_url, from, to, entity, sample).await,
+        DataCommand::Query {
+            entity,
+            version,
+            archived,
+            limit,
+        } => query(server_url, version, entity, archived, limit).await,
+        DataCommand::Archives => archives(server_url).await,
+        DataCommand::CheckCounts { version, fix } => check_counts(server_url, version, fix).await,
+    }
+}
+
+async fn diff(
+    server_url: String,
+    from_version_id: String,
+    to_version_id: String,
+    entity_name: String,
+    sample_percentage: f64,
+) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = tonic::Request::new(DataDiffRequest {
+        from_version_id: from_version_id.clone(),
+        to_version_id: to_version_id.clone(),
+        entity_name,
+        sample_percentage,
+    });
+    let mut stream = execute!(client.data_diff(request).await);
+
+    while let Some(msg) = stream
+        .message()
+        .await
+        .map_err(|x| anyhow!(x.message().to_owned()))?
+    {
+        match msg.event {
+            Some(data_diff_response::Event::Row(row)) => {
+                print_row(&row, &from_version_id, &to_version_id)
+            }
+            Some(data_diff_response::Event::Summary(summary)) => {
+                print_summary(&summary, &from_version_id, &to_version_id);
+                return Ok(());
+            }
+            None => {}
+        }
+    }
+    bail!("Diff was interrupted before it completed")
+}
+
+async fn query(
+    server_url: String,
+    version_id: String,
+    entity_name: String,
+    archived: bool,
+    limit: u32,
+) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = tonic::Request::new(DataQueryRequest {
+        version_id,
+        entity_name,
+        archived,
+        limit,
+    });
+    let response = execute!(client.data_query(request).await);
+    for row in response.rows {
+        println!("{}", row);
+    }
+    Ok(())
+}
+
+async fn archives(server_url: String) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = tonic::Request::new(ListArchivesRequest {});
+    let response = execute!(client.list_archives(request).await);
+    for archived in response.archives {
+        let archived_at = time::OffsetDateTime::from_unix_timestamp(archived.archived_at)?;
+        println!(
+            "{}.{} (archived at {})",
+            archived.version_id, archived.entity_name, archived_at
+        );
+    }
+    Ok(())
+}
+
+async fn check_counts(server_url: String, version_id: String, fix: bool) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = tonic::Request::new(CheckCountsRequest { version_id, fix });
+    let response = execute!(client.check_counts(request).await);
+    for mismatch in response.mismatches.iter() {
+        let stored = mismatch
+            .stored
+            .map(|stored| stored.to_string())
+            .unwrap_or_else(|| "<missing>".into());
+        println!(
+            "{}.{} of {}: stored {}, actual {}",
+            mismatch.entity_name, mismatch.field_name, mismatch.id, stored, mismatch.actual
+        );
+    }
+    if response.mismatches.is_empty() {
+        println!(
+            "Checked {} @count fields, all of them match the data",
+            response.checked
+        );
+    } else if fix {
+        println!(
+            "Checked {} @count fields, recomputed {} mismatched counts",
+            response.checked,
+            response.mismatches.len()
+        );
+    } else {
+        bail!(
+            "Found {} mismatched counts in {} @count fields, use --fix to recompute them",
+            response.mismatches.len(),
+            response.checked
+        );
+    }
+    Ok(())
+}
+
+fn print_row(row: &RowDiff, from: &str, to: &str) {
+    match row_diff::Kind::from_i32(row.kind) {
+        Some(row_diff::Kind::Changed) => {
+            println!("~ {}", row.id);
+            for field in row.fields.iter() {
+                let missing = "<missing>".to_string();
+                println!(
+                    "    {}: {} -> {}",
+                    field.field_name,
+                    field.from_value.as_ref().unwrap_or(&missing),
+                    field.to_value.as_ref().unwrap_or(&missing),
+                );
+            }
+        }
+        Some(row_diff::Kind::OnlyInFrom) => println!("- {} (only in {})", row.id, from),
+        Some(row_diff::Kind::OnlyInTo) => println!("+ {} (only in {})", row.id, to),
+        None => println!("? {}", row.id),
+    }
+}
+
+fn print_summary(summary: &DataDiffSummary, from: &str, to: &str) {
+    println!(
+        "Compared {} rows: {} changed, {} only in {}, {} only in {}",
+        summary.compared, summary.changed, summary.only_in_from, from, summary.only_in_to, to,
+    );
+    if !summary.from_only_fields.is_empty() {
+        println!(
+            "Fields only in {} (not compared): {}",
+            from,
+            summary.from_only_fields.join(", ")
+        );
+    }
+    if !summary.to_only_fields.is_empty() {
+        println!(
+            "Fields only in {} (not compared): {}",
+            to,
+            summary.to_only_fields.join(", ")
+        );
+    }
+}
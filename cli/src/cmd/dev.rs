@@ -1,10 +1,13 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use crate::cmd::apply::{apply, AllowTypeDeletion, TypeChecking};
+use crate::cmd::apply::{
+    apply, AllowTypeDeletion, AllowedChanges, ApplyLock, ApplyOptions, TypeChecking,
+};
+use crate::procs::DevProcs;
 use crate::project::read_manifest;
 use crate::server::wait;
 use crate::DEFAULT_API_VERSION;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use deno_core::futures;
 use endpoint_tsc::tsc_compile;
 use futures::channel::mpsc::channel;
@@ -23,7 +26,9 @@ use tsc_compile::deno_core;
 pub(crate) async fn cmd_dev(
     server_url: String,
     type_check: bool,
-) -> Result<JoinHandle<Result<()>>> {
+    reload: bool,
+    offline: bool,
+) -> Result<()> {
     let type_check = type_check.into();
     let cwd = env::current_dir()?;
     let manifest = read_manifest(&cwd)?;
@@ -41,7 +46,15 @@ pub(crate) async fn cmd_dev(
         Ok(())
     });
     wait(server_url.clone()).await?;
-    apply_from_dev(server_url.clone(), type_check).await;
+    let apply_options = |reload| ApplyOptions {
+        incremental: true,
+        reload,
+        offline,
+        ..Default::default()
+    };
+    // the remote imports are only fetched again by the first apply
+    apply_from_dev(server_url.clone(), type_check, apply_options(reload)).await;
+    let mut procs = DevProcs::spawn(&cwd, &manifest.dev.proc)?;
     let (mut watcher_tx, mut watcher_rx) = channel(1);
     let config = Config::default()
         .with_poll_interval(Duration::from_millis(100))
@@ -61,6 +74,9 @@ pub(crate) async fn cmd_dev(
     tracked.extend(manifest.models.iter().map(|d| cwd.join(d)));
     tracked.extend(manifest.policies.iter().map(|d| cwd.join(d)));
     tracked.extend(manifest.routes.iter().map(|d| cwd.join(d)));
+    tracked.extend(manifest.auth_hook.iter().map(|p| cwd.join(p)));
+    tracked.extend(manifest.seed_dirs().iter().map(|d| cwd.join(d)));
+    tracked.insert(cwd.join(manifest.static_dir()));
     tracked.extend(
         manifest
             .events
@@ -70,10 +86,13 @@ pub(crate) async fn cmd_dev(
     );
     apply_watcher.watch(&cwd, RecursiveMode::Recursive)?;
 
-    loop {
+    let proc_exit = loop {
         tokio::select! {
             _ = signal_rx.next() => {
-                break;
+                break None;
+            }
+            (name, status) = procs.wait_any() => {
+                break Some((name, status));
             }
             res = watcher_rx.next() => {
                 let res = res.unwrap();
@@ -93,7 +112,8 @@ pub(crate) async fn cmd_dev(
                         };
 
                         if paths.iter().any(is_tracked) {
-                            apply_from_dev(server_url.clone(), type_check).await;
+                            let options = apply_options(false);
+                            apply_from_dev(server_url.clone(), type_check, options).await;
                         }
                     }
                     Ok(_) => { /* ignore */ }
@@ -101,15 +121,30 @@ pub(crate) async fn cmd_dev(
                 }
             }
         }
+    };
+    procs.shutdown().await;
+    match proc_exit {
+        None => sig_task.await?,
+        Some((name, status)) => {
+            sig_task.abort();
+            let status = status.map_or_else(|e| e.to_string(), |s| s.to_string());
+            Err(anyhow!(
+                "Dev process `{}` exited ({}), stopping",
+                name,
+                status
+            ))
+        }
     }
-    Ok(sig_task)
 }
 
-async fn apply_from_dev(server_url: String, type_check: TypeChecking) {
+async fn apply_from_dev(server_url: String, type_check: TypeChecking, options: ApplyOptions) {
     if let Err(e) = apply(
         server_url,
-        DEFAULT_API_VERSION.to_string(),
+        DEFAULT_API_VERSION.to_string().into(),
         AllowTypeDeletion::No,
+        AllowedChanges::default(),
+        options,
+        ApplyLock::default(),
         type_check,
     )
     .await
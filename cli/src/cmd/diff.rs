@@ -0,0 +1,412 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::cmd::apply::{compile_project, ApplyOptions, CompiledProject, TypeChecking};
+use crate::proto::chisel_rpc_client::ChiselRpcClient;
+use crate::proto::{FieldDefinition, VersionStateRequest, VersionStateResponse};
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
+use std::path::Path;
+
+/// Compares the project in the current directory with the version that is applied in the server,
+/// and prints what `chisel apply` would change.
+pub(crate) async fn cmd_diff(server_url: String, version_id: String) -> Result<()> {
+    let project = compile_project(TypeChecking::No, &ApplyOptions::default()).await?;
+
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = VersionStateRequest {
+        version_id: version_id.clone(),
+    };
+    let state = execute!(client.get_version_state(tonic::Request::new(request)).await);
+
+    let cwd = env::current_dir()?;
+    let lines = diff_lines(&project, &state, &cwd);
+    if lines.is_empty() {
+        println!("The project matches version {}", version_id);
+    } else {
+        println!(
+            "Changes of version {} (+ added, - removed, ~ changed):",
+            version_id
+        );
+        for line in lines.iter() {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn diff_lines(local: &CompiledProject, remote: &VersionStateResponse, cwd: &Path) -> Vec<String> {
+    let mut lines = vec![];
+    diff_entities(&mut lines, local, remote);
+    diff_indexes(&mut lines, local, remote);
+    diff_routes(&mut lines, local, remote);
+    diff_modules(&mut lines, local, remote, cwd);
+    diff_policies(&mut lines, local, remote, cwd);
+    lines
+}
+
+fn diff_entities(lines: &mut Vec<String>, local: &CompiledProject, remote: &VersionStateResponse) {
+    let remote_types = remote
+        .type_defs
+        .iter()
+        .map(|def| (def.name.as_str(), def))
+        .collect::<BTreeMap<_, _>>();
+    let local_types = local
+        .types
+        .iter()
+        .map(|def| (def.name.as_str(), def))
+        .collect::<BTreeMap<_, _>>();
+
+    for (name, local_def) in local_types.iter() {
+        let remote_def = match remote_types.get(name) {
+            Some(remote_def) => remote_def,
+            None => {
+                lines.push(format!("+ entity {}", name));
+                continue;
+            }
+        };
+
+        // the server describes the `id` field, which is implicit in the project
+        let remote_fields = remote_def
+            .field_defs
+            .iter()
+            .filter(|field| field.name != "id" || has_field(&local_def.field_defs, "id"))
+            .collect::<Vec<_>>();
+        let mut field_lines = vec![];
+        // fields that are renamed with `@renamedFrom` (by their old name), which the server
+        // doesn't remember after the rename
+        let mut renamed = HashSet::new();
+        for field in local_def.field_defs.iter() {
+            let remote_field = remote_fields.iter().find(|f| f.name == field.name);
+            // like the server, ignore the rename if the project still has the old field
+            let renamed_field = remote_fields.iter().find(|f| {
+                !field.renamed_from.is_empty()
+                    && f.name == field.renamed_from
+                    && !has_field(&local_def.field_defs, &f.name)
+            });
+            match (remote_field, renamed_field) {
+                (None, Some(old)) => {
+                    renamed.insert(old.name.as_str());
+                    field_lines.push(format!("    ~ field {} -> {}", old.name, field.name))
+                }
+                (None, None) => field_lines.push(format!("    + field {}", field.name)),
+                (Some(remote_field), _) if !same_field(remote_field, field) => {
+                    field_lines.push(format!("    ~ field {}", field.name))
+                }
+                (Some(_), _) => {}
+            }
+        }
+        for field in remote_fields.iter() {
+            if !renamed.contains(field.name.as_str())
+                && !has_field(&local_def.field_defs, &field.name)
+            {
+                field_lines.push(format!("    - field {}", field.name));
+            }
+        }
+
+        let entity_changed = local_def.locked != remote_def.locked
+            || local_def.aggregate != remote_def.aggregate
+            || local_def.owned_by != remote_def.owned_by;
+        if entity_changed || !field_lines.is_empty() {
+            lines.push(format!("~ entity {}", name));
+            lines.append(&mut field_lines);
+        }
+    }
+    for name in remote_types.keys() {
+        if !local_types.contains_key(name) {
+            lines.push(format!("- entity {}", name));
+        }
+    }
+}
+
+/// Compares a field of the server with a field of the project, ignoring `@renamedFrom`.
+fn same_field(remote: &FieldDefinition, local: &FieldDefinition) -> bool {
+    *remote
+        == FieldDefinition {
+            renamed_from: String::new(),
+            ..local.clone()
+        }
+}
+
+fn has_field(fields: &[FieldDefinition], name: &str) -> bool {
+    fields.iter().any(|field| field.name == name)
+}
+
+fn diff_indexes(lines: &mut Vec<String>, local: &CompiledProject, remote: &VersionStateResponse) {
+    let local_indexes = local
+        .index_candidates
+        .iter()
+        .map(|index| (index.entity_name.as_str(), index.properties.join(", ")))
+        .collect::<BTreeSet<_>>();
+    let remote_indexes = remote
+        .indexes
+        .iter()
+        .map(|index| (index.entity_name.as_str(), index.fields.join(", ")))
+        .collect::<BTreeSet<_>>();
+
+    for (entity_name, fields) in local_indexes.difference(&remote_indexes) {
+        lines.push(format!("+ index {}({})", entity_name, fields));
+    }
+    for (entity_name, fields) in remote_indexes.difference(&local_indexes) {
+        lines.push(format!("- index {}({})", entity_name, fields));
+    }
+}
+
+/// Compares the file-based routes of the project with the routes of the server. Files define
+/// prefixes (a file can contain a whole `RouteMap`), so a route of the server matches a file if it
+/// is under the prefix of the file.
+fn diff_routes(lines: &mut Vec<String>, local: &CompiledProject, remote: &VersionStateResponse) {
+    for prefix in local.route_patterns.iter() {
+        if !remote
+            .routes
+            .iter()
+            .any(|pattern| is_under_prefix(pattern, prefix))
+        {
+            lines.push(format!("+ route {}", prefix));
+        }
+    }
+    for pattern in remote.routes.iter() {
+        if !local
+            .route_patterns
+            .iter()
+            .any(|prefix| is_under_prefix(pattern, prefix))
+        {
+            lines.push(format!("- route {}", pattern));
+        }
+    }
+}
+
+fn is_under_prefix(pattern: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty()
+        || pattern == prefix
+        || pattern
+            .strip_prefix(prefix)
+            .map_or(false, |rest| rest.starts_with('/'))
+}
+
+fn diff_modules(
+    lines: &mut Vec<String>,
+    local: &CompiledProject,
+    remote: &VersionStateResponse,
+    cwd: &Path,
+) {
+    let local_modules = local
+        .modules
+        .iter()
+        .map(|module| (module.url.as_str(), module.code.as_str()))
+        .collect::<BTreeMap<_, _>>();
+    let remote_modules = remote
+        .modules
+        .iter()
+        .map(|module| (module.url.as_str(), module.code.as_str()))
+        .collect::<BTreeMap<_, _>>();
+
+    for (url, code) in local_modules.iter() {
+        match remote_modules.get(url) {
+            None => lines.push(format!("+ module {}", display_url(url, cwd))),
+            Some(remote_code) if remote_code != code => {
+                lines.push(format!("~ module {}", display_url(url, cwd)))
+            }
+            Some(_) => {}
+        }
+    }
+    for url in remote_modules.keys() {
+        if !local_modules.contains_key(url) {
+            lines.push(format!("- module {}", display_url(url, cwd)));
+        }
+    }
+}
+
+/// Shows the URLs of modules in the project as paths relative to the project.
+fn display_url(url: &str, cwd: &Path) -> String {
+    url.strip_prefix("file://")
+        .and_then(|path| Path::new(path).strip_prefix(cwd).ok())
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| url.to_owned())
+}
+
+fn diff_policies(
+    lines: &mut Vec<String>,
+    local: &CompiledProject,
+    remote: &VersionStateResponse,
+    cwd: &Path,
+) {
+    let mut local_yaml = None;
+    let mut local_type_policies = BTreeMap::new();
+    for policy in local.policies.iter() {
+        let path = Path::new(&policy.path);
+        let display_path = path.strip_prefix(cwd).unwrap_or(path).display().to_string();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ts") => {
+                let entity_name = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or_default();
+                local_type_policies.insert(entity_name, (display_path, &policy.policy_config));
+            }
+            _ => local_yaml = Some((display_path, &policy.policy_config)),
+        }
+    }
+
+    match local_yaml {
+        Some((path, yaml)) if remote.policy_yaml.is_empty() => {
+            if !yaml.trim().is_empty() {
+                lines.push(format!("+ policy {}", path));
+            }
+        }
+        Some((path, yaml)) if *yaml != remote.policy_yaml => {
+            lines.push(format!("~ policy {}", path))
+        }
+        Some(_) => {}
+        None if !remote.policy_yaml.trim().is_empty() => {
+            lines.push("- policy (YAML policies)".into())
+        }
+        None => {}
+    }
+
+    for (entity_name, (path, code)) in local_type_policies.iter() {
+        match remote.type_policies.get(*entity_name) {
+            None => lines.push(format!("+ policy {}", path)),
+            Some(remote_code) if remote_code != *code => lines.push(format!("~ policy {}", path)),
+            Some(_) => {}
+        }
+    }
+    let mut removed = remote
+        .type_policies
+        .keys()
+        .filter(|entity_name| !local_type_policies.contains_key(entity_name.as_str()))
+        .collect::<Vec<_>>();
+    removed.sort_unstable();
+    for entity_name in removed {
+        lines.push(format!("- policy of {}", entity_name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{type_msg::TypeEnum, AddTypeRequest, TypeDefinition, TypeMsg};
+
+    fn field(name: &str, is_optional: bool) -> FieldDefinition {
+        FieldDefinition {
+            name: name.into(),
+            field_type: Some(TypeMsg {
+                type_enum: Some(TypeEnum::String(true)),
+            }),
+            is_optional,
+            ..Default::default()
+        }
+    }
+
+    fn project(types: Vec<AddTypeRequest>, route_patterns: &[&str]) -> CompiledProject {
+        CompiledProject {
+            types,
+            modules: vec![],
+            reused_modules: Default::default(),
+            index_candidates: vec![],
+            policies: vec![],
+            route_patterns: route_patterns.iter().map(|p| p.to_string()).collect(),
+            static_files: vec![],
+            allowed_hosts: vec![],
+            worker_pool: Default::default(),
+            import_map: Default::default(),
+        }
+    }
+
+    #[test]
+    fn entities() {
+        let local = project(
+            vec![
+                AddTypeRequest {
+                    name: "Book".into(),
+                    field_defs: vec![field("title", false), field("author", true)],
+                    ..Default::default()
+                },
+                AddTypeRequest {
+                    name: "Person".into(),
+                    field_defs: vec![field("name", false)],
+                    ..Default::default()
+                },
+            ],
+            &[],
+        );
+        let remote = VersionStateResponse {
+            type_defs: vec![
+                TypeDefinition {
+                    name: "Book".into(),
+                    field_defs: vec![
+                        field("id", false),
+                        field("author", false),
+                        field("isbn", false),
+                    ],
+                    ..Default::default()
+                },
+                TypeDefinition {
+                    name: "Old".into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            diff_lines(&local, &remote, Path::new("/project")),
+            vec![
+                "~ entity Book",
+                "    + field title",
+                "    ~ field author",
+                "    - field isbn",
+                "+ entity Person",
+                "- entity Old",
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_fields() {
+        let renamed = |name: &str, renamed_from: &str| FieldDefinition {
+            renamed_from: renamed_from.into(),
+            ..field(name, false)
+        };
+        let local = project(
+            vec![AddTypeRequest {
+                name: "Person".into(),
+                field_defs: vec![renamed("name", "fullName"), renamed("email", "mail")],
+                ..Default::default()
+            }],
+            &[],
+        );
+        let remote = VersionStateResponse {
+            type_defs: vec![TypeDefinition {
+                name: "Person".into(),
+                field_defs: vec![
+                    field("id", false),
+                    field("fullName", false),
+                    field("email", false),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        // `email` was already renamed, so its `@renamedFrom` no longer matters
+        assert_eq!(
+            diff_lines(&local, &remote, Path::new("/project")),
+            vec!["~ entity Person", "    ~ field fullName -> name"]
+        );
+    }
+
+    #[test]
+    fn routes() {
+        let local = project(vec![], &["/books", "/new"]);
+        let remote = VersionStateResponse {
+            routes: vec!["/books".into(), "/books/:id".into(), "/old".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            diff_lines(&local, &remote, Path::new("/project")),
+            vec!["+ route /new", "- route /old"]
+        );
+        assert!(is_under_prefix("/anything", "/"));
+        assert!(!is_under_prefix("/bookshelf", "/books"));
+    }
+}
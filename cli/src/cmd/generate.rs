@@ -81,16 +81,28 @@ fn generate_models(version_def: &VersionDefinition, omit_id: bool) -> Result<Str
     for def in &version_def.type_defs {
         writeln!(output, "export type {} = {{", def.name)?;
         for field in &def.field_defs {
-            if omit_id && field.name == "id" {
+            // the id and the timestamps are set by the server, so they are not sent to it
+            let is_timestamp = !field.timestamp.is_empty();
+            if omit_id && (field.name == "id" || is_timestamp) {
                 continue;
             }
+            // the owner is set by the server, unless the client is an admin
+            let is_owner = omit_id && field.name == def.owned_by;
             let field_type = field.field_type()?;
             writeln!(
                 output,
-                "    {}{}: {};",
+                "    {}{}{}: {};",
+                if is_timestamp { "readonly " } else { "" },
                 field.name,
-                if field.is_optional { "?" } else { "" },
-                type_enum_to_code(field_type)?
+                if field.is_optional || is_owner {
+                    "?"
+                } else {
+                    ""
+                },
+                match &field.enum_type {
+                    Some(enum_type) => crate::enum_union(&enum_type.values),
+                    None => type_enum_to_code(field_type)?,
+                }
             )?;
         }
         writeln!(output, "}}")?;
@@ -104,7 +116,10 @@ fn type_enum_to_code(type_enum: &TypeEnum) -> Result<String> {
         TypeEnum::Bool(_) => "boolean".to_owned(),
         TypeEnum::JsDate(_) => "Date".to_owned(),
         TypeEnum::Number(_) => "number".to_owned(),
-        TypeEnum::String(_) | TypeEnum::EntityId(_) => "string".to_owned(),
+        TypeEnum::Bigint(_) => "bigint".to_owned(),
+        TypeEnum::Json(_) => "unknown".to_owned(),
+        TypeEnum::GeoPoint(_) => "{ lat: number; lng: number }".to_owned(),
+        TypeEnum::String(_) | TypeEnum::EntityId(_) | TypeEnum::Decimal(_) => "string".to_owned(),
         TypeEnum::Array(container) => {
             let element_type = &container
                 .value_type
@@ -201,7 +216,8 @@ fn make_field_obj(
         "name": field.name,
         "type": type_obj,
         "isOptional": field.is_optional,
-        "isUnique": field.is_unique
+        "isUnique": field.is_unique,
+        "isVersion": field.is_version
     }))
 }
 
@@ -218,7 +234,11 @@ fn type_to_obj(
         TypeEnum::Bool(_) => json!({"name": "boolean"}),
         TypeEnum::JsDate(_) => json!({"name": "date"}),
         TypeEnum::Number(_) => json!({"name": "number"}),
-        TypeEnum::String(_) => json!({"name": "string"}),
+        TypeEnum::Bigint(_) => json!({"name": "bigint"}),
+        TypeEnum::Json(_) => json!({"name": "json"}),
+        TypeEnum::GeoPoint(_) => json!({"name": "geoPoint"}),
+        // decimals are strings on the wire
+        TypeEnum::String(_) | TypeEnum::Decimal(_) => json!({"name": "string"}),
         TypeEnum::EntityId(entity_name) => json!({
             "name": "entityId",
             "entityName": entity_name
@@ -280,6 +300,10 @@ async fn get_routing_info(api_listen_addr: &str, version: &str) -> Result<Vec<Ro
 #[serde(tag = "kind", content = "handler", rename_all = "camelCase")]
 enum HandlerKind {
     Crud(CrudHandler),
+    /// Routes created by `ingest()`, which are not part of the generated client.
+    Ingest,
+    /// Routes created by `upload()`, which are not part of the generated client.
+    Upload,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -370,6 +394,9 @@ fn build_routing(routes: &Vec<RouteInfo>) -> Result<SubRoute> {
     for route in routes {
         let methods = &route.methods;
         if let Some(meta) = &route.client_metadata {
+            if let HandlerKind::Ingest | HandlerKind::Upload = meta.handler {
+                continue;
+            }
             anyhow::ensure!(
                 methods.len() == 1,
                 "the number of allowed route methods must be one"
@@ -387,7 +414,10 @@ fn build_routing(routes: &Vec<RouteInfo>) -> Result<SubRoute> {
 }
 
 fn handler_to_ts(handler: &RouteHandler, url: &str) -> Vec<String> {
-    let HandlerKind::Crud(crud_handler) = &handler.kind;
+    let crud_handler = match &handler.kind {
+        HandlerKind::Crud(crud_handler) => crud_handler,
+        HandlerKind::Ingest | HandlerKind::Upload => return vec![],
+    };
     match &crud_handler {
         CrudHandler::DeleteMany(entity_name) => {
             vec![format!(
@@ -404,6 +434,8 @@ fn handler_to_ts(handler: &RouteHandler, url: &str) -> Vec<String> {
                 "getIter: Ωlib.makeGetManyIter<Ωmodels.{entity_name}>(Ωurl(`{url}`), Ωreflection.Ω{entity_name}, Ωconfig)"
             ), format!(
                 "getAll: Ωlib.makeGetAll<Ωmodels.{entity_name}>(Ωurl(`{url}`), Ωreflection.Ω{entity_name}, Ωconfig)"
+            ), format!(
+                "getPage: Ωlib.makeGetPage<Ωmodels.{entity_name}>(Ωurl(`{url}`), Ωreflection.Ω{entity_name}, Ωconfig)"
             )]
         }
         CrudHandler::GetOne(entity_name) => {
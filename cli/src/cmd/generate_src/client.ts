@@ -7,6 +7,17 @@ export type ClientConfig = Ωlib.ClientConfig;
  * (it removes id fields for nested entities as well)
  */
 export type WithoutId<Entity> = ΩWithoutId<Entity>;
+/**
+ * Thrown by writes of entities with a `@version` field, when the version is
+ * out of date.
+ */
+export const ConflictError = Ωlib.ConflictError;
+export type ConflictError = Ωlib.ConflictError;
+/**
+ * A page of entities returned by `getPage()`, the same type as the `Page`
+ * returned by `Entity.page()` in the backend.
+ */
+export type Page<Entity> = Ωlib.Page<Entity>;
 
 /**
  * Creates an object that exposes an API to make requests of a ChiselStrike
@@ -55,6 +66,7 @@ export type WithoutId<Entity> = ΩWithoutId<Entity>;
  * - `chiselClient.myEntities.get()`
  * - `chiselClient.myEntities.getAll()`
  * - `chiselClient.myEntities.getIter()`
+ * - `chiselClient.myEntities.getPage()`
  * - `chiselClient.myEntities.post()`
  *
  * The generated methods that deal with specific instances of an entity given
@@ -76,7 +76,23 @@ function assertNever(x: never): never {
     return x;
 }
 
+/**
+ * Thrown when a write fails because the `@version` field of the entity is out
+ * of date, which means that the entity was modified by somebody else since it
+ * was read.
+ */
+export class ConflictError extends Error {
+    constructor(msg: string) {
+        super(msg);
+    }
+}
+
 async function throwOnError(resp: Response) {
+    if (resp.status === 409) {
+        throw new ConflictError(
+            `entity was modified concurrently: '${await resp.text()}'`,
+        );
+    }
     if (!resp.ok) {
         // TODO: Improve error handling
         throw Error(
@@ -103,6 +119,9 @@ function valueToJson(v: unknown): JSONValue {
         return v as string;
     } else if (typeof v === "number" || v instanceof Number) {
         return v as number;
+    } else if (typeof v === "bigint") {
+        // JSON numbers can't hold all 64-bit integers
+        return v.toString();
     } else if (typeof v === "boolean" || v instanceof Boolean) {
         return v as boolean;
     } else if (v instanceof Date) {
@@ -231,6 +250,8 @@ function entityFromJson<Entity>(
                 throw err("number");
             }
             entityValue[fieldName] = fieldValue;
+        } else if (fieldType === "bigint") {
+            entityValue[fieldName] = bigintFromJson(context, fieldValue);
         } else if (fieldType === "boolean") {
             if (typeof fieldValue !== "boolean") {
                 throw err("boolean");
@@ -246,6 +267,8 @@ function entityFromJson<Entity>(
                 context,
                 fieldValue,
             );
+        } else if (fieldType === "json" || fieldType === "geoPoint") {
+            entityValue[fieldName] = fieldValue;
         } else if (fieldType === "array") {
             entityValue[fieldName] = arrayFromJson(
                 context,
@@ -268,6 +291,24 @@ function entityFromJson<Entity>(
     return entityValue as unknown as Entity;
 }
 
+function bigintFromJson(
+    context: AccessContext,
+    value: unknown,
+): bigint {
+    if (typeof value === "string" || typeof value === "number") {
+        try {
+            return BigInt(value);
+        } catch {
+            throw new Error(
+                `failed to convert value to bigint for ${context}`,
+            );
+        }
+    }
+    throw new Error(
+        `${context} is of type bigint (transported as string), but received value is of type ${typeof value}`,
+    );
+}
+
 function dateFromJson(
     context: AccessContext,
     value: unknown,
@@ -315,7 +356,11 @@ function arrayFromJson(
             case "number":
             case "boolean":
             case "entityId":
+            case "json":
+            case "geoPoint":
                 return arrayValue;
+            case "bigint":
+                return arrayValue.map((e) => bigintFromJson(arrayContext, e));
             case "date":
                 return arrayValue.map((e) => dateFromJson(arrayContext, e));
             case "arrayBuffer":
@@ -412,6 +457,11 @@ function entityToJson<Entity>(
                 throw err("number");
             }
             outputJson[fieldName] = fieldValue;
+        } else if (fieldType === "bigint") {
+            if (typeof fieldValue !== "bigint") {
+                throw err("bigint");
+            }
+            outputJson[fieldName] = fieldValue.toString();
         } else if (fieldType === "boolean") {
             if (typeof fieldValue !== "boolean") {
                 throw err("boolean");
@@ -424,6 +474,8 @@ function entityToJson<Entity>(
                 context,
                 fieldValue,
             );
+        } else if (fieldType === "json" || fieldType === "geoPoint") {
+            outputJson[fieldName] = fieldValue;
         } else if (fieldType === "array") {
             outputJson[fieldName] = arrayToJson(
                 context,
@@ -484,7 +536,11 @@ function arrayToJson(
             case "number":
             case "boolean":
             case "entityId":
+            case "json":
+            case "geoPoint":
                 return arrayValue;
+            case "bigint":
+                return arrayValue.map((e) => String(e));
             case "arrayBuffer":
                 return arrayValue.map(arrayBufferToJson);
             case "date":
@@ -567,9 +623,19 @@ export type GetResponse<Entity> = {
     nextPageUrl?: string;
     prevPage?: () => Promise<GetResponse<Entity>>;
     prevPageUrl?: string;
+    pagination: Pagination;
     results: Entity[];
 };
 
+export type Pagination = {
+    /** Null if the whole result set was returned at once with `full_scan=true`. */
+    pageSize: number | null;
+    maxPageSize: number;
+    /** Number of entities in this page. */
+    count: number;
+    fullScan: boolean;
+};
+
 export function makeGetMany<Entity>(
     origUrl: URL,
     entityType: reflect.Entity,
@@ -604,6 +670,12 @@ export function makeGetMany<Entity>(
             type PagingResponse = {
                 next_page?: string;
                 prev_page?: string;
+                pagination: {
+                    page_size: number | null;
+                    max_page_size: number;
+                    count: number;
+                    full_scan: boolean;
+                };
                 results: Record<string, unknown>[];
             };
             const resp: PagingResponse = await r.json();
@@ -627,6 +699,12 @@ export function makeGetMany<Entity>(
                 nextPageUrl: resp.next_page,
                 prevPage,
                 prevPageUrl: resp.prev_page,
+                pagination: {
+                    pageSize: resp.pagination.page_size,
+                    maxPageSize: resp.pagination.max_page_size,
+                    count: resp.pagination.count,
+                    fullScan: resp.pagination.full_scan,
+                },
                 results: resp.results.map((e) =>
                     entityFromJson<Entity>(entityType, e)
                 ),
@@ -636,6 +714,63 @@ export function makeGetMany<Entity>(
     };
 }
 
+export type PageParams<Entity> = {
+    /** The `nextCursor` of the previous page. The first page is returned if not set. */
+    cursor?: string;
+    /** Maximum number of entities in the page. Defaults to the page size of the route. */
+    size?: number;
+    filter?: FilterExpr<Entity>;
+    headers?: Headers | Record<string, string>;
+};
+
+/**
+ * A page of entities, the same type as the `Page` returned by `Entity.page()`
+ * in the backend. The cursors are interchangeable.
+ */
+export type Page<Entity> = {
+    items: Entity[];
+    /** Cursor of the next page, undefined if this is the last page. */
+    nextCursor?: string;
+    total?: number;
+};
+
+export function makeGetPage<Entity>(
+    origUrl: URL,
+    entityType: reflect.Entity,
+    cliConfig: InternalClientConfig,
+): (params?: PageParams<Entity>) => Promise<Page<Entity>> {
+    return async function (
+        params?: PageParams<Entity>,
+    ): Promise<Page<Entity>> {
+        const url = new URL(origUrl);
+        if (params?.cursor !== undefined) {
+            url.searchParams.set("cursor", params.cursor);
+        }
+        if (params?.size !== undefined) {
+            url.searchParams.set("page_size", params.size.toString());
+        }
+        if (params?.filter !== undefined) {
+            const jsonFilter = valueToJson(params.filter);
+            url.searchParams.set("filter", JSON.stringify(jsonFilter));
+        }
+        const r = await fetch(url.toString(), {
+            method: "GET",
+            headers: mergeHeaders(cliConfig.headers, params?.headers),
+        });
+        await throwOnError(r);
+        const resp: {
+            next_cursor?: string;
+            results: Record<string, unknown>[];
+        } = await r.json();
+        return {
+            items: resp.results.map((e) =>
+                entityFromJson<Entity>(entityType, e)
+            ),
+            nextCursor: resp.next_cursor,
+        };
+    };
+}
+
 export function makeGetManyIter<Entity>(
     origUrl: URL,
     entityType: reflect.Entity,
@@ -1,9 +1,12 @@
 export type Type =
     | { name: "string" }
     | { name: "number" }
+    | { name: "bigint" }
     | { name: "boolean" }
     | { name: "date" }
     | { name: "arrayBuffer" }
+    | { name: "json" }
+    | { name: "geoPoint" }
     | { name: "array"; elementType: Type }
     | { name: "entity"; entityType: Entity }
     | { name: "entityId"; entityName: string };
@@ -13,6 +16,7 @@ export type Field = {
     type: Type;
     isOptional: boolean;
     isUnique: boolean;
+    isVersion: boolean;
 };
 
 export type Entity = {
@@ -0,0 +1,450 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::events::build_file_topic_map;
+use crate::project::{read_manifest, Manifest, Module};
+use crate::ts::{diagnostics_handler, parse_module};
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use swc_common::errors::Handler;
+use swc_common::sync::Lrc;
+use swc_common::{SourceMap, Spanned};
+use swc_ecmascript::ast::{
+    self, Callee, ExportSpecifier, Expr, Lit, MemberProp, ModuleDecl, ModuleExportName, ModuleItem,
+    NamedExport,
+};
+
+/// HTTP methods that can be passed to `RouteMap.route()` (the methods are case-insensitive).
+const HTTP_METHODS: &[&str] = &[
+    "*", "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Methods of `RouteMap` that can be chained in the default export of a route.
+const ROUTE_MAP_METHODS: &[&str] = &[
+    "route",
+    "prefix",
+    "socket",
+    "get",
+    "post",
+    "put",
+    "delete",
+    "patch",
+    "middleware",
+    "securityHeaders",
+];
+
+/// Checks the project in the current directory without a server: the models, the policies, the
+/// default exports of routes, event handlers, seeds and the auth hook, and the imports of all the
+/// code. The problems are reported in the same format as in `chisel apply`.
+pub(crate) fn cmd_lint() -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    let manifest = read_manifest(&cwd)?;
+    let mut linter = Linter::new(&cwd, &manifest);
+    linter.lint(&manifest);
+
+    match linter.handler.err_count() {
+        0 => {
+            println!("No problems found");
+            Ok(())
+        }
+        1 => bail!("Found 1 problem"),
+        count => bail!("Found {} problems", count),
+    }
+}
+
+/// Kind of the files that must have a default export.
+#[derive(Debug, Clone, Copy)]
+enum EntryKind {
+    Route,
+    EventHandler,
+    Seed,
+    AuthHook,
+}
+
+impl EntryKind {
+    fn expected_export(&self) -> &'static str {
+        match self {
+            EntryKind::Route => "a RouteMap or a handler function",
+            EntryKind::EventHandler => "an event handler function",
+            EntryKind::Seed => "a seed function",
+            EntryKind::AuthHook => "an auth hook function",
+        }
+    }
+}
+
+struct Linter {
+    cwd: PathBuf,
+    cm: Lrc<SourceMap>,
+    handler: Handler,
+    /// Whether the project uses node-style modules (which can import packages from
+    /// `node_modules`), instead of deno-style modules.
+    node_modules: bool,
+    /// Parsed files (`None` if the file could not be parsed).
+    modules: HashMap<PathBuf, Option<Rc<ast::Module>>>,
+    /// Files whose imports were checked.
+    imports_checked: HashSet<PathBuf>,
+}
+
+impl Linter {
+    fn new(cwd: &Path, manifest: &Manifest) -> Self {
+        let cm: Lrc<SourceMap> = Default::default();
+        let handler = diagnostics_handler(cm.clone());
+        Self {
+            cwd: cwd.to_owned(),
+            cm,
+            handler,
+            node_modules: manifest.modules == Module::Node,
+            modules: HashMap::new(),
+            imports_checked: HashSet::new(),
+        }
+    }
+
+    fn lint(&mut self, manifest: &Manifest) {
+        let cwd = self.cwd.clone();
+
+        if let Some(models) = self.check(manifest.models(&cwd)) {
+            // the syntax errors in models are reported by `parse_types()`, so we don't parse the
+            // models again if there are any
+            if self.check(crate::ts::parse_types(&models)).is_some() {
+                for path in models.iter() {
+                    self.check_file(path, None);
+                }
+            }
+        }
+
+        if let Some(route_map) = self.check(manifest.route_map(&cwd)) {
+            for route in route_map.routes.iter() {
+                self.check_file(&route.file_path, Some(EntryKind::Route));
+            }
+        }
+
+        // `chisel apply` only warns about invalid event handlers, but they are problems all the same
+        let event_dirs = manifest.events.clone().unwrap_or_default();
+        if let Some(topic_map) = self.check(build_file_topic_map(&cwd, &event_dirs)) {
+            for topic in topic_map.topics.iter() {
+                self.check_file(&topic.file_path, Some(EntryKind::EventHandler));
+            }
+        }
+
+        if let Some(seed_map) = self.check(manifest.seed_map(&cwd)) {
+            for seed in seed_map.seeds.iter() {
+                self.check_file(&seed.file_path, Some(EntryKind::Seed));
+            }
+        }
+
+        let auth_hook = self.check(manifest.auth_hook(&cwd)).flatten();
+        if let Some(auth_hook) = auth_hook.as_ref() {
+            self.check_file(auth_hook, Some(EntryKind::AuthHook));
+        }
+
+        if let Some(policies) = self.check(manifest.policies(&cwd)) {
+            let mut yaml_policies = 0;
+            for path in policies.iter().filter(|p| Some(*p) != auth_hook.as_ref()) {
+                if path.extension() != Some(OsStr::new("ts")) {
+                    yaml_policies += 1;
+                    if yaml_policies > 1 {
+                        self.error(format!(
+                            "{}: currently only one policy file is supported",
+                            path.display()
+                        ));
+                    }
+                }
+                let policy = fs::read_to_string(path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|code| chisel_server::check_policy(path, &code));
+                if let Err(err) = policy {
+                    self.error(format!("Invalid policy {}: {:#}", path.display(), err));
+                }
+            }
+        }
+    }
+
+    /// Reports the error of `result`, if any.
+    fn check<T>(&self, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.error(format!("{:#}", err));
+                None
+            }
+        }
+    }
+
+    fn error(&self, msg: String) {
+        self.handler.err(&msg);
+    }
+
+    fn parse(&mut self, path: &Path) -> Option<Rc<ast::Module>> {
+        if let Some(module) = self.modules.get(path) {
+            return module.clone();
+        }
+        // syntax errors are reported by the parser, other errors (such as I/O errors) are not
+        let err_count = self.handler.err_count();
+        let module = match parse_module(&self.cm, &self.handler, path) {
+            Ok(module) => Some(Rc::new(module)),
+            Err(err) => {
+                if self.handler.err_count() == err_count {
+                    self.error(format!("Could not parse {}: {:#}", path.display(), err));
+                }
+                None
+            }
+        };
+        self.modules.insert(path.to_owned(), module.clone());
+        module
+    }
+
+    fn check_file(&mut self, path: &Path, kind: Option<EntryKind>) {
+        let module = match self.parse(path) {
+            Some(module) => module,
+            None => return,
+        };
+        if let Some(kind) = kind {
+            self.check_default_export(path, &module, kind);
+        }
+        self.check_imports(path, &module);
+    }
+
+    fn check_default_export(&mut self, path: &Path, module: &ast::Module, kind: EntryKind) {
+        let export = module.body.iter().find_map(default_export);
+        let export = match export {
+            Some(export) => export,
+            None => {
+                self.error(format!(
+                    "{} has no default export (it should export {})",
+                    path.display(),
+                    kind.expected_export()
+                ));
+                return;
+            }
+        };
+
+        if let DefaultExport::Expr(expr) = export {
+            if is_plain_value(expr) {
+                self.handler.span_err(
+                    expr.span(),
+                    &format!("The default export should be {}", kind.expected_export()),
+                );
+            } else if let EntryKind::Route = kind {
+                self.check_route_chain(expr);
+            }
+        }
+    }
+
+    /// Checks a chain of `RouteMap` method calls (`new RouteMap().get(...).post(...)`). Returns
+    /// false if `expr` is not such a chain.
+    fn check_route_chain(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::New(new) => {
+                matches!(&*new.callee, Expr::Ident(ident) if &*ident.sym == "RouteMap")
+            }
+            Expr::Paren(paren) => self.check_route_chain(&paren.expr),
+            Expr::Call(call) => {
+                let member = match &call.callee {
+                    Callee::Expr(callee) => match &**callee {
+                        Expr::Member(member) => member,
+                        _ => return false,
+                    },
+                    _ => return false,
+                };
+                let method = match &member.prop {
+                    MemberProp::Ident(method) => method,
+                    _ => return false,
+                };
+                if !self.check_route_chain(&member.obj) {
+                    return false;
+                }
+
+                if !ROUTE_MAP_METHODS.contains(&&*method.sym) {
+                    self.handler.span_err(
+                        method.span,
+                        &format!("RouteMap has no method `{}`", method.sym),
+                    );
+                } else if &*method.sym == "route" {
+                    if let Some(arg) = call.args.first() {
+                        self.check_http_methods(&arg.expr);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn check_http_methods(&self, expr: &Expr) {
+        match expr {
+            Expr::Lit(Lit::Str(method)) => {
+                if !HTTP_METHODS.contains(&method.value.to_uppercase().as_str()) {
+                    self.handler.span_err(
+                        method.span,
+                        &format!("Unknown HTTP method {:?}", &*method.value),
+                    );
+                }
+            }
+            Expr::Array(array) => {
+                for elem in array.elems.iter().flatten() {
+                    self.check_http_methods(&elem.expr);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks that the imports of the file can be resolved, and then checks the imports of the
+    /// imported files of the project.
+    fn check_imports(&mut self, path: &Path, module: &ast::Module) {
+        if !self.imports_checked.insert(path.to_owned()) {
+            return;
+        }
+
+        let mut imported = vec![];
+        for item in module.body.iter() {
+            let src = match item {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => &import.src,
+                ModuleItem::ModuleDecl(ModuleDecl::ExportAll(export)) => &export.src,
+                ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(NamedExport {
+                    src: Some(src),
+                    ..
+                })) => src,
+                _ => continue,
+            };
+            match self.resolve_import(path, &src.value) {
+                Ok(Some(resolved)) => imported.push(resolved),
+                Ok(None) => {}
+                Err(msg) => self.handler.span_err(src.span, &msg),
+            }
+        }
+
+        for path in imported {
+            let ext = path.extension().and_then(OsStr::to_str);
+            if matches!(ext, Some("ts" | "js")) {
+                self.check_file(&path, None);
+            }
+        }
+    }
+
+    /// Resolves the import `spec` in the file `from`. Returns the imported file if it is a part
+    /// of the project.
+    fn resolve_import(&self, from: &Path, spec: &str) -> Result<Option<PathBuf>, String> {
+        if has_url_scheme(spec) || spec == "@chiselstrike/api" {
+            return Ok(None);
+        }
+
+        if spec.starts_with("./") || spec.starts_with("../") || spec.starts_with('/') {
+            let base = from.parent().unwrap_or(&self.cwd).join(spec);
+            let mut candidates = vec![base.clone()];
+            if self.node_modules {
+                // node-style resolution also allows imports without the extension
+                for suffix in [".ts", ".js", "/index.ts", "/index.js"] {
+                    let mut candidate = base.clone().into_os_string();
+                    candidate.push(suffix);
+                    candidates.push(candidate.into());
+                }
+            }
+            return match candidates.into_iter().find(|path| path.is_file()) {
+                Some(path) => Ok(Some(path)),
+                None => Err(format!("Cannot resolve import {:?}", spec)),
+            };
+        }
+
+        if !self.node_modules {
+            return Err(format!(
+                "Cannot resolve import {:?}: deno-style modules can only import relative paths and URLs",
+                spec
+            ));
+        }
+        let package = package_name(spec);
+        if self.cwd.join("node_modules").join(package).exists() {
+            Ok(None)
+        } else {
+            Err(format!(
+                "Cannot resolve import {:?}: package {} is not installed in node_modules",
+                spec, package
+            ))
+        }
+    }
+}
+
+enum DefaultExport<'a> {
+    /// `export default <expr>`
+    Expr(&'a Expr),
+    /// `export default function ...`, `export default class ...` or `export { x as default }`
+    Decl,
+}
+
+fn default_export(item: &ModuleItem) -> Option<DefaultExport<'_>> {
+    match item {
+        ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => {
+            Some(DefaultExport::Expr(&export.expr))
+        }
+        ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(_)) => Some(DefaultExport::Decl),
+        ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export)) => {
+            let is_default = export.specifiers.iter().any(|specifier| match specifier {
+                ExportSpecifier::Default(_) => true,
+                ExportSpecifier::Named(named) => {
+                    let name = named.exported.as_ref().unwrap_or(&named.orig);
+                    matches!(name, ModuleExportName::Ident(ident) if &*ident.sym == "default")
+                }
+                ExportSpecifier::Namespace(_) => false,
+            });
+            if is_default {
+                Some(DefaultExport::Decl)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Values that can never be a handler or a `RouteMap`.
+fn is_plain_value(expr: &Expr) -> bool {
+    match expr {
+        Expr::Paren(paren) => is_plain_value(&paren.expr),
+        Expr::Lit(_) | Expr::Tpl(_) | Expr::Object(_) | Expr::Array(_) => true,
+        _ => false,
+    }
+}
+
+fn has_url_scheme(spec: &str) -> bool {
+    match spec.split_once(':') {
+        // a single letter is a drive on Windows, not a scheme
+        Some((scheme, _)) => {
+            scheme.len() > 1
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => false,
+    }
+}
+
+/// The package of a bare import, such as `lodash` in `lodash/fp` or `@scope/pkg` in
+/// `@scope/pkg/sub`.
+fn package_name(spec: &str) -> &str {
+    let segments = if spec.starts_with('@') { 2 } else { 1 };
+    match spec.match_indices('/').nth(segments - 1) {
+        Some((idx, _)) => &spec[..idx],
+        None => spec,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_specifiers() {
+        assert!(has_url_scheme("https://deno.land/std/fmt/colors.ts"));
+        assert!(has_url_scheme("node:fs"));
+        assert!(!has_url_scheme("c:/project/lib.ts"));
+        assert!(!has_url_scheme("./lib.ts"));
+
+        assert_eq!(package_name("lodash"), "lodash");
+        assert_eq!(package_name("lodash/fp"), "lodash");
+        assert_eq!(package_name("@scope/pkg"), "@scope/pkg");
+        assert_eq!(package_name("@scope/pkg/sub/module"), "@scope/pkg");
+    }
+}
@@ -0,0 +1,43 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::proto::chisel_rpc_client::ChiselRpcClient;
+use crate::proto::MetaGcRequest;
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum MetaCommand {
+    /// Remove the rows of deleted versions, expired audit entries and old apply records from the
+    /// meta database, and compact it.
+    ///
+    /// The server also does this periodically (see the `--meta-gc-interval-s` option of chiseld).
+    /// The rows of versions that still exist and the apply that holds the apply lock are never
+    /// removed.
+    Gc {
+        /// Only report what would be removed.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+pub(crate) async fn cmd_meta(server_url: String, cmd: MetaCommand) -> Result<()> {
+    match cmd {
+        MetaCommand::Gc { dry_run } => {
+            let mut client = ChiselRpcClient::connect(server_url).await?;
+            let request = MetaGcRequest { dry_run };
+            let response = execute!(client.meta_gc(tonic::Request::new(request)).await);
+
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            if response.tables.is_empty() {
+                println!("There is no garbage in the meta database");
+            }
+            for table in response.tables.iter() {
+                println!("{} {} rows from {}", verb, table.removed_rows, table.table);
+            }
+            if let Some(reclaimed_bytes) = response.reclaimed_bytes {
+                println!("Compaction reclaimed {} bytes", reclaimed_bytes);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::{parse_version, DEFAULT_API_VERSION};
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Header that carries the `CHISEL_ADMIN_SECRET` secret.
+const ADMIN_SECRET_HEADER: &str = "X-Chisel-Admin-Secret";
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum ModulesCommand {
+    /// Download the modules that a version executes. Every module is written to `<HASH>.js`,
+    /// where `<HASH>` is its content hash, and `modules.json` maps the hashes to module URLs.
+    Pull {
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+        /// Directory where the modules are written. Defaults to `modules-<VERSION>`.
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// The `CHISEL_ADMIN_SECRET` secret of the server, which is required when the server does
+        /// not run in debug mode. Defaults to the `CHISEL_ADMIN_SECRET` environment variable.
+        #[arg(long)]
+        admin_secret: Option<String>,
+    },
+}
+
+/// Module as listed by the server.
+#[derive(Debug, Deserialize, Serialize)]
+struct ModuleInfo {
+    url: String,
+    hash: String,
+}
+
+pub(crate) async fn cmd_modules(api_listen_addr: String, cmd: ModulesCommand) -> Result<()> {
+    match cmd {
+        ModulesCommand::Pull {
+            version,
+            out_dir,
+            admin_secret,
+        } => {
+            let out_dir = out_dir.unwrap_or_else(|| format!("modules-{}", version).into());
+            let admin_secret = admin_secret.or_else(|| std::env::var("CHISEL_ADMIN_SECRET").ok());
+            pull(&api_listen_addr, &version, out_dir, admin_secret).await
+        }
+    }
+}
+
+async fn pull(
+    api_listen_addr: &str,
+    version: &str,
+    out_dir: PathBuf,
+    admin_secret: Option<String>,
+) -> Result<()> {
+    let base_url = reqwest::Url::parse(&format!("http://{}", api_listen_addr))?
+        .join(&format!("/{}/__chiselstrike/modules/", version))?;
+    let client = reqwest::Client::new();
+    let get = |url: reqwest::Url| {
+        let mut request = client.get(url);
+        if let Some(secret) = admin_secret.as_ref() {
+            request = request.header(ADMIN_SECRET_HEADER, secret);
+        }
+        request.send()
+    };
+
+    let response = get(base_url.clone()).await?;
+    let modules: Vec<ModuleInfo> = check_status(response).await?.json().await?;
+
+    fs::create_dir_all(&out_dir)
+        .with_context(|| format!("Could not create directory {}", out_dir.display()))?;
+    for module in modules.iter() {
+        let response = get(base_url.join(&module.hash)?).await?;
+        let code = check_status(response).await?.text().await?;
+        fs::write(out_dir.join(format!("{}.js", module.hash)), code)?;
+        println!("{} {}", module.hash, module.url);
+    }
+    fs::write(
+        out_dir.join("modules.json"),
+        serde_json::to_string_pretty(&modules)?,
+    )?;
+    println!(
+        "Pulled {} modules of version {} into {}",
+        modules.len(),
+        version,
+        out_dir.display()
+    );
+    Ok(())
+}
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let message = response.text().await.unwrap_or_default();
+    anyhow::bail!("Server responded with status {}: {}", status, message)
+}
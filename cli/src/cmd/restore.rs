@@ -0,0 +1,18 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use anyhow::Result;
+use chisel_server::backup::restore_backup;
+use std::path::PathBuf;
+
+/// Restores a backup created by chiseld with `--backup-dir` into the database `db_uri`.
+pub(crate) async fn cmd_restore(backup: PathBuf, db_uri: String, force: bool) -> Result<()> {
+    let manifest = restore_backup(&backup, &db_uri, force).await?;
+    println!(
+        "Restored backup {} (schema version {}, created by chiseld {}) into {}",
+        backup.display(),
+        manifest.schema_version,
+        manifest.chiseld_version,
+        db_uri
+    );
+    Ok(())
+}
@@ -0,0 +1,81 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::proto::chisel_rpc_client::ChiselRpcClient;
+use crate::proto::{secrets_request, SecretInfo, SecretsRequest};
+use anyhow::{anyhow, Context, Result};
+use clap::Subcommand;
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum SecretsCommand {
+    /// Set a secret, overriding the secret from the secrets file.
+    Set {
+        name: String,
+        value: String,
+        /// Parse the value as JSON, instead of passing it as a string.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Unset a secret, so that the secret from the secrets file is used again.
+    Unset { name: String },
+    /// List the names and versions of the secrets (values are not printed).
+    List,
+}
+
+pub(crate) async fn cmd_secrets(server_url: String, cmd: SecretsCommand) -> Result<()> {
+    let request = match cmd {
+        SecretsCommand::Set { name, value, json } => {
+            let value = if json {
+                serde_json::from_str::<serde_json::Value>(&value)
+                    .context("Value is not valid JSON")?
+            } else {
+                serde_json::Value::String(value)
+            };
+            SecretsRequest {
+                action: secrets_request::Action::Set as i32,
+                name,
+                value: value.to_string(),
+            }
+        }
+        SecretsCommand::Unset { name } => SecretsRequest {
+            action: secrets_request::Action::Unset as i32,
+            name,
+            value: String::new(),
+        },
+        SecretsCommand::List => SecretsRequest {
+            action: secrets_request::Action::List as i32,
+            ..Default::default()
+        },
+    };
+    let action = request.action;
+    let name = request.name.clone();
+
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let response = execute!(client.secrets(tonic::Request::new(request)).await);
+
+    if action == secrets_request::Action::List as i32 {
+        for secret in response.secrets.iter() {
+            print_secret(secret)?;
+        }
+    } else if let Some(secret) = response.secrets.iter().find(|s| s.name == name) {
+        print_secret(secret)?;
+    }
+    Ok(())
+}
+
+fn print_secret(secret: &SecretInfo) -> Result<()> {
+    if secret.version == 0 {
+        println!("{} (secrets file)", secret.name);
+        return Ok(());
+    }
+    let updated_at = time::OffsetDateTime::from_unix_timestamp(secret.updated_at)?;
+    let state = match (secret.is_set, secret.in_file) {
+        (true, _) => "set",
+        (false, true) => "unset, using secrets file",
+        (false, false) => "unset",
+    };
+    println!(
+        "{} (version {}, {} at {})",
+        secret.name, secret.version, state, updated_at
+    );
+    Ok(())
+}
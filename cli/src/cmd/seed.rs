@@ -0,0 +1,28 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::proto::chisel_rpc_client::ChiselRpcClient;
+use crate::proto::RunSeedsRequest;
+use anyhow::{anyhow, bail, Result};
+
+/// Runs the seeds of a version that have not been applied yet.
+pub(crate) async fn cmd_seed(server_url: String, version_id: String) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = RunSeedsRequest {
+        version_id: version_id.clone(),
+    };
+    let response = execute!(client.run_seeds(tonic::Request::new(request)).await);
+
+    for name in response.skipped.iter() {
+        println!("Skipped seed {} (already applied)", name);
+    }
+    for name in response.applied.iter() {
+        println!("Applied seed {}", name);
+    }
+    if !response.error.is_empty() {
+        bail!("{}", response.error);
+    }
+    if response.applied.is_empty() && response.skipped.is_empty() {
+        println!("Version {} has no seeds", version_id);
+    }
+    Ok(())
+}
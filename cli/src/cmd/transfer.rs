@@ -0,0 +1,339 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::proto::chisel_rpc_client::ChiselRpcClient;
+use crate::proto::{
+    DataFormat as ProtoDataFormat, ExportDataRequest, ImportDataRequest, ImportDataResponse,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::channel::mpsc;
+use futures::SinkExt;
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of rows that are sent in a single message of the import.
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// Number of bytes of CSV that are read for a single message of the import.
+const IMPORT_CSV_CHUNK_SIZE: usize = 1 << 20;
+
+/// The progress of a CSV export is printed every time this many rows have been written.
+const PROGRESS_ROWS: u64 = 100_000;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum DataFormat {
+    /// A file `<entity>.jsonl` for every entity, with one row per line encoded as a JSON object.
+    Jsonl,
+    /// A file `<entity>.csv` for every entity, with a header that names the columns, in the
+    /// format of Postgres `COPY ... WITH (FORMAT csv, HEADER true)`. Much faster for large
+    /// tables, but only for entities whose fields are all scalars.
+    Csv,
+}
+
+impl DataFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            DataFormat::Jsonl => "jsonl",
+            DataFormat::Csv => "csv",
+        }
+    }
+
+    fn to_proto(self) -> i32 {
+        match self {
+            DataFormat::Jsonl => ProtoDataFormat::Jsonl as i32,
+            DataFormat::Csv => ProtoDataFormat::Csv as i32,
+        }
+    }
+}
+
+pub(crate) async fn cmd_export(
+    server_url: String,
+    version_id: String,
+    format: DataFormat,
+    output: PathBuf,
+    entity_names: Vec<String>,
+) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = tonic::Request::new(ExportDataRequest {
+        version_id,
+        entity_names,
+        format: format.to_proto(),
+    });
+    let mut stream = execute!(client.export_data(request).await);
+
+    fs::create_dir_all(&output)
+        .with_context(|| format!("Could not create directory {}", output.display()))?;
+    let mut files = BTreeMap::new();
+    while let Some(msg) = stream
+        .message()
+        .await
+        .map_err(|x| anyhow!(x.message().to_owned()))?
+    {
+        let (file, count) = match files.entry(msg.entity_name.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = output.join(format!("{}.{}", entry.key(), format.extension()));
+                let file = fs::File::create(&path)
+                    .with_context(|| format!("Could not create file {}", path.display()))?;
+                entry.insert((BufWriter::new(file), 0))
+            }
+        };
+        match format {
+            DataFormat::Jsonl => {
+                for row in msg.rows.iter() {
+                    writeln!(file, "{}", row)?;
+                }
+                *count += msg.rows.len() as u64;
+            }
+            DataFormat::Csv => {
+                file.write_all(&msg.csv)?;
+                let before = *count;
+                *count += msg.csv_rows;
+                if *count / PROGRESS_ROWS > before / PROGRESS_ROWS {
+                    println!("Exporting {}: {} rows so far", msg.entity_name, count);
+                }
+            }
+        }
+    }
+
+    for (entity_name, (mut file, count)) in files {
+        file.flush()?;
+        println!("Exported {} rows of {}", count, entity_name);
+    }
+    Ok(())
+}
+
+pub(crate) async fn cmd_import(
+    server_url: String,
+    version_id: String,
+    format: DataFormat,
+    input: PathBuf,
+    replace: bool,
+) -> Result<()> {
+    if let DataFormat::Csv = format {
+        return import_csv(server_url, version_id, input, replace).await;
+    }
+
+    // the import is a single transaction in the server, so we read all files before we start
+    // streaming, to not commit a partial import if a file cannot be read
+    let mut messages = vec![];
+    for (entity_name, path) in import_files(&input, format)? {
+        let file = fs::File::open(&path)
+            .with_context(|| format!("Could not open file {}", path.display()))?;
+        let mut rows = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.with_context(|| format!("Could not read file {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            rows.push(line);
+        }
+
+        // an entity without rows is still imported, so that `--replace` deletes its rows
+        let mut batches = rows.chunks(IMPORT_BATCH_SIZE).peekable();
+        if batches.peek().is_none() {
+            messages.push(ImportDataRequest {
+                version_id: version_id.clone(),
+                replace,
+                entity_name: entity_name.clone(),
+                rows: vec![],
+                ..Default::default()
+            });
+        }
+        for batch in batches {
+            messages.push(ImportDataRequest {
+                version_id: version_id.clone(),
+                replace,
+                entity_name: entity_name.clone(),
+                rows: batch.to_vec(),
+                ..Default::default()
+            });
+        }
+    }
+    if messages.is_empty() {
+        bail!(
+            "Found no .{} files to import in {}",
+            format.extension(),
+            input.display()
+        );
+    }
+
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let request = tonic::Request::new(futures::stream::iter(messages));
+    let response = execute!(client.import_data(request).await);
+    print_imported(&response);
+    Ok(())
+}
+
+/// Imports CSV files, streaming them to the server while they are read, as they can be too large
+/// to be read into memory first.
+async fn import_csv(
+    server_url: String,
+    version_id: String,
+    input: PathBuf,
+    replace: bool,
+) -> Result<()> {
+    let files = import_files(&input, DataFormat::Csv)?;
+    if files.is_empty() {
+        bail!("Found no .csv files to import in {}", input.display());
+    }
+
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let (mut tx, rx) = mpsc::channel(4);
+    let import = tokio::task::spawn(async move {
+        client
+            .import_data(tonic::Request::new(rx))
+            .await
+            .map_err(|x| anyhow!(x.message().to_owned()))
+    });
+    let reader = tokio::task::spawn_blocking(move || {
+        let result = send_csv_files(&files, &version_id, replace, &mut tx);
+        (result, tx)
+    });
+
+    // the import is a single transaction in the server, which commits when the stream ends, so if
+    // a file cannot be read, the import is cancelled (which resets the stream) before the sender
+    // is dropped
+    let (result, tx) = reader.await?;
+    if let Err(e) = result {
+        import.abort();
+        let _ = import.await;
+        drop(tx);
+        return Err(e);
+    }
+    drop(tx);
+    let response = import.await??.into_inner();
+    print_imported(&response);
+    Ok(())
+}
+
+/// Sends the CSV files to the server in chunks of whole records, printing the progress.
+fn send_csv_files(
+    files: &[(String, PathBuf)],
+    version_id: &str,
+    replace: bool,
+    tx: &mut mpsc::Sender<ImportDataRequest>,
+) -> Result<()> {
+    let mut buf = vec![0; IMPORT_CSV_CHUNK_SIZE];
+    for (entity_name, path) in files {
+        let mut file = fs::File::open(path)
+            .with_context(|| format!("Could not open file {}", path.display()))?;
+        let size = file.metadata()?.len().max(1);
+        let mut chunker = CsvChunker::default();
+        let mut read = 0;
+        let mut percent = 0;
+        loop {
+            let n = file
+                .read(&mut buf)
+                .with_context(|| format!("Could not read file {}", path.display()))?;
+            read += n as u64;
+            let csv = match n {
+                0 => std::mem::take(&mut chunker).finish(),
+                n => chunker.push(&buf[..n]),
+            };
+            if !csv.is_empty() {
+                let msg = ImportDataRequest {
+                    version_id: version_id.to_owned(),
+                    replace,
+                    entity_name: entity_name.clone(),
+                    format: DataFormat::Csv.to_proto(),
+                    csv,
+                    ..Default::default()
+                };
+                if futures::executor::block_on(tx.send(msg)).is_err() {
+                    // the server stopped the import, its error is reported by the caller
+                    return Ok(());
+                }
+            }
+            if read * 10 / size > percent {
+                percent = read * 10 / size;
+                println!("Importing {}: {}%", entity_name, percent * 10);
+            }
+            if n == 0 {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Splits CSV into chunks of whole records (a newline in a quoted field does not end a record).
+#[derive(Default)]
+struct CsvChunker {
+    pending: Vec<u8>,
+    in_quotes: bool,
+}
+
+impl CsvChunker {
+    /// Appends `data` and returns the records that it completes.
+    fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let start = self.pending.len();
+        self.pending.extend_from_slice(data);
+        let mut end = None;
+        for (i, &b) in data.iter().enumerate() {
+            match b {
+                b'"' => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => end = Some(start + i + 1),
+                _ => {}
+            }
+        }
+        match end {
+            Some(end) => {
+                let rest = self.pending.split_off(end);
+                std::mem::replace(&mut self.pending, rest)
+            }
+            None => vec![],
+        }
+    }
+
+    /// Returns the last record, which does not have to be terminated by a newline.
+    fn finish(self) -> Vec<u8> {
+        self.pending
+    }
+}
+
+fn print_imported(response: &ImportDataResponse) {
+    for imported in response.entities.iter() {
+        println!(
+            "Imported {} rows of {}",
+            imported.rows, imported.entity_name
+        );
+    }
+}
+
+/// Finds the files with the rows of entities in `input`, sorted by the name of the entity.
+fn import_files(input: &Path, format: DataFormat) -> Result<Vec<(String, PathBuf)>> {
+    let entries = fs::read_dir(input)
+        .with_context(|| format!("Could not read directory {}", input.display()))?;
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new(format.extension())) {
+            continue;
+        }
+        let entity_name = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .with_context(|| format!("Filename of {} is not in UTF-8", path.display()))?
+            .to_string();
+        files.push((entity_name, path));
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_chunks_end_with_whole_records() {
+        let mut chunker = CsvChunker::default();
+        assert_eq!(chunker.push(b"id,name\na,\"multi"), b"id,name\n");
+        assert_eq!(chunker.push(b"\nline\"\nb,"), b"a,\"multi\nline\"\n");
+        assert_eq!(chunker.push(b"bob"), b"");
+        assert_eq!(chunker.finish(), b"b,bob");
+    }
+}
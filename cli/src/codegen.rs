@@ -2,19 +2,29 @@
 
 use crate::events::FileTopicMap;
 use crate::routes::FileRouteMap;
+use crate::seeds::FileSeedMap;
 use anyhow::{Context, Result};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// The sources from the filesystem that are imported by the root module.
+pub(crate) struct RootSources {
+    pub route_map: FileRouteMap,
+    pub topic_map: FileTopicMap,
+    pub auth_hook: Option<PathBuf>,
+    pub seed_map: FileSeedMap,
+}
 
 pub(crate) fn codegen_root_module(
-    route_map: &FileRouteMap,
-    topic_map: &FileTopicMap,
+    sources: &RootSources,
     import_fn: &dyn Fn(&Path) -> Result<String>,
 ) -> Result<String> {
     let mut lines = Vec::new();
     lines.push(format!("// this code is auto-generated by {}", file!()));
     lines.push("".into());
-    codegen_route_map(&mut lines, route_map, import_fn)?;
-    codegen_topic_map(&mut lines, topic_map, import_fn)?;
+    codegen_route_map(&mut lines, &sources.route_map, import_fn)?;
+    codegen_topic_map(&mut lines, &sources.topic_map, import_fn)?;
+    codegen_auth_hook(&mut lines, sources.auth_hook.as_deref(), import_fn)?;
+    codegen_seed_map(&mut lines, &sources.seed_map, import_fn)?;
     Ok(lines.join("\n"))
 }
 
@@ -78,13 +88,64 @@ fn codegen_topic_map(
         })?;
 
         // TODO: same quotation issues as above
-        lines.push(format!("import eventHandler{} from {:?}", i, import));
+        lines.push(format!("import * as eventModule{} from {:?}", i, import));
         lines.push(format!(
-            "topicMap.topic({:?}, eventHandler{});",
-            topic.topic, i
+            "topicMap.topic({:?}, eventModule{}.default, eventModule{}.options, eventModule{}.filter);",
+            topic.topic, i, i, i
         ));
     }
     lines.push("".into());
 
     Ok(())
 }
+
+fn codegen_auth_hook(
+    lines: &mut Vec<String>,
+    auth_hook: Option<&Path>,
+    import_fn: &dyn Fn(&Path) -> Result<String>,
+) -> Result<()> {
+    match auth_hook {
+        Some(auth_hook) => {
+            let import = import_fn(auth_hook).with_context(|| {
+                format!(
+                    "Cannot convert path of auth hook {} to a JavaScript import",
+                    auth_hook.display(),
+                )
+            })?;
+            // TODO: same quotation issues as above
+            lines.push(format!("import authHook from {:?};", import));
+            lines.push("export { authHook };".into());
+        }
+        None => lines.push("export const authHook = undefined;".into()),
+    }
+    lines.push("".into());
+
+    Ok(())
+}
+
+fn codegen_seed_map(
+    lines: &mut Vec<String>,
+    seed_map: &FileSeedMap,
+    import_fn: &dyn Fn(&Path) -> Result<String>,
+) -> Result<()> {
+    lines.push("import { SeedMap } from 'chisel://api/seed.ts';".into());
+    lines.push("".into());
+
+    lines.push("export const seedMap = new SeedMap();".into());
+
+    for (i, seed) in seed_map.seeds.iter().enumerate() {
+        let import = import_fn(&seed.file_path).with_context(|| {
+            format!(
+                "Cannot convert path of seed {} to a JavaScript import",
+                seed.file_path.display(),
+            )
+        })?;
+
+        // TODO: same quotation issues as above
+        lines.push(format!("import seed{} from {:?};", i, import));
+        lines.push(format!("seedMap.seed({:?}, seed{});", seed.name, i));
+    }
+    lines.push("".into());
+
+    Ok(())
+}
@@ -15,6 +15,9 @@ pub(crate) struct FileTopicMap {
 }
 
 /// A file with event handler for a Kafka topic.
+///
+/// The handler of `events/<topic>.ts` handles the topic `<topic>`. A topic can also have multiple
+/// handlers (typically with different filters) in `events/<topic>/<name>.ts`.
 #[derive(Debug)]
 pub(crate) struct FileTopic {
     /// Absolute path to the file with the event handler.
@@ -35,32 +38,53 @@ pub(crate) fn build_file_topic_map(
             .with_context(|| format!("Could not canonicalize path {}", event_dir.display()))?;
 
         for entry in fs::read_dir(event_dir)? {
-            let entry = entry?;
-            let entry_path = entry.path();
-
-            if entry_path.extension() == Some(OsStr::new("ts")) {
-                guard! {let Some(stem) = entry_path.file_stem() else {
-                    continue
-                }};
-
-                let topic = stem
-                    .to_str()
-                    .with_context(|| {
-                        format!("Filename of {} is not in UTF-8", entry_path.display())
-                    })?
-                    .to_string();
-                topic_map.topics.push(FileTopic {
-                    file_path: entry_path,
-                    topic,
-                });
-            } else if entry_path.extension() == Some(OsStr::new("js")) {
-                bail!(
-                    "Found file {}, but only TypeScript files (.ts) are supported as event handlers",
-                    entry_path.display(),
-                );
+            let entry_path = entry?.path();
+            if entry_path.is_dir() {
+                let topic = utf8_name(entry_path.file_name(), &entry_path)?;
+                for entry in fs::read_dir(&entry_path)? {
+                    add_topic_file(&mut topic_map, entry?.path(), Some(&topic))?;
+                }
+            } else {
+                add_topic_file(&mut topic_map, entry_path, None)?;
             }
         }
     }
 
+    // the order of the handlers of a topic must not depend on the order of directory entries
+    topic_map
+        .topics
+        .sort_by(|a, b| a.file_path.cmp(&b.file_path));
     Ok(topic_map)
 }
+
+fn add_topic_file(
+    topic_map: &mut FileTopicMap,
+    file_path: PathBuf,
+    topic: Option<&str>,
+) -> Result<()> {
+    if file_path.extension() == Some(OsStr::new("ts")) {
+        let topic = match topic {
+            Some(topic) => topic.to_string(),
+            None => {
+                guard! {let Some(stem) = file_path.file_stem() else {
+                    return Ok(())
+                }};
+                utf8_name(Some(stem), &file_path)?
+            }
+        };
+        topic_map.topics.push(FileTopic { file_path, topic });
+    } else if file_path.extension() == Some(OsStr::new("js")) {
+        bail!(
+            "Found file {}, but only TypeScript files (.ts) are supported as event handlers",
+            file_path.display(),
+        );
+    }
+    Ok(())
+}
+
+fn utf8_name(name: Option<&OsStr>, path: &Path) -> Result<String> {
+    Ok(name
+        .and_then(OsStr::to_str)
+        .with_context(|| format!("Filename of {} is not in UTF-8", path.display()))?
+        .to_string())
+}
@@ -1,12 +1,27 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use crate::cmd::apply::apply;
+use crate::cmd::apply::{
+    apply, apply_policies, resume_apply, AllowedChanges, ApplyLock, ApplyOptions, ApplyTarget,
+    Canary,
+};
+use crate::cmd::contract::{cmd_contract, ContractCommand};
+use crate::cmd::data::{cmd_data, DataCommand};
 use crate::cmd::dev::cmd_dev;
+use crate::cmd::diff::cmd_diff;
 use crate::cmd::generate;
+use crate::cmd::lint::cmd_lint;
+use crate::cmd::meta::{cmd_meta, MetaCommand};
+use crate::cmd::modules::{cmd_modules, ModulesCommand};
+use crate::cmd::restore::cmd_restore;
+use crate::cmd::secrets::{cmd_secrets, SecretsCommand};
+use crate::cmd::seed::cmd_seed;
+use crate::cmd::transfer::{cmd_export, cmd_import, DataFormat};
 use crate::project::{create_project, CreateProjectOptions};
 use crate::proto::chisel_rpc_client::ChiselRpcClient;
 use crate::proto::{
-    type_msg::TypeEnum, DeleteRequest, DescribeRequest, PopulateRequest, StatusRequest,
+    type_msg::TypeEnum, DeleteRequest, DescribeRequest, IndexSuggestionsRequest, JsonSchemaRequest,
+    ListAuditLogRequest, MirrorRequest, MirrorStatusRequest, OpenApiRequest, PopulateRequest,
+    StatusRequest,
 };
 use crate::server::{start_server, wait};
 use anyhow::{anyhow, Result};
@@ -21,8 +36,10 @@ use tokio::process::Child;
 mod cmd;
 mod codegen;
 mod events;
+mod procs;
 mod project;
 mod routes;
+mod seeds;
 mod server;
 mod ts;
 
@@ -36,6 +53,18 @@ fn parse_version(version: &str) -> anyhow::Result<String> {
     Ok(version.to_string())
 }
 
+fn parse_percentage(percentage: &str) -> anyhow::Result<f64> {
+    let number = percentage.strip_suffix('%').unwrap_or(percentage);
+    let percentage: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("expected a percentage such as `10%`, got {percentage:?}"))?;
+    anyhow::ensure!(
+        (0.0..=100.0).contains(&percentage),
+        "percentage must be between 0% and 100%"
+    );
+    Ok(percentage)
+}
+
 fn parse_generate_mode(mode: &str) -> anyhow::Result<generate::Mode> {
     match mode {
         "deno" => Ok(generate::Mode::Deno),
@@ -77,7 +106,12 @@ enum Command {
         auto_index: bool,
     },
     /// Describe the endpoints, types, and policies.
-    Describe,
+    Describe {
+        /// Output format: `text`, `openapi` for an OpenAPI 3.0 document in JSON, or `json-schema`
+        /// for the JSON Schema documents of the entities (for creating, updating and reading).
+        #[arg(long, default_value = "text", value_parser = ["text", "openapi", "json-schema"])]
+        format: String,
+    },
     /// Start a ChiselStrike server for local development.
     Dev {
         /// calls tsc --noEmit to check types. Useful if your IDE isn't doing it.
@@ -86,6 +120,14 @@ enum Command {
         /// Activate inspector and let a debugger attach at any time.
         #[arg(long)]
         inspect: bool,
+        /// Fetch the remote imports again, instead of taking them from the cache in
+        /// `~/.cache/chiselstrike`.
+        #[arg(long)]
+        reload: bool,
+        /// Never fetch the remote imports, only take them from the cache in
+        /// `~/.cache/chiselstrike`.
+        #[arg(long, conflicts_with = "reload")]
+        offline: bool,
     },
     /// Generate a ChiselStrike client API for this project.
     Generate {
@@ -121,13 +163,135 @@ enum Command {
     Wait,
     /// Apply configuration to the ChiselStrike server.
     Apply {
+        /// Allow dropping all models and fields that still have data. Prefer approving them one
+        /// by one with --allow-drop.
         #[arg(long)]
         allow_type_deletion: bool,
+        /// Allow dropping a model (`Name`) or a field (`Name.field`) that still has data. Can be
+        /// repeated. The drop is recorded in the audit log.
+        #[arg(long = "allow-drop", value_name = "MODEL[.FIELD]")]
+        allow_drop: Vec<String>,
+        /// Allow changing or removing a model that is marked with `@locked`. Can be repeated.
+        /// The change is recorded in the audit log.
+        #[arg(long = "unlock", value_name = "MODEL")]
+        unlock: Vec<String>,
+        /// Archive the tables of removed models that still have data, instead of dropping them.
+        /// Archived data can be read with `chisel data query --archived`.
+        #[arg(long)]
+        archive: bool,
+        /// How long (in seconds) to wait for another apply to the same server to finish.
+        #[arg(long, default_value = "60")]
+        lock_timeout: u32,
+        /// Remove the lock of another apply before applying. Use this only if the other apply is
+        /// stuck, for example because the server crashed in the middle of it.
+        #[arg(long)]
+        force_unlock: bool,
         #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
         version: String,
         /// calls tsc --noEmit to check types. Useful if your IDE isn't doing it.
         #[arg(long)]
         type_check: bool,
+        /// Fetch the remote imports again, instead of taking them from the cache in
+        /// `~/.cache/chiselstrike`.
+        #[arg(long)]
+        reload: bool,
+        /// Never fetch the remote imports, only take them from the cache in
+        /// `~/.cache/chiselstrike`.
+        #[arg(long, conflicts_with = "reload")]
+        offline: bool,
+        /// Compile the routes with this many compilers in parallel, which speeds up the apply of
+        /// projects with many routes.
+        #[arg(long, value_name = "N", default_value = "1")]
+        compile_jobs: usize,
+        /// Resume an interrupted apply with the given id, instead of applying the current project.
+        #[arg(long)]
+        resume: Option<String>,
+        /// Route this percentage (such as `10%`) of the requests to another version of the same
+        /// app to the applied version. Logged-in users are consistently routed to the same
+        /// version. Use `0%` to stop routing requests to the applied version.
+        #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+        canary: Option<f64>,
+        /// Version whose requests are routed to the applied version with --canary. Defaults to the
+        /// only other running version of the app.
+        #[arg(long, value_name = "VERSION", requires = "canary")]
+        canary_of: Option<String>,
+        /// Apply only the policies to the running version, without compiling and applying the
+        /// models and routes.
+        #[arg(long, conflicts_with_all = ["resume", "type_check", "canary"])]
+        policies_only: bool,
+        /// Print the plan of the apply (the changes of the models, indexes and data, and the
+        /// warnings about data that would be dropped) without changing anything.
+        #[arg(long, conflicts_with_all = ["resume", "policies_only"])]
+        dry_run: bool,
+        /// Format of the plan printed by --dry-run: `text`, or `json` with the steps marked as
+        /// destructive or not.
+        #[arg(long, default_value = "text", value_parser = ["text", "json"], requires = "dry_run")]
+        format: String,
+    },
+    /// Check the models, policies, routes, event handlers and seeds of the current project, and
+    /// the imports of their code, without a running server.
+    Lint,
+    /// Show how the current project differs from a version in the server: the entities, fields,
+    /// indexes, routes, modules and policies that `chisel apply` would change.
+    Diff {
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+    },
+    /// Run the seed scripts (the files in `seeds/`) that have not been applied to a version yet.
+    ///
+    /// Seeds run in the order of their names, each in its own transaction, and every seed runs
+    /// only once per version. If a seed fails, the seeds after it are not run, and the failed seed
+    /// runs again the next time.
+    Seed {
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+    },
+    /// Export the rows of the entities of a version to files, for example to back up the data or
+    /// to move it to another server with `chisel import`.
+    Export {
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: DataFormat,
+        /// Directory where the files are written (one file per entity).
+        #[arg(long, default_value = "dump")]
+        output: PathBuf,
+        /// Export only the given entity (can be repeated; all entities are exported by default).
+        #[arg(long)]
+        entity: Vec<String>,
+    },
+    /// Import the rows of entities from files written by `chisel export`.
+    ///
+    /// The rows are validated against the entities of the version and they are imported in a
+    /// single transaction, so if any row is invalid, nothing is imported. A row with the id of an
+    /// existing row overwrites that row.
+    ///
+    /// On Postgres, CSV files are loaded with `COPY`, which only validates the header and the
+    /// column types of the values, and fails on rows whose id already exists (use `--replace`).
+    Import {
+        /// Directory with the files (one file per entity, named after the entity).
+        input: PathBuf,
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: DataFormat,
+        /// Delete the existing rows of the imported entities first.
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Restore a backup of the database that chiseld created in its `--backup-dir`.
+    ///
+    /// chiseld must not be running. The schema version of the backup is checked first, and the
+    /// restored database is migrated to the latest schema when chiseld starts.
+    Restore {
+        /// Directory of the backup (the one with `backup.json`).
+        backup: PathBuf,
+        /// Database to restore the backup into, the same as `chiseld --db-uri`.
+        #[arg(long, default_value = "sqlite://.chiseld.db?mode=rwc")]
+        db_uri: String,
+        /// Overwrite the database if it already exists (SQLite) or is not empty (Postgres).
+        #[arg(long)]
+        force: bool,
     },
     /// Delete configuration from the ChiselStrike server.
     Delete {
@@ -140,6 +304,69 @@ enum Command {
         #[arg(long)]
         from: String,
     },
+    /// Mirror a percentage of the requests to a version to another version.
+    ///
+    /// Mirrored requests run in a sandbox in the target version: their responses are discarded,
+    /// their database writes are rolled back, and blob writes and socket messages are skipped.
+    /// Outbound `fetch()` requests are still made. Use a percentage of 0 to stop mirroring.
+    Mirror {
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+        #[arg(long, value_parser = parse_version)]
+        to: String,
+        #[arg(long, default_value = "100")]
+        percentage: f64,
+    },
+    /// Show statistics of the active mirrors.
+    MirrorStatus,
+    /// Show the audit log, which records the changes of models marked with `@locked` and the
+    /// models and fields that were dropped with their data.
+    AuditLog,
+    /// Suggest indexes from the fields that the queries of a version filtered and sorted by since
+    /// the server started, ordered by their estimated benefit.
+    IndexSuggestions {
+        #[arg(long, default_value = DEFAULT_API_VERSION, value_parser = parse_version)]
+        version: String,
+    },
+    /// Inspect the data stored in the ChiselStrike server.
+    Data {
+        #[command(subcommand)]
+        cmd: DataCommand,
+    },
+    /// Fetch the modules that a version of the ChiselStrike server executes, to resolve the code
+    /// that a stack trace refers to.
+    Modules {
+        #[command(subcommand)]
+        cmd: ModulesCommand,
+    },
+    /// Maintain the meta database, where the ChiselStrike server keeps the versions, the modules
+    /// and the other metadata.
+    Meta {
+        #[command(subcommand)]
+        cmd: MetaCommand,
+    },
+    /// Verify that a deployment still answers the route examples of a version in the same shape.
+    Contract {
+        #[command(subcommand)]
+        cmd: ContractCommand,
+    },
+    /// Manage the secrets that are stored in the ChiselStrike server.
+    ///
+    /// These secrets take precedence over the secrets file and they can be changed without
+    /// restarting the server.
+    Secrets {
+        #[command(subcommand)]
+        cmd: SecretsCommand,
+    },
+}
+
+/// Returns the TypeScript union of the values of a string enum, such as `"a" | "b"`.
+pub(crate) fn enum_union(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| format!("{:?}", value))
+        .collect::<Vec<_>>()
+        .join(" | ")
 }
 
 async fn delete(server_url: String, version_id: String) -> Result<()> {
@@ -173,6 +400,97 @@ async fn populate(
     Ok(())
 }
 
+async fn mirror(
+    server_url: String,
+    from_version_id: String,
+    to_version_id: String,
+    percentage: f64,
+) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+
+    let msg = execute!(
+        client
+            .mirror(tonic::Request::new(MirrorRequest {
+                from_version_id,
+                to_version_id,
+                percentage,
+            }))
+            .await
+    );
+    println!("{}", msg.message);
+    Ok(())
+}
+
+async fn mirror_status(server_url: String) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+
+    let msg = execute!(
+        client
+            .get_mirror_status(tonic::Request::new(MirrorStatusRequest {}))
+            .await
+    );
+    if msg.mirrors.is_empty() {
+        println!("No active mirrors");
+    }
+    for m in msg.mirrors {
+        println!(
+            "{} -> {} ({}%): {} mirrored, {} status mismatches, errors {}/{}, avg latency {:.2}ms/{:.2}ms",
+            m.from_version_id,
+            m.to_version_id,
+            m.percentage,
+            m.mirrored,
+            m.status_mismatches,
+            m.from_errors,
+            m.to_errors,
+            m.from_latency_avg_ms,
+            m.to_latency_avg_ms,
+        );
+    }
+    Ok(())
+}
+
+async fn audit_log(server_url: String) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+
+    let msg = execute!(
+        client
+            .list_audit_log(tonic::Request::new(ListAuditLogRequest {}))
+            .await
+    );
+    for entry in msg.entries {
+        let created_at = time::OffsetDateTime::from_unix_timestamp(entry.created_at)?;
+        println!(
+            "{} {} {}: {} {}",
+            created_at, entry.version_id, entry.actor, entry.action, entry.detail
+        );
+    }
+    Ok(())
+}
+
+async fn index_suggestions(server_url: String, version_id: String) -> Result<()> {
+    let mut client = ChiselRpcClient::connect(server_url).await?;
+
+    let msg = execute!(
+        client
+            .index_suggestions(tonic::Request::new(IndexSuggestionsRequest { version_id }))
+            .await
+    );
+    if msg.suggestions.is_empty() {
+        println!("No index suggestions");
+    }
+    for s in msg.suggestions {
+        println!(
+            "{}({}): serves {} queries on {} rows, saves ~{} scanned rows",
+            s.entity_name,
+            s.fields.join(", "),
+            s.query_count,
+            s.row_count,
+            s.estimated_rows_saved,
+        );
+    }
+    Ok(())
+}
+
 async fn spawn_server<T, F, Fut, Fut2>(chiseld_args: Vec<String>, fut: Fut, cb: F) -> Result<()>
 where
     Fut: Future<Output = T>,
@@ -223,7 +541,19 @@ async fn main() -> Result<()> {
             };
             create_project(&cwd, opts)?;
         }
-        Command::Describe => {
+        Command::Describe { format } if format == "openapi" => {
+            let mut client = ChiselRpcClient::connect(server_url).await?;
+            let request = tonic::Request::new(OpenApiRequest {});
+            let response = execute!(client.open_api(request).await);
+            println!("{}", response.document);
+        }
+        Command::Describe { format } if format == "json-schema" => {
+            let mut client = ChiselRpcClient::connect(server_url).await?;
+            let request = tonic::Request::new(JsonSchemaRequest {});
+            let response = execute!(client.json_schema(request).await);
+            println!("{}", response.document);
+        }
+        Command::Describe { .. } => {
             let mut client = ChiselRpcClient::connect(server_url).await?;
             let request = tonic::Request::new(DescribeRequest {});
             let response = execute!(client.describe(request).await);
@@ -231,6 +561,24 @@ async fn main() -> Result<()> {
             for version_def in response.version_defs {
                 println!("Version: {} {{", version_def.version_id);
                 for def in &version_def.type_defs {
+                    if def.locked {
+                        println!("  @locked");
+                    }
+                    if def.field_defs.iter().any(|f| !f.timestamp.is_empty()) {
+                        println!("  @timestamps");
+                    }
+                    if !def.owned_by.is_empty() {
+                        println!("  @ownedBy({:?})", def.owned_by);
+                    }
+                    if let Some(aggregate) = &def.aggregate {
+                        match aggregate.refresh_interval_s {
+                            Some(interval_s) => println!(
+                                "  @aggregate({}, {{ refreshEvery: {} }})",
+                                aggregate.source_entity, interval_s
+                            ),
+                            None => println!("  @aggregate({})", aggregate.source_entity),
+                        }
+                    }
                     println!("  class {} {{", def.name);
                     for field in &def.field_defs {
                         let labels = if field.labels.is_empty() {
@@ -247,21 +595,69 @@ async fn main() -> Result<()> {
                             labels.pop();
                             format!("@labels({}) ", labels)
                         };
+                        let count = field
+                            .count
+                            .as_ref()
+                            .map(|c| format!("@count({}, \"{}\") ", c.entity_name, c.field_name))
+                            .unwrap_or_default();
+                        let aggregate = field
+                            .aggregate
+                            .as_ref()
+                            .map(|a| {
+                                if a.source_field.is_empty() {
+                                    format!("@{}Of() ", a.function)
+                                } else {
+                                    format!("@{}Of(\"{}\") ", a.function, a.source_field)
+                                }
+                            })
+                            .unwrap_or_default();
                         let field_type = field.field_type()?;
+                        // enum fields are strings restricted to the values of the enum
+                        let type_str = match &field.enum_type {
+                            Some(enum_type) => enum_union(&enum_type.values),
+                            None => field_type.to_string(),
+                        };
+                        let max_bytes = field
+                            .max_bytes
+                            .map(|max_bytes| format!("@maxBytes({}) ", max_bytes))
+                            .unwrap_or_default();
+                        let on_delete = if field.on_delete.is_empty() {
+                            String::new()
+                        } else {
+                            format!("@onDelete(\"{}\") ", field.on_delete)
+                        };
+                        let collation = if field.collation.is_empty() {
+                            String::new()
+                        } else {
+                            format!("@collate(\"{}\") ", field.collation)
+                        };
                         println!(
-                            "    {}{}{}{}: {}{};",
+                            "    {}{}{}{}{}{}{}{}{}{}{}: {}{};",
                             if field.is_unique { "@unique " } else { "" },
+                            if field.is_version { "@version " } else { "" },
+                            count,
+                            aggregate,
+                            max_bytes,
+                            on_delete,
+                            collation,
                             labels,
+                            if field.timestamp.is_empty() {
+                                ""
+                            } else {
+                                "readonly "
+                            },
                             field.name,
                             if field.is_optional { "?" } else { "" },
-                            field_type,
+                            type_str,
                             field
                                 .default_value
                                 .as_ref()
-                                .map(|d| if matches!(field_type, TypeEnum::String(_)) {
-                                    format!(" = \"{}\"", d)
-                                } else {
-                                    format!(" = {}", d)
+                                .map(|d| match field_type {
+                                    TypeEnum::String(_) | TypeEnum::Decimal(_) => {
+                                        format!(" = \"{}\"", d)
+                                    }
+                                    TypeEnum::Bigint(_) => format!(" = {}n", d),
+                                    _ => format!(" = {}", d),
                                 })
                                 .unwrap_or_else(|| "".into()),
                         );
@@ -277,15 +673,14 @@ async fn main() -> Result<()> {
         Command::Dev {
             type_check,
             inspect,
+            reload,
+            offline,
         } => {
-            let fut = cmd_dev(server_url.clone(), type_check);
+            let fut = cmd_dev(server_url.clone(), type_check, reload, offline);
             let cb = |mut server: Child, res| async move {
-                let sig_task = res?;
                 server.kill().await?;
                 server.wait().await?;
-                sig_task.await??;
-
-                Ok(())
+                res
             };
             chiseld_args.push("--debug".to_string());
             if inspect {
@@ -358,16 +753,99 @@ async fn main() -> Result<()> {
         }
         Command::Apply {
             allow_type_deletion,
+            allow_drop,
+            unlock,
+            archive,
+            lock_timeout,
+            force_unlock,
             version,
             type_check,
+            reload,
+            offline,
+            compile_jobs,
+            resume,
+            policies_only,
+            canary,
+            canary_of,
+            dry_run,
+            format,
+        } => match resume {
+            Some(apply_id) => resume_apply(server_url, apply_id).await?,
+            None if policies_only => {
+                apply_policies(
+                    server_url,
+                    version,
+                    ApplyLock {
+                        timeout_s: lock_timeout,
+                        force_unlock,
+                    },
+                )
+                .await?
+            }
+            None => {
+                apply(
+                    server_url,
+                    ApplyTarget {
+                        version_id: version,
+                        canary: canary.map(|percentage| Canary {
+                            percentage,
+                            of: canary_of,
+                        }),
+                    },
+                    allow_type_deletion.into(),
+                    AllowedChanges {
+                        drops: allow_drop,
+                        unlocked: unlock,
+                    },
+                    ApplyOptions {
+                        archive_removed: archive,
+                        dry_run,
+                        json_plan: format == "json",
+                        incremental: false,
+                        reload,
+                        offline,
+                        compile_jobs,
+                    },
+                    ApplyLock {
+                        timeout_s: lock_timeout,
+                        force_unlock,
+                    },
+                    type_check.into(),
+                )
+                .await?
+            }
+        },
+        Command::Lint => {
+            cmd_lint()?;
+        }
+        Command::Diff { version } => {
+            cmd_diff(server_url, version).await?;
+        }
+        Command::Seed { version } => {
+            cmd_seed(server_url, version).await?;
+        }
+        Command::Export {
+            version,
+            format,
+            output,
+            entity,
         } => {
-            apply(
-                server_url,
-                version,
-                allow_type_deletion.into(),
-                type_check.into(),
-            )
-            .await?;
+            cmd_export(server_url, version, format, output, entity).await?;
+        }
+        Command::Import {
+            input,
+            version,
+            format,
+            replace,
+        } => {
+            cmd_import(server_url, version, format, input, replace).await?;
+        }
+        Command::Restore {
+            backup,
+            db_uri,
+            force,
+        } => {
+            cmd_restore(backup, db_uri, force).await?;
         }
         Command::Delete { version } => {
             delete(server_url, version).await?;
@@ -375,6 +853,37 @@ async fn main() -> Result<()> {
         Command::Populate { version, from } => {
             populate(server_url, version, from).await?;
         }
+        Command::Mirror {
+            version,
+            to,
+            percentage,
+        } => {
+            mirror(server_url, version, to, percentage).await?;
+        }
+        Command::MirrorStatus => {
+            mirror_status(server_url).await?;
+        }
+        Command::AuditLog => {
+            audit_log(server_url).await?;
+        }
+        Command::IndexSuggestions { version } => {
+            index_suggestions(server_url, version).await?;
+        }
+        Command::Data { cmd } => {
+            cmd_data(server_url, cmd).await?;
+        }
+        Command::Modules { cmd } => {
+            cmd_modules(api_listen_addr, cmd).await?;
+        }
+        Command::Meta { cmd } => {
+            cmd_meta(server_url, cmd).await?;
+        }
+        Command::Contract { cmd } => {
+            cmd_contract(api_listen_addr, cmd).await?;
+        }
+        Command::Secrets { cmd } => {
+            cmd_secrets(server_url, cmd).await?;
+        }
     }
 
     Ok(())
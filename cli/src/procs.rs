@@ -0,0 +1,143 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Companion processes that `chisel dev` runs and supervises next to `chiseld`.
+
+use crate::project::DevProc;
+use anyhow::{Context, Result};
+use futures::future::{join_all, pending, select_all};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{setpgid, Pid};
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+
+/// How long processes get to exit after SIGTERM before they are killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct RunningProc {
+    name: String,
+    child: Child,
+    pgid: Pid,
+}
+
+impl RunningProc {
+    fn signal(&self, signal: Signal) {
+        // The process group may already be gone, which is fine.
+        let _ = killpg(self.pgid, signal);
+    }
+}
+
+/// The set of running companion processes.
+///
+/// Dropping it kills every process that has not been shut down yet, so that nothing outlives
+/// `chisel dev` even if it bails out early.
+pub(crate) struct DevProcs {
+    procs: Vec<RunningProc>,
+}
+
+impl DevProcs {
+    /// Starts every process in `config`, forwarding its output prefixed with its name.
+    pub(crate) fn spawn(base_dir: &Path, config: &BTreeMap<String, DevProc>) -> Result<Self> {
+        let width = config.keys().map(|name| name.len()).max().unwrap_or(0);
+        let mut procs = DevProcs { procs: vec![] };
+        for (name, proc) in config {
+            let cwd = match &proc.cwd {
+                Some(cwd) => base_dir.join(cwd),
+                None => base_dir.to_owned(),
+            };
+            let mut cmd = std::process::Command::new("sh");
+            cmd.arg("-c")
+                .arg(&proc.command)
+                .current_dir(&cwd)
+                .envs(&proc.env)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            // Each process gets its own process group, so that signals reach whatever it forks
+            // (`npm run dev` does not run the server itself) and so that a Ctrl-C in the terminal
+            // only reaches `chisel`, which then stops everything in order.
+            unsafe {
+                cmd.pre_exec(|| {
+                    setpgid(Pid::from_raw(0), Pid::from_raw(0))
+                        .map_err(|e| io::Error::from_raw_os_error(e as i32))
+                });
+            }
+            let mut child = Command::from(cmd)
+                .spawn()
+                .with_context(|| format!("Could not start dev process `{}`", name))?;
+            let pid = child
+                .id()
+                .with_context(|| format!("Dev process `{}` exited immediately", name))?;
+
+            let prefix = format!("[{:width$}]", name, width = width);
+            tokio::spawn(forward_lines(
+                prefix.clone(),
+                child.stdout.take().unwrap(),
+                false,
+            ));
+            tokio::spawn(forward_lines(prefix, child.stderr.take().unwrap(), true));
+            println!("Started dev process `{}`: {}", name, proc.command);
+
+            procs.procs.push(RunningProc {
+                name: name.clone(),
+                child,
+                pgid: Pid::from_raw(pid as i32),
+            });
+        }
+        Ok(procs)
+    }
+
+    /// Resolves with the name and status of the first process that exits. Never resolves if
+    /// there are no processes.
+    pub(crate) async fn wait_any(&mut self) -> (String, io::Result<ExitStatus>) {
+        if self.procs.is_empty() {
+            return pending().await;
+        }
+        let waits = self.procs.iter_mut().map(|p| Box::pin(p.child.wait()));
+        let (status, idx, _) = select_all(waits).await;
+        (self.procs[idx].name.clone(), status)
+    }
+
+    /// Sends SIGTERM to every process and kills the ones that are still around after
+    /// `SHUTDOWN_TIMEOUT`.
+    pub(crate) async fn shutdown(mut self) {
+        for proc in &self.procs {
+            proc.signal(Signal::SIGTERM);
+        }
+        let waits = join_all(self.procs.iter_mut().map(|p| p.child.wait()));
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, waits).await.is_err() {
+            for proc in &mut self.procs {
+                if let Ok(None) = proc.child.try_wait() {
+                    eprintln!("Dev process `{}` did not stop, killing it", proc.name);
+                }
+                proc.signal(Signal::SIGKILL);
+            }
+            join_all(self.procs.iter_mut().map(|p| p.child.wait())).await;
+        }
+        self.procs.clear();
+    }
+}
+
+impl Drop for DevProcs {
+    fn drop(&mut self) {
+        for proc in &self.procs {
+            proc.signal(Signal::SIGKILL);
+        }
+    }
+}
+
+async fn forward_lines(prefix: String, stream: impl AsyncRead + Unpin, stderr: bool) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if stderr {
+            eprintln!("{} {}", prefix, line);
+        } else {
+            println!("{} {}", prefix, line);
+        }
+    }
+}
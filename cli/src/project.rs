@@ -2,6 +2,7 @@
 
 use crate::events::{build_file_topic_map, FileTopicMap};
 use crate::routes::{build_file_route_map, FileRouteMap};
+use crate::seeds::{build_file_seed_map, FileSeedMap};
 use anyhow::{Context, Result};
 use handlebars::Handlebars;
 use serde_derive::Deserialize;
@@ -15,8 +16,10 @@ const MANIFEST_FILE: &str = "Chisel.toml";
 const TYPES_DIR: &str = "./models";
 const ROUTES_DIR: &str = "./routes";
 const EVENTS_DIR: &str = "./events";
+const SEEDS_DIR: &str = "./seeds";
 const LIB_DIR: &str = "./lib";
 const POLICIES_DIR: &str = "./policies";
+const STATIC_DIR: &str = "./static";
 const VSCODE_DIR: &str = "./.vscode/";
 
 #[derive(Deserialize, PartialEq)]
@@ -61,6 +64,42 @@ impl Default for AutoIndex {
     }
 }
 
+/// A companion process that `chisel dev` runs next to `chiseld`.
+#[derive(Deserialize, Debug, PartialEq)]
+pub(crate) struct DevProc {
+    /// Shell command line that starts the process.
+    pub(crate) command: String,
+    /// Working directory, relative to the project root.
+    pub(crate) cwd: Option<PathBuf>,
+    /// Extra environment variables for the process.
+    #[serde(default)]
+    pub(crate) env: BTreeMap<String, String>,
+}
+
+/// Settings that only apply to `chisel dev`.
+#[derive(Deserialize, Default)]
+pub(crate) struct DevConfig {
+    /// Companion processes (such as a frontend dev server), keyed by name.
+    #[serde(default)]
+    pub(crate) proc: BTreeMap<String, DevProc>,
+}
+
+/// Worker pool of the version (the `[workers]` section). The settings that are not given use the
+/// defaults of chiseld (`--worker-threads` and friends).
+#[derive(Deserialize, Default)]
+pub(crate) struct WorkersConfig {
+    /// Number of workers that are always running.
+    pub(crate) min: Option<u32>,
+    /// Maximum number of workers, which are added when jobs queue up.
+    pub(crate) max: Option<u32>,
+    /// Add a worker when this many jobs wait for an idle worker.
+    pub(crate) scale_up_queue_depth: Option<u32>,
+    /// Add a worker when a job waits for an idle worker for this long (in milliseconds).
+    pub(crate) scale_up_latency_ms: Option<u32>,
+    /// Remove a worker after it has been idle for this long (in seconds).
+    pub(crate) scale_down_idle_s: Option<f64>,
+}
+
 /// Manifest defines the files that describe types, routes, events, and policies.
 ///
 /// The manifest is a high-level declaration of application behavior.
@@ -79,6 +118,11 @@ pub(crate) struct Manifest {
     pub(crate) events: Option<Vec<PathBuf>>,
     /// Vector of directories to scan for policy definitions.
     pub(crate) policies: Vec<PathBuf>,
+    /// Vector of directories to scan for seed scripts (`seeds/` by default).
+    pub(crate) seeds: Option<Vec<PathBuf>>,
+    /// Directory of the static files that are served with the version (`static/` by default).
+    #[serde(rename = "static")]
+    pub(crate) static_dir: Option<PathBuf>,
     /// Whether to use deno-style or node-style modules
     #[serde(default)]
     pub(crate) modules: Module,
@@ -88,6 +132,20 @@ pub(crate) struct Manifest {
     /// Enable or disable auto-indexing.
     #[serde(default)]
     pub(crate) auto_index: AutoIndex,
+    /// Hosts (with optional ports) that the code may connect to, such as `api.example.com` or
+    /// `localhost:8080`. Connections to other hosts are denied, unless chiseld runs with
+    /// `--allow-all-net`.
+    #[serde(default)]
+    pub(crate) allowed_hosts: Vec<String>,
+    /// File whose default export resolves the user that performs a request (see `AuthHook` in
+    /// the API).
+    pub(crate) auth_hook: Option<PathBuf>,
+    /// Worker pool of the version.
+    #[serde(default)]
+    pub(crate) workers: WorkersConfig,
+    /// Settings for `chisel dev`.
+    #[serde(default)]
+    pub(crate) dev: DevConfig,
 }
 
 impl Manifest {
@@ -114,10 +172,72 @@ impl Manifest {
         }
     }
 
+    pub fn seed_map(&self, base_dir: &Path) -> anyhow::Result<FileSeedMap> {
+        build_file_seed_map(base_dir, &self.seed_dirs())
+            .context("Could not read seeds from filesystem")
+    }
+
+    pub fn seed_dirs(&self) -> Vec<PathBuf> {
+        match self.seeds.as_ref() {
+            Some(seeds) => seeds.clone(),
+            None => vec![PathBuf::from(SEEDS_DIR)],
+        }
+    }
+
+    pub fn static_dir(&self) -> PathBuf {
+        self.static_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(STATIC_DIR))
+    }
+
+    /// Returns the files of the static directory, as pairs of the path relative to the directory
+    /// (with `/` separators) and the path of the file.
+    pub fn static_files(&self, base_dir: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let dir = self.static_dir();
+        let root = match dir.canonicalize() {
+            Ok(root) => root,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        Self::dirs_to_paths(base_dir, &[dir])?
+            .into_iter()
+            .map(|path| {
+                let relative = path
+                    .strip_prefix(&root)?
+                    .iter()
+                    .map(|segment| {
+                        segment.to_str().with_context(|| {
+                            format!("Static file {} has an invalid name", path.display())
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .join("/");
+                Ok((relative, path))
+            })
+            .collect()
+    }
+
     pub fn policies(&self, base_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
         Self::dirs_to_paths(base_dir, &self.policies)
     }
 
+    pub fn auth_hook(&self, base_dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let path = match self.auth_hook.as_ref() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        anyhow::ensure!(
+            path.is_relative(),
+            "{} is not relative to the current tree",
+            path.display()
+        );
+        let path = base_dir
+            .join(path)
+            .canonicalize()
+            .with_context(|| format!("Could not find the auth hook {}", path.display()))?;
+        Ok(Some(path))
+    }
+
     fn dirs_to_paths(base_dir: &Path, dirs: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
         let mut paths = vec![];
         for dir in dirs {
@@ -363,6 +483,45 @@ policies = ["policies"]
         assert_eq!(m.routes, vec![PathBuf::from("endpoints")]);
     }
 
+    #[test]
+    fn parse_dev_procs() {
+        let d = gen_manifest(
+            r#"
+models = ["models"]
+routes = ["routes"]
+policies = ["policies"]
+
+[dev.proc.web]
+command = "npm run dev"
+cwd = "frontend"
+env = { PORT = "3000" }
+
+[dev.proc.worker]
+command = "./worker.sh"
+"#,
+        );
+        let m = check_manifest(&d);
+        assert_eq!(
+            m.dev.proc["web"],
+            DevProc {
+                command: "npm run dev".into(),
+                cwd: Some(PathBuf::from("frontend")),
+                env: BTreeMap::from([("PORT".into(), "3000".into())]),
+            }
+        );
+        assert_eq!(m.dev.proc["worker"].cwd, None);
+        assert!(m.dev.proc["worker"].env.is_empty());
+
+        let d = gen_manifest(
+            r#"
+models = ["models"]
+routes = ["routes"]
+policies = ["policies"]
+"#,
+        );
+        assert!(check_manifest(&d).dev.proc.is_empty());
+    }
+
     #[should_panic(expected = "is not relative")]
     #[test]
     fn parse_absolute_fails() {
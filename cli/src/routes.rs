@@ -117,7 +117,10 @@ fn walk_dir_entry(
     let metadata = fs::metadata(&entry_path)
         .with_context(|| format!("Could not read metadata of {}", entry_path.display()))?;
     if metadata.is_file() {
-        if let Some(stem) = entry_name.strip_suffix(".ts") {
+        let stem = entry_name
+            .strip_suffix(".ts")
+            .or_else(|| entry_name.strip_suffix(".tsx"));
+        if let Some(stem) = stem {
             let legacy_file_name = get_legacy_file_name(route_dir, &entry_path);
             route_map.add_route(
                 entry_path,
@@ -129,7 +132,7 @@ fn walk_dir_entry(
             )?;
         } else if entry_name.ends_with(".js") {
             bail!(
-                "Found file {}, but only TypeScript files (.ts) are supported",
+                "Found file {}, but only TypeScript files (.ts and .tsx) are supported",
                 entry_path.display()
             );
         }
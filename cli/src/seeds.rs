@@ -0,0 +1,111 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use anyhow::{bail, Context, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The set of seed scripts extracted from the filesystem.
+///
+/// We generate a TypeScript `SeedMap` from this struct.
+#[derive(Debug, Default)]
+pub(crate) struct FileSeedMap {
+    pub seeds: Vec<FileSeed>,
+}
+
+/// A file with a seed script.
+///
+/// The default export of `seeds/<name>.ts` is the seed `<name>`. Seeds run in the order of their
+/// names, so they are typically prefixed with a number (`seeds/001_users.ts`).
+#[derive(Debug)]
+pub(crate) struct FileSeed {
+    /// Absolute path to the file with the seed.
+    pub file_path: PathBuf,
+    /// Name of the seed, which identifies it in the server.
+    pub name: String,
+}
+
+pub(crate) fn build_file_seed_map(base_dir: &Path, seed_dirs: &[PathBuf]) -> Result<FileSeedMap> {
+    let mut seed_map = FileSeedMap::default();
+
+    for seed_dir in seed_dirs.iter() {
+        let seed_dir = base_dir.join(seed_dir);
+        let entries = match fs::read_dir(&seed_dir) {
+            Ok(entries) => entries,
+            // the seed directory is optional
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Could not read directory {}", seed_dir.display()))
+            }
+        };
+
+        for entry in entries {
+            let file_path = fs::canonicalize(entry?.path())?;
+            if file_path.extension() == Some(OsStr::new("ts")) {
+                let name = file_path
+                    .file_stem()
+                    .and_then(OsStr::to_str)
+                    .with_context(|| {
+                        format!("Filename of {} is not in UTF-8", file_path.display())
+                    })?
+                    .to_string();
+                seed_map.seeds.push(FileSeed { file_path, name });
+            } else if file_path.extension() == Some(OsStr::new("js")) {
+                bail!(
+                    "Found file {}, but only TypeScript files (.ts) are supported as seeds",
+                    file_path.display(),
+                );
+            }
+        }
+    }
+
+    seed_map.seeds.sort_by(|a, b| a.name.cmp(&b.name));
+    for pair in seed_map.seeds.windows(2) {
+        if pair[0].name == pair[1].name {
+            bail!(
+                "Seed {:?} is defined both in {} and in {}",
+                pair[0].name,
+                pair[0].file_path.display(),
+                pair[1].file_path.display(),
+            );
+        }
+    }
+    Ok(seed_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_are_sorted_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("seeds")).unwrap();
+        for file in ["002_books.ts", "001_people.ts", "README.md"] {
+            fs::write(dir.path().join("seeds").join(file), "").unwrap();
+        }
+
+        let seed_map = build_file_seed_map(dir.path(), &["seeds".into()]).unwrap();
+        let names = seed_map
+            .seeds
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["001_people", "002_books"]);
+
+        let seed_map = build_file_seed_map(dir.path(), &["missing".into()]).unwrap();
+        assert!(seed_map.seeds.is_empty());
+    }
+
+    #[test]
+    fn duplicate_seeds() {
+        let dir = tempfile::tempdir().unwrap();
+        for seed_dir in ["a", "b"] {
+            fs::create_dir(dir.path().join(seed_dir)).unwrap();
+            fs::write(dir.path().join(seed_dir).join("001_people.ts"), "").unwrap();
+        }
+        assert!(build_file_seed_map(dir.path(), &["a".into(), "b".into()]).is_err());
+    }
+}
@@ -1,9 +1,12 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-use crate::proto::{type_msg::TypeEnum, AddTypeRequest, ContainerType, FieldDefinition, TypeMsg};
+use crate::proto::{
+    type_msg::TypeEnum, AddTypeRequest, AggregateDefinition, AggregateFieldDefinition,
+    ContainerType, CountDefinition, DecimalType, EnumDefinition, FieldDefinition, TypeMsg,
+};
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use chisel_server::is_auth_entity_name;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::path::Path;
 use swc_common::sync::Lrc;
@@ -13,8 +16,9 @@ use swc_common::{
 };
 use swc_ecma_ast::PropName;
 use swc_ecma_ast::{
-    ClassMember, ClassProp, Decl, Decorator, Expr, Ident, Lit, ModuleDecl, ModuleItem,
-    TsEntityName, TsKeywordTypeKind, TsType, TsTypeAnn,
+    ClassMember, ClassProp, Decl, Decorator, Expr, Ident, Lit, MemberProp, ModuleDecl, ModuleItem,
+    Prop, PropOrSpread, Stmt, TsEntityName, TsEnumDecl, TsEnumMemberId, TsKeywordTypeKind, TsLit,
+    TsType, TsTypeAnn,
 };
 use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax, TsConfig};
 use swc_ecmascript::ast::{self as swc_ecma_ast, TsTypeRef};
@@ -58,9 +62,15 @@ impl fmt::Display for TypeEnum {
         match self {
             TypeEnum::String(_) => f.write_str("string"),
             TypeEnum::Number(_) => f.write_str("number"),
+            TypeEnum::Bigint(_) => f.write_str("bigint"),
+            TypeEnum::Json(_) => f.write_str("unknown"),
             TypeEnum::Bool(_) => f.write_str("boolean"),
             TypeEnum::JsDate(_) => f.write_str("jsDate"),
             TypeEnum::ArrayBuffer(_) => f.write_str("ArrayBuffer"),
+            TypeEnum::GeoPoint(_) => f.write_str("GeoPoint"),
+            TypeEnum::Decimal(DecimalType { precision, scale }) => {
+                write!(f, "Decimal<{precision}, {scale}>")
+            }
             TypeEnum::Entity(name) => name.fmt(f),
             TypeEnum::EntityId(entity_name) => write!(f, "Id<{entity_name}>"),
             TypeEnum::Array(inner) => {
@@ -134,7 +144,9 @@ fn map_type(handler: &Handler, x: &TsType) -> Result<TypeEnum> {
         TsType::TsKeywordType(kw) => match kw.kind {
             TsKeywordTypeKind::TsStringKeyword => Ok(TypeEnum::String(true)),
             TsKeywordTypeKind::TsNumberKeyword => Ok(TypeEnum::Number(true)),
+            TsKeywordTypeKind::TsBigIntKeyword => Ok(TypeEnum::Bigint(true)),
             TsKeywordTypeKind::TsBooleanKeyword => Ok(TypeEnum::Bool(true)),
+            TsKeywordTypeKind::TsUnknownKeyword => Ok(TypeEnum::Json(true)),
             _ => Err(swc_err(handler, x, "type keyword not supported")),
         },
         TsType::TsTypeRef(tr) => match &tr.type_name {
@@ -143,6 +155,8 @@ fn map_type(handler: &Handler, x: &TsType) -> Result<TypeEnum> {
                 match ident_name.as_str() {
                     "Date" => Ok(TypeEnum::JsDate(true)),
                     "ArrayBuffer" => Ok(TypeEnum::ArrayBuffer(true)),
+                    "GeoPoint" => Ok(TypeEnum::GeoPoint(true)),
+                    "Decimal" => map_decimal(handler, tr),
                     "Id" => map_entity_id(handler, tr),
                     _ => Ok(TypeEnum::Entity(ident_name)),
                 }
@@ -154,6 +168,31 @@ fn map_type(handler: &Handler, x: &TsType) -> Result<TypeEnum> {
     }
 }
 
+fn map_decimal(handler: &Handler, tr: &TsTypeRef) -> Result<TypeEnum> {
+    let err = || {
+        swc_err(
+            handler,
+            tr,
+            "type Decimal requires the precision and the scale as number literals, such as Decimal<10, 2>",
+        )
+    };
+    let type_params = &tr.type_params.as_ref().ok_or_else(err)?.params;
+    let params = type_params
+        .iter()
+        .map(|param| match &**param {
+            TsType::TsLitType(lit) => match &lit.lit {
+                TsLit::Number(n) if n.value >= 0. && n.value.fract() == 0. => Some(n.value as u32),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    match params.as_deref() {
+        Some(&[precision, scale]) => Ok(TypeEnum::Decimal(DecimalType { precision, scale })),
+        _ => Err(err()),
+    }
+}
+
 fn map_entity_id(handler: &Handler, tr: &TsTypeRef) -> Result<TypeEnum> {
     let type_params = &tr
         .type_params
@@ -187,6 +226,7 @@ fn parse_literal(handler: &Handler, x: &Lit) -> Result<(String, TypeEnum)> {
         Lit::Str(x) => (x.value.to_string(), TypeEnum::String(true)),
         Lit::Bool(x) => (x.value.to_string(), TypeEnum::Bool(true)),
         Lit::Num(x) => (x.value.to_string(), TypeEnum::Number(true)),
+        Lit::BigInt(x) => (x.value.to_string(), TypeEnum::Bigint(true)),
         x => anyhow::bail!(swc_err(handler, x, "literal not supported")),
     };
     Ok(r)
@@ -213,9 +253,21 @@ fn get_field_value(handler: &Handler, x: &Expr) -> Result<Option<(String, TypeEn
     }
 }
 
-fn get_type_decorators(handler: &Handler, x: &[Decorator]) -> Result<(Vec<String>, bool)> {
-    let mut output = vec![];
-    let mut is_unique = false;
+#[derive(Default)]
+struct FieldDecorators {
+    labels: Vec<String>,
+    is_unique: bool,
+    is_version: bool,
+    count: Option<CountDefinition>,
+    aggregate: Option<AggregateFieldDefinition>,
+    max_bytes: Option<u64>,
+    on_delete: Option<String>,
+    collation: Option<String>,
+    renamed_from: Option<String>,
+}
+
+fn get_type_decorators(handler: &Handler, x: &[Decorator]) -> Result<FieldDecorators> {
+    let mut output = FieldDecorators::default();
     for dec in x.iter() {
         match &*dec.expr {
             Expr::Call(call) => {
@@ -223,36 +275,192 @@ fn get_type_decorators(handler: &Handler, x: &[Decorator]) -> Result<(Vec<String
                     anyhow!("expected expression, got {:?} instead", call.callee.clone())
                 })?;
                 let name = get_ident_string(handler, &callee)?;
-                ensure!(
-                    name == "labels",
-                    format!("decorator '{}' is not supported by ChiselStrike", name)
-                );
-                for arg in &call.args {
-                    if let Some((label, ty)) = get_field_value(handler, &arg.expr)? {
+                match name.as_str() {
+                    "labels" => {
+                        for arg in &call.args {
+                            if let Some((label, ty)) = get_field_value(handler, &arg.expr)? {
+                                ensure!(
+                                    matches!(ty, TypeEnum::String(_)),
+                                    "Only strings accepted as labels"
+                                );
+                                output.labels.push(label);
+                            }
+                        }
+                    }
+                    "count" => {
+                        ensure!(
+                            call.args.len() == 2,
+                            swc_err(
+                                handler,
+                                call,
+                                "@count expects an entity and the name of its field"
+                            )
+                        );
+                        let entity_name = get_ident_string(handler, &call.args[0].expr)?;
+                        let field_name = match get_field_value(handler, &call.args[1].expr)? {
+                            Some((field_name, TypeEnum::String(_))) => field_name,
+                            _ => bail!(swc_err(
+                                handler,
+                                &call.args[1],
+                                "the field of @count must be a string"
+                            )),
+                        };
+                        output.count = Some(CountDefinition {
+                            entity_name,
+                            field_name,
+                        });
+                    }
+                    "countOf" | "sumOf" | "avgOf" | "minOf" | "maxOf" => {
+                        let function = name.trim_end_matches("Of").to_owned();
+                        let source_field = if function == "count" {
+                            ensure!(
+                                call.args.is_empty(),
+                                swc_err(handler, call, "@countOf does not expect any arguments")
+                            );
+                            String::new()
+                        } else {
+                            match call
+                                .args
+                                .first()
+                                .map(|arg| get_field_value(handler, &arg.expr))
+                            {
+                                Some(Ok(Some((field_name, TypeEnum::String(_)))))
+                                    if call.args.len() == 1 =>
+                                {
+                                    field_name
+                                }
+                                _ => bail!(swc_err(
+                                    handler,
+                                    call,
+                                    &format!("@{name} expects the name of the aggregated field")
+                                )),
+                            }
+                        };
                         ensure!(
-                            matches!(ty, TypeEnum::String(_)),
-                            "Only strings accepted as labels"
+                            output.aggregate.is_none(),
+                            swc_err(
+                                handler,
+                                call,
+                                "a field can only have one aggregate decorator"
+                            )
                         );
-                        output.push(label);
+                        output.aggregate = Some(AggregateFieldDefinition {
+                            function,
+                            source_field,
+                        });
+                    }
+                    "maxBytes" => {
+                        let max_bytes = match call.args.as_slice() {
+                            [arg] => match get_field_value(handler, &arg.expr)? {
+                                Some((value, TypeEnum::Number(_))) => value.parse::<u64>().ok(),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        output.max_bytes = Some(max_bytes.ok_or_else(|| {
+                            swc_err(
+                                handler,
+                                call,
+                                "@maxBytes expects the maximum size in bytes, as a positive integer",
+                            )
+                        })?);
                     }
+                    "onDelete" => {
+                        let action = match call.args.as_slice() {
+                            [arg] => match get_field_value(handler, &arg.expr)? {
+                                Some((action, TypeEnum::String(_)))
+                                    if matches!(
+                                        action.as_str(),
+                                        "cascade" | "restrict" | "setNull"
+                                    ) =>
+                                {
+                                    Some(action)
+                                }
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        output.on_delete = Some(action.ok_or_else(|| {
+                            swc_err(
+                                handler,
+                                call,
+                                "@onDelete expects one of \"cascade\", \"restrict\" or \"setNull\"",
+                            )
+                        })?);
+                    }
+                    "collate" => {
+                        let collation = match call.args.as_slice() {
+                            [arg] => match get_field_value(handler, &arg.expr)? {
+                                Some((collation, TypeEnum::String(_)))
+                                    if matches!(collation.as_str(), "nocase" | "binary") =>
+                                {
+                                    Some(collation)
+                                }
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        output.collation = Some(collation.ok_or_else(|| {
+                            swc_err(
+                                handler,
+                                call,
+                                "@collate expects one of \"nocase\" or \"binary\"",
+                            )
+                        })?);
+                    }
+                    "renamedFrom" => {
+                        let old_name = match call.args.as_slice() {
+                            [arg] => match get_field_value(handler, &arg.expr)? {
+                                Some((old_name, TypeEnum::String(_))) if !old_name.is_empty() => {
+                                    Some(old_name)
+                                }
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        output.renamed_from = Some(old_name.ok_or_else(|| {
+                            swc_err(
+                                handler,
+                                call,
+                                "@renamedFrom expects the previous name of the field",
+                            )
+                        })?);
+                    }
+                    _ => bail!("decorator '{}' is not supported by ChiselStrike", name),
                 }
             }
             Expr::Ident(x) => {
                 let name = ident_to_string(x);
-                ensure!(name != "labels", "expected a call-like decorator");
-
                 ensure!(
-                    name == "unique",
-                    format!("decorator '{}' is not supported by ChiselStrike", name)
+                    !matches!(
+                        name.as_str(),
+                        "labels"
+                            | "count"
+                            | "countOf"
+                            | "sumOf"
+                            | "avgOf"
+                            | "minOf"
+                            | "maxOf"
+                            | "maxBytes"
+                            | "onDelete"
+                            | "collate"
+                            | "renamedFrom"
+                    ),
+                    "expected a call-like decorator"
                 );
-                is_unique = true;
+
+                match name.as_str() {
+                    "unique" => output.is_unique = true,
+                    "version" => output.is_version = true,
+                    _ => bail!("decorator '{}' is not supported by ChiselStrike", name),
+                }
             }
             z => {
                 return Err(swc_err(handler, z, "expected a call-like decorator"));
             }
         };
     }
-    Ok((output, is_unique))
+    Ok(output)
 }
 
 fn validate_type_vec(type_vec: &[AddTypeRequest], valid_entities: &BTreeSet<String>) -> Result<()> {
@@ -272,7 +480,74 @@ fn validate_type_vec(type_vec: &[AddTypeRequest], valid_entities: &BTreeSet<Stri
     Ok(())
 }
 
-fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Result<FieldDefinition> {
+/// String enums declared in the models, by name. The members are pairs of name and value.
+type Enums = BTreeMap<String, Vec<(String, String)>>;
+
+/// Parses the declaration of a string enum, which can be the type of fields.
+fn parse_enum_decl(handler: &Handler, x: &TsEnumDecl) -> Result<(String, Vec<(String, String)>)> {
+    let name = ident_to_string(&x.id);
+    let mut members = vec![];
+    for member in x.members.iter() {
+        let member_name = match &member.id {
+            TsEnumMemberId::Ident(id) => ident_to_string(id),
+            TsEnumMemberId::Str(s) => s.value.to_string(),
+        };
+        let value = match member.init.as_deref() {
+            Some(Expr::Lit(Lit::Str(s))) => s.value.to_string(),
+            _ => bail!(swc_err(
+                handler,
+                member,
+                &format!("member `{member_name}` of enum `{name}` must be initialized with a string, only string enums are supported"),
+            )),
+        };
+        members.push((member_name, value));
+    }
+    Ok((name, members))
+}
+
+/// Collects the enums declared in a module, exported or not.
+fn collect_enums(
+    handler: &Handler,
+    module: &swc_ecma_ast::Module,
+    enums: &mut Enums,
+) -> Result<()> {
+    for item in module.body.iter() {
+        let decl = match item {
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(exp)) => &exp.decl,
+            ModuleItem::Stmt(Stmt::Decl(decl)) => decl,
+            _ => continue,
+        };
+        if let Decl::TsEnum(x) = decl {
+            let (name, members) = parse_enum_decl(handler, x)?;
+            if enums.insert(name.clone(), members).is_some() {
+                bail!("Enum {} defined twice", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the value of `x` if it is a member of `enum_name`, such as `Color.Red`.
+fn get_enum_value(x: &Expr, enum_name: &str, members: &[(String, String)]) -> Option<String> {
+    let member = match x {
+        Expr::Member(member) => member,
+        _ => return None,
+    };
+    match (&*member.obj, &member.prop) {
+        (Expr::Ident(obj), MemberProp::Ident(prop)) if &*obj.sym == enum_name => members
+            .iter()
+            .find(|(name, _)| *name == *prop.sym)
+            .map(|(_, value)| value.clone()),
+        _ => None,
+    }
+}
+
+fn parse_class_prop(
+    x: &ClassProp,
+    class_name: &str,
+    enums: &Enums,
+    handler: &Handler,
+) -> Result<FieldDefinition> {
     macro_rules! swc_err {
         ($span:ident, $msg:literal, $($args:tt)*) => {{
             let formatted_msg = format!($msg, $($args)*);
@@ -283,7 +558,7 @@ fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Resul
     let (field_name, is_optional) = get_field_info(handler, &x.key)?;
     anyhow::ensure!(field_name != "id", "Creating a field with the name `id` is not supported. 😟\nBut don't worry! ChiselStrike creates an id field automatically, and you can access it in your endpoints as {}.id 🤩", class_name);
 
-    let (field_type, default_value) = match (&x.type_ann, &x.value) {
+    let (mut field_type, mut default_value) = match (&x.type_ann, &x.value) {
         (Some(type_ann), Some(value)) => {
             let field_type = get_field_type(handler, type_ann)?;
             let default_value = if let Some((default_value, value_type)) =
@@ -319,7 +594,87 @@ fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Resul
         )),
     };
 
-    let (labels, is_unique) = get_type_decorators(handler, &x.decorators)?;
+    // fields of enum types are strings, which the server checks against the values of the enum
+    let mut enum_type = None;
+    if let TypeEnum::Entity(type_name) = &field_type {
+        if let Some(members) = enums.get(type_name) {
+            if let Some(value) = &x.value {
+                default_value = Some(get_enum_value(value, type_name, members).ok_or_else(|| {
+                    swc_err!(
+                        x,
+                        "field `{field_name}` of enum type `{type_name}` must be initialized with a member of the enum, such as `{type_name}.{}`",
+                        members.first().map(|(name, _)| name.as_str()).unwrap_or("Member")
+                    )
+                })?);
+            }
+            enum_type = Some(EnumDefinition {
+                name: type_name.clone(),
+                values: members.iter().map(|(_, value)| value.clone()).collect(),
+            });
+            field_type = TypeEnum::String(true);
+        }
+    }
+
+    let FieldDecorators {
+        labels,
+        is_unique,
+        is_version,
+        count,
+        aggregate,
+        max_bytes,
+        on_delete,
+        collation,
+        renamed_from,
+    } = get_type_decorators(handler, &x.decorators)?;
+    anyhow::ensure!(
+        renamed_from.as_deref() != Some(field_name.as_str()),
+        swc_err!(
+            x,
+            "field `{field_name}` is marked with @renamedFrom its own name",
+        )
+    );
+    anyhow::ensure!(
+        !is_version || (matches!(field_type, TypeEnum::Number(_)) && !is_optional),
+        swc_err!(
+            x,
+            "field `{field_name}` is marked with @version, so it must be a non-optional number",
+        )
+    );
+    anyhow::ensure!(
+        max_bytes.is_none() || matches!(field_type, TypeEnum::ArrayBuffer(_)),
+        swc_err!(
+            x,
+            "field `{field_name}` is marked with @maxBytes, so it must be an ArrayBuffer",
+        )
+    );
+    anyhow::ensure!(
+        on_delete.is_none() || matches!(field_type, TypeEnum::Entity(_) | TypeEnum::EntityId(_)),
+        swc_err!(
+            x,
+            "field `{field_name}` is marked with @onDelete, so it must refer to an entity",
+        )
+    );
+    anyhow::ensure!(
+        collation.is_none() || matches!(field_type, TypeEnum::String(_)),
+        swc_err!(
+            x,
+            "field `{field_name}` is marked with @collate, so it must be a string",
+        )
+    );
+    anyhow::ensure!(
+        on_delete.as_deref() != Some("setNull") || is_optional,
+        swc_err!(
+            x,
+            "field `{field_name}` is marked with @onDelete(\"setNull\"), so it must be optional",
+        )
+    );
+    anyhow::ensure!(
+        count.is_none() || (matches!(field_type, TypeEnum::Number(_)) && !is_optional),
+        swc_err!(
+            x,
+            "field `{field_name}` is marked with @count, so it must be a non-optional number",
+        )
+    );
 
     match &field_type {
         TypeEnum::Entity(name) if !is_optional => match &x.value {
@@ -345,17 +700,220 @@ fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Resul
         name: field_name,
         is_optional,
         is_unique,
+        is_version,
+        count,
+        aggregate,
         default_value,
         field_type: Some(TypeMsg {
             type_enum: field_type.into(),
         }),
         labels,
+        timestamp: String::new(),
+        enum_type,
+        max_bytes,
+        on_delete: on_delete.unwrap_or_default(),
+        collation: collation.unwrap_or_default(),
+        renamed_from: renamed_from.unwrap_or_default(),
     })
 }
 
+/// Parses the `@aggregate(Source, { refreshEvery: seconds })` decorator of a class, if there is
+/// one.
+fn get_class_aggregate(handler: &Handler, x: &[Decorator]) -> Result<Option<AggregateDefinition>> {
+    let mut output = None;
+    for dec in x.iter() {
+        let call = match &*dec.expr {
+            Expr::Call(call) => call,
+            Expr::Ident(id) if ident_to_string(id) == "aggregate" => {
+                bail!(swc_err(
+                    handler,
+                    id,
+                    "@aggregate expects the aggregated entity"
+                ))
+            }
+            _ => continue,
+        };
+        let callee = match call.callee.clone().expr() {
+            Some(callee) => callee,
+            None => continue,
+        };
+        if !matches!(&*callee, Expr::Ident(id) if ident_to_string(id) == "aggregate") {
+            continue;
+        }
+        ensure!(
+            output.is_none(),
+            swc_err(handler, call, "@aggregate can only be used once")
+        );
+        ensure!(
+            matches!(call.args.len(), 1 | 2),
+            swc_err(
+                handler,
+                call,
+                "@aggregate expects the aggregated entity and optional options"
+            )
+        );
+        let source_entity = get_ident_string(handler, &call.args[0].expr)?;
+        let mut refresh_interval_s = None;
+        if let Some(options) = call.args.get(1) {
+            let options = match &*options.expr {
+                Expr::Object(options) => options,
+                z => bail!(swc_err(
+                    handler,
+                    z,
+                    "the options of @aggregate must be an object literal"
+                )),
+            };
+            for prop in options.props.iter() {
+                let kv = match prop {
+                    PropOrSpread::Prop(prop) => match &**prop {
+                        Prop::KeyValue(kv) => kv,
+                        z => bail!(swc_err(handler, z, "expected `key: value`")),
+                    },
+                    z => bail!(swc_err(handler, z, "expected `key: value`")),
+                };
+                let (key, _) = get_field_info(handler, &kv.key)?;
+                match (key.as_str(), get_field_value(handler, &kv.value)?) {
+                    ("refreshEvery", Some((value, TypeEnum::Number(_)))) => {
+                        let value = value.parse::<u64>().ok().filter(|v| *v > 0);
+                        refresh_interval_s = Some(value.ok_or_else(|| {
+                            swc_err(
+                                handler,
+                                &kv.value,
+                                "refreshEvery must be a positive whole number of seconds",
+                            )
+                        })?);
+                    }
+                    _ => bail!(swc_err(
+                        handler,
+                        kv,
+                        &format!("unsupported option `{key}` of @aggregate")
+                    )),
+                }
+            }
+        }
+        output = Some(AggregateDefinition {
+            source_entity,
+            refresh_interval_s,
+        });
+    }
+    Ok(output)
+}
+
+/// Parses the `@ownedBy("field")` decorator of a class, returning the owner field or an empty
+/// string if there is no such decorator.
+fn get_class_owner(handler: &Handler, x: &[Decorator]) -> Result<String> {
+    get_class_name_decorator(handler, x, "ownedBy", "the name of the owner field")
+}
+
+/// Parses the `@renamedFrom("OldName")` decorator of a class, returning the previous name of the
+/// entity or an empty string if there is no such decorator.
+fn get_class_renamed_from(handler: &Handler, x: &[Decorator]) -> Result<String> {
+    get_class_name_decorator(handler, x, "renamedFrom", "the previous name of the entity")
+}
+
+/// Parses a class decorator `@decorator("name")` that takes a single non-empty string, which is
+/// described by `what` in the errors.
+fn get_class_name_decorator(
+    handler: &Handler,
+    x: &[Decorator],
+    decorator: &str,
+    what: &str,
+) -> Result<String> {
+    let mut output = String::new();
+    for dec in x.iter() {
+        let call = match &*dec.expr {
+            Expr::Call(call) => call,
+            Expr::Ident(id) if ident_to_string(id) == decorator => {
+                bail!(swc_err(
+                    handler,
+                    id,
+                    &format!("@{decorator} expects {what}")
+                ))
+            }
+            _ => continue,
+        };
+        let callee = match call.callee.clone().expr() {
+            Some(callee) => callee,
+            None => continue,
+        };
+        if !matches!(&*callee, Expr::Ident(id) if ident_to_string(id) == decorator) {
+            continue;
+        }
+        ensure!(
+            output.is_empty(),
+            swc_err(
+                handler,
+                call,
+                &format!("@{decorator} can only be used once")
+            )
+        );
+        output = match call.args.as_slice() {
+            [arg] => match get_field_value(handler, &arg.expr)? {
+                Some((name, TypeEnum::String(_))) if !name.is_empty() => name,
+                _ => bail!(swc_err(
+                    handler,
+                    &*arg.expr,
+                    &format!("@{decorator} expects {what} as a string")
+                )),
+            },
+            _ => bail!(swc_err(
+                handler,
+                call,
+                &format!("@{decorator} expects {what}")
+            )),
+        };
+    }
+    Ok(output)
+}
+
+/// Returns true if a class is marked with `@locked`.
+fn is_class_locked(x: &[Decorator]) -> bool {
+    x.iter()
+        .any(|dec| matches!(&*dec.expr, Expr::Ident(id) if ident_to_string(id) == "locked"))
+}
+
+/// Returns true if a class is marked with `@timestamps`.
+fn is_class_timestamped(x: &[Decorator]) -> bool {
+    x.iter()
+        .any(|dec| matches!(&*dec.expr, Expr::Ident(id) if ident_to_string(id) == "timestamps"))
+}
+
+/// Marks the `createdAt` and `updatedAt` fields of a `@timestamps` entity, adding the ones that
+/// are not declared. The declared ones (which give the entity code access to them) must be plain
+/// `Date` fields.
+fn add_timestamp_fields(entity_name: &str, field_defs: &mut Vec<FieldDefinition>) -> Result<()> {
+    for (field_name, timestamp) in [("createdAt", "created"), ("updatedAt", "updated")] {
+        match field_defs.iter_mut().find(|field| field.name == field_name) {
+            Some(field) => {
+                ensure!(
+                    matches!(field.field_type()?, TypeEnum::JsDate(_))
+                        && !field.is_optional
+                        && field.default_value.is_none()
+                        && !field.is_unique
+                        && !field.is_version
+                        && field.count.is_none()
+                        && field.aggregate.is_none(),
+                    "field `{field_name}` of entity `{entity_name}` is set by @timestamps, so it can only be declared as `readonly {field_name}: Date`"
+                );
+                field.timestamp = timestamp.into();
+            }
+            None => field_defs.push(FieldDefinition {
+                name: field_name.into(),
+                field_type: Some(TypeMsg {
+                    type_enum: Some(TypeEnum::JsDate(true)),
+                }),
+                timestamp: timestamp.into(),
+                ..Default::default()
+            }),
+        }
+    }
+    Ok(())
+}
+
 fn parse_class_decl<P: AsRef<Path>>(
     handler: &Handler,
     filename: &P,
+    enums: &Enums,
     type_vec: &mut Vec<AddTypeRequest>,
     valid_types: &mut BTreeSet<String>,
     decl: &Decl,
@@ -366,11 +924,14 @@ fn parse_class_decl<P: AsRef<Path>>(
             if !valid_types.insert(name.clone()) {
                 bail!("Model {} defined twice", name);
             }
+            if enums.contains_key(&name) {
+                bail!("Model {} has the same name as an enum", name);
+            }
 
             let mut field_defs: Vec<FieldDefinition> = Vec::default();
             for member in &x.class.body {
                 match member {
-                    ClassMember::ClassProp(x) => match parse_class_prop(x, &name, handler) {
+                    ClassMember::ClassProp(x) => match parse_class_prop(x, &name, enums, handler) {
                         Err(err) => {
                             handler.span_err(x.span(), &format!("While parsing class {}", name));
                             bail!("{}", err);
@@ -396,33 +957,57 @@ fn parse_class_decl<P: AsRef<Path>>(
                     _ => {}
                 }
             }
-            type_vec.push(AddTypeRequest { name, field_defs });
+            if is_class_timestamped(&x.class.decorators) {
+                add_timestamp_fields(&name, &mut field_defs)?;
+            }
+            let aggregate = get_class_aggregate(handler, &x.class.decorators)?;
+            let locked = is_class_locked(&x.class.decorators);
+            let owned_by = get_class_owner(handler, &x.class.decorators)?;
+            let renamed_from = get_class_renamed_from(handler, &x.class.decorators)?;
+            ensure!(
+                renamed_from != name,
+                "entity `{name}` is marked with @renamedFrom its own name"
+            );
+            type_vec.push(AddTypeRequest {
+                name,
+                field_defs,
+                aggregate,
+                locked,
+                owned_by,
+                renamed_from,
+            });
         }
+        // enums are collected before the classes, see `collect_enums()`
+        Decl::TsEnum(_) => {}
         z => {
-            handler.span_err(z.span(), "Only class definitions allowed in the types file");
+            handler.span_err(
+                z.span(),
+                "Only class and enum definitions allowed in the types file",
+            );
             bail!("invalid type file {}", filename.as_ref().display());
         }
     }
     Ok(())
 }
 
-fn parse_one_file<P: AsRef<Path>>(
-    filename: &P,
-    type_vec: &mut Vec<AddTypeRequest>,
-    valid_types: &mut BTreeSet<String>,
-) -> Result<()> {
-    let cm: Lrc<SourceMap> = Default::default();
-
+/// Creates a handler that reports problems in the files of `cm` to stderr.
+pub(crate) fn diagnostics_handler(cm: Lrc<SourceMap>) -> Handler {
     let emitter = Box::new(emitter::EmitterWriter::new(
         Box::new(std::io::stderr()),
-        Some(cm.clone()),
+        Some(cm),
         false,
         true,
     ));
+    Handler::with_emitter(true, false, emitter)
+}
 
-    let handler = Handler::with_emitter(true, false, emitter);
-
-    let fm = cm.load_file(filename.as_ref())?;
+/// Parses a TypeScript module, reporting the syntax errors with `handler`.
+pub(crate) fn parse_module(
+    cm: &Lrc<SourceMap>,
+    handler: &Handler,
+    filename: &Path,
+) -> Result<swc_ecma_ast::Module> {
+    let fm = cm.load_file(filename)?;
 
     let mut config = TsConfig {
         decorators: true,
@@ -442,21 +1027,30 @@ fn parse_one_file<P: AsRef<Path>>(
     let mut errors = false;
     for e in parser.take_errors() {
         errors = true;
-        e.into_diagnostic(&handler).emit();
+        e.into_diagnostic(handler).emit();
     }
     if errors {
         bail!("Exiting on parsing errors");
     }
 
-    let x = parser.parse_typescript_module().map_err(|e| {
-        e.into_diagnostic(&handler).emit();
+    parser.parse_typescript_module().map_err(|e| {
+        e.into_diagnostic(handler).emit();
         anyhow!("Exiting on script parsing errors")
-    })?;
+    })
+}
 
-    for decl in &x.body {
+fn parse_one_file<P: AsRef<Path>>(
+    filename: &P,
+    handler: &Handler,
+    module: &swc_ecma_ast::Module,
+    enums: &Enums,
+    type_vec: &mut Vec<AddTypeRequest>,
+    valid_types: &mut BTreeSet<String>,
+) -> Result<()> {
+    for decl in &module.body {
         match decl {
             ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(exp)) => {
-                parse_class_decl(&handler, filename, type_vec, valid_types, &exp.decl)?;
+                parse_class_decl(handler, filename, enums, type_vec, valid_types, &exp.decl)?;
             }
             ModuleItem::ModuleDecl(ModuleDecl::Import(_)) => {
                 // Right now just accept imports, but don't try to parse them.
@@ -480,8 +1074,27 @@ pub(crate) fn parse_types<P: AsRef<Path>>(files: &[P]) -> Result<Vec<AddTypeRequ
 
     let mut valid_types = BTreeSet::new();
 
+    // enums may be used by models in other files, so we collect them from all files before
+    // parsing the classes
+    let mut modules = vec![];
+    let mut enums = Enums::new();
     for filename in files {
-        parse_one_file(filename, &mut type_vec, &mut valid_types)?;
+        let cm: Lrc<SourceMap> = Default::default();
+        let handler = diagnostics_handler(cm.clone());
+        let module = parse_module(&cm, &handler, filename.as_ref())?;
+        collect_enums(&handler, &module, &mut enums)?;
+        modules.push((filename, handler, module));
+    }
+
+    for (filename, handler, module) in modules.iter() {
+        parse_one_file(
+            filename,
+            handler,
+            module,
+            &enums,
+            &mut type_vec,
+            &mut valid_types,
+        )?;
     }
 
     validate_type_vec(&type_vec, &valid_types)?;
@@ -568,6 +568,12 @@ impl Chisel {
             .expect("chisel generate failed")
     }
 
+    /// Reads the file on given relative `path` in ChiselStrike project.
+    pub fn read_to_string(&self, path: &str) -> String {
+        fs::read_to_string(self.tmp_dir.path().join(path))
+            .unwrap_or_else(|e| panic!("Unable to read {:?}: {}", path, e))
+    }
+
     /// Writes given `bytes` into a file on given relative `path` in ChiselStrike project.
     pub fn write_bytes(&self, path: &str, bytes: &[u8]) {
         let full_path = self.tmp_dir.path().join(path);
@@ -711,6 +717,10 @@ impl RequestBuilder {
         self.map(|b| b.header(name, value))
     }
 
+    pub fn body<B: Into<reqwest::Body>>(self, body: B) -> Self {
+        self.map(|b| b.body(body))
+    }
+
     pub async fn send(&self) -> Response {
         let request = self.builder.try_clone().unwrap().build().unwrap();
         let (method, url) = (request.method().clone(), request.url().clone());
@@ -900,6 +910,10 @@ impl Response {
 
         value.into()
     }
+
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers.contains_key(name)
+    }
 }
 
 pub struct TypeScriptRunner {
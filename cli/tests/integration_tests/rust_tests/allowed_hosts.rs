@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+/// Writes a route that connects to `/{port}` of localhost and reports the name of the error.
+/// Nothing listens on these ports, so allowed connections fail with a different error than the
+/// denied ones.
+fn write_files(c: &TestContext, allowed_hosts: &str) {
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        &format!(
+            r#"
+            models = ["models"]
+            routes = ["routes"]
+            events = ["events"]
+            policies = ["policies"]
+            allowed_hosts = {allowed_hosts}
+            "#
+        ),
+    );
+    c.chisel.write(
+        "routes/connect/[port].ts",
+        r#"
+        import { ChiselRequest } from "@chiselstrike/api";
+        export default async function (req: ChiselRequest) {
+            try {
+                await fetch(`http://127.0.0.1:${req.params.get("port")}/`);
+                return "connected";
+            } catch (e) {
+                return e instanceof Deno.errors.PermissionDenied ? "denied" : "failed";
+            }
+        }
+        "#,
+    );
+}
+
+async fn connect(c: &TestContext, port: u16) -> String {
+    c.chisel.get_text(&format!("/dev/connect/{port}")).await
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn allowlist(c: TestContext) {
+    write_files(&c, r#"["127.0.0.1:43190"]"#);
+    c.chisel.apply_ok().await;
+    assert_eq!(connect(&c, 43190).await, "failed");
+    assert_eq!(connect(&c, 43191).await, "denied");
+
+    // the whole host is allowed without a port
+    write_files(&c, r#"["127.0.0.1"]"#);
+    c.chisel.apply_ok().await;
+    assert_eq!(connect(&c, 43191).await, "failed");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn denied_by_default(c: TestContext) {
+    write_files(&c, "[]");
+    c.chisel.apply_ok().await;
+    assert_eq!(connect(&c, 43190).await, "denied");
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--allow-all-net"])]
+pub async fn allow_all_net(c: TestContext) {
+    write_files(&c, "[]");
+    c.chisel.apply_ok().await;
+    assert_eq!(connect(&c, 43190).await, "failed");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_host(c: TestContext) {
+    write_files(&c, r#"["https://example.com/path"]"#);
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("Invalid allowed host \"https://example.com/path\"");
+}
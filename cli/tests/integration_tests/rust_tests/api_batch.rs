@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r#"
+    import { ChiselEntity } from "@chiselstrike/api";
+
+    export class Person extends ChiselEntity {
+        name: string = "";
+        age: number = 0;
+    }
+
+    export class Company extends ChiselEntity {
+        name: string = "";
+    }
+"#;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn batch_queries(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/models.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/companies.ts",
+        r#"
+        import { Company } from "../models/models.ts";
+        export default Company.crud();
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/batch.ts",
+        r#"
+        import { ChiselCursor } from "@chiselstrike/api";
+        import { Company, Person } from "../models/models.ts";
+        export default async function () {
+            const [adults, companies, names, empty] = await ChiselCursor.batch(
+                Person.cursor().filter({ age: 40 }).sortBy("name"),
+                Company.cursor(),
+                Person.cursor().map((p) => p.name.toUpperCase()),
+                Person.cursor().filter({ name: "Nobody" }),
+            );
+            return {
+                adults: adults.map((p) => p.name),
+                companies: companies.map((c) => c.name),
+                names: names.sort(),
+                empty,
+            };
+        }
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/store_and_batch.ts",
+        r#"
+        import { ChiselCursor } from "@chiselstrike/api";
+        import { Company, Person } from "../models/models.ts";
+        export default async function () {
+            await Person.create({ name: "Dave", age: 40 });
+            const [people, companies] = await ChiselCursor.batch(
+                Person.cursor().filter({ age: 40 }),
+                Company.cursor(),
+            );
+            return [people.map((p) => p.name).sort(), companies.length];
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    for (name, age) in [("Carol", 40), ("Alice", 40), ("Bob", 12)] {
+        c.chisel
+            .post_json("/dev/people", json!({"name": name, "age": age}))
+            .await;
+    }
+    c.chisel
+        .post_json("/dev/companies", json!({"name": "Acme"}))
+        .await;
+
+    assert_eq!(
+        c.chisel.get_json("/dev/batch").await,
+        json!({
+            "adults": ["Alice", "Carol"],
+            "companies": ["Acme"],
+            "names": ["ALICE", "BOB", "CAROL"],
+            "empty": [],
+        })
+    );
+
+    // the queries see the writes of the request
+    c.chisel
+        .post("/dev/store_and_batch")
+        .send()
+        .await
+        .assert_json(json!([["Alice", "Carol", "Dave"], 1]));
+}
@@ -399,3 +399,68 @@ pub async fn find_by(c: TestContext) {
         "Error: expression error: entity 'Person' doesn't have field 'misspelled_field_name'"
     ));
 }
+
+#[chisel_macros::test(modules = Deno, optimize = Both)]
+pub async fn select_columns(c: TestContext) {
+    c.chisel.copy_to_dir("examples/person.ts", "models");
+    c.chisel.copy_to_dir("examples/store.ts", "routes");
+    c.chisel.write(
+        "routes/select.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+
+        export default async function chisel() {
+            return await Person.cursor()
+                .filter({ human: true })
+                .sortBy("age")
+                .select("first_name", "age")
+                .toArray();
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    store_people(&c.chisel).await;
+
+    assert_eq!(
+        c.chisel.get_json("dev/select").await,
+        json!([
+            {"first_name": "Jan", "age": -666},
+            {"first_name": "Glauber", "age": 666},
+        ])
+    );
+}
+
+#[chisel_macros::test(modules = Deno, optimize = Both)]
+pub async fn pages(c: TestContext) {
+    c.chisel.copy_to_dir("examples/person.ts", "models");
+    c.chisel.copy_to_dir("examples/store.ts", "routes");
+    c.chisel.write(
+        "routes/pages.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+
+        export default async function chisel() {
+            const names = [];
+            for await (const page of Person.cursor().sortBy("age").pages(2)) {
+                names.push(page.map((p) => p.first_name));
+            }
+            const filtered = [];
+            const cursor = Person.cursor().filter((p) => p.human).sortBy("age");
+            for await (const page of cursor.pages(1)) {
+                filtered.push(page.length);
+            }
+            return { names, filtered };
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    store_people(&c.chisel).await;
+
+    assert_eq!(
+        c.chisel.get_json("dev/pages").await,
+        json!({
+            "names": [["Jan", "Glauber"], ["Pekka"]],
+            "filtered": [1, 1],
+        })
+    );
+}
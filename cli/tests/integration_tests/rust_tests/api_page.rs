@@ -0,0 +1,97 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODEL: &str = r#"
+    import { ChiselEntity } from "@chiselstrike/api";
+
+    export class Post extends ChiselEntity {
+        title: string;
+        score: number;
+    }
+"#;
+
+static ROUTES: &str = r#"
+    import { Post } from "../models/post.ts";
+    import { RouteMap } from "@chiselstrike/api";
+
+    export default new RouteMap()
+        .post("/store", async (req) => {
+            for (const [title, score] of await req.json()) {
+                await Post.create({ title, score });
+            }
+            return "ok";
+        })
+        .post("/page", async (req) => {
+            const page = await Post.page(await req.json());
+            return {
+                titles: page.items.map((post) => post.title),
+                nextCursor: page.nextCursor ?? null,
+                total: page.total ?? null,
+            };
+        });
+"#;
+
+async fn page(c: &TestContext, params: serde_json::Value) -> serde_json::Value {
+    c.chisel
+        .post_json_response("/dev/posts/page", params)
+        .await
+        .assert_ok()
+        .json()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn sorted_pages(c: TestContext) {
+    c.chisel.write("models/post.ts", MODEL);
+    c.chisel.write("routes/posts.ts", ROUTES);
+    c.chisel.write(
+        "routes/crud.ts",
+        r#"
+        import { Post } from "../models/post.ts";
+        export default Post.crud();
+        "#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json(
+            "/dev/posts/store",
+            json!([["a", 3], ["b", 5], ["c", 1], ["d", 5], ["e", 4]]),
+        )
+        .await;
+
+    let first = page(
+        &c,
+        json!({"size": 2, "sort": {"key": "score", "ascending": false}, "total": true}),
+    )
+    .await;
+    assert_eq!(first["total"], json!(5));
+    let mut titles = first["titles"].as_array().unwrap().clone();
+    titles.sort_by_key(|t| t.as_str().unwrap().to_owned());
+    assert_eq!(titles, vec![json!("b"), json!("d")]);
+
+    let second = page(&c, json!({"size": 2, "cursor": first["nextCursor"]})).await;
+    assert_eq!(second["titles"], json!(["e", "a"]));
+    assert_eq!(second["total"], json!(null));
+
+    let third = page(&c, json!({"size": 2, "cursor": second["nextCursor"]})).await;
+    assert_eq!(third["titles"], json!(["c"]));
+    assert_eq!(third["nextCursor"], json!(null));
+
+    // filters apply to all pages
+    let filtered = page(&c, json!({"size": 10, "filter": {"score": {"$gte": 4}}})).await;
+    assert_eq!(filtered["titles"].as_array().unwrap().len(), 3);
+    assert_eq!(filtered["nextCursor"], json!(null));
+
+    // the cursors of the CRUD routes can be passed to `page()`
+    let crud = c.chisel.get_json("/dev/crud?sort=score&page_size=3").await;
+    let from_crud = page(&c, json!({"size": 10, "cursor": crud["next_cursor"]})).await;
+    let mut titles = from_crud["titles"].as_array().unwrap().clone();
+    titles.sort_by_key(|t| t.as_str().unwrap().to_owned());
+    assert_eq!(titles, vec![json!("b"), json!("d")]);
+
+    c.chisel
+        .post_json_response("/dev/posts/page", json!({"size": 0}))
+        .await
+        .assert_status(500)
+        .assert_text_contains("size must be a positive integer, got 0");
+}
@@ -0,0 +1,119 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_files(c: &TestContext) {
+    c.chisel.write(
+        "models/profile.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Profile extends ChiselEntity {
+            name: string;
+            email: string;
+            bio?: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/profiles.ts",
+        r#"
+        import { Profile } from "../models/profile.ts";
+        export default Profile.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/update.ts",
+        r#"
+        import { Profile } from "../models/profile.ts";
+        export default async function chisel(req: Request) {
+            const { id, fields } = await req.json();
+            const profile = await Profile.update(id, fields);
+            return profile ?? "not found";
+        }
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn writes_only_given_fields(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    let profile = c
+        .chisel
+        .post_json_response(
+            "/dev/profiles",
+            json!({"name": "alice", "email": "alice@example.com", "bio": "hi"}),
+        )
+        .await
+        .assert_ok()
+        .json();
+    let id = profile["id"].as_str().unwrap();
+
+    let updated = c
+        .chisel
+        .post_json_response(
+            "/dev/update",
+            json!({"id": id, "fields": {"email": "alice@chiselstrike.com", "bio": null}}),
+        )
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(
+        updated,
+        json!({"id": id, "name": "alice", "email": "alice@chiselstrike.com"})
+    );
+
+    let missing = c
+        .chisel
+        .post_json_response(
+            "/dev/update",
+            json!({"id": "00000000-0000-0000-0000-000000000000", "fields": {"name": "bob"}}),
+        )
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(missing, json!("not found"));
+
+    c.chisel
+        .post("/dev/update")
+        .json(json!({"id": id, "fields": {"name": null}}))
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("update(): field `name` of Profile is not optional");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn concurrent_patches_keep_other_fields(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    let profile = c
+        .chisel
+        .post_json_response(
+            "/dev/profiles",
+            json!({"name": "alice", "email": "alice@example.com"}),
+        )
+        .await
+        .assert_ok()
+        .json();
+    let id = profile["id"].as_str().unwrap();
+
+    let path = format!("/dev/profiles/{id}");
+    let (name, email) = futures::join!(
+        c.chisel.patch(&path).json(json!({"name": "Alice"})).send(),
+        c.chisel
+            .patch(&path)
+            .json(json!({"email": "alice@chiselstrike.com"}))
+            .send(),
+    );
+    name.assert_ok();
+    email.assert_ok();
+
+    json_is_subset(
+        &c.chisel.get_json(&path).await,
+        &json!({"name": "Alice", "email": "alice@chiselstrike.com"}),
+    )
+    .unwrap();
+}
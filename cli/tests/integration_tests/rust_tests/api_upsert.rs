@@ -0,0 +1,120 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use futures::future::join_all;
+
+fn write_files(c: &TestContext) {
+    c.chisel.write(
+        "models/counter.ts",
+        r#"
+        import { ChiselEntity, unique } from "@chiselstrike/api";
+        export class Counter extends ChiselEntity {
+            @unique name: string;
+            hits: number;
+            note?: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/hit.ts",
+        r#"
+        import { Counter } from "../models/counter.ts";
+        export default async function chisel(req: Request) {
+            const { name, hits } = await req.json();
+            const counter = await Counter.upsert({
+                find: { name },
+                create: { hits, note: "created" },
+                update: { hits },
+            });
+            return { name: counter.name, hits: counter.hits, note: counter.note };
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/counters.ts",
+        r#"
+        import { Counter } from "../models/counter.ts";
+        export default async function chisel(req: Request) {
+            const counters = await Counter.findAll();
+            return counters.map((c) => `${c.name} ${c.hits}`).sort();
+        }
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn create_then_update(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    let created = c
+        .chisel
+        .post_json_response("/dev/hit", json!({"name": "a", "hits": 1}))
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(created, json!({"name": "a", "hits": 1, "note": "created"}));
+
+    let updated = c
+        .chisel
+        .post_json_response("/dev/hit", json!({"name": "a", "hits": 5}))
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(updated, json!({"name": "a", "hits": 5, "note": "created"}));
+
+    c.chisel
+        .post_json("/dev/hit", json!({"name": "b", "hits": 2}))
+        .await;
+    assert_eq!(
+        c.chisel.get_json("/dev/counters").await,
+        json!(["a 5", "b 2"])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn concurrent_upserts(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    let requests = (0..10).map(|hits| {
+        c.chisel
+            .post("/dev/hit")
+            .json(json!({"name": "a", "hits": hits}))
+            .send()
+    });
+    for response in join_all(requests).await {
+        response.assert_ok();
+    }
+    let counters = c.chisel.get_json("/dev/counters").await;
+    assert_eq!(counters.as_array().unwrap().len(), 1);
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn find_must_be_unique(c: TestContext) {
+    write_files(&c);
+    c.chisel.write(
+        "routes/bad.ts",
+        r#"
+        import { Counter } from "../models/counter.ts";
+        export default async function chisel(req: Request) {
+            await Counter.upsert({
+                find: { hits: 1 },
+                create: { name: "a" },
+                update: {},
+            });
+            return "ok";
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/bad")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains(
+            "field `hits` of Counter in `find` must be the id or a @unique field",
+        );
+}
@@ -0,0 +1,106 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+async fn dry_run_prints_plan(c: TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            age: number;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/person.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/person", json!({"name": "Alice", "age": 30}))
+        .await;
+
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            email: string = "";
+        }
+        export class Company extends ChiselEntity {
+            name: string;
+        }
+        "#,
+    );
+    c.chisel
+        .exec("apply", &["--dry-run"])
+        .await
+        .expect("chisel apply --dry-run failed")
+        .stdout
+        .read("Dry run: the apply would")
+        .read("create entity Company")
+        .read("add field Person.email")
+        .read("remove field Person.age")
+        .read("warning: drops Person.age with 1 rows of data")
+        .read("rejected: Trying to drop models or fields that still have data");
+
+    // nothing was changed
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .read("class Person")
+        .read("age: number;");
+    let people = c.chisel.get_json("/dev/person").await;
+    assert_eq!(people["results"][0]["age"], json!(30));
+
+    c.chisel
+        .exec("apply", &["--dry-run", "--allow-drop", "Person.age"])
+        .await
+        .expect("chisel apply --dry-run failed")
+        .stdout
+        .read("warning: drops Person.age with 1 rows of data");
+
+    c.chisel
+        .exec("apply", &["--dry-run", "--format", "json"])
+        .await
+        .expect("chisel apply --dry-run --format json failed")
+        .stdout
+        .read(r#""step": "AddTable""#)
+        .read(r#""entity": "Company""#)
+        .read(r#""destructive": false"#)
+        .read(r#""step": "DropColumn""#)
+        .read(r#""field": "age""#)
+        .read(r#""destructive": true"#)
+        .read(r#""warnings": ["#)
+        .read("drops Person.age with 1 rows of data")
+        .read(r#""rejected": ["#)
+        .read("Trying to drop models or fields that still have data");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn dry_run_without_changes(c: TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("apply", &["--dry-run"])
+        .await
+        .expect("chisel apply --dry-run failed")
+        .stdout
+        .read("Dry run: the apply would not change the database");
+}
@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_people(c: &TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            age?: number;
+        }
+    "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+    "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn archive_removed_model(c: TestContext) {
+    write_people(&c);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Alice", "age": 30}))
+        .await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Bob"}))
+        .await;
+
+    c.chisel.remove_file("models/person.ts");
+    c.chisel.remove_file("routes/people.ts");
+    c.chisel
+        .exec("apply", &["--archive"])
+        .await
+        .expect("chisel apply failed")
+        .stdout
+        .read("archived Person");
+
+    c.chisel
+        .exec("data", &["archives"])
+        .await
+        .expect("chisel data archives failed")
+        .stdout
+        .read("dev.Person (archived at");
+
+    let output = c
+        .chisel
+        .exec("data", &["query", "Person", "--archived"])
+        .await
+        .expect("chisel data query failed");
+    output.stdout.read(r#""name":"Alice""#);
+    output.stdout.read(r#""name":"Bob""#);
+
+    c.chisel
+        .exec("data", &["query", "Person"])
+        .await
+        .expect_err("live entity should not exist")
+        .stderr
+        .read("Entity \"Person\" does not exist in version \"dev\"");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn readd_archived_model(c: TestContext) {
+    write_people(&c);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Alice"}))
+        .await;
+
+    c.chisel.remove_file("models/person.ts");
+    c.chisel.remove_file("routes/people.ts");
+    c.chisel
+        .exec("apply", &["--archive"])
+        .await
+        .expect("chisel apply failed");
+
+    // the new model gets a fresh table, the archive is not restored
+    write_people(&c);
+    c.chisel.apply_ok().await;
+    json_is_subset(
+        &c.chisel.get_json("/dev/people").await,
+        &json!({"results": []}),
+    )
+    .unwrap();
+
+    c.chisel
+        .exec("data", &["query", "Person", "--archived"])
+        .await
+        .expect("chisel data query failed")
+        .stdout
+        .read(r#""name":"Alice""#);
+}
@@ -54,7 +54,10 @@ pub async fn next_auth_crud(mut c: TestContext) {
             .header("ChiselAuth", "1234")
             .send()
             .await
-            .assert_json(json!({"results": []}));
+            .assert_json(json!({
+                "pagination": {"page_size": 1000, "max_page_size": 10000, "count": 0, "full_scan": false},
+                "results": [],
+            }));
     }
 
     c.chisel
@@ -123,7 +126,10 @@ pub async fn cant_save_auth_from_user_route(mut c: TestContext) {
         .header("ChiselAuth", "1234")
         .send()
         .await
-        .assert_json(json!({"results": []}));
+        .assert_json(json!({
+            "pagination": {"page_size": 1000, "max_page_size": 10000, "count": 0, "full_scan": false},
+            "results": [],
+        }));
 }
 
 #[chisel_macros::test(modules = Node)]
@@ -168,5 +174,8 @@ pub async fn cant_save_auth_from_user_route_via_relation(mut c: TestContext) {
         .header("ChiselAuth", "1234")
         .send()
         .await
-        .assert_json(json!({"results": []}));
+        .assert_json(json!({
+            "pagination": {"page_size": 1000, "max_page_size": 10000, "count": 0, "full_scan": false},
+            "results": [],
+        }));
 }
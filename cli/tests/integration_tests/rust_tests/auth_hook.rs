@@ -0,0 +1,109 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_files(c: &TestContext) {
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        events = ["events"]
+        policies = ["policies"]
+        auth_hook = "policies/auth.ts"
+        "#,
+    );
+    c.chisel.write(
+        "policies/auth.ts",
+        r#"
+        import type { AuthHook } from "@chiselstrike/api";
+        const authHook: AuthHook = (req: Request) => {
+            const key = req.headers.get("x-api-key");
+            if (key === null) {
+                return null;
+            } else if (key.startsWith("key-")) {
+                return { userId: key.substring(4), claims: { admin: key == "key-root" } };
+            }
+            throw new Error("unknown API key");
+        };
+        export default authHook;
+        "#,
+    );
+    c.chisel.write(
+        "models/note.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Note extends ChiselEntity {
+            owner: string;
+            text: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "policies/Note.ts",
+        r#"
+        export default {
+            read: (note, ctx) => {
+                if (ctx.token !== null && ctx.token.admin) {
+                    return Action.Allow;
+                }
+                return note.owner == ctx.userId ? Action.Allow : Action.Skip;
+            }
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/notes.ts",
+        r#"
+        import { Note } from "../models/note.ts";
+        export default async function chisel(req: Request) {
+            if (req.method == "POST") {
+                await Note.create(await req.json());
+                return "ok";
+            }
+            const notes = await Note.findMany({});
+            return notes.map((note) => note.text).sort();
+        }
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--typescript-policies"])]
+async fn hook_resolves_user_for_policies(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    for (owner, text) in [("alice", "a"), ("bob", "b")] {
+        c.chisel
+            .post("/dev/notes")
+            .json(json!({"owner": owner, "text": text}))
+            .send()
+            .await
+            .assert_ok();
+    }
+
+    let read_as = |key: Option<&'static str>| {
+        let mut request = c.chisel.get("/dev/notes");
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        async move { request.send().await.assert_ok().json() }
+    };
+    assert_eq!(read_as(Some("key-alice")).await, json!(["a"]));
+    assert_eq!(read_as(Some("key-bob")).await, json!(["b"]));
+    assert_eq!(read_as(Some("key-root")).await, json!(["a", "b"]));
+    assert_eq!(read_as(None).await, json!([]));
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--typescript-policies"])]
+async fn hook_rejects_request(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/notes")
+        .header("x-api-key", "invalid")
+        .send()
+        .await
+        .assert_status(401);
+}
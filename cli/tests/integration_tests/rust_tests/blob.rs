@@ -0,0 +1,244 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_routes(c: &TestContext) {
+    c.chisel.write(
+        "routes/blob.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        export default async function chisel(req: Request) {
+            const cmd = await req.json();
+            let result;
+            if (cmd.op === "put") {
+                const options = { contentType: cmd.contentType };
+                result = await Chisel.blob.put(cmd.bucket, cmd.key, cmd.text, options);
+            } else if (cmd.op === "get") {
+                const blob = await Chisel.blob.get(cmd.bucket, cmd.key);
+                result = blob === undefined ? null : {
+                    text: new TextDecoder().decode(blob.data),
+                    contentType: blob.contentType ?? null,
+                };
+            } else if (cmd.op === "delete") {
+                result = await Chisel.blob.delete(cmd.bucket, cmd.key);
+            } else if (cmd.op === "signedUrl") {
+                const options = { expiresInS: cmd.expiresInS };
+                result = await Chisel.blob.signedUrl(cmd.bucket, cmd.key, options);
+            }
+            return { result };
+        }
+        "#,
+    );
+}
+
+async fn blob(chisel: &Chisel, cmd: serde_json::Value) -> serde_json::Value {
+    chisel
+        .post_json_response("/dev/blob", cmd)
+        .await
+        .assert_ok()
+        .json()["result"]
+        .clone()
+}
+
+async fn blob_as(chisel: &Chisel, user: &str, cmd: serde_json::Value) -> Response {
+    chisel
+        .post("/dev/blob")
+        .header("ChiselUID", user)
+        .json(cmd)
+        .send()
+        .await
+}
+
+/// Command `op` for the blob of the `local_store` test.
+fn photo(op: &str) -> serde_json::Value {
+    json!({"op": op, "bucket": "avatars", "key": "alice/photo 1.txt"})
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--blob-dir", "blobs"])]
+pub async fn local_store(c: TestContext) {
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    blob(
+        &c.chisel,
+        json!({"op": "put", "bucket": "avatars", "key": "alice/photo 1.txt",
+            "text": "hello", "contentType": "text/plain"}),
+    )
+    .await;
+    assert_eq!(
+        blob(&c.chisel, photo("get")).await,
+        json!({"text": "hello", "contentType": "text/plain"})
+    );
+    assert_eq!(
+        blob(
+            &c.chisel,
+            json!({"op": "get", "bucket": "avatars", "key": "alice/missing.txt"})
+        )
+        .await,
+        serde_json::Value::Null
+    );
+
+    // the blob can be downloaded with a signed URL
+    let url = blob(&c.chisel, photo("signedUrl")).await;
+    let url = url.as_str().unwrap();
+    let response = c.chisel.get(url).send().await;
+    response.assert_status(200).assert_text("hello");
+    assert_eq!(response.header("content-type"), "text/plain");
+    c.chisel
+        .get(&url.replace("signature=", "signature=00"))
+        .send()
+        .await
+        .assert_status(403);
+
+    // the content type is replaced together with the data
+    blob(
+        &c.chisel,
+        json!({"op": "put", "bucket": "avatars", "key": "alice/photo 1.txt", "text": "bye"}),
+    )
+    .await;
+    assert_eq!(
+        blob(&c.chisel, photo("get")).await,
+        json!({"text": "bye", "contentType": null})
+    );
+
+    assert_eq!(blob(&c.chisel, photo("delete")).await, json!(true));
+    assert_eq!(blob(&c.chisel, photo("delete")).await, json!(false));
+    c.chisel.get(url).send().await.assert_status(404);
+
+    for (bucket, key) in [("Avatars", "a"), ("avatars", "../a"), ("avatars", "")] {
+        c.chisel
+            .post("/dev/blob")
+            .json(json!({"op": "get", "bucket": bucket, "key": key}))
+            .send()
+            .await
+            .assert_status(500);
+    }
+    c.chisel
+        .post("/dev/blob")
+        .json(json!({"op": "signedUrl", "bucket": "avatars", "key": "a", "expiresInS": 0}))
+        .send()
+        .await
+        .assert_status(500);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn disabled(c: TestContext) {
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/blob")
+        .json(json!({"op": "get", "bucket": "avatars", "key": "a"}))
+        .send()
+        .await
+        .assert_status(500);
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--blob-dir", "blobs"])]
+pub async fn policy(c: TestContext) {
+    write_routes(&c);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r#"
+        blobs:
+          - bucket: private
+            users: ^alice$
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    blob_as(
+        &c.chisel,
+        "alice",
+        json!({"op": "put", "bucket": "private", "key": "x", "text": "secret"}),
+    )
+    .await
+    .assert_status(200);
+    blob_as(
+        &c.chisel,
+        "bob",
+        json!({"op": "put", "bucket": "public", "key": "x", "text": "hi"}),
+    )
+    .await
+    .assert_status(200);
+
+    blob_as(
+        &c.chisel,
+        "bob",
+        json!({"op": "get", "bucket": "private", "key": "x"}),
+    )
+    .await
+    .assert_status(500);
+    blob_as(
+        &c.chisel,
+        "bob",
+        json!({"op": "signedUrl", "bucket": "private", "key": "x"}),
+    )
+    .await
+    .assert_status(500);
+    c.chisel
+        .post("/dev/blob")
+        .json(json!({"op": "delete", "bucket": "private", "key": "x"}))
+        .send()
+        .await
+        .assert_status(500);
+
+    let found = blob_as(
+        &c.chisel,
+        "alice",
+        json!({"op": "get", "bucket": "private", "key": "x"}),
+    )
+    .await
+    .assert_status(200)
+    .json();
+    assert_eq!(found["result"]["text"], "secret");
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--blob-dir", "blobs"])]
+pub async fn sandbox_does_not_write(c: TestContext) {
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    blob(
+        &c.chisel,
+        json!({"op": "put", "bucket": "avatars", "key": "kept.txt", "text": "kept"}),
+    )
+    .await;
+    let sandboxed = |cmd: serde_json::Value| {
+        let chisel = &c.chisel;
+        async move {
+            chisel
+                .post("/dev/blob")
+                .header("X-Chisel-Sandbox", "1")
+                .json(cmd)
+                .send()
+                .await
+                .assert_ok()
+                .json()["result"]
+                .clone()
+        }
+    };
+    sandboxed(json!({"op": "put", "bucket": "avatars", "key": "new.txt", "text": "new"})).await;
+    assert_eq!(
+        sandboxed(json!({"op": "delete", "bucket": "avatars", "key": "kept.txt"})).await,
+        json!(true)
+    );
+
+    // neither the put nor the delete of the sandboxed requests reached the store
+    assert_eq!(
+        blob(
+            &c.chisel,
+            json!({"op": "get", "bucket": "avatars", "key": "new.txt"})
+        )
+        .await,
+        serde_json::Value::Null
+    );
+    assert_eq!(
+        blob(
+            &c.chisel,
+            json!({"op": "get", "bucket": "avatars", "key": "kept.txt"})
+        )
+        .await["text"],
+        json!("kept")
+    );
+}
@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn verify_registered_examples(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r#"
+        import { RouteMap } from "@chiselstrike/api";
+
+        export default new RouteMap()
+            .get("/:id", (req) => ({ id: req.params.id, title: "Dune", pages: 412 }))
+            .post("/", async (req) => ({ created: (await req.json()).title }))
+            .example("get a book", {
+                path: "/1",
+                response: { id: "1", title: "Dune", pages: 412 },
+            })
+            .example("create a book", {
+                method: "POST",
+                path: "/",
+                body: { title: "Emma" },
+                response: { created: "Emma" },
+            });
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    let examples = c.chisel.get_json("/dev/__chiselstrike/examples").await;
+    json_is_subset(
+        &examples,
+        &json!([
+            { "name": "get a book", "method": "GET", "path": "/books/1", "status": 200 },
+            {
+                "name": "create a book",
+                "method": "POST",
+                "path": "/books/",
+                "headers": [["content-type", "application/json"]],
+                "body": "{\"title\":\"Emma\"}",
+            },
+        ]),
+    )
+    .unwrap();
+
+    c.chisel
+        .exec("contract", &["verify", "--version", "dev"])
+        .await
+        .expect("chisel contract verify failed")
+        .stdout
+        .read("PASS get a book")
+        .read("PASS create a book")
+        .read("All 2 route examples passed");
+
+    // the title is still a string, but the pages are now a string too
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r#"
+        import { RouteMap } from "@chiselstrike/api";
+
+        export default new RouteMap()
+            .get("/:id", (req) => ({ id: req.params.id, title: "Dune", pages: "412" }))
+            .example("get a book", {
+                path: "/1",
+                response: { id: "1", title: "Dune", pages: 412 },
+            });
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("contract", &["verify", "--version", "dev"])
+        .await
+        .expect_err("changed shape should fail verification")
+        .stdout
+        .read("FAIL get a book")
+        .read("$.pages: expected number, got string");
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--record-examples-rate", "1"])]
+pub async fn record_redacted_examples(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/login.ts",
+        r#"
+        import { RouteMap } from "@chiselstrike/api";
+
+        export default new RouteMap().post("/", async (req) => {
+            const { user } = await req.json();
+            return { user, token: "s3cr3t", expires: 3600 };
+        });
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json_response(
+            "/dev/login?token=abc",
+            json!({ "user": "alice", "password": "hunter2" }),
+        )
+        .await
+        .assert_ok();
+
+    // examples are recorded in the background
+    let mut examples = json!([]);
+    for _ in 0..50 {
+        examples = c.chisel.get_json("/dev/__chiselstrike/examples").await;
+        if !examples.as_array().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let examples = examples.as_array().unwrap();
+    assert_eq!(examples.len(), 1);
+    let example = &examples[0];
+    assert_eq!(example["name"], json!("POST /login/"));
+    assert_eq!(example["path"], json!("/login?token=%3Credacted%3E"));
+    assert_eq!(example["recorded"], json!(true));
+    let body: serde_json::Value = serde_json::from_str(example["body"].as_str().unwrap()).unwrap();
+    assert_eq!(body, json!({ "user": "alice", "password": "<redacted>" }));
+    assert_eq!(
+        example["response"],
+        json!({ "user": "alice", "token": "<redacted>", "expires": 3600 })
+    );
+
+    c.chisel
+        .exec("contract", &["verify", "--version", "dev"])
+        .await
+        .expect("chisel contract verify failed")
+        .stdout
+        .read("PASS POST /login/ (recorded)");
+}
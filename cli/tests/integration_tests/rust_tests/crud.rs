@@ -99,7 +99,10 @@ pub async fn basic(c: TestContext) {
     c.chisel.apply_ok().await;
     assert_eq!(
         c.chisel.get_json("/dev/people").await,
-        json!({"results": []})
+        json!({
+            "pagination": {"page_size": 1000, "max_page_size": 10000, "count": 0, "full_scan": false},
+            "results": [],
+        })
     );
 
     c.chisel.post_json("/dev/people", &*JAN).await;
@@ -450,3 +453,58 @@ pub async fn paging(c: TestContext) {
     let r = c.chisel.get_json(prev_page).await;
     json_is_subset(&r, &json!({"results": [*HONZA, *JAN]})).unwrap();
 }
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--crud-default-page-size", "2", "--crud-max-page-size", "3"],
+)]
+pub async fn page_limits(c: TestContext) {
+    c.chisel.write("models/person.ts", PERSON_MODEL);
+    c.chisel.write("routes/people.ts", PEOPLE_CRUD);
+    c.chisel.write(
+        "routes/all_people.ts",
+        r#"
+        import { crud } from "@chiselstrike/api";
+        import { Person } from "../models/person.ts";
+        export default crud(Person, { pageSize: 1, maxPageSize: 4, allowFullScan: true });
+        "#,
+    );
+    c.chisel.apply_ok().await;
+    store_all_people(&c.chisel).await;
+
+    let page = c.chisel.get_json("/dev/people?sort=first_name").await;
+    json_is_subset(&page, &json!({"results": [*DEJAN, *GLAUBER]})).unwrap();
+    assert_eq!(
+        page["pagination"],
+        json!({"page_size": 2, "max_page_size": 3, "count": 2, "full_scan": false})
+    );
+    let page = c
+        .chisel
+        .get_json("/dev/people?sort=first_name&page_size=1000")
+        .await;
+    json_is_subset(&page, &json!({"results": [*DEJAN, *GLAUBER, *HONZA]})).unwrap();
+    c.chisel
+        .get("/dev/people?full_scan=true")
+        .send()
+        .await
+        .assert_status(400);
+
+    let page = c.chisel.get_json("/dev/all_people?sort=first_name").await;
+    json_is_subset(&page, &json!({"results": [*DEJAN]})).unwrap();
+    let page = c
+        .chisel
+        .get_json("/dev/all_people?sort=first_name&page_size=1000")
+        .await;
+    assert_eq!(page["pagination"]["count"], json!(4));
+    let page = c
+        .chisel
+        .get_json("/dev/all_people?sort=first_name&full_scan=true")
+        .await;
+    json_is_subset(
+        &page,
+        &json!({"results": [*DEJAN, *GLAUBER, *HONZA, *JAN, *PEKKA]}),
+    )
+    .unwrap();
+    assert_eq!(page["pagination"]["full_scan"], json!(true));
+    assert!(page.get("next_page").is_none());
+}
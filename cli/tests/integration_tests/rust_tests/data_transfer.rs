@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_files(c: &TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            age: number;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function chisel(req: Request) {
+            if (req.method == "POST") {
+                await Person.create(await req.json());
+                return "ok";
+            }
+            const people = await Person.findMany({});
+            return people.map((person) => `${person.name} ${person.age}`).sort();
+        }
+        "#,
+    );
+}
+
+async fn create_people(c: &TestContext) {
+    for (name, age) in [("alice", 30), ("bob", 40)] {
+        c.chisel
+            .post("/dev/people")
+            .json(json!({"name": name, "age": age}))
+            .send()
+            .await
+            .assert_ok();
+    }
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn export_and_import(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+    create_people(&c).await;
+
+    c.chisel
+        .exec(
+            "export",
+            &["--version", "dev", "--format", "jsonl", "--output", "dump"],
+        )
+        .await
+        .expect("chisel export failed")
+        .stdout
+        .read("Exported 2 rows of Person");
+    let dump = c.chisel.read_to_string("dump/Person.jsonl");
+    assert_eq!(dump.lines().count(), 2);
+
+    c.chisel
+        .post("/dev/people")
+        .json(json!({"name": "carol", "age": 50}))
+        .send()
+        .await
+        .assert_ok();
+
+    // rows with existing ids are overwritten, other rows are kept
+    c.chisel
+        .exec("import", &["dump"])
+        .await
+        .expect("chisel import failed")
+        .stdout
+        .read("Imported 2 rows of Person");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice 30", "bob 40", "carol 50"])
+    );
+
+    c.chisel
+        .exec("import", &["dump", "--replace"])
+        .await
+        .expect("chisel import failed")
+        .stdout
+        .read("Imported 2 rows of Person");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice 30", "bob 40"])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn import_validates_rows(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+    create_people(&c).await;
+
+    c.chisel.write_unindent(
+        "invalid/Person.jsonl",
+        r#"
+        {"name": "carol", "age": 50}
+        {"name": "dave", "age": "old"}
+        "#,
+    );
+    c.chisel
+        .exec("import", &["invalid", "--replace"])
+        .await
+        .expect_err("import of invalid rows should fail")
+        .stderr
+        .read("row 2: invalid value \"old\" of field `age`");
+    // the import is rolled back as a whole
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice 30", "bob 40"])
+    );
+
+    c.chisel.write_unindent(
+        "unknown/Animal.jsonl",
+        r#"
+        {"name": "rex"}
+        "#,
+    );
+    c.chisel
+        .exec("import", &["unknown"])
+        .await
+        .expect_err("import into unknown entity should fail")
+        .stderr
+        .read("Entity \"Animal\" does not exist");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn export_and_import_csv(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+    create_people(&c).await;
+
+    c.chisel
+        .exec(
+            "export",
+            &["--version", "dev", "--format", "csv", "--output", "dump"],
+        )
+        .await
+        .expect("chisel export failed")
+        .stdout
+        .read("Exported 2 rows of Person");
+    let dump = c.chisel.read_to_string("dump/Person.csv");
+    let mut lines = dump.lines();
+    assert_eq!(lines.next(), Some("id,name,age"));
+    assert_eq!(lines.count(), 2);
+
+    c.chisel
+        .exec("import", &["dump", "--format", "csv", "--replace"])
+        .await
+        .expect("chisel import failed")
+        .stdout
+        .read("Imported 2 rows of Person");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice 30", "bob 40"])
+    );
+
+    // the header is checked before any row is loaded
+    c.chisel.write_unindent(
+        "invalid/Person.csv",
+        r#"
+        id,name,height
+        a,"carol, jr",170
+        "#,
+    );
+    c.chisel
+        .exec("import", &["invalid", "--format", "csv", "--replace"])
+        .await
+        .expect_err("import with an invalid header should fail")
+        .stderr
+        .read("Entity Person has no field `height`");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice 30", "bob 40"])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn csv_rejects_non_scalar_fields(c: TestContext) {
+    c.chisel.write(
+        "models/post.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Post extends ChiselEntity {
+            tags: string[];
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec(
+            "export",
+            &["--version", "dev", "--format", "csv", "--output", "dump"],
+        )
+        .await
+        .expect_err("CSV export of an array field should fail")
+        .stderr
+        .read("cannot be transferred as CSV");
+}
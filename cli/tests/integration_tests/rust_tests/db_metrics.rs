@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--db-max-connections", "4", "--db-min-connections", "1"],
+)]
+pub async fn pool_and_query_metrics(mut c: TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string = "";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel
+        .write(".env", r##"{ "CHISELD_AUTH_SECRET" : "1234" }"##);
+    c.restart_chiseld().await;
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/__chiselstrike/metrics")
+        .send()
+        .await
+        .assert_status(403);
+
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Alice"}))
+        .await;
+    c.chisel.get_json("/dev/people").await;
+
+    let metrics = c
+        .chisel
+        .get("/__chiselstrike/metrics")
+        .header("ChiselAuth", "1234")
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    let db = &metrics["db"];
+    assert_eq!(db["pool"]["maxConnections"], json!(4));
+    assert_eq!(db["pool"]["minConnections"], json!(1));
+    assert!(db["acquire"]["count"].as_u64().unwrap() >= 2);
+    assert_eq!(db["queries"]["insert"]["count"], json!(1));
+    assert!(db["queries"]["select"]["count"].as_u64().unwrap() >= 1);
+    assert_eq!(
+        db["queries"]["select"]["buckets"].as_array().unwrap().len(),
+        12
+    );
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = [
+        "--db-max-connections", "5",
+        "--db-min-connections", "3",
+        "--db-keepalive-interval-s", "0.1",
+    ],
+)]
+pub async fn warm_pool(mut c: TestContext) {
+    c.chisel
+        .write(".env", r##"{ "CHISELD_AUTH_SECRET" : "1234" }"##);
+    c.restart_chiseld().await;
+
+    // the minimum connections are opened at startup, before any request needs them, and the
+    // keepalive pings don't close them
+    let mut size = 0;
+    for _ in 0..50 {
+        let metrics = c
+            .chisel
+            .get("/__chiselstrike/metrics")
+            .header("ChiselAuth", "1234")
+            .send()
+            .await
+            .assert_ok()
+            .json();
+        size = metrics["db"]["pool"]["size"].as_u64().unwrap();
+        if size >= 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(size >= 3, "pool has only {size} connections");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let metrics = c
+        .chisel
+        .get("/__chiselstrike/metrics")
+        .header("ChiselAuth", "1234")
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    assert!(metrics["db"]["pool"]["size"].as_u64().unwrap() >= 3);
+}
@@ -0,0 +1,45 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn version_docs(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/book.ts",
+        r##"
+        import { ChiselEntity, unique } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            @unique title: string;
+            pages: number;
+            subtitle?: string;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r##"
+        import { Book } from "../models/book.ts";
+        export default Book.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/__docs").send().await;
+    response.assert_status(200);
+    assert_eq!(response.header("content-type"), "text/html; charset=utf-8");
+    let page = response.text();
+    assert!(page.contains("<h3 id=\"entity-Book\">Book</h3>"));
+    assert!(page.contains("<code>title</code></td><td><code>string</code></td><td>unique</td>"));
+    assert!(
+        page.contains("<code>subtitle</code></td><td><code>string</code></td><td>optional</td>")
+    );
+    assert!(page.contains("/dev/books/{id}"));
+    assert!(page.contains("Creates an instance"));
+    assert!(
+        page.contains("-d &#39;{&quot;pages&quot;:0,&quot;title&quot;:&quot;string&quot;}&#39;")
+    );
+
+    let index = c.chisel.get("/__docs").send().await;
+    index.assert_status(200);
+    assert!(index.text().contains("<a href=\"/dev/__docs\">dev</a>"));
+}
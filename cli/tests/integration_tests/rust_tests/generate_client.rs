@@ -312,6 +312,32 @@ pub async fn get_iterable_simple(c: TestContext) {
     c.ts_runner.run_ok("generated/test.ts", &src).await;
 }
 
+#[chisel_macros::test(modules = Deno, client_modes = Both)]
+pub async fn get_page(c: TestContext) {
+    c.chisel.write("models/person.ts", PERSON_MODEL);
+    c.chisel.write("routes/people.ts", PEOPLE_CRUD);
+
+    c.chisel.apply_ok().await;
+    store_people(&c.chisel).await;
+
+    c.chisel.generate_ok("generated").await;
+    let src = with_client(
+        &c,
+        r#"
+            const names = [];
+            let page = await cli.people.getPage({size: 2});
+            names.push(...page.items.map(p => p.firstName));
+            assertEquals(page.items.length, 2);
+            page = await cli.people.getPage({size: 2, cursor: page.nextCursor});
+            names.push(...page.items.map(p => p.firstName));
+            assertEquals(page.nextCursor, undefined);
+            names.sort();
+            assertEquals(names, ["Glauber", "Jan", "Pekka"]);
+        "#,
+    );
+    c.ts_runner.run_ok("generated/test.ts", &src).await;
+}
+
 #[chisel_macros::test(modules = Deno, client_modes = Both)]
 pub async fn get_iterable_repeated(c: TestContext) {
     c.chisel.write("models/person.ts", PERSON_MODEL);
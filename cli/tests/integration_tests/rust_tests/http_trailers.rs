@@ -0,0 +1,53 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn trailers_as_headers_over_http1(c: TestContext) {
+    c.chisel.write(
+        "routes/rows.ts",
+        r#"
+        import { ChiselContext, ChiselRequest } from "@chiselstrike/api";
+        export default function (_req: ChiselRequest, ctx: ChiselContext) {
+            let rows = 0;
+            const body = new ReadableStream({
+                pull(controller) {
+                    if (rows < 3) {
+                        rows += 1;
+                        controller.enqueue(new TextEncoder().encode(`row ${rows}\n`));
+                    } else {
+                        // the trailers are known only after the last chunk
+                        ctx.trailers.set("x-row-count", `${rows}`);
+                        ctx.trailers.set("x-status", "complete");
+                        controller.close();
+                    }
+                },
+            });
+            return new Response(body, { headers: { "x-started": "yes" } });
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/rows").send().await;
+    response.assert_text("row 1\nrow 2\nrow 3\n");
+    assert_eq!(response.header("x-started"), "yes");
+    assert_eq!(response.header("x-row-count"), "3");
+    assert_eq!(response.header("x-status"), "complete");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn read_trailers(c: TestContext) {
+    c.chisel.write(
+        "routes/echo.ts",
+        r#"
+        import { ChiselContext, ChiselRequest } from "@chiselstrike/api";
+        export default function (_req: ChiselRequest, ctx: ChiselContext) {
+            ctx.trailers.set("x-done", "1");
+            ctx.trailers.append("x-done", "2");
+            return new Response(ctx.trailers.get("x-done"));
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/echo").send().await.assert_text("1, 2");
+}
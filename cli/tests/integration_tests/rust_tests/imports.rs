@@ -169,3 +169,60 @@ async fn node_require(c: TestContext) {
         .await
         .assert_json(json!(100));
 }
+
+#[self::test(modules = Deno)]
+async fn import_map(mut c: TestContext) {
+    c.chisel.write(
+        "import_map.json",
+        r#"{"imports": {"@lib/": "./lib/", "greeting": "./lib/greeting.ts"}}"#,
+    );
+    c.chisel
+        .write("lib/greeting.ts", r#"export const greeting = "Hello";"#);
+    c.chisel.write(
+        "lib/util.ts",
+        r#"
+        import { greeting } from "greeting";
+        export function greet(name: string) { return `${greeting} ${name}`; }
+        "#,
+    );
+    c.chisel.write(
+        "routes/greet.ts",
+        r#"
+        import { greet } from "@lib/util.ts";
+        export default async function () {
+            return greet("world");
+        }
+        "#,
+    );
+
+    c.chisel.apply_ok().await;
+    assert_eq!(c.chisel.get_text("/dev/greet").await, "Hello world");
+
+    // the import map is persisted with the version
+    c.restart_chiseld().await;
+    assert_eq!(c.chisel.get_text("/dev/greet").await, "Hello world");
+}
+
+#[self::test(modules = Deno)]
+async fn tsconfig_paths(c: TestContext) {
+    c.chisel.write(
+        "tsconfig.json",
+        r#"{"compilerOptions": {"paths": {"~/*": ["./lib/*"]}}}"#,
+    );
+    c.chisel.write(
+        "lib/util.ts",
+        r#"export function greet(name: string) { return `Hello ${name}`; }"#,
+    );
+    c.chisel.write(
+        "routes/greet.ts",
+        r#"
+        import { greet } from "~/util";
+        export default async function () {
+            return greet("world");
+        }
+        "#,
+    );
+
+    c.chisel.apply_ok().await;
+    assert_eq!(c.chisel.get_text("/dev/greet").await, "Hello world");
+}
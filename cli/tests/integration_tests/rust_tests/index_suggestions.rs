@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn suggests_filtered_fields(c: TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            age: number;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/by_name.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function chisel(req: Request) {
+            const name = new URL(req.url).searchParams.get("name");
+            const people = await Person.findMany({ name });
+            return people.map((p) => p.age);
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("index-suggestions", &["--version", "dev"])
+        .await
+        .expect("chisel index-suggestions failed")
+        .stdout
+        .read("No index suggestions");
+
+    for (name, age) in [("alice", 30), ("bob", 40), ("carol", 50)] {
+        c.chisel
+            .post_json("/dev/people", json!({"name": name, "age": age}))
+            .await;
+    }
+    for _ in 0..4 {
+        c.chisel.get_json("/dev/by_name?name=bob").await;
+    }
+
+    c.chisel
+        .exec("index-suggestions", &["--version", "dev"])
+        .await
+        .expect("chisel index-suggestions failed")
+        .stdout
+        .read("Person(name): serves 4 queries on 3 rows, saves ~12 scanned rows");
+}
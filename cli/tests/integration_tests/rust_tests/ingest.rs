@@ -0,0 +1,112 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+async fn write_files(c: &TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            age: number;
+            nickname?: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/import.ts",
+        r#"
+        import { ingest } from "@chiselstrike/api";
+        import { Person } from "../models/person.ts";
+        export default ingest(Person, { batchSize: 2 });
+        "#,
+    );
+    c.chisel.apply_ok().await;
+}
+
+fn names(c: &serde_json::Value) -> Vec<String> {
+    let mut names: Vec<String> = c["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap().to_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn csv(c: TestContext) {
+    write_files(&c).await;
+
+    c.chisel
+        .post("/dev/import")
+        .header("content-type", "text/csv")
+        .body("name,age,nickname\nAlice,30,Al\n\"Smith, Bob\",41,\nCarol,25,Caz\n")
+        .send()
+        .await
+        .assert_json(json!({"inserted": 3}));
+
+    let people = c.chisel.get_json("/dev/people?sort=age").await;
+    assert_eq!(names(&people), ["Alice", "Carol", "Smith, Bob"]);
+    assert_eq!(people["results"][1]["age"], json!(30));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn ndjson(c: TestContext) {
+    write_files(&c).await;
+
+    c.chisel
+        .post("/dev/import")
+        .header("content-type", "application/x-ndjson")
+        .body("{\"name\": \"Alice\", \"age\": 30}\n\n{\"name\": \"Bob\", \"age\": 41}\n")
+        .send()
+        .await
+        .assert_json(json!({"inserted": 2}));
+
+    let people = c.chisel.get_json("/dev/people").await;
+    assert_eq!(names(&people), ["Alice", "Bob"]);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_rows(c: TestContext) {
+    write_files(&c).await;
+
+    // the invalid row is in the second batch, so the first batch must be rolled back
+    c.chisel
+        .post("/dev/import")
+        .header("content-type", "text/csv")
+        .body("name,age\nAlice,30\nBob,41\nCarol,old\n")
+        .send()
+        .await
+        .assert_status(400)
+        .assert_text_contains("row 3");
+
+    c.chisel
+        .post("/dev/import")
+        .header("content-type", "application/x-ndjson")
+        .body("{\"name\": \"Alice\", \"age\": 30, \"email\": \"alice@example.com\"}\n")
+        .send()
+        .await
+        .assert_status(400)
+        .assert_text_contains("email");
+
+    c.chisel
+        .post("/dev/import")
+        .header("content-type", "application/json")
+        .body("[]")
+        .send()
+        .await
+        .assert_status(415);
+
+    let people = c.chisel.get_json("/dev/people").await;
+    assert_eq!(people["results"], json!([]));
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/models.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+        export class Book extends ChiselEntity {
+            title: string;
+            pages: number = 0;
+            published: boolean = false;
+            tags?: string[];
+            author: Author;
+        }
+    "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn shapes(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    let document = c.chisel.get_json("/__jsonschema.json").await;
+    let author = json!({
+        "type": "object",
+        "properties": {"name": {"type": "string"}},
+        "required": ["name"],
+    });
+    json_is_subset(
+        &document["dev"]["Book"]["create"],
+        &json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "type": "object",
+            "properties": {
+                "title": {"type": "string"},
+                "pages": {"type": "number", "default": 0.0},
+                "published": {"type": "boolean", "default": false},
+                "tags": {"type": ["array", "null"], "items": {"type": "string"}},
+                "author": {"$ref": "#/$defs/Author"},
+            },
+            "required": ["title", "author"],
+            "$defs": {"Author": author},
+        }),
+    )
+    .unwrap();
+    assert!(document["dev"]["Book"]["create"]["properties"]["id"].is_null());
+    assert!(document["dev"]["Book"]["update"]["required"].is_null());
+
+    json_is_subset(
+        &document["dev"]["Book"]["read"],
+        &json!({
+            "properties": {
+                "id": {"type": "string", "format": "uuid"},
+                "title": {"type": "string"},
+            },
+            "required": ["id", "title", "pages", "published", "author"],
+        }),
+    )
+    .unwrap();
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn describe_format(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("describe", &["--format", "json-schema"])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read(r#""dev": {"#)
+        .read(r#""Author": {"#)
+        .read(r#""create": {"#)
+        .read(r#""Book": {"#);
+}
@@ -227,3 +227,382 @@ pub async fn test_kafka_produce_multiple(c: TestContext) {
         }
     }
 }
+
+#[chisel_macros::test(modules = Node, kafka_topics = 1)]
+pub async fn test_kafka_start_offset_earliest(c: TestContext) {
+    if let Some(ref kafka_connection) = c.kafka_connection {
+        let kafka_topic = c.kafka_topic(0);
+
+        // the event is produced before the handler subscribes to the topic
+        let client = ClientBuilder::new(vec![kafka_connection.to_string()])
+            .build()
+            .await
+            .unwrap();
+        let partition_client = client.partition_client(kafka_topic.clone(), 0).unwrap();
+        let record = Record {
+            key: None,
+            value: Some(b"early bird".to_vec()),
+            headers: BTreeMap::from([]),
+            timestamp: OffsetDateTime::now_utc(),
+        };
+        partition_client
+            .produce(vec![record], Compression::default())
+            .await
+            .unwrap();
+
+        c.chisel.write(
+            "models/event.ts",
+            r##"
+            import { ChiselEntity } from '@chiselstrike/api';
+            export class Event extends ChiselEntity {
+                value: string;
+            }
+        "##,
+        );
+        c.chisel.write(
+            "routes/events.ts",
+            r##"
+            import { Event } from "../models/event.ts";
+            export default Event.crud();
+        "##,
+        );
+        c.chisel.write(
+            &format!("events/{}.ts", kafka_topic),
+            r##"
+            import { ChiselEvent, TopicOptions } from "@chiselstrike/api";
+            import { Event } from "../models/event.ts";
+
+            export const options: TopicOptions = { startOffset: "earliest" };
+
+            export default async function (event: ChiselEvent) {
+                const value = await event.value.text();
+                await Event.create({ value });
+            }
+        "##,
+        );
+        c.chisel.apply().await.unwrap();
+
+        let response = c.chisel.get("/dev/events")
+            .send_retry(|resp| {
+                !resp.json()["results"].as_array().unwrap().is_empty()
+            })
+            .await
+            .json();
+        assert_eq!("early bird", response["results"][0]["value"]);
+    }
+}
+
+#[chisel_macros::test(modules = Node, kafka_topics = 1)]
+pub async fn test_kafka_manual_commit_redelivers(c: TestContext) {
+    if let Some(ref kafka_connection) = c.kafka_connection {
+        let kafka_topic = c.kafka_topic(0);
+        c.chisel.write(
+            "models/event.ts",
+            r##"
+            import { ChiselEntity } from '@chiselstrike/api';
+            export class Event extends ChiselEntity {
+                value: string;
+            }
+        "##,
+        );
+        c.chisel.write(
+            "routes/events.ts",
+            r##"
+            import { Event } from "../models/event.ts";
+            export default Event.crud();
+        "##,
+        );
+        c.chisel.write(
+            &format!("events/{}.ts", kafka_topic),
+            r##"
+            import { ChiselEvent, TopicOptions } from "@chiselstrike/api";
+            import { Event } from "../models/event.ts";
+
+            export const options: TopicOptions = { manualCommit: true };
+
+            let attempts = 0;
+            export default async function (event: ChiselEvent) {
+                const value = await event.value.text();
+                await Event.create({ value });
+                attempts += 1;
+                if (attempts == 1) {
+                    throw new Error("first delivery fails");
+                }
+                await event.commit();
+            }
+        "##,
+        );
+        c.chisel.apply().await.unwrap();
+
+        let client = ClientBuilder::new(vec![kafka_connection.to_string()])
+            .build()
+            .await
+            .unwrap();
+        let partition_client = client.partition_client(kafka_topic, 0).unwrap();
+        let record = Record {
+            key: None,
+            value: Some(b"at least once".to_vec()),
+            headers: BTreeMap::from([]),
+            timestamp: OffsetDateTime::now_utc(),
+        };
+        partition_client
+            .produce(vec![record], Compression::default())
+            .await
+            .unwrap();
+
+        let response = c.chisel.get("/dev/events")
+            .send_retry(|resp| {
+                !resp.json()["results"].as_array().unwrap().is_empty()
+            })
+            .await
+            .json();
+        let results = response["results"].as_array().unwrap();
+        assert_eq!(1, results.len());
+        assert_eq!("at least once", results[0]["value"]);
+    }
+}
+
+#[chisel_macros::test(modules = Node, kafka_topics = 1)]
+pub async fn test_kafka_manual_commit_skips_poison_event(c: TestContext) {
+    if let Some(ref kafka_connection) = c.kafka_connection {
+        let kafka_topic = c.kafka_topic(0);
+        c.chisel.write(
+            "models/event.ts",
+            r##"
+            import { ChiselEntity } from '@chiselstrike/api';
+            export class Event extends ChiselEntity {
+                value: string;
+            }
+        "##,
+        );
+        c.chisel.write(
+            "routes/events.ts",
+            r##"
+            import { Event } from "../models/event.ts";
+            export default Event.crud();
+        "##,
+        );
+        c.chisel.write(
+            &format!("events/{}.ts", kafka_topic),
+            r##"
+            import { ChiselEvent, TopicOptions } from "@chiselstrike/api";
+            import { Event } from "../models/event.ts";
+
+            export const options: TopicOptions = { manualCommit: true };
+
+            export default async function (event: ChiselEvent) {
+                const value = await event.value.text();
+                if (value == "poison") {
+                    throw new Error("poison event");
+                }
+                await Event.create({ value });
+                await event.commit();
+            }
+        "##,
+        );
+        c.chisel.apply().await.unwrap();
+
+        let client = ClientBuilder::new(vec![kafka_connection.to_string()])
+            .build()
+            .await
+            .unwrap();
+        let partition_client = client.partition_client(kafka_topic, 0).unwrap();
+        let records = ["poison", "healthy"].map(|value| Record {
+            key: None,
+            value: Some(value.as_bytes().to_vec()),
+            headers: BTreeMap::from([]),
+            timestamp: OffsetDateTime::now_utc(),
+        });
+        partition_client
+            .produce(Vec::from(records), Compression::default())
+            .await
+            .unwrap();
+
+        let response = c.chisel.get("/dev/events")
+            .send_retry(|resp| {
+                !resp.json()["results"].as_array().unwrap().is_empty()
+            })
+            .await
+            .json();
+        let results = response["results"].as_array().unwrap();
+        assert_eq!(1, results.len());
+        assert_eq!("healthy", results[0]["value"]);
+    }
+}
+
+#[chisel_macros::test(modules = Node, kafka_topics = 1)]
+pub async fn test_kafka_filtered_handlers(c: TestContext) {
+    if let Some(ref kafka_connection) = c.kafka_connection {
+        let kafka_topic = c.kafka_topic(0);
+        c.chisel.write(
+            "models/event.ts",
+            r##"
+            import { ChiselEntity } from '@chiselstrike/api';
+            export class Event extends ChiselEntity {
+                handler: string;
+                key: string;
+            }
+        "##,
+        );
+        c.chisel.write(
+            "routes/events.ts",
+            r##"
+            import { Event } from "../models/event.ts";
+            export default Event.crud();
+        "##,
+        );
+        c.chisel.write(
+            &format!("events/{}/orders.ts", kafka_topic),
+            r##"
+            import { ChiselEvent, EventFilter } from "@chiselstrike/api";
+            import { Event } from "../../models/event.ts";
+
+            export const filter: EventFilter = { keyPrefix: "order-" };
+            export default async function (event: ChiselEvent) {
+                await Event.create({ handler: "orders", key: await event.key.text() });
+            }
+        "##,
+        );
+        c.chisel.write(
+            &format!("events/{}/web.ts", kafka_topic),
+            r##"
+            import { ChiselEvent, EventFilter } from "@chiselstrike/api";
+            import { Event } from "../../models/event.ts";
+
+            export const filter: EventFilter = {
+                headers: { "source": "web" },
+                fields: { "customer.country": "CZ" },
+            };
+            export default async function (event: ChiselEvent) {
+                await Event.create({ handler: "web", key: await event.key.text() });
+            }
+        "##,
+        );
+        c.chisel.apply().await.unwrap();
+
+        let client = ClientBuilder::new(vec![kafka_connection.to_string()])
+            .build()
+            .await
+            .unwrap();
+        let partition_client = client.partition_client(kafka_topic, 0).unwrap();
+        let record = |key: &str, source: &str, country: &str| Record {
+            key: Some(key.as_bytes().to_vec()),
+            value: Some(format!(r#"{{"customer": {{"country": "{}"}}}}"#, country).into_bytes()),
+            headers: BTreeMap::from([("source".to_string(), source.as_bytes().to_vec())]),
+            timestamp: OffsetDateTime::now_utc(),
+        };
+        let records = vec![
+            record("order-1", "app", "US"),
+            record("user-1", "web", "CZ"),
+            record("order-2", "web", "CZ"),
+            record("user-2", "web", "US"),
+        ];
+        partition_client
+            .produce(records, Compression::default())
+            .await
+            .unwrap();
+
+        let response = c.chisel.get("/dev/events?sort=key")
+            .send_retry(|resp| {
+                resp.json()["results"].as_array().unwrap().len() >= 4
+            })
+            .await
+            .json();
+        let mut handled = response["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| format!("{} {}", event["handler"].as_str().unwrap(), event["key"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+        handled.sort();
+        assert_eq!(handled, vec!["orders order-1", "orders order-2", "web order-2", "web user-1"]);
+    }
+}
+
+#[chisel_macros::test(modules = Node, kafka_topics = 1, chiseld_args = ["--typescript-policies"])]
+pub async fn test_kafka_reads_entity_with_policy(c: TestContext) {
+    if let Some(ref kafka_connection) = c.kafka_connection {
+        let kafka_topic = c.kafka_topic(0);
+        c.chisel.write(
+            "models/models.ts",
+            r##"
+            import { ChiselEntity } from '@chiselstrike/api';
+            export class Secret extends ChiselEntity {
+                text: string;
+            }
+            export class Event extends ChiselEntity {
+                texts: string;
+            }
+        "##,
+        );
+        // Kafka events are seen by the policies as POST requests to the topic
+        c.chisel.write(
+            "policies/Secret.ts",
+            &format!(
+                r##"
+            export default {{
+                read: (secret, ctx) => ctx.method == "POST" && ctx.path == "{}"
+                    ? Action.Allow
+                    : Action.Skip,
+            }}
+        "##,
+                kafka_topic
+            ),
+        );
+        c.chisel.write(
+            "routes/secrets.ts",
+            r##"
+            import { Secret } from "../models/models.ts";
+            export default async function () {
+                await Secret.create({ text: "s" });
+                return "ok";
+            }
+        "##,
+        );
+        c.chisel.write(
+            "routes/events.ts",
+            r##"
+            import { Event } from "../models/models.ts";
+            export default Event.crud();
+        "##,
+        );
+        c.chisel.write(
+            &format!("events/{}.ts", kafka_topic),
+            r##"
+            import { ChiselEvent } from "@chiselstrike/api";
+            import { Event, Secret } from "../models/models.ts";
+
+            export default async function (event: ChiselEvent) {
+                const texts = (await Secret.findMany({})).map((secret) => secret.text);
+                await Event.create({ texts: texts.join(",") });
+            }
+        "##,
+        );
+        c.chisel.apply().await.unwrap();
+        c.chisel.post("/dev/secrets").send().await.assert_ok();
+
+        let client = ClientBuilder::new(vec![kafka_connection.to_string()])
+            .build()
+            .await
+            .unwrap();
+        let partition_client = client.partition_client(kafka_topic, 0).unwrap();
+        let record = Record {
+            key: None,
+            value: Some(b"read the secrets".to_vec()),
+            headers: BTreeMap::from([]),
+            timestamp: OffsetDateTime::now_utc(),
+        };
+        partition_client
+            .produce(vec![record], Compression::default())
+            .await
+            .unwrap();
+
+        let response = c.chisel.get("/dev/events")
+            .send_retry(|resp| {
+                !resp.json()["results"].as_array().unwrap().is_empty()
+            })
+            .await
+            .json();
+        assert_eq!("s", response["results"][0]["texts"]);
+    }
+}
@@ -0,0 +1,210 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_routes(c: &TestContext) {
+    c.chisel.write(
+        "routes/kv.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        export default async function chisel(req: Request) {
+            const cmd = await req.json();
+            let result;
+            if (cmd.op === "get") {
+                result = await Chisel.kv.get(cmd.key);
+            } else if (cmd.op === "set") {
+                result = await Chisel.kv.set(cmd.key, cmd.value, { ttlMs: cmd.ttlMs });
+            } else if (cmd.op === "delete") {
+                result = await Chisel.kv.delete(cmd.key);
+            } else if (cmd.op === "list") {
+                const entries = await Chisel.kv.list(cmd.prefix, { limit: cmd.limit });
+                result = entries.map((entry) => [entry.key, entry.value]);
+            }
+            if (cmd.fail) {
+                throw new Error("failed on purpose");
+            }
+            return { result };
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/freeze.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        export default async function chisel(req: Request) {
+            Chisel.testing.freezeTime(await req.json());
+            return "ok";
+        }
+        "#,
+    );
+}
+
+async fn kv(chisel: &Chisel, cmd: serde_json::Value) -> serde_json::Value {
+    chisel
+        .post_json_response("/dev/kv", cmd)
+        .await
+        .assert_ok()
+        .json()["result"]
+        .clone()
+}
+
+async fn kv_as(chisel: &Chisel, user: &str, cmd: serde_json::Value) -> Response {
+    chisel
+        .post("/dev/kv")
+        .header("ChiselUID", user)
+        .json(cmd)
+        .send()
+        .await
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--testing"])]
+pub async fn basic(c: TestContext) {
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    for key in ["b/2", "a", "b/1", "c"] {
+        kv(
+            &c.chisel,
+            json!({"op": "set", "key": key, "value": {"k": key}}),
+        )
+        .await;
+    }
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "get", "key": "b/1"})).await,
+        json!({"k": "b/1"})
+    );
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "get", "key": "x"})).await,
+        serde_json::Value::Null
+    );
+
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "list", "prefix": ""})).await,
+        json!([
+            ["a", {"k": "a"}],
+            ["b/1", {"k": "b/1"}],
+            ["b/2", {"k": "b/2"}],
+            ["c", {"k": "c"}],
+        ])
+    );
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "list", "prefix": "b/", "limit": 1})).await,
+        json!([["b/1", {"k": "b/1"}]])
+    );
+
+    kv(&c.chisel, json!({"op": "set", "key": "a", "value": 42})).await;
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "get", "key": "a"})).await,
+        json!(42)
+    );
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "delete", "key": "a"})).await,
+        json!(true)
+    );
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "delete", "key": "a"})).await,
+        json!(false)
+    );
+
+    // changes are rolled back with the transaction of the request
+    c.chisel
+        .post("/dev/kv")
+        .json(json!({"op": "set", "key": "c", "value": "lost", "fail": true}))
+        .send()
+        .await
+        .assert_status(500);
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "get", "key": "c"})).await,
+        json!({"k": "c"})
+    );
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--testing"])]
+pub async fn ttl(c: TestContext) {
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json_text("/dev/freeze", json!(1_000_000))
+        .await;
+    kv(
+        &c.chisel,
+        json!({"op": "set", "key": "session", "value": "s", "ttlMs": 1000}),
+    )
+    .await;
+    c.chisel
+        .post_json_text("/dev/freeze", json!(1_000_999))
+        .await;
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "get", "key": "session"})).await,
+        json!("s")
+    );
+    c.chisel
+        .post_json_text("/dev/freeze", json!(1_001_000))
+        .await;
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "get", "key": "session"})).await,
+        serde_json::Value::Null
+    );
+    assert_eq!(
+        kv(&c.chisel, json!({"op": "list", "prefix": ""})).await,
+        json!([])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn policy(c: TestContext) {
+    write_routes(&c);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r#"
+        kv:
+          - prefix: private
+            users: ^alice$
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    kv_as(
+        &c.chisel,
+        "alice",
+        json!({"op": "set", "key": "private/x", "value": 1}),
+    )
+    .await
+    .assert_status(200);
+    kv_as(
+        &c.chisel,
+        "alice",
+        json!({"op": "set", "key": "public", "value": 2}),
+    )
+    .await
+    .assert_status(200);
+
+    kv_as(&c.chisel, "bob", json!({"op": "get", "key": "private/x"}))
+        .await
+        .assert_status(500);
+    kv_as(
+        &c.chisel,
+        "bob",
+        json!({"op": "set", "key": "private/y", "value": 3}),
+    )
+    .await
+    .assert_status(500);
+    c.chisel
+        .post("/dev/kv")
+        .json(json!({"op": "get", "key": "private/x"}))
+        .send()
+        .await
+        .assert_status(500);
+
+    let listed = kv_as(&c.chisel, "bob", json!({"op": "list", "prefix": ""}))
+        .await
+        .assert_status(200)
+        .json();
+    assert_eq!(listed["result"], json!([["public", 2]]));
+    let listed = kv_as(&c.chisel, "alice", json!({"op": "list", "prefix": ""}))
+        .await
+        .assert_status(200)
+        .json();
+    assert_eq!(listed["result"], json!([["private/x", 1], ["public", 2]]));
+}
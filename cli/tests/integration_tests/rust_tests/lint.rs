@@ -0,0 +1,97 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_model(c: &TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn valid_project(c: TestContext) {
+    write_model(&c);
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { RouteMap } from "@chiselstrike/api";
+        import { Person } from "../models/person.ts";
+        export default new RouteMap()
+            .get("/", () => Person.findMany({}))
+            .route(["post", "PUT"], "/:id", () => "ok");
+        "#,
+    );
+
+    c.chisel
+        .exec("lint", &[])
+        .await
+        .expect("chisel lint failed")
+        .stdout
+        .read("No problems found");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn unresolved_imports(c: TestContext) {
+    write_model(&c);
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/people.ts";
+        import { sortBy } from "lodash";
+        export default async function chisel(req: Request) {
+            return sortBy(await Person.findMany({}), "name");
+        }
+        "#,
+    );
+
+    let output = c
+        .chisel
+        .exec("lint", &[])
+        .await
+        .expect_err("chisel lint should fail");
+    output
+        .stderr
+        .read("Cannot resolve import \"../models/people.ts\"")
+        .read("Cannot resolve import \"lodash\"")
+        .read("Found 2 problems");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn invalid_routes(c: TestContext) {
+    write_model(&c);
+    c.chisel.write(
+        "routes/missing.ts",
+        r#"
+        export function chisel(req: Request) {
+            return "no default";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/methods.ts",
+        r#"
+        import { RouteMap } from "@chiselstrike/api";
+        export default new RouteMap()
+            .route("FETCH", "/", () => "ok")
+            .options("/", () => "ok");
+        "#,
+    );
+
+    let output = c
+        .chisel
+        .exec("lint", &[])
+        .await
+        .expect_err("chisel lint should fail");
+    output
+        .stderr
+        .read("Unknown HTTP method \"FETCH\"")
+        .read("RouteMap has no method `options`")
+        .read("routes/missing.ts has no default export")
+        .read("Found 3 problems");
+}
@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static HELLO_ROUTE: &str = r#"
+    export default function () {
+        console.log("hello from the route");
+        return new Response("hello");
+    }
+"#;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn trace_id_is_generated(c: TestContext) {
+    c.chisel.write_unindent("routes/hello.ts", HELLO_ROUTE);
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/hello").send().await;
+    response.assert_text("hello");
+    let first_id = response.header("x-trace-id");
+    assert_eq!(first_id.len(), 32);
+
+    let second_id = c.chisel.get("/dev/hello").send().await.header("x-trace-id");
+    assert_ne!(first_id, second_id);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn trace_id_in_text_log(mut c: TestContext) {
+    c.chisel.write_unindent("routes/hello.ts", HELLO_ROUTE);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/hello")
+        .header("x-trace-id", "my-trace-1")
+        .send()
+        .await
+        .assert_text("hello");
+
+    c.chiseld
+        .stderr
+        .read("INFO - hello from the route version=dev trace_id=my-trace-1")
+        .await;
+    c.chiseld
+        .stderr
+        .read("Handled request method=GET path=/dev/hello status=200")
+        .await;
+    c.chiseld.stderr.read("trace_id=my-trace-1").await;
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--log-format", "json"])]
+pub async fn trace_id_in_json_log(mut c: TestContext) {
+    c.chisel.write_unindent("routes/hello.ts", HELLO_ROUTE);
+    c.chisel.apply_ok().await;
+
+    let response = c
+        .chisel
+        .get("/dev/hello")
+        .header("x-trace-id", "my-trace-2")
+        .send()
+        .await;
+    response.assert_text("hello");
+    assert_eq!(response.header("x-trace-id"), "my-trace-2");
+
+    c.chiseld
+        .stderr
+        .read(r#""message":"hello from the route""#)
+        .await;
+    c.chiseld.stderr.read(r#""trace_id":"my-trace-2""#).await;
+    c.chiseld.stderr.read(r#""path":"/dev/hello""#).await;
+    c.chiseld.stderr.read(r#""trace_id":"my-trace-2""#).await;
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_trace_id_is_replaced(c: TestContext) {
+    c.chisel.write_unindent("routes/hello.ts", HELLO_ROUTE);
+    c.chisel.apply_ok().await;
+
+    let trace_id = c
+        .chisel
+        .get("/dev/hello")
+        .header("x-trace-id", "no spaces allowed")
+        .send()
+        .await
+        .header("x-trace-id");
+    assert_eq!(trace_id.len(), 32);
+}
@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_invoice(c: &TestContext, extra_field: &str) {
+    c.chisel.write(
+        "models/invoice.ts",
+        &format!(
+            r#"
+            import {{ ChiselEntity, locked }} from "@chiselstrike/api";
+            @locked
+            export class Invoice extends ChiselEntity {{
+                customer: string;
+                {extra_field}
+            }}
+            "#
+        ),
+    );
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--audit-retention-s", "1"])]
+async fn removes_expired_audit_entries(c: TestContext) {
+    write_invoice(&c, "");
+    c.chisel.write(
+        "routes/invoices.ts",
+        r#"
+        import { Invoice } from "../models/invoice.ts";
+        export default Invoice.crud();
+        "#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/invoices", json!({"customer": "alice"}))
+        .await;
+
+    // the rejected apply is recorded in the audit log
+    write_invoice(&c, "note: string = \"\";");
+    c.chisel.apply_err().await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    c.chisel
+        .exec("meta", &["gc", "--dry-run"])
+        .await
+        .expect("chisel meta gc --dry-run failed")
+        .stdout
+        .read("Would remove 1 rows from audit_log");
+    c.chisel
+        .exec("audit-log", &[])
+        .await
+        .expect("chisel audit-log failed")
+        .stdout
+        .read("locked_change_rejected Invoice");
+
+    c.chisel
+        .exec("meta", &["gc"])
+        .await
+        .expect("chisel meta gc failed")
+        .stdout
+        .read("Removed 1 rows from audit_log")
+        .read("Compaction reclaimed");
+
+    // the version is untouched
+    let invoices = c.chisel.get_json("/dev/invoices").await;
+    json_is_subset(&invoices, &json!({"results": [{"customer": "alice"}]})).unwrap();
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn deleted_versions_leave_no_garbage(c: TestContext) {
+    c.chisel
+        .write("routes/hello.ts", r#"export default () => "hello";"#);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("apply", &["--version", "v2"])
+        .await
+        .expect("chisel apply --version v2 failed");
+    c.chisel
+        .exec("delete", &["--version", "v2"])
+        .await
+        .expect("chisel delete failed");
+
+    c.chisel
+        .exec("meta", &["gc", "--dry-run"])
+        .await
+        .expect("chisel meta gc --dry-run failed")
+        .stdout
+        .read("There is no garbage in the meta database");
+    assert_eq!(c.chisel.get_text("/dev/hello").await, "hello");
+}
@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext, options: &str) {
+    c.chisel.write(
+        "models/order.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Order extends ChiselEntity {
+            region: string;
+            total: number;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "models/sales.ts",
+        &format!(
+            r#"
+            import {{ aggregate, avgOf, ChiselEntity, countOf, maxOf, sumOf }} from "@chiselstrike/api";
+            import {{ Order }} from "./order.ts";
+            @aggregate(Order{options})
+            export class Sales extends ChiselEntity {{
+                region: string;
+                @countOf() orders: number;
+                @sumOf("total") revenue: number;
+                @avgOf("total") average: number;
+                @maxOf("total") largest: number;
+            }}
+            "#
+        ),
+    );
+    c.chisel.write(
+        "routes/orders.ts",
+        r#"
+        import { Order } from "../models/order.ts";
+        export default Order.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/sales.ts",
+        r#"
+        import { Sales } from "../models/sales.ts";
+        export default Sales.crud();
+        "#,
+    );
+}
+
+async fn order_id(c: &TestContext, region: &str, total: f64) -> String {
+    c.chisel
+        .post_json_response("/dev/orders", json!({"region": region, "total": total}))
+        .await
+        .json()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+async fn sales(c: &TestContext) -> Vec<(String, serde_json::Value, serde_json::Value)> {
+    c.chisel.get_json("/dev/sales?sort=region").await["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| {
+            (
+                s["region"].as_str().unwrap().to_owned(),
+                s["orders"].clone(),
+                s["revenue"].clone(),
+            )
+        })
+        .collect()
+}
+
+fn row(region: &str, orders: u64, revenue: u64) -> (String, serde_json::Value, serde_json::Value) {
+    (region.to_owned(), json!(orders), json!(revenue))
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn maintained_on_writes(c: TestContext) {
+    write_models(&c, "");
+    c.chisel.apply_ok().await;
+    assert_eq!(sales(&c).await, vec![]);
+
+    let first = order_id(&c, "eu", 10.0).await;
+    order_id(&c, "eu", 30.0).await;
+    order_id(&c, "us", 5.0).await;
+    assert_eq!(sales(&c).await, vec![row("eu", 2, 40), row("us", 1, 5)]);
+
+    let eu = c.chisel.get_json("/dev/sales?sort=region").await["results"][0].clone();
+    assert_eq!(eu["average"], json!(20));
+    assert_eq!(eu["largest"], json!(30));
+
+    // moving an order to another group updates both groups
+    c.chisel
+        .patch_json(&format!("/dev/orders/{first}"), json!({"region": "us"}))
+        .await;
+    assert_eq!(sales(&c).await, vec![row("eu", 1, 30), row("us", 2, 15)]);
+
+    c.chisel
+        .delete("/dev/orders?.region=eu")
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(sales(&c).await, vec![row("us", 2, 15)]);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn computed_on_apply(c: TestContext) {
+    write_models(&c, "");
+    c.chisel.apply_ok().await;
+    order_id(&c, "eu", 10.0).await;
+
+    // the aggregate is recomputed when its definition changes
+    c.chisel.write(
+        "models/sales.ts",
+        r#"
+        import { aggregate, ChiselEntity, countOf } from "@chiselstrike/api";
+        import { Order } from "./order.ts";
+        @aggregate(Order)
+        export class Sales extends ChiselEntity {
+            @countOf() orders: number;
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+    let results = c.chisel.get_json("/dev/sales").await["results"].clone();
+    assert_eq!(results.as_array().unwrap().len(), 1);
+    assert_eq!(results[0]["orders"], json!(1));
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("@aggregate(Order)")
+        .read("@countOf() orders: number;");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn refreshed_on_schedule(c: TestContext) {
+    write_models(&c, ", { refreshEvery: 1 }");
+    c.chisel.apply_ok().await;
+
+    order_id(&c, "eu", 10.0).await;
+    order_id(&c, "eu", 20.0).await;
+    let mut refreshed = false;
+    for _ in 0..20 {
+        if sales(&c).await == vec![row("eu", 2, 30)] {
+            refreshed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    assert!(refreshed, "the aggregate was not refreshed");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn read_only(c: TestContext) {
+    write_models(&c, "");
+    c.chisel.apply_ok().await;
+
+    let status = c
+        .chisel
+        .post_json_status("/dev/sales", json!({"region": "eu", "orders": 1}))
+        .await;
+    assert!(status >= 400, "writing an aggregate returned {status}");
+    assert_eq!(sales(&c).await, vec![]);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_aggregate(c: TestContext) {
+    write_models(&c, "");
+    c.chisel.write(
+        "models/sales.ts",
+        r#"
+        import { aggregate, ChiselEntity, sumOf } from "@chiselstrike/api";
+        import { Order } from "./order.ts";
+        @aggregate(Order)
+        export class Sales extends ChiselEntity {
+            country: string;
+            @sumOf("total") revenue: number;
+        }
+        "#,
+    );
+    c.chisel
+        .apply()
+        .await
+        .expect_err("apply should fail")
+        .stderr
+        .read("but entity `Order` has no such field");
+}
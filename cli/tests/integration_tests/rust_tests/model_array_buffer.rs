@@ -123,6 +123,65 @@ pub async fn crud(c: TestContext) {
     let response_text = String::from_utf8_lossy(&text_bytes);
     assert_eq!(response_text, "kůň");
 }
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn max_bytes(c: TestContext) {
+    write_files(&c.chisel);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/data", json!({ "data": base64::encode("abcdef") }))
+        .await;
+
+    // a limited field can be added to an entity that already has data
+    c.chisel.write(
+        "models/storage.ts",
+        r#"
+        import { ChiselEntity, maxBytes } from "@chiselstrike/api";
+        export class StorageEntity extends ChiselEntity {
+            data: ArrayBuffer;
+            @maxBytes(4) thumb?: ArrayBuffer;
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json(
+            "/dev/data",
+            json!({ "data": base64::encode("x"), "thumb": base64::encode("abcd") }),
+        )
+        .await;
+    c.chisel
+        .post_json_response(
+            "/dev/data",
+            json!({ "data": base64::encode("y"), "thumb": base64::encode("abcde") }),
+        )
+        .await
+        .assert_status(422);
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("@maxBytes(4) thumb?: ArrayBuffer;");
+
+    c.chisel.write(
+        "models/storage.ts",
+        r#"
+        import { ChiselEntity, maxBytes } from "@chiselstrike/api";
+        export class StorageEntity extends ChiselEntity {
+            @maxBytes(4) data: string;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("field `data` is marked with @maxBytes, so it must be an ArrayBuffer");
+}
+
 // TODO: Use when arrays of buffers are implemented.
 // #[chisel_macros::test(modules = Deno)]
 // pub async fn array_of_buffers(c: TestContext) {
@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+/// 2^53 + 1, the smallest integer that a double can't represent.
+const BIG: &str = "9007199254740993";
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/account.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Account extends ChiselEntity {
+            name: string;
+            externalId: bigint;
+            balance: bigint = -1n;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/accounts.ts",
+        r#"
+        import { Account } from "../models/account.ts";
+        export default Account.crud();
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn store_and_load(c: TestContext) {
+    write_models(&c);
+    c.chisel.write(
+        "routes/store.ts",
+        &format!(
+            r#"
+            import {{ Account }} from "../models/account.ts";
+            export default async function () {{
+                await Account.create({{ name: "a", externalId: {BIG}n }});
+                await Account.create({{ name: "b", externalId: 1n }});
+                const a = (await Account.findOne({{ externalId: {BIG}n }}))!;
+                const sorted = await Account.cursor()
+                    .sortBy("externalId", false)
+                    .map((account) => account.name)
+                    .toArray();
+                return {{
+                    name: a.name,
+                    type: typeof a.externalId,
+                    exact: a.externalId === {BIG}n,
+                    balance: a.balance.toString(),
+                    sorted,
+                    count: await Account.cursor().count(),
+                }};
+            }}
+            "#
+        ),
+    );
+    c.chisel.apply_ok().await;
+
+    assert_eq!(
+        c.chisel
+            .post_json_response("/dev/store", json!({}))
+            .await
+            .json(),
+        json!({
+            "name": "a",
+            "type": "bigint",
+            "exact": true,
+            "balance": "-1",
+            "sorted": ["a", "b"],
+            "count": 2,
+        })
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn crud_encodes_as_strings(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    let account = c
+        .chisel
+        .post_json_response("/dev/accounts", json!({"name": "a", "externalId": BIG}))
+        .await
+        .json();
+    assert_eq!(account["externalId"], json!(BIG));
+    assert_eq!(account["balance"], json!("-1"));
+
+    let accounts = c
+        .chisel
+        .get_json(&format!("/dev/accounts?.externalId={BIG}"))
+        .await;
+    assert_eq!(accounts["results"][0]["name"], json!("a"));
+    assert_eq!(accounts["results"][0]["externalId"], json!(BIG));
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("externalId: bigint;")
+        .read("balance: bigint = -1n;");
+}
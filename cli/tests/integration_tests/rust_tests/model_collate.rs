@@ -0,0 +1,153 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/user.ts",
+        r#"
+        import { ChiselEntity, collate, unique } from "@chiselstrike/api";
+        export class User extends ChiselEntity {
+            @unique @collate("nocase") email: string;
+            @collate("nocase") name: string = "";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/users.ts",
+        r#"
+        import { User } from "../models/user.ts";
+        export default User.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/upsert.ts",
+        r#"
+        import { User } from "../models/user.ts";
+        export default async function (req: Request) {
+            const { email, name } = await req.json();
+            const user = await User.upsert({
+                find: { email },
+                create: { email, name },
+                update: { name },
+            });
+            return user.email;
+        }
+        "#,
+    );
+}
+
+fn emails(response: &serde_json::Value) -> Vec<&str> {
+    response["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["email"].as_str().unwrap())
+        .collect()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn case_insensitive_unique(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .read("@unique @collate(\"nocase\") email: string;");
+
+    c.chisel
+        .post_json("/dev/users", json!({"email": "alice@example.com"}))
+        .await;
+    c.chisel
+        .post("/dev/users")
+        .json(json!({"email": "Alice@Example.com"}))
+        .send()
+        .await
+        .assert_status(500);
+    let users = c.chisel.get_json("/dev/users").await;
+    assert_eq!(emails(&users), vec!["alice@example.com"]);
+
+    // upsert finds the existing user in a different case
+    let email = c
+        .chisel
+        .post_json_response(
+            "/dev/upsert",
+            json!({"email": "ALICE@example.com", "name": "Alice"}),
+        )
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(email, json!("alice@example.com"));
+    let users = c.chisel.get_json("/dev/users").await;
+    assert_eq!(users["results"].as_array().unwrap().len(), 1);
+    assert_eq!(users["results"][0]["name"], json!("Alice"));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn case_insensitive_filters_and_sorting(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+    for (email, name) in [
+        ("b@x.com", "bob"),
+        ("C@x.com", "Carol"),
+        ("a@x.com", "Alice"),
+    ] {
+        c.chisel
+            .post_json("/dev/users", json!({"email": email, "name": name}))
+            .await;
+    }
+
+    let users = c.chisel.get_json("/dev/users?.email=c@X.COM").await;
+    assert_eq!(emails(&users), vec!["C@x.com"]);
+    let users = c.chisel.get_json("/dev/users?.name~like=car%25").await;
+    assert_eq!(emails(&users), vec!["C@x.com"]);
+
+    let users = c.chisel.get_json("/dev/users?sort=name").await;
+    assert_eq!(emails(&users), vec!["a@x.com", "b@x.com", "C@x.com"]);
+    let users = c.chisel.get_json("/dev/users?sort=-email").await;
+    assert_eq!(emails(&users), vec!["C@x.com", "b@x.com", "a@x.com"]);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_collation(c: TestContext) {
+    c.chisel.write(
+        "models/user.ts",
+        r#"
+        import { ChiselEntity, collate } from "@chiselstrike/api";
+        export class User extends ChiselEntity {
+            @collate("nocase") age: number;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("is marked with @collate, so it must be a string");
+
+    c.chisel.write(
+        "models/user.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class User extends ChiselEntity {
+            email: string;
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel.write(
+        "models/user.ts",
+        r#"
+        import { ChiselEntity, collate } from "@chiselstrike/api";
+        export class User extends ChiselEntity {
+            @collate("nocase") email: string;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("changing the collation of field email. Incompatible change");
+}
@@ -0,0 +1,162 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext, with_count: bool) {
+    let (import, count) = if with_count {
+        (
+            r#"import { Comment } from "./comment.ts";"#,
+            r#"@count(Comment, "postId") commentCount = 0;"#,
+        )
+    } else {
+        ("", "")
+    };
+    c.chisel.write(
+        "models/post.ts",
+        &format!(
+            r#"
+            import {{ ChiselEntity, count }} from "@chiselstrike/api";
+            {import}
+            export class Post extends ChiselEntity {{
+                title: string;
+                {count}
+            }}
+            "#
+        ),
+    );
+    c.chisel.write(
+        "models/comment.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Comment extends ChiselEntity {
+            postId: string;
+            text: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/posts.ts",
+        r#"
+        import { Post } from "../models/post.ts";
+        export default Post.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/comments.ts",
+        r#"
+        import { Comment } from "../models/comment.ts";
+        export default Comment.crud();
+        "#,
+    );
+}
+
+async fn post_id(c: &TestContext, url: &str, data: serde_json::Value) -> String {
+    c.chisel.post_json_response(url, data).await.json()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+async fn comment_count(c: &TestContext, post_id: &str) -> serde_json::Value {
+    c.chisel.get_json(&format!("/dev/posts/{post_id}")).await["commentCount"].clone()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn maintained_on_writes(c: TestContext) {
+    write_models(&c, true);
+    c.chisel.apply_ok().await;
+
+    let a = post_id(&c, "/dev/posts", json!({"title": "A"})).await;
+    let b = post_id(&c, "/dev/posts", json!({"title": "B"})).await;
+    let comment = post_id(&c, "/dev/comments", json!({"postId": a, "text": "1"})).await;
+    c.chisel
+        .post_json("/dev/comments", json!({"postId": a, "text": "2"}))
+        .await;
+    c.chisel
+        .post_json("/dev/comments", json!({"postId": b, "text": "3"}))
+        .await;
+    assert_eq!(comment_count(&c, &a).await, json!(2));
+    assert_eq!(comment_count(&c, &b).await, json!(1));
+
+    // updating a comment without moving it keeps the counts
+    c.chisel
+        .patch_json(&format!("/dev/comments/{comment}"), json!({"text": "1!"}))
+        .await;
+    assert_eq!(comment_count(&c, &a).await, json!(2));
+
+    // moving a comment to another post updates both counts
+    c.chisel
+        .patch_json(&format!("/dev/comments/{comment}"), json!({ "postId": b }))
+        .await;
+    assert_eq!(comment_count(&c, &a).await, json!(1));
+    assert_eq!(comment_count(&c, &b).await, json!(2));
+
+    // the count cannot be overwritten
+    c.chisel
+        .patch_json(&format!("/dev/posts/{a}"), json!({"commentCount": 100}))
+        .await;
+    assert_eq!(comment_count(&c, &a).await, json!(1));
+
+    c.chisel
+        .delete(&format!("/dev/comments?.postId={b}"))
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(comment_count(&c, &a).await, json!(1));
+    assert_eq!(comment_count(&c, &b).await, json!(0));
+
+    c.chisel
+        .exec("data", &["check-counts"])
+        .await
+        .expect("chisel data check-counts failed")
+        .stdout
+        .read("Checked 1 @count fields, all of them match the data");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn backfilled_on_apply(c: TestContext) {
+    write_models(&c, false);
+    c.chisel.apply_ok().await;
+
+    let a = post_id(&c, "/dev/posts", json!({"title": "A"})).await;
+    let b = post_id(&c, "/dev/posts", json!({"title": "B"})).await;
+    for text in ["1", "2", "3"] {
+        c.chisel
+            .post_json("/dev/comments", json!({"postId": a, "text": text}))
+            .await;
+    }
+
+    write_models(&c, true);
+    c.chisel.apply_ok().await;
+    assert_eq!(comment_count(&c, &a).await, json!(3));
+    assert_eq!(comment_count(&c, &b).await, json!(0));
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read(r#"@count(Comment, "postId") commentCount: number = 0;"#);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_count(c: TestContext) {
+    write_models(&c, false);
+    c.chisel.write(
+        "models/post.ts",
+        r#"
+        import { ChiselEntity, count } from "@chiselstrike/api";
+        import { Comment } from "./comment.ts";
+        export class Post extends ChiselEntity {
+            title: string;
+            @count(Comment, "authorId") commentCount = 0;
+        }
+        "#,
+    );
+    c.chisel
+        .apply()
+        .await
+        .expect_err("apply should fail")
+        .stderr
+        .read("but entity `Comment` has no such field");
+}
@@ -0,0 +1,142 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/invoice.ts",
+        r#"
+        import { ChiselEntity, Decimal } from "@chiselstrike/api";
+        export class Invoice extends ChiselEntity {
+            customer: string;
+            amount: Decimal<10, 2> = "0.00";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "models/balance.ts",
+        r#"
+        import { aggregate, ChiselEntity, Decimal, maxOf, sumOf } from "@chiselstrike/api";
+        import { Invoice } from "./invoice.ts";
+        @aggregate(Invoice)
+        export class Balance extends ChiselEntity {
+            customer: string;
+            @sumOf("amount") total: Decimal<10, 2>;
+            @maxOf("amount") largest: Decimal<10, 2>;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/invoices.ts",
+        r#"
+        import { Invoice } from "../models/invoice.ts";
+        export default Invoice.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/balances.ts",
+        r#"
+        import { Balance } from "../models/balance.ts";
+        export default Balance.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/large.ts",
+        r#"
+        import { Invoice } from "../models/invoice.ts";
+        export default async function (req: Request) {
+            const min = new URL(req.url).searchParams.get("min");
+            const invoices = await Invoice.cursor()
+                .filter({ amount: { "$gte": min } })
+                .sortBy("amount")
+                .toArray();
+            return invoices.map((invoice) => invoice.amount);
+        }
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn exact_arithmetic(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    for (customer, amount) in [
+        ("acme", json!("0.10")),
+        ("acme", json!("0.2")),
+        ("acme", json!("99999999.99")),
+        ("initech", json!("-5")),
+        ("initech", json!("10.05")),
+    ] {
+        c.chisel
+            .post_json(
+                "/dev/invoices",
+                json!({"customer": customer, "amount": amount}),
+            )
+            .await;
+    }
+    c.chisel
+        .post_json("/dev/invoices", json!({"customer": "nobody"}))
+        .await;
+
+    // the sum of 0.10 and 0.2 is exact, unlike with floats
+    json_is_subset(
+        &c.chisel.get_json("/dev/balances?sort=customer").await,
+        &json!({
+            "results": [
+                {"customer": "acme", "total": "100000000.29", "largest": "99999999.99"},
+                {"customer": "initech", "total": "5.05", "largest": "10.05"},
+                {"customer": "nobody", "total": "0.00", "largest": "0.00"},
+            ],
+        }),
+    )
+    .unwrap();
+
+    // decimals are compared as numbers, not as strings
+    assert_eq!(
+        c.chisel.get_json("/dev/large?min=0.2").await,
+        json!(["0.20", "10.05", "99999999.99"])
+    );
+    json_is_subset(
+        &c.chisel.get_json("/dev/invoices?.amount~lt=0").await,
+        &json!({"results": [{"customer": "initech", "amount": "-5.00"}]}),
+    )
+    .unwrap();
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn validated(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    for amount in [
+        json!("1.001"),
+        json!("100000000"),
+        json!("12,50"),
+        json!("abc"),
+        json!(12.5),
+    ] {
+        c.chisel
+            .post_json_response(
+                "/dev/invoices",
+                json!({"customer": "acme", "amount": amount}),
+            )
+            .await
+            .assert_status(422);
+    }
+
+    c.chisel.write(
+        "models/invoice.ts",
+        r#"
+        import { ChiselEntity, Decimal } from "@chiselstrike/api";
+        export class Invoice extends ChiselEntity {
+            amount: Decimal<19, 2>;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("Decimal<19, 2> is not supported");
+}
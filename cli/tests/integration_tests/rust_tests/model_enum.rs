@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext, members: &str) {
+    c.chisel.write(
+        "models/post.ts",
+        &format!(
+            r#"
+            import {{ ChiselEntity }} from "@chiselstrike/api";
+            export enum Status {{
+                {members}
+            }}
+            export class Post extends ChiselEntity {{
+                title: string;
+                status: Status = Status.Draft;
+            }}
+            "#
+        ),
+    );
+    c.chisel.write(
+        "routes/posts.ts",
+        r#"
+        import { Post } from "../models/post.ts";
+        export default Post.crud();
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn validated(c: TestContext) {
+    write_models(&c, r#"Draft = "draft", Published = "published","#);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "A", "status": "published"}))
+        .await;
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "B"}))
+        .await;
+    c.chisel
+        .post_json_response("/dev/posts", json!({"title": "C", "status": "deleted"}))
+        .await
+        .assert_status(422);
+
+    let r = c.chisel.get_json("/dev/posts?sort=title").await;
+    json_is_subset(
+        &r,
+        &json!({
+            "results": [
+                {"title": "A", "status": "published"},
+                {"title": "B", "status": "draft"},
+            ],
+        }),
+    )
+    .unwrap();
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read(r#"status: "draft" | "published" = "draft";"#);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn evolve(c: TestContext) {
+    write_models(&c, r#"Draft = "draft", Published = "published","#);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "A", "status": "published"}))
+        .await;
+
+    // adding a value is always safe
+    write_models(
+        &c,
+        r#"Draft = "draft", Published = "published", Archived = "archived","#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "B", "status": "archived"}))
+        .await;
+
+    // removing a value that is still used must be approved
+    write_models(&c, r#"Draft = "draft", Published = "published","#);
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("Trying to drop models or fields that still have data")
+        .read("Post.status=archived (1 elements)");
+
+    c.chisel
+        .exec("apply", &["--allow-drop", "Post.status=archived"])
+        .await
+        .expect("chisel apply failed");
+    c.chisel
+        .post_json_response("/dev/posts", json!({"title": "C", "status": "archived"}))
+        .await
+        .assert_status(422);
+
+    write_models(&c, r#"Draft = "draft","#);
+    c.chisel.apply_err().await;
+    c.chisel
+        .exec("apply", &["--allow-type-deletion"])
+        .await
+        .expect("chisel apply failed");
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "D"}))
+        .await;
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn numeric_enum(c: TestContext) {
+    c.chisel.write(
+        "models/post.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export enum Priority { Low = 1, High = 2 }
+        export class Post extends ChiselEntity {
+            priority: Priority;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("member `Low` of enum `Priority` must be initialized with a string");
+}
@@ -89,7 +89,25 @@ pub async fn remove_field(c: TestContext) {
             a: string = "";
         }"##,
     );
-    c.chisel.apply_ok().await;
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("Evolving.b (1 elements)")
+        .read("--allow-drop Evolving.b");
+    c.chisel
+        .exec("apply", &["--allow-drop", "Evolving.b"])
+        .await
+        .expect("chisel apply failed")
+        .stdout
+        .read("dropped Evolving.b");
+    c.chisel
+        .exec("audit-log", &[])
+        .await
+        .expect("chisel audit-log failed")
+        .stdout
+        .read("dev")
+        .read("drop Evolving.b (1 elements)");
 
     let r = c.chisel.get_json("/dev/evolving").await;
     json_is_subset(
@@ -238,7 +256,10 @@ pub async fn add_and_remove_optional_field(c: TestContext) {
         export class Evolving extends ChiselEntity {
         }"##,
     );
-    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("apply", &["--allow-drop", "Evolving.opt_field"])
+        .await
+        .expect("chisel apply failed");
 }
 
 #[chisel_macros::test(modules = Deno, optimize = Yes)]
@@ -335,3 +356,147 @@ pub async fn remove_unique_constraint(c: TestContext) {
     );
     c.chisel.apply_ok().await;
 }
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn rename_field(mut c: TestContext) {
+    write_crud_route(&c.chisel);
+    c.chisel.write(
+        "models/model.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Evolving extends ChiselEntity {
+            fullName: string;
+        }"##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/evolving", json!({"fullName": "Alice"}))
+        .await;
+
+    c.chisel.write(
+        "models/model.ts",
+        r##"
+        import { ChiselEntity, renamedFrom } from "@chiselstrike/api";
+
+        export class Evolving extends ChiselEntity {
+            @renamedFrom("fullName") name: string;
+        }"##,
+    );
+    c.chisel
+        .exec("apply", &["--dry-run"])
+        .await
+        .expect("chisel apply --dry-run failed")
+        .stdout
+        .read("rename field Evolving.fullName to name");
+    // the field has data, but it is not dropped
+    c.chisel.apply_ok().await;
+
+    json_is_subset(
+        &c.chisel.get_json("/dev/evolving").await,
+        &json!({
+            "results": [{"name": "Alice"}],
+        }),
+    )
+    .unwrap();
+
+    // the hint is ignored once the field is renamed
+    c.chisel.apply_ok().await;
+    c.restart_chiseld().await;
+    json_is_subset(
+        &c.chisel.get_json("/dev/evolving").await,
+        &json!({
+            "results": [{"name": "Alice"}],
+        }),
+    )
+    .unwrap();
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn rename_entity(mut c: TestContext) {
+    c.chisel.write(
+        "models/model.ts",
+        r##"
+        import { ChiselEntity, Id } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string;
+            manager?: Id<Person>;
+        }
+
+        export class Post extends ChiselEntity {
+            title: string;
+            author?: Id<Person>;
+        }"##,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r##"
+        import { Person } from "../models/model.ts";
+        export default Person.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Alice"}))
+        .await;
+
+    c.chisel.write(
+        "models/model.ts",
+        r##"
+        import { ChiselEntity, Id, renamedFrom } from "@chiselstrike/api";
+
+        @renamedFrom("Person")
+        export class User extends ChiselEntity {
+            name: string;
+            manager?: Id<User>;
+        }
+
+        export class Post extends ChiselEntity {
+            title: string;
+            author?: Id<User>;
+        }"##,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r##"
+        import { User } from "../models/model.ts";
+        export default User.crud();
+        "##,
+    );
+    c.chisel
+        .exec("apply", &["--dry-run"])
+        .await
+        .expect("chisel apply --dry-run failed")
+        .stdout
+        .read("rename entity Person to User");
+    // the entity has data, but it is not dropped
+    c.chisel.apply_ok().await;
+
+    json_is_subset(
+        &c.chisel.get_json("/dev/people").await,
+        &json!({
+            "results": [{"name": "Alice"}],
+        }),
+    )
+    .unwrap();
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .read("class Post")
+        .read("author?: Id<User>;")
+        .read("class User")
+        .read("manager?: Id<User>;");
+
+    // the hint is ignored once the entity is renamed
+    c.chisel.apply_ok().await;
+    c.restart_chiseld().await;
+    json_is_subset(
+        &c.chisel.get_json("/dev/people").await,
+        &json!({
+            "results": [{"name": "Alice"}],
+        }),
+    )
+    .unwrap();
+}
@@ -0,0 +1,112 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/shop.ts",
+        r#"
+        import { ChiselEntity, GeoPoint } from "@chiselstrike/api";
+        export class Shop extends ChiselEntity {
+            name: string;
+            location?: GeoPoint;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/shops.ts",
+        r#"
+        import { Shop } from "../models/shop.ts";
+        export default Shop.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/near.ts",
+        r#"
+        import { Shop } from "../models/shop.ts";
+        export default async function (req: Request) {
+            const near = await req.json();
+            const shops = await Shop.cursor().near("location", near).toArray();
+            return shops.map((shop) => shop.name).sort();
+        }
+        "#,
+    );
+}
+
+async fn near(c: &TestContext, lat: f64, lng: f64, radius_meters: f64) -> serde_json::Value {
+    c.chisel
+        .post_json_response(
+            "/dev/near",
+            json!({"lat": lat, "lng": lng, "radiusMeters": radius_meters}),
+        )
+        .await
+        .assert_ok()
+        .json()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn nearby_queries(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    for (name, location) in [
+        ("old-town", json!({"lat": 50.0870, "lng": 14.4208})),
+        ("castle", json!({"lat": 50.0903, "lng": 14.4000})),
+        ("brno", json!({"lat": 49.1951, "lng": 16.6068})),
+        ("fiji-east", json!({"lat": -17.0, "lng": 179.99})),
+        ("fiji-west", json!({"lat": -17.0, "lng": -179.99})),
+        ("nowhere", serde_json::Value::Null),
+    ] {
+        c.chisel
+            .post_json("/dev/shops", json!({"name": name, "location": location}))
+            .await;
+    }
+
+    // the castle is about 1.6 km from the old town square
+    assert_eq!(near(&c, 50.0870, 14.4208, 1000.).await, json!(["old-town"]));
+    assert_eq!(
+        near(&c, 50.0870, 14.4208, 2000.).await,
+        json!(["castle", "old-town"])
+    );
+    assert_eq!(
+        near(&c, 50.0870, 14.4208, 200_000.).await,
+        json!(["brno", "castle", "old-town"])
+    );
+    // the circle crosses the antimeridian
+    assert_eq!(
+        near(&c, -17.0, 180.0, 5000.).await,
+        json!(["fiji-east", "fiji-west"])
+    );
+
+    let shop = c.chisel.get_json("/dev/shops?.name=castle").await;
+    assert_eq!(
+        shop["results"][0]["location"],
+        json!({"lat": 50.0903, "lng": 14.4000})
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_points(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    for location in [
+        json!({"lat": 91, "lng": 0}),
+        json!({"lat": 0, "lng": -181}),
+        json!({"lat": "50", "lng": 14}),
+        json!({"lat": 50}),
+        json!([50, 14]),
+    ] {
+        c.chisel
+            .post_json_response("/dev/shops", json!({"name": "x", "location": location}))
+            .await
+            .assert_status(422);
+    }
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("location?: GeoPoint;");
+}
@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/event.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Event extends ChiselEntity {
+            name: string;
+            meta: unknown;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/events.ts",
+        r#"
+        import { Event } from "../models/event.ts";
+        export default Event.crud();
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn store_and_filter(c: TestContext) {
+    write_models(&c);
+    c.chisel.write(
+        "routes/store.ts",
+        r#"
+        import { Event } from "../models/event.ts";
+        export default async function () {
+            await Event.create({ name: "a", meta: { kind: "click", count: 1, tags: ["x"] } });
+            await Event.create({ name: "b", meta: { kind: "click", count: 3, nested: { deep: true } } });
+            await Event.create({ name: "c", meta: { kind: "view", count: 2 } });
+            await Event.create({ name: "d", meta: "just a string" });
+
+            const names = (events: Event[]) => events.map((e) => e.name).sort();
+            const a = (await Event.findOne({ name: "a" }))!;
+            return {
+                meta: a.meta,
+                clicks: names(await Event.findMany({ "meta.kind": "click" })),
+                nestedObject: names(await Event.findMany({ meta: { kind: "view" } })),
+                greater: names(await Event.findMany({ "meta.count": { "$gt": 1 } })),
+                deep: names(await Event.findMany({ "meta.nested.deep": true })),
+                string: names(await Event.findMany({ meta: "just a string" })),
+            };
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    assert_eq!(
+        c.chisel
+            .post_json_response("/dev/store", json!({}))
+            .await
+            .json(),
+        json!({
+            "meta": {"kind": "click", "count": 1, "tags": ["x"]},
+            "clicks": ["a", "b"],
+            "nestedObject": ["c"],
+            "greater": ["b", "c"],
+            "deep": ["b"],
+            "string": ["d"],
+        })
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn crud_and_describe(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    let meta = json!({"kind": "click", "items": [1, {"a": null}]});
+    let event = c
+        .chisel
+        .post_json_response("/dev/events", json!({"name": "a", "meta": meta}))
+        .await
+        .json();
+    assert_eq!(event["meta"], meta);
+
+    let events = c.chisel.get_json("/dev/events").await;
+    assert_eq!(events["results"][0]["meta"], meta);
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("meta: unknown;");
+}
@@ -0,0 +1,107 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_invoice(c: &TestContext, locked: bool, extra_field: &str) {
+    let decorator = if locked { "@locked" } else { "" };
+    c.chisel.write(
+        "models/invoice.ts",
+        &format!(
+            r#"
+            import {{ ChiselEntity, locked }} from "@chiselstrike/api";
+            {decorator}
+            export class Invoice extends ChiselEntity {{
+                customer: string;
+                amount: number;
+                {extra_field}
+            }}
+            "#
+        ),
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn locked_model_needs_unlock(c: TestContext) {
+    write_invoice(&c, true, "");
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("@locked");
+
+    write_invoice(&c, true, "note: string = \"\";");
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("This apply changes the locked models Invoice")
+        .read("--unlock Invoice");
+    c.chisel
+        .exec("audit-log", &[])
+        .await
+        .expect("chisel audit-log failed")
+        .stdout
+        .read("dev")
+        .read("locked_change_rejected Invoice");
+
+    c.chisel
+        .exec("apply", &["--unlock", "Invoice"])
+        .await
+        .expect("chisel apply --unlock failed");
+    c.chisel
+        .exec("audit-log", &[])
+        .await
+        .expect("chisel audit-log failed")
+        .stdout
+        .read("locked_change Invoice");
+
+    // an apply that doesn't change the model needs no unlock
+    c.chisel.apply_ok().await;
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn removing_lock_needs_unlock(c: TestContext) {
+    write_invoice(&c, true, "");
+    c.chisel.apply_ok().await;
+
+    write_invoice(&c, false, "");
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("This apply changes the locked models Invoice");
+
+    c.chisel.remove_file("models/invoice.ts");
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("This apply changes the locked models Invoice");
+
+    write_invoice(&c, false, "");
+    c.chisel
+        .exec("apply", &["--unlock", "Invoice"])
+        .await
+        .expect("chisel apply --unlock failed");
+
+    // the model is no longer locked
+    write_invoice(&c, false, "note: string = \"\";");
+    c.chisel.apply_ok().await;
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn locking_needs_no_unlock(c: TestContext) {
+    write_invoice(&c, false, "");
+    c.chisel.apply_ok().await;
+
+    write_invoice(&c, true, "");
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("@locked");
+}
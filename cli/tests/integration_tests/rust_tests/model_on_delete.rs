@@ -0,0 +1,176 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/models.ts",
+        r#"
+        import { ChiselEntity, Id, onDelete } from "@chiselstrike/api";
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+        export class Post extends ChiselEntity {
+            title: string;
+            @onDelete("cascade") author: Id<Author>;
+        }
+        export class Comment extends ChiselEntity {
+            text: string;
+            @onDelete("cascade") post: Id<Post>;
+        }
+        export class Review extends ChiselEntity {
+            text: string;
+            @onDelete("setNull") post?: Id<Post>;
+        }
+        export class Contract extends ChiselEntity {
+            text: string;
+            @onDelete("restrict") author: Id<Author>;
+        }
+        "#,
+    );
+    for (route, model) in [
+        ("authors", "Author"),
+        ("posts", "Post"),
+        ("comments", "Comment"),
+        ("reviews", "Review"),
+        ("contracts", "Contract"),
+    ] {
+        c.chisel.write(
+            &format!("routes/{route}.ts"),
+            &format!(
+                r#"
+                import {{ {model} }} from "../models/models.ts";
+                export default {model}.crud();
+                "#
+            ),
+        );
+    }
+}
+
+async fn post_id(c: &TestContext, url: &str, data: serde_json::Value) -> String {
+    c.chisel.post_json_response(url, data).await.json()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+async fn count(c: &TestContext, url: &str) -> usize {
+    c.chisel.get_json(url).await["results"]
+        .as_array()
+        .unwrap()
+        .len()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn cascade_and_set_null(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    let alice = post_id(&c, "/dev/authors", json!({"name": "Alice"})).await;
+    let bob = post_id(&c, "/dev/authors", json!({"name": "Bob"})).await;
+    let a = post_id(&c, "/dev/posts", json!({"title": "A", "author": alice})).await;
+    let b = post_id(&c, "/dev/posts", json!({"title": "B", "author": bob})).await;
+    for (text, post) in [("1", &a), ("2", &a), ("3", &b)] {
+        c.chisel
+            .post_json("/dev/comments", json!({"text": text, "post": post}))
+            .await;
+    }
+    c.chisel
+        .post_json("/dev/reviews", json!({"text": "good", "post": a}))
+        .await;
+
+    // deleting Alice deletes her post and, transitively, its comments
+    c.chisel
+        .delete(&format!("/dev/authors/{alice}"))
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(count(&c, "/dev/authors").await, 1);
+    json_is_subset(
+        &c.chisel.get_json("/dev/posts").await,
+        &json!({"results": [{"title": "B"}]}),
+    )
+    .unwrap();
+    json_is_subset(
+        &c.chisel.get_json("/dev/comments").await,
+        &json!({"results": [{"text": "3"}]}),
+    )
+    .unwrap();
+
+    // the review is kept, but it does not refer to the deleted post anymore
+    let reviews = c.chisel.get_json("/dev/reviews").await;
+    assert_eq!(reviews["results"][0]["text"], json!("good"));
+    assert!(reviews["results"][0]["post"].is_null());
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn restrict(c: TestContext) {
+    write_models(&c);
+    c.chisel.apply_ok().await;
+
+    let alice = post_id(&c, "/dev/authors", json!({"name": "Alice"})).await;
+    post_id(&c, "/dev/posts", json!({"title": "A", "author": alice})).await;
+    let contract = post_id(
+        &c,
+        "/dev/contracts",
+        json!({"text": "exclusive", "author": alice}),
+    )
+    .await;
+
+    c.chisel
+        .delete(&format!("/dev/authors/{alice}"))
+        .send()
+        .await
+        .assert_status(409);
+    // nothing was deleted
+    assert_eq!(count(&c, "/dev/authors").await, 1);
+    assert_eq!(count(&c, "/dev/posts").await, 1);
+
+    c.chisel
+        .delete(&format!("/dev/contracts/{contract}"))
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .delete(&format!("/dev/authors/{alice}"))
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(count(&c, "/dev/posts").await, 0);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_decorators(c: TestContext) {
+    c.chisel.write(
+        "models/models.ts",
+        r#"
+        import { ChiselEntity, Id, onDelete } from "@chiselstrike/api";
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+        export class Post extends ChiselEntity {
+            @onDelete("setNull") author: Id<Author>;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("is marked with @onDelete(\"setNull\"), so it must be optional");
+
+    c.chisel.write(
+        "models/models.ts",
+        r#"
+        import { ChiselEntity, onDelete } from "@chiselstrike/api";
+        export class Post extends ChiselEntity {
+            @onDelete("cascade") author: string;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("is marked with @onDelete, so it must refer to an entity");
+}
@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use crate::framework::Chisel;
+
+const ADMIN_SECRET: &str = "s3cret";
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/note.ts",
+        r#"
+        import { ChiselEntity, ownedBy } from "@chiselstrike/api";
+        @ownedBy("userId")
+        export class Note extends ChiselEntity {
+            userId: string;
+            text: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/notes.ts",
+        r#"
+        import { Note } from "../models/note.ts";
+        export default Note.crud();
+        "#,
+    );
+}
+
+async fn post_note(chisel: &Chisel, user: &str, text: &str) -> serde_json::Value {
+    chisel
+        .post("/dev/notes")
+        .header("ChiselUID", user)
+        .json(json!({"text": text, "userId": "someone-else"}))
+        .send()
+        .await
+        .assert_status(200)
+        .json()
+}
+
+async fn note_texts(chisel: &Chisel, header: Option<(&str, &str)>) -> Vec<String> {
+    let mut request = chisel.get("/dev/notes");
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    let mut texts = request.send().await.assert_status(200).json()["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|note| note["text"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    texts.sort();
+    texts
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn restricted_to_owner(mut c: TestContext) {
+    write_models(&c);
+    c.chisel.write(
+        ".env",
+        &format!(r#"{{ "CHISEL_ADMIN_SECRET": "{ADMIN_SECRET}" }}"#),
+    );
+    c.chisel.apply_ok().await;
+    c.restart_chiseld().await;
+
+    let note = post_note(&c.chisel, "alice", "a1").await;
+    assert_eq!(note["userId"], json!("alice"));
+    post_note(&c.chisel, "alice", "a2").await;
+    post_note(&c.chisel, "bob", "b1").await;
+
+    assert_eq!(
+        note_texts(&c.chisel, Some(("ChiselUID", "alice"))).await,
+        ["a1", "a2"]
+    );
+    assert_eq!(
+        note_texts(&c.chisel, Some(("ChiselUID", "bob"))).await,
+        ["b1"]
+    );
+    assert!(note_texts(&c.chisel, None).await.is_empty());
+    assert_eq!(
+        note_texts(&c.chisel, Some(("X-Chisel-Admin-Secret", ADMIN_SECRET))).await,
+        ["a1", "a2", "b1"]
+    );
+
+    // other users can neither delete nor overwrite the note
+    let id = note["id"].as_str().unwrap();
+    c.chisel
+        .delete(&format!("/dev/notes/{id}"))
+        .header("ChiselUID", "bob")
+        .send()
+        .await;
+    c.chisel
+        .put(&format!("/dev/notes/{id}"))
+        .header("ChiselUID", "bob")
+        .json(json!({"text": "stolen", "userId": "bob"}))
+        .send()
+        .await
+        .assert_status(500);
+    assert_eq!(
+        note_texts(&c.chisel, Some(("ChiselUID", "alice"))).await,
+        ["a1", "a2"]
+    );
+
+    // anonymous users cannot write
+    c.chisel
+        .post("/dev/notes")
+        .json(json!({"text": "anonymous", "userId": "alice"}))
+        .send()
+        .await
+        .assert_status(500);
+
+    // admins can set the owner
+    c.chisel
+        .post("/dev/notes")
+        .header("X-Chisel-Admin-Secret", ADMIN_SECRET)
+        .json(json!({"text": "b2", "userId": "bob"}))
+        .send()
+        .await
+        .assert_status(200);
+    assert_eq!(
+        note_texts(&c.chisel, Some(("ChiselUID", "bob"))).await,
+        ["b1", "b2"]
+    );
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read(r#"@ownedBy("userId")"#);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_owner_field(c: TestContext) {
+    c.chisel.write(
+        "models/note.ts",
+        r#"
+        import { ChiselEntity, ownedBy } from "@chiselstrike/api";
+        @ownedBy("userId")
+        export class Note extends ChiselEntity {
+            userId?: string;
+            text: string;
+        }
+        "#,
+    );
+    c.chisel.apply_err().await.stderr.read(
+        "field `userId` of entity `Note` holds the owner of @ownedBy, so it must be a non-optional string",
+    );
+}
@@ -0,0 +1,179 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(chisel: &Chisel) {
+    chisel.write(
+        "models/models.ts",
+        r#"
+        import { ChiselEntity, Id } from "@chiselstrike/api";
+
+        export class Publisher extends ChiselEntity {
+            name: string;
+        }
+        export class Author extends ChiselEntity {
+            name: string;
+            publisher?: Id<Publisher>;
+        }
+        export class Book extends ChiselEntity {
+            title: string;
+            author: Id<Author>;
+        }
+        export class Employee extends ChiselEntity {
+            name: string;
+            manager?: Id<Employee>;
+        }"#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn nested(c: TestContext) {
+    write_models(&c.chisel);
+    c.chisel.write(
+        "routes/books.ts",
+        r#"
+        import { Book, Author, Publisher } from "../models/models.ts";
+        export default async function (req: Request) {
+            if (req.method == "POST") {
+                const penguin = await Publisher.create({ name: "Penguin" });
+                const kafka = await Author.create({ name: "Kafka", publisher: penguin.id });
+                const orphan = await Author.create({ name: "Orphan" });
+                await Book.create({ title: "The Trial", author: kafka.id });
+                await Book.create({ title: "Lost", author: orphan.id });
+                await Book.create({ title: "Dangling", author: "missing" });
+                return "ok";
+            }
+            const books = await Book.cursor()
+                .with({ author: { publisher: true } })
+                .sortBy("title")
+                .toArray();
+            return books.map((book) => {
+                const author = book.author as unknown as Author | undefined;
+                return [book.title, author?.name, author?.publisher];
+            });
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.post("/dev/books").send().await.assert_ok();
+    let books = c.chisel.get_json("/dev/books").await;
+    assert_eq!(books[0][0], json!("Dangling"));
+    assert_eq!(books[0][1], json!(null));
+    assert_eq!(books[1], json!(["Lost", "Orphan", null]));
+    assert_eq!(books[2][0], json!("The Trial"));
+    assert_eq!(books[2][1], json!("Kafka"));
+    assert_eq!(books[2][2]["name"], json!("Penguin"));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn save_loaded(c: TestContext) {
+    write_models(&c.chisel);
+    c.chisel.write(
+        "routes/rename.ts",
+        r#"
+        import { Book, Author } from "../models/models.ts";
+        export default async function () {
+            const kafka = await Author.create({ name: "Kafka" });
+            await Book.create({ title: "The Trial", author: kafka.id });
+
+            const [book] = await Book.cursor().with({ author: true }).toArray();
+            book.title = "Der Process";
+            await book.save();
+
+            const saved = await Book.findOne({ title: "Der Process" });
+            return saved!.author == kafka.id;
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/rename")
+        .send()
+        .await
+        .assert_json(json!(true));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn self_referential_depth(c: TestContext) {
+    write_models(&c.chisel);
+    c.chisel.write(
+        "routes/chain.ts",
+        r#"
+        import { Employee } from "../models/models.ts";
+        export default async function (req: Request) {
+            if (req.method == "POST") {
+                let manager = undefined;
+                for (const name of ["a", "b", "c", "d", "e", "f", "g"]) {
+                    manager = (await Employee.create({ name, manager })).id;
+                }
+                return "ok";
+            }
+            const depth = Number(new URL(req.url).searchParams.get("depth"));
+            let selection = {};
+            for (let i = 0; i < depth; i++) {
+                selection = { manager: i == 0 ? true : selection };
+            }
+            let g;
+            try {
+                [g] = await Employee.cursor()
+                    .with(selection)
+                    .filter({ name: "g" })
+                    .toArray();
+            } catch (e) {
+                return (e as Error).message;
+            }
+            const names = [];
+            let employee = g as unknown as Employee | undefined;
+            while (employee !== undefined && typeof employee == "object") {
+                names.push(employee.name);
+                employee = employee.manager as unknown as Employee | undefined;
+            }
+            return names.join("");
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.post("/dev/chain").send().await.assert_ok();
+    c.chisel
+        .get("/dev/chain?depth=2")
+        .send()
+        .await
+        .assert_text("gfe");
+    c.chisel
+        .get("/dev/chain?depth=5")
+        .send()
+        .await
+        .assert_text("gfedcb");
+    c.chisel
+        .get("/dev/chain?depth=6")
+        .send()
+        .await
+        .assert_text_contains("with() can load relations at most 5 levels deep");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn unknown_relation(c: TestContext) {
+    write_models(&c.chisel);
+    c.chisel.write(
+        "routes/books.ts",
+        r#"
+        import { Book } from "../models/models.ts";
+        export default async function () {
+            try {
+                await Book.cursor()
+                    .with({ title: true } as Record<string, boolean>)
+                    .toArray();
+                return "loaded";
+            } catch (e) {
+                return (e as Error).message;
+            }
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/books")
+        .send()
+        .await
+        .assert_text_contains("entity `Book` has no relation `title`");
+}
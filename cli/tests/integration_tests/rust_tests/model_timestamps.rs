@@ -0,0 +1,160 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_models(c: &TestContext, with_timestamps: bool) {
+    let (decorator, fields) = if with_timestamps {
+        (
+            "@timestamps",
+            "readonly createdAt: Date; readonly updatedAt: Date;",
+        )
+    } else {
+        ("", "")
+    };
+    c.chisel.write(
+        "models/post.ts",
+        &format!(
+            r#"
+            import {{ ChiselEntity, timestamps }} from "@chiselstrike/api";
+            {decorator}
+            export class Post extends ChiselEntity {{
+                title: string;
+                {fields}
+            }}
+            "#
+        ),
+    );
+    c.chisel.write(
+        "routes/posts.ts",
+        r#"
+        import { Post } from "../models/post.ts";
+        export default Post.crud();
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn maintained_on_writes(c: TestContext) {
+    write_models(&c, true);
+    c.chisel.write(
+        "routes/write.ts",
+        r#"
+        import { Post } from "../models/post.ts";
+        const sleep = () => new Promise((resolve) => setTimeout(resolve, 10));
+        export default async function () {
+            const post = await Post.create({ title: "A" });
+            const createdAt = post.createdAt.getTime();
+            await sleep();
+            post.title = "B";
+            await post.save();
+            await sleep();
+            await Post.create({ title: "C" });
+
+            const stored = (await Post.findOne({ title: "B" }))!;
+            const titles = await Post.cursor()
+                .sortBy("updatedAt", false)
+                .filter((p) => p.createdAt.getTime() > createdAt)
+                .map((p) => p.title)
+                .toArray();
+            return {
+                keepsCreatedAt: stored.createdAt.getTime() === createdAt,
+                setsUpdatedAt: stored.updatedAt.getTime() > createdAt,
+                backfillsObject: post.updatedAt.getTime() === stored.updatedAt.getTime(),
+                titles,
+            };
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    assert_eq!(
+        c.chisel
+            .post_json_response("/dev/write", json!({}))
+            .await
+            .json(),
+        json!({
+            "keepsCreatedAt": true,
+            "setsUpdatedAt": true,
+            "backfillsObject": true,
+            "titles": ["C"],
+        })
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn values_of_clients_are_ignored(c: TestContext) {
+    write_models(&c, true);
+    c.chisel.apply_ok().await;
+
+    let post = c
+        .chisel
+        .post_json_response(
+            "/dev/posts",
+            json!({"title": "A", "createdAt": 0, "updatedAt": 0}),
+        )
+        .await
+        .json();
+    let created_at = post["createdAt"].as_f64().unwrap();
+    assert!(created_at > 0.0);
+    assert_eq!(post["updatedAt"].as_f64().unwrap(), created_at);
+
+    let id = post["id"].as_str().unwrap();
+    c.chisel
+        .patch_json(&format!("/dev/posts/{id}"), json!({"createdAt": 0}))
+        .await;
+    let post = c.chisel.get_json(&format!("/dev/posts/{id}")).await;
+    assert_eq!(post["createdAt"].as_f64().unwrap(), created_at);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn backfilled_on_apply(c: TestContext) {
+    write_models(&c, false);
+    c.chisel.apply_ok().await;
+    let id = c
+        .chisel
+        .post_json_response("/dev/posts", json!({"title": "A"}))
+        .await
+        .json()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    write_models(&c, true);
+    c.chisel
+        .exec("apply", &["--dry-run"])
+        .await
+        .expect("chisel apply --dry-run failed")
+        .stdout
+        .read("set Post.createdAt of the existing rows to the current time");
+    c.chisel.apply_ok().await;
+
+    let post = c.chisel.get_json(&format!("/dev/posts/{id}")).await;
+    assert!(post["createdAt"].as_f64().unwrap() > 0.0);
+    assert_eq!(post["updatedAt"], post["createdAt"]);
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read("@timestamps")
+        .read("readonly createdAt: Date;");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_declaration(c: TestContext) {
+    c.chisel.write(
+        "models/post.ts",
+        r#"
+        import { ChiselEntity, timestamps } from "@chiselstrike/api";
+        @timestamps
+        export class Post extends ChiselEntity {
+            title: string;
+            createdAt?: Date;
+        }
+        "#,
+    );
+    c.chisel.apply_err().await.stderr.read(
+        "field `createdAt` of entity `Post` is set by @timestamps, so it can only be declared as `readonly createdAt: Date`",
+    );
+}
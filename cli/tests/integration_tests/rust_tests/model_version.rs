@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static DOCUMENT_MODEL: &str = r#"
+    import { ChiselEntity, version } from "@chiselstrike/api";
+
+    export class Document extends ChiselEntity {
+        title: string = "";
+        @version revision: number = 0;
+    }
+"#;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn crud_version_conflicts(c: TestContext) {
+    c.chisel
+        .write_unindent("models/document.ts", DOCUMENT_MODEL);
+    c.chisel.write_unindent(
+        "routes/documents.ts",
+        r#"
+        import { Document } from "../models/document.ts";
+        export default Document.crud();
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    let doc = c
+        .chisel
+        .post("/dev/documents")
+        .json(json!({"title": "draft"}))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(doc["revision"], json!(1));
+    let id = doc["id"].as_str().unwrap();
+
+    c.chisel
+        .put(&format!("/dev/documents/{id}"))
+        .json(json!({"title": "first", "revision": 1}))
+        .send()
+        .await
+        .assert_ok()
+        .assert_json(json!({"id": id, "title": "first", "revision": 2}));
+
+    // the revision is out of date
+    c.chisel
+        .put(&format!("/dev/documents/{id}"))
+        .json(json!({"title": "second", "revision": 1}))
+        .send()
+        .await
+        .assert_status(409);
+    c.chisel
+        .patch(&format!("/dev/documents/{id}"))
+        .json(json!({"title": "second", "revision": 1}))
+        .send()
+        .await
+        .assert_status(409);
+
+    // without a revision, PATCH updates the revision that it has read
+    c.chisel
+        .patch(&format!("/dev/documents/{id}"))
+        .json(json!({"title": "third"}))
+        .send()
+        .await
+        .assert_ok()
+        .assert_json(json!({"id": id, "title": "third", "revision": 3}));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn save_stale_entity(c: TestContext) {
+    c.chisel
+        .write_unindent("models/document.ts", DOCUMENT_MODEL);
+    c.chisel.write_unindent(
+        "routes/edit.ts",
+        r#"
+        import { ConflictError } from "@chiselstrike/api";
+        import { Document } from "../models/document.ts";
+
+        export default async function () {
+            const created = await Document.create({ title: "draft" });
+            const first = (await Document.findOne({ id: created.id }))!;
+            const second = (await Document.findOne({ id: created.id }))!;
+
+            first.title = "first";
+            await first.save();
+            try {
+                second.title = "second";
+                await second.save();
+                return "saved";
+            } catch (e) {
+                if (e instanceof ConflictError) {
+                    return `conflict at revision ${first.revision}`;
+                }
+                throw e;
+            }
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/edit")
+        .send()
+        .await
+        .assert_text("conflict at revision 2");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn version_must_be_number(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/document.ts",
+        r#"
+        import { ChiselEntity, version } from "@chiselstrike/api";
+
+        export class Document extends ChiselEntity {
+            @version revision: string = "";
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("field `revision` is marked with @version, so it must be a non-optional number");
+}
@@ -0,0 +1,81 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static ROUTE: &str = r##"
+    export default function() {
+        return "hello from the route";
+    }
+    "##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn serve_modules_by_hash(c: TestContext) {
+    c.chisel.write_unindent("routes/hello.ts", ROUTE);
+    c.chisel.apply_ok().await;
+
+    let modules = c.chisel.get_json("/dev/__chiselstrike/modules").await;
+    let modules = modules.as_array().unwrap();
+    let hello = modules
+        .iter()
+        .find(|module| {
+            module["url"]
+                .as_str()
+                .unwrap()
+                .ends_with("/routes/hello.ts")
+        })
+        .expect("module of the route is not listed");
+    let hash = hello["hash"].as_str().unwrap();
+    assert_eq!(hash.len(), 64);
+
+    let response = c
+        .chisel
+        .get(&format!("/dev/__chiselstrike/modules/{}", hash))
+        .send()
+        .await;
+    response.assert_status(200);
+    assert!(response
+        .header("x-chisel-module-url")
+        .ends_with("/routes/hello.ts"));
+    assert!(response.text().contains("hello from the route"));
+
+    c.chisel
+        .get("/dev/__chiselstrike/modules/0000")
+        .send()
+        .await
+        .assert_status(404);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn pull_modules(c: TestContext) {
+    c.chisel.write_unindent("routes/hello.ts", ROUTE);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec(
+            "modules",
+            &["pull", "--version", "dev", "--out-dir", "pulled"],
+        )
+        .await
+        .expect("chisel modules pull failed")
+        .stdout
+        .read("/routes/hello.ts")
+        .read("Pulled");
+
+    let index = c.chisel.read_to_string("pulled/modules.json");
+    let index: serde_json::Value = serde_json::from_str(&index).unwrap();
+    let hello = index
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|module| {
+            module["url"]
+                .as_str()
+                .unwrap()
+                .ends_with("/routes/hello.ts")
+        })
+        .unwrap();
+    let code = c
+        .chisel
+        .read_to_string(&format!("pulled/{}.js", hello["hash"].as_str().unwrap()));
+    assert!(code.contains("hello from the route"));
+}
@@ -0,0 +1,89 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_books(c: &TestContext) {
+    c.chisel.write(
+        "models/book.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            title: string;
+            pages: number = 0;
+            tags?: string[];
+        }
+    "#,
+    );
+    c.chisel.write(
+        "routes/books.ts",
+        r#"
+        import { Book } from "../models/book.ts";
+        export default Book.crud();
+    "#,
+    );
+    c.chisel.write(
+        "routes/hello.ts",
+        r#"
+        import { RouteMap } from "@chiselstrike/api";
+        export default new RouteMap()
+            .get("/:name", (req) => `Hello ${req.params.get("name")}`);
+    "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn document(c: TestContext) {
+    write_books(&c);
+    c.chisel.apply_ok().await;
+
+    let book_ref = json!({"$ref": "#/components/schemas/dev.Book"});
+    json_is_subset(
+        &c.chisel.get_json("/__openapi.json").await,
+        &json!({
+            "openapi": "3.0.3",
+            "paths": {
+                "/dev/books/{id}": {
+                    "get": {
+                        "parameters": [{"name": "id", "in": "path"}],
+                        "responses": {"200": {"content": {"application/json": {"schema": book_ref}}}},
+                    },
+                    "put": {
+                        "requestBody": {"content": {"application/json": {"schema": book_ref}}},
+                    },
+                },
+                "/dev/hello/{name}": {
+                    "get": {"parameters": [{"name": "name", "in": "path"}]},
+                },
+            },
+            "components": {
+                "schemas": {
+                    "dev.Book": {
+                        "type": "object",
+                        "properties": {
+                            "id": {"type": "string"},
+                            "title": {"type": "string"},
+                            "pages": {"type": "number"},
+                            "tags": {"type": "array", "items": {"type": "string"}},
+                        },
+                        "required": ["title"],
+                    },
+                },
+            },
+        }),
+    )
+    .unwrap();
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn describe_format(c: TestContext) {
+    write_books(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("describe", &["--format", "openapi"])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .read(r#""openapi": "3.0.3""#)
+        .read(r#""/dev/books/{id}""#);
+}
@@ -0,0 +1,54 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+use crate::framework::prelude::*;
+
+#[self::test(modules = Deno)]
+async fn compile_jobs(c: TestContext) {
+    c.chisel.write(
+        "lib/greet.ts",
+        r#"export function greet(name: string) { return `Hello ${name}`; }"#,
+    );
+    for name in ["a", "b", "c"] {
+        c.chisel.write(
+            &format!("routes/{}.ts", name),
+            &format!(
+                r#"
+                import {{ greet }} from "../lib/greet.ts";
+                export default async function () {{
+                    return greet("{}");
+                }}
+                "#,
+                name
+            ),
+        );
+    }
+
+    c.chisel
+        .exec("apply", &["--compile-jobs", "2"])
+        .await
+        .expect("chisel apply failed");
+    for name in ["a", "b", "c"] {
+        assert_eq!(
+            c.chisel.get_text(&format!("/dev/{}", name)).await,
+            format!("Hello {}", name)
+        );
+    }
+
+    // type errors are still reported
+    c.chisel.write(
+        "routes/b.ts",
+        r#"
+        import { greet } from "../lib/greet.ts";
+        export default async function () {
+            return greet(42);
+        }
+        "#,
+    );
+    let mut output = c
+        .chisel
+        .exec("apply", &["--compile-jobs", "2"])
+        .await
+        .expect_err("chisel apply succeeded, but it should have failed");
+    output
+        .stderr
+        .read("Argument of type 'number' is not assignable to parameter of type 'string'");
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static PERSON: &str = r##"
+    import { ChiselEntity, labels } from "@chiselstrike/api";
+
+    export class Person extends ChiselEntity {
+        name: string = "";
+        @labels("pii") email: string = "";
+    }
+    "##;
+
+static PERSONS_ROUTE: &str = r##"
+    import { Person } from "../models/person.ts";
+    export default Person.crud();
+    "##;
+
+#[chisel_macros::test(modules = Deno)]
+async fn policies_only_apply(c: TestContext) {
+    c.chisel.write_unindent("models/person.ts", PERSON);
+    c.chisel.write_unindent("routes/persons.ts", PERSONS_ROUTE);
+    c.chisel.apply_ok().await;
+
+    let resp = c
+        .chisel
+        .post("/dev/persons")
+        .json(json!({"name": "Alice", "email": "alice@example.com"}))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    let id = resp["id"].as_str().unwrap().to_owned();
+    let person = c.chisel.get_json(&format!("/dev/persons/{}", id)).await;
+    assert_eq!(person["email"], json!("alice@example.com"));
+
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: pii
+            transform: anonymize
+        "##,
+    );
+    // this route is not applied, because only the policies are
+    c.chisel.write_unindent(
+        "routes/persons.ts",
+        r##"
+        export default function () {
+            return "changed";
+        }
+        "##,
+    );
+    c.chisel
+        .exec("apply", &["--policies-only"])
+        .await
+        .expect("chisel apply --policies-only failed")
+        .stdout
+        .read("1 labels");
+
+    let person = c.chisel.get_json(&format!("/dev/persons/{}", id)).await;
+    assert_eq!(person["name"], json!("Alice"));
+    assert_eq!(person["email"], json!("xxxxx"));
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn policies_only_apply_needs_running_version(c: TestContext) {
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: pii
+            transform: anonymize
+        "##,
+    );
+    c.chisel
+        .exec("apply", &["--policies-only", "--version", "missing"])
+        .await
+        .expect_err("chisel apply --policies-only succeeded, but the version is not running")
+        .stderr
+        .read("Version `missing` is not running");
+}
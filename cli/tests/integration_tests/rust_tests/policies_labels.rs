@@ -328,3 +328,129 @@ async fn anonymize_and_filter(c: TestContext) {
     )
     .unwrap();
 }
+
+#[self::test(modules = Deno)]
+async fn policy_trace(c: TestContext) {
+    c.chisel.write_unindent("routes/persons.ts", PERSONS_ROUTE);
+    c.chisel
+        .write_unindent("models/person.ts", PERSON_WITH_LABELS);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: L1
+            transform: anonymize
+          - name: pii
+            transform: omit
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    store_person(&c.chisel, &PEKKA).await;
+
+    let response = c
+        .chisel
+        .get("/dev/persons")
+        .header("X-Chisel-Policy-Trace", "1")
+        .send()
+        .await;
+    response.assert_ok();
+    let trace: serde_json::Value =
+        serde_json::from_str(&response.header("x-chisel-policy-trace")).unwrap();
+    assert_eq!(
+        trace["Person"]["transformedFields"],
+        json!(["first_name", "human"])
+    );
+    assert_eq!(trace["Person"]["omittedFields"], json!(["last_name"]));
+
+    c.chisel
+        .get("/dev/persons")
+        .header("X-Chisel-Policy-Trace", "maybe")
+        .send()
+        .await
+        .assert_status(400);
+}
+
+#[self::test(modules = Deno)]
+async fn transform_functions(mut c: TestContext) {
+    c.chisel.write_unindent("routes/persons.ts", PERSONS_ROUTE);
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity, labels } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            @labels("hash") first_name: string;
+            @labels("mask") last_name: string;
+            @labels("bucket") age: number;
+            @labels("nullify") human: boolean;
+            height: number;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: hash
+            transform: hash
+          - name: mask
+            transform:
+              partial_mask: { keep_start: 1, keep_end: 2 }
+          - name: bucket
+            transform:
+              bucket: { size: 100 }
+          - name: nullify
+            transform: nullify
+        "##,
+    );
+    c.chisel
+        .write(".env", r#"{ "CHISEL_ANONYMIZATION_KEY": "k3y" }"#);
+    c.chisel.apply_ok().await;
+    c.restart_chiseld().await;
+
+    let glauber_id = store_person(&c.chisel, &GLAUBER).await;
+    let other_id = store_person(
+        &c.chisel,
+        &json!({
+            "first_name": "Glauber",
+            "last_name": "Other",
+            "age": 42,
+            "human": true,
+            "height": 1
+        }),
+    )
+    .await;
+
+    let glauber = fetch_person(&c.chisel, &glauber_id).await;
+    let hash = glauber["first_name"].as_str().unwrap();
+    assert_eq!(hash.len(), 64);
+    assert_eq!(
+        glauber,
+        json!({
+            "first_name": hash,
+            "last_name": "C**ta",
+            "age": 600,
+            "human": null,
+            "height": 10.01
+        })
+    );
+    // hashes are deterministic, so equal values can still be matched
+    assert_eq!(
+        fetch_person(&c.chisel, &other_id).await["first_name"],
+        hash
+    );
+
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: bucket
+            transform: bucket
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("transform bucket for label bucket requires a size");
+}
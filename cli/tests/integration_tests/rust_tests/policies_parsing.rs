@@ -156,3 +156,26 @@ pub async fn route_unknown_key(c: TestContext) {
         .write("policies/p.yaml", "routes: [{ path: /, 84390232: 0 }]");
     c.chisel.apply_err().await.stderr.read("84390232");
 }
+
+#[chisel_macros::test(modules = Node)]
+pub async fn route_invalid_rate_limit(c: TestContext) {
+    c.chisel.write(
+        "policies/p.yaml",
+        "routes: [{ path: /a, rate_limit: { requests_per_minute: 0 } }]",
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("must allow at least one request per minute");
+
+    c.chisel.write(
+        "policies/p.yaml",
+        "routes: [{ path: /a, rate_limit: { requests_per_minute: 10, key: session } }]",
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("unknown variant `session`");
+}
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static PERSON_MODEL: &str = r#"
+    import { ChiselEntity } from "@chiselstrike/api";
+
+    export class Person extends ChiselEntity {
+        name: string = "";
+    }
+"#;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn cached_queries_are_invalidated(c: TestContext) {
+    c.chisel.write_unindent("models/person.ts", PERSON_MODEL);
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/cached.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function () {
+            const people = await Person.cached({ ttlMs: 60000 }).findMany({});
+            return people.map((p) => p.name).sort();
+        }
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/store_and_read.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function (req: Request) {
+            await Person.create({ name: await req.json() });
+            const people = await Person.cached().findMany({});
+            return people.map((p) => p.name).sort();
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    assert_eq!(c.chisel.get_json("/dev/cached").await, json!([]));
+
+    // committed writes invalidate the cached results
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Alice"}))
+        .await;
+    assert_eq!(c.chisel.get_json("/dev/cached").await, json!(["Alice"]));
+
+    // a transaction always sees its own writes
+    c.chisel
+        .post("/dev/store_and_read")
+        .json(json!("Bob"))
+        .send()
+        .await
+        .assert_json(json!(["Alice", "Bob"]));
+    assert_eq!(
+        c.chisel.get_json("/dev/cached").await,
+        json!(["Alice", "Bob"])
+    );
+
+    c.chisel
+        .delete("/dev/people?.name=Alice")
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(c.chisel.get_json("/dev/cached").await, json!(["Bob"]));
+}
@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static TEST_ROUTE: &str = r##"
+    export default function() {
+        return "ok";
+    }
+    "##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn rate_limited_route(c: TestContext) {
+    c.chisel.write_unindent("routes/limited.ts", TEST_ROUTE);
+    c.chisel.write_unindent("routes/free.ts", TEST_ROUTE);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            routes:
+            - path: /limited
+              rateLimit:
+                requestsPerMinute: 2
+                key: global
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/limited").send().await.assert_text("ok");
+    c.chisel.get("/dev/limited").send().await.assert_text("ok");
+    let response = c.chisel.get("/dev/limited").send().await;
+    response.assert_status(429);
+    assert_eq!(response.header("retry-after"), "30");
+
+    // routes without a limit are not affected
+    for _ in 0..5 {
+        c.chisel.get("/dev/free").send().await.assert_text("ok");
+    }
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn rate_limit_reset_by_apply(c: TestContext) {
+    c.chisel.write_unindent("routes/limited.ts", TEST_ROUTE);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            routes:
+            - path: /limited
+              rate_limit:
+                requests_per_minute: 1
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/limited").send().await.assert_text("ok");
+    c.chisel.get("/dev/limited").send().await.assert_status(429);
+
+    c.chisel.remove_file("policies/pol.yaml");
+    c.chisel.apply_ok().await;
+    c.chisel.get("/dev/limited").send().await.assert_text("ok");
+}
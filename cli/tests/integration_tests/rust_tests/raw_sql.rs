@@ -0,0 +1,152 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_files(c: &TestContext) {
+    c.chisel.write_unindent(
+        "models/book.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            title: string;
+            author: string;
+            pages: number;
+        }
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r#"
+        import { Book } from "../models/book.ts";
+        export default Book.crud();
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/raw.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        import { Book } from "../models/book.ts";
+        export default async function chisel(req: Request) {
+            const { sql, params } = await req.json();
+            return await Chisel.rawQuery(sql.replace("$BOOK", Chisel.table(Book)), params);
+        }
+        "#,
+    );
+}
+
+async fn store_books(c: &TestContext) {
+    for (title, author, pages) in [
+        ("Dune", "Herbert", 412),
+        ("Emma", "Austen", 474),
+        ("Persuasion", "Austen", 249),
+    ] {
+        c.chisel
+            .post_json(
+                "/dev/books",
+                json!({"title": title, "author": author, "pages": pages}),
+            )
+            .await;
+    }
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--allow-raw-sql"])]
+pub async fn query_and_update(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+    store_books(&c).await;
+
+    let rows = c
+        .chisel
+        .post_json_response(
+            "/dev/raw",
+            json!({
+                "sql": "SELECT author, count(*) AS books FROM $BOOK WHERE pages > $1 GROUP BY author ORDER BY author",
+                "params": [300],
+            }),
+        )
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(
+        rows,
+        json!([{"author": "Austen", "books": 1}, {"author": "Herbert", "books": 1}])
+    );
+
+    c.chisel
+        .post_json_response(
+            "/dev/raw",
+            json!({"sql": "UPDATE $BOOK SET pages = pages + 1 WHERE author = $1", "params": ["Austen"]}),
+        )
+        .await
+        .assert_ok();
+    let books = c.chisel.get_json("/dev/books?sort=title").await;
+    let pages = books["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|book| book["pages"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(pages, vec![json!(412), json!(475), json!(250)]);
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--allow-raw-sql"])]
+pub async fn other_tables_are_blocked(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/raw")
+        .json(json!({"sql": "SELECT * FROM secrets"}))
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains(
+            "raw SQL queries cannot use `secrets`, which is not a table of this version",
+        );
+    c.chisel
+        .post("/dev/raw")
+        .json(json!({"sql": "DROP TABLE $BOOK"}))
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("raw SQL queries can only read and write data");
+    c.chisel
+        .post("/dev/raw")
+        .json(json!({"sql": "SELECT $$x$$ FROM $BOOK"}))
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("raw SQL queries cannot use dollar-quoted strings");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn disabled_by_default(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/raw")
+        .json(json!({"sql": "SELECT 1 AS one"}))
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("available only when chiseld runs with --allow-raw-sql");
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--allow-raw-sql", "--tenant-header", "X-Tenant", "--trust-tenant-header"]
+)]
+pub async fn disabled_with_tenancy(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/raw")
+        .header("X-Tenant", "acme")
+        .json(json!({"sql": "SELECT 1 AS one"}))
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("not available when the data is isolated per tenant");
+}
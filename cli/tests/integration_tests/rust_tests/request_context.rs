@@ -0,0 +1,133 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_route(c: &TestContext) {
+    c.chisel.write(
+        "routes/ctx.ts",
+        r#"
+        import { ChiselContext, ChiselRequest, RouteMap } from "@chiselstrike/api";
+        export default new RouteMap()
+            .middleware(async (req, next, ctx) => {
+                const res = await next(req);
+                res.headers.set("x-request-id", ctx.requestId);
+                return res;
+            })
+            .get("/", (req: ChiselRequest, ctx: ChiselContext) => ({
+                versionId: ctx.versionId,
+                requestId: ctx.requestId,
+                clientIp: ctx.clientIp,
+                userId: ctx.userId ?? null,
+                roles: ctx.roles,
+                admin: ctx.hasRole("admin"),
+                sameAsRequest: req.ctx === ctx,
+                traceHeaders: ctx.traceHeaders()["x-trace-id"],
+            }));
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn handler_receives_ctx(c: TestContext) {
+    write_route(&c);
+    c.chisel.apply_ok().await;
+
+    let response = c
+        .chisel
+        .get("/dev/ctx")
+        .header("ChiselUID", "alice")
+        .header("x-trace-id", "trace-123")
+        .send()
+        .await;
+    response.assert_status(200);
+    assert_eq!(response.header("x-request-id"), "trace-123");
+    assert_eq!(
+        response.json(),
+        json!({
+            "versionId": "dev",
+            "requestId": "trace-123",
+            "clientIp": "127.0.0.1",
+            "userId": "alice",
+            "roles": [],
+            "admin": false,
+            "sameAsRequest": true,
+            "traceHeaders": "trace-123",
+        })
+    );
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--typescript-policies"])]
+pub async fn roles_from_auth_hook(c: TestContext) {
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        events = ["events"]
+        policies = ["policies"]
+        auth_hook = "policies/auth.ts"
+        "#,
+    );
+    c.chisel.write(
+        "policies/auth.ts",
+        r#"
+        import type { AuthHook } from "@chiselstrike/api";
+        const authHook: AuthHook = (req: Request) => {
+            const user = req.headers.get("x-user");
+            if (user === null) {
+                return null;
+            }
+            return { userId: user, claims: { roles: user == "root" ? ["admin"] : [] } };
+        };
+        export default authHook;
+        "#,
+    );
+    c.chisel.write(
+        "models/secret.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Secret extends ChiselEntity {
+            text: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "policies/Secret.ts",
+        r#"
+        export default {
+            read: (secret, ctx) => ctx.roles.includes("admin") ? Action.Allow : Action.Skip,
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/secrets.ts",
+        r#"
+        import { ChiselContext, ChiselRequest } from "@chiselstrike/api";
+        import { Secret } from "../models/secret.ts";
+        export default async function (req: ChiselRequest, ctx: ChiselContext) {
+            if (req.method == "POST") {
+                await Secret.create({ text: "s" });
+                return "ok";
+            }
+            const texts = (await Secret.findMany({})).map((secret) => secret.text);
+            return { admin: ctx.hasRole("admin"), userId: ctx.userId, texts };
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.post("/dev/secrets").send().await.assert_ok();
+
+    let read_as = |user: &'static str| {
+        let request = c.chisel.get("/dev/secrets").header("x-user", user);
+        async move { request.send().await.assert_ok().json() }
+    };
+    assert_eq!(
+        read_as("root").await,
+        json!({"admin": true, "userId": "root", "texts": ["s"]})
+    );
+    assert_eq!(
+        read_as("alice").await,
+        json!({"admin": false, "userId": "alice", "texts": []})
+    );
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Address of the fake log sink, which must match `--request-log-endpoint` below.
+const SINK_ADDR: &str = "127.0.0.1:43188";
+
+/// Starts a fake HTTP sink that accepts all requests and stores their raw bytes.
+async fn spawn_sink() -> Arc<Mutex<Vec<u8>>> {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind(SINK_ADDR).await.unwrap();
+    let received_clone = received.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let received = received_clone.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            received.lock().unwrap().extend_from_slice(&buf[..n]);
+                            let _ = socket
+                                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                                .await;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    received
+}
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle.as_bytes())
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = [
+    "--request-log-endpoint", "http://127.0.0.1:43188/logs",
+    "--request-log-sample-rate", "0",
+    "--request-log-version-sample-rate", "dev=1",
+    "--request-log-flush-interval-ms", "100",
+])]
+pub async fn sampled_per_version(c: TestContext) {
+    let received = spawn_sink().await;
+    c.chisel.write(
+        "routes/hello.ts",
+        r#"export default function () { return new Response("hello"); }"#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/hello").send().await.assert_text("hello");
+    c.chisel.get("/dev/missing").send().await.assert_status(404);
+    // this request is not in the `dev` version, so it is sampled with rate 0
+    c.chisel.get("/__openapi.json").send().await.assert_ok();
+
+    let expected = [
+        r#""path":"/dev/hello""#,
+        r#""status":200"#,
+        r#""path":"/dev/missing""#,
+        r#""status":404"#,
+        r#""versionId":"dev""#,
+    ];
+    let mut exported = false;
+    for _ in 0..60 {
+        {
+            let received = received.lock().unwrap();
+            if expected.iter().all(|needle| contains(&received, needle)) {
+                exported = true;
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(exported, "request logs were not exported to the sink");
+    assert!(!contains(&received.lock().unwrap(), "__openapi.json"));
+}
@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+/// Address of a server that accepts connections but never responds.
+const BLACKHOLE_ADDR: &str = "127.0.0.1:43188";
+
+async fn spawn_blackhole() {
+    let listener = tokio::net::TcpListener::bind(BLACKHOLE_ADDR).await.unwrap();
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--request-timeout-s", "1"])]
+pub async fn deadline_aborts_fetch(mut c: TestContext) {
+    spawn_blackhole().await;
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        &format!(
+            r#"
+            models = ["models"]
+            routes = ["routes"]
+            events = ["events"]
+            policies = ["policies"]
+            allowed_hosts = ["{}"]
+            "#,
+            BLACKHOLE_ADDR
+        ),
+    );
+    c.chisel.write(
+        "routes/wait.ts",
+        &format!(
+            r#"
+            export default async function () {{
+                try {{
+                    await fetch("http://{}/");
+                }} catch (e) {{
+                    console.log(`fetch failed with ${{e.name}}`);
+                }}
+                return "done";
+            }}
+            "#,
+            BLACKHOLE_ADDR
+        ),
+    );
+    c.chisel
+        .write("routes/fast.ts", r#"export default () => "fast";"#);
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/wait").send().await.assert_status(504);
+    c.chiseld.stderr.read("fetch failed with AbortError").await;
+
+    // the worker is free to handle other requests
+    assert_eq!(c.chisel.get_text("/dev/fast").await, "fast");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn no_deadline_by_default(c: TestContext) {
+    c.chisel.write(
+        "routes/slow.ts",
+        r#"
+        export default async function () {
+            await new Promise((resolve) => setTimeout(resolve, 1500));
+            return "slow";
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    assert_eq!(c.chisel.get_text("/dev/slow").await, "slow");
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--request-timeout-s", "1"])]
+pub async fn deadline_terminates_spinning_handler(mut c: TestContext) {
+    c.chisel.write(
+        "routes/spin.ts",
+        r#"
+        export default function () {
+            for (;;) {}
+        }
+        "#,
+    );
+    c.chisel
+        .write("routes/fast.ts", r#"export default () => "fast";"#);
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/spin").send().await.assert_status(504);
+    c.chiseld.stderr.read("was terminated").await;
+
+    // the worker is restarted and handles other requests
+    assert_eq!(c.chisel.get_text("/dev/fast").await, "fast");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn route_timeout(mut c: TestContext) {
+    c.chisel.write(
+        "routes/limited.ts",
+        r#"
+        import { ChiselRequest, RouteMap } from "@chiselstrike/api";
+        async function handler(req: ChiselRequest) {
+            req.signal.addEventListener("abort", () => {
+                console.log(`signal aborted with ${req.signal.reason.name}`);
+            });
+            await new Promise((resolve) => setTimeout(resolve, 2000));
+            return "limited";
+        }
+        export default new RouteMap().get("/", handler).timeout(0.5);
+        "#,
+    );
+    c.chisel.write(
+        "routes/slow.ts",
+        r#"
+        export default async function () {
+            await new Promise((resolve) => setTimeout(resolve, 1000));
+            return "slow";
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/limited").send().await.assert_status(504);
+    c.chiseld
+        .stderr
+        .read("signal aborted with AbortError")
+        .await;
+    // other routes have no deadline
+    assert_eq!(c.chisel.get_text("/dev/slow").await, "slow");
+}
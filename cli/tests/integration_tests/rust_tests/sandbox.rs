@@ -0,0 +1,64 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static POST_MODEL: &str = r#"
+    import { ChiselEntity } from "@chiselstrike/api";
+    export class Post extends ChiselEntity {
+        title: string = "";
+    }
+"#;
+
+static POSTS_CRUD: &str = r#"
+    import { Post } from "../models/post.ts";
+    export default Post.crud();
+"#;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn sandbox_rolls_back_writes(c: TestContext) {
+    c.chisel.write("models/post.ts", POST_MODEL);
+    c.chisel.write("routes/posts.ts", POSTS_CRUD);
+    c.chisel.apply_ok().await;
+
+    let response = c
+        .chisel
+        .post("/dev/posts")
+        .header("X-Chisel-Sandbox", "1")
+        .json(json!({"title": "Sandboxed"}))
+        .send()
+        .await;
+    response.assert_ok();
+    assert_eq!(response.header("x-chisel-sandbox"), "1");
+    assert_eq!(response.json()["title"], json!("Sandboxed"));
+
+    c.chisel
+        .post("/dev/posts")
+        .header("X-Chisel-Sandbox", "0")
+        .json(json!({"title": "Committed"}))
+        .send()
+        .await
+        .assert_ok();
+
+    let titles = c.chisel.get_json("/dev/posts").await["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|post| post["title"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, vec![json!("Committed")]);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn sandbox_invalid_header(c: TestContext) {
+    c.chisel.write("models/post.ts", POST_MODEL);
+    c.chisel.write("routes/posts.ts", POSTS_CRUD);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/posts")
+        .header("X-Chisel-Sandbox", "maybe")
+        .json(json!({"title": "Sandboxed"}))
+        .send()
+        .await
+        .assert_status(400);
+}
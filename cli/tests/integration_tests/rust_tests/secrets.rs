@@ -117,3 +117,56 @@ pub async fn load_secret_out_of_function(mut c: TestContext) {
     c.restart_chiseld().await;
     assert_eq!(c.chisel.get_text("/dev/secret").await, "728 is fixed");
 }
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn rotate_secret(mut c: TestContext) {
+    setup_secret_endpoint(&c.chisel).await;
+    c.chisel.write(".env", r##"{"secret": "from file"}"##);
+    c.restart_chiseld().await;
+    assert_eq!(c.chisel.get_json("/dev/secret").await, json!("from file"));
+
+    // secrets from the database override the file, without a restart
+    c.chisel
+        .exec("secrets", &["set", "secret", "first"])
+        .await
+        .expect("chisel secrets set failed")
+        .stdout
+        .read("secret (version 1, set at");
+    assert_eq!(c.chisel.get_json("/dev/secret").await, json!("first"));
+
+    c.chisel
+        .exec("secrets", &["set", "secret", r#"{"key": 1}"#, "--json"])
+        .await
+        .expect("chisel secrets set failed")
+        .stdout
+        .read("secret (version 2, set at");
+    assert_eq!(c.chisel.get_json("/dev/secret").await, json!({"key": 1}));
+
+    c.chisel
+        .exec("secrets", &["unset", "secret"])
+        .await
+        .expect("chisel secrets unset failed")
+        .stdout
+        .read("secret (version 3, unset, using secrets file at");
+    assert_eq!(c.chisel.get_json("/dev/secret").await, json!("from file"));
+
+    c.chisel
+        .exec("secrets", &["set", "other", "value"])
+        .await
+        .expect("chisel secrets set failed");
+    c.chisel
+        .exec("secrets", &["list"])
+        .await
+        .expect("chisel secrets list failed")
+        .stdout
+        .read("other (version 1, set at")
+        .read("secret (version 3, unset, using secrets file at");
+
+    // stored secrets survive a restart
+    c.restart_chiseld().await;
+    c.chisel
+        .exec("secrets", &["set", "secret", "second"])
+        .await
+        .expect("chisel secrets set failed");
+    assert_eq!(c.chisel.get_json("/dev/secret").await, json!("second"));
+}
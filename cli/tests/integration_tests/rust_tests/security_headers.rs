@@ -0,0 +1,103 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static POLICY: &str = r##"
+    security_headers:
+      hsts:
+        max_age: 31536000
+        include_subdomains: true
+      content_type_options: true
+      frame_options: DENY
+      custom:
+        X-Powered-By: ChiselStrike
+    "##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn headers_added_to_responses(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/plain.ts",
+        r##"
+        export default function() {
+            return "ok";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/custom.ts",
+        r##"
+        export default function() {
+            return new Response("ok", { headers: { "x-frame-options": "SAMEORIGIN" } });
+        }
+        "##,
+    );
+    c.chisel.write_unindent("policies/pol.yaml", POLICY);
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/plain").send().await;
+    response.assert_status(200);
+    assert_eq!(
+        response.header("strict-transport-security"),
+        "max-age=31536000; includeSubDomains"
+    );
+    assert_eq!(response.header("x-content-type-options"), "nosniff");
+    assert_eq!(response.header("x-frame-options"), "DENY");
+    assert_eq!(response.header("x-powered-by"), "ChiselStrike");
+
+    // headers from the handler are not replaced
+    let response = c.chisel.get("/dev/custom").send().await;
+    assert_eq!(response.header("x-frame-options"), "SAMEORIGIN");
+    assert_eq!(response.header("x-content-type-options"), "nosniff");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn route_overrides(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/embed.ts",
+        r##"
+        import { RouteMap } from "@chiselstrike/api";
+
+        const inner = new RouteMap()
+            .get("/", () => "inner")
+            .securityHeaders({ "X-Powered-By": "inner" });
+
+        export default new RouteMap()
+            .get("/", () => "embed")
+            .prefix("/inner", inner)
+            .securityHeaders({ "X-Frame-Options": null, "X-Powered-By": "embed" });
+        "##,
+    );
+    c.chisel.write_unindent("policies/pol.yaml", POLICY);
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/embed").send().await;
+    response.assert_status(200);
+    assert!(!response.has_header("x-frame-options"));
+    assert_eq!(response.header("x-powered-by"), "embed");
+    assert_eq!(response.header("x-content-type-options"), "nosniff");
+
+    let response = c.chisel.get("/dev/embed/inner").send().await;
+    assert!(!response.has_header("x-frame-options"));
+    assert_eq!(response.header("x-powered-by"), "inner");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_headers(c: TestContext) {
+    c.chisel.write(
+        "policies/p.yaml",
+        "security_headers: { custom: { \"bad header\": x } }",
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("Invalid security header name");
+
+    c.chisel
+        .write("policies/p.yaml", "security_headers: { hsts: { age: 10 } }");
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("unknown field `age`");
+}
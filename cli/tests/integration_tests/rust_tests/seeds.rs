@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_files(c: &TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function chisel(req: Request) {
+            const people = await Person.findMany({});
+            return people.map((person) => person.name).sort();
+        }
+        "#,
+    );
+    c.chisel.write(
+        "seeds/001_people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function () {
+            await Person.create({ name: "alice" });
+            await Person.create({ name: "bob" });
+        }
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn seeds_run_once(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("seed", &[])
+        .await
+        .expect("chisel seed failed")
+        .stdout
+        .read("Applied seed 001_people");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice", "bob"])
+    );
+
+    c.chisel
+        .exec("seed", &[])
+        .await
+        .expect("chisel seed failed")
+        .stdout
+        .read("Skipped seed 001_people (already applied)");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice", "bob"])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn failed_seed_runs_again(c: TestContext) {
+    write_files(&c);
+    c.chisel.write(
+        "seeds/002_more_people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function () {
+            await Person.create({ name: "carol" });
+            throw new Error("seed failure");
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    let mut output = c
+        .chisel
+        .exec("seed", &[])
+        .await
+        .expect_err("chisel seed should fail");
+    output.stdout.read("Applied seed 001_people");
+    output.stderr.read("seed failure");
+    // the failed seed is rolled back
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice", "bob"])
+    );
+
+    c.chisel.write(
+        "seeds/002_more_people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function () {
+            await Person.create({ name: "carol" });
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("seed", &[])
+        .await
+        .expect("chisel seed failed")
+        .stdout
+        .read("Skipped seed 001_people (already applied)")
+        .read("Applied seed 002_more_people");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice", "bob", "carol"])
+    );
+}
@@ -0,0 +1,90 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+
+use crate::framework::prelude::*;
+
+fn write_chat(c: &TestContext) {
+    c.chisel.write(
+        "models/message.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Message extends ChiselEntity {
+            text: string = "";
+        }
+    "#,
+    );
+    c.chisel.write(
+        "routes/messages.ts",
+        r#"
+        import { Message } from "../models/message.ts";
+        export default Message.crud();
+    "#,
+    );
+    c.chisel.write(
+        "routes/chat.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        import { Message } from "../models/message.ts";
+
+        export default Chisel.socket({
+            open(socket) {
+                socket.send("welcome");
+            },
+            async message(socket, data) {
+                if (data === "bye") {
+                    socket.close(1000, "bye");
+                    return;
+                }
+                await Message.create({ text: String(data) });
+                socket.send(`echo ${data}`);
+            },
+        });
+    "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn echo(c: TestContext) {
+    write_chat(&c);
+    c.chisel.apply_ok().await;
+
+    let url = format!("ws://{}/dev/chat", c.chisel.api_address);
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        WsMessage::Text("welcome".into())
+    );
+
+    ws.send(WsMessage::Text("hello".into())).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        WsMessage::Text("echo hello".into())
+    );
+
+    ws.send(WsMessage::Text("bye".into())).await.unwrap();
+    match ws.next().await.unwrap().unwrap() {
+        WsMessage::Close(Some(frame)) => assert_eq!(frame.reason, "bye"),
+        msg => panic!("Expected a close frame, got {:?}", msg),
+    }
+
+    json_is_subset(
+        &c.chisel.get_json("/dev/messages").await,
+        &json!({"results": [{"text": "hello"}]}),
+    )
+    .unwrap();
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn no_socket_route(c: TestContext) {
+    write_chat(&c);
+    c.chisel.apply_ok().await;
+
+    let url = format!("ws://{}/dev/messages", c.chisel.api_address);
+    match tokio_tungstenite::connect_async(url).await {
+        Err(WsError::Http(response)) => assert_eq!(response.status(), 404),
+        Err(err) => panic!("Expected HTTP error, got {:?}", err),
+        Ok(_) => panic!("Socket was opened on a route that does not accept sockets"),
+    }
+}
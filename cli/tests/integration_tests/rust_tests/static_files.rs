@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn serve_static_files(mut c: TestContext) {
+    c.chisel.write("static/index.html", "<h1>Hello</h1>");
+    c.chisel.write("static/css/app.css", "h1 { color: red; }");
+    c.chisel.write(
+        "routes/hello.ts",
+        r#"export default () => "hello from a route";"#,
+    );
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/static").send().await;
+    response.assert_status(200).assert_text("<h1>Hello</h1>");
+    assert_eq!(response.header("content-type"), "text/html; charset=utf-8");
+    assert_eq!(response.header("cache-control"), "no-cache");
+    let etag = response.header("etag");
+
+    // the file is revalidated with its ETag
+    c.chisel
+        .get("/dev/static/index.html")
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .assert_status(304);
+
+    c.chisel
+        .get("/dev/static/css/app.css")
+        .send()
+        .await
+        .assert_status(200)
+        .assert_text("h1 { color: red; }");
+    c.chisel
+        .get("/dev/static/missing.js")
+        .send()
+        .await
+        .assert_status(404);
+    c.chisel
+        .post("/dev/static/index.html")
+        .send()
+        .await
+        .assert_status(405);
+    c.chisel
+        .get("/dev/hello")
+        .send()
+        .await
+        .assert_text("hello from a route");
+
+    // the files are stored with the version
+    c.restart_chiseld().await;
+    c.chisel
+        .get("/dev/static/css/app.css")
+        .send()
+        .await
+        .assert_text("h1 { color: red; }");
+
+    // a changed file gets a new ETag, removed files are no longer served
+    c.chisel.write("static/index.html", "<h1>Hello again</h1>");
+    c.chisel.remove_file("static/css/app.css");
+    c.chisel.apply_ok().await;
+    c.chisel
+        .get("/dev/static")
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .assert_status(200)
+        .assert_text("<h1>Hello again</h1>");
+    c.chisel
+        .get("/dev/static/css/app.css")
+        .send()
+        .await
+        .assert_status(404);
+}
@@ -0,0 +1,116 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Address of the fake OTLP collector, which must match `--otlp-endpoint` below.
+const COLLECTOR_ADDR: &str = "127.0.0.1:43187";
+
+/// Starts a fake OTLP/HTTP collector that accepts all requests and stores their raw bodies. The
+/// spans are encoded with protobuf, which stores strings verbatim, so we can look for span names
+/// in the received bytes.
+async fn spawn_collector() -> Arc<Mutex<Vec<u8>>> {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind(COLLECTOR_ADDR).await.unwrap();
+    let received_clone = received.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let received = received_clone.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            received.lock().unwrap().extend_from_slice(&buf[..n]);
+                            let _ = socket
+                                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                                .await;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    received
+}
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle.as_bytes())
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--otlp-endpoint", "http://127.0.0.1:43187/v1/traces"])]
+pub async fn spans_are_exported(c: TestContext) {
+    let received = spawn_collector().await;
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/people")
+        .header(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        )
+        .json(json!({"name": "Alice"}))
+        .send()
+        .await
+        .assert_ok();
+    c.chisel.get("/dev/people").send().await.assert_ok();
+
+    // spans are exported in batches every few seconds
+    let expected = [
+        "HTTP POST",
+        "HTTP GET",
+        "worker dispatch",
+        "SQL insert",
+        "SQL select",
+    ];
+    let mut exported = false;
+    for _ in 0..60 {
+        {
+            let received = received.lock().unwrap();
+            if expected.iter().all(|name| contains(&received, name)) {
+                exported = true;
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    assert!(exported, "spans were not exported to the collector");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn traceparent_without_exporter(c: TestContext) {
+    c.chisel.write(
+        "routes/hello.ts",
+        r#"export default function () { return new Response("hello"); }"#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .get("/dev/hello")
+        .header(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        )
+        .send()
+        .await
+        .assert_text("hello");
+}
@@ -0,0 +1,388 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use crate::framework::Chisel;
+
+const ADMIN_SECRET: &str = "s3cret";
+
+fn write_models(c: &TestContext) {
+    c.chisel.write(
+        "models/note.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Note extends ChiselEntity {
+            text: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/notes.ts",
+        r#"
+        import { Note } from "../models/note.ts";
+        export default Note.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/tenant.ts",
+        r#"
+        import { ChiselRequest, ChiselContext } from "@chiselstrike/api";
+        import { Note } from "../models/note.ts";
+        export default async function (req: ChiselRequest, ctx: ChiselContext) {
+            const count = await Note.cursor().count();
+            return { tenant: ctx.tenantId ?? null, count };
+        }
+        "#,
+    );
+}
+
+async fn post_note(chisel: &Chisel, tenant: &str, text: &str) -> serde_json::Value {
+    chisel
+        .post("/dev/notes")
+        .header("X-Tenant", tenant)
+        .json(json!({ "text": text }))
+        .send()
+        .await
+        .assert_status(200)
+        .json()
+}
+
+async fn note_texts(chisel: &Chisel, header: Option<(&str, &str)>) -> Vec<String> {
+    let mut request = chisel.get("/dev/notes");
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    let mut texts = request.send().await.assert_status(200).json()["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|note| note["text"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    texts.sort();
+    texts
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--tenant-header", "X-Tenant", "--trust-tenant-header"]
+)]
+pub async fn isolated_per_tenant(mut c: TestContext) {
+    write_models(&c);
+    c.chisel.write(
+        ".env",
+        &format!(r#"{{ "CHISEL_ADMIN_SECRET": "{ADMIN_SECRET}" }}"#),
+    );
+    c.chisel.apply_ok().await;
+    c.restart_chiseld().await;
+
+    let note = post_note(&c.chisel, "acme", "a1").await;
+    post_note(&c.chisel, "acme", "a2").await;
+    post_note(&c.chisel, "globex", "g1").await;
+
+    assert_eq!(
+        note_texts(&c.chisel, Some(("X-Tenant", "acme"))).await,
+        ["a1", "a2"]
+    );
+    assert_eq!(
+        note_texts(&c.chisel, Some(("X-Tenant", "globex"))).await,
+        ["g1"]
+    );
+    assert!(note_texts(&c.chisel, Some(("X-Tenant", "initech")))
+        .await
+        .is_empty());
+    assert!(note_texts(&c.chisel, None).await.is_empty());
+    assert_eq!(
+        note_texts(&c.chisel, Some(("X-Chisel-Admin-Secret", ADMIN_SECRET))).await,
+        ["a1", "a2", "g1"]
+    );
+
+    // the handlers see the tenant, and their queries are filtered too
+    c.chisel
+        .get("/dev/tenant")
+        .header("X-Tenant", "acme")
+        .send()
+        .await
+        .assert_json(json!({"tenant": "acme", "count": 2}));
+
+    // other tenants can neither read, delete nor overwrite the note
+    let id = note["id"].as_str().unwrap();
+    c.chisel
+        .get(&format!("/dev/notes/{id}"))
+        .header("X-Tenant", "globex")
+        .send()
+        .await
+        .assert_status(404);
+    c.chisel
+        .delete(&format!("/dev/notes/{id}"))
+        .header("X-Tenant", "globex")
+        .send()
+        .await;
+    c.chisel
+        .put(&format!("/dev/notes/{id}"))
+        .header("X-Tenant", "globex")
+        .json(json!({"text": "stolen"}))
+        .send()
+        .await
+        .assert_status(500);
+    assert_eq!(
+        note_texts(&c.chisel, Some(("X-Tenant", "acme"))).await,
+        ["a1", "a2"]
+    );
+
+    // requests without a tenant cannot write
+    c.chisel
+        .post("/dev/notes")
+        .json(json!({"text": "nobody"}))
+        .send()
+        .await
+        .assert_status(500);
+
+    // the tenant column is not a field of the entity
+    assert_eq!(note.as_object().unwrap().len(), 2);
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--tenant-header", "X-Tenant", "--trust-tenant-header"]
+)]
+pub async fn tenant_column_is_reserved(c: TestContext) {
+    c.chisel.write(
+        "models/note.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Note extends ChiselEntity {
+            tenant_id: string;
+        }
+        "#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("clashes with the column that stores the tenant");
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--tenant-claim", "org", "--tenant-header", "X-Tenant"]
+)]
+pub async fn tenant_from_claim(c: TestContext) {
+    write_models(&c);
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        events = ["events"]
+        policies = ["policies"]
+        auth_hook = "policies/auth.ts"
+        "#,
+    );
+    c.chisel.write(
+        "policies/auth.ts",
+        r#"
+        import type { AuthHook } from "@chiselstrike/api";
+        const authHook: AuthHook = (req: Request) => {
+            const user = req.headers.get("x-user");
+            if (user === null) {
+                return null;
+            }
+            return { userId: user, claims: { org: user == "alice" ? "acme" : "globex" } };
+        };
+        export default authHook;
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/notes")
+        .header("X-User", "alice")
+        .json(json!({ "text": "a1" }))
+        .send()
+        .await
+        .assert_status(200);
+
+    // the tenant comes from the claim of the user, not from the header
+    assert_eq!(note_texts(&c.chisel, Some(("X-User", "alice"))).await, ["a1"]);
+    assert!(note_texts(&c.chisel, Some(("X-User", "bob")))
+        .await
+        .is_empty());
+    c.chisel
+        .get("/dev/tenant")
+        .header("X-User", "alice")
+        .send()
+        .await
+        .assert_json(json!({"tenant": "acme", "count": 1}));
+
+    // a header that names another tenant than the user is rejected
+    c.chisel
+        .get("/dev/notes")
+        .header("X-User", "bob")
+        .header("X-Tenant", "acme")
+        .send()
+        .await
+        .assert_status(403);
+    c.chisel
+        .get("/dev/notes")
+        .header("X-Tenant", "acme")
+        .send()
+        .await
+        .assert_status(403);
+    c.chisel
+        .get("/dev/notes")
+        .header("X-User", "alice")
+        .header("X-Tenant", "acme")
+        .send()
+        .await
+        .assert_status(200);
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--tenant-header", "X-Tenant", "--trust-tenant-header"]
+)]
+pub async fn on_delete_stays_in_tenant(c: TestContext) {
+    c.chisel.write(
+        "models/models.ts",
+        r#"
+        import { ChiselEntity, Id, onDelete } from "@chiselstrike/api";
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+        export class Post extends ChiselEntity {
+            title: string;
+            @onDelete("cascade") author: Id<Author>;
+        }
+        export class Review extends ChiselEntity {
+            text: string;
+            @onDelete("setNull") author?: Id<Author>;
+        }
+        "#,
+    );
+    for (route, model) in [("authors", "Author"), ("posts", "Post"), ("reviews", "Review")] {
+        c.chisel.write(
+            &format!("routes/{route}.ts"),
+            &format!(
+                r#"
+                import {{ {model} }} from "../models/models.ts";
+                export default {model}.crud();
+                "#
+            ),
+        );
+    }
+    c.chisel.apply_ok().await;
+
+    let post = |tenant: &'static str, url: &'static str, data: serde_json::Value| {
+        let chisel = &c.chisel;
+        async move {
+            chisel
+                .post(url)
+                .header("X-Tenant", tenant)
+                .json(data)
+                .send()
+                .await
+                .assert_status(200)
+                .json()["id"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        }
+    };
+    let alice = post("acme", "/dev/authors", json!({"name": "Alice"})).await;
+    post("acme", "/dev/posts", json!({"title": "A", "author": alice})).await;
+    // another tenant refers to the author of acme
+    post("globex", "/dev/posts", json!({"title": "G", "author": alice})).await;
+    post("globex", "/dev/reviews", json!({"text": "g", "author": alice})).await;
+
+    c.chisel
+        .delete(&format!("/dev/authors/{alice}"))
+        .header("X-Tenant", "acme")
+        .send()
+        .await
+        .assert_ok();
+
+    // the cascade and the setNull only follow the references of acme
+    let results = |tenant: &'static str, url: &'static str| {
+        let chisel = &c.chisel;
+        async move {
+            chisel
+                .get(url)
+                .header("X-Tenant", tenant)
+                .send()
+                .await
+                .assert_status(200)
+                .json()["results"]
+                .clone()
+        }
+    };
+    assert_eq!(results("acme", "/dev/posts").await, json!([]));
+    json_is_subset(
+        &results("globex", "/dev/posts").await,
+        &json!([{"title": "G", "author": alice}]),
+    )
+    .unwrap();
+    json_is_subset(
+        &results("globex", "/dev/reviews").await,
+        &json!([{"text": "g", "author": alice}]),
+    )
+    .unwrap();
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--tenant-header", "X-Tenant", "--trust-tenant-header", "--blob-dir", "blobs"]
+)]
+pub async fn stores_isolated_per_tenant(c: TestContext) {
+    c.chisel.write(
+        "routes/stores.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        export default async function chisel(req: Request) {
+            const cmd = await req.json();
+            if (cmd.op === "set") {
+                await Chisel.kv.set(cmd.key, cmd.value);
+                await Chisel.blob.put("files", cmd.key, cmd.value);
+                return { result: null };
+            }
+            const entry = await Chisel.kv.get(cmd.key);
+            const blob = await Chisel.blob.get("files", cmd.key);
+            const keys = (await Chisel.kv.list("")).map((entry) => entry.key);
+            return {
+                kv: entry?.value ?? null,
+                blob: blob === undefined ? null : new TextDecoder().decode(blob.data),
+                keys,
+            };
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    let stores = |tenant: Option<&'static str>, cmd: serde_json::Value| {
+        let chisel = &c.chisel;
+        async move {
+            let mut request = chisel.post("/dev/stores").json(cmd);
+            if let Some(tenant) = tenant {
+                request = request.header("X-Tenant", tenant);
+            }
+            request.send().await
+        }
+    };
+    stores(
+        Some("acme"),
+        json!({"op": "set", "key": "plan", "value": "gold"}),
+    )
+    .await
+    .assert_ok();
+
+    stores(Some("acme"), json!({"op": "get", "key": "plan"}))
+        .await
+        .assert_json(json!({"kv": "gold", "blob": "gold", "keys": ["plan"]}));
+    // another tenant can neither read nor list the entries of acme
+    stores(Some("globex"), json!({"op": "get", "key": "plan"}))
+        .await
+        .assert_json(json!({"kv": null, "blob": null, "keys": []}));
+    // and requests without a tenant cannot use the stores
+    stores(None, json!({"op": "get", "key": "plan"}))
+        .await
+        .assert_status(500)
+        .assert_text_contains("the data is isolated per tenant");
+}
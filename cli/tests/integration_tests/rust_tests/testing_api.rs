@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_files(c: &TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            age: number = 0;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/seed.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        export default async function chisel(req: Request) {
+            const ids = await Chisel.testing.seed(await req.json());
+            return ids["Person"].length;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/reset.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        export default async function chisel(req: Request) {
+            await Chisel.testing.reset();
+            return "ok";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/count.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default async function chisel(req: Request) {
+            return await Person.cursor().count();
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/freeze.ts",
+        r#"
+        import { Chisel } from "@chiselstrike/api";
+        export default async function chisel(req: Request) {
+            const time = await req.json();
+            if (time === null) {
+                Chisel.testing.unfreezeTime();
+            } else {
+                Chisel.testing.freezeTime(new Date(time));
+            }
+            return "ok";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/now.ts",
+        r#"
+        export default async function chisel(req: Request) {
+            return new Date().toISOString() + " " + Date.now();
+        }
+        "#,
+    );
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--testing"])]
+async fn seed_and_reset(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    let seeded = c
+        .chisel
+        .post_json_text(
+            "/dev/seed",
+            json!({"Person": [{"name": "Alice", "age": 30}, {"name": "Bob"}]}),
+        )
+        .await;
+    assert_eq!(seeded, "2");
+    assert_eq!(c.chisel.get_text("/dev/count").await, "2");
+
+    c.chisel.post_json("/dev/reset", json!({})).await;
+    assert_eq!(c.chisel.get_text("/dev/count").await, "0");
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--testing"])]
+async fn freeze_time(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json("/dev/freeze", json!("2022-01-01T00:00:00.000Z"))
+        .await;
+    assert_eq!(
+        c.chisel.get_text("/dev/now").await,
+        "2022-01-01T00:00:00.000Z 1640995200000"
+    );
+
+    c.chisel.post_json("/dev/freeze", json!(null)).await;
+    assert!(!c
+        .chisel
+        .get_text("/dev/now")
+        .await
+        .starts_with("2022-01-01T00:00:00.000Z"));
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn unavailable_without_testing_flag(c: TestContext) {
+    write_files(&c);
+    c.chisel.apply_ok().await;
+
+    let status = c
+        .chisel
+        .post_json_status("/dev/seed", json!({"Person": [{"name": "Alice"}]}))
+        .await;
+    assert_eq!(status, 500);
+    assert_eq!(c.chisel.get_text("/dev/count").await, "0");
+
+    assert_eq!(
+        c.chisel.post_json_status("/dev/reset", json!({})).await,
+        500
+    );
+}
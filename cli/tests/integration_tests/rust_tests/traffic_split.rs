@@ -0,0 +1,69 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_route(c: &TestContext, color: &str) {
+    c.chisel.write(
+        "routes/color.ts",
+        &format!(r#"export default function () {{ return "{color}"; }}"#),
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn canary_receives_requests(c: TestContext) {
+    write_route(&c, "blue");
+    c.chisel.apply_ok().await;
+
+    write_route(&c, "green");
+    c.chisel
+        .exec("apply", &["--version", "v2", "--canary", "100%"])
+        .await
+        .expect("chisel apply --canary failed")
+        .stdout
+        .read("routing 100% of the requests to dev to this version");
+    assert_eq!(c.chisel.get_text("/dev/color").await, "green");
+    assert_eq!(c.chisel.get_text("/v2/color").await, "green");
+
+    c.chisel
+        .exec("apply", &["--version", "v2", "--canary", "0%"])
+        .await
+        .expect("chisel apply --canary failed");
+    assert_eq!(c.chisel.get_text("/dev/color").await, "blue");
+    assert_eq!(c.chisel.get_text("/v2/color").await, "green");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn deleting_canary_stops_split(c: TestContext) {
+    write_route(&c, "blue");
+    c.chisel.apply_ok().await;
+    write_route(&c, "green");
+    c.chisel
+        .exec("apply", &["--version", "v2", "--canary", "100"])
+        .await
+        .expect("chisel apply --canary failed");
+    assert_eq!(c.chisel.get_text("/dev/color").await, "green");
+
+    c.chisel
+        .exec("delete", &["--version", "v2"])
+        .await
+        .expect("chisel delete failed");
+    assert_eq!(c.chisel.get_text("/dev/color").await, "blue");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn canary_needs_stable_version(c: TestContext) {
+    write_route(&c, "green");
+    c.chisel
+        .exec("apply", &["--version", "v2", "--canary", "10%"])
+        .await
+        .expect_err("chisel apply --canary succeeded without a stable version")
+        .stderr
+        .read("There is no other version of");
+
+    c.chisel
+        .exec("apply", &["--version", "v2", "--canary", "150%"])
+        .await
+        .expect_err("chisel apply --canary succeeded with invalid percentage")
+        .stderr
+        .read("percentage must be between 0% and 100%");
+}
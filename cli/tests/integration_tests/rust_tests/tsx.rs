@@ -0,0 +1,34 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+use crate::framework::prelude::*;
+
+#[self::test(modules = Deno)]
+async fn render_html(c: TestContext) {
+    c.chisel.write(
+        "tsconfig.json",
+        r#"{"compilerOptions": {"jsxFactory": "h"}}"#,
+    );
+    c.chisel.write(
+        "routes/page.tsx",
+        r##"
+        declare global {
+            namespace JSX {
+                type Element = string;
+                interface IntrinsicElements { [name: string]: unknown }
+            }
+        }
+
+        function h(tag: string, _props: unknown, ...children: string[]): string {
+            return `<${tag}>${children.join("")}</${tag}>`;
+        }
+
+        export default async function () {
+            return new Response(<h1>Hello {"world"}</h1>, {
+                headers: { "content-type": "text/html" },
+            });
+        }
+        "##,
+    );
+
+    c.chisel.apply_ok().await;
+    assert_eq!(c.chisel.get_text("/dev/page").await, "<h1>Hello world</h1>");
+}
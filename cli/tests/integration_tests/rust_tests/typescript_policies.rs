@@ -319,3 +319,91 @@ pub async fn read_ctx_headers(c: TestContext) {
     let results = c.chisel.get("/dev/person").send().await;
     assert!(results.json()["results"].as_array().unwrap().is_empty());
 }
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--typescript-policies"])]
+pub async fn read_masks(c: TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            owner: string;
+            email: string;
+            phone: string;
+        }
+    "##,
+    );
+    c.chisel.write(
+        "routes/person.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+
+        export default Person.crud();
+    "##,
+    );
+    c.chisel.write(
+        "routes/emails.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+
+        export default async function () {
+            const people = await Person.findMany({});
+            return people.map((person) => person.email ?? null);
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json(
+            "/dev/person",
+            json!({ "owner": "marin", "email": "marin@example.com", "phone": "555" }),
+        )
+        .await;
+
+    c.chisel.write(
+        "policies/Person.ts",
+        r##"
+        export default {
+            onRead: (person, ctx) => {
+                if (person.owner == ctx.headers["owner"]) {
+                    return person;
+                }
+                return Mask.fields({ email: Mask.Redact, phone: Mask.Hash });
+            }
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let read_as = |owner: &'static str| {
+        let request = c.chisel.get("/dev/person").header("owner", owner);
+        async move { request.send().await.json()["results"][0].clone() }
+    };
+
+    let person = read_as("marin").await;
+    assert_eq!(person["email"], json!("marin@example.com"));
+    assert_eq!(person["phone"], json!("555"));
+
+    let person = read_as("jim").await;
+    assert!(person.get("email").is_none());
+    assert_eq!(
+        person["phone"],
+        json!("91a73fd806ab2c005c13b4dc19130a884e909dea3f72d46e30266fe1a1f588d8")
+    );
+    assert_eq!(person["owner"], json!("marin"));
+
+    // the endpoints get the masked entities too
+    c.chisel
+        .get("/dev/emails")
+        .header("owner", "jim")
+        .send()
+        .await
+        .assert_json(json!([null]));
+    c.chisel
+        .get("/dev/emails")
+        .header("owner", "marin")
+        .send()
+        .await
+        .assert_json(json!(["marin@example.com"]));
+}
@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn rejection_fails_request(mut c: TestContext) {
+    c.chisel.write(
+        "models/person.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string = "";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/forgotten.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+        async function fail() {
+            throw new Error("forgotten failure");
+        }
+        export default async function () {
+            await Person.create({ name: "Alice" });
+            fail();
+            await Person.create({ name: "Bob" });
+            return "ok";
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/handled_later.ts",
+        r#"
+        export default async function () {
+            const promise = Promise.reject(new Error("late"));
+            await new Promise((resolve) => setTimeout(resolve, 10));
+            try {
+                await promise;
+            } catch (e) {
+                return `caught ${e.message}`;
+            }
+        }
+        "#,
+    );
+    c.chisel
+        .write(".env", r##"{ "CHISELD_AUTH_SECRET" : "1234" }"##);
+    c.restart_chiseld().await;
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/forgotten")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("Promise rejected without a handler: Error: forgotten failure")
+        .assert_text_contains("at fail");
+
+    // the writes of the failed request are rolled back
+    assert_eq!(c.chisel.get_json("/dev/people").await["results"], json!([]));
+
+    // the worker keeps handling requests, and a handler added later is fine
+    c.chisel
+        .get("/dev/handled_later")
+        .send()
+        .await
+        .assert_text("caught late");
+
+    let metrics = c
+        .chisel
+        .get("/__chiselstrike/metrics")
+        .header("ChiselAuth", "1234")
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(metrics["workers"]["dev"]["unhandledRejections"], json!(1));
+}
@@ -0,0 +1,145 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+const BOUNDARY: &str = "----chisel-boundary";
+
+fn write_routes(c: &TestContext) {
+    c.chisel.write(
+        "routes/files.ts",
+        r#"
+        import { upload } from "@chiselstrike/api";
+        export default upload(async (parts) => {
+            const received = [];
+            for await (const part of parts) {
+                if (part.filename === undefined) {
+                    received.push({ name: part.name, text: await part.text() });
+                    continue;
+                }
+                let size = 0;
+                for await (const chunk of part.chunks()) {
+                    size += chunk.length;
+                }
+                received.push({
+                    name: part.name,
+                    filename: part.filename,
+                    contentType: part.contentType,
+                    size,
+                });
+            }
+            return received;
+        });
+        "#,
+    );
+    c.chisel.write(
+        "routes/form.ts",
+        r#"
+        import { ChiselRequest } from "@chiselstrike/api";
+        export default async function (req: ChiselRequest) {
+            const names = [];
+            for await (const part of req.multipart()) {
+                names.push(part.name);
+            }
+            return names;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/echo.ts",
+        r#"
+        import { ChiselRequest } from "@chiselstrike/api";
+        export default async function (req: ChiselRequest) {
+            return (await req.text()).length;
+        }
+        "#,
+    );
+}
+
+fn multipart_body(file_size: usize) -> String {
+    format!(
+        "preamble\r\n\
+        --{BOUNDARY}\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        holiday\r\n\
+        --{BOUNDARY}\r\n\
+        Content-Disposition: form-data; name=\"photo\"; filename=\"beach.png\"\r\n\
+        Content-Type: image/png\r\n\
+        \r\n\
+        {}\r\n\
+        --{BOUNDARY}--\r\n",
+        "x".repeat(file_size)
+    )
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--max-body-size", "1024"])]
+pub async fn multipart_uploads(c: TestContext) {
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    // the upload is streamed, so it is not limited by --max-body-size
+    c.chisel
+        .post("/dev/files")
+        .header(
+            "content-type",
+            &format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(multipart_body(100_000))
+        .send()
+        .await
+        .assert_json(json!([
+            {"name": "title", "text": "holiday"},
+            {"name": "photo", "filename": "beach.png", "contentType": "image/png", "size": 100_000},
+        ]));
+
+    c.chisel
+        .post("/dev/form")
+        .header(
+            "content-type",
+            &format!("multipart/form-data; boundary=\"{BOUNDARY}\""),
+        )
+        .body(multipart_body(10))
+        .send()
+        .await
+        .assert_json(json!(["title", "photo"]));
+
+    c.chisel
+        .post("/dev/files")
+        .header("content-type", "text/plain")
+        .body("hello")
+        .send()
+        .await
+        .assert_status(415);
+    c.chisel
+        .post("/dev/form")
+        .header(
+            "content-type",
+            &format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nunterminated"
+        ))
+        .send()
+        .await
+        .assert_status(400);
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--max-body-size", "16"])]
+pub async fn max_body_size(c: TestContext) {
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/echo")
+        .body("x".repeat(16))
+        .send()
+        .await
+        .assert_json(json!(16));
+    c.chisel
+        .post("/dev/echo")
+        .body("x".repeat(17))
+        .send()
+        .await
+        .assert_status(413)
+        .assert_text("Request body is larger than 16 bytes");
+}
@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+async fn diff_before_apply(c: TestContext) {
+    c.chisel.write(
+        "models/book.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            title: string;
+            pages: number;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/books.ts",
+        r#"
+        import { Book } from "../models/book.ts";
+        export default Book.crud();
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("diff", &[])
+        .await
+        .expect("chisel diff failed")
+        .stdout
+        .read("The project matches version dev");
+
+    c.chisel.write(
+        "models/book.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            title: string;
+            author: string = "";
+        }
+        export class Shelf extends ChiselEntity {
+            name: string;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/shelves.ts",
+        r#"
+        import { Shelf } from "../models/book.ts";
+        export default Shelf.crud();
+        "#,
+    );
+    c.chisel.remove_file("routes/books.ts");
+
+    c.chisel
+        .exec("diff", &[])
+        .await
+        .expect("chisel diff failed")
+        .stdout
+        .read("Changes of version dev")
+        .read("~ entity Book")
+        .read("+ field author")
+        .read("- field pages")
+        .read("+ entity Shelf")
+        .read("+ route /shelves")
+        .read("- route /books");
+
+    // diff does not apply anything
+    c.chisel
+        .exec("diff", &[])
+        .await
+        .expect("chisel diff failed")
+        .stdout
+        .read("+ entity Shelf");
+}
+
+#[chisel_macros::test(modules = Deno)]
+async fn diff_of_unknown_version(c: TestContext) {
+    c.chisel
+        .exec("diff", &["--version", "nonexistent"])
+        .await
+        .expect_err("chisel diff should fail")
+        .stderr
+        .read("Version \"nonexistent\" does not exist");
+}
@@ -0,0 +1,46 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static COUNTER_ROUTE: &str = r#"
+    let counter = 0;
+    export default function () {
+        counter += 1;
+        return counter;
+    }
+"#;
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = ["--worker-threads", "4", "--worker-affinity", "header:x-session"],
+)]
+pub async fn requests_pinned_by_header(c: TestContext) {
+    c.chisel.write_unindent("routes/counter.ts", COUNTER_ROUTE);
+    c.chisel.apply_ok().await;
+
+    // requests with the same session are handled by the same worker, which sees all of them
+    for i in 1..=8 {
+        c.chisel
+            .get("/dev/counter")
+            .header("x-session", "alice")
+            .send()
+            .await
+            .assert_json(json!(i));
+    }
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--worker-threads", "4"])]
+pub async fn requests_not_pinned_by_default(c: TestContext) {
+    c.chisel.write_unindent("routes/counter.ts", COUNTER_ROUTE);
+    c.chisel.apply_ok().await;
+
+    // without affinity, the requests are distributed round-robin among the workers
+    for _ in 1..=4 {
+        c.chisel
+            .get("/dev/counter")
+            .header("x-session", "alice")
+            .send()
+            .await
+            .assert_json(json!(1));
+    }
+}
@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use futures::future::join_all;
+
+async fn pool_metrics(c: &TestContext) -> serde_json::Value {
+    let metrics = c
+        .chisel
+        .get("/__chiselstrike/metrics")
+        .header("ChiselAuth", "1234")
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    metrics["workers"]["dev"].clone()
+}
+
+#[chisel_macros::test(
+    modules = Deno,
+    chiseld_args = [
+        "--worker-threads", "1",
+        "--max-worker-threads", "3",
+        "--worker-scale-up-queue-depth", "2",
+        "--worker-scale-up-latency-ms", "50",
+        "--worker-scale-down-idle-s", "1",
+    ],
+)]
+pub async fn scale_up_and_down(mut c: TestContext) {
+    c.chisel.write(
+        "routes/slow.ts",
+        r#"
+        export default async function () {
+            await new Promise((resolve) => setTimeout(resolve, 300));
+            return "done";
+        }
+        "#,
+    );
+    c.chisel
+        .write(".env", r##"{ "CHISELD_AUTH_SECRET" : "1234" }"##);
+    c.restart_chiseld().await;
+    c.chisel.apply_ok().await;
+
+    let pool = pool_metrics(&c).await;
+    assert_eq!(pool["workers"], json!(1));
+    assert_eq!(pool["minWorkers"], json!(1));
+    assert_eq!(pool["maxWorkers"], json!(3));
+
+    let requests = (0..8).map(|_| async { c.chisel.get_text("/dev/slow").await });
+    for response in join_all(requests).await {
+        assert_eq!(response, "done");
+    }
+    let pool = pool_metrics(&c).await;
+    assert!(pool["scaleUps"].as_u64().unwrap() >= 1);
+    assert!(pool["workers"].as_u64().unwrap() <= 3);
+
+    // the added workers are removed once they are idle
+    let mut workers = pool["workers"].clone();
+    for _ in 0..50 {
+        if workers == json!(1) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        workers = pool_metrics(&c).await["workers"].clone();
+    }
+    assert_eq!(workers, json!(1));
+    assert!(pool_metrics(&c).await["scaleDowns"].as_u64().unwrap() >= 1);
+}
+
+fn write_manifest(c: &TestContext, workers: &str) {
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        &format!(
+            r#"
+            models = ["models"]
+            routes = ["routes"]
+            events = ["events"]
+            policies = ["policies"]
+
+            [workers]
+            {workers}
+            "#
+        ),
+    );
+}
+
+#[chisel_macros::test(modules = Deno, chiseld_args = ["--worker-threads", "1"])]
+pub async fn per_version_pool(mut c: TestContext) {
+    c.chisel
+        .write(".env", r##"{ "CHISELD_AUTH_SECRET" : "1234" }"##);
+    c.restart_chiseld().await;
+    write_manifest(&c, "min = 2\nmax = 4");
+    c.chisel.apply_ok().await;
+
+    let pool = pool_metrics(&c).await;
+    assert_eq!(pool["workers"], json!(2));
+    assert_eq!(pool["minWorkers"], json!(2));
+    assert_eq!(pool["maxWorkers"], json!(4));
+
+    // the settings are restored when chiseld restarts
+    c.restart_chiseld().await;
+    let pool = pool_metrics(&c).await;
+    assert_eq!(pool["minWorkers"], json!(2));
+    assert_eq!(pool["maxWorkers"], json!(4));
+
+    // without the section, the version uses the defaults of the server again
+    write_manifest(&c, "");
+    c.chisel.apply_ok().await;
+    let pool = pool_metrics(&c).await;
+    assert_eq!(pool["minWorkers"], json!(1));
+    assert_eq!(pool["maxWorkers"], json!(1));
+
+    write_manifest(&c, "min = 3\nmax = 2");
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("workers.max (2) must not be less than workers.min (3)");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn saturation(mut c: TestContext) {
+    c.chisel.write(
+        "routes/slow.ts",
+        r#"
+        export default async function () {
+            await new Promise((resolve) => setTimeout(resolve, 300));
+            return "done";
+        }
+        "#,
+    );
+    c.chisel
+        .write(".env", r##"{ "CHISELD_AUTH_SECRET" : "1234" }"##);
+    c.restart_chiseld().await;
+    write_manifest(&c, "min = 1\nmax = 2\nscale_up_latency_ms = 0");
+    c.chisel.apply_ok().await;
+
+    let pool = pool_metrics(&c).await;
+    assert_eq!(pool["saturated"], json!(false));
+    assert_eq!(pool["saturations"], json!(0));
+
+    // two workers cannot keep up with eight slow requests at once
+    let requests = (0..8).map(|_| async { c.chisel.get_text("/dev/slow").await });
+    for response in join_all(requests).await {
+        assert_eq!(response, "done");
+    }
+    let pool = pool_metrics(&c).await;
+    assert!(pool["saturations"].as_u64().unwrap() >= 1);
+    assert!(pool["saturatedMs"].as_u64().unwrap() > 0);
+    assert!(pool["workers"].as_u64().unwrap() <= 2);
+}
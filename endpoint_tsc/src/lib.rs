@@ -3,21 +3,69 @@
 use anyhow::{Context, Result};
 use std::collections::HashMap;
 pub use tsc_compile;
+use tsc_compile::CompileCache;
 use tsc_compile::CompileOptions;
 use tsc_compile::FixedUrl;
+use tsc_compile::ImportMap;
+use tsc_compile::RemoteOptions;
+use tsc_compile::TsConfig;
 use url::Url;
 
 pub struct Compiler {
     pub tsc: tsc_compile::Compiler,
+    /// How the remote imports are fetched.
+    pub remote: RemoteOptions,
+    /// Options of the `tsconfig.json` of the project.
+    pub tsconfig: TsConfig,
+    /// Import map of the project.
+    pub import_map: ImportMap,
 }
 
 impl Compiler {
     pub fn new(use_snapshot: bool) -> Compiler {
         let tsc = tsc_compile::Compiler::new(use_snapshot);
-        Compiler { tsc }
+        Compiler {
+            tsc,
+            remote: Default::default(),
+            tsconfig: Default::default(),
+            import_map: Default::default(),
+        }
     }
 
     pub async fn compile(&mut self, url: Url) -> Result<Vec<(FixedUrl, String, bool)>> {
+        let opts = self.api_options();
+        self.tsc
+            .compile_urls(vec![url], opts)
+            .await
+            .context("Could not compile TypeScript")
+    }
+
+    /// Compiles only the modules that changed since the compilation that filled `cache`.
+    pub async fn compile_incremental(
+        &mut self,
+        url: Url,
+        cache: &mut CompileCache,
+    ) -> Result<Vec<(FixedUrl, String, bool)>> {
+        let opts = self.api_options();
+        self.tsc
+            .compile_urls_incremental(vec![url], opts, cache)
+            .await
+            .context("Could not compile TypeScript")
+    }
+
+    /// Compiles `urls` with up to `jobs` compilers in parallel (see
+    /// `tsc_compile::compile_urls_parallel()`). This blocks until the compilation is done.
+    pub fn compile_parallel(
+        &self,
+        urls: Vec<Url>,
+        jobs: usize,
+    ) -> Result<Vec<(FixedUrl, String, bool)>> {
+        tsc_compile::compile_urls_parallel(urls, self.api_options(), jobs)
+            .context("Could not compile TypeScript")
+    }
+
+    /// Options that make the ChiselStrike API available to the compiled code.
+    fn api_options(&self) -> CompileOptions<'static> {
         let mut mods = HashMap::new();
         mods.insert(
             "@chiselstrike/api".to_string(),
@@ -28,14 +76,12 @@ impl Compiler {
             mods.insert(name.to_string(), code.to_string());
         }
 
-        let opts = CompileOptions {
+        CompileOptions {
             extra_libs: mods,
+            remote: self.remote.clone(),
+            tsconfig: self.tsconfig.clone(),
+            import_map: self.import_map.clone(),
             ..Default::default()
-        };
-
-        self.tsc
-            .compile_urls(vec![url], opts)
-            .await
-            .context("Could not compile TypeScript")
+        }
     }
 }
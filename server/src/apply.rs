@@ -1,33 +1,71 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-use std::collections::{BTreeSet, HashMap};
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Context, Result};
 use petgraph::graphmap::GraphMap;
 use petgraph::Directed;
+use prost::Message;
+use sqlx::{Any, Transaction};
 
-use crate::datastore::{MetaService, QueryEngine};
-use crate::feat_typescript_policies;
+use crate::apply_plan::{MigrationPlan, PlanStep};
+use crate::datastore::decimal;
+use crate::datastore::engine::now_ms;
+use crate::datastore::tenancy::TENANT_COLUMN;
+use crate::datastore::{ArchivedEntity, AuditEntry, MetaService};
 use crate::policies::PolicySystem;
 use crate::proto::type_msg::TypeEnum;
 use crate::proto::{
-    AddTypeRequest, ApplyRequest, ContainerType, FieldDefinition, IndexCandidate,
-    PolicyUpdateRequest, TypeMsg,
+    AddTypeRequest, ApplyRequest, ApplyResponse, ContainerType, DecimalType, FieldDefinition,
+    IndexCandidate, PolicyUpdateRequest, TypeMsg,
 };
 use crate::server::Server;
 use crate::types::{
-    DbIndex, Entity, Field, NewField, NewObject, ObjectType, Type, TypeSystem, TypeSystemError,
+    AggregateFieldSpec, AggregateFn, AggregateSpec, Collation, CountSpec, DbIndex, Entity,
+    EnumSpec, Field, NewField, NewObject, ObjectType, OnDelete, Timestamp, Type, TypeId,
+    TypeSystem, TypeSystemError,
 };
 use crate::version::VersionInfo;
+use crate::worker_pool::WorkerPoolOverrides;
+use crate::{feat_typescript_policies, tenancy};
 
 pub struct ApplyResult {
     pub type_system: TypeSystem,
     pub policy_system: PolicySystem,
     pub type_names_user_order: Vec<String>,
     pub labels: Vec<String>,
+    /// Models and fields with data that were dropped by the apply.
+    pub dropped: Vec<String>,
+    /// Models with data whose tables were archived by the apply.
+    pub archived: Vec<String>,
     pub policy_sources: Arc<HashMap<String, Box<[u8]>>>,
+    /// Changes that a dry-run apply would make (`None` for a real apply).
+    pub plan: Option<MigrationPlan>,
+}
+
+impl ApplyResult {
+    pub fn response(&self) -> ApplyResponse {
+        ApplyResponse {
+            types: self.type_names_user_order.clone(),
+            labels: self.labels.clone(),
+            event_handlers: Vec::new(),
+            dropped: self.dropped.clone(),
+            archived: self.archived.clone(),
+            plan: self
+                .plan
+                .as_ref()
+                .map(MigrationPlan::describe)
+                .unwrap_or_default(),
+            plan_json: self
+                .plan
+                .as_ref()
+                .map(MigrationPlan::to_json)
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
 }
 
 pub struct ParsedPolicies {
@@ -75,13 +113,24 @@ impl ParsedPolicies {
     }
 }
 
+/// Checks a policy file in the same way as an apply would, without applying it (this is used by
+/// `chisel lint`). TypeScript policies are checked even if they are not enabled in the server.
+pub fn check_policy(path: &Path, policy_config: &str) -> Result<()> {
+    match path.extension().and_then(|s| s.to_str()) {
+        Some("ts") => chiselc::policies::Policies::parse_code(policy_config.as_bytes()).map(|_| ()),
+        _ => PolicySystem::from_yaml(policy_config).map(|_| ()),
+    }
+}
+
 pub async fn apply(
     server: Arc<Server>,
     apply_request: &ApplyRequest,
+    apply_id: Option<&str>,
     type_system: &mut TypeSystem,
     version_id: String,
     version_info: &VersionInfo,
     modules: &HashMap<String, String>,
+    static_files: &HashMap<String, Vec<u8>>,
 ) -> Result<ApplyResult> {
     let mut type_names = BTreeSet::new();
     let mut type_names_user_order = vec![];
@@ -92,62 +141,102 @@ pub async fn apply(
     }
 
     let mut to_remove = vec![];
-    let mut to_remove_has_data = vec![];
+    let mut to_archive = vec![];
     let mut to_insert = vec![];
     let mut to_update = vec![];
+    // models and fields that still have data, but that are dropped by this apply
+    let mut drops = vec![];
+    // locked models that are changed or removed by this apply
+    let mut locked_changes = BTreeSet::new();
 
     let meta = &server.meta_service;
     let mut transaction = meta.begin_transaction().await?;
+    meta.lock_apply_transaction(&mut transaction).await?;
 
-    for (existing, removed) in type_system.custom_types.iter() {
-        if !type_names.contains(existing) {
-            match meta.count_rows(&mut transaction, removed).await? {
-                0 => to_remove.push(removed.clone()),
-                cnt => to_remove_has_data.push((removed.clone(), cnt)),
+    // entities marked with `@renamedFrom` are renamed before the changes are computed, so that
+    // they (and the references to them) are compared with the old entities under the new names
+    let entity_renames = find_entity_renames(apply_request, type_system, &type_names)?;
+    let mut renamed_type_system = None;
+    if !entity_renames.is_empty() {
+        let mut renamed = type_system.clone();
+        for (old_name, new_name) in entity_renames.iter() {
+            if type_system.lookup_custom_type(old_name)?.is_locked() {
+                locked_changes.insert(new_name.clone());
             }
+            renamed.rename_custom_type(old_name, new_name)?;
         }
+        renamed_type_system = Some(renamed);
     }
+    let old_type_system = renamed_type_system.as_ref().unwrap_or(&*type_system);
 
-    if !to_remove_has_data.is_empty() && !apply_request.allow_type_deletion {
-        let s = to_remove_has_data
-            .iter()
-            .map(|x| format!("{} ({} elements)", x.0.name(), x.1))
-            .fold("\t".to_owned(), |acc, x| format!("{}\n\t{}", acc, x));
-        bail!(
-            r"Trying to remove models from the models file, but the following models still have data:
-{}
-
-To proceed, try:
-
-'npx chisel apply --allow-type-deletion' (if installed from npm)
-
-or
-
-'chisel apply --allow-type-deletion' (otherwise)",
-            s
-        );
+    for (existing, removed) in old_type_system.custom_types.iter() {
+        if !type_names.contains(existing) {
+            if removed.is_locked() {
+                locked_changes.insert(existing.clone());
+            }
+            // the data of materialized aggregates is derived, so it can always be dropped
+            let rows = if removed.aggregate().is_some() {
+                0
+            } else {
+                meta.count_rows(&mut transaction, removed).await?
+            };
+            if rows == 0 {
+                to_remove.push(removed.clone());
+            } else if apply_request.archive_removed {
+                to_archive.push(removed.clone());
+            } else {
+                drops.push(DroppedItem {
+                    name: existing.clone(),
+                    rows,
+                });
+                to_remove.push(removed.clone());
+            }
+        }
     }
-    // if we got here, either the slice is empty anyway, or the user is forcing the deletion.
-    to_remove.extend(to_remove_has_data.iter().map(|x| x.0.clone()));
 
     let mut decorators = BTreeSet::default();
     let mut new_types = HashMap::<String, Entity>::default();
+    // `@count` fields (`Name.field`) that are new or count something else than before, so they
+    // must be computed from the existing data
+    let mut backfilled_counts = HashSet::<String>::default();
+    // `@timestamps` fields (`Name.field`) that are added to existing entities, so they must be
+    // set for the existing rows
+    let mut backfilled_timestamps = HashSet::<String>::default();
+    // materialized aggregates whose rows are deleted before their tables are altered (they are
+    // recomputed at the end of the apply)
+    let mut cleared_aggregates = HashSet::<String>::default();
     let indexes = aggregate_indexes(&apply_request.index_candidates);
 
     // No changes are made to the type system in this loop. We re-read the database after we
     // apply the changes, and this way we don't have to deal with the case of succeding to
     // apply a type, but failing the next
-    for type_def in sort_custom_types(type_system, apply_request.types.clone())? {
+    for type_def in sort_custom_types(old_type_system, apply_request.types.clone())? {
         let name = type_def.name;
-        if type_system.lookup_builtin_type(&name).is_ok() {
+        if old_type_system.lookup_builtin_type(&name).is_ok() {
             bail!("custom type expected, got `{name}` instead");
         }
 
         let mut fields = Vec::new();
+        // fields marked with `@renamedFrom`, new name to old name
+        let mut renames = HashMap::new();
         for field in type_def.field_defs {
             for label in &field.labels {
                 decorators.insert(label.clone());
             }
+            if tenancy().is_some() && field.name == TENANT_COLUMN {
+                bail!(
+                    "field `{TENANT_COLUMN}` of entity `{name}` clashes with the column that stores the tenant of the rows"
+                );
+            }
+            if !field.renamed_from.is_empty() {
+                if renames.values().any(|old| *old == field.renamed_from) {
+                    bail!(
+                        "more than one field of entity `{name}` is renamed from `{}`",
+                        field.renamed_from
+                    );
+                }
+                renames.insert(field.name.clone(), field.renamed_from.clone());
+            }
 
             let field_ty = field.field_type()?;
             let field_ty = if let TypeEnum::EntityId(entity_name) = field_ty {
@@ -158,8 +247,8 @@ or
                     );
                 }
                 Type::EntityId(entity_name.to_owned())
-            } else if field_ty.is_builtin(type_system)? {
-                field_ty.get_builtin(type_system)?
+            } else if field_ty.is_builtin(old_type_system)? {
+                field_ty.get_builtin(old_type_system)?
             } else if let TypeEnum::Entity(entity_name) = field_ty {
                 match new_types.get(entity_name) {
                     Some(ty) => Type::Entity(ty.clone()),
@@ -174,28 +263,275 @@ or
                 );
             };
 
-            fields.push(Field::new(
-                &NewField::new(&field.name, field_ty, &version_id)?,
-                field.labels,
-                field.default_value,
-                field.is_optional,
-                field.is_unique,
-            ));
+            if field.is_version && (!matches!(field_ty, Type::Float) || field.is_optional) {
+                bail!(
+                    "field `{}` of entity `{name}` is marked with @version, so it must be a non-optional number",
+                    field.name
+                );
+            }
+
+            if field.count.is_some()
+                && (!matches!(field_ty, Type::Float) || field.is_optional || field.is_version)
+            {
+                bail!(
+                    "field `{}` of entity `{name}` is marked with @count, so it must be a non-optional number",
+                    field.name
+                );
+            }
+
+            let aggregate = match field.aggregate {
+                Some(aggregate) => Some(AggregateFieldSpec {
+                    function: aggregate.function.parse().with_context(|| {
+                        format!(
+                            "invalid aggregate of field `{}` of entity `{name}`",
+                            field.name
+                        )
+                    })?,
+                    source_field: Some(aggregate.source_field).filter(|f| !f.is_empty()),
+                }),
+                None => None,
+            };
+
+            let timestamp = match field.timestamp.as_str() {
+                "" => None,
+                timestamp => Some(timestamp.parse::<Timestamp>().with_context(|| {
+                    format!(
+                        "invalid timestamp of field `{}` of entity `{name}`",
+                        field.name
+                    )
+                })?),
+            };
+            if timestamp.is_some()
+                && (!matches!(field_ty, Type::JsDate)
+                    || field.is_optional
+                    || field.is_version
+                    || field.count.is_some())
+            {
+                bail!(
+                    "field `{}` of entity `{name}` is added by @timestamps, so it must be a non-optional Date",
+                    field.name
+                );
+            }
+
+            let enum_type = field.enum_type.map(|enum_type| EnumSpec {
+                name: enum_type.name,
+                values: enum_type.values,
+            });
+            if let Some(enum_type) = &enum_type {
+                if !matches!(field_ty, Type::String) || field.is_version || field.count.is_some() {
+                    bail!(
+                        "field `{}` of entity `{name}` is of enum type `{}`, so it must be a string",
+                        field.name,
+                        enum_type.name
+                    );
+                }
+                if enum_type.values.is_empty() {
+                    bail!(
+                        "enum `{}` of field `{}` of entity `{name}` has no values",
+                        enum_type.name,
+                        field.name
+                    );
+                }
+                if let Some(default) = &field.default_value {
+                    if !enum_type.contains(default) {
+                        bail!(
+                            "default value {:?} of field `{}` of entity `{name}` is not a value of enum `{}`",
+                            default,
+                            field.name,
+                            enum_type.name
+                        );
+                    }
+                }
+            }
+
+            if let Some(max_bytes) = field.max_bytes {
+                if !matches!(field_ty, Type::ArrayBuffer) {
+                    bail!(
+                        "field `{}` of entity `{name}` is marked with @maxBytes, so it must be an ArrayBuffer",
+                        field.name
+                    );
+                }
+                if max_bytes == 0 {
+                    bail!(
+                        "the @maxBytes limit of field `{}` of entity `{name}` must be positive",
+                        field.name
+                    );
+                }
+            }
+
+            let on_delete = match field.on_delete.as_str() {
+                "" => None,
+                on_delete => Some(on_delete.parse::<OnDelete>().with_context(|| {
+                    format!(
+                        "invalid @onDelete action of field `{}` of entity `{name}`",
+                        field.name
+                    )
+                })?),
+            };
+            if let Some(on_delete) = on_delete {
+                if !matches!(field_ty, Type::Entity(_) | Type::EntityId(_)) {
+                    bail!(
+                        "field `{}` of entity `{name}` is marked with @onDelete, so it must refer to an entity",
+                        field.name
+                    );
+                }
+                if on_delete == OnDelete::SetNull && !field.is_optional {
+                    bail!(
+                        "field `{}` of entity `{name}` is marked with @onDelete(\"setNull\"), so it must be optional",
+                        field.name
+                    );
+                }
+            }
+
+            let collation = match field.collation.as_str() {
+                "" => None,
+                collation => match collation.parse::<Collation>().with_context(|| {
+                    format!(
+                        "invalid @collate collation of field `{}` of entity `{name}`",
+                        field.name
+                    )
+                })? {
+                    Collation::Binary => None,
+                    collation => Some(collation),
+                },
+            };
+            if collation.is_some() && !matches!(field_ty, Type::String) {
+                bail!(
+                    "field `{}` of entity `{name}` is marked with @collate, so it must be a string",
+                    field.name
+                );
+            }
+
+            if let (Type::Decimal { precision, scale }, Some(default)) =
+                (&field_ty, &field.default_value)
+            {
+                decimal::parse(default, *precision, *scale).map_err(|e| {
+                    anyhow!(
+                        "invalid default value of field `{}` of entity `{name}`: {e}",
+                        field.name
+                    )
+                })?;
+            }
+
+            fields.push(
+                Field::new(
+                    &NewField::new(&field.name, field_ty, &version_id)?,
+                    field.labels,
+                    field.default_value,
+                    field.is_optional,
+                    field.is_unique,
+                    field.is_version,
+                )
+                .with_count(field.count.map(|count| CountSpec {
+                    entity: count.entity_name,
+                    field: count.field_name,
+                }))
+                .with_aggregate(aggregate)
+                .with_timestamp(timestamp)
+                .with_enum_type(enum_type)
+                .with_max_bytes(field.max_bytes)
+                .with_on_delete(on_delete)
+                .with_collation(collation),
+            );
+        }
+        if fields.iter().filter(|f| f.is_version).count() > 1 {
+            bail!("entity `{name}` has more than one field marked with @version");
+        }
+        let old_type = old_type_system.lookup_custom_type(&name).ok();
+        for field in fields.iter().filter(|f| f.count.is_some()) {
+            let old_count = old_type
+                .as_ref()
+                .and_then(|old| old_field(old, &renames, &field.name))
+                .and_then(|old| old.count.as_ref());
+            if old_count != field.count.as_ref() {
+                backfilled_counts.insert(format!("{}.{}", name, field.name));
+            }
+        }
+        if let Some(old_type) = &old_type {
+            for field in fields.iter().filter(|f| f.timestamp.is_some()) {
+                let old_timestamp =
+                    old_field(old_type, &renames, &field.name).and_then(|old| old.timestamp);
+                if old_timestamp.is_none() {
+                    backfilled_timestamps.insert(format!("{}.{}", name, field.name));
+                }
+            }
         }
         let ty_indexes = indexes.get(&name).cloned().unwrap_or_default();
 
-        let ty = Arc::new(ObjectType::new(
-            &NewObject::new(&name, &version_id),
-            fields,
-            ty_indexes,
-        )?);
+        let aggregate = type_def.aggregate.map(|aggregate| AggregateSpec {
+            source: aggregate.source_entity,
+            refresh_interval_s: aggregate.refresh_interval_s,
+        });
+
+        let ty = Arc::new(
+            ObjectType::new(&NewObject::new(&name, &version_id), fields, ty_indexes)?
+                .with_aggregate(aggregate)
+                .with_locked(type_def.locked)
+                .with_owned_by(Some(type_def.owned_by).filter(|f| !f.is_empty())),
+        );
 
         new_types.insert(name.to_owned(), Entity::Custom(ty.clone()));
 
-        match type_system.lookup_custom_type(&name) {
+        match old_type_system.lookup_custom_type(&name) {
             Ok(old_type) => {
-                let is_empty = meta.count_rows(&mut transaction, &old_type).await? == 0;
-                let delta = type_system.generate_type_delta(&old_type, ty, is_empty)?;
+                let rows = if old_type.aggregate().is_some() {
+                    0
+                } else {
+                    meta.count_rows(&mut transaction, &old_type).await?
+                };
+                if old_type.aggregate().is_some() || ty.aggregate().is_some() {
+                    cleared_aggregates.insert(name.clone());
+                    if rows > 0 {
+                        // an entity with data is turned into an aggregate
+                        drops.push(DroppedItem {
+                            name: name.clone(),
+                            rows,
+                        });
+                    }
+                }
+                let delta = old_type_system.generate_type_delta(
+                    &old_type,
+                    ty.clone(),
+                    &renames,
+                    rows == 0 || cleared_aggregates.contains(&name),
+                )?;
+                if old_type.is_locked() && (!ty.is_locked() || delta.changes_definition(&old_type))
+                {
+                    locked_changes.insert(name.clone());
+                }
+                if rows > 0 && !cleared_aggregates.contains(&name) {
+                    for field in delta.removed_fields.iter() {
+                        drops.push(DroppedItem {
+                            name: format!("{}.{}", name, field.name),
+                            rows,
+                        });
+                    }
+                    // the rows whose values were removed from the enum keep them, but they are
+                    // out of range, so removing values is treated like dropping data
+                    for field in ty.user_fields() {
+                        let enum_type = match &field.enum_type {
+                            Some(enum_type) => enum_type,
+                            None => continue,
+                        };
+                        let old_field = match old_field(&old_type, &renames, &field.name) {
+                            Some(old_field) if old_field.enum_type.as_ref() != Some(enum_type) => {
+                                old_field
+                            }
+                            _ => continue,
+                        };
+                        let counts = meta
+                            .count_rows_by_value(&mut transaction, &old_type, &old_field.name)
+                            .await?;
+                        for (value, rows) in counts {
+                            if !enum_type.contains(&value) {
+                                drops.push(DroppedItem {
+                                    name: format!("{}.{}={}", name, field.name, value),
+                                    rows,
+                                });
+                            }
+                        }
+                    }
+                }
                 to_update.push((old_type.clone(), delta));
             }
             Err(TypeSystemError::NoSuchType(_)) => {
@@ -205,10 +541,170 @@ or
         }
     }
 
+    for ty in new_types.values() {
+        for field in ty.user_fields() {
+            if let Some(count) = &field.count {
+                check_count(ty, field, count, &new_types)?;
+            }
+        }
+        match ty.aggregate() {
+            Some(aggregate) => check_aggregate(ty, aggregate, &new_types)?,
+            None => {
+                if let Some(field) = ty.user_fields().find(|f| f.aggregate.is_some()) {
+                    bail!(
+                        "field `{}` of entity `{}` is aggregated, but the entity is not marked with @aggregate",
+                        field.name,
+                        ty.name()
+                    );
+                }
+            }
+        }
+        if let Some(owner) = ty.owned_by() {
+            check_owner(ty, owner)?;
+        }
+    }
+
+    let rejected = locked_changes
+        .iter()
+        .filter(|name| !apply_request.unlocked.contains(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+
     let ParsedPolicies {
         policy_system: (policy_system, policy_system_str),
         policy_sources,
     } = ParsedPolicies::parse(&apply_request.policies)?;
+    let labels: Vec<String> = policy_system.labels.keys().map(|x| x.to_owned()).collect();
+
+    if apply_request.dry_run {
+        let mut plan = MigrationPlan::default();
+        for (old_name, new_name) in entity_renames.iter() {
+            plan.push(PlanStep::RenameTable {
+                from: old_name.clone(),
+                to: new_name.clone(),
+            });
+        }
+        for ty in to_insert.iter() {
+            plan.push(PlanStep::AddTable {
+                entity: ty.name().to_owned(),
+            });
+            for index in ty.indexes() {
+                plan.push(PlanStep::AddIndex {
+                    entity: ty.name().to_owned(),
+                    fields: index.fields.clone(),
+                });
+            }
+        }
+        for (old, delta) in to_update.iter() {
+            plan.push_update(old, delta);
+        }
+        for ty in to_remove.iter() {
+            plan.push(PlanStep::DropTable {
+                entity: ty.name().to_owned(),
+            });
+        }
+        for ty in to_archive.iter() {
+            plan.push(PlanStep::ArchiveTable {
+                entity: ty.name().to_owned(),
+            });
+        }
+        // the backfilled fields are named `Name.field`
+        let split = |name: &String| {
+            let (entity, field) = name.split_once('.').unwrap();
+            (entity.to_owned(), field.to_owned())
+        };
+        let mut backfilled_counts = backfilled_counts.iter().map(split).collect::<Vec<_>>();
+        backfilled_counts.sort_unstable();
+        for (entity, field) in backfilled_counts {
+            plan.push(PlanStep::CopyCount { entity, field });
+        }
+        let mut backfilled_timestamps = backfilled_timestamps.iter().map(split).collect::<Vec<_>>();
+        backfilled_timestamps.sort_unstable();
+        for (entity, field) in backfilled_timestamps {
+            plan.push(PlanStep::CopyTimestamp { entity, field });
+        }
+        for drop in drops.iter() {
+            plan.warnings.push(format!(
+                "drops {} with {} rows of data",
+                drop.name, drop.rows
+            ));
+        }
+        for name in locked_changes.iter() {
+            if rejected.contains(name) {
+                plan.rejected.push(format!(
+                    "changes the locked model {}, pass `--unlock {}` to allow it",
+                    name, name
+                ));
+            } else {
+                plan.warnings
+                    .push(format!("changes the locked model {}", name));
+            }
+        }
+        if let Err(err) = check_drops(apply_request, &drops) {
+            plan.rejected.push(err.to_string());
+        }
+
+        // nothing is persisted
+        transaction.rollback().await?;
+        return Ok(ApplyResult {
+            type_system: type_system.clone(),
+            policy_system,
+            type_names_user_order,
+            labels,
+            dropped: drops.into_iter().map(|d| d.name).collect(),
+            archived: to_archive.iter().map(|ty| ty.name().to_owned()).collect(),
+            policy_sources,
+            plan: Some(plan),
+        });
+    }
+
+    if !rejected.is_empty() {
+        // the attempt is recorded even though the apply is rolled back
+        transaction.rollback().await?;
+        let mut transaction = meta.begin_transaction().await?;
+        audit_apply(
+            &mut transaction,
+            apply_request,
+            &version_id,
+            &rejected,
+            "locked_change_rejected",
+        )
+        .await?;
+        MetaService::commit_transaction(transaction).await?;
+        bail!(
+            "This apply changes the locked models {}. If the change is intended, pass {}",
+            rejected.join(", "),
+            rejected
+                .iter()
+                .map(|name| format!("`--unlock {}`", name))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+    let locked_changes = locked_changes.into_iter().collect::<Vec<_>>();
+    audit_apply(
+        &mut transaction,
+        apply_request,
+        &version_id,
+        &locked_changes,
+        "locked_change",
+    )
+    .await?;
+
+    check_drops(apply_request, &drops)?;
+    // the dropped data is gone for good, so we record who approved dropping it
+    let dropped_details = drops
+        .iter()
+        .map(|d| format!("{} ({} elements)", d.name, d.rows))
+        .collect::<Vec<_>>();
+    audit_apply(
+        &mut transaction,
+        apply_request,
+        &version_id,
+        &dropped_details,
+        "drop",
+    )
+    .await?;
 
     meta.persist_policy_sources(&mut transaction, &version_id, &policy_sources)
         .await?;
@@ -216,14 +712,32 @@ or
         .await?;
     meta.persist_version_info(&mut transaction, &version_id, version_info)
         .await?;
+    meta.persist_allowed_hosts(&mut transaction, &version_id, &apply_request.allowed_hosts)
+        .await?;
+    let worker_pool = apply_request
+        .worker_pool
+        .as_ref()
+        .map(WorkerPoolOverrides::from)
+        .unwrap_or_default();
+    meta.persist_worker_pool(&mut transaction, &version_id, &worker_pool)
+        .await?;
+    meta.persist_import_map(&mut transaction, &version_id, &apply_request.import_map)
+        .await?;
     meta.persist_modules(&mut transaction, &version_id, modules)
         .await?;
+    meta.persist_static_files(&mut transaction, &version_id, static_files)
+        .await?;
 
     for ty in to_insert.iter() {
-        // FIXME: Consistency between metadata and backing store updates.
         meta.insert_type(&mut transaction, ty).await?;
     }
 
+    for (old_name, new_name) in entity_renames.iter() {
+        let old = type_system.lookup_custom_type(old_name)?;
+        meta.rename_type(&mut transaction, &old, new_name, old_type_system)
+            .await?;
+    }
+
     for (old, delta) in to_update.iter() {
         meta.update_type(&mut transaction, old, delta.clone())
             .await?;
@@ -233,64 +747,512 @@ or
         meta.remove_type(&mut transaction, ty).await?;
     }
 
-    MetaService::commit_transaction(transaction).await?;
+    let mut archives = vec![];
+    for ty in to_archive.iter() {
+        let archived = ArchivedEntity::new(ty, archived_table_name(ty));
+        MetaService::persist_archived_entity(&mut transaction, &archived).await?;
+        meta.remove_type(&mut transaction, ty).await?;
+        archives.push(archived);
+    }
 
-    let labels: Vec<String> = policy_system.labels.keys().map(|x| x.to_owned()).collect();
+    // Reload the type system so that we have new ids. The metadata and the data live in the same
+    // database, so we can see the new ids before the transaction is committed, and the whole
+    // apply is committed atomically at the end.
+    let new_type_system =
+        MetaService::load_type_systems_in(&mut transaction, &server.builtin_types)
+            .await?
+            .remove(&version_id)
+            .unwrap_or_else(|| TypeSystem::new(server.builtin_types.clone(), version_id.clone()));
 
-    // Reload the type system so that we have new ids
-    *type_system = meta
-        .load_type_systems(&server.builtin_types)
-        .await?
-        .remove(&version_id)
-        .unwrap_or_else(|| TypeSystem::new(server.builtin_types.clone(), version_id.clone()));
-
-    // FIXME: Now that we have --db-uri, this is the reason we still have to drop
-    // the transaction on meta, and acquire on query_engine: we need to reload the
-    // type system to get db-side IDs, and if we do that before we get the transactions
-    // then we won't get them. Without ids, we can't build the to_insert and to_update
-    // arrays.
-    //
     // Refresh to_insert types so that they have fresh meta ids (e.g. new  DbIndexes
     // need their meta id to be created in the storage database).
     let to_insert = to_insert
         .iter()
-        .map(|ty| type_system.lookup_custom_type(ty.name()))
+        .map(|ty| new_type_system.lookup_custom_type(ty.name()))
         .collect::<Result<Vec<_>, _>>()?;
 
     let to_update = to_update
         .into_iter()
         .map(|(ty, delta)| {
-            let updated_ty = type_system.lookup_custom_type(ty.name());
+            let updated_ty = new_type_system.lookup_custom_type(ty.name());
             updated_ty.map(|ty| (ty, delta))
         })
         .collect::<Result<Vec<_>, _>>()?;
 
     let query_engine = &server.query_engine;
-    let mut transaction = query_engine.begin_transaction().await?;
     for ty in to_insert.into_iter() {
         query_engine.create_table(&mut transaction, &ty).await?;
+        if tenancy().is_some() {
+            query_engine
+                .add_tenant_column(&mut transaction, &ty)
+                .await?;
+        }
     }
+    query_engine
+        .create_kv_table(&mut transaction, &version_id)
+        .await?;
 
     for ty in to_remove.into_iter() {
         query_engine.drop_table(&mut transaction, &ty).await?;
     }
 
+    for (ty, archived) in to_archive.iter().zip(archives.iter()) {
+        query_engine
+            .archive_table(&mut transaction, ty, &archived.table_name)
+            .await?;
+    }
+
     for (old, delta) in to_update.into_iter() {
+        if cleared_aggregates.contains(old.name()) {
+            query_engine.truncate_table(&mut transaction, &old).await?;
+        }
         query_engine
             .alter_table(&mut transaction, &old, delta)
             .await?;
     }
-    QueryEngine::commit_transaction(transaction).await?;
 
-    Ok(ApplyResult {
-        type_system: type_system.clone(),
+    for counter in new_type_system.counters() {
+        if backfilled_counts.contains(&format!("{}.{}", counter.counting.name(), counter.field)) {
+            query_engine
+                .backfill_counter(&mut transaction, &counter)
+                .await?;
+        }
+    }
+
+    let now = now_ms();
+    for ty in new_type_system.custom_types.values() {
+        for field in ty.user_fields() {
+            if backfilled_timestamps.contains(&format!("{}.{}", ty.name(), field.name)) {
+                query_engine
+                    .backfill_timestamp(&mut transaction, ty, field, now)
+                    .await?;
+            }
+        }
+    }
+
+    // the source entities might have changed, so we recompute all aggregates
+    for agg in new_type_system.aggregators() {
+        query_engine
+            .refresh_aggregate(&mut transaction, &agg)
+            .await
+            .with_context(|| format!("Could not compute aggregate `{}`", agg.aggregate.name()))?;
+    }
+
+    let result = ApplyResult {
+        type_system: new_type_system,
         type_names_user_order,
         labels,
+        dropped: drops.into_iter().map(|d| d.name).collect(),
+        archived: archives.into_iter().map(|a| a.entity_name).collect(),
+        policy_system,
+        policy_sources,
+        plan: None,
+    };
+    if let Some(apply_id) = apply_id {
+        let response = result.response().encode_to_vec();
+        MetaService::finish_staged_apply(&mut transaction, apply_id, &response).await?;
+    }
+    MetaService::commit_transaction(transaction).await?;
+
+    *type_system = result.type_system.clone();
+    Ok(result)
+}
+
+/// Records in the audit log an `action` of the apply on each of `details` (such as the locked
+/// models that it changed or tried to change).
+async fn audit_apply(
+    transaction: &mut Transaction<'_, Any>,
+    apply_request: &ApplyRequest,
+    version_id: &str,
+    details: &[String],
+    action: &str,
+) -> Result<()> {
+    let actor = match apply_request.lock_holder.as_str() {
+        "" => "unknown client",
+        holder => holder,
+    };
+    let created_at = time::OffsetDateTime::now_utc().unix_timestamp();
+    for detail in details {
+        let entry = AuditEntry {
+            version_id: version_id.to_owned(),
+            actor: actor.to_owned(),
+            action: action.to_owned(),
+            detail: detail.clone(),
+            created_at,
+        };
+        MetaService::persist_audit_entry(transaction, &entry).await?;
+    }
+    Ok(())
+}
+
+/// Policies of a version that were applied without re-applying the types and modules.
+pub struct PolicyApplyResult {
+    pub policy_system: PolicySystem,
+    pub policy_sources: Arc<HashMap<String, Box<[u8]>>>,
+    pub labels: Vec<String>,
+}
+
+/// Replaces the persisted policies of `version_id` with `policies`.
+pub async fn apply_policies(
+    server: &Server,
+    version_id: &str,
+    policies: &[PolicyUpdateRequest],
+) -> Result<PolicyApplyResult> {
+    let ParsedPolicies {
+        policy_system: (policy_system, policy_system_str),
+        policy_sources,
+    } = ParsedPolicies::parse(policies)?;
+
+    let meta = &server.meta_service;
+    let mut transaction = meta.begin_transaction().await?;
+    meta.lock_apply_transaction(&mut transaction).await?;
+    meta.persist_policy_sources(&mut transaction, version_id, &policy_sources)
+        .await?;
+    meta.persist_policy_version(&mut transaction, version_id, &policy_system_str)
+        .await?;
+    MetaService::commit_transaction(transaction).await?;
+
+    let labels = policy_system.labels.keys().map(|x| x.to_owned()).collect();
+    Ok(PolicyApplyResult {
         policy_system,
         policy_sources,
+        labels,
     })
 }
 
+/// Finds the entities marked with `@renamedFrom` that are renamed by the apply, as pairs of the old
+/// and the new name. The decorator is ignored if the old entity doesn't exist (for example,
+/// because a previous apply renamed it), if the new entity already exists or if the project still
+/// defines the old entity.
+fn find_entity_renames(
+    apply_request: &ApplyRequest,
+    type_system: &TypeSystem,
+    type_names: &BTreeSet<String>,
+) -> Result<Vec<(String, String)>> {
+    let mut old_names = HashSet::new();
+    let mut renames = vec![];
+    for type_def in apply_request.types.iter() {
+        let old_name = &type_def.renamed_from;
+        if old_name.is_empty() {
+            continue;
+        }
+        if !old_names.insert(old_name) {
+            bail!("more than one entity is renamed from `{old_name}`");
+        }
+        if type_system.lookup_custom_type(old_name).is_ok()
+            && type_system.lookup_custom_type(&type_def.name).is_err()
+            && !type_names.contains(old_name)
+        {
+            renames.push((old_name.clone(), type_def.name.clone()));
+        }
+    }
+    Ok(renames)
+}
+
+/// Returns the field of `old_type` that is replaced by the field `name`, which has the same name
+/// unless the field is renamed with `@renamedFrom`.
+fn old_field<'a>(
+    old_type: &'a ObjectType,
+    renames: &HashMap<String, String>,
+    name: &str,
+) -> Option<&'a Field> {
+    old_type.get_field(name).or_else(|| {
+        renames
+            .get(name)
+            .and_then(|old_name| old_type.get_field(old_name))
+    })
+}
+
+/// Checks that the `@count` field of `ty` counts an entity with a field that refers to `ty`.
+fn check_count(
+    ty: &ObjectType,
+    field: &Field,
+    count: &CountSpec,
+    new_types: &HashMap<String, Entity>,
+) -> Result<()> {
+    let counted = new_types.get(&count.entity).with_context(|| {
+        format!(
+            "field `{}` of entity `{}` counts entity `{}`, which is undefined",
+            field.name,
+            ty.name(),
+            count.entity
+        )
+    })?;
+    let counted_field = counted.get_field(&count.field).with_context(|| {
+        format!(
+            "field `{}` of entity `{}` counts by field `{}`, but entity `{}` has no such field",
+            field.name,
+            ty.name(),
+            count.field,
+            count.entity
+        )
+    })?;
+    let refers_to_ty = match &counted_field.type_id {
+        TypeId::String => true,
+        TypeId::EntityId(name) | TypeId::Entity { name, .. } => name == ty.name(),
+        _ => false,
+    };
+    if !refers_to_ty {
+        bail!(
+            "field `{}` of entity `{}` counts by field `{}.{}`, which must be a string, an `Id<{}>` or a `{}`",
+            field.name,
+            ty.name(),
+            count.entity,
+            count.field,
+            ty.name(),
+            ty.name()
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the materialized aggregate `ty` groups an entity by its fields, and that the
+/// aggregated fields have types that match their aggregate functions.
+/// Checks the owner field of an entity marked with `@ownedBy`, which must hold the id of a user.
+fn check_owner(ty: &ObjectType, owner: &str) -> Result<()> {
+    if ty.aggregate().is_some() {
+        bail!(
+            "aggregate `{}` cannot be marked with @ownedBy, its rows are computed by the server",
+            ty.name()
+        );
+    }
+    let field = ty.get_field(owner).with_context(|| {
+        format!(
+            "entity `{}` is owned by field `{owner}`, which is undefined",
+            ty.name()
+        )
+    })?;
+    if field.type_id != TypeId::String
+        || field.is_optional
+        || field.is_version
+        || field.count.is_some()
+        || field.timestamp.is_some()
+    {
+        bail!(
+            "field `{owner}` of entity `{}` holds the owner of @ownedBy, so it must be a non-optional string",
+            ty.name()
+        );
+    }
+    Ok(())
+}
+
+fn check_aggregate(
+    ty: &ObjectType,
+    aggregate: &AggregateSpec,
+    new_types: &HashMap<String, Entity>,
+) -> Result<()> {
+    let source = new_types.get(&aggregate.source).with_context(|| {
+        format!(
+            "entity `{}` aggregates entity `{}`, which is undefined",
+            ty.name(),
+            aggregate.source
+        )
+    })?;
+    if source.aggregate().is_some() {
+        bail!(
+            "entity `{}` aggregates entity `{}`, which is itself an aggregate",
+            ty.name(),
+            aggregate.source
+        );
+    }
+    if aggregate.refresh_interval_s == Some(0) {
+        bail!(
+            "the refresh interval of aggregate `{}` must be positive",
+            ty.name()
+        );
+    }
+
+    for field in ty.user_fields() {
+        if field.timestamp.is_some() {
+            bail!(
+                "aggregate `{}` cannot be marked with @timestamps",
+                ty.name()
+            );
+        }
+        if field.is_unique || field.is_version || field.count.is_some() {
+            bail!(
+                "field `{}` of aggregate `{}` cannot be marked with @unique, @version or @count",
+                field.name,
+                ty.name()
+            );
+        }
+        let spec = match &field.aggregate {
+            Some(spec) => spec,
+            None => {
+                // fields without an aggregate function are grouped by
+                let source_field = source.get_field(&field.name).with_context(|| {
+                    format!(
+                        "field `{}` of aggregate `{}` is grouped by, but entity `{}` has no such field",
+                        field.name,
+                        ty.name(),
+                        source.name()
+                    )
+                })?;
+                if source_field.type_id.name() != field.type_id.name()
+                    || source_field.is_optional
+                    || field.is_optional
+                {
+                    bail!(
+                        "field `{}` of aggregate `{}` is grouped by, so it must have the same non-optional type as `{}.{}`",
+                        field.name,
+                        ty.name(),
+                        source.name(),
+                        field.name
+                    );
+                }
+                continue;
+            }
+        };
+
+        let source_field = match (&spec.source_field, spec.function) {
+            (None, AggregateFn::Count) => None,
+            (Some(name), function) if function != AggregateFn::Count => {
+                Some(source.get_field(name).with_context(|| {
+                    format!(
+                        "field `{}` of aggregate `{}` aggregates field `{}`, but entity `{}` has no such field",
+                        field.name,
+                        ty.name(),
+                        name,
+                        source.name()
+                    )
+                })?)
+            }
+            _ => bail!(
+                "field `{}` of aggregate `{}` has an invalid aggregate definition",
+                field.name,
+                ty.name()
+            ),
+        };
+        // integers and decimals are aggregated into fields of the same type, so sums stay exact
+        let is_exact = |type_id: &TypeId| matches!(type_id, TypeId::Int64 | TypeId::Decimal { .. });
+        let valid = match (spec.function, source_field) {
+            (AggregateFn::Count, _) => field.type_id == TypeId::Float && !field.is_optional,
+            (AggregateFn::Sum, Some(source_field)) => {
+                (source_field.type_id == TypeId::Float && field.type_id == TypeId::Float
+                    || is_exact(&source_field.type_id) && source_field.type_id == field.type_id)
+                    && !field.is_optional
+            }
+            (AggregateFn::Avg | AggregateFn::Min | AggregateFn::Max, Some(source_field)) => {
+                matches!(spec.function, AggregateFn::Min | AggregateFn::Max)
+                    && source_field.type_id == TypeId::JsDate
+                    && field.type_id == TypeId::JsDate
+                    || source_field.type_id == TypeId::Float && field.type_id == TypeId::Float
+                    || is_exact(&source_field.type_id) && source_field.type_id == field.type_id
+            }
+            _ => false,
+        };
+        if !valid {
+            bail!(
+                "field `{}` of aggregate `{}` is of type {}, which does not fit {}({})",
+                field.name,
+                ty.name(),
+                field.type_id.name(),
+                spec.function.as_str(),
+                spec.source_field.as_deref().unwrap_or_default()
+            );
+        }
+        // avg, min and max are null if all aggregated values are missing
+        if matches!(
+            spec.function,
+            AggregateFn::Avg | AggregateFn::Min | AggregateFn::Max
+        ) && source_field.map_or(false, |f| f.is_optional)
+            && !field.is_optional
+        {
+            bail!(
+                "field `{}` of aggregate `{}` must be optional, because it aggregates the optional field `{}.{}`",
+                field.name,
+                ty.name(),
+                source.name(),
+                spec.source_field.as_deref().unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Archived tables are kept next to the live ones, in a namespace that cannot clash with them.
+fn archived_table_name(ty: &ObjectType) -> String {
+    format!("__archived_{}", ty.backing_table())
+}
+
+/// Drops the archived tables that are older than the retention period.
+pub async fn remove_expired_archives(server: &Server) -> Result<()> {
+    let retention_s = server.opt.archive_retention_s as i64;
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let meta = &server.meta_service;
+    for archived in meta.load_archived_entities().await? {
+        if archived.archived_at + retention_s > now {
+            continue;
+        }
+        let mut transaction = meta.begin_transaction().await?;
+        server
+            .query_engine
+            .drop_archived_table(&mut transaction, &archived.table_name)
+            .await?;
+        MetaService::remove_archived_entity(&mut transaction, &archived.table_name).await?;
+        MetaService::commit_transaction(transaction).await?;
+        log::info!(
+            "Dropped archived table of {}.{}",
+            archived.version_id,
+            archived.entity_name
+        );
+    }
+    Ok(())
+}
+
+/// Model (`Name`), field (`Name.field`) or value of an enum field (`Name.field=value`) that still
+/// has data, but would be dropped by an apply.
+struct DroppedItem {
+    name: String,
+    rows: i64,
+}
+
+/// Checks that the user has approved every destructive change, either one by one with
+/// `--allow-drop`, or all of them with `--allow-type-deletion`.
+fn check_drops(apply_request: &ApplyRequest, drops: &[DroppedItem]) -> Result<()> {
+    if apply_request.allow_type_deletion {
+        return Ok(());
+    }
+    let allowed = apply_request
+        .allowed_drops
+        .iter()
+        .map(String::as_str)
+        .collect::<HashSet<_>>();
+    let unapproved = drops
+        .iter()
+        .filter(|d| !allowed.contains(d.name.as_str()))
+        .collect::<Vec<_>>();
+    if unapproved.is_empty() {
+        return Ok(());
+    }
+
+    let s = unapproved
+        .iter()
+        .map(|d| format!("\t{} ({} elements)", d.name, d.rows))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let flags = unapproved
+        .iter()
+        .map(|d| format!("--allow-drop {}", d.name))
+        .collect::<Vec<_>>()
+        .join(" ");
+    bail!(
+        r"Trying to drop models or fields that still have data:
+{}
+
+To proceed, approve each of them explicitly:
+
+'npx chisel apply {}' (if installed from npm)
+
+or
+
+'chisel apply {}' (otherwise)",
+        s,
+        flags,
+        flags
+    );
+}
+
 fn aggregate_indexes(indexes: &Vec<IndexCandidate>) -> HashMap<String, Vec<DbIndex>> {
     let mut index_map = HashMap::<String, Vec<DbIndex>>::new();
     for candidate in indexes {
@@ -372,9 +1334,13 @@ impl TypeEnum {
         let is_builtin = match self {
             TypeEnum::String(_)
             | TypeEnum::Number(_)
+            | TypeEnum::Bigint(_)
             | TypeEnum::Bool(_)
             | TypeEnum::JsDate(_)
             | TypeEnum::ArrayBuffer(_)
+            | TypeEnum::Json(_)
+            | TypeEnum::GeoPoint(_)
+            | TypeEnum::Decimal(_)
             | TypeEnum::EntityId(_) => true,
             TypeEnum::Entity(name) => ts.lookup_builtin_type(name).is_ok(),
             TypeEnum::Array(inner) => inner.value_type()?.is_builtin(ts)?,
@@ -386,9 +1352,24 @@ impl TypeEnum {
         let ty = match self {
             TypeEnum::String(_) => Type::String,
             TypeEnum::Number(_) => Type::Float,
+            TypeEnum::Bigint(_) => Type::Int64,
             TypeEnum::Bool(_) => Type::Boolean,
             TypeEnum::JsDate(_) => Type::JsDate,
             TypeEnum::ArrayBuffer(_) => Type::ArrayBuffer,
+            TypeEnum::Json(_) => Type::Json,
+            TypeEnum::GeoPoint(_) => Type::GeoPoint,
+            TypeEnum::Decimal(DecimalType { precision, scale }) => {
+                if *precision == 0 || *precision > decimal::MAX_PRECISION || scale > precision {
+                    bail!(
+                        "Decimal<{precision}, {scale}> is not supported: the precision must be between 1 and {}, and the scale must not exceed it",
+                        decimal::MAX_PRECISION
+                    );
+                }
+                Type::Decimal {
+                    precision: *precision,
+                    scale: *scale,
+                }
+            }
             TypeEnum::Entity(name) => ts.lookup_builtin_type(name)?,
             TypeEnum::EntityId(entity_name) => Type::EntityId(entity_name.to_owned()),
             TypeEnum::Array(inner) => Type::Array(Box::new(inner.value_type()?.get_builtin(ts)?)),
@@ -401,10 +1382,16 @@ impl From<Type> for TypeMsg {
     fn from(ty: Type) -> TypeMsg {
         let ty = match ty {
             Type::Float => TypeEnum::Number(true),
+            Type::Int64 => TypeEnum::Bigint(true),
             Type::String => TypeEnum::String(true),
             Type::Boolean => TypeEnum::Bool(true),
             Type::JsDate => TypeEnum::JsDate(true),
             Type::ArrayBuffer => TypeEnum::ArrayBuffer(true),
+            Type::Json => TypeEnum::Json(true),
+            Type::GeoPoint => TypeEnum::GeoPoint(true),
+            Type::Decimal { precision, scale } => {
+                TypeEnum::Decimal(DecimalType { precision, scale })
+            }
             Type::Entity(entity) => TypeEnum::Entity(entity.name().to_owned()),
             Type::EntityId(entity_name) => TypeEnum::EntityId(entity_name),
             Type::Array(elem_type) => {
@@ -0,0 +1,301 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Plan of a dry-run apply.
+//!
+//! The plan lists the changes that the apply would make to the database, so that `chisel apply
+//! --dry-run` can show them either as text or as JSON. Destructive steps are marked, and the plan
+//! also lists the changes that the apply would reject until the user approves them (with
+//! `--allow-drop` or `--unlock`).
+
+use serde::Serialize;
+
+use crate::types::{ObjectDelta, ObjectType};
+
+/// Change of the database made by an apply.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "step")]
+pub enum PlanStep {
+    AddTable {
+        entity: String,
+    },
+    DropTable {
+        entity: String,
+    },
+    /// Renames an entity marked with `@renamedFrom`, keeping its table and data.
+    RenameTable {
+        from: String,
+        to: String,
+    },
+    /// The table is kept under another name, see `chisel apply --archive`.
+    ArchiveTable {
+        entity: String,
+    },
+    AddColumn {
+        entity: String,
+        field: String,
+    },
+    DropColumn {
+        entity: String,
+        field: String,
+    },
+    ChangeColumn {
+        entity: String,
+        field: String,
+    },
+    /// Renames a field marked with `@renamedFrom`, keeping its data.
+    RenameColumn {
+        entity: String,
+        from: String,
+        to: String,
+    },
+    AddIndex {
+        entity: String,
+        fields: Vec<String>,
+    },
+    DropIndex {
+        entity: String,
+        fields: Vec<String>,
+    },
+    ChangeAggregate {
+        entity: String,
+    },
+    Lock {
+        entity: String,
+    },
+    Unlock {
+        entity: String,
+    },
+    SetOwner {
+        entity: String,
+        owner: Option<String>,
+    },
+    /// Fills a new `@count` field from the existing rows.
+    CopyCount {
+        entity: String,
+        field: String,
+    },
+    /// Fills a new timestamp field of the existing rows with the current time.
+    CopyTimestamp {
+        entity: String,
+        field: String,
+    },
+}
+
+impl PlanStep {
+    /// Returns true if the step loses data.
+    pub fn is_destructive(&self) -> bool {
+        matches!(
+            self,
+            PlanStep::DropTable { .. } | PlanStep::DropColumn { .. }
+        )
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            PlanStep::AddTable { entity } => format!("create entity {}", entity),
+            PlanStep::DropTable { entity } => format!("drop entity {}", entity),
+            PlanStep::RenameTable { from, to } => format!("rename entity {} to {}", from, to),
+            PlanStep::ArchiveTable { entity } => format!("archive entity {}", entity),
+            PlanStep::AddColumn { entity, field } => format!("add field {}.{}", entity, field),
+            PlanStep::DropColumn { entity, field } => {
+                format!("remove field {}.{}", entity, field)
+            }
+            PlanStep::ChangeColumn { entity, field } => {
+                format!("change field {}.{}", entity, field)
+            }
+            PlanStep::RenameColumn { entity, from, to } => {
+                format!("rename field {}.{} to {}", entity, from, to)
+            }
+            PlanStep::AddIndex { entity, fields } => {
+                format!("create index on {}({})", entity, fields.join(", "))
+            }
+            PlanStep::DropIndex { entity, fields } => {
+                format!("drop index on {}({})", entity, fields.join(", "))
+            }
+            PlanStep::ChangeAggregate { entity } => {
+                format!("change the aggregate definition of {}", entity)
+            }
+            PlanStep::Lock { entity } => format!("lock {}", entity),
+            PlanStep::Unlock { entity } => format!("unlock {}", entity),
+            PlanStep::SetOwner {
+                entity,
+                owner: Some(owner),
+            } => format!("restrict {} to the owners in {}", entity, owner),
+            PlanStep::SetOwner {
+                entity,
+                owner: None,
+            } => format!("remove the owner restriction of {}", entity),
+            PlanStep::CopyCount { entity, field } => {
+                format!("compute {}.{} from the existing data", entity, field)
+            }
+            PlanStep::CopyTimestamp { entity, field } => format!(
+                "set {}.{} of the existing rows to the current time",
+                entity, field
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonStep<'a> {
+    #[serde(flatten)]
+    step: &'a PlanStep,
+    destructive: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonPlan<'a> {
+    steps: Vec<JsonStep<'a>>,
+    warnings: &'a [String],
+    rejected: &'a [String],
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MigrationPlan {
+    pub steps: Vec<PlanStep>,
+    /// Changes that need attention, like drops of data that the user has approved.
+    pub warnings: Vec<String>,
+    /// Changes that the apply would refuse to make.
+    pub rejected: Vec<String>,
+}
+
+impl MigrationPlan {
+    pub fn push(&mut self, step: PlanStep) {
+        self.steps.push(step);
+    }
+
+    /// Adds the steps that change the entity `old` by `delta`.
+    pub fn push_update(&mut self, old: &ObjectType, delta: &ObjectDelta) {
+        let entity = old.name().to_owned();
+        for field in delta.added_fields.iter() {
+            self.push(PlanStep::AddColumn {
+                entity: entity.clone(),
+                field: field.name.clone(),
+            });
+        }
+        for rename in delta.renamed_fields.iter() {
+            self.push(PlanStep::RenameColumn {
+                entity: entity.clone(),
+                from: rename.old_name.clone(),
+                to: rename.new_name.clone(),
+            });
+        }
+        for field in delta.removed_fields.iter() {
+            self.push(PlanStep::DropColumn {
+                entity: entity.clone(),
+                field: field.name.clone(),
+            });
+        }
+        for field_delta in delta.updated_fields.iter() {
+            if field_delta.attrs.is_none() && field_delta.labels.is_none() {
+                continue;
+            }
+            if let Some(field) = old.user_fields().find(|f| f.id == Some(field_delta.id)) {
+                self.push(PlanStep::ChangeColumn {
+                    entity: entity.clone(),
+                    field: field.name.clone(),
+                });
+            }
+        }
+        for index in delta.added_indexes.iter() {
+            self.push(PlanStep::AddIndex {
+                entity: entity.clone(),
+                fields: index.fields.clone(),
+            });
+        }
+        for index in delta.removed_indexes.iter() {
+            self.push(PlanStep::DropIndex {
+                entity: entity.clone(),
+                fields: index.fields.clone(),
+            });
+        }
+        if delta.aggregate.as_ref() != old.aggregate() {
+            self.push(PlanStep::ChangeAggregate {
+                entity: entity.clone(),
+            });
+        }
+        if delta.locked != old.is_locked() {
+            self.push(match delta.locked {
+                true => PlanStep::Lock {
+                    entity: entity.clone(),
+                },
+                false => PlanStep::Unlock {
+                    entity: entity.clone(),
+                },
+            });
+        }
+        if delta.owned_by.as_deref() != old.owned_by() {
+            self.push(PlanStep::SetOwner {
+                entity,
+                owner: delta.owned_by.clone(),
+            });
+        }
+    }
+
+    /// Human-readable plan, one step per line.
+    pub fn describe(&self) -> Vec<String> {
+        let steps = self.steps.iter().map(PlanStep::describe);
+        let warnings = self.warnings.iter().map(|w| format!("warning: {}", w));
+        let rejected = self.rejected.iter().map(|r| format!("rejected: {}", r));
+        steps.chain(warnings).chain(rejected).collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        let steps = self
+            .steps
+            .iter()
+            .map(|step| JsonStep {
+                step,
+                destructive: step.is_destructive(),
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_string_pretty(&JsonPlan {
+            steps,
+            warnings: &self.warnings,
+            rejected: &self.rejected,
+        })
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_marks_destructive_steps() {
+        let plan = MigrationPlan {
+            steps: vec![
+                PlanStep::AddTable {
+                    entity: "Company".into(),
+                },
+                PlanStep::DropColumn {
+                    entity: "Person".into(),
+                    field: "age".into(),
+                },
+            ],
+            warnings: vec![],
+            rejected: vec!["Trying to drop models or fields that still have data".into()],
+        };
+        let json: serde_json::Value = serde_json::from_str(&plan.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "steps": [
+                    {"step": "AddTable", "entity": "Company", "destructive": false},
+                    {"step": "DropColumn", "entity": "Person", "field": "age", "destructive": true},
+                ],
+                "warnings": [],
+                "rejected": ["Trying to drop models or fields that still have data"],
+            })
+        );
+        assert_eq!(
+            plan.describe(),
+            vec![
+                "create entity Company",
+                "remove field Person.age",
+                "rejected: Trying to drop models or fields that still have data",
+            ]
+        );
+    }
+}
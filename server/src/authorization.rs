@@ -1,5 +1,6 @@
 use http::request::Parts;
 use sqlx::Row;
+use subtle::ConstantTimeEq;
 
 use crate::authentication::Authentication;
 use crate::datastore::engine::SqlWithArguments;
@@ -22,6 +23,15 @@ const AUTH_ENTITY_NAMES: [&str; 4] = [
     AUTH_ACCOUNT_NAME,
 ];
 
+/// Header that requests execution of the request in a sandbox (see [`authorize_sandbox`]).
+pub const SANDBOX_HEADER: &str = "x-chisel-sandbox";
+/// Header that must contain the `CHISEL_ADMIN_SECRET` secret to use admin-only features outside
+/// of debug mode.
+pub const ADMIN_SECRET_HEADER: &str = "x-chisel-admin-secret";
+/// Header that asks for the trace of the policy decisions of the request (see
+/// [`authorize_policy_trace`]). The response returns the trace in the same header.
+pub const POLICY_TRACE_HEADER: &str = "x-chisel-policy-trace";
+
 pub fn is_auth_entity_name(entity_name: &str) -> bool {
     AUTH_ENTITY_NAMES.contains(&entity_name)
 }
@@ -78,7 +88,7 @@ async fn authorize_user_id(
     let user_id = authentication.user_id();
     let username = get_username_from_id(server, version, user_id).await;
     if !version
-        .policy_system
+        .policy_system()
         .user_authorization
         .is_allowed(username.as_deref(), routing_path)
     {
@@ -99,7 +109,7 @@ pub async fn authorize(
 
     let secrets = server.secrets.read();
     if !version
-        .policy_system
+        .policy_system()
         .secret_authorization
         .is_allowed(req_parts, &secrets, routing_path)
     {
@@ -108,3 +118,73 @@ pub async fn authorize(
 
     Ok(())
 }
+
+/// Checks whether the request asks to be executed in a sandbox, using the header
+/// `X-Chisel-Sandbox: 1`. Sandboxed requests are executed normally, but their transaction is
+/// always rolled back, so they can be used to safely test mutations.
+///
+/// The sandbox is only available when chiseld runs in debug mode, or when the request carries
+/// the `CHISEL_ADMIN_SECRET` secret in the `X-Chisel-Admin-Secret` header. Requests that ask for a
+/// sandbox without being allowed to are rejected, instead of being executed for real.
+pub fn authorize_sandbox(server: &Server, req_parts: &Parts) -> Result<bool> {
+    let sandbox = flag_header(req_parts, SANDBOX_HEADER)?;
+    if !sandbox || is_admin(server, req_parts) {
+        return Ok(sandbox);
+    }
+    forbidden!(
+        "Sandbox mode is only available in debug mode or with a valid {} header",
+        ADMIN_SECRET_HEADER
+    );
+}
+
+/// Checks whether the request asks for the trace of its policy decisions in the
+/// `X-Chisel-Policy-Trace` header.
+///
+/// The trace tells which rows the policies hid or changed, so, like the sandbox, it is only
+/// available in debug mode or with the `CHISEL_ADMIN_SECRET` secret.
+pub fn authorize_policy_trace(server: &Server, req_parts: &Parts) -> Result<bool> {
+    let policy_trace = flag_header(req_parts, POLICY_TRACE_HEADER)?;
+    if !policy_trace || is_admin(server, req_parts) {
+        return Ok(policy_trace);
+    }
+    forbidden!(
+        "Policy traces are only available in debug mode or with a valid {} header",
+        ADMIN_SECRET_HEADER
+    );
+}
+
+/// Parses a header that enables a feature with `1` or `true`; a missing header disables it.
+fn flag_header(req_parts: &Parts, name: &str) -> Result<bool> {
+    match req_parts.headers.get(name) {
+        None => Ok(false),
+        Some(value) => match value.to_str().map(str::trim) {
+            Ok("1") | Ok("true") => Ok(true),
+            Ok("0") | Ok("false") => Ok(false),
+            _ => bad_request!("Header {} must be either 1 or 0", name),
+        },
+    }
+}
+
+/// Checks whether the request may use admin-only features: chiseld runs in debug mode, or the
+/// request carries the `CHISEL_ADMIN_SECRET` secret in the `X-Chisel-Admin-Secret` header.
+pub fn is_admin(server: &Server, req_parts: &Parts) -> bool {
+    server.current_opt.read().debug || has_admin_secret(server, req_parts)
+}
+
+/// Checks whether the request carries the `CHISEL_ADMIN_SECRET` secret in the
+/// `X-Chisel-Admin-Secret` header. Unlike [`is_admin`], this does not hold for all requests in
+/// debug mode, so it is used for the restrictions that must also apply during development (such
+/// as the owners of `@ownedBy` entities). The secret is compared in constant time, so that it
+/// cannot be guessed from the response times.
+pub fn has_admin_secret(server: &Server, req_parts: &Parts) -> bool {
+    let secrets = server.secrets.read();
+    let admin_secret = secrets.get("CHISEL_ADMIN_SECRET").and_then(|s| s.as_str());
+    let given_secret = req_parts
+        .headers
+        .get(ADMIN_SECRET_HEADER)
+        .and_then(|value| value.to_str().ok());
+    match (admin_secret, given_secret) {
+        (Some(expected), Some(given)) => expected.as_bytes().ct_eq(given.as_bytes()).into(),
+        _ => false,
+    }
+}
@@ -0,0 +1,367 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Point-in-time backups of the database (`--backup-dir`) and their restore (`chisel restore`).
+//!
+//! The metadata and the data of all versions live in the same database, so a backup is a single
+//! consistent snapshot of that database, stored in its own directory together with a
+//! `backup.json` manifest. SQLite databases are snapshotted with `VACUUM INTO`, Postgres databases
+//! with `pg_dump` (and restored with `pg_restore`).
+
+use crate::datastore::meta::LATEST_SCHEMA_VERSION;
+use crate::datastore::{DbConnection, MetaService};
+use crate::server::extract_sqlite_file;
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::fs;
+use tokio::process::Command;
+
+/// Name of the manifest file in the directory of a backup.
+pub const MANIFEST_FILE: &str = "backup.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupFormat {
+    /// A copy of the SQLite database file.
+    Sqlite,
+    /// A `pg_dump` archive in the custom format.
+    Postgres,
+}
+
+impl BackupFormat {
+    fn of_db_uri(db_uri: &str) -> Result<Self> {
+        if db_uri.starts_with("sqlite:") {
+            Ok(BackupFormat::Sqlite)
+        } else if db_uri.starts_with("postgres:") || db_uri.starts_with("postgresql:") {
+            Ok(BackupFormat::Postgres)
+        } else {
+            bail!("Backups of database {:?} are not supported", db_uri)
+        }
+    }
+
+    fn snapshot_file(&self) -> &'static str {
+        match self {
+            BackupFormat::Sqlite => "database.sqlite",
+            BackupFormat::Postgres => "database.pgdump",
+        }
+    }
+}
+
+/// Contents of the manifest of a backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format: BackupFormat,
+    /// Version of the database schema when the backup was created. Backups can only be restored
+    /// by a chiseld that knows this version.
+    pub schema_version: String,
+    /// Unix timestamp (in seconds) of the backup.
+    pub created_at: i64,
+    /// Version of the chiseld that created the backup.
+    pub chiseld_version: String,
+}
+
+/// Creates backups of the database of the server in the backup directory.
+pub(crate) struct BackupService {
+    db: Arc<DbConnection>,
+    db_uri: String,
+    backup_dir: PathBuf,
+    /// Backups are created one at a time.
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl BackupService {
+    pub fn new(db: Arc<DbConnection>, db_uri: String, backup_dir: PathBuf) -> Self {
+        Self {
+            db,
+            db_uri,
+            backup_dir,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Creates a snapshot of the database and returns the directory of the backup.
+    pub async fn create_backup(&self) -> Result<(PathBuf, BackupManifest)> {
+        let _guard = self.lock.lock().await;
+        let format = BackupFormat::of_db_uri(&self.db_uri)?;
+        // the schema is only migrated when chiseld starts, so it cannot change under us
+        let schema_version = MetaService::new(self.db.clone()).schema_version().await?;
+        let now = OffsetDateTime::now_utc();
+
+        fs::create_dir_all(&self.backup_dir)
+            .await
+            .with_context(|| {
+                format!(
+                    "Could not create backup directory {}",
+                    self.backup_dir.display()
+                )
+            })?;
+        let dir = self.backup_dir.join(backup_name(now));
+        fs::create_dir(&dir)
+            .await
+            .with_context(|| format!("Could not create directory {}", dir.display()))?;
+
+        let manifest = BackupManifest {
+            format,
+            schema_version,
+            created_at: now.unix_timestamp(),
+            chiseld_version: env!("VERGEN_GIT_SEMVER_LIGHTWEIGHT").into(),
+        };
+        if let Err(err) = self.write_backup(&dir, &manifest).await {
+            // do not leave an incomplete backup behind
+            let _ = fs::remove_dir_all(&dir).await;
+            return Err(err);
+        }
+
+        log::info!("Created backup {}", dir.display());
+        Ok((dir, manifest))
+    }
+
+    async fn write_backup(&self, dir: &Path, manifest: &BackupManifest) -> Result<()> {
+        let snapshot = dir.join(manifest.format.snapshot_file());
+        match manifest.format {
+            BackupFormat::Sqlite => snapshot_sqlite(&self.db, &snapshot).await,
+            BackupFormat::Postgres => snapshot_postgres(&self.db_uri, &snapshot).await,
+        }
+        .context("Could not create a snapshot of the database")?;
+
+        // the manifest is written last, so a backup without a manifest is never complete
+        let manifest = serde_json::to_string_pretty(manifest)?;
+        fs::write(dir.join(MANIFEST_FILE), manifest).await?;
+        Ok(())
+    }
+}
+
+/// Name of the directory of a backup created at `time`, such as `20221017T093012Z`.
+fn backup_name(time: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+async fn snapshot_sqlite(db: &DbConnection, snapshot: &Path) -> Result<()> {
+    let snapshot = snapshot
+        .to_str()
+        .with_context(|| format!("Path {} is not in UTF-8", snapshot.display()))?;
+    // VACUUM INTO reads the database in a single transaction, so the copy is consistent even if
+    // there are concurrent writes
+    let query = format!("VACUUM INTO '{}'", snapshot.replace('\'', "''"));
+    sqlx::query(&query).execute(&db.pool).await?;
+    Ok(())
+}
+
+async fn snapshot_postgres(db_uri: &str, snapshot: &Path) -> Result<()> {
+    let mut cmd = Command::new("pg_dump");
+    cmd.arg("--format=custom")
+        .arg("--file")
+        .arg(snapshot)
+        .arg("--dbname")
+        .arg(db_uri);
+    run_command(cmd, "pg_dump").await
+}
+
+async fn run_command(mut cmd: Command, name: &str) -> Result<()> {
+    let output = cmd
+        .output()
+        .await
+        .with_context(|| format!("Could not run {}", name))?;
+    ensure!(
+        output.status.success(),
+        "{} failed ({}): {}",
+        name,
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
+
+/// Reads the manifest of the backup in directory `backup`.
+pub async fn read_manifest(backup: &Path) -> Result<BackupManifest> {
+    let path = backup.join(MANIFEST_FILE);
+    let manifest = fs::read_to_string(&path).await.with_context(|| {
+        format!(
+            "Could not read {} (is it a complete backup?)",
+            path.display()
+        )
+    })?;
+    serde_json::from_str(&manifest).with_context(|| format!("Invalid manifest {}", path.display()))
+}
+
+/// Restores the backup in directory `backup` into the database `db_uri`, which must not be used by
+/// a running chiseld. Unless `force` is set, the database must not exist (SQLite) or must be empty
+/// (Postgres). The restored database is migrated to the latest schema when chiseld starts.
+pub async fn restore_backup(backup: &Path, db_uri: &str, force: bool) -> Result<BackupManifest> {
+    let manifest = read_manifest(backup).await?;
+    check_schema_version(&manifest.schema_version)?;
+
+    let format = BackupFormat::of_db_uri(db_uri)?;
+    ensure!(
+        format == manifest.format,
+        "Cannot restore a {:?} backup into a {:?} database",
+        manifest.format,
+        format
+    );
+    let snapshot = backup.join(manifest.format.snapshot_file());
+    ensure!(
+        fs::metadata(&snapshot).await.is_ok(),
+        "Snapshot {} of the backup does not exist",
+        snapshot.display()
+    );
+
+    match format {
+        BackupFormat::Sqlite => restore_sqlite(&snapshot, &manifest, db_uri, force).await?,
+        BackupFormat::Postgres => restore_postgres(&snapshot, db_uri, force).await?,
+    }
+    Ok(manifest)
+}
+
+/// Checks that a backup with schema `version` can be migrated by this chiseld.
+fn check_schema_version(version: &str) -> Result<()> {
+    let latest: u32 = LATEST_SCHEMA_VERSION.parse().unwrap();
+    match version.parse::<u32>() {
+        Ok(version) if version <= latest => Ok(()),
+        Ok(version) => bail!(
+            "The backup has schema version {}, but this version of chiseld only supports schema versions up to {}; restore it with a newer version of chiseld",
+            version,
+            latest
+        ),
+        Err(_) => bail!("The backup has an unknown schema version {:?}", version),
+    }
+}
+
+async fn restore_sqlite(
+    snapshot: &Path,
+    manifest: &BackupManifest,
+    db_uri: &str,
+    force: bool,
+) -> Result<()> {
+    // check that the snapshot is an intact database with the schema from the manifest
+    let snapshot_uri = format!("sqlite://{}?mode=ro", snapshot.display());
+    let db = Arc::new(DbConnection::connect(&snapshot_uri, 1).await?);
+    let schema_version = MetaService::new(db.clone())
+        .schema_version()
+        .await
+        .with_context(|| format!("Could not read snapshot {}", snapshot.display()))?;
+    db.pool.close().await;
+    ensure!(
+        schema_version == manifest.schema_version,
+        "Snapshot {} has schema version {:?}, but the manifest says {:?}",
+        snapshot.display(),
+        schema_version,
+        manifest.schema_version
+    );
+
+    let target = PathBuf::from(
+        extract_sqlite_file(db_uri)
+            .with_context(|| format!("Database {:?} is not a SQLite file", db_uri))?,
+    );
+    if !force && fs::metadata(&target).await.is_ok() {
+        bail!(
+            "Database {} already exists, use --force to overwrite it",
+            target.display()
+        );
+    }
+
+    // copy the snapshot next to the target first, so that the target is replaced atomically
+    let mut tmp_target = target.clone().into_os_string();
+    tmp_target.push(".restore");
+    fs::copy(snapshot, &tmp_target).await?;
+    for suffix in ["-wal", "-shm"] {
+        let mut path = target.clone().into_os_string();
+        path.push(suffix);
+        match fs::remove_file(&path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    fs::rename(&tmp_target, &target).await?;
+    Ok(())
+}
+
+async fn restore_postgres(snapshot: &Path, db_uri: &str, force: bool) -> Result<()> {
+    let db = Arc::new(DbConnection::connect(db_uri, 1).await?);
+    let schema_version = MetaService::new(db.clone()).schema_version().await?;
+    db.pool.close().await;
+    if !force && schema_version != "empty" {
+        bail!("Database is not empty, use --force to overwrite it");
+    }
+
+    let mut cmd = Command::new("pg_restore");
+    cmd.arg("--clean")
+        .arg("--if-exists")
+        .arg("--no-owner")
+        .arg("--single-transaction")
+        .arg("--exit-on-error")
+        .arg("--dbname")
+        .arg(db_uri)
+        .arg(snapshot);
+    run_command(cmd, "pg_restore").await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    async fn sqlite_db(path: &Path) -> Result<Arc<DbConnection>> {
+        let uri = format!("sqlite://{}?mode=rwc", path.display());
+        Ok(Arc::new(DbConnection::connect(&uri, 1).await?))
+    }
+
+    #[tokio::test]
+    async fn backup_and_restore_sqlite() -> Result<()> {
+        let tmp_dir = TempDir::new("backup_and_restore_sqlite")?;
+        let db_path = tmp_dir.path().join("chiseld.db");
+        let db = sqlite_db(&db_path).await?;
+        MetaService::new(db.clone()).migrate_schema().await?;
+        sqlx::query("CREATE TABLE ui_Person (id TEXT PRIMARY KEY, name TEXT)")
+            .execute(&db.pool)
+            .await?;
+        sqlx::query("INSERT INTO ui_Person (id, name) VALUES ('1', 'alice')")
+            .execute(&db.pool)
+            .await?;
+
+        let db_uri = format!("sqlite://{}?mode=rwc", db_path.display());
+        let backups = BackupService::new(db, db_uri, tmp_dir.path().join("backups"));
+        let (backup, manifest) = backups.create_backup().await?;
+        assert_eq!(manifest.format, BackupFormat::Sqlite);
+        assert_eq!(manifest.schema_version, LATEST_SCHEMA_VERSION);
+        assert_eq!(
+            read_manifest(&backup).await?.created_at,
+            manifest.created_at
+        );
+
+        let restored_path = tmp_dir.path().join("restored.db");
+        let restored_uri = format!("sqlite://{}?mode=rwc", restored_path.display());
+        restore_backup(&backup, &restored_uri, false).await?;
+        let restored = sqlite_db(&restored_path).await?;
+        let (name,): (String,) = sqlx::query_as("SELECT name FROM ui_Person")
+            .fetch_one(&restored.pool)
+            .await?;
+        assert_eq!(name, "alice");
+
+        // the restored database exists now
+        restore_backup(&backup, &restored_uri, false)
+            .await
+            .unwrap_err();
+        restore_backup(&backup, &restored_uri, true).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn schema_versions() {
+        check_schema_version("2").unwrap();
+        check_schema_version(LATEST_SCHEMA_VERSION).unwrap();
+        let next = LATEST_SCHEMA_VERSION.parse::<u32>().unwrap() + 1;
+        check_schema_version(&next.to_string()).unwrap_err();
+        check_schema_version("0.7").unwrap_err();
+    }
+}
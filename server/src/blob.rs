@@ -0,0 +1,721 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Storage of blobs (`Chisel.blob`).
+//!
+//! Blobs are binary objects addressed by a bucket and a key. They are kept in a [`BlobStore`],
+//! which is either a directory on the local disk (`--blob-dir`) or an S3-compatible object store
+//! (`--blob-s3-endpoint`). Unlike `Chisel.kv`, the blobs are not part of the transaction of the
+//! request: a blob that was put is stored even if the request later fails. So sandboxed requests
+//! (including the mirrored ones) do not write the store at all: their puts and deletes succeed
+//! without effect.
+//!
+//! Signed URLs give temporary access to a blob to anyone who has the URL. The URLs of S3 are
+//! presigned requests to the object store; the URLs of the local store point to
+//! [`SIGNED_PATH`] of chiseld, which checks their signature before serving the blob.
+
+use crate::error::ResultExt;
+use crate::opt::Opt;
+use anyhow::{bail, ensure, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::fs;
+
+/// Path of the API server where the signed URLs of the local store are served.
+pub const SIGNED_PATH: &str = "/__chiselstrike/blob";
+
+/// Maximum validity of a signed URL, which is also the limit of S3.
+pub const MAX_SIGNED_URL_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Directory of the local store with the content types of the blobs. Bucket names cannot start
+/// with a dot, so it never clashes with a bucket.
+const CONTENT_TYPES_DIR: &str = ".content-types";
+/// Directory of the local store where the blobs are written before they are moved in place.
+const TMP_DIR: &str = ".tmp";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug)]
+pub struct Blob {
+    pub data: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+#[async_trait::async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores `data` under `key` of `bucket`, replacing the blob that was stored there.
+    async fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> Result<()>;
+
+    /// Returns the blob stored under `key` of `bucket`, if any.
+    async fn get(&self, bucket: &str, key: &str) -> Result<Option<Blob>>;
+
+    /// Deletes the blob stored under `key` of `bucket`. Returns false if there was no such blob.
+    async fn delete(&self, bucket: &str, key: &str) -> Result<bool>;
+
+    /// Returns a URL that gives read access to the blob under `key` of `bucket` for `expires_in`.
+    fn signed_url(&self, bucket: &str, key: &str, expires_in: Duration) -> Result<String>;
+
+    /// Checks the signature of a URL returned by `signed_url()` that is served by chiseld (at
+    /// [`SIGNED_PATH`]). Stores whose URLs point elsewhere never accept such URLs.
+    fn verify_signed_url(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        _expires: i64,
+        _signature: &str,
+    ) -> bool {
+        false
+    }
+}
+
+/// Creates the blob store configured by `--blob-dir` or `--blob-s3-endpoint`, if any.
+pub fn from_opt(opt: &Opt) -> Result<Option<Arc<dyn BlobStore>>> {
+    match (&opt.blob_dir, &opt.blob_s3_endpoint) {
+        (Some(_), Some(_)) => bail!("--blob-dir and --blob-s3-endpoint cannot be used together"),
+        (Some(dir), None) => Ok(Some(Arc::new(LocalBlobStore::new(dir.clone())))),
+        (None, Some(endpoint)) => {
+            let store =
+                S3BlobStore::new(endpoint, &opt.blob_s3_region, S3Credentials::from_env()?)?;
+            Ok(Some(Arc::new(store)))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Checks that `bucket` is a valid bucket name (the same rules as S3, without the dots).
+pub fn validate_bucket(bucket: &str) -> Result<()> {
+    ensure!(
+        (3..=63).contains(&bucket.len())
+            && bucket
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+            && !bucket.starts_with('-')
+            && !bucket.ends_with('-'),
+        "Invalid bucket name {:?}, bucket names have 3 to 63 characters, which are lowercase \
+        letters, digits and hyphens",
+        bucket
+    );
+    Ok(())
+}
+
+/// Checks that `key` is a valid key of a blob. Keys are paths separated by `/`, which must not
+/// contain empty, `.` or `..` segments, so that they map to the files of the local store.
+pub fn validate_key(key: &str) -> Result<()> {
+    ensure!(
+        !key.is_empty()
+            && key.len() <= 1024
+            && !key.contains(['\\', '\0'])
+            && key
+                .split('/')
+                .all(|segment| !matches!(segment, "" | "." | "..")),
+        "Invalid blob key {:?}",
+        key
+    );
+    Ok(())
+}
+
+/// Serves a signed URL of the local store, with `path` relative to [`SIGNED_PATH`].
+pub async fn handle_signed(
+    store: &dyn BlobStore,
+    request: &hyper::Request<hyper::Body>,
+    path: &str,
+) -> crate::error::Result<hyper::Response<hyper::Body>> {
+    if request.method() != hyper::Method::GET {
+        return Ok(hyper::Response::builder()
+            .status(hyper::StatusCode::METHOD_NOT_ALLOWED)
+            .header(hyper::header::ALLOW, "GET")
+            .body(hyper::Body::empty())
+            .unwrap());
+    }
+    let (bucket, key) = match path.strip_prefix('/').and_then(|path| path.split_once('/')) {
+        Some(found) => found,
+        None => bad_request!("the URL of a blob must contain its bucket and key"),
+    };
+    let key = urlencoding::decode(key).err_bad_request()?;
+    let params: std::collections::HashMap<_, _> =
+        form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes()).collect();
+    let expires = params
+        .get("expires")
+        .and_then(|expires| expires.parse().ok());
+    let signature = params.get("signature");
+    let verified = match (expires, signature) {
+        (Some(expires), Some(signature)) => {
+            store.verify_signed_url(bucket, &key, expires, signature)
+        }
+        _ => false,
+    };
+    if !verified {
+        forbidden!("the signature of the blob URL is invalid or it has expired");
+    }
+
+    let response = match store.get(bucket, &key).await.err_internal()? {
+        Some(blob) => hyper::Response::builder()
+            .header(
+                hyper::header::CONTENT_TYPE,
+                blob.content_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+            )
+            .body(hyper::Body::from(blob.data)),
+        None => hyper::Response::builder()
+            .status(hyper::StatusCode::NOT_FOUND)
+            .body(hyper::Body::from(format!("Unknown blob {bucket}/{key}"))),
+    };
+    Ok(response.unwrap())
+}
+
+/// Stores the blobs in a directory: the blob under `key` of `bucket` is the file `bucket/key`.
+///
+/// The signed URLs are relative to the API server and they are signed with a key that is
+/// generated at startup, so they are invalidated when chiseld restarts.
+pub struct LocalBlobStore {
+    dir: PathBuf,
+    signing_key: [u8; 32],
+}
+
+impl LocalBlobStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            signing_key: rand::random(),
+        }
+    }
+
+    fn data_path(&self, bucket: &str, key: &str) -> PathBuf {
+        self.dir.join(bucket).join(key)
+    }
+
+    fn content_type_path(&self, bucket: &str, key: &str) -> PathBuf {
+        self.dir.join(CONTENT_TYPES_DIR).join(bucket).join(key)
+    }
+
+    fn signature(&self, bucket: &str, key: &str, expires: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.signing_key).unwrap();
+        mac.update(format!("{bucket}/{key}\n{expires}").as_bytes());
+        mac
+    }
+}
+
+/// Writes `content` to `path` atomically, by renaming a temporary file in `tmp_dir`.
+async fn write_file(tmp_dir: &Path, path: &Path, content: &[u8]) -> Result<()> {
+    fs::create_dir_all(tmp_dir).await?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let tmp_path = tmp_dir.join(uuid::Uuid::new_v4().to_simple().to_string());
+    fs::write(&tmp_path, content).await?;
+    if let Err(err) = fs::rename(&tmp_path, path).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Removes the file at `path`. Returns false if there was no such file.
+async fn remove_file(path: &Path) -> Result<bool> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> Result<()> {
+        let tmp_dir = self.dir.join(TMP_DIR);
+        let path = self.data_path(bucket, key);
+        write_file(&tmp_dir, &path, &data)
+            .await
+            .with_context(|| format!("Could not write blob {}", path.display()))?;
+        let content_type_path = self.content_type_path(bucket, key);
+        match content_type {
+            Some(content_type) => {
+                write_file(&tmp_dir, &content_type_path, content_type.as_bytes()).await?
+            }
+            None => {
+                remove_file(&content_type_path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<Option<Blob>> {
+        let path = self.data_path(bucket, key);
+        match fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let data = fs::read(&path)
+            .await
+            .with_context(|| format!("Could not read blob {}", path.display()))?;
+        let content_type = match fs::read_to_string(self.content_type_path(bucket, key)).await {
+            Ok(content_type) => Some(content_type),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Some(Blob { data, content_type }))
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<bool> {
+        let deleted = remove_file(&self.data_path(bucket, key)).await?;
+        remove_file(&self.content_type_path(bucket, key)).await?;
+        Ok(deleted)
+    }
+
+    fn signed_url(&self, bucket: &str, key: &str, expires_in: Duration) -> Result<String> {
+        let expires = OffsetDateTime::now_utc().unix_timestamp() + expires_in.as_secs() as i64;
+        let signature = hex::encode(self.signature(bucket, key, expires).finalize().into_bytes());
+        Ok(format!(
+            "{SIGNED_PATH}/{bucket}/{}?expires={expires}&signature={signature}",
+            uri_encode(key, false)
+        ))
+    }
+
+    fn verify_signed_url(&self, bucket: &str, key: &str, expires: i64, signature: &str) -> bool {
+        let signature = match hex::decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        expires >= OffsetDateTime::now_utc().unix_timestamp()
+            && self
+                .signature(bucket, key, expires)
+                .verify_slice(&signature)
+                .is_ok()
+    }
+}
+
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl S3Credentials {
+    /// Reads the credentials from the `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment
+    /// variables.
+    fn from_env() -> Result<Self> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .with_context(|| format!("--blob-s3-endpoint requires the {name} variable"))
+        };
+        Ok(Self {
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+        })
+    }
+}
+
+/// Stores the blobs in an S3-compatible object store, in the buckets of the same names. The
+/// objects are addressed with path-style URLs (`{endpoint}/{bucket}/{key}`), which are supported
+/// by AWS as well as MinIO and other implementations, and the requests are signed with AWS
+/// Signature Version 4.
+pub struct S3BlobStore {
+    endpoint: url::Url,
+    region: String,
+    credentials: S3Credentials,
+    client: reqwest::Client,
+}
+
+/// Hash of the payload of presigned requests, which is unknown when the URL is signed.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+impl S3BlobStore {
+    pub fn new(endpoint: &str, region: &str, credentials: S3Credentials) -> Result<Self> {
+        let endpoint = url::Url::parse(endpoint)
+            .with_context(|| format!("Invalid --blob-s3-endpoint {:?}", endpoint))?;
+        ensure!(
+            matches!(endpoint.scheme(), "http" | "https") && endpoint.host_str().is_some(),
+            "--blob-s3-endpoint must be an HTTP(S) URL, not {:?}",
+            endpoint.as_str()
+        );
+        Ok(Self {
+            endpoint,
+            region: region.into(),
+            credentials,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Returns the value of the `Host` header of the requests, as sent by `reqwest`.
+    fn host(&self) -> String {
+        let host = self.endpoint.host_str().unwrap();
+        match self.endpoint.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.into(),
+        }
+    }
+
+    /// Returns the URI-encoded path of the object under `key` of `bucket`.
+    fn object_path(&self, bucket: &str, key: &str) -> String {
+        format!(
+            "{}/{bucket}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(key, false)
+        )
+    }
+
+    fn object_url(&self, path: &str) -> String {
+        format!("{}://{}{path}", self.endpoint.scheme(), self.host())
+    }
+
+    fn scope(&self, date: &str) -> String {
+        format!("{date}/{}/s3/aws4_request", self.region)
+    }
+
+    /// Signs a request and returns the hex-encoded signature. The `headers` must be sorted by
+    /// their (lowercase) names and the `query` must be canonical.
+    fn sign(
+        &self,
+        method: &str,
+        path: &str,
+        query: &str,
+        headers: &[(&str, &str)],
+        payload_hash: &str,
+        now: OffsetDateTime,
+    ) -> String {
+        let mut canonical_headers = String::new();
+        for (name, value) in headers {
+            writeln!(canonical_headers, "{name}:{}", value.trim()).unwrap();
+        }
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\n{canonical_headers}\n{}\n{payload_hash}",
+            signed_headers(headers)
+        );
+        let (date, amz_date) = amz_dates(now);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{}\n{:x}",
+            self.scope(&date),
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let key = signing_key(
+            &self.credentials.secret_access_key,
+            &date,
+            &self.region,
+            "s3",
+        );
+        hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()))
+    }
+
+    /// Sends a request for the object under `key` of `bucket`, signed in the `Authorization`
+    /// header.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        bucket: &str,
+        key: &str,
+        body: Option<(Vec<u8>, Option<&str>)>,
+    ) -> Result<reqwest::Response> {
+        let path = self.object_path(bucket, key);
+        let now = OffsetDateTime::now_utc();
+        let (date, amz_date) = amz_dates(now);
+        let host = self.host();
+        let (body, content_type) = body.unwrap_or_default();
+        let payload_hash = format!("{:x}", Sha256::digest(&body));
+
+        let mut headers = vec![];
+        if let Some(content_type) = content_type {
+            headers.push(("content-type", content_type));
+        }
+        headers.push(("host", &host));
+        headers.push(("x-amz-content-sha256", &payload_hash));
+        headers.push(("x-amz-date", &amz_date));
+        let signature = self.sign(method.as_str(), &path, "", &headers, &payload_hash, now);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{},SignedHeaders={},Signature={signature}",
+            self.credentials.access_key_id,
+            self.scope(&date),
+            signed_headers(&headers)
+        );
+
+        let mut request = self
+            .client
+            .request(method.clone(), self.object_url(&path))
+            .header("authorization", authorization)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("x-amz-date", &amz_date);
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+        let response =
+            request.body(body).send().await.with_context(|| {
+                format!("Could not send {method} request for blob {bucket}/{key}")
+            })?;
+
+        let status = response.status();
+        if status.is_success() || status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(response);
+        }
+        let text = response.text().await.unwrap_or_default();
+        bail!(
+            "{method} request for blob {bucket}/{key} failed with status {status}: {}",
+            text.trim()
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> Result<()> {
+        let response = self
+            .send(
+                reqwest::Method::PUT,
+                bucket,
+                key,
+                Some((data, content_type)),
+            )
+            .await?;
+        ensure!(
+            response.status().is_success(),
+            "Bucket {:?} does not exist in the object store",
+            bucket
+        );
+        Ok(())
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<Option<Blob>> {
+        let response = self.send(reqwest::Method::GET, bucket, key, None).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let data = response.bytes().await?.to_vec();
+        Ok(Some(Blob { data, content_type }))
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<bool> {
+        // S3 deletes missing objects successfully, so their existence is checked first
+        let response = self.send(reqwest::Method::HEAD, bucket, key, None).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        self.send(reqwest::Method::DELETE, bucket, key, None)
+            .await?;
+        Ok(true)
+    }
+
+    fn signed_url(&self, bucket: &str, key: &str, expires_in: Duration) -> Result<String> {
+        let path = self.object_path(bucket, key);
+        let now = OffsetDateTime::now_utc();
+        let (date, amz_date) = amz_dates(now);
+        let host = self.host();
+        let credential = format!("{}/{}", self.credentials.access_key_id, self.scope(&date));
+        // the parameters are sorted by name, as the canonical query requires
+        let params = [
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".into()),
+            ("X-Amz-Credential", credential),
+            ("X-Amz-Date", amz_date),
+            ("X-Amz-Expires", expires_in.as_secs().to_string()),
+            ("X-Amz-SignedHeaders", "host".into()),
+        ];
+        let query = params
+            .iter()
+            .map(|(name, value)| format!("{name}={}", uri_encode(value, true)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let signature = self.sign(
+            "GET",
+            &path,
+            &query,
+            &[("host", &host)],
+            UNSIGNED_PAYLOAD,
+            now,
+        );
+        Ok(format!(
+            "{}?{query}&X-Amz-Signature={signature}",
+            self.object_url(&path)
+        ))
+    }
+}
+
+/// Returns the date (`YYYYMMDD`) and the timestamp (`YYYYMMDDTHHMMSSZ`) of `now`, in the formats
+/// of AWS Signature Version 4.
+fn amz_dates(now: OffsetDateTime) -> (String, String) {
+    let date = format!(
+        "{:04}{:02}{:02}",
+        now.year(),
+        u8::from(now.month()),
+        now.day()
+    );
+    let amz_date = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    (date, amz_date)
+}
+
+fn signed_headers(headers: &[(&str, &str)]) -> String {
+    headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Derives the key that signs the requests of `service` in `region` on `date`.
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(
+        format!("AWS4{secret_access_key}").as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// Percent-encodes everything except the unreserved characters (and `/`, unless
+/// `encode_slash`), as AWS Signature Version 4 requires.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => write!(encoded, "%{b:02X}").unwrap(),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(validate_bucket("avatars").is_ok());
+        assert!(validate_bucket("user-2-files").is_ok());
+        for bucket in [
+            "ab",
+            "Avatars",
+            "-avatars",
+            "avatars-",
+            ".content-types",
+            "a_b",
+        ] {
+            assert!(validate_bucket(bucket).is_err(), "{bucket:?}");
+        }
+        assert!(validate_key("alice/photo 1.png").is_ok());
+        for key in ["", "/alice", "alice/", "a//b", "a/../b", "./a", "a\\b"] {
+            assert!(validate_key(key).is_err(), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(
+            uri_encode("alice/photo 1.png", false),
+            "alice/photo%201.png"
+        );
+        assert_eq!(uri_encode("a/b~c+d", true), "a%2Fb~c%2Bd");
+        assert_eq!(uri_encode("ž", true), "%C5%BE");
+    }
+
+    #[test]
+    fn aws_signing_key() {
+        // example from the AWS documentation of Signature Version 4
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn presigned_url() {
+        let store = S3BlobStore::new(
+            "http://localhost:9000",
+            "eu-west-1",
+            S3Credentials {
+                access_key_id: "AKID".into(),
+                secret_access_key: "secret".into(),
+            },
+        )
+        .unwrap();
+        let url = store
+            .signed_url("avatars", "alice/photo 1.png", Duration::from_secs(60))
+            .unwrap();
+        let url = url::Url::parse(&url).unwrap();
+        assert_eq!(url.path(), "/avatars/alice/photo%201.png");
+        let params: std::collections::HashMap<_, _> = url.query_pairs().collect();
+        assert_eq!(params["X-Amz-Expires"], "60");
+        assert_eq!(params["X-Amz-SignedHeaders"], "host");
+        assert!(params["X-Amz-Credential"].starts_with("AKID/"));
+        assert!(params["X-Amz-Credential"].ends_with("/eu-west-1/s3/aws4_request"));
+        assert_eq!(params["X-Amz-Signature"].len(), 64);
+    }
+
+    #[test]
+    fn local_signed_url() {
+        let store = LocalBlobStore::new("blobs".into());
+        let url = store
+            .signed_url("avatars", "alice/photo 1.png", Duration::from_secs(60))
+            .unwrap();
+        let url = url::Url::parse("http://localhost")
+            .unwrap()
+            .join(&url)
+            .unwrap();
+        assert_eq!(
+            url.path(),
+            "/__chiselstrike/blob/avatars/alice/photo%201.png"
+        );
+        let params: std::collections::HashMap<_, _> = url.query_pairs().collect();
+        let expires: i64 = params["expires"].parse().unwrap();
+        let signature = &params["signature"];
+        let key = "alice/photo 1.png";
+        assert!(store.verify_signed_url("avatars", key, expires, signature));
+        assert!(!store.verify_signed_url("avatars", "alice/other.png", expires, signature));
+        assert!(!store.verify_signed_url("avatars", key, expires + 1, signature));
+        assert!(!store.verify_signed_url("avatars", key, expires, "zz"));
+
+        let past = OffsetDateTime::now_utc().unix_timestamp() - 1;
+        let mac = store
+            .signature("avatars", key, past)
+            .finalize()
+            .into_bytes();
+        assert!(!store.verify_signed_url("avatars", key, past, &hex::encode(mac)));
+    }
+}
@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Cancellation of the jobs of HTTP requests.
+//!
+//! The HTTP layer holds a `CancelGuard` while it waits for the response of a job. If the client
+//! disconnects (hyper drops the future of the request) or the deadline of the request expires,
+//! the guard is dropped without being disarmed and the `CancelToken` that was passed to the worker
+//! with the job is cancelled. The worker ops use the token to abort in-flight queries and
+//! outbound fetches.
+
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Creates a guard and the token that is cancelled when the guard is dropped, unless it is
+/// disarmed first. `deadline` is the time when the job will be cancelled at the latest.
+pub(crate) fn cancel_pair(deadline: Option<Instant>) -> (CancelGuard, CancelToken) {
+    let (tx, rx) = watch::channel(false);
+    let guard = CancelGuard { tx: Some(tx) };
+    let token = CancelToken { rx, deadline };
+    (guard, token)
+}
+
+/// Cancels the corresponding `CancelToken` when dropped.
+#[derive(Debug)]
+pub(crate) struct CancelGuard {
+    tx: Option<watch::Sender<bool>>,
+}
+
+impl CancelGuard {
+    /// Drops the guard without cancelling the token, because the job has finished.
+    pub fn disarm(mut self) {
+        self.tx.take();
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            let _: Result<_, _> = tx.send(true);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    rx: watch::Receiver<bool>,
+    deadline: Option<Instant>,
+}
+
+impl Default for CancelToken {
+    /// Returns a token that is never cancelled.
+    fn default() -> Self {
+        let (_tx, rx) = watch::channel(false);
+        CancelToken { rx, deadline: None }
+    }
+}
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Time that is left until the deadline of the job, if it has one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Waits until the guard is dropped; returns true if the job was cancelled and false if the
+    /// guard was disarmed.
+    pub async fn finished(&self) -> bool {
+        let mut rx = self.rx.clone();
+        loop {
+            if *rx.borrow() {
+                return true;
+            }
+            if rx.changed().await.is_err() {
+                return *rx.borrow();
+            }
+        }
+    }
+
+    /// Resolves when the job is cancelled, never resolves if it finishes normally.
+    pub async fn cancelled(&self) {
+        if !self.finished().await {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn dropped_guard_cancels() {
+        let (guard, token) = cancel_pair(None);
+        assert!(!token.is_cancelled());
+        drop(guard);
+        assert!(token.is_cancelled());
+        assert!(token.finished().await);
+    }
+
+    #[tokio::test]
+    async fn disarmed_guard_does_not_cancel() {
+        let (guard, token) = cancel_pair(None);
+        guard.disarm();
+        assert!(!token.is_cancelled());
+        assert!(!token.finished().await);
+    }
+
+    #[tokio::test]
+    async fn default_token_is_never_cancelled() {
+        let token = CancelToken::default();
+        assert!(!token.is_cancelled());
+        assert!(!token.finished().await);
+    }
+
+    #[test]
+    fn remaining_time_saturates() {
+        let (_guard, token) = cancel_pair(Some(Instant::now()));
+        assert_eq!(token.remaining(), Some(Duration::ZERO));
+        let (_guard, token) = cancel_pair(None);
+        assert_eq!(token.remaining(), None);
+    }
+}
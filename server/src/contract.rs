@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Example requests and responses of the routes of a version, which `chisel contract verify`
+//! replays against a deployment to detect changes in the shape of the responses.
+//!
+//! Examples are either registered in code with `RouteMap.example()` (reported by
+//! `op_chisel_set_examples` when the workers start up), or recorded from a sample of the real
+//! traffic (see `--record-examples-rate`). Recorded examples are redacted and stored in the meta
+//! database, so they survive restarts; only the latest example of every route is kept.
+
+use crate::server::Server;
+use crate::version::Version;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// Value that replaces the redacted fields of the recorded examples.
+pub const REDACTED: &str = "<redacted>";
+
+/// An example request of a route, together with the expected response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteExample {
+    pub name: String,
+    pub method: String,
+    /// Path (with the query string) of the request, relative to the version.
+    pub path: String,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    pub status: u16,
+    /// Expected JSON body of the response; only its shape is compared. If not set, only the
+    /// status is compared.
+    pub response: Option<JsonValue>,
+    /// Whether the example was recorded from real traffic (rather than registered in code).
+    #[serde(default)]
+    pub recorded: bool,
+}
+
+/// A request (and its response) that was sampled for recording, as reported by
+/// `op_chisel_record_example`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampledRequest {
+    /// Method and path pattern of the matched route, such as `GET /books/:id`.
+    pub name: String,
+    pub method: String,
+    pub path: String,
+    pub content_type: Option<String>,
+    pub body: Option<String>,
+    pub status: u16,
+    pub response: Option<String>,
+}
+
+/// Decides whether a successful request should be recorded as an example.
+pub fn should_record(server: &Server) -> bool {
+    let rate = server.opt.record_examples_rate;
+    rate > 0. && rand::random::<f64>() < rate
+}
+
+/// Converts a sampled request into an example, redacting the values of `redact_fields` in the
+/// query string and in the JSON bodies. Bodies that are not JSON are dropped, because they might
+/// contain anything.
+pub fn recorded_example(request: SampledRequest, redact_fields: &[String]) -> RouteExample {
+    let redact_body = |body: Option<String>| {
+        let mut value = serde_json::from_str::<JsonValue>(&body?).ok()?;
+        redact_json(&mut value, redact_fields);
+        Some(value)
+    };
+    let is_json = request
+        .content_type
+        .as_deref()
+        .map_or(false, |content_type| content_type.contains("json"));
+    let body = if is_json {
+        redact_body(request.body).map(|body| body.to_string())
+    } else {
+        None
+    };
+    let headers = match (&body, request.content_type) {
+        (Some(_), Some(content_type)) => vec![("content-type".into(), content_type)],
+        _ => vec![],
+    };
+    RouteExample {
+        name: request.name,
+        method: request.method,
+        path: redact_query(&request.path, redact_fields),
+        headers,
+        body,
+        status: request.status,
+        response: redact_body(request.response),
+        recorded: true,
+    }
+}
+
+/// Returns the registered and recorded examples of a version.
+pub async fn version_examples(server: &Server, version: &Version) -> Result<Vec<RouteExample>> {
+    let mut examples = version.examples.read().clone();
+    let recorded = server
+        .meta_service
+        .load_route_examples(&version.version_id)
+        .await?;
+    examples.extend(recorded);
+    Ok(examples)
+}
+
+fn is_redacted(name: &str, redact_fields: &[String]) -> bool {
+    redact_fields
+        .iter()
+        .any(|field| field.eq_ignore_ascii_case(name))
+}
+
+fn redact_json(value: &mut JsonValue, redact_fields: &[String]) {
+    match value {
+        JsonValue::Object(object) => {
+            for (name, value) in object.iter_mut() {
+                if is_redacted(name, redact_fields) {
+                    *value = JsonValue::from(REDACTED);
+                } else {
+                    redact_json(value, redact_fields);
+                }
+            }
+        }
+        JsonValue::Array(array) => {
+            for value in array.iter_mut() {
+                redact_json(value, redact_fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn redact_query(path: &str, redact_fields: &[String]) -> String {
+    let (path, query) = match path.split_once('?') {
+        Some(parts) => parts,
+        None => return path.into(),
+    };
+    let query = form_urlencoded::parse(query.as_bytes())
+        .map(|(name, value)| {
+            let value = if is_redacted(&name, redact_fields) {
+                REDACTED.into()
+            } else {
+                value
+            };
+            (name, value)
+        })
+        .fold(
+            form_urlencoded::Serializer::new(String::new()),
+            |mut serializer, (name, value)| {
+                serializer.append_pair(&name, &value);
+                serializer
+            },
+        )
+        .finish();
+    format!("{}?{}", path, query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields() -> Vec<String> {
+        vec!["password".into(), "token".into()]
+    }
+
+    #[test]
+    fn redacts_nested_json() {
+        let mut value = json!({
+            "name": "alice",
+            "Password": "hunter2",
+            "sessions": [{"token": "abc", "device": "phone"}],
+        });
+        redact_json(&mut value, &fields());
+        assert_eq!(
+            value,
+            json!({
+                "name": "alice",
+                "Password": REDACTED,
+                "sessions": [{"token": REDACTED, "device": "phone"}],
+            })
+        );
+    }
+
+    #[test]
+    fn redacts_query() {
+        assert_eq!(
+            redact_query("/users?name=alice&token=abc", &fields()),
+            "/users?name=alice&token=%3Credacted%3E"
+        );
+        assert_eq!(redact_query("/users", &fields()), "/users");
+    }
+
+    #[test]
+    fn recorded_example_drops_opaque_bodies() {
+        let request = SampledRequest {
+            name: "POST /upload".into(),
+            method: "POST".into(),
+            path: "/upload".into(),
+            content_type: Some("text/plain".into()),
+            body: Some("password=hunter2".into()),
+            status: 200,
+            response: Some("{\"ok\": true, \"token\": \"abc\"}".into()),
+        };
+        let example = recorded_example(request, &fields());
+        assert_eq!(example.body, None);
+        assert!(example.headers.is_empty());
+        assert_eq!(
+            example.response,
+            Some(json!({"ok": true, "token": REDACTED}))
+        );
+        assert!(example.recorded);
+    }
+}
@@ -0,0 +1,136 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! SQL that maintains materialized aggregates.
+//!
+//! The rows of an aggregate are computed by grouping the rows of the source entity by the
+//! group-by fields of the aggregate. The id of an aggregate row is derived from the values of
+//! these fields, so it is stable across refreshes.
+//!
+//! Aggregates that are updated on write are maintained group by group: when a row of the source
+//! is written, the group that contains the stored row is recomputed without the row (before the
+//! write), and the group that contains the written row is recomputed after the write. When rows of
+//! the source are deleted, the whole aggregate is recomputed.
+
+use crate::datastore::engine::SqlWithArguments;
+use crate::datastore::query::SqlValue;
+use crate::types::{AggregateFn, Aggregator, TypeId};
+
+/// Builds the statements that recompute all rows of the aggregate.
+pub fn refresh_sql(agg: &Aggregator) -> Vec<String> {
+    vec![
+        format!(r#"DELETE FROM "{}""#, agg.aggregate.backing_table()),
+        insert_groups_sql(agg, None),
+    ]
+}
+
+/// Builds the statements that must be executed before and after the row of the source with id
+/// `source_id` is written.
+pub fn row_write_sql(
+    agg: &Aggregator,
+    source_id: &str,
+) -> (Vec<SqlWithArguments>, Vec<SqlWithArguments>) {
+    let agg_table = format!(r#""{}""#, agg.aggregate.backing_table());
+    let delete_group = SqlWithArguments {
+        sql: format!(
+            "DELETE FROM {agg_table} WHERE {}",
+            same_group_sql(agg, &agg_table)
+        ),
+        args: vec![SqlValue::String(source_id.to_owned())],
+    };
+    let old_group = format!(r#"{} AND src."id" <> $1"#, same_group_sql(agg, "src"));
+    let before = vec![
+        delete_group.clone(),
+        SqlWithArguments {
+            sql: insert_groups_sql(agg, Some(&old_group)),
+            args: vec![SqlValue::String(source_id.to_owned())],
+        },
+    ];
+    let after = vec![
+        delete_group,
+        SqlWithArguments {
+            sql: insert_groups_sql(agg, Some(&same_group_sql(agg, "src"))),
+            args: vec![SqlValue::String(source_id.to_owned())],
+        },
+    ];
+    (before, after)
+}
+
+/// SQL condition which is true if the row `alias` is in the same group as the stored row of the
+/// source with id `$1`.
+fn same_group_sql(agg: &Aggregator, alias: &str) -> String {
+    let mut sql = format!(
+        r#"EXISTS (SELECT 1 FROM "{}" AS written WHERE written."id" = $1"#,
+        agg.source.backing_table()
+    );
+    for field in agg.aggregate.group_by_fields() {
+        sql += &format!(r#" AND written."{0}" = {alias}."{0}""#, field.name);
+    }
+    sql += ")";
+    sql
+}
+
+/// Builds the statement that inserts the rows of the groups of the source rows that match
+/// `filter` (all groups if `filter` is `None`).
+fn insert_groups_sql(agg: &Aggregator, filter: Option<&str>) -> String {
+    let group_by = agg
+        .aggregate
+        .group_by_fields()
+        .map(|f| format!(r#"src."{}""#, f.name))
+        .collect::<Vec<_>>();
+    let id_sql = if group_by.is_empty() {
+        "'all'".to_owned()
+    } else {
+        group_by
+            .iter()
+            .map(|column| format!("CAST({column} AS TEXT)"))
+            .collect::<Vec<_>>()
+            .join(" || '/' || ")
+    };
+
+    let mut columns = vec![r#""id""#.to_owned()];
+    let mut select = vec![format!(r#"{id_sql} AS "id""#)];
+    for field in agg.aggregate.user_fields() {
+        columns.push(format!(r#""{}""#, field.name));
+        let value = match &field.aggregate {
+            None => format!(r#"src."{}""#, field.name),
+            Some(spec) => {
+                let source_field = spec.source_field.as_deref().unwrap_or_default();
+                match spec.function {
+                    AggregateFn::Count => "COUNT(*)".to_owned(),
+                    AggregateFn::Sum => format!(r#"COALESCE(SUM(src."{source_field}"), 0)"#),
+                    // averages of integers and decimals are rounded to the stored integers
+                    AggregateFn::Avg
+                        if matches!(field.type_id, TypeId::Int64 | TypeId::Decimal { .. }) =>
+                    {
+                        format!(r#"CAST(ROUND(AVG(src."{source_field}")) AS BIGINT)"#)
+                    }
+                    AggregateFn::Avg => format!(r#"AVG(src."{source_field}")"#),
+                    AggregateFn::Min => format!(r#"MIN(src."{source_field}")"#),
+                    AggregateFn::Max => format!(r#"MAX(src."{source_field}")"#),
+                }
+            }
+        };
+        select.push(format!(r#"{value} AS "{}""#, field.name));
+    }
+    // the aggregate functions return a row even if there are no source rows when there is no
+    // GROUP BY, so we filter out empty groups
+    select.push(r#"COUNT(*) AS "__rows""#.to_owned());
+
+    let mut inner = format!(
+        r#"SELECT {} FROM "{}" AS src"#,
+        select.join(", "),
+        agg.source.backing_table()
+    );
+    if let Some(filter) = filter {
+        inner += &format!(" WHERE {filter}");
+    }
+    if !group_by.is_empty() {
+        inner += &format!(" GROUP BY {}", group_by.join(", "));
+    }
+
+    let columns = columns.join(", ");
+    format!(
+        r#"INSERT INTO "{}" ({columns}) SELECT {columns} FROM ({inner}) AS grouped WHERE grouped."__rows" > 0"#,
+        agg.aggregate.backing_table()
+    )
+}
@@ -10,7 +10,7 @@ use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
 
 use super::query::{Mutation, QueryOp, QueryPlan, SortBy, SortKey};
-use super::value::EntityMap;
+use super::value::{EntityMap, EntityValue};
 use super::{DataContext, QueryEngine};
 use crate::datastore::expr::{BinaryExpr, BinaryOp, Expr, PropertyAccess, Value as ExprValue};
 use crate::datastore::filter;
@@ -24,6 +24,52 @@ pub struct QueryParams {
     pub(super) type_name: String,
     pub(super) url_path: String,
     pub(super) url_query: Vec<(String, String)>,
+    /// Page size used when the request does not specify one, overriding the global default.
+    #[serde(default)]
+    pub(super) page_size: Option<u64>,
+    /// Maximum page size, overriding the global maximum.
+    #[serde(default)]
+    pub(super) max_page_size: Option<u64>,
+    /// Allows the request to fetch all matching entities at once with `full_scan=true`.
+    #[serde(default)]
+    pub(super) allow_full_scan: bool,
+}
+
+/// Error returned for a query of a CRUD endpoint that the client is not allowed to make, such as a
+/// full scan of an entity that does not allow them. Endpoints respond to it with 400 Bad Request.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("{0}")]
+pub struct CrudQueryError(pub String);
+
+/// Limits on the size of the pages returned by the CRUD `GET` endpoints, so that a single request
+/// cannot load a whole table into memory.
+#[derive(Clone, Copy, Debug)]
+pub struct PageLimits {
+    /// Page size used when the request does not specify one.
+    pub default_size: u64,
+    /// Larger page sizes requested by the clients are clamped to this size.
+    pub max_size: u64,
+}
+
+impl Default for PageLimits {
+    fn default() -> Self {
+        Self {
+            default_size: 1000,
+            max_size: 10000,
+        }
+    }
+}
+
+impl PageLimits {
+    /// Applies the per-entity overrides from `params`.
+    fn overridden(&self, params: &QueryParams) -> Self {
+        let max_size = params.max_page_size.unwrap_or(self.max_size);
+        let default_size = params.page_size.unwrap_or(self.default_size).min(max_size);
+        Self {
+            default_size,
+            max_size,
+        }
+    }
 }
 
 impl QueryEngine {
@@ -47,7 +93,8 @@ impl QueryEngine {
             .lookup_entity(&params.type_name)
             .context("unexpected type name as crud query base type")?;
 
-        let query = Query::from_url_query(base_type, &params.url_query, &ctx.type_system)?;
+        let limits = self.crud_page_limits().overridden(&params);
+        let query = Query::from_url_query(base_type, &params, &limits, &ctx.type_system)?;
         let ops = query.make_query_ops()?;
         let query_plan = QueryPlan::from_ops(ctx, base_type, ops)?;
         let stream = self.query(ctx.txn.clone(), query_plan)?;
@@ -83,8 +130,9 @@ impl QueryEngine {
                 }
             }
             let results: Vec<_> = results
-            .iter()
-            .map(|entity_fields: &EntityMap| {
+            .into_iter()
+            .map(|entity_fields: EntityMap| {
+                let entity_fields = encode_int64(EntityValue::Map(entity_fields));
                 let v = serde_json::to_value(entity_fields).unwrap();
                 guard! {let serde_json::Value::Object(map) = v else { panic!("expected json object") }}
                 map
@@ -92,14 +140,33 @@ impl QueryEngine {
             .collect();
 
             let mut ret = JsonObject::new();
-            let next_page = get_next_page(&params, &query, &results)?;
-            if let Some(next_page) = next_page {
-                ret.insert("next_page".into(), json!(next_page));
-            }
-            let prev_page = get_prev_page(&params, &query, &results)?;
-            if let Some(prev_page) = prev_page {
-                ret.insert("prev_page".into(), json!(prev_page));
+            // A full scan returns everything there is, so there are no pages to link to.
+            if query.page_size.is_some() {
+                let next_page = get_next_page(&params, &query, &results)?;
+                if let Some(next_page) = next_page {
+                    ret.insert("next_page".into(), json!(next_page));
+                }
+                // The bare cursor of the next page, which can be passed to `Entity.page()`. Unlike
+                // `next_page`, it is omitted when this page is not full, because then it is the
+                // last one.
+                if query.page_size == Some(results.len() as u64) && !results.is_empty() {
+                    let cursor = cursor_from_pivot(&query, results.last().unwrap(), true)?;
+                    ret.insert("next_cursor".into(), json!(cursor.to_string()?));
+                }
+                let prev_page = get_prev_page(&params, &query, &results)?;
+                if let Some(prev_page) = prev_page {
+                    ret.insert("prev_page".into(), json!(prev_page));
+                }
             }
+            ret.insert(
+                "pagination".into(),
+                json!({
+                    "page_size": query.page_size,
+                    "max_page_size": limits.max_size,
+                    "count": results.len(),
+                    "full_scan": query.page_size.is_none(),
+                }),
+            );
 
             ret.insert("results".into(), json!(results));
             Ok(ret)
@@ -194,7 +261,8 @@ fn make_page_url(url_path: &str, url_query: &[(String, String)], cursor: &str) -
 
 /// Query is used in the process of parsing crud url query to rust representation.
 struct Query {
-    page_size: u64,
+    /// `None` for full scans.
+    page_size: Option<u64>,
     offset: Option<u64>,
     cursor: Option<Cursor>,
     sort: SortBy,
@@ -203,9 +271,9 @@ struct Query {
 }
 
 impl Query {
-    fn new() -> Self {
+    fn new(page_size: u64) -> Self {
         Query {
-            page_size: 1000,
+            page_size: Some(page_size),
             offset: None,
             cursor: None,
             sort: SortBy {
@@ -218,20 +286,29 @@ impl Query {
         }
     }
 
-    /// Parses provided `url_query` and builds a `Query` that can be used to build a `QueryPlan`.
+    /// Parses the URL query in `params` and builds a `Query` that can be used to build a
+    /// `QueryPlan`. Page sizes are clamped to `limits.max_size`.
     fn from_url_query(
         base_type: &Entity,
-        url_query: &[(String, String)],
+        params: &QueryParams,
+        limits: &PageLimits,
         ts: &TypeSystem,
     ) -> Result<Self> {
-        let mut q = Query::new();
-        for (param_key, value) in url_query.iter() {
+        let mut q = Query::new(limits.default_size);
+        let mut full_scan = false;
+        for (param_key, value) in params.url_query.iter() {
             match param_key.as_str() {
                 "sort" => q.sort = parse_sort(base_type, value)?,
                 "limit" | "page_size" => {
-                    q.page_size = value.parse().with_context(|| {
+                    let page_size: u64 = value.parse().with_context(|| {
                         format!("failed to parse {param_key}. Expected u64, got '{}'", value)
                     })?;
+                    q.page_size = Some(page_size.min(limits.max_size));
+                }
+                "full_scan" => {
+                    full_scan = value.parse().with_context(|| {
+                        format!("failed to parse full_scan. Expected bool, got '{}'", value)
+                    })?;
                 }
                 "offset" => {
                     let o = value.parse().with_context(|| {
@@ -265,6 +342,16 @@ impl Query {
                 }
             }
         }
+        if full_scan {
+            if !params.allow_full_scan {
+                return Err(CrudQueryError(format!(
+                    "full scans of {} are disabled, enable them with `crud({}, {{ allowFullScan: true }})`",
+                    params.type_name, params.type_name,
+                ))
+                .into());
+            }
+            q.page_size = None;
+        }
         // We need to ensure sorting by ID for cursors to work.
         ensure_sort_by_id(&mut q.sort);
         if let Some(cursor) = &q.cursor {
@@ -284,9 +371,9 @@ impl Query {
         if let Some(offset) = self.offset {
             ops.push(QueryOp::Skip { count: offset });
         }
-        ops.push(QueryOp::Take {
-            count: self.page_size,
-        });
+        if let Some(page_size) = self.page_size {
+            ops.push(QueryOp::Take { count: page_size });
+        }
         Ok(ops)
     }
 }
@@ -402,6 +489,22 @@ impl Cursor {
     }
 }
 
+/// Encodes the 64-bit integers in `value` as strings, because JSON numbers can't hold all of them.
+fn encode_int64(value: EntityValue) -> EntityValue {
+    match value {
+        EntityValue::Int64(value) => EntityValue::String(value.to_string()),
+        EntityValue::Array(values) => {
+            EntityValue::Array(values.into_iter().map(encode_int64).collect())
+        }
+        EntityValue::Map(map) => EntityValue::Map(
+            map.into_iter()
+                .map(|(key, value)| (key, encode_int64(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
 fn json_to_value(field_type: &Type, value: &serde_json::Value) -> Result<Expr> {
     macro_rules! convert {
         ($as_type:ident, $ty_name:literal) => {{
@@ -413,16 +516,31 @@ fn json_to_value(field_type: &Type, value: &serde_json::Value) -> Result<Expr> {
                 .to_owned()
         }};
     }
-    let expr_val = match field_type {
-        Type::Entity(_) | Type::Array(_) | Type::ArrayBuffer => anyhow::bail!(
-            "trying to filter by property of type '{}' which is not supported",
-            field_type.name()
-        ),
-        Type::String => ExprValue::String(convert!(as_str, "string")),
-        Type::Float | Type::JsDate => ExprValue::F64(convert!(as_f64, "float")),
-        Type::Boolean => ExprValue::Bool(convert!(as_bool, "bool")),
-        Type::EntityId { .. } => ExprValue::String(convert!(as_str, "string")),
-    };
+    let expr_val =
+        match field_type {
+            Type::Entity(_) | Type::Array(_) | Type::ArrayBuffer | Type::Json | Type::GeoPoint => {
+                anyhow::bail!(
+                    "trying to filter by property of type '{}' which is not supported",
+                    field_type.name()
+                )
+            }
+            Type::String => ExprValue::String(convert!(as_str, "string")),
+            // decimals are compared as strings, which the query scales to the stored integers
+            Type::Decimal { .. } => match value.as_str() {
+                Some(value) => ExprValue::String(value.to_owned()),
+                None => ExprValue::F64(convert!(as_f64, "decimal")),
+            },
+            Type::Float | Type::JsDate => ExprValue::F64(convert!(as_f64, "float")),
+            // 64-bit integers are encoded as strings in JSON, because they don't fit in its numbers
+            Type::Int64 => match value.as_str() {
+                Some(value) => ExprValue::I64(value.parse().with_context(|| {
+                    format!("failed to convert filter value '{}' to int64", value)
+                })?),
+                None => ExprValue::I64(convert!(as_i64, "int64")),
+            },
+            Type::Boolean => ExprValue::Bool(convert!(as_bool, "bool")),
+            Type::EntityId { .. } => ExprValue::String(convert!(as_str, "string")),
+        };
     Ok(expr_val.into())
 }
 
@@ -471,17 +589,20 @@ fn filter_from_param(
 
     let err_msg = |ty_name| format!("failed to convert filter value '{}' to {}", value, ty_name);
     let expr_value = match &field_type {
-        Type::String => ExprValue::String(value.to_owned()),
+        Type::String | Type::Decimal { .. } => ExprValue::String(value.to_owned()),
         Type::Float | Type::JsDate => {
             ExprValue::F64(value.parse::<f64>().with_context(|| err_msg("f64"))?)
         }
+        Type::Int64 => ExprValue::I64(value.parse::<i64>().with_context(|| err_msg("int64"))?),
         Type::Boolean => ExprValue::Bool(value.parse::<bool>().with_context(|| err_msg("bool"))?),
         Type::EntityId { .. } => ExprValue::String(value.to_owned()),
-        Type::Entity(_) | Type::Array(_) | Type::ArrayBuffer => anyhow::bail!(
-            "trying to filter by property '{}' of type '{}' which is not supported",
-            fields.last().unwrap(),
-            field_type.name()
-        ),
+        Type::Entity(_) | Type::Array(_) | Type::ArrayBuffer | Type::Json | Type::GeoPoint => {
+            anyhow::bail!(
+                "trying to filter by property '{}' of type '{}' which is not supported",
+                fields.last().unwrap(),
+                field_type.name()
+            )
+        }
     };
 
     Ok(BinaryExpr::new(operator, property_chain, expr_value.into()).into())
@@ -1074,6 +1195,66 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_page_limits() {
+        let (query_engine, _db_file) = setup_clear_db(&*ENTITIES).await;
+        let qe = &query_engine.with_crud_page_limits(PageLimits {
+            default_size: 2,
+            max_size: 3,
+        });
+        qe.with_dummy_ctx(Default::default(), |ctx| async {
+            for (name, age) in [("Alan", 30.), ("Alex", 40.), ("John", 20.), ("Steve", 29.)] {
+                add_row(qe, &PERSON_TY, &json!({"name": name, "age": age}), &ctx).await;
+            }
+            ctx
+        })
+        .await;
+
+        let params = |query: &str, allow_full_scan: bool| QueryParams {
+            type_name: "Person".into(),
+            url_path: "/test".into(),
+            url_query: url(query).query_pairs().into_owned().collect(),
+            page_size: None,
+            max_page_size: None,
+            allow_full_scan,
+        };
+        qe.with_dummy_ctx(Default::default(), |ctx| async {
+            let r = qe.run_query(&ctx, params("", false)).await.unwrap();
+            assert_eq!(collect_names(&r).len(), 2);
+            assert_eq!(
+                r["pagination"],
+                json!({"page_size": 2, "max_page_size": 3, "count": 2, "full_scan": false})
+            );
+
+            let r = qe.run_query(&ctx, params("page_size=100", false)).await;
+            assert_eq!(collect_names(&r.unwrap()).len(), 3);
+
+            let mut overridden = params("page_size=100", false);
+            overridden.page_size = Some(1);
+            overridden.max_page_size = Some(10);
+            let r = qe.run_query(&ctx, overridden.clone()).await.unwrap();
+            assert_eq!(collect_names(&r).len(), 4);
+            overridden.url_query.clear();
+            let r = qe.run_query(&ctx, overridden).await.unwrap();
+            assert_eq!(collect_names(&r).len(), 1);
+
+            let err = qe
+                .run_query(&ctx, params("full_scan=true", false))
+                .await
+                .unwrap_err();
+            assert!(err.downcast_ref::<CrudQueryError>().is_some());
+            let r = qe
+                .run_query(&ctx, params("full_scan=true", true))
+                .await
+                .unwrap();
+            assert_eq!(collect_names(&r).len(), 4);
+            assert_eq!(r["pagination"]["full_scan"], json!(true));
+            assert!(!r.contains_key("next_page"));
+            ctx
+        })
+        .await;
+    }
+
     #[tokio::test]
     async fn test_query_str_to_ops_errors() {
         let (query_engine, _db_file) = setup_clear_db(&*ENTITIES).await;
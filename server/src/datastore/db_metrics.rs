@@ -0,0 +1,184 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Metrics of the database connection pool and of the queries on entities, reported on the
+//! `/__chiselstrike/metrics` route.
+
+use futures::Stream;
+use parking_lot::Mutex;
+use pin_project::pin_project;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Upper bounds (in milliseconds) of the histogram buckets; the last bucket is unbounded.
+const BUCKET_BOUNDS_MS: [f64; 11] = [1., 2.5, 5., 10., 25., 50., 100., 250., 500., 1000., 5000.];
+
+/// Latency histogram with fixed buckets.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: [AtomicU64; BUCKET_BOUNDS_MS.len() + 1],
+    sum_us: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramReport {
+    pub count: u64,
+    pub sum_ms: f64,
+    pub buckets: Vec<BucketReport>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketReport {
+    /// Upper bound of the bucket, `None` for the last one.
+    pub le_ms: Option<f64>,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.;
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> HistogramReport {
+        let buckets: Vec<_> = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| BucketReport {
+                le_ms: BUCKET_BOUNDS_MS.get(i).copied(),
+                count: count.load(Ordering::Relaxed),
+            })
+            .collect();
+        HistogramReport {
+            count: buckets.iter().map(|b| b.count).sum(),
+            sum_ms: self.sum_us.load(Ordering::Relaxed) as f64 / 1000.,
+            buckets,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DbMetrics {
+    /// Time to acquire a connection from the pool and begin a transaction.
+    acquire: Histogram,
+    /// Latencies of the queries on entities, by the kind of the query (such as `select`).
+    queries: Mutex<BTreeMap<&'static str, Arc<Histogram>>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbMetricsReport {
+    pub pool: PoolReport,
+    pub acquire: HistogramReport,
+    pub queries: BTreeMap<&'static str, HistogramReport>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolReport {
+    pub min_connections: u32,
+    pub max_connections: u32,
+    /// Number of open connections, both idle and in use.
+    pub size: u32,
+    pub idle: u32,
+    /// Fraction of `max_connections` that is in use.
+    pub utilization: f64,
+}
+
+impl DbMetrics {
+    pub fn observe_acquire(&self, duration: Duration) {
+        self.acquire.observe(duration);
+    }
+
+    pub fn query_histogram(&self, kind: &'static str) -> Arc<Histogram> {
+        self.queries.lock().entry(kind).or_default().clone()
+    }
+
+    pub fn observe_query(&self, kind: &'static str, duration: Duration) {
+        self.query_histogram(kind).observe(duration);
+    }
+
+    /// Wraps `stream` so that the time until it is exhausted is recorded as the latency of a
+    /// query of the given `kind`.
+    pub fn timed_stream<S: Stream>(&self, kind: &'static str, stream: S) -> TimedStream<S> {
+        TimedStream {
+            stream,
+            histogram: Some(self.query_histogram(kind)),
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn report(&self, pool: PoolReport) -> DbMetricsReport {
+        let queries = self
+            .queries
+            .lock()
+            .iter()
+            .map(|(&kind, histogram)| (kind, histogram.report()))
+            .collect();
+        DbMetricsReport {
+            pool,
+            acquire: self.acquire.report(),
+            queries,
+        }
+    }
+}
+
+#[pin_project]
+pub struct TimedStream<S> {
+    #[pin]
+    stream: S,
+    /// Taken when the latency is recorded.
+    histogram: Option<Arc<Histogram>>,
+    started_at: Instant,
+}
+
+impl<S: Stream> Stream for TimedStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let poll = this.stream.poll_next(cx);
+        if let Poll::Ready(None) = poll {
+            if let Some(histogram) = this.histogram.take() {
+                histogram.observe(this.started_at.elapsed());
+            }
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(1));
+        histogram.observe(Duration::from_millis(30));
+        histogram.observe(Duration::from_secs(10));
+
+        let report = histogram.report();
+        assert_eq!(report.count, 4);
+        assert_eq!(report.buckets[0].count, 2);
+        assert_eq!(report.buckets[5].le_ms, Some(50.));
+        assert_eq!(report.buckets[5].count, 1);
+        let last = report.buckets.last().unwrap();
+        assert_eq!((last.le_ms, last.count), (None, 1));
+        assert!((report.sum_ms - 10031.5).abs() < 1e-6);
+    }
+}
@@ -1,20 +1,73 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
+use super::db_metrics::{DbMetrics, DbMetricsReport, PoolReport};
 use anyhow::Context;
 use anyhow::Result;
 use sea_query::{PostgresQueryBuilder, QueryBuilder, SchemaBuilder, SqliteQueryBuilder};
-use sqlx::any::{AnyKind, AnyPool, AnyPoolOptions};
-use sqlx::Executor;
+use sqlx::any::{Any, AnyConnectOptions, AnyKind, AnyPool, AnyPoolOptions};
+use sqlx::{Connection, Executor, Transaction};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 pub struct DbConnection {
     pub pool: AnyPool,
+    pub metrics: Arc<DbMetrics>,
+    options: DbPoolOptions,
+}
+
+/// Tuning of the database connection pool.
+#[derive(Debug, Clone)]
+pub struct DbPoolOptions {
+    pub min_connections: u32,
+    pub max_connections: u32,
+    /// How long to wait for a connection before the query fails.
+    pub acquire_timeout: Duration,
+    /// Idle connections above `min_connections` are closed after this time, `None` keeps them
+    /// open.
+    pub idle_timeout: Option<Duration>,
+    /// Number of prepared statements that every connection keeps, keyed by the SQL text. The
+    /// least recently used statement is closed when the cache is full, and all of them are closed
+    /// with the connection. Zero prepares the statements on every execution.
+    pub statement_cache_capacity: usize,
+}
+
+impl DbPoolOptions {
+    pub fn new(max_connections: usize) -> Self {
+        Self {
+            min_connections: 0,
+            max_connections: max_connections as u32,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+            statement_cache_capacity: 100,
+        }
+    }
 }
 
 impl DbConnection {
     pub async fn connect(uri: &str, max_connections: usize) -> Result<Self> {
+        Self::connect_with(uri, DbPoolOptions::new(max_connections)).await
+    }
+
+    pub async fn connect_with(uri: &str, options: DbPoolOptions) -> Result<Self> {
+        let mut connect_options = AnyConnectOptions::from_str(uri)
+            .with_context(|| format!("invalid database URI {}", uri))?;
+        if let Some(pg) = connect_options.as_postgres_mut() {
+            *pg = pg
+                .clone()
+                .statement_cache_capacity(options.statement_cache_capacity);
+        }
+        if let Some(sqlite) = connect_options.as_sqlite_mut() {
+            *sqlite = sqlite
+                .clone()
+                .statement_cache_capacity(options.statement_cache_capacity);
+        }
         let pool = AnyPoolOptions::new()
-            .max_connections(max_connections as u32)
+            .min_connections(options.min_connections)
+            .max_connections(options.max_connections)
+            .acquire_timeout(options.acquire_timeout)
+            .idle_timeout(options.idle_timeout)
             .after_connect(move |conn, _meta| {
                 Box::pin(async move {
                     if matches!(conn.kind(), AnyKind::Sqlite) {
@@ -23,10 +76,77 @@ impl DbConnection {
                     Ok(())
                 })
             })
-            .connect(uri)
+            .connect_with(connect_options)
             .await
             .with_context(|| format!("failed to connect to {}", uri))?;
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            metrics: Default::default(),
+            options,
+        })
+    }
+
+    /// Acquires a connection from the pool and begins a transaction on it, recording the time it
+    /// took in the metrics.
+    pub async fn begin(&self) -> Result<Transaction<'static, Any>> {
+        let started_at = Instant::now();
+        let txn = self.pool.begin().await?;
+        self.metrics.observe_acquire(started_at.elapsed());
+        Ok(txn)
+    }
+
+    /// Opens the `min_connections` connections at once, so that the first requests don't pay for
+    /// opening them. Returns the number of open connections.
+    pub async fn warm_up(&self) -> Result<u32> {
+        let conns = futures::future::try_join_all(
+            (0..self.options.min_connections).map(|_| self.pool.acquire()),
+        )
+        .await
+        .context("Could not open the minimum number of database connections")?;
+        drop(conns);
+        Ok(self.pool.size())
+    }
+
+    /// Pings up to `min_connections` idle connections, so that they are not dropped by the
+    /// database (or a firewall) for inactivity. The connections that fail the ping are closed.
+    /// Returns the number of pinged connections.
+    pub async fn ping_idle(&self) -> usize {
+        let mut conns = vec![];
+        while conns.len() < self.options.min_connections as usize {
+            match self.pool.try_acquire() {
+                Some(conn) => conns.push(conn),
+                None => break,
+            }
+        }
+        let count = conns.len();
+        for mut conn in conns {
+            if let Err(e) = conn.ping().await {
+                log::debug!(
+                    "Closing a database connection that failed the ping: {:?}",
+                    e
+                );
+                drop(conn.detach());
+            }
+        }
+        count
+    }
+
+    pub fn max_connections(&self) -> u32 {
+        self.options.max_connections
+    }
+
+    pub fn metrics_report(&self) -> DbMetricsReport {
+        let size = self.pool.size();
+        let idle = self.pool.num_idle() as u32;
+        let max_connections = self.options.max_connections;
+        let pool = PoolReport {
+            min_connections: self.options.min_connections,
+            max_connections,
+            size,
+            idle,
+            utilization: size.saturating_sub(idle) as f64 / max_connections.max(1) as f64,
+        };
+        self.metrics.report(pool)
     }
 
     // TODO: replace `query_builder()` and `schema_builder()` with a single method that returns
@@ -47,3 +167,37 @@ impl DbConnection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+
+    /// Runs three different statements on a connection whose cache has room for `capacity`
+    /// statements and returns how many of them stay prepared.
+    async fn cached_statements(capacity: usize) -> usize {
+        let options = DbPoolOptions {
+            statement_cache_capacity: capacity,
+            ..DbPoolOptions::new(1)
+        };
+        let db = DbConnection::connect_with("sqlite::memory:", options)
+            .await
+            .unwrap();
+        let mut conn = db.pool.acquire().await.unwrap();
+        for n in 0..3 {
+            sqlx::query(&format!("SELECT {n}"))
+                .execute(&mut *conn)
+                .await
+                .unwrap();
+        }
+        conn.cached_statements_size()
+    }
+
+    #[tokio::test]
+    async fn statement_cache_capacity() {
+        assert_eq!(cached_statements(100).await, 3);
+        assert_eq!(cached_statements(2).await, 2);
+        // zero disables the cache
+        assert_eq!(cached_statements(0).await, 0);
+    }
+}
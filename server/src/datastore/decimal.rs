@@ -0,0 +1,105 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Fixed-precision decimal numbers.
+//!
+//! A `Decimal<precision, scale>` field holds numbers with at most `precision` digits, `scale` of
+//! which are after the decimal point. The values are strings in TypeScript, such as `"12.50"`, and
+//! they are stored as 64-bit integers scaled by `10^scale`, so the database compares and sums
+//! them exactly.
+
+/// Maximum precision of a decimal, so that every value fits in 64 bits.
+pub const MAX_PRECISION: u32 = 18;
+
+/// Parses the decimal string `text` into an integer scaled by `10^scale`. Returns a description
+/// of the problem if `text` is not a decimal number or if it does not fit `precision` and `scale`.
+pub fn parse(text: &str, precision: u32, scale: u32) -> Result<i64, String> {
+    let invalid = || format!("{text:?} is not a decimal number");
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if integer.is_empty() || !is_digits(integer) || !is_digits(fraction) {
+        return Err(invalid());
+    }
+    if digits.ends_with('.') {
+        return Err(invalid());
+    }
+
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > scale as usize {
+        return Err(format!(
+            "{text:?} has more than {scale} digits after the decimal point"
+        ));
+    }
+    let integer = integer.trim_start_matches('0');
+    if integer.len() > (precision - scale) as usize {
+        return Err(format!(
+            "{text:?} has more than {} digits before the decimal point",
+            precision - scale
+        ));
+    }
+
+    let padded = format!("{integer}{fraction:0<width$}", width = scale as usize);
+    let value = if padded.is_empty() {
+        0
+    } else {
+        padded.parse::<i64>().map_err(|_| invalid())?
+    };
+    Ok(if negative { -value } else { value })
+}
+
+/// Formats the integer `value` scaled by `10^scale` as a decimal string with exactly `scale`
+/// digits after the decimal point.
+pub fn format(value: i64, scale: u32) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let digits = format!(
+        "{:0>width$}",
+        value.unsigned_abs(),
+        width = scale as usize + 1
+    );
+    let (integer, fraction) = digits.split_at(digits.len() - scale as usize);
+    if fraction.is_empty() {
+        format!("{sign}{integer}")
+    } else {
+        format!("{sign}{integer}.{fraction}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid() {
+        assert_eq!(parse("12.5", 10, 2), Ok(1250));
+        assert_eq!(parse("12.50", 10, 2), Ok(1250));
+        assert_eq!(parse("-0.01", 10, 2), Ok(-1));
+        assert_eq!(parse("+7", 10, 2), Ok(700));
+        assert_eq!(parse("0007.100", 4, 2), Ok(710));
+        assert_eq!(parse("0", 10, 0), Ok(0));
+        assert_eq!(
+            parse("999999999999999999", 18, 0),
+            Ok(999_999_999_999_999_999)
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for text in ["", "-", ".5", "5.", "1e3", "1,5", "12.3.4", " 1", "0x10"] {
+            assert!(parse(text, 10, 2).is_err(), "{text:?}");
+        }
+        assert!(parse("1.005", 10, 2).is_err());
+        assert!(parse("123.4", 4, 2).is_err());
+    }
+
+    #[test]
+    fn format_scaled() {
+        assert_eq!(format(1250, 2), "12.50");
+        assert_eq!(format(-1, 2), "-0.01");
+        assert_eq!(format(0, 3), "0.000");
+        assert_eq!(format(42, 0), "42");
+        assert_eq!(format(i64::MIN, 2), "-92233720368547758.08");
+    }
+}
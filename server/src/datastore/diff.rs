@@ -0,0 +1,231 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Comparison of the data of an entity between two versions.
+//!
+//! Rows are matched by their id. The rows of the target version are loaded in memory and then
+//! the rows of the source version are streamed and compared against them, field by field. Only
+//! the fields that exist in both versions are compared.
+
+use crate::datastore::engine::QueryEngine;
+use crate::datastore::query::QueryPlan;
+use crate::datastore::value::{EntityMap, EntityValue};
+use crate::types::Entity;
+use anyhow::{Context, Result};
+use futures::{Future, StreamExt};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Difference between two rows with the same id.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowDiff {
+    /// The row exists in both versions, but some fields differ.
+    Changed { id: String, fields: Vec<FieldDiff> },
+    /// The row exists only in the source version.
+    OnlyInFrom { id: String },
+    /// The row exists only in the target version.
+    OnlyInTo { id: String },
+}
+
+/// Difference in a single field. A value of `None` means that the (optional) field is missing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    pub name: String,
+    pub from: Option<EntityValue>,
+    pub to: Option<EntityValue>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiffSummary {
+    /// Number of rows (after sampling) that exist in both versions.
+    pub compared: u64,
+    pub changed: u64,
+    pub only_in_from: u64,
+    pub only_in_to: u64,
+    /// Fields that exist only in the source version and were not compared.
+    pub from_only_fields: Vec<String>,
+    /// Fields that exist only in the target version and were not compared.
+    pub to_only_fields: Vec<String>,
+}
+
+/// Decides whether the row with given `id` is part of the sample. The decision depends only on
+/// the id, so that the same rows are selected in both versions.
+pub fn is_sampled(id: &str, fraction: f64) -> bool {
+    if fraction >= 1.0 {
+        return true;
+    }
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    (hasher.finish() as f64 / u64::MAX as f64) < fraction
+}
+
+/// Compares the given fields of two rows.
+pub fn compare_rows(from: &EntityMap, to: &EntityMap, fields: &[String]) -> Vec<FieldDiff> {
+    fields
+        .iter()
+        .filter(|name| from.get(*name) != to.get(*name))
+        .map(|name| FieldDiff {
+            name: name.clone(),
+            from: from.get(name).cloned(),
+            to: to.get(name).cloned(),
+        })
+        .collect()
+}
+
+fn row_id(row: &EntityMap) -> Result<String> {
+    match row.get("id") {
+        Some(EntityValue::String(id)) => Ok(id.clone()),
+        other => anyhow::bail!("Row has an invalid id {:?}", other),
+    }
+}
+
+async fn load_rows(
+    engine: &QueryEngine,
+    entity: &Entity,
+    fraction: f64,
+) -> Result<BTreeMap<String, EntityMap>> {
+    let txn = engine.begin_transaction_static().await?;
+    let mut rows = BTreeMap::new();
+    let mut row_stream = engine.query(txn.clone(), QueryPlan::from_type(entity))?;
+    while let Some(row) = row_stream.next().await {
+        let row = row.with_context(|| format!("Could not read rows of {}", entity.name()))?;
+        let id = row_id(&row)?;
+        if is_sampled(&id, fraction) {
+            rows.insert(id, row);
+        }
+    }
+    drop(row_stream);
+    QueryEngine::commit_transaction_static(txn).await?;
+    Ok(rows)
+}
+
+/// Compares the rows of entity `from` with the rows of entity `to`, which are usually the same
+/// entity in two different versions. Only a `fraction` of the rows (selected by their id) is
+/// compared. Every difference is passed to `report` as soon as it is found.
+pub async fn diff_entity<F, Fut>(
+    engine: &QueryEngine,
+    from: &Entity,
+    to: &Entity,
+    fraction: f64,
+    mut report: F,
+) -> Result<DiffSummary>
+where
+    F: FnMut(RowDiff) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let from_fields = from
+        .user_fields()
+        .map(|f| f.name.clone())
+        .collect::<HashSet<_>>();
+    let to_fields = to
+        .user_fields()
+        .map(|f| f.name.clone())
+        .collect::<HashSet<_>>();
+    let mut fields = from_fields
+        .intersection(&to_fields)
+        .cloned()
+        .collect::<Vec<_>>();
+    fields.sort_unstable();
+    let mut summary = DiffSummary {
+        from_only_fields: from_fields.difference(&to_fields).cloned().collect(),
+        to_only_fields: to_fields.difference(&from_fields).cloned().collect(),
+        ..DiffSummary::default()
+    };
+    summary.from_only_fields.sort_unstable();
+    summary.to_only_fields.sort_unstable();
+
+    let mut to_rows = load_rows(engine, to, fraction).await?;
+
+    let txn = engine.begin_transaction_static().await?;
+    let mut row_stream = engine.query(txn.clone(), QueryPlan::from_type(from))?;
+    while let Some(from_row) = row_stream.next().await {
+        let from_row =
+            from_row.with_context(|| format!("Could not read rows of {}", from.name()))?;
+        let id = row_id(&from_row)?;
+        if !is_sampled(&id, fraction) {
+            continue;
+        }
+
+        match to_rows.remove(&id) {
+            Some(to_row) => {
+                summary.compared += 1;
+                let field_diffs = compare_rows(&from_row, &to_row, &fields);
+                if !field_diffs.is_empty() {
+                    summary.changed += 1;
+                    report(RowDiff::Changed {
+                        id,
+                        fields: field_diffs,
+                    })
+                    .await?;
+                }
+            }
+            None => {
+                summary.only_in_from += 1;
+                report(RowDiff::OnlyInFrom { id }).await?;
+            }
+        }
+    }
+    drop(row_stream);
+    QueryEngine::commit_transaction_static(txn).await?;
+
+    for id in to_rows.into_keys() {
+        summary.only_in_to += 1;
+        report(RowDiff::OnlyInTo { id }).await?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(fields: &[(&str, EntityValue)]) -> EntityMap {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn compare_rows_reports_changed_and_missing_fields() {
+        let from = row(&[
+            ("id", EntityValue::String("1".into())),
+            ("title", EntityValue::String("hello".into())),
+            ("likes", EntityValue::Float64(1.0)),
+            ("tag", EntityValue::String("a".into())),
+        ]);
+        let to = row(&[
+            ("id", EntityValue::String("1".into())),
+            ("title", EntityValue::String("hello".into())),
+            ("likes", EntityValue::Float64(2.0)),
+        ]);
+        let fields = vec!["likes".to_string(), "tag".to_string(), "title".to_string()];
+        assert_eq!(
+            compare_rows(&from, &to, &fields),
+            vec![
+                FieldDiff {
+                    name: "likes".into(),
+                    from: Some(EntityValue::Float64(1.0)),
+                    to: Some(EntityValue::Float64(2.0)),
+                },
+                FieldDiff {
+                    name: "tag".into(),
+                    from: Some(EntityValue::String("a".into())),
+                    to: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sampling_is_deterministic() {
+        let ids = (0..1000).map(|i| format!("id-{}", i)).collect::<Vec<_>>();
+        assert!(ids.iter().all(|id| is_sampled(id, 1.0)));
+        assert!(!ids.iter().any(|id| is_sampled(id, 0.0)));
+
+        let sampled = ids.iter().filter(|id| is_sampled(id, 0.5)).count();
+        assert!((300..700).contains(&sampled), "sampled {} rows", sampled);
+        let sampled_again = ids.iter().filter(|id| is_sampled(id, 0.5)).count();
+        assert_eq!(sampled, sampled_again);
+    }
+}
@@ -6,6 +6,7 @@ use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use async_lock::{Mutex, MutexGuardArc};
@@ -14,24 +15,37 @@ use futures::stream::BoxStream;
 use futures::stream::Stream;
 use futures::FutureExt;
 use futures::StreamExt;
+use futures::TryStreamExt;
 use itertools::Itertools;
 use pin_project::pin_project;
 use sea_query::{Alias, ColumnDef, Index, PostgresQueryBuilder, Table};
 use serde::Serialize;
-use sqlx::any::{Any, AnyArguments, AnyKind, AnyRow};
-use sqlx::{Executor, Row, Transaction, ValueRef};
+use sqlx::any::{Any, AnyArguments, AnyConnectionKind, AnyKind, AnyQueryResult, AnyRow};
+use sqlx::{Execute, Executor, Row, Transaction, ValueRef};
 use uuid::Uuid;
 
+use crate::datastore::aggregate;
+use crate::datastore::crud::PageLimits;
+use crate::datastore::decimal;
+use crate::datastore::kv;
 use crate::datastore::query::{
-    KeepOrOmitField, Mutation, QueryField, QueryPlan, SqlValue, TargetDatabase,
+    self, KeepOrOmitField, Mutation, Query, QueryField, QueryPlan, SqlValue, TargetDatabase,
 };
+use crate::datastore::query_cache::{self, CacheKey, QueryCache};
+use crate::datastore::query_stats::{self, QueryStats};
+use crate::datastore::tenancy::{self, TenantFilter, TENANT_COLUMN};
+use crate::datastore::txn_stats::{LockWait, TxnStats, TxnStatsReport};
 use crate::datastore::value::{EntityMap, EntityValue};
 use crate::datastore::DbConnection;
 use crate::feat_typescript_policies;
-use crate::ops::job_context::JobInfo;
+use crate::ops::job_context::{JobInfo, OwnerScope};
 use crate::policies::PolicySystem;
 use crate::policy::{Location, PolicyContext, PolicyProcessor, WriteAction};
-use crate::types::{DbIndex, Field, ObjectDelta, ObjectType, Type, TypeId, TypeSystem};
+use crate::telemetry::{self, SpannedStream};
+use crate::types::{
+    Aggregator, Counter, DbIndex, Field, ObjectDelta, ObjectType, OnDelete, Reference, Timestamp,
+    Type, TypeId, TypeSystem,
+};
 
 use super::DataContext;
 
@@ -82,6 +96,59 @@ fn new_query_results(
     make_transactioned_stream(tr, raw_query).flatten_stream()
 }
 
+/// Executes `query` in `transaction`, traced by a span of the given `kind` that is a child of the
+/// span in `parent`.
+async fn execute_traced<'q>(
+    transaction: &mut Transaction<'_, Any>,
+    query: sqlx::query::Query<'q, Any, AnyArguments<'q>>,
+    kind: &'static str,
+    parent: &opentelemetry::Context,
+) -> Result<AnyQueryResult> {
+    let span_cx = telemetry::start_sql_span(parent, kind, query.sql());
+    let result = transaction
+        .execute(query)
+        .await
+        .map_err(anyhow::Error::from);
+    telemetry::end(&span_cx, &result);
+    result
+}
+
+/// Binds `ids` to the parameters of `query`.
+fn bind_ids<'q>(
+    mut query: sqlx::query::Query<'q, Any, AnyArguments<'q>>,
+    ids: &[&String],
+) -> sqlx::query::Query<'q, Any, AnyArguments<'q>> {
+    for id in ids {
+        query = query.bind((*id).clone());
+    }
+    query
+}
+
+/// Returns the id and the referred id of the instances of `tenant` that refer by `reference` to one
+/// of `ids`.
+async fn fetch_referring_rows(
+    transaction: &mut Transaction<'_, Any>,
+    reference: &Reference,
+    ids: &[String],
+    tenant: &TenantFilter,
+    parent: &opentelemetry::Context,
+) -> Result<Vec<(String, String)>> {
+    let mut rows = vec![];
+    let ids: Vec<&String> = ids.iter().collect();
+    for chunk in ids.chunks(CASCADE_CHUNK_SIZE) {
+        let raw_sql = query::referring_rows_sql(reference, chunk.len(), tenant);
+        let query = bind_ids(sqlx::query(&raw_sql), chunk);
+        let span_cx = telemetry::start_sql_span(parent, "on_delete", query.sql());
+        let result = transaction
+            .fetch_all(query)
+            .await
+            .map_err(anyhow::Error::from);
+        telemetry::end(&span_cx, &result);
+        rows.extend(result?.iter().map(|row| (row.get(0), row.get(1))));
+    }
+    Ok(rows)
+}
+
 impl<T: Stream<Item = Result<AnyRow>>> Stream for RawQueryResults<T> {
     type Item = Result<AnyRow>;
 
@@ -101,11 +168,12 @@ impl TryFrom<&Field> for ColumnDef {
             TypeId::String => column_def.text(),
             TypeId::Id => column_def.text().primary_key(),
             TypeId::Float | TypeId::JsDate => column_def.double(),
-            TypeId::Int64 => column_def.big_integer(),
+            TypeId::Int64 | TypeId::Decimal { .. } => column_def.big_integer(),
             TypeId::Boolean => column_def.boolean(),
             TypeId::ArrayBuffer => column_def.binary(),
             TypeId::Entity { .. } | TypeId::EntityId { .. } => column_def.text(), // Foreign key, must the be same type as Type::Id
-            TypeId::Array(_) => column_def.json_binary(), // Arrays are stored as serialized JSONs.
+            // Arrays are stored as serialized JSONs, and so are the points.
+            TypeId::Array(_) | TypeId::Json | TypeId::GeoPoint => column_def.json_binary(),
         };
 
         Ok(column_def)
@@ -114,7 +182,7 @@ impl TryFrom<&Field> for ColumnDef {
 
 /// An SQL string with placeholders, plus its argument values.  Keeps them all alive so they can be fed to
 /// sqlx::Query by reference.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SqlWithArguments {
     /// SQL query text with placeholders $1, $2, ...
     pub sql: String,
@@ -161,9 +229,255 @@ impl SqlWithArguments {
 #[derive(Debug, Serialize)]
 pub struct IdTree {
     pub id: String,
+    /// New value of the `@version` field, if the entity has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<f64>,
+    /// Time of the write, if the entity has `@timestamps` fields.
+    #[serde(rename = "writtenAt", skip_serializing_if = "Option::is_none")]
+    written_at: Option<f64>,
+    /// Owner that was assigned to an entity marked with `@ownedBy`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
     children: HashMap<String, IdTree>,
 }
 
+/// Error returned when saving an entity whose `@version` field does not match the stored version,
+/// which means that the entity was modified since it was read.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Entity {entity} with id {id} was modified concurrently: version {version} is out of date")]
+pub struct ConflictError {
+    pub entity: String,
+    pub id: String,
+    pub version: f64,
+}
+
+/// Error returned when a written value is out of the range of its field, such as a value of an enum
+/// field that is not one of the values of the enum, or a value of an `ArrayBuffer` field that is
+/// larger than its `@maxBytes` limit.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Invalid value of field {field} of entity {entity}: {message}")]
+pub struct ValidationError {
+    pub entity: String,
+    pub field: String,
+    pub message: String,
+}
+
+/// Error returned when deleting an entity that is referred to by an `@onDelete("restrict")` field
+/// of an entity that is not deleted.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Cannot delete {entity} with id {id}: it is referred to by field {field} of {referring} with id {referring_id}")]
+pub struct RestrictError {
+    pub entity: String,
+    pub id: String,
+    pub referring: String,
+    pub field: String,
+    pub referring_id: String,
+}
+
+/// Maximum number of ids that are bound to a single statement when a `Mutation` follows the
+/// `@onDelete` references.
+const CASCADE_CHUNK_SIZE: usize = 500;
+
+/// Insert (or update) of a single row, see `QueryEngine::prepare_insertion()`.
+struct RowInsertion {
+    query: SqlWithArguments,
+    /// Set for entities with a `@version` field: if the query does not affect any row, the stored
+    /// version did not match.
+    conflict: Option<ConflictError>,
+    /// Set for entities marked with `@ownedBy` that are written on behalf of a user: if the query
+    /// does not affect any row, the stored row is owned by another user. Contains the name of the
+    /// entity and the id of the row.
+    owner_check: Option<(String, String)>,
+    /// Set for rows that are written on behalf of a tenant: if the query does not affect any row,
+    /// the stored row belongs to another tenant.
+    tenant_check: Option<(String, String)>,
+    /// Updates of the data derived from the row, executed before and after `query`: the count of
+    /// the instance that the stored row refers to is decremented, and the count of the instance
+    /// that the written row refers to is incremented (for `@count` fields); the group of the
+    /// stored row and the group of the written row are recomputed (for materialized aggregates).
+    before: Vec<SqlWithArguments>,
+    after: Vec<SqlWithArguments>,
+}
+
+/// Instance whose `@count` field does not match the data, see `QueryEngine::check_counter()`.
+#[derive(Debug)]
+pub struct CountMismatch {
+    pub id: String,
+    /// Stored value, `None` if the field was never computed.
+    pub stored: Option<f64>,
+    pub actual: i64,
+}
+
+/// SQL expression that counts the instances of `counter.counted` which refer to `id_sql`.
+fn count_sql(counter: &Counter, id_sql: &str) -> String {
+    format!(
+        r#"(SELECT COUNT(*) FROM "{}" AS counted WHERE counted."{}" = {})"#,
+        counter.counted.backing_table(),
+        counter.counted_field,
+        id_sql
+    )
+}
+
+/// Adds `delta` to the `@count` field of the instance that the stored row of `counter.counted`
+/// with id `counted_id` refers to.
+fn count_update(counter: &Counter, delta: i32, counted_id: &str) -> SqlWithArguments {
+    SqlWithArguments {
+        sql: format!(
+            r#"UPDATE "{counting}" SET "{field}" = "{field}" + ({delta}) WHERE "id" = (SELECT "{counted_field}" FROM "{counted}" WHERE "id" = $1)"#,
+            counting = counter.counting.backing_table(),
+            field = counter.field,
+            counted_field = counter.counted_field,
+            counted = counter.counted.backing_table(),
+        ),
+        args: vec![SqlValue::String(counted_id.to_owned())],
+    }
+}
+
+/// Returns the current time in milliseconds since the UNIX epoch, which is how `Date` fields are
+/// stored.
+pub(crate) fn now_ms() -> f64 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    elapsed.as_millis() as f64
+}
+
+/// Returns the id of the related entity in `value`, the value of the `field` that refers to
+/// another entity. Used by `upsert_row()` and `update_row()` (named by `op`), which do not write
+/// related entities.
+fn related_id(
+    op: &str,
+    ty: &ObjectType,
+    field: &Field,
+    value: Option<&EntityValue>,
+) -> Result<SqlValue> {
+    let id = match value {
+        Some(EntityValue::Map(related)) => related.get("id"),
+        value => value,
+    };
+    match id {
+        Some(EntityValue::String(id)) => Ok(SqlValue::String(id.clone())),
+        _ => anyhow::bail!(
+            "{}() cannot write the related entity in field `{}` of {}, it must be an existing entity with an id",
+            op,
+            field.name,
+            ty.name()
+        ),
+    }
+}
+
+/// Checks that the value written to a `Decimal` field is a decimal string that fits its precision
+/// and scale. Missing values are replaced by the default value, which is checked by apply.
+fn check_decimal_value(
+    ty: &ObjectType,
+    field: &Field,
+    value: Option<&EntityValue>,
+    precision: u32,
+    scale: u32,
+) -> Result<()> {
+    let error = match value {
+        None => None,
+        Some(EntityValue::String(text)) => decimal::parse(text, precision, scale).err(),
+        Some(_) => Some("decimal values must be strings, such as \"12.50\"".to_owned()),
+    };
+    match error {
+        Some(message) => Err(ValidationError {
+            entity: ty.name().to_owned(),
+            field: field.name.clone(),
+            message,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the value written to a field is in its range: the value of an enum field must be one
+/// of the values of the enum, the value of a `@maxBytes` field must not exceed the limit, and the
+/// value of a `GeoPoint` field must be a valid location.
+fn check_field_value(ty: &ObjectType, field: &Field, arg: &SqlValue) -> Result<()> {
+    if let (TypeId::GeoPoint, SqlValue::Json(value)) = (&field.type_id, arg) {
+        let error = match EntityValue::from_json(value) {
+            Ok(value) => geo_point_error(&value),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(message) = error {
+            return Err(ValidationError {
+                entity: ty.name().to_owned(),
+                field: field.name.clone(),
+                message,
+            }
+            .into());
+        }
+    }
+    if let (Some(max_bytes), SqlValue::Bytes(value)) = (field.max_bytes, arg) {
+        if value.len() as u64 > max_bytes {
+            return Err(ValidationError {
+                entity: ty.name().to_owned(),
+                field: field.name.clone(),
+                message: format!(
+                    "the value has {} bytes, but the field is limited to {} bytes",
+                    value.len(),
+                    max_bytes
+                ),
+            }
+            .into());
+        }
+    }
+    if let (Some(enum_type), SqlValue::String(value)) = (&field.enum_type, arg) {
+        if !enum_type.contains(value) {
+            return Err(ValidationError {
+                entity: ty.name().to_owned(),
+                field: field.name.clone(),
+                message: format!(
+                    "{:?} is not a value of enum {} (expected one of {})",
+                    value,
+                    enum_type.name,
+                    enum_type
+                        .values
+                        .iter()
+                        .map(|v| format!("{:?}", v))
+                        .join(", ")
+                ),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `value` is a `GeoPoint`, that is an object with the latitude `lat` (between -90 and
+/// 90) and the longitude `lng` (between -180 and 180) in degrees. Returns the reason if it is not.
+fn geo_point_error(value: &EntityValue) -> Option<String> {
+    let map = match value.as_map() {
+        Ok(map) => map,
+        Err(_) => {
+            return Some(format!(
+                "expected a point {{ lat, lng }}, got {}",
+                value.kind_str()
+            ))
+        }
+    };
+    for (key, limit) in [("lat", 90.), ("lng", 180.)] {
+        match map.get(key).map(EntityValue::as_f64) {
+            Some(Ok(degrees)) if (-limit..=limit).contains(&degrees) => {}
+            Some(Ok(degrees)) => {
+                return Some(format!(
+                    "{key} must be between -{limit} and {limit}, got {degrees}"
+                ))
+            }
+            _ => {
+                return Some(format!(
+                    "expected a point {{ lat, lng }}, but {key} is not a number"
+                ))
+            }
+        }
+    }
+    if map.len() != 2 {
+        return Some("a point must have only the lat and lng properties".to_owned());
+    }
+    None
+}
+
 fn column_is_null(row: &AnyRow, column_idx: usize) -> bool {
     row.try_get_raw(column_idx).unwrap().is_null()
 }
@@ -180,6 +494,41 @@ fn id_idx(fields: &Vec<QueryField>) -> usize {
     panic!("No id field among Entity children");
 }
 
+/// Collects the backing tables that are written when an object of type `ty` is saved (nested
+/// objects are saved, too).
+/// Whether an existing index (or the unique constraint of a field) serves the queries that filter
+/// and sort by `fields`.
+fn is_indexed(ty: &ObjectType, fields: &[String]) -> bool {
+    let unique = match fields {
+        [field] => ty.get_field(field).map_or(false, |field| field.is_unique),
+        _ => false,
+    };
+    unique
+        || ty
+            .indexes()
+            .iter()
+            .any(|index| index.fields.starts_with(fields))
+}
+
+fn collect_written_tables(ty: &ObjectType, ts: &TypeSystem, tables: &mut HashSet<String>) {
+    if !tables.insert(ty.backing_table().to_owned()) {
+        return;
+    }
+    for counter in ts.counters_of(ty.name()) {
+        tables.insert(counter.counting.backing_table().to_owned());
+    }
+    for agg in ts.aggregators_of(ty.name()) {
+        tables.insert(agg.aggregate.backing_table().to_owned());
+    }
+    for field in ty.all_fields() {
+        if let Ok(Type::Entity(nested_type)) = ts.get(&field.type_id) {
+            if !nested_type.is_auth() {
+                collect_written_tables(&nested_type, ts, tables);
+            }
+        }
+    }
+}
+
 /// Query engine.
 ///
 /// The query engine provides a way to transactionally mutate entities and
@@ -194,11 +543,55 @@ fn id_idx(fields: &Vec<QueryField>) -> usize {
 #[derive(Clone)]
 pub struct QueryEngine {
     db: Arc<DbConnection>,
+    cache: Arc<QueryCache>,
+    txn_stats: Arc<TxnStats>,
+    query_stats: Arc<QueryStats>,
+    crud_page_limits: PageLimits,
+}
+
+/// An index that would serve some of the queries of an entity, see
+/// [`QueryEngine::index_suggestions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSuggestion {
+    pub entity_name: String,
+    pub fields: Vec<String>,
+    /// Number of queries (since the server started) that the index would serve.
+    pub query_count: u64,
+    pub row_count: u64,
+    /// Rough estimate of the rows that the index would have saved from being scanned: every
+    /// query that the index serves currently scans the whole table.
+    pub estimated_rows_saved: u64,
 }
 
 impl QueryEngine {
     pub fn new(db: Arc<DbConnection>) -> Self {
-        Self { db }
+        Self {
+            db,
+            cache: Arc::new(QueryCache::new(query_cache::DEFAULT_MAX_ENTRIES)),
+            txn_stats: Default::default(),
+            query_stats: Default::default(),
+            crud_page_limits: Default::default(),
+        }
+    }
+
+    /// Limits the number of queries in the query cache, zero disables the cache.
+    pub fn with_query_cache_size(self, max_entries: usize) -> Self {
+        Self {
+            cache: Arc::new(QueryCache::new(max_entries)),
+            ..self
+        }
+    }
+
+    /// Sets the global page size limits of the CRUD `GET` endpoints.
+    pub fn with_crud_page_limits(self, crud_page_limits: PageLimits) -> Self {
+        Self {
+            crud_page_limits,
+            ..self
+        }
+    }
+
+    pub(crate) fn crud_page_limits(&self) -> PageLimits {
+        self.crud_page_limits
     }
 
     fn target_db(&self) -> TargetDatabase {
@@ -221,12 +614,80 @@ impl QueryEngine {
         let drop_table = drop_table.build_any(self.db.schema_builder());
         let drop_table = sqlx::query(&drop_table);
         transaction.execute(drop_table).await?;
+        self.query_stats.forget(ty.backing_table());
+
+        Ok(())
+    }
+
+    /// Renames the backing table of `ty` to `archived_table`, so that its data is retained after the
+    /// type is removed. The indexes are dropped, the archived table is only ever scanned.
+    pub async fn archive_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        archived_table: &str,
+    ) -> Result<()> {
+        self.drop_indexes(transaction, ty, ty.indexes()).await?;
+
+        let rename_table = Table::rename()
+            .table(Alias::new(ty.backing_table()), Alias::new(archived_table))
+            .to_owned();
+        let rename_table = rename_table.build_any(self.db.schema_builder());
+        let rename_table = sqlx::query(&rename_table);
+        transaction.execute(rename_table).await?;
 
         Ok(())
     }
 
+    pub async fn drop_archived_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        archived_table: &str,
+    ) -> Result<()> {
+        let drop_table = Table::drop()
+            .table(Alias::new(archived_table))
+            .if_exists()
+            .to_owned();
+        let drop_table = drop_table.build_any(self.db.schema_builder());
+        let drop_table = sqlx::query(&drop_table);
+        transaction.execute(drop_table).await?;
+
+        Ok(())
+    }
+
+    /// Creates the table of the key-value store of the version (see `kv`), if it does not exist.
+    pub async fn create_kv_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+    ) -> Result<()> {
+        // the keys are compared byte-wise, so that `Chisel.kv.list()` is ordered the same way on
+        // both databases (SQLite compares byte-wise by default)
+        let collate = match self.target_db() {
+            TargetDatabase::Postgres => r#" COLLATE "C""#,
+            TargetDatabase::Sqlite => "",
+        };
+        let sql = format!(
+            r#"CREATE TABLE IF NOT EXISTS "{}" ("key" TEXT{} PRIMARY KEY, "value" TEXT NOT NULL, "expires_at" DOUBLE PRECISION)"#,
+            kv::kv_table(version_id),
+            collate,
+        );
+        transaction.execute(sqlx::query(&sql)).await?;
+        Ok(())
+    }
+
+    pub async fn drop_kv_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+    ) -> Result<()> {
+        let sql = format!(r#"DROP TABLE IF EXISTS "{}""#, kv::kv_table(version_id));
+        transaction.execute(sqlx::query(&sql)).await?;
+        Ok(())
+    }
+
     pub async fn begin_transaction_static(&self) -> Result<TransactionStatic> {
-        Ok(Arc::new(Mutex::new(self.db.pool.begin().await?)))
+        Ok(Arc::new(Mutex::new(self.db.begin().await?)))
     }
 
     pub async fn create_data_context(
@@ -234,20 +695,91 @@ impl QueryEngine {
         type_system: Arc<TypeSystem>,
         policy_system: Arc<PolicySystem>,
         policy_context: PolicyContext,
+        anonymization_key: Option<Arc<str>>,
         job_info: Rc<JobInfo>,
     ) -> Result<DataContext> {
-        let txn = self.begin_transaction_static().await?;
+        let mut txn = self.db.begin().await?;
+        // on Postgres, the server cancels statements that would outlive the deadline of the job,
+        // so that they don't keep running after the job was cancelled
+        let remaining = job_info
+            .cancel_token()
+            .and_then(|cancel| cancel.remaining());
+        if let (AnyKind::Postgres, Some(remaining)) = (self.db.pool.any_kind(), remaining) {
+            // a timeout of 0 disables the timeout, but the job is cancelled anyway
+            let timeout_ms = remaining.as_millis().max(1);
+            let sql = format!("SET LOCAL statement_timeout = {}", timeout_ms);
+            txn.execute(sql.as_str()).await?;
+        }
+        let txn = Arc::new(Mutex::new(txn));
+        let txn_guard = self
+            .txn_stats
+            .begin(type_system.version_id.clone(), job_info.description());
         Ok(DataContext {
             type_system,
             policy_system,
             policy_context: policy_context.into(),
+            anonymization_key,
             txn,
             job_info,
+            query_cache: self.cache.clone(),
+            written_tables: Default::default(),
+            txn_guard,
         })
     }
 
+    /// Reports the statistics of transactions and, on Postgres, the sessions that wait for locks
+    /// while touching the backing tables of entities.
+    pub async fn txn_stats_report(&self) -> Result<TxnStatsReport> {
+        let lock_waits = match self.db.pool.any_kind() {
+            AnyKind::Postgres => Some(self.lock_waits().await?),
+            AnyKind::Sqlite => None,
+        };
+        Ok(self.txn_stats.report(lock_waits))
+    }
+
+    async fn lock_waits(&self) -> Result<Vec<LockWait>> {
+        let rows = sqlx::query(
+            r"
+            SELECT
+                a.pid AS pid,
+                array_to_string(pg_blocking_pids(a.pid), ',') AS blocking_pids,
+                CAST(EXTRACT(EPOCH FROM now() - a.query_start) AS float8) AS waiting_s,
+                t.tables AS tables,
+                a.query AS query
+            FROM pg_stat_activity a
+            JOIN LATERAL (
+                SELECT string_agg(DISTINCT CAST(c.relname AS text), ',') AS tables
+                FROM pg_locks l
+                JOIN pg_class c ON c.oid = l.relation
+                WHERE l.pid = a.pid AND c.relname LIKE 'ty\_%'
+            ) t ON t.tables IS NOT NULL
+            WHERE a.datname = current_database() AND cardinality(pg_blocking_pids(a.pid)) > 0
+            ORDER BY waiting_s DESC",
+        )
+        .fetch_all(&self.db.pool)
+        .await
+        .context("Could not query lock waits")?;
+
+        let split = |s: &str| s.split(',').filter(|x| !x.is_empty()).map(str::to_owned);
+        rows.iter()
+            .map(|row| {
+                let blocking_pids: String = row.try_get("blocking_pids")?;
+                let tables: String = row.try_get("tables")?;
+                Ok(LockWait {
+                    pid: row.try_get("pid")?,
+                    blocking_pids: split(&blocking_pids)
+                        .map(|pid| pid.parse())
+                        .collect::<Result<_, _>>()?,
+                    waiting_s: row.try_get("waiting_s")?,
+                    tables: split(&tables).collect(),
+                    query: row.try_get("query")?,
+                })
+            })
+            .collect()
+    }
+
     pub async fn begin_transaction(&self) -> Result<Transaction<'static, Any>> {
-        Ok(self.db.pool.begin().await?)
+        self.db.begin().await
     }
 
     pub async fn commit_transaction(transaction: Transaction<'static, Any>) -> Result<()> {
@@ -272,7 +804,7 @@ impl QueryEngine {
             .to_owned();
 
         for field in ty.all_fields() {
-            let mut column_def = ColumnDef::try_from(field)?;
+            let mut column_def = self.column_def(field)?;
             create_table.col(&mut column_def);
         }
         let create_table = create_table.build_any(self.db.schema_builder());
@@ -280,10 +812,176 @@ impl QueryEngine {
         let create_table = sqlx::query(&create_table);
         transaction.execute(create_table).await?;
 
+        self.create_nocase_indexes(transaction, ty, ty.all_fields())
+            .await?;
         Self::create_indexes(transaction, ty, ty.indexes()).await?;
         Ok(())
     }
 
+    /// Returns the definition of the column of `field`. SQLite declares the columns of
+    /// case-insensitive fields with `COLLATE NOCASE`. Postgres has no such collation, so the
+    /// uniqueness of these fields is enforced by an index on the lowercase values instead (see
+    /// `create_nocase_indexes()`).
+    fn column_def(&self, field: &Field) -> Result<ColumnDef> {
+        if !field.is_nocase() {
+            return ColumnDef::try_from(field);
+        }
+        match self.db.pool.any_kind() {
+            AnyKind::Sqlite => {
+                let mut column_def = ColumnDef::try_from(field)?;
+                column_def.extra("COLLATE NOCASE".into());
+                Ok(column_def)
+            }
+            AnyKind::Postgres => {
+                let mut field = field.clone();
+                field.is_unique = false;
+                ColumnDef::try_from(&field)
+            }
+        }
+    }
+
+    /// Creates the unique indexes on the lowercase values of the unique case-insensitive
+    /// `fields` on Postgres.
+    async fn create_nocase_indexes(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        fields: impl Iterator<Item = &Field>,
+    ) -> Result<()> {
+        if self.db.pool.any_kind() != AnyKind::Postgres {
+            return Ok(());
+        }
+        for field in fields.filter(|field| field.is_unique && field.is_nocase()) {
+            let idx_name = format!("{}_{}_nocase", ty.backing_table(), field.name);
+            let create_index = format!(
+                r#"CREATE UNIQUE INDEX IF NOT EXISTS "{}" ON "{}" (LOWER("{}"))"#,
+                query::truncate_identifier(&idx_name),
+                ty.backing_table(),
+                field.name
+            );
+            transaction.execute(sqlx::query(&create_index)).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds the hidden column that stores the tenant of the rows to the table of `ty`, unless the
+    /// table already has it (see `datastore::tenancy`).
+    pub async fn add_tenant_column(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+    ) -> Result<()> {
+        let table = ty.backing_table();
+        let add_column = match self.db.pool.any_kind() {
+            AnyKind::Postgres => {
+                format!(r#"ALTER TABLE "{table}" ADD COLUMN IF NOT EXISTS "{TENANT_COLUMN}" TEXT"#)
+            }
+            AnyKind::Sqlite => {
+                let has_column = format!(
+                    "SELECT 1 FROM pragma_table_info('{table}') WHERE name = '{TENANT_COLUMN}'"
+                );
+                if transaction
+                    .fetch_optional(sqlx::query(&has_column))
+                    .await?
+                    .is_some()
+                {
+                    return Ok(());
+                }
+                format!(r#"ALTER TABLE "{table}" ADD COLUMN "{TENANT_COLUMN}" TEXT"#)
+            }
+        };
+        transaction.execute(sqlx::query(&add_column)).await?;
+
+        let idx_name = format!("{table}_{TENANT_COLUMN}");
+        let create_index = format!(
+            r#"CREATE INDEX IF NOT EXISTS "{}" ON "{table}" ("{TENANT_COLUMN}")"#,
+            query::truncate_identifier(&idx_name),
+        );
+        transaction.execute(sqlx::query(&create_index)).await?;
+        Ok(())
+    }
+
+    /// Deletes all entries of the key-value store of the version.
+    pub async fn truncate_kv_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+    ) -> Result<()> {
+        let sql = format!(r#"DELETE FROM "{}""#, kv::kv_table(version_id));
+        transaction.execute(sqlx::query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Whether the datastore can load and dump tables with `COPY` (only Postgres can), see
+    /// `copy_in_csv()` and `copy_out_csv()`.
+    pub fn supports_copy(&self) -> bool {
+        matches!(self.db.pool.any_kind(), AnyKind::Postgres)
+    }
+
+    /// Loads CSV records (without a header) into `columns` of the table of `ty` with `COPY ...
+    /// FROM STDIN`. Returns the number of rows.
+    pub async fn copy_in_csv(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        columns: &[String],
+        csv: &[u8],
+    ) -> Result<u64> {
+        let conn = match transaction.private_get_mut() {
+            AnyConnectionKind::Postgres(conn) => conn,
+            _ => anyhow::bail!("COPY is only supported on Postgres"),
+        };
+        let sql = format!(
+            r#"COPY "{}" ({}) FROM STDIN WITH (FORMAT csv)"#,
+            ty.backing_table(),
+            columns.iter().map(|c| format!(r#""{}""#, c)).join(", "),
+        );
+        let mut copy = conn.copy_in_raw(&sql).await?;
+        if let Err(e) = copy.send(csv).await {
+            copy.abort(e.to_string()).await?;
+            return Err(e.into());
+        }
+        Ok(copy.finish().await?)
+    }
+
+    /// Dumps `columns` of the table of `ty` as CSV with a header with `COPY ... TO STDOUT`. Every
+    /// chunk of the stream is a single record, the first one is the header.
+    pub async fn copy_out_csv<'t>(
+        &self,
+        transaction: &'t mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        columns: &[String],
+    ) -> Result<BoxStream<'t, Result<Vec<u8>>>> {
+        let conn = match transaction.private_get_mut() {
+            AnyConnectionKind::Postgres(conn) => conn,
+            _ => anyhow::bail!("COPY is only supported on Postgres"),
+        };
+        let sql = format!(
+            r#"COPY (SELECT {} FROM "{}" ORDER BY "id") TO STDOUT WITH (FORMAT csv, HEADER true)"#,
+            columns.iter().map(|c| format!(r#""{}""#, c)).join(", "),
+            ty.backing_table(),
+        );
+        let stream = conn.copy_out_raw(&sql).await?;
+        Ok(stream
+            .map(|chunk| {
+                chunk
+                    .map(|chunk| chunk.to_vec())
+                    .map_err(anyhow::Error::from)
+            })
+            .boxed())
+    }
+
+    /// Deletes all rows of the table of `ty`.
+    pub async fn truncate_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+    ) -> Result<()> {
+        let sql = format!(r#"DELETE FROM "{}""#, ty.backing_table());
+        transaction.execute(sqlx::query(&sql)).await?;
+        Ok(())
+    }
+
     pub async fn alter_table(
         &self,
         transaction: &mut Transaction<'_, Any>,
@@ -321,8 +1019,19 @@ impl QueryEngine {
         // FIXME: When we start generating indexes or using foreign keys, we'll have to make sure
         // that those are still safe. Adding columns is always safe, but removals may not be if
         // they are used in relations or indexes (see the document above)
+        //
+        // Renaming a column keeps its data and its indexes (SQLite supports it since 3.25).
+        for rename in delta.renamed_fields.iter() {
+            let table = Table::alter()
+                .table(Alias::new(ty.backing_table()))
+                .rename_column(Alias::new(&rename.old_name), Alias::new(&rename.new_name))
+                .to_owned();
+
+            do_query!(table)?;
+        }
+
         for field in delta.added_fields.iter() {
-            let mut column_def = ColumnDef::try_from(field)?;
+            let mut column_def = self.column_def(field)?;
             let table = Table::alter()
                 .table(Alias::new(ty.backing_table()))
                 .add_column(&mut column_def)
@@ -347,6 +1056,8 @@ impl QueryEngine {
         // since we always write with defaults. For all others, we should error out way before we
         // get here.
 
+        self.create_nocase_indexes(transaction, ty, delta.added_fields.iter())
+            .await?;
         Self::create_indexes(transaction, ty, ty.indexes()).await?;
 
         Ok(())
@@ -429,7 +1140,11 @@ impl QueryEngine {
                             let val: f64 = row.get_unchecked(column_idx);
                             EntityValue::JsDate(val)
                         }
-                        TypeId::Int64 => EntityValue::Float64(row.get::<i64, _>(column_idx) as f64),
+                        TypeId::Int64 => EntityValue::Int64(row.get::<i64, _>(column_idx)),
+                        TypeId::Decimal { scale, .. } => EntityValue::String(decimal::format(
+                            row.get::<i64, _>(column_idx),
+                            *scale,
+                        )),
                         TypeId::String | TypeId::Id | TypeId::EntityId { .. } => {
                             let val = row.get::<&str, _>(column_idx);
                             EntityValue::String(val.to_owned())
@@ -456,10 +1171,15 @@ impl QueryEngine {
                             serde_json::from_value::<EntityValue>(array_json)
                                 .context("failed to deserialize array from raw JSON string")?
                         }
+                        TypeId::Json | TypeId::GeoPoint => {
+                            let json = row.get::<serde_json::Value, _>(column_idx);
+                            serde_json::from_value::<EntityValue>(json)
+                                .context("failed to deserialize JSON value from raw JSON string")?
+                        }
                     };
                     if let Some(tr) = transform {
                         // Apply policy transformation
-                        val = tr(val);
+                        val = tr.apply(val);
                     }
                     ret.insert(name.clone(), val);
                 }
@@ -478,7 +1198,7 @@ impl QueryEngine {
                     let mut val = EntityValue::Map(val);
                     if let Some(tr) = transform {
                         // Apply policy transformation
-                        val = tr(val);
+                        val = tr.apply(val);
                     }
                     ret.insert(name.clone(), val);
                 }
@@ -487,51 +1207,354 @@ impl QueryEngine {
         Ok(ret)
     }
 
-    fn project(
-        o: Result<EntityMap>,
-        allowed_fields: &Option<HashSet<String>>,
-    ) -> Result<EntityMap> {
-        let mut o = o?;
-        if let Some(allowed_fields) = &allowed_fields {
-            let removed_keys = o
-                .iter()
-                .map(|(k, _)| k.to_owned())
-                .filter(|k| !allowed_fields.contains(k))
-                .collect::<Vec<String>>();
-            for k in &removed_keys {
-                o.remove(k);
-            }
-        }
-        Ok(o)
-    }
-
     /// Execute the given `query` and return a stream to the results.
     pub fn query(
         &self,
         txn: TransactionStatic,
         query_plan: QueryPlan,
     ) -> anyhow::Result<QueryResults> {
+        self.record_query_stats(&query_plan);
         let query = query_plan.build_query(&self.target_db())?;
-        let allowed_fields = query.allowed_fields;
+        Ok(self.query_results(txn, query, query_plan.otel_context()))
+    }
+
+    fn record_query_stats(&self, query_plan: &QueryPlan) {
+        self.query_stats.record(
+            query_plan.base_type().backing_table(),
+            query_plan.index_fields(),
+        );
+    }
+
+    /// Suggests indexes for the entities of `ts` from the fields that the queries filtered and
+    /// sorted by since the server started. Queries that are already served by an index (or by a
+    /// unique field) are not considered. The suggestions are ordered by their estimated benefit.
+    pub async fn index_suggestions(&self, ts: &TypeSystem) -> Result<Vec<IndexSuggestion>> {
+        let mut txn = self.begin_transaction().await?;
+        let mut suggestions = vec![];
+        for ty in ts.custom_types.values() {
+            let counts = self
+                .query_stats
+                .table_counts(ty.backing_table())
+                .into_iter()
+                .filter(|(fields, _)| fields.iter().all(|f| ty.get_field(f).is_some()))
+                .filter(|(fields, _)| !is_indexed(ty, fields))
+                .collect::<Vec<_>>();
+            if counts.is_empty() {
+                continue;
+            }
+            let sql = format!("SELECT COUNT(*) AS count FROM \"{}\"", ty.backing_table());
+            let row = txn.fetch_one(sqlx::query(&sql)).await?;
+            let row_count = row.get::<i64, _>("count") as u64;
+            for (fields, query_count) in query_stats::merge_prefixes(counts) {
+                suggestions.push(IndexSuggestion {
+                    entity_name: ty.name().to_owned(),
+                    fields,
+                    query_count,
+                    row_count,
+                    estimated_rows_saved: query_count * row_count,
+                });
+            }
+        }
+        QueryEngine::commit_transaction(txn).await?;
+        suggestions.sort_by(|x, y| {
+            y.estimated_rows_saved
+                .cmp(&x.estimated_rows_saved)
+                .then_with(|| x.entity_name.cmp(&y.entity_name))
+                .then_with(|| x.fields.cmp(&y.fields))
+        });
+        Ok(suggestions)
+    }
+
+    fn query_results(
+        &self,
+        txn: TransactionStatic,
+        query: Query,
+        parent: &opentelemetry::Context,
+    ) -> QueryResults {
         let db_kind = self.db.pool.any_kind();
 
+        let span_cx = telemetry::start_sql_span(parent, "select", &query.raw_sql);
         let stream = new_query_results(query.raw_sql, txn);
-        let stream =
-            stream.map(move |row| Self::row_to_entity_value(db_kind, &query.fields, &row?));
-        let stream = Box::pin(stream.map(move |o| Self::project(o, &allowed_fields)));
-        Ok(stream)
+        let stream = self.db.metrics.timed_stream("select", stream);
+        let stream = SpannedStream::new(stream, span_cx);
+        Box::pin(stream.map(move |row| Self::row_to_entity_value(db_kind, &query.fields, &row?)))
+    }
+
+    pub fn clear_query_cache(&self) {
+        self.cache.clear();
+    }
+
+    /// Invalidates the cached queries that read `table`, which was changed outside of a
+    /// `DataContext`.
+    pub fn invalidate_query_cache(&self, table: &str) {
+        self.cache.invalidate([&table.to_owned()]);
+    }
+
+    /// Execute the given `query` like `query()`, but serve the results from the query cache if
+    /// they were cached less than `ttl` ago.
+    ///
+    /// The cache is bypassed if the transaction has mutated any table that the query reads,
+    /// because the results would contain uncommitted changes.
+    pub async fn query_cached(
+        &self,
+        ctx: &DataContext,
+        query_plan: QueryPlan,
+        ttl: Duration,
+    ) -> Result<QueryResults> {
+        let tables = query_plan.backing_tables();
+        let written = {
+            let written_tables = ctx.written_tables.borrow();
+            tables.iter().any(|table| written_tables.contains(table))
+        };
+        if !self.cache.is_enabled() || written {
+            return self.query(ctx.txn.clone(), query_plan);
+        }
+
+        self.record_query_stats(&query_plan);
+        let query = query_plan.build_query(&self.target_db())?;
+        let key = CacheKey {
+            version_id: ctx.type_system.version_id.clone(),
+            sql: query.raw_sql.clone(),
+            user_id: ctx.job_info.user_id().map(ToString::to_string),
+            path: ctx.job_info.path().unwrap_or_default().to_string(),
+        };
+        let rows = match self.cache.get(&key, Instant::now()) {
+            Some(rows) => rows,
+            None => {
+                let generations = self.cache.generations(&tables);
+                let rows = self
+                    .query_results(ctx.txn.clone(), query, query_plan.otel_context())
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                let rows = Arc::new(rows);
+                let expires_at = Instant::now() + ttl;
+                self.cache
+                    .insert(key, rows.clone(), generations, expires_at);
+                rows
+            }
+        };
+        let rows = (*rows).clone();
+        Ok(Box::pin(futures::stream::iter(rows.into_iter().map(Ok))))
     }
 
+    /// Executes all `query_plans` and returns their rows, in the order of the plans.
+    ///
+    /// On Postgres, the queries that read no table written in the transaction of `ctx` are sent
+    /// at once, each on its own connection of the pool, so that a batch of independent queries
+    /// costs about one roundtrip to the database instead of one per query. They see the same
+    /// data as in the transaction, because with the `READ COMMITTED` isolation every statement
+    /// sees what was committed before it started. One batch uses at most half of the connections
+    /// of the pool. The other queries run one after another in the transaction.
+    pub async fn query_batch(
+        &self,
+        ctx: &DataContext,
+        query_plans: Vec<QueryPlan>,
+    ) -> Result<Vec<Vec<EntityMap>>> {
+        let mut in_txn = vec![];
+        let mut pooled = vec![];
+        for (idx, query_plan) in query_plans.into_iter().enumerate() {
+            self.record_query_stats(&query_plan);
+            let query = query_plan.build_query(&self.target_db())?;
+            let otel_cx = query_plan.otel_context().clone();
+            let written = {
+                let written_tables = ctx.written_tables.borrow();
+                query_plan
+                    .backing_tables()
+                    .iter()
+                    .any(|table| written_tables.contains(table))
+            };
+            match self.db.pool.any_kind() {
+                AnyKind::Postgres if !written => pooled.push((idx, query, otel_cx)),
+                _ => in_txn.push((idx, query, otel_cx)),
+            }
+        }
+
+        let in_txn = async {
+            let mut results = vec![];
+            for (idx, query, otel_cx) in in_txn {
+                let rows = self
+                    .query_results(ctx.txn.clone(), query, &otel_cx)
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                results.push((idx, rows));
+            }
+            Ok::<_, anyhow::Error>(results)
+        };
+        let depth = (self.db.max_connections() / 2).max(1) as usize;
+        let pooled = futures::stream::iter(pooled)
+            .map(|(idx, query, otel_cx)| async move {
+                let rows = self.fetch_from_pool(query, &otel_cx).await?;
+                Ok::<_, anyhow::Error>((idx, rows))
+            })
+            .buffer_unordered(depth)
+            .try_collect::<Vec<_>>();
+        let (mut results, pooled): (Vec<_>, Vec<_>) = futures::try_join!(in_txn, pooled)?;
+        results.extend(pooled);
+        results.sort_by_key(|(idx, _)| *idx);
+        Ok(results.into_iter().map(|(_, rows)| rows).collect())
+    }
+
+    /// Executes `query` on a connection of the pool, outside of any transaction.
+    async fn fetch_from_pool(
+        &self,
+        query: Query,
+        parent: &opentelemetry::Context,
+    ) -> Result<Vec<EntityMap>> {
+        let db_kind = self.db.pool.any_kind();
+        let span_cx = telemetry::start_sql_span(parent, "select", &query.raw_sql);
+        let started_at = Instant::now();
+        let rows = sqlx::query(&query.raw_sql)
+            .fetch_all(&self.db.pool)
+            .await
+            .map_err(anyhow::Error::from);
+        telemetry::end(&span_cx, &rows);
+        self.db
+            .metrics
+            .observe_query("select", started_at.elapsed());
+        rows?
+            .iter()
+            .map(|row| Self::row_to_entity_value(db_kind, &query.fields, row))
+            .collect()
+    }
+
+    /// Executes the `mutation` and returns the number of affected rows.
     pub async fn mutate_with_transaction(
         &self,
         mutation: Mutation,
         txn: &mut Transaction<'_, Any>,
-    ) -> Result<()> {
+    ) -> Result<u64> {
+        if mutation.has_references() {
+            return self.mutate_by_ids(mutation, txn).await;
+        }
+        let otel_cx = mutation.otel_context();
+        for raw_sql in mutation.build_count_sql(self.target_db())? {
+            execute_traced(txn, sqlx::query(&raw_sql), "count_update", otel_cx).await?;
+        }
         let raw_sql = mutation.build_sql(self.target_db())?;
         let query = sqlx::query(&raw_sql);
-        txn.execute(query).await?;
+        let started_at = Instant::now();
+        let result = execute_traced(txn, query, "mutation", otel_cx).await?;
+        self.db
+            .metrics
+            .observe_query("mutation", started_at.elapsed());
+        for raw_sql in mutation.build_aggregate_sql() {
+            execute_traced(txn, sqlx::query(&raw_sql), "aggregate_update", otel_cx).await?;
+        }
 
-        Ok(())
+        Ok(result.rows_affected())
+    }
+
+    /// Executes a `mutation` that must follow `@onDelete` references: collects the ids of all
+    /// deleted instances, checks the restricting references and then deletes the instances entity
+    /// by entity. Returns the number of deleted instances of the base entity.
+    async fn mutate_by_ids(
+        &self,
+        mutation: Mutation,
+        txn: &mut Transaction<'_, Any>,
+    ) -> Result<u64> {
+        let otel_cx = mutation.otel_context();
+        let started_at = Instant::now();
+        let base_name = mutation.base_entity_name().to_owned();
+        let ids_sql = mutation.build_ids_sql(self.target_db())?;
+        let base_ids: Vec<String> = txn
+            .fetch_all(sqlx::query(&ids_sql))
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        // ids of the deleted instances, keyed by the name of the entity
+        let mut deleted = HashMap::<String, HashSet<String>>::new();
+        deleted.insert(base_name.clone(), base_ids.iter().cloned().collect());
+        let mut pending = vec![(base_name.clone(), base_ids)];
+        while let Some((name, ids)) = pending.pop() {
+            for reference in mutation.references_to(&name) {
+                if reference.on_delete != OnDelete::Cascade {
+                    continue;
+                }
+                let referring = reference.referring.name();
+                let rows =
+                    fetch_referring_rows(txn, reference, &ids, mutation.tenant(), otel_cx).await?;
+                let deleted_referring = deleted.entry(referring.to_owned()).or_default();
+                let new_ids: Vec<String> = rows
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .filter(|id| deleted_referring.insert(id.clone()))
+                    .collect();
+                if !new_ids.is_empty() {
+                    pending.push((referring.to_owned(), new_ids));
+                }
+            }
+        }
+
+        for (name, ids) in deleted.iter() {
+            let ids: Vec<String> = ids.iter().cloned().collect();
+            for reference in mutation.references_to(name) {
+                if reference.on_delete != OnDelete::Restrict {
+                    continue;
+                }
+                let referring = reference.referring.name();
+                let rows =
+                    fetch_referring_rows(txn, reference, &ids, mutation.tenant(), otel_cx).await?;
+                let kept = rows.into_iter().find(|(referring_id, _)| {
+                    !deleted
+                        .get(referring)
+                        .map_or(false, |ids| ids.contains(referring_id))
+                });
+                if let Some((referring_id, id)) = kept {
+                    return Err(RestrictError {
+                        entity: name.clone(),
+                        id,
+                        referring: referring.to_owned(),
+                        field: reference.field.clone(),
+                        referring_id,
+                    }
+                    .into());
+                }
+            }
+        }
+
+        let mut rows_affected = 0;
+        for (name, ids) in deleted.iter() {
+            let (table, counters) = mutation.deleted_entity(name)?;
+            let ids: Vec<&String> = ids.iter().collect();
+            for chunk in ids.chunks(CASCADE_CHUNK_SIZE) {
+                let params = query::id_params(chunk.len());
+                for raw_sql in query::count_update_sql(counters, &params) {
+                    let query = bind_ids(sqlx::query(&raw_sql), chunk);
+                    execute_traced(txn, query, "count_update", otel_cx).await?;
+                }
+                let raw_sql = query::delete_ids_sql(table, chunk.len());
+                let query = bind_ids(sqlx::query(&raw_sql), chunk);
+                let result = execute_traced(txn, query, "mutation", otel_cx).await?;
+                if *name == base_name {
+                    rows_affected += result.rows_affected();
+                }
+            }
+        }
+
+        // the instances that are deleted are gone now, so this only updates the kept ones
+        for (name, ids) in deleted.iter() {
+            let ids: Vec<&String> = ids.iter().collect();
+            for reference in mutation.references_to(name) {
+                if reference.on_delete != OnDelete::SetNull {
+                    continue;
+                }
+                for chunk in ids.chunks(CASCADE_CHUNK_SIZE) {
+                    let raw_sql = query::set_null_sql(reference, chunk.len(), mutation.tenant());
+                    let query = bind_ids(sqlx::query(&raw_sql), chunk);
+                    execute_traced(txn, query, "set_null", otel_cx).await?;
+                }
+            }
+        }
+        self.db
+            .metrics
+            .observe_query("mutation", started_at.elapsed());
+
+        for raw_sql in mutation.build_aggregate_sql() {
+            execute_traced(txn, sqlx::query(&raw_sql), "aggregate_update", otel_cx).await?;
+        }
+        Ok(rows_affected)
     }
 
     /// Inserts object of type `ty` and value `ty_value` into the database.
@@ -549,23 +1572,484 @@ impl QueryEngine {
         record: EntityMap,
         ctx: &DataContext,
     ) -> Result<(EntityMap, IdTree)> {
+        let (record, id_tree, inserts) = self.prepare_row(&ty, record, ctx).await?;
+        let txn = ctx.txn.clone();
+        let mut txn = txn.lock().await;
+        self.execute_insertions(&inserts, &mut txn, &ctx.job_info.otel_context())
+            .await?;
+        Ok((record, id_tree))
+    }
+
+    /// Inserts a batch of objects of type `ty`, like `add_row()`, but locks the transaction only
+    /// once for the whole batch.
+    pub async fn add_rows(
+        &self,
+        ty: Arc<ObjectType>,
+        records: Vec<EntityMap>,
+        ctx: &DataContext,
+    ) -> Result<()> {
+        let mut inserts = Vec::with_capacity(records.len());
+        for record in records {
+            let (_, _, row_inserts) = self.prepare_row(&ty, record, ctx).await?;
+            inserts.extend(row_inserts);
+        }
+        let txn = ctx.txn.clone();
+        let mut txn = txn.lock().await;
+        self.execute_insertions(&inserts, &mut txn, &ctx.job_info.otel_context())
+            .await
+    }
+
+    /// Inserts `create` (together with the field in `find`) into the table of `ty` or, if a row
+    /// with the same value of the unique field in `find` already exists, writes the fields of
+    /// `update` into that row (see `ChiselEntity.upsert()`). This is a single `INSERT ... ON
+    /// CONFLICT DO UPDATE`, so concurrent upserts of the same row don't race. Returns the id of the
+    /// written row.
+    ///
+    /// Related entities are written as their ids. Entities with a `@version` field, marked with
+    /// `@ownedBy`, with `@count` fields or counted, and aggregated entities are not supported.
+    pub async fn upsert_row(
+        &self,
+        ty: Arc<ObjectType>,
+        find: EntityMap,
+        mut create: EntityMap,
+        update: EntityMap,
+        ctx: &DataContext,
+    ) -> Result<String> {
+        let ts = &ctx.type_system;
+        let unsupported = |what: &str| {
+            anyhow!(
+                "upsert() is not supported for {}, because it {}",
+                ty.name(),
+                what
+            )
+        };
+        if feat_typescript_policies() {
+            anyhow::bail!("upsert() is not supported with TypeScript policies");
+        }
+        if ty.aggregate().is_some() || !ts.aggregators_of(ty.name()).is_empty() {
+            return Err(unsupported("is aggregated"));
+        }
+        if ty.all_fields().any(|f| f.is_version) {
+            return Err(unsupported("has a @version field"));
+        }
+        if ty.owned_by().is_some() {
+            return Err(unsupported("is marked with @ownedBy"));
+        }
+        let tenant = tenancy::written_tenant(ctx.job_info.tenant_scope(), ty.name())?;
+        let counted = |c: &Counter| c.counted.name() == ty.name() || c.counting.name() == ty.name();
+        if ts.counters().iter().any(counted) {
+            return Err(unsupported("has a @count field or is counted"));
+        }
+
+        let mut find = find.into_iter();
+        let (key, key_value) = match (find.next(), find.next()) {
+            (Some(entry), None) => entry,
+            _ => anyhow::bail!("upsert(): `find` must have exactly one field"),
+        };
+        let key_field = ty
+            .get_field(&key)
+            .with_context(|| format!("upsert(): entity {} has no field `{}`", ty.name(), key))?;
+        anyhow::ensure!(
+            key_field.type_id == TypeId::Id || key_field.is_unique,
+            "upsert(): field `{}` of {} in `find` must be the id or a @unique field",
+            key,
+            ty.name()
+        );
+        anyhow::ensure!(
+            !key_value.is_null(),
+            "upsert(): the value of `{}` in `find` is null",
+            key
+        );
+        match create.get(&key) {
+            Some(value) if !value.is_null() && *value != key_value => anyhow::bail!(
+                "upsert(): `create` and `find` have different values of `{}`",
+                key
+            ),
+            _ => create.insert(key.clone(), key_value),
+        };
+        create.retain(|_, value| !value.is_null());
+
+        let mut args = vec![];
+        let mut written_at = None;
+        let mut columns = vec![];
+        let mut binds = vec![];
+        for field in ty.all_fields() {
+            if !create.contains_key(&field.name) && field.is_optional {
+                continue;
+            }
+            let arg = match ts.get(&field.type_id)? {
+                Type::Entity(_) => related_id("upsert", &ty, field, create.get(&field.name))?,
+                _ => self.field_argument(&ty, field, &create, &mut written_at)?,
+            };
+            args.push(arg);
+            columns.push(format!(r#""{}""#, field.name));
+            binds.push(format!("${}", args.len()));
+        }
+        // the row is only updated if it belongs to the tenant, otherwise nothing is returned
+        let mut tenant_condition = String::new();
+        if let Some(tenant) = tenant {
+            args.push(SqlValue::String(tenant.to_owned()));
+            columns.push(format!(r#""{TENANT_COLUMN}""#));
+            binds.push(format!("${}", args.len()));
+            tenant_condition = format!(
+                r#" WHERE "{}"."{TENANT_COLUMN}" = ${}"#,
+                ty.backing_table(),
+                args.len()
+            );
+        }
+        for name in create.keys() {
+            anyhow::ensure!(
+                ty.has_field(name),
+                "field {} not present in {}",
+                name,
+                ty.name()
+            );
+        }
+
+        let mut sets = vec![];
+        for (name, value) in update.iter() {
+            let field = ty
+                .get_field(name)
+                .with_context(|| format!("field {} not present in {}", name, ty.name()))?;
+            anyhow::ensure!(
+                field.type_id != TypeId::Id,
+                "upsert(): `update` cannot change the id"
+            );
+            // the timestamps are set by the server, the values of the object are ignored
+            if field.timestamp.is_some() {
+                continue;
+            }
+            let bind = if value.is_null() {
+                anyhow::ensure!(
+                    field.is_optional,
+                    "upsert(): field `{}` of {} is not optional",
+                    name,
+                    ty.name()
+                );
+                "NULL".to_owned()
+            } else {
+                let arg = match ts.get(&field.type_id)? {
+                    Type::Entity(_) => related_id("upsert", &ty, field, Some(value))?,
+                    _ => self.field_argument(&ty, field, &update, &mut written_at)?,
+                };
+                args.push(arg);
+                format!("${}", args.len())
+            };
+            sets.push(format!(r#""{}" = {}"#, name, bind));
+        }
+        if sets.is_empty() {
+            // an update that changes nothing, so that the existing row is still returned
+            sets.push(format!(r#""{0}" = excluded."{0}""#, key));
+        } else {
+            for field in ty.all_fields() {
+                if field.timestamp == Some(Timestamp::Updated) {
+                    args.push(SqlValue::F64(*written_at.get_or_insert_with(now_ms)));
+                    sets.push(format!(r#""{}" = ${}"#, field.name, args.len()));
+                }
+            }
+        }
+
+        // the uniqueness of case-insensitive fields is enforced by an index on the lowercase
+        // values on Postgres, see `column_def()`
+        let conflict_target = match self.db.pool.any_kind() {
+            AnyKind::Postgres if key_field.is_nocase() => format!(r#"LOWER("{}")"#, key),
+            _ => format!(r#""{}""#, key),
+        };
+        let query = SqlWithArguments {
+            sql: format!(
+                r#"INSERT INTO "{}" ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {}{} RETURNING "id""#,
+                ty.backing_table(),
+                columns.join(", "),
+                binds.join(", "),
+                conflict_target,
+                sets.join(", "),
+                tenant_condition,
+            ),
+            args,
+        };
+        let mut written_tables = HashSet::new();
+        collect_written_tables(&ty, ts, &mut written_tables);
+        ctx.mark_written(written_tables);
+        ctx.txn_guard.add_rows_written(1);
+
+        let txn = ctx.txn.clone();
+        let mut txn = txn.lock().await;
+        let started_at = Instant::now();
+        let row = (&mut *txn)
+            .fetch_one(query.get_sqlx())
+            .await
+            .with_context(|| format!("Could not upsert {}", ty.name()))?;
+        self.db
+            .metrics
+            .observe_query("insert", started_at.elapsed());
+        Ok(row.try_get("id")?)
+    }
+
+    /// Writes the `fields` of the row with the given `id` with a single `UPDATE ... SET`
+    /// (`ChiselEntity.update()`), leaving the other fields untouched. Returns false if there is
+    /// no such row.
+    ///
+    /// With TypeScript policies, the update policies and transforms are applied to the written
+    /// fields and the id. If `fields` has the `@version` field, the stored version must match it,
+    /// otherwise a `ConflictError` is returned; the version is incremented in any case. Related
+    /// entities are written as their ids.
+    pub async fn update_row(
+        &self,
+        ty: Arc<ObjectType>,
+        id: String,
+        mut fields: EntityMap,
+        ctx: &DataContext,
+    ) -> Result<bool> {
+        let ts = &ctx.type_system;
+        if ty.aggregate().is_some() {
+            anyhow::bail!(
+                "entity `{}` is a materialized aggregate, so it cannot be written",
+                ty.name()
+            );
+        }
+        match fields.remove("id") {
+            Some(EntityValue::String(new_id)) if new_id != id => {
+                anyhow::bail!("update() cannot change the id of {}", ty.name())
+            }
+            _ => {}
+        }
+        if feat_typescript_policies() {
+            fields.insert("id".to_owned(), EntityValue::String(id.clone()));
+            let policy_ctx = ctx.policy_context.clone();
+            fields = self
+                .apply_write_policies(ty.clone(), fields, policy_ctx, false)?
+                .0;
+            fields.remove("id");
+        }
+        for name in fields.keys() {
+            anyhow::ensure!(
+                ty.has_field(name),
+                "field {} not present in {}",
+                name,
+                ty.name()
+            );
+        }
+
+        let owner = ctx.job_info.owner_scope();
+        let mut args = vec![];
+        let mut sets = vec![];
+        let mut written_at = None;
+        let mut expected_version = None;
+        for field in ty.user_fields() {
+            if field.timestamp == Some(Timestamp::Updated) {
+                args.push(SqlValue::F64(*written_at.get_or_insert_with(now_ms)));
+                sets.push(format!(r#""{}" = ${}"#, field.name, args.len()));
+                continue;
+            }
+            if field.is_version {
+                sets.push(format!(r#""{0}" = "{0}" + 1"#, field.name));
+            }
+            let value = match fields.get(&field.name) {
+                Some(value) => value,
+                None => continue,
+            };
+            // the timestamps are set by the server, the values of the object are ignored
+            if field.timestamp.is_some() {
+                continue;
+            }
+            anyhow::ensure!(
+                field.count.is_none(),
+                "update(): field `{}` of {} is a @count field, so it cannot be written",
+                field.name,
+                ty.name()
+            );
+            anyhow::ensure!(
+                ty.owned_by() != Some(field.name.as_str()) || owner == OwnerScope::All,
+                "update(): field `{}` of {} is the owner, so it can only be changed by admins",
+                field.name,
+                ty.name()
+            );
+            if field.is_version {
+                match self.convert_to_argument(field, &fields) {
+                    Ok(SqlValue::F64(version)) => expected_version = Some(version),
+                    _ => return Err(QueryEngine::incompatible(field, &ty)),
+                }
+                continue;
+            }
+            let bind = if value.is_null() {
+                anyhow::ensure!(
+                    field.is_optional,
+                    "update(): field `{}` of {} is not optional",
+                    field.name,
+                    ty.name()
+                );
+                "NULL".to_owned()
+            } else {
+                let arg = match ts.get(&field.type_id)? {
+                    Type::Entity(_) => related_id("update", &ty, field, Some(value))?,
+                    _ => self.field_argument(&ty, field, &fields, &mut written_at)?,
+                };
+                args.push(arg);
+                format!("${}", args.len())
+            };
+            sets.push(format!(r#""{}" = {}"#, field.name, bind));
+        }
+        if sets.is_empty() {
+            // nothing to write, but the result still tells whether the row exists
+            sets.push(r#""id" = "id""#.to_owned());
+        }
+
+        args.push(SqlValue::String(id.clone()));
+        let mut conditions = vec![format!(r#""id" = ${}"#, args.len())];
+        if let Some(version) = expected_version {
+            let field = ty.all_fields().find(|f| f.is_version).unwrap();
+            args.push(SqlValue::F64(version));
+            conditions.push(format!(r#""{}" = ${}"#, field.name, args.len()));
+        }
+        let owner_field = ty.owned_by().filter(|_| owner != OwnerScope::All);
+        if let Some(owner_field) = owner_field {
+            let user_id = match owner {
+                OwnerScope::User(Some(user_id)) => user_id,
+                _ => anyhow::bail!(
+                    "Cannot write {}: it is marked with @ownedBy, so it can only be written by logged-in users",
+                    ty.name()
+                ),
+            };
+            args.push(SqlValue::String(user_id.to_owned()));
+            conditions.push(format!(r#""{}" = ${}"#, owner_field, args.len()));
+        }
+        let tenant = tenancy::written_tenant(ctx.job_info.tenant_scope(), ty.name())?;
+        if let Some(tenant) = tenant {
+            args.push(SqlValue::String(tenant.to_owned()));
+            conditions.push(format!(r#""{TENANT_COLUMN}" = ${}"#, args.len()));
+        }
+        let query = SqlWithArguments {
+            sql: format!(
+                r#"UPDATE "{}" SET {} WHERE {}"#,
+                ty.backing_table(),
+                sets.join(", "),
+                conditions.join(" AND "),
+            ),
+            args,
+        };
+
+        let (mut before, mut after): (Vec<_>, Vec<_>) = ts
+            .counters()
+            .iter()
+            .filter(|c| c.counted.name() == ty.name())
+            .map(|c| (count_update(c, -1, &id), count_update(c, 1, &id)))
+            .unzip();
+        for agg in ts.aggregators_of(ty.name()) {
+            let (agg_before, agg_after) = aggregate::row_write_sql(&agg, &id);
+            before.extend(agg_before);
+            after.extend(agg_after);
+        }
+
+        let mut written_tables = HashSet::new();
+        collect_written_tables(&ty, ts, &mut written_tables);
+        ctx.mark_written(written_tables);
+        ctx.txn_guard.add_rows_written(1);
+
+        let otel_cx = ctx.job_info.otel_context();
+        let txn = ctx.txn.clone();
+        let mut txn = txn.lock().await;
+        self.run_sql_queries(&before, &mut txn, &otel_cx).await?;
+        let started_at = Instant::now();
+        let result = execute_traced(&mut txn, query.get_sqlx(), "update", &otel_cx)
+            .await
+            .with_context(|| format!("Could not update {} with id {}", ty.name(), id))?;
+        self.db
+            .metrics
+            .observe_query("update", started_at.elapsed());
+        // if the row was not written, this restores the counts that were decremented
+        self.run_sql_queries(&after, &mut txn, &otel_cx).await?;
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        let id_value = EntityValue::String(id.clone());
+        if (expected_version.is_some() || owner_field.is_some())
+            && self
+                .exists_entity_id(&mut txn, &id_value, &ty, tenant, &otel_cx)
+                .await?
+        {
+            if let Some(version) = expected_version {
+                return Err(ConflictError {
+                    entity: ty.name().to_owned(),
+                    id,
+                    version,
+                }
+                .into());
+            }
+            anyhow::bail!(
+                "Cannot write {} with id {}: it is owned by another user",
+                ty.name(),
+                id
+            );
+        }
+        Ok(false)
+    }
+
+    /// Applies the write policies to `record` and prepares the queries that insert it.
+    async fn prepare_row(
+        &self,
+        ty: &Arc<ObjectType>,
+        record: EntityMap,
+        ctx: &DataContext,
+    ) -> Result<(EntityMap, IdTree, Vec<RowInsertion>)> {
         let (record, location) = if feat_typescript_policies() {
-            let is_creation = self.is_object_creation(ctx, &ty, &record).await?;
+            let is_creation = self.is_object_creation(ctx, ty, &record).await?;
             self.apply_write_policies(ty.clone(), record, ctx.policy_context.clone(), is_creation)?
         } else {
             (record, None)
         };
-        let (inserts, id_tree) = self.prepare_insertion(&ty, &record, &ctx.type_system)?;
+        let tenant = tenancy::written_tenant(ctx.job_info.tenant_scope(), ty.name())?;
+        let (inserts, id_tree) = self.prepare_insertion(
+            ty,
+            &record,
+            &ctx.type_system,
+            ctx.job_info.owner_scope(),
+            tenant,
+        )?;
+        let mut written_tables = HashSet::new();
+        collect_written_tables(ty, &ctx.type_system, &mut written_tables);
+        ctx.mark_written(written_tables);
+        ctx.txn_guard.add_rows_written(inserts.len() as u64);
         // mock saving to some region
         if let Some(loc) = location {
             log::info!("Saving {} to region {loc:?}", id_tree.id);
         }
+        Ok((record, id_tree, inserts))
+    }
 
-        let txn = ctx.txn.clone();
-        let mut txn = txn.lock().await;
-
-        self.run_sql_queries(&inserts, &mut txn).await?;
-        Ok((record, id_tree))
+    async fn execute_insertions(
+        &self,
+        inserts: &[RowInsertion],
+        txn: &mut Transaction<'static, Any>,
+        otel_cx: &opentelemetry::Context,
+    ) -> Result<()> {
+        for insertion in inserts.iter() {
+            self.run_sql_queries(&insertion.before, txn, otel_cx)
+                .await?;
+            let started_at = Instant::now();
+            let result = execute_traced(txn, insertion.query.get_sqlx(), "insert", otel_cx).await?;
+            self.db
+                .metrics
+                .observe_query("insert", started_at.elapsed());
+            // if the row was not written, this restores the counts that were decremented
+            self.run_sql_queries(&insertion.after, txn, otel_cx).await?;
+            if result.rows_affected() == 0 {
+                if let Some(conflict) = &insertion.conflict {
+                    return Err(conflict.clone().into());
+                }
+                if let Some((entity, id)) = &insertion.owner_check {
+                    anyhow::bail!(
+                        "Cannot write {entity} with id {id}: it is owned by another user"
+                    );
+                }
+                if let Some((entity, id)) = &insertion.tenant_check {
+                    anyhow::bail!(
+                        "Cannot write {entity} with id {id}: it belongs to another tenant"
+                    );
+                }
+            }
+        }
+        Ok(())
     }
 
     pub async fn add_row_shallow(
@@ -575,7 +2059,8 @@ impl QueryEngine {
         fields_map: &EntityMap,
     ) -> Result<()> {
         let query = self.prepare_insertion_shallow(ty, fields_map)?;
-        self.run_sql_queries(&[query], txn).await?;
+        self.run_sql_queries(&[query], txn, &opentelemetry::Context::new())
+            .await?;
         Ok(())
     }
 
@@ -587,9 +2072,10 @@ impl QueryEngine {
         &self,
         queries: &[SqlWithArguments],
         transaction: &mut Transaction<'_, Any>,
+        otel_cx: &opentelemetry::Context,
     ) -> Result<()> {
         for q in queries {
-            transaction.execute(q.get_sqlx()).await?;
+            execute_traced(transaction, q.get_sqlx(), "write", otel_cx).await?;
         }
 
         Ok(())
@@ -607,22 +2093,55 @@ impl QueryEngine {
     /// and value `ty_value` into database.
     /// Returns vector of SQL insert queries with corresponding arguments and IdTree of
     /// inserted objects.
+    ///
+    /// If the type has a `@version` field, the value in `fields_map` is the expected version of
+    /// the stored row: the row is written with an incremented version, but only if the stored
+    /// version matches.
+    ///
+    /// The values of `@count` fields in `fields_map` are ignored: they are computed when the row
+    /// is inserted, and updated when the counted entities are written.
+    ///
+    /// If the type is marked with `@ownedBy` and `owner` is restricted to a user, the owner field
+    /// is set to that user, and an existing row is only updated if the user owns it.
+    ///
+    /// If `tenant` is set, the rows are stored with that tenant, and an existing row is only
+    /// updated if it belongs to the tenant.
     fn prepare_insertion(
         &self,
         ty: &ObjectType,
         fields_map: &EntityMap,
         ts: &TypeSystem,
-    ) -> Result<(Vec<SqlWithArguments>, IdTree)> {
+        owner: OwnerScope,
+        tenant: Option<&str>,
+    ) -> Result<(Vec<RowInsertion>, IdTree)> {
+        if ty.aggregate().is_some() {
+            anyhow::bail!(
+                "entity `{}` is a materialized aggregate, so it cannot be written",
+                ty.name()
+            );
+        }
         let mut child_ids = HashMap::<String, IdTree>::new();
         let mut obj_id = Option::<String>::None;
         let mut query_args = Vec::<SqlValue>::new();
-        let mut inserts = Vec::<SqlWithArguments>::new();
+        let mut inserts = Vec::<RowInsertion>::new();
+        let mut expected_version = Option::<f64>::None;
+        let mut written_at = Option::<f64>::None;
+        let mut assigned_owner = Option::<String>::None;
+        let counters = ts.counters();
+        let counts = counters
+            .iter()
+            .filter(|c| c.counting.name() == ty.name())
+            .cloned()
+            .collect::<Vec<_>>();
 
         for field in ty.all_fields() {
             let field_value = fields_map.get(&field.name);
             if (field_value.is_none() || field_value.unwrap().is_null()) && field.is_optional {
                 continue;
             }
+            if field.count.is_some() {
+                continue;
+            }
             let incompatible_data = || QueryEngine::incompatible(field, ty);
             let arg = match ts.get(&field.type_id)? {
                 Type::Entity(nested_type) => {
@@ -648,7 +2167,7 @@ impl QueryEngine {
                         }
                     } else {
                         let (nested_inserts, nested_ids) =
-                            self.prepare_insertion(&nested_type, nested_value, ts)?;
+                            self.prepare_insertion(&nested_type, nested_value, ts, owner, tenant)?;
                         inserts.extend(nested_inserts);
                         let nested_id = nested_ids.id.to_owned();
                         child_ids.insert(field.name.to_owned(), nested_ids);
@@ -656,9 +2175,27 @@ impl QueryEngine {
                     };
                     SqlValue::String(nested_id)
                 }
-                _ => self
-                    .convert_to_argument(field, fields_map)
-                    .with_context(incompatible_data)?,
+                // the owner is the user that writes the row, unless it is written by an admin
+                _ if ty.owned_by() == Some(field.name.as_str()) && owner != OwnerScope::All => {
+                    let user_id = match owner {
+                        OwnerScope::User(Some(user_id)) => user_id,
+                        _ => anyhow::bail!(
+                            "Cannot write {}: it is marked with @ownedBy, so it can only be written by logged-in users",
+                            ty.name()
+                        ),
+                    };
+                    assigned_owner = Some(user_id.to_owned());
+                    SqlValue::String(user_id.to_owned())
+                }
+                _ if field.is_version => {
+                    let version = match self.convert_to_argument(field, fields_map) {
+                        Ok(SqlValue::F64(version)) => version,
+                        _ => return Err(incompatible_data()),
+                    };
+                    expected_version = Some(version);
+                    SqlValue::F64(version + 1.0)
+                }
+                _ => self.field_argument(ty, field, fields_map, &mut written_at)?,
             };
 
             if field.name == "id" {
@@ -671,21 +2208,99 @@ impl QueryEngine {
             query_args.push(arg);
         }
 
-        inserts.push(SqlWithArguments {
-            sql: self.make_insert_query(ty, fields_map)?,
-            args: query_args,
-        });
         let obj_id = obj_id
             .ok_or_else(|| anyhow!("attempting to insert an object `{}` with no id", ty.name()))?;
+        if let Some(tenant) = tenant {
+            query_args.push(SqlValue::String(tenant.to_owned()));
+        }
+        let conflict = expected_version.map(|version| {
+            query_args.push(SqlValue::F64(version));
+            ConflictError {
+                entity: ty.name().to_owned(),
+                id: obj_id.clone(),
+                version,
+            }
+        });
+        let (mut before, mut after): (Vec<_>, Vec<_>) = counters
+            .iter()
+            .filter(|c| c.counted.name() == ty.name())
+            .map(|c| (count_update(c, -1, &obj_id), count_update(c, 1, &obj_id)))
+            .unzip();
+        for agg in ts.aggregators_of(ty.name()) {
+            let (agg_before, agg_after) = aggregate::row_write_sql(&agg, &obj_id);
+            before.extend(agg_before);
+            after.extend(agg_after);
+        }
+        let owner_check = assigned_owner
+            .is_some()
+            .then(|| (ty.name().to_owned(), obj_id.clone()));
+        let tenant_check = tenant.map(|_| (ty.name().to_owned(), obj_id.clone()));
+        inserts.push(RowInsertion {
+            query: SqlWithArguments {
+                sql: self.make_insert_query(
+                    ty,
+                    fields_map,
+                    conflict.is_some(),
+                    owner_check.is_some(),
+                    tenant.is_some(),
+                    &counts,
+                )?,
+                args: query_args,
+            },
+            conflict,
+            owner_check,
+            tenant_check,
+            before,
+            after,
+        });
         Ok((
             inserts,
             IdTree {
                 id: obj_id,
+                version: expected_version.map(|version| version + 1.0),
+                written_at,
+                owner: assigned_owner,
                 children: child_ids,
             },
         ))
     }
 
+    /// Converts the value of the scalar `field` in `fields_map` into an argument, checking the
+    /// constraints of the field. Timestamp fields are set to `written_at`, which is initialized to
+    /// the current time the first time.
+    fn field_argument(
+        &self,
+        ty: &ObjectType,
+        field: &Field,
+        fields_map: &EntityMap,
+        written_at: &mut Option<f64>,
+    ) -> Result<SqlValue> {
+        let incompatible_data = || QueryEngine::incompatible(field, ty);
+        let arg = match field.type_id {
+            // the timestamps are set by the server, the values of the object are ignored
+            _ if field.timestamp.is_some() => SqlValue::F64(*written_at.get_or_insert_with(now_ms)),
+            TypeId::Decimal { precision, scale } => {
+                check_decimal_value(ty, field, fields_map.get(&field.name), precision, scale)?;
+                self.convert_to_argument(field, fields_map)
+                    .with_context(incompatible_data)?
+            }
+            _ if field.enum_type.is_some()
+                || field.max_bytes.is_some()
+                || field.type_id == TypeId::GeoPoint =>
+            {
+                let arg = self
+                    .convert_to_argument(field, fields_map)
+                    .with_context(incompatible_data)?;
+                check_field_value(ty, field, &arg)?;
+                arg
+            }
+            _ => self
+                .convert_to_argument(field, fields_map)
+                .with_context(incompatible_data)?,
+        };
+        Ok(arg)
+    }
+
     /// Converts `field` with value `ty_value` into SqlValue while ensuring the
     /// generation of default and generable values.
     fn convert_to_argument(&self, field: &Field, fields: &EntityMap) -> Result<SqlValue> {
@@ -717,11 +2332,28 @@ impl QueryEngine {
         }
 
         let arg = match &field.type_id {
+            // a related entity that was loaded by `with()` is stored by its id
+            TypeId::EntityId { .. }
+                if fields.get(&field.name).map_or(false, EntityValue::is_map) =>
+            {
+                let related = fields[&field.name].as_map()?;
+                let id = related
+                    .get("id")
+                    .context("related entity that was loaded by with() has no id")?
+                    .as_str()?;
+                SqlValue::String(id.to_owned())
+            }
             TypeId::String | TypeId::Id | TypeId::Entity { .. } | TypeId::EntityId { .. } => {
                 SqlValue::String(convert_value!(as_str, String))
             }
             TypeId::Float => SqlValue::F64(convert_value!(as_f64, f64)),
             TypeId::Int64 => SqlValue::I64(convert_value!(as_i64, i64)),
+            TypeId::Decimal { precision, scale } => {
+                let text = convert_value!(as_str, String);
+                SqlValue::I64(
+                    decimal::parse(&text, *precision, *scale).map_err(anyhow::Error::msg)?,
+                )
+            }
             TypeId::Boolean => SqlValue::Bool(convert_value!(as_bool, bool)),
             TypeId::JsDate => SqlValue::F64(convert_value!(as_date, f64)),
             TypeId::ArrayBuffer => {
@@ -747,6 +2379,16 @@ impl QueryEngine {
                 };
                 SqlValue::Json(val)
             }
+            TypeId::Json | TypeId::GeoPoint => {
+                let val = match fields.get(&field.name) {
+                    Some(field) => serde_json::to_value(field)?,
+                    None => serde_json::from_str(
+                        &field.generate_value().context("failed to generate value")?,
+                    )
+                    .context("Failed to convert provided default value to JSON")?,
+                };
+                SqlValue::Json(val)
+            }
         };
 
         Ok(arg)
@@ -773,6 +2415,11 @@ impl QueryEngine {
                 }
                 TypeId::Float => maybe_bail!(is_f64),
                 TypeId::Int64 => maybe_bail!(is_i64),
+                TypeId::Decimal { precision, scale } => match e {
+                    EntityValue::String(text)
+                        if decimal::parse(text, *precision, *scale).is_ok() => {}
+                    _ => bail!(),
+                },
                 TypeId::JsDate => {
                     if !e.is_date() && !e.is_f64() {
                         bail!();
@@ -782,6 +2429,12 @@ impl QueryEngine {
                     unreachable!("ArrayBuffer can't be contained within an array")
                 }
                 TypeId::Boolean => maybe_bail!(is_boolean),
+                TypeId::Json => {}
+                TypeId::GeoPoint => {
+                    if geo_point_error(e).is_some() {
+                        bail!();
+                    }
+                }
                 TypeId::Array(inner_element) => Self::validate_array(inner_element, e)
                     .context("failed to validate inner array at position {i}")?,
                 TypeId::Entity { .. } => {
@@ -794,12 +2447,29 @@ impl QueryEngine {
 
     /// For given object of type `ty` and its value `ty_value` computes a string
     /// representing SQL query which inserts the object into database.
-    fn make_insert_query(&self, ty: &ObjectType, fields_map: &EntityMap) -> Result<String> {
+    /// Generates an upsert of an object of type `ty`. If `check_version` is true, the update is
+    /// only performed if the stored `@version` field is equal to the last bound argument. If
+    /// `check_owner` is true, the update is only performed if the stored `@ownedBy` field is
+    /// equal to the written one.
+    ///
+    /// The `@count` fields in `counts` are not bound to arguments: they are computed when the row
+    /// is inserted and they are left untouched when the row is updated.
+    fn make_insert_query(
+        &self,
+        ty: &ObjectType,
+        fields_map: &EntityMap,
+        check_version: bool,
+        check_owner: bool,
+        check_tenant: bool,
+        counts: &[Counter],
+    ) -> Result<String> {
         let mut field_binds = String::new();
         let mut field_names = vec![];
         let mut id_name = String::new();
         let mut update_binds = String::new();
         let mut id_bind = String::new();
+        let mut version_name = None;
+        let mut owner_bind = None;
 
         let mut i = 0;
         for f in ty.all_fields() {
@@ -807,6 +2477,12 @@ impl QueryEngine {
             if val.is_none() && f.is_optional {
                 continue;
             }
+            if let Some(counter) = counts.iter().find(|c| c.field == f.name) {
+                field_binds.push_str(&count_sql(counter, &id_bind));
+                field_binds.push(',');
+                field_names.push(f.name.clone());
+                continue;
+            }
             let bind = if f.is_optional && val.unwrap().is_null() {
                 // sqlx has trouble binding null values in some cases; insert them verbatim.
                 "NULL".to_string()
@@ -827,6 +2503,16 @@ impl QueryEngine {
                 id_name = f.name.to_string();
                 id_bind = bind.clone();
             }
+            if f.is_version {
+                version_name = Some(f.name.clone());
+            }
+            if ty.owned_by() == Some(f.name.as_str()) {
+                owner_bind = Some((f.name.clone(), bind.clone()));
+            }
+            // an update keeps the time of the creation
+            if f.timestamp == Some(Timestamp::Created) {
+                continue;
+            }
             write!(update_binds, "\"{}\" = {},", &f.name, &bind).unwrap();
         }
         field_binds.pop();
@@ -840,8 +2526,17 @@ impl QueryEngine {
                 ty.name()
             );
         }
+        // the tenant is not a field, so it is bound after the fields (see `prepare_insertion()`)
+        let tenant_bind = check_tenant.then(|| {
+            i += 1;
+            format!("${}", i)
+        });
+        if let Some(tenant_bind) = &tenant_bind {
+            write!(field_binds, ",{}", tenant_bind).unwrap();
+            field_names.push(TENANT_COLUMN.to_owned());
+        }
 
-        Ok(std::format!(
+        let mut sql = std::format!(
             "INSERT INTO \"{}\" ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {} WHERE \"{}\".\"{}\" = {}",
             &ty.backing_table(),
             field_names.into_iter().map(|f| format!("\"{}\"", f)).join(","),
@@ -851,7 +2546,40 @@ impl QueryEngine {
             &ty.backing_table(),
             id_name,
             id_bind,
-        ))
+        );
+        if check_version {
+            let version_name = version_name.context("type has no @version field")?;
+            write!(
+                sql,
+                " AND \"{}\".\"{}\" = ${}",
+                &ty.backing_table(),
+                version_name,
+                i + 1
+            )
+            .unwrap();
+        }
+        if check_owner {
+            let (owner_name, owner_bind) = owner_bind.context("type has no @ownedBy field")?;
+            write!(
+                sql,
+                " AND \"{}\".\"{}\" = {}",
+                &ty.backing_table(),
+                owner_name,
+                owner_bind
+            )
+            .unwrap();
+        }
+        if let Some(tenant_bind) = tenant_bind {
+            write!(
+                sql,
+                " AND \"{}\".\"{}\" = {}",
+                &ty.backing_table(),
+                TENANT_COLUMN,
+                tenant_bind
+            )
+            .unwrap();
+        }
+        Ok(sql)
     }
 
     fn prepare_insertion_shallow(
@@ -871,7 +2599,7 @@ impl QueryEngine {
         }
 
         Ok(SqlWithArguments {
-            sql: self.make_insert_query(ty, fields_map)?,
+            sql: self.make_insert_query(ty, fields_map, false, false, false, &[])?,
             args: query_args,
         })
     }
@@ -894,6 +2622,79 @@ impl QueryEngine {
         processor.process_write(&value, action)
     }
 
+    /// Computes the `@count` field of all instances from the data.
+    pub async fn backfill_counter(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        counter: &Counter,
+    ) -> Result<u64> {
+        let table = counter.counting.backing_table();
+        let sql = format!(
+            r#"UPDATE "{table}" SET "{field}" = {count}"#,
+            field = counter.field,
+            count = count_sql(counter, &format!(r#""{table}"."id""#)),
+        );
+        let result = transaction.execute(sqlx::query(&sql)).await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Sets the `@timestamps` field of the rows that don't have a value yet to `now`.
+    pub async fn backfill_timestamp(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        field: &Field,
+        now: f64,
+    ) -> Result<u64> {
+        let sql = format!(
+            r#"UPDATE "{table}" SET "{field}" = $1 WHERE "{field}" IS NULL"#,
+            table = ty.backing_table(),
+            field = field.name,
+        );
+        let result = transaction.execute(sqlx::query(&sql).bind(now)).await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Recomputes all rows of a materialized aggregate from the data.
+    pub async fn refresh_aggregate(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        agg: &Aggregator,
+    ) -> Result<()> {
+        let started_at = Instant::now();
+        for sql in aggregate::refresh_sql(agg) {
+            transaction.execute(sqlx::query(&sql)).await?;
+        }
+        self.db
+            .metrics
+            .observe_query("aggregate_refresh", started_at.elapsed());
+        Ok(())
+    }
+
+    /// Returns the instances whose `@count` field does not match the data.
+    pub async fn check_counter(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        counter: &Counter,
+    ) -> Result<Vec<CountMismatch>> {
+        let table = counter.counting.backing_table();
+        let count = count_sql(counter, &format!(r#""{table}"."id""#));
+        let sql = format!(
+            r#"SELECT "id", "{field}", {count} FROM "{table}" WHERE "{field}" IS NULL OR "{field}" <> {count}"#,
+            field = counter.field,
+        );
+        let rows = transaction.fetch_all(sqlx::query(&sql)).await?;
+        rows.iter()
+            .map(|row| -> Result<CountMismatch> {
+                Ok(CountMismatch {
+                    id: row.try_get(0)?,
+                    stored: row.try_get(1)?,
+                    actual: row.try_get(2)?,
+                })
+            })
+            .collect()
+    }
+
     async fn is_object_creation(
         &self,
         ctx: &DataContext,
@@ -904,19 +2705,44 @@ impl QueryEngine {
         let mut txn = txn.lock().await;
         match obj.get("id") {
             None => Ok(true),
-            Some(id) => Ok(!self.exists_entity_id(&mut txn, id, ty).await?),
+            Some(id) => Ok(!self
+                .exists_entity_id(
+                    &mut txn,
+                    id,
+                    ty,
+                    ctx.job_info.tenant_id(),
+                    &ctx.job_info.otel_context(),
+                )
+                .await?),
         }
     }
 
+    /// Returns true if the table of `ty` has a row with the given `id` (that belongs to `tenant`,
+    /// if it is set).
     async fn exists_entity_id(
         &self,
         txn: &mut Transaction<'_, Any>,
         id: &EntityValue,
         ty: &ObjectType,
+        tenant: Option<&str>,
+        otel_cx: &opentelemetry::Context,
     ) -> Result<bool, anyhow::Error> {
         let id = id.as_str()?;
-        let query = format!("SELECT 1 from \"{}\" where id=$1", ty.backing_table(),);
-        let query = sqlx::query(&query).bind(id);
-        Ok(txn.fetch_optional(query).await?.is_some())
+        let mut query = format!("SELECT 1 from \"{}\" where id=$1", ty.backing_table(),);
+        if tenant.is_some() {
+            write!(query, " and \"{}\"=$2", TENANT_COLUMN).unwrap();
+        }
+        let span_cx = telemetry::start_sql_span(otel_cx, "exists", &query);
+        let mut query = sqlx::query(&query).bind(id);
+        if let Some(tenant) = tenant {
+            query = query.bind(tenant);
+        }
+        let result = txn
+            .fetch_optional(query)
+            .await
+            .map(|row| row.is_some())
+            .map_err(anyhow::Error::from);
+        telemetry::end(&span_cx, &result);
+        result
     }
 }
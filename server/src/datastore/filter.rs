@@ -35,7 +35,7 @@ fn to_expr_rec(depth: u32, e: &serde_json::Value) -> Result<Expr> {
                 }
                 expr
             }
-            "$not" => Expr::Not(to_expr_rec(depth + 1, value)?.into()),
+            "$not" => Expr::negate(to_expr_rec(depth + 1, value)?),
             field_name => {
                 anyhow::ensure!(
                     !field_name.starts_with('$'),
@@ -0,0 +1,456 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Incremental parsing of the CSV and NDJSON uploads to `ingest()` routes. The body of the
+//! request is pushed to a `RowParser` chunk by chunk as it arrives, and every complete row is
+//! validated against the entity type and converted to an `EntityMap` that can be inserted.
+
+use csv_core::ReadRecordResult;
+use serde_json::Value as JsonValue;
+
+use super::decimal;
+use super::value::{EntityMap, EntityValue};
+use crate::types::{Field, ObjectType, TypeId};
+
+/// Error in the uploaded data, reported to the client as 400 Bad Request.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("row {row}: {message}")]
+pub struct IngestError {
+    /// Number of the data row (starting at 1, not counting the CSV header).
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestFormat {
+    Csv,
+    Ndjson,
+}
+
+impl IngestFormat {
+    /// Negotiates the format from the `content-type` of the request.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        match mime.to_ascii_lowercase().as_str() {
+            "text/csv" => Some(IngestFormat::Csv),
+            "application/x-ndjson"
+            | "application/ndjson"
+            | "application/jsonl"
+            | "application/x-jsonlines" => Some(IngestFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// Row of an upload, before it is validated against the entity type.
+#[derive(Debug)]
+pub struct Row {
+    pub number: usize,
+    values: RowValues,
+}
+
+#[derive(Debug)]
+enum RowValues {
+    /// Values from CSV, which are all strings.
+    Text(Vec<(String, String)>),
+    Json(serde_json::Map<String, JsonValue>),
+}
+
+pub enum RowParser {
+    Csv(CsvParser),
+    Ndjson(NdjsonParser),
+}
+
+impl RowParser {
+    pub fn new(format: IngestFormat) -> Self {
+        match format {
+            IngestFormat::Csv => RowParser::Csv(CsvParser::new()),
+            IngestFormat::Ndjson => RowParser::Ndjson(NdjsonParser::default()),
+        }
+    }
+
+    /// Parses the rows that were completed by `chunk` into `rows`.
+    pub fn push(&mut self, chunk: &[u8], rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        match self {
+            RowParser::Csv(parser) => parser.push(chunk, rows),
+            RowParser::Ndjson(parser) => parser.push(chunk, rows),
+        }
+    }
+
+    /// Parses the last row, which does not have to be terminated by a newline.
+    pub fn finish(&mut self, rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        match self {
+            RowParser::Csv(parser) => parser.finish(rows),
+            RowParser::Ndjson(parser) => parser.finish(rows),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct NdjsonParser {
+    pending: Vec<u8>,
+    rows_parsed: usize,
+}
+
+impl NdjsonParser {
+    fn push(&mut self, chunk: &[u8], rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        self.pending.extend_from_slice(chunk);
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            self.parse_line(&line, rows)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        let line = std::mem::take(&mut self.pending);
+        self.parse_line(&line, rows)
+    }
+
+    fn parse_line(&mut self, line: &[u8], rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        self.rows_parsed += 1;
+        let error = |message: String| IngestError {
+            row: self.rows_parsed,
+            message,
+        };
+        let values = match serde_json::from_slice(line) {
+            Ok(JsonValue::Object(values)) => values,
+            Ok(_) => return Err(error("expected a JSON object".into())),
+            Err(e) => return Err(error(format!("invalid JSON: {}", e))),
+        };
+        rows.push(Row {
+            number: self.rows_parsed,
+            values: RowValues::Json(values),
+        });
+        Ok(())
+    }
+}
+
+pub struct CsvParser {
+    reader: csv_core::Reader,
+    /// Field names from the first record.
+    header: Option<Vec<String>>,
+    /// Fields of the record that is being parsed, ending at the offsets in `ends`.
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+    rows_parsed: usize,
+}
+
+impl CsvParser {
+    fn new() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            header: None,
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+            rows_parsed: 0,
+        }
+    }
+
+    fn push(&mut self, mut input: &[u8], rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        // `csv_core` treats empty input as the end of the data, which is signalled by `finish()`
+        while !input.is_empty() {
+            let (_, nin) = self.read_record(input, rows)?;
+            input = &input[nin..];
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        loop {
+            if let (ReadRecordResult::End, _) = self.read_record(&[], rows)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Feeds `input` to the reader and handles the record that it completes (if any). Returns
+    /// the result of the reader and the number of consumed bytes.
+    fn read_record(
+        &mut self,
+        input: &[u8],
+        rows: &mut Vec<Row>,
+    ) -> Result<(ReadRecordResult, usize), IngestError> {
+        let (result, nin, nout, nend) = self.reader.read_record(
+            input,
+            &mut self.output[self.output_len..],
+            &mut self.ends[self.ends_len..],
+        );
+        self.output_len += nout;
+        self.ends_len += nend;
+        match result {
+            ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+            ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+            ReadRecordResult::Record => self.record(rows)?,
+            ReadRecordResult::InputEmpty | ReadRecordResult::End => {}
+        }
+        Ok((result, nin))
+    }
+
+    fn record(&mut self, rows: &mut Vec<Row>) -> Result<(), IngestError> {
+        let row = self.rows_parsed + 1;
+        let mut fields = Vec::with_capacity(self.ends_len);
+        let mut start = 0;
+        for &end in &self.ends[..self.ends_len] {
+            let field = std::str::from_utf8(&self.output[start..end]).map_err(|_| IngestError {
+                row,
+                message: "field is not valid UTF-8".into(),
+            })?;
+            fields.push(field.to_string());
+            start = end;
+        }
+        self.output_len = 0;
+        self.ends_len = 0;
+
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                self.header = Some(fields.into_iter().map(|f| f.trim().into()).collect());
+                return Ok(());
+            }
+        };
+        self.rows_parsed = row;
+        if fields.len() != header.len() {
+            return Err(IngestError {
+                row,
+                message: format!(
+                    "expected {} fields like the header, got {}",
+                    header.len(),
+                    fields.len()
+                ),
+            });
+        }
+        rows.push(Row {
+            number: row,
+            values: RowValues::Text(header.iter().cloned().zip(fields).collect()),
+        });
+        Ok(())
+    }
+}
+
+/// Converts a parsed row to an entity of type `ty`, checking that every field is known, has a
+/// value of the right type, and that no required field is missing.
+pub fn row_to_entity(ty: &ObjectType, row: Row) -> Result<EntityMap, IngestError> {
+    convert_row(ty, row, false)
+}
+
+/// Like `row_to_entity()`, but reads CSV values of fields that refer to another entity as the id
+/// of that entity, which is how they are stored (and exported).
+pub fn row_to_stored_entity(ty: &ObjectType, row: Row) -> Result<EntityMap, IngestError> {
+    convert_row(ty, row, true)
+}
+
+fn convert_row(
+    ty: &ObjectType,
+    row: Row,
+    references_as_ids: bool,
+) -> Result<EntityMap, IngestError> {
+    let error = |message: String| IngestError {
+        row: row.number,
+        message,
+    };
+    let mut entity = EntityMap::new();
+    let mut set_value = |name: &str, value: Option<EntityValue>| {
+        if let Some(value) = value {
+            entity.insert(name.into(), value);
+        }
+    };
+    match row.values {
+        RowValues::Text(ref values) => {
+            for (name, text) in values {
+                let field = lookup_field(ty, name).map_err(error)?;
+                set_value(
+                    name,
+                    text_to_value(field, text, references_as_ids).map_err(error)?,
+                );
+            }
+        }
+        RowValues::Json(ref values) => {
+            for (name, json) in values {
+                let field = lookup_field(ty, name).map_err(error)?;
+                set_value(name, json_to_value(field, json).map_err(error)?);
+            }
+        }
+    }
+
+    for field in ty.user_fields() {
+        let generated = field.generate_value().is_some();
+        if !entity.contains_key(&field.name) && !field.is_optional && !generated {
+            return Err(error(format!("missing value of field `{}`", field.name)));
+        }
+    }
+    Ok(entity)
+}
+
+fn lookup_field<'a>(ty: &'a ObjectType, name: &str) -> Result<&'a Field, String> {
+    ty.get_field(name)
+        .ok_or_else(|| format!("entity {} has no field `{}`", ty.name(), name))
+}
+
+/// Converts a CSV value, empty values are treated as missing (except for strings). Booleans are
+/// also accepted in the `t`/`f` form written by Postgres.
+fn text_to_value(
+    field: &Field,
+    text: &str,
+    references_as_ids: bool,
+) -> Result<Option<EntityValue>, String> {
+    let invalid = || {
+        format!(
+            "invalid value {:?} of field `{}` with type {}",
+            text,
+            field.name,
+            field.type_id.name()
+        )
+    };
+    if text.is_empty() && field.type_id != TypeId::String {
+        return Ok(None);
+    }
+    let value = match &field.type_id {
+        TypeId::String | TypeId::Id | TypeId::EntityId(_) => EntityValue::String(text.into()),
+        TypeId::Float => EntityValue::Float64(text.trim().parse().map_err(|_| invalid())?),
+        TypeId::Int64 => EntityValue::Int64(text.trim().parse().map_err(|_| invalid())?),
+        TypeId::Decimal { precision, scale } => {
+            decimal::parse(text.trim(), *precision, *scale).map_err(|_| invalid())?;
+            EntityValue::String(text.trim().into())
+        }
+        TypeId::JsDate => EntityValue::JsDate(text.trim().parse().map_err(|_| invalid())?),
+        TypeId::Boolean => match text.trim() {
+            "true" | "t" | "1" => EntityValue::Boolean(true),
+            "false" | "f" | "0" => EntityValue::Boolean(false),
+            _ => return Err(invalid()),
+        },
+        TypeId::Entity { .. } if references_as_ids => EntityValue::String(text.into()),
+        TypeId::ArrayBuffer
+        | TypeId::Entity { .. }
+        | TypeId::Array(_)
+        | TypeId::Json
+        | TypeId::GeoPoint => {
+            return Err(format!(
+                "field `{}` with type {} cannot be ingested from CSV",
+                field.name,
+                field.type_id.name()
+            ))
+        }
+    };
+    Ok(Some(value))
+}
+
+/// Converts a JSON value, nulls are treated as missing.
+fn json_to_value(field: &Field, json: &JsonValue) -> Result<Option<EntityValue>, String> {
+    let invalid = || {
+        format!(
+            "invalid value {} of field `{}` with type {}",
+            json,
+            field.name,
+            field.type_id.name()
+        )
+    };
+    let value = match (&field.type_id, json) {
+        (_, JsonValue::Null) => return Ok(None),
+        (TypeId::String | TypeId::Id | TypeId::EntityId(_), JsonValue::String(s)) => {
+            EntityValue::String(s.clone())
+        }
+        (TypeId::Float, JsonValue::Number(n)) => {
+            EntityValue::Float64(n.as_f64().ok_or_else(invalid)?)
+        }
+        (TypeId::Int64, JsonValue::Number(n)) => {
+            EntityValue::Int64(n.as_i64().ok_or_else(invalid)?)
+        }
+        (TypeId::JsDate, JsonValue::Number(n)) => {
+            EntityValue::JsDate(n.as_f64().ok_or_else(invalid)?)
+        }
+        (TypeId::Decimal { precision, scale }, JsonValue::String(_) | JsonValue::Number(_)) => {
+            let text = match json {
+                JsonValue::String(s) => s.clone(),
+                _ => json.to_string(),
+            };
+            decimal::parse(&text, *precision, *scale).map_err(|_| invalid())?;
+            EntityValue::String(text)
+        }
+        (TypeId::Boolean, JsonValue::Bool(b)) => EntityValue::Boolean(*b),
+        (TypeId::Entity { .. }, JsonValue::Object(_))
+        | (TypeId::Array(_), JsonValue::Array(_))
+        | (TypeId::Json, _)
+        | (TypeId::GeoPoint, JsonValue::Object(_)) => {
+            EntityValue::from_json(json).map_err(|e| format!("{}: {}", invalid(), e))?
+        }
+        (TypeId::ArrayBuffer, _) => {
+            return Err(format!(
+                "field `{}` with type {} cannot be ingested from JSON",
+                field.name,
+                field.type_id.name()
+            ))
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: IngestFormat, chunks: &[&str]) -> Result<Vec<Row>, IngestError> {
+        let mut parser = RowParser::new(format);
+        let mut rows = vec![];
+        for chunk in chunks {
+            parser.push(chunk.as_bytes(), &mut rows)?;
+        }
+        parser.finish(&mut rows)?;
+        Ok(rows)
+    }
+
+    fn text_values(row: &Row) -> &[(String, String)] {
+        match &row.values {
+            RowValues::Text(values) => values,
+            RowValues::Json(_) => panic!("expected CSV values"),
+        }
+    }
+
+    #[test]
+    fn negotiate_format() {
+        let format = IngestFormat::from_content_type;
+        assert_eq!(format("text/csv; charset=utf-8"), Some(IngestFormat::Csv));
+        assert_eq!(format("application/x-ndjson"), Some(IngestFormat::Ndjson));
+        assert_eq!(format("application/json"), None);
+    }
+
+    #[test]
+    fn parse_csv_in_chunks() {
+        let rows = parse(
+            IngestFormat::Csv,
+            &["name,ag", "e\nAlice,3", "0\n\"Bob, \"\"Jr\"\"\",", "40"],
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            text_values(&rows[0]),
+            [("name".into(), "Alice".into()), ("age".into(), "30".into())]
+        );
+        assert_eq!(text_values(&rows[1])[0].1, "Bob, \"Jr\"");
+        assert_eq!(rows[1].number, 2);
+
+        let err = parse(IngestFormat::Csv, &["name,age\nAlice\n"]).unwrap_err();
+        assert_eq!(err.row, 1);
+    }
+
+    #[test]
+    fn parse_ndjson_in_chunks() {
+        let rows = parse(
+            IngestFormat::Ndjson,
+            &["{\"name\": \"Al", "ice\"}\n\n{\"name\"", ": \"Bob\"}"],
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].number, 2);
+
+        let err = parse(IngestFormat::Ndjson, &["{}\n[1]\n"]).unwrap_err();
+        assert_eq!(err.row, 2);
+    }
+}
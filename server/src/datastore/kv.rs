@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Ordered key-value store of a version, which TypeScript exposes as `Chisel.kv`.
+//!
+//! Each version has its own table (see `QueryEngine::create_kv_table()`), and the store is
+//! accessed in the transaction of the job, so the changes to the store are committed or rolled
+//! back together with the changes to the entities. Values are stored as JSON. An entry may expire:
+//! expired entries are never returned, and they are deleted lazily when the store is written.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sqlx::any::Any;
+use sqlx::{Executor, Row, Transaction};
+
+/// Returns the name of the table that backs the key-value store of the version.
+pub fn kv_table(version_id: &str) -> String {
+    format!("kv_{}", version_id)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KvEntry {
+    pub key: String,
+    pub value: JsonValue,
+    /// Time when the entry expires, in milliseconds since the UNIX epoch.
+    pub expires_at: Option<f64>,
+}
+
+fn entry_from_row(row: &sqlx::any::AnyRow) -> Result<KvEntry> {
+    let value: String = row.try_get("value")?;
+    Ok(KvEntry {
+        key: row.try_get("key")?,
+        value: serde_json::from_str(&value).context("Invalid JSON in the key-value store")?,
+        // sqlx gets confused if the float doesn't have decimal points.
+        expires_at: row.try_get_unchecked("expires_at")?,
+    })
+}
+
+/// Returns the entry with the given key, unless it does not exist or has expired at `now_ms`.
+pub async fn get(
+    txn: &mut Transaction<'_, Any>,
+    version_id: &str,
+    key: &str,
+    now_ms: f64,
+) -> Result<Option<KvEntry>> {
+    let sql = format!(
+        r#"SELECT "key", "value", "expires_at" FROM "{}" WHERE "key" = $1 AND ("expires_at" IS NULL OR "expires_at" > $2)"#,
+        kv_table(version_id)
+    );
+    let query = sqlx::query(&sql).bind(key).bind(now_ms);
+    let row = txn.fetch_optional(query).await?;
+    row.as_ref().map(entry_from_row).transpose()
+}
+
+/// Sets the value of the key, replacing the previous entry. The entry expires at `expires_at`
+/// (milliseconds since the UNIX epoch), or never if it is `None`.
+pub async fn set(
+    txn: &mut Transaction<'_, Any>,
+    version_id: &str,
+    key: &str,
+    value: &JsonValue,
+    expires_at: Option<f64>,
+    now_ms: f64,
+) -> Result<()> {
+    let table = kv_table(version_id);
+    let sql = format!(r#"DELETE FROM "{}" WHERE "expires_at" <= $1"#, table);
+    txn.execute(sqlx::query(&sql).bind(now_ms)).await?;
+
+    let sql = format!(
+        r#"INSERT INTO "{}" ("key", "value", "expires_at") VALUES ($1, $2, $3)
+        ON CONFLICT ("key") DO UPDATE SET "value" = excluded."value", "expires_at" = excluded."expires_at""#,
+        table
+    );
+    let query = sqlx::query(&sql)
+        .bind(key)
+        .bind(value.to_string())
+        .bind(expires_at);
+    txn.execute(query).await?;
+    Ok(())
+}
+
+/// Deletes the entry with the given key. Returns false if there was no such entry.
+pub async fn delete(txn: &mut Transaction<'_, Any>, version_id: &str, key: &str) -> Result<bool> {
+    let sql = format!(r#"DELETE FROM "{}" WHERE "key" = $1"#, kv_table(version_id));
+    let result = txn.execute(sqlx::query(&sql).bind(key)).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the entries whose keys start with `prefix` that have not expired at `now_ms`, ordered
+/// by their keys.
+pub async fn list(
+    txn: &mut Transaction<'_, Any>,
+    version_id: &str,
+    prefix: &str,
+    limit: Option<u64>,
+    now_ms: f64,
+) -> Result<Vec<KvEntry>> {
+    let mut sql = format!(
+        r#"SELECT "key", "value", "expires_at" FROM "{}"
+        WHERE substr("key", 1, $1) = $2 AND ("expires_at" IS NULL OR "expires_at" > $3)
+        ORDER BY "key""#,
+        kv_table(version_id)
+    );
+    if let Some(limit) = limit {
+        sql += &format!(" LIMIT {}", limit);
+    }
+    // Postgres has no implicit cast of BIGINT to the INTEGER argument of substr()
+    let query = sqlx::query(&sql)
+        .bind(prefix.chars().count() as i32)
+        .bind(prefix)
+        .bind(now_ms);
+    let rows = txn.fetch_all(query).await?;
+    rows.iter().map(entry_from_row).collect()
+}
@@ -0,0 +1,173 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Garbage collection of the meta database, run by `chisel meta gc` and periodically by the
+//! server (see `--meta-gc-interval-s`).
+//!
+//! The collection removes the rows that nothing refers to anymore: the rows of versions that were
+//! deleted, audit entries older than `--audit-retention-s`, records of applies older than
+//! `--apply-retention-s` and the sources of servers older than schema version 2 (which were
+//! converted to modules). Then the database is compacted, because every apply rewrites the modules
+//! of the version and the old rows leave free pages behind.
+//!
+//! The collection never removes the rows of a version that the server runs or that is recorded
+//! in `api_info` (another server may run it), the record of the apply that holds the apply lock,
+//! nor the archived entities, whose tables still hold data and expire on their own.
+
+use super::{execute, fetch_all, unix_timestamp, MetaService};
+use anyhow::{Context, Result};
+use sqlx::any::{AnyKind, AnyPool};
+use sqlx::Row;
+use std::collections::HashSet;
+
+/// Tables that hold rows of versions, with the name of the column that holds the version id.
+pub(super) const VERSION_TABLES: &[(&str, &str)] = &[
+    ("modules", "version"),
+    ("static_files", "version"),
+    ("policies", "version"),
+    ("policy_store", "version"),
+    ("seeds_applied", "version_id"),
+    ("route_examples", "version_id"),
+];
+
+/// How long the garbage collection keeps the rows that expire.
+#[derive(Debug, Clone, Copy)]
+pub struct GcRetention {
+    /// Zero keeps the audit log forever.
+    pub audit_retention_s: u64,
+    pub apply_retention_s: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Number of removed rows, by table (tables without removed rows are omitted).
+    pub removed_rows: Vec<(String, u64)>,
+    /// Size of the database that was reclaimed by the compaction. Only known for SQLite.
+    pub reclaimed_bytes: Option<u64>,
+    /// True if the rows were only counted, and nothing was removed.
+    pub dry_run: bool,
+}
+
+impl MetaService {
+    /// Removes the garbage from the meta database and compacts it. The versions in
+    /// `live_versions` (which the server runs) are kept even if they are missing in `api_info`.
+    pub async fn collect_garbage(
+        &self,
+        live_versions: &HashSet<String>,
+        retention: GcRetention,
+        dry_run: bool,
+    ) -> Result<GcReport> {
+        let mut transaction = self.begin_transaction().await?;
+        // an apply that commits in the middle of the collection could add the modules of a
+        // version that is not yet in `api_info`
+        self.lock_apply_transaction(&mut transaction).await?;
+        let mut removed_rows = vec![];
+        let mut remove = |table: &str, count: u64| {
+            if count > 0 {
+                removed_rows.push((table.to_owned(), count));
+            }
+        };
+
+        let mut kept_versions = live_versions.clone();
+        let query = sqlx::query("SELECT api_version FROM api_info");
+        for row in fetch_all(&mut transaction, query).await? {
+            kept_versions.insert(row.get("api_version"));
+        }
+        for &(table, column) in VERSION_TABLES.iter() {
+            let sql = format!("SELECT DISTINCT {column} AS version FROM {table}");
+            let versions = fetch_all(&mut transaction, sqlx::query(&sql))
+                .await?
+                .into_iter()
+                .map(|row| row.get::<String, _>("version"))
+                .filter(|version| !kept_versions.contains(version))
+                .collect::<Vec<_>>();
+            let sql = format!("DELETE FROM {table} WHERE {column} = $1");
+            let mut count = 0;
+            for version in versions.iter() {
+                let delete = sqlx::query(&sql).bind(version.clone());
+                count += execute(&mut transaction, delete).await?.rows_affected();
+            }
+            remove(table, count);
+        }
+
+        if retention.audit_retention_s > 0 {
+            let delete = sqlx::query("DELETE FROM audit_log WHERE created_at < $1")
+                .bind(unix_timestamp().saturating_sub(retention.audit_retention_s as i64));
+            remove(
+                "audit_log",
+                execute(&mut transaction, delete).await?.rows_affected(),
+            );
+        }
+
+        let delete = sqlx::query(
+            r#"
+            DELETE FROM applies WHERE updated_at < $1
+            AND apply_id NOT IN (SELECT apply_id FROM apply_lock WHERE apply_id IS NOT NULL)"#,
+        )
+        .bind(unix_timestamp().saturating_sub(retention.apply_retention_s as i64));
+        remove(
+            "applies",
+            execute(&mut transaction, delete).await?.rows_affected(),
+        );
+
+        for table in ["sources", "endpoints"] {
+            let sql = format!("DELETE FROM {table}");
+            let delete = sqlx::query(&sql);
+            remove(
+                table,
+                execute(&mut transaction, delete).await?.rows_affected(),
+            );
+        }
+
+        if dry_run {
+            transaction.rollback().await?;
+            return Ok(GcReport {
+                removed_rows,
+                reclaimed_bytes: None,
+                dry_run,
+            });
+        }
+        Self::commit_transaction(transaction).await?;
+        let reclaimed_bytes = self
+            .compact()
+            .await
+            .context("Could not compact the meta database")?;
+        Ok(GcReport {
+            removed_rows,
+            reclaimed_bytes,
+            dry_run,
+        })
+    }
+
+    /// Returns the number of bytes that were reclaimed, if it is known.
+    async fn compact(&self) -> Result<Option<u64>> {
+        let pool = &self.db.pool;
+        match pool.any_kind() {
+            AnyKind::Sqlite => {
+                let before = sqlite_size(pool).await?;
+                sqlx::query("VACUUM").execute(pool).await?;
+                let after = sqlite_size(pool).await?;
+                Ok(Some(before.saturating_sub(after)))
+            }
+            AnyKind::Postgres => {
+                // VACUUM cannot run in a transaction, and it only makes the space reusable
+                for &(table, _) in VERSION_TABLES.iter() {
+                    let sql = format!("VACUUM {table}");
+                    sqlx::query(&sql).execute(pool).await?;
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+async fn sqlite_size(pool: &AnyPool) -> Result<u64> {
+    let count: i64 = sqlx::query("PRAGMA page_count")
+        .fetch_one(pool)
+        .await?
+        .get(0);
+    let size: i64 = sqlx::query("PRAGMA page_size")
+        .fetch_one(pool)
+        .await?
+        .get(0);
+    Ok((count * size) as u64)
+}
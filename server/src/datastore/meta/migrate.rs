@@ -11,6 +11,10 @@ pub struct MigrateContext<'t, 'c> {
     pub transaction: &'t mut sqlx::Transaction<'c, sqlx::Any>,
 }
 
+/// The version of the schema that `migrate_schema_step()` migrates to. It must be updated with
+/// every new migration.
+pub const LATEST_SCHEMA_VERSION: &str = "27";
+
 // Migrates the database schema from given version and returns the new version or `None` if we are
 // already at the latest version.
 pub async fn migrate_schema_step(
@@ -51,7 +55,99 @@ pub async fn migrate_schema_step(
             migrate_to_4(ctx).await?;
             Some("4")
         }
-        "4" => None,
+        "4" => {
+            migrate_to_5(ctx).await?;
+            Some("5")
+        }
+        "5" => {
+            migrate_to_6(ctx).await?;
+            Some("6")
+        }
+        "6" => {
+            migrate_to_7(ctx).await?;
+            Some("7")
+        }
+        "7" => {
+            migrate_to_8(ctx).await?;
+            Some("8")
+        }
+        "8" => {
+            migrate_to_9(ctx).await?;
+            Some("9")
+        }
+        "9" => {
+            migrate_to_10(ctx).await?;
+            Some("10")
+        }
+        "10" => {
+            migrate_to_11(ctx).await?;
+            Some("11")
+        }
+        "11" => {
+            migrate_to_12(ctx).await?;
+            Some("12")
+        }
+        "12" => {
+            migrate_to_13(ctx).await?;
+            Some("13")
+        }
+        "13" => {
+            migrate_to_14(ctx).await?;
+            Some("14")
+        }
+        "14" => {
+            migrate_to_15(ctx).await?;
+            Some("15")
+        }
+        "15" => {
+            migrate_to_16(ctx).await?;
+            Some("16")
+        }
+        "16" => {
+            migrate_to_17(ctx).await?;
+            Some("17")
+        }
+        "17" => {
+            migrate_to_18(ctx).await?;
+            Some("18")
+        }
+        "18" => {
+            migrate_to_19(ctx).await?;
+            Some("19")
+        }
+        "19" => {
+            migrate_to_20(ctx).await?;
+            Some("20")
+        }
+        "20" => {
+            migrate_to_21(ctx).await?;
+            Some("21")
+        }
+        "21" => {
+            migrate_to_22(ctx).await?;
+            Some("22")
+        }
+        "22" => {
+            migrate_to_23(ctx).await?;
+            Some("23")
+        }
+        "23" => {
+            migrate_to_24(ctx).await?;
+            Some("24")
+        }
+        "24" => {
+            migrate_to_25(ctx).await?;
+            Some("25")
+        }
+        "25" => {
+            migrate_to_26(ctx).await?;
+            Some("26")
+        }
+        "26" => {
+            migrate_to_27(ctx).await?;
+            Some("27")
+        }
+        LATEST_SCHEMA_VERSION => None,
         _ => bail!("Don't know how to migrate from version {:?}", old_version),
     })
 }
@@ -410,6 +506,494 @@ async fn migrate_to_4(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
     Ok(())
 }
 
+async fn migrate_to_5(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(KafkaOffsets::Table)
+            .col(sea_query::ColumnDef::new(KafkaOffsets::ConsumerGroup).text())
+            .col(sea_query::ColumnDef::new(KafkaOffsets::Topic).text())
+            .col(sea_query::ColumnDef::new(KafkaOffsets::Partition).integer())
+            .col(sea_query::ColumnDef::new(KafkaOffsets::Offset).big_integer())
+            .primary_key(
+                sea_query::Index::create()
+                    .col(KafkaOffsets::ConsumerGroup)
+                    .col(KafkaOffsets::Topic)
+                    .col(KafkaOffsets::Partition),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_6(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(Applies::Table)
+            .col(
+                sea_query::ColumnDef::new(Applies::ApplyId)
+                    .text()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(Applies::VersionId).text())
+            .col(sea_query::ColumnDef::new(Applies::Request).text())
+            .col(sea_query::ColumnDef::new(Applies::Status).text())
+            .col(sea_query::ColumnDef::new(Applies::Response).text())
+            .col(sea_query::ColumnDef::new(Applies::Error).text())
+            .col(sea_query::ColumnDef::new(Applies::UpdatedAt).big_integer()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_7(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(ArchivedEntities::Table)
+            .col(
+                sea_query::ColumnDef::new(ArchivedEntities::TableName)
+                    .text()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(ArchivedEntities::VersionId).text())
+            .col(sea_query::ColumnDef::new(ArchivedEntities::EntityName).text())
+            .col(sea_query::ColumnDef::new(ArchivedEntities::Fields).text())
+            .col(sea_query::ColumnDef::new(ArchivedEntities::ArchivedAt).big_integer()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_8(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(Secrets::Table)
+            .col(sea_query::ColumnDef::new(Secrets::Name).text())
+            .col(sea_query::ColumnDef::new(Secrets::Version).big_integer())
+            .col(sea_query::ColumnDef::new(Secrets::Value).text())
+            .col(sea_query::ColumnDef::new(Secrets::CreatedAt).big_integer())
+            .primary_key(
+                sea_query::Index::create()
+                    .col(Secrets::Name)
+                    .col(Secrets::Version),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_9(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(ApplyLock::Table)
+            .col(
+                sea_query::ColumnDef::new(ApplyLock::Id)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(ApplyLock::ApplyId).text())
+            .col(sea_query::ColumnDef::new(ApplyLock::Holder).text())
+            .col(sea_query::ColumnDef::new(ApplyLock::AcquiredAt).big_integer()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_10(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::alter().table(Fields::Table).add_column(
+            sea_query::ColumnDef::new(Fields::IsVersion)
+                .boolean()
+                .not_null()
+                .default(false),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_11(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(FieldCounts::Table)
+            .col(
+                sea_query::ColumnDef::new(FieldCounts::FieldId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(FieldCounts::EntityName).text())
+            .col(sea_query::ColumnDef::new(FieldCounts::CountedField).text())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(FieldCounts::Table, FieldCounts::FieldId)
+                    .to(Fields::Table, Fields::FieldId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_12(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(TypeAggregates::Table)
+            .col(
+                sea_query::ColumnDef::new(TypeAggregates::TypeId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(TypeAggregates::SourceEntity).text())
+            .col(sea_query::ColumnDef::new(TypeAggregates::RefreshIntervalS).big_integer())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(TypeAggregates::Table, TypeAggregates::TypeId)
+                    .to(Types::Table, Types::TypeId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(FieldAggregates::Table)
+            .col(
+                sea_query::ColumnDef::new(FieldAggregates::FieldId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(FieldAggregates::Function).text())
+            .col(sea_query::ColumnDef::new(FieldAggregates::SourceField).text())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(FieldAggregates::Table, FieldAggregates::FieldId)
+                    .to(Fields::Table, Fields::FieldId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_13(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::alter().table(Types::Table).add_column(
+            sea_query::ColumnDef::new(Types::Locked)
+                .boolean()
+                .not_null()
+                .default(false),
+        ),
+    )
+    .await?;
+
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(AuditLog::Table)
+            .col(
+                sea_query::ColumnDef::new(AuditLog::Id)
+                    .integer()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(AuditLog::VersionId).text())
+            .col(sea_query::ColumnDef::new(AuditLog::Actor).text())
+            .col(sea_query::ColumnDef::new(AuditLog::Action).text())
+            .col(sea_query::ColumnDef::new(AuditLog::Detail).text())
+            .col(sea_query::ColumnDef::new(AuditLog::CreatedAt).big_integer()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_14(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(SeedsApplied::Table)
+            .col(sea_query::ColumnDef::new(SeedsApplied::VersionId).text())
+            .col(sea_query::ColumnDef::new(SeedsApplied::Name).text())
+            .col(sea_query::ColumnDef::new(SeedsApplied::AppliedAt).big_integer())
+            .primary_key(
+                sea_query::Index::create()
+                    .col(SeedsApplied::VersionId)
+                    .col(SeedsApplied::Name),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_15(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(FieldTimestamps::Table)
+            .col(
+                sea_query::ColumnDef::new(FieldTimestamps::FieldId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(FieldTimestamps::Kind).text())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(FieldTimestamps::Table, FieldTimestamps::FieldId)
+                    .to(Fields::Table, Fields::FieldId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_16(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::alter()
+            .table(Types::Table)
+            .add_column(sea_query::ColumnDef::new(Types::OwnedBy).text()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_17(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(FieldEnums::Table)
+            .col(
+                sea_query::ColumnDef::new(FieldEnums::FieldId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(FieldEnums::Name).text())
+            // JSON array of the values
+            .col(sea_query::ColumnDef::new(FieldEnums::EnumValues).text())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(FieldEnums::Table, FieldEnums::FieldId)
+                    .to(Fields::Table, Fields::FieldId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_18(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(FieldSizeLimits::Table)
+            .col(
+                sea_query::ColumnDef::new(FieldSizeLimits::FieldId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(FieldSizeLimits::MaxBytes).big_integer())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(FieldSizeLimits::Table, FieldSizeLimits::FieldId)
+                    .to(Fields::Table, Fields::FieldId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_19(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(FieldOnDelete::Table)
+            .col(
+                sea_query::ColumnDef::new(FieldOnDelete::FieldId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(FieldOnDelete::Action).text())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(FieldOnDelete::Table, FieldOnDelete::FieldId)
+                    .to(Fields::Table, Fields::FieldId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_20(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(RouteExamples::Table)
+            .col(sea_query::ColumnDef::new(RouteExamples::VersionId).text())
+            .col(sea_query::ColumnDef::new(RouteExamples::Name).text())
+            .col(sea_query::ColumnDef::new(RouteExamples::Example).text())
+            .col(sea_query::ColumnDef::new(RouteExamples::RecordedAt).big_integer())
+            .primary_key(
+                sea_query::Index::create()
+                    .col(RouteExamples::VersionId)
+                    .col(RouteExamples::Name),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_21(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(FieldCollations::Table)
+            .col(
+                sea_query::ColumnDef::new(FieldCollations::FieldId)
+                    .integer()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(FieldCollations::Collation).text())
+            .foreign_key(
+                sea_query::ForeignKey::create()
+                    .from(FieldCollations::Table, FieldCollations::FieldId)
+                    .to(Fields::Table, Fields::FieldId)
+                    .on_delete(sea_query::ForeignKeyAction::Cascade),
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_22(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(LoginStates::Table)
+            .col(
+                sea_query::ColumnDef::new(LoginStates::State)
+                    .text()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(LoginStates::Nonce).text())
+            .col(sea_query::ColumnDef::new(LoginStates::RedirectTo).text())
+            .col(sea_query::ColumnDef::new(LoginStates::CreatedAt).big_integer()),
+    )
+    .await?;
+
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(LoginSessions::Table)
+            .col(
+                sea_query::ColumnDef::new(LoginSessions::TokenHash)
+                    .text()
+                    .primary_key(),
+            )
+            .col(sea_query::ColumnDef::new(LoginSessions::UserId).text())
+            .col(sea_query::ColumnDef::new(LoginSessions::CreatedAt).big_integer())
+            .col(sea_query::ColumnDef::new(LoginSessions::ExpiresAt).big_integer()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn migrate_to_23(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    // the content is stored as base64, because the meta database only stores text
+    execute_stmt(
+        ctx,
+        sea_query::Table::create()
+            .table(StaticFiles::Table)
+            .col(sea_query::ColumnDef::new(StaticFiles::Version).text())
+            .col(sea_query::ColumnDef::new(StaticFiles::Path).text())
+            .col(sea_query::ColumnDef::new(StaticFiles::Content).text())
+            .primary_key(
+                sea_query::Index::create()
+                    .col(StaticFiles::Version)
+                    .col(StaticFiles::Path),
+            ),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn migrate_to_24(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    // NULL for the versions that were applied before the allowlist existed, which keep
+    // unrestricted network access
+    execute_stmt(
+        ctx,
+        sea_query::Table::alter()
+            .table(ApiInfo::Table)
+            .add_column(sea_query::ColumnDef::new(ApiInfo::AllowedHosts).text()),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn migrate_to_25(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    // NULL for the versions that use the worker pool settings of the server
+    execute_stmt(
+        ctx,
+        sea_query::Table::alter()
+            .table(ApiInfo::Table)
+            .add_column(sea_query::ColumnDef::new(ApiInfo::WorkerPool).text()),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn migrate_to_26(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    // NULL for the versions that were applied without an import map
+    execute_stmt(
+        ctx,
+        sea_query::Table::alter()
+            .table(ApiInfo::Table)
+            .add_column(sea_query::ColumnDef::new(ApiInfo::ImportMap).text()),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn migrate_to_27(ctx: &mut MigrateContext<'_, '_>) -> Result<()> {
+    // NULL for the logins that were started without PKCE, which the provider will refuse
+    execute_stmt(
+        ctx,
+        sea_query::Table::alter()
+            .table(LoginStates::Table)
+            .add_column(sea_query::ColumnDef::new(LoginStates::CodeVerifier).text()),
+    )
+    .await?;
+    Ok(())
+}
+
 async fn execute_stmt<S>(ctx: &mut MigrateContext<'_, '_>, stmt: &S) -> Result<()>
 where
     S: sea_query::SchemaStatementBuilder,
@@ -1,17 +1,22 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
+mod gc;
 mod migrate;
 mod migrate_to_2;
 mod schema;
 
+use crate::contract::RouteExample;
 use crate::datastore::DbConnection;
 use crate::policies::PolicySystem;
 use crate::types::{
-    BuiltinTypes, DbIndex, Entity, ExistingField, ExistingObject, Field, FieldDelta, ObjectDelta,
-    ObjectDescriptor, ObjectType, TypeSystem,
+    AggregateFieldSpec, AggregateSpec, BuiltinTypes, Collation, CountSpec, DbIndex, Entity,
+    EnumSpec, ExistingField, ExistingObject, Field, FieldDelta, ObjectDelta, ObjectDescriptor,
+    ObjectType, OnDelete, Timestamp, TypeId, TypeSystem,
 };
 use crate::version::VersionInfo;
+use crate::worker_pool::WorkerPoolOverrides;
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use sqlx::any::{Any, AnyKind};
 use sqlx::{Execute, Executor, Row, Transaction};
 use std::collections::{HashMap, HashSet};
@@ -19,6 +24,10 @@ use std::fmt::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
+use utils::ImportMap;
+
+pub use gc::{GcReport, GcRetention};
+pub use migrate::LATEST_SCHEMA_VERSION;
 
 /// Meta service.
 ///
@@ -29,6 +38,177 @@ pub struct MetaService {
     db: Arc<DbConnection>,
 }
 
+/// Status of an apply that was staged by [`MetaService::stage_apply()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyStatus {
+    Pending,
+    Applied,
+    Failed,
+}
+
+impl ApplyStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ApplyStatus::Pending => "pending",
+            ApplyStatus::Applied => "applied",
+            ApplyStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Result<Self> {
+        Ok(match status {
+            "pending" => ApplyStatus::Pending,
+            "applied" => ApplyStatus::Applied,
+            "failed" => ApplyStatus::Failed,
+            _ => anyhow::bail!("Unknown apply status {:?}", status),
+        })
+    }
+}
+
+/// Apply that was staged by [`MetaService::stage_apply()`].
+#[derive(Debug)]
+pub struct StagedApply {
+    pub version_id: String,
+    /// Encoded `ApplyRequest`.
+    pub request: Vec<u8>,
+    pub status: ApplyStatus,
+    /// Encoded `ApplyResponse`, set once the apply is applied.
+    pub response: Option<Vec<u8>>,
+    /// Error message, set if the apply has failed.
+    pub error: Option<String>,
+}
+
+/// Entity whose backing table was archived instead of dropped when it was removed by an apply.
+#[derive(Debug, Clone)]
+pub struct ArchivedEntity {
+    /// Name of the archived backing table.
+    pub table_name: String,
+    pub version_id: String,
+    pub entity_name: String,
+    pub fields: Vec<ArchivedField>,
+    /// UNIX timestamp (in seconds) of the apply that archived the entity.
+    pub archived_at: i64,
+}
+
+/// Entry of the audit log, which records sensitive operations (such as changes of locked entities).
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub version_id: String,
+    /// Who performed the operation (such as `user@host`).
+    pub actor: String,
+    pub action: String,
+    pub detail: String,
+    /// UNIX timestamp (in seconds) of the operation.
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedField {
+    pub name: String,
+    /// Name of a builtin type; fields that referenced other entities are stored as `Id<Entity>`.
+    pub type_name: String,
+    pub is_optional: bool,
+}
+
+impl ArchivedEntity {
+    pub fn new(ty: &ObjectType, table_name: String) -> Self {
+        let fields = ty
+            .user_fields()
+            .map(|field| ArchivedField {
+                name: field.name.clone(),
+                type_name: archived_type_name(&field.type_id),
+                is_optional: field.is_optional,
+            })
+            .collect();
+        Self {
+            table_name,
+            version_id: ty.version_id.clone(),
+            entity_name: ty.name().to_owned(),
+            fields,
+            archived_at: unix_timestamp(),
+        }
+    }
+
+    /// Reconstructs the type of the archived table, so that it can be queried.
+    pub fn object_type(&self, builtin: &Arc<BuiltinTypes>) -> Result<ObjectType> {
+        let ts = TypeSystem::new(builtin.clone(), self.version_id.clone());
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| {
+                let ty = ts.lookup_builtin_type(&field.type_name)?;
+                let desc = ExistingField::new(&field.name, ty, 0, &self.version_id);
+                Ok(Field::new(
+                    &desc,
+                    vec![],
+                    None,
+                    field.is_optional,
+                    false,
+                    false,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let name = format!("{}.{}", self.version_id, self.entity_name);
+        let desc = ExistingObject::new(&name, &self.table_name, 0)?;
+        ObjectType::new(&desc, fields, vec![])
+    }
+}
+
+/// Apply that holds the apply lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplyLockHolder {
+    pub apply_id: String,
+    /// Who started the apply (usually `user@host` of the `chisel apply` client).
+    pub holder: String,
+    /// UNIX timestamp (in seconds) when the lock was acquired.
+    pub acquired_at: i64,
+}
+
+/// Secret that was set (or unset) with `chisel secrets`.
+#[derive(Debug, Clone)]
+pub struct StoredSecret {
+    pub name: String,
+    /// Incremented on every change of the secret, starting from 1.
+    pub version: i64,
+    /// `None` if the secret was unset.
+    pub value: Option<serde_json::Value>,
+    /// UNIX timestamp (in seconds) of the latest change.
+    pub updated_at: i64,
+}
+
+/// Login that was started with the OIDC provider and waits for its callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginState {
+    /// Random value that the provider passes back to the callback.
+    pub state: String,
+    /// Random value that the provider puts into the ID token.
+    pub nonce: String,
+    /// Random PKCE verifier, whose hash is sent to the provider with the login and which is sent
+    /// with the authorization code, so that only chiseld can exchange the code.
+    pub code_verifier: String,
+    /// Path that the user is redirected to after the login.
+    pub redirect_to: String,
+    /// UNIX timestamp (in seconds) when the login was started.
+    pub created_at: i64,
+}
+
+/// The archived table keeps only the ids of the referenced entities, which may be gone by the time
+/// the archive is queried.
+fn archived_type_name(type_id: &TypeId) -> String {
+    match type_id {
+        TypeId::Entity { name, .. } => format!("Id<{}>", name),
+        TypeId::Array(element) => format!("Array<{}>", archived_type_name(element)),
+        type_id => type_id.name(),
+    }
+}
+
+/// Key of the Postgres advisory lock that is held by applies (an arbitrary constant).
+const APPLY_LOCK_KEY: i64 = 0x63686973656c;
+
+fn unix_timestamp() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
 async fn execute<'a, 'b>(
     transaction: &mut Transaction<'b, sqlx::Any>,
     query: sqlx::query::Query<'a, sqlx::Any, sqlx::any::AnyArguments<'a>>,
@@ -87,7 +267,7 @@ async fn update_field_query(
         let default_stmt = if field.default.is_none() {
             ""
         } else {
-            ", default_value = $5"
+            ", default_value = $6"
         };
 
         let querystr = format!(
@@ -96,7 +276,8 @@ async fn update_field_query(
             SET
                 field_type = $1,
                 is_optional = $2::bool,
-                is_unique = $3::bool {default_stmt}
+                is_unique = $3::bool,
+                is_version = $5::bool {default_stmt}
             WHERE field_id = $4"#
         );
         let mut query = sqlx::query(&querystr);
@@ -105,13 +286,21 @@ async fn update_field_query(
             .bind(field.type_id.name())
             .bind(field.is_optional)
             .bind(field.is_unique)
-            .bind(field_id);
+            .bind(field_id)
+            .bind(field.is_version);
 
         if let Some(value) = &field.default {
             query = query.bind(value.to_owned());
         }
 
         execute(transaction, query).await?;
+        persist_field_count(transaction, field_id, &field.count).await?;
+        persist_field_aggregate(transaction, field_id, &field.aggregate).await?;
+        persist_field_timestamp(transaction, field_id, field.timestamp).await?;
+        persist_field_enum(transaction, field_id, &field.enum_type).await?;
+        persist_field_max_bytes(transaction, field_id, field.max_bytes).await?;
+        persist_field_on_delete(transaction, field_id, field.on_delete).await?;
+        persist_field_collation(transaction, field_id, field.collation).await?;
     }
 
     if let Some(labels) = &delta.labels {
@@ -142,6 +331,169 @@ async fn remove_field_query(transaction: &mut Transaction<'_, Any>, field: &Fiel
     let query = sqlx::query("DELETE FROM field_labels WHERE field_id = $1").bind(field_id);
     execute(transaction, query).await?;
 
+    persist_field_count(transaction, field_id, &None).await?;
+    persist_field_aggregate(transaction, field_id, &None).await?;
+    persist_field_timestamp(transaction, field_id, None).await?;
+    persist_field_enum(transaction, field_id, &None).await?;
+    persist_field_max_bytes(transaction, field_id, None).await?;
+    persist_field_on_delete(transaction, field_id, None).await?;
+    persist_field_collation(transaction, field_id, None).await?;
+    Ok(())
+}
+
+/// Replaces the `@count` definition of a field.
+async fn persist_field_count(
+    transaction: &mut Transaction<'_, Any>,
+    field_id: i32,
+    count: &Option<CountSpec>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM field_counts WHERE field_id = $1").bind(field_id);
+    execute(transaction, flush).await?;
+
+    if let Some(count) = count {
+        let q = sqlx::query(
+            "INSERT INTO field_counts (field_id, entity_name, counted_field) VALUES ($1, $2, $3)",
+        )
+        .bind(field_id)
+        .bind(&count.entity)
+        .bind(&count.field);
+        execute(transaction, q).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the aggregate function of a field of a materialized aggregate.
+async fn persist_field_aggregate(
+    transaction: &mut Transaction<'_, Any>,
+    field_id: i32,
+    aggregate: &Option<AggregateFieldSpec>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM field_aggregates WHERE field_id = $1").bind(field_id);
+    execute(transaction, flush).await?;
+
+    if let Some(aggregate) = aggregate {
+        let q = sqlx::query(
+            "INSERT INTO field_aggregates (field_id, function, source_field) VALUES ($1, $2, $3)",
+        )
+        .bind(field_id)
+        .bind(aggregate.function.as_str())
+        .bind(aggregate.source_field.clone());
+        execute(transaction, q).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the kind of a field that is added by `@timestamps`.
+async fn persist_field_timestamp(
+    transaction: &mut Transaction<'_, Any>,
+    field_id: i32,
+    timestamp: Option<Timestamp>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM field_timestamps WHERE field_id = $1").bind(field_id);
+    execute(transaction, flush).await?;
+
+    if let Some(timestamp) = timestamp {
+        let q = sqlx::query("INSERT INTO field_timestamps (field_id, kind) VALUES ($1, $2)")
+            .bind(field_id)
+            .bind(timestamp.as_str());
+        execute(transaction, q).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the enum that is the type of a field.
+async fn persist_field_enum(
+    transaction: &mut Transaction<'_, Any>,
+    field_id: i32,
+    enum_type: &Option<EnumSpec>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM field_enums WHERE field_id = $1").bind(field_id);
+    execute(transaction, flush).await?;
+
+    if let Some(enum_type) = enum_type {
+        let q = sqlx::query(
+            "INSERT INTO field_enums (field_id, name, enum_values) VALUES ($1, $2, $3)",
+        )
+        .bind(field_id)
+        .bind(enum_type.name.clone())
+        .bind(serde_json::to_string(&enum_type.values)?);
+        execute(transaction, q).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the `@maxBytes` limit of a field.
+async fn persist_field_max_bytes(
+    transaction: &mut Transaction<'_, Any>,
+    field_id: i32,
+    max_bytes: Option<u64>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM field_size_limits WHERE field_id = $1").bind(field_id);
+    execute(transaction, flush).await?;
+
+    if let Some(max_bytes) = max_bytes {
+        let q = sqlx::query("INSERT INTO field_size_limits (field_id, max_bytes) VALUES ($1, $2)")
+            .bind(field_id)
+            .bind(i64::try_from(max_bytes)?);
+        execute(transaction, q).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the `@onDelete` action of a field.
+async fn persist_field_on_delete(
+    transaction: &mut Transaction<'_, Any>,
+    field_id: i32,
+    on_delete: Option<OnDelete>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM field_on_delete WHERE field_id = $1").bind(field_id);
+    execute(transaction, flush).await?;
+
+    if let Some(on_delete) = on_delete {
+        let q = sqlx::query("INSERT INTO field_on_delete (field_id, action) VALUES ($1, $2)")
+            .bind(field_id)
+            .bind(on_delete.as_str());
+        execute(transaction, q).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the `@collate` collation of a field.
+async fn persist_field_collation(
+    transaction: &mut Transaction<'_, Any>,
+    field_id: i32,
+    collation: Option<Collation>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM field_collations WHERE field_id = $1").bind(field_id);
+    execute(transaction, flush).await?;
+
+    if let Some(collation) = collation {
+        let q = sqlx::query("INSERT INTO field_collations (field_id, collation) VALUES ($1, $2)")
+            .bind(field_id)
+            .bind(collation.as_str());
+        execute(transaction, q).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the definition of a materialized aggregate.
+async fn persist_type_aggregate(
+    transaction: &mut Transaction<'_, Any>,
+    type_id: i32,
+    aggregate: &Option<AggregateSpec>,
+) -> Result<()> {
+    let flush = sqlx::query("DELETE FROM type_aggregates WHERE type_id = $1").bind(type_id);
+    execute(transaction, flush).await?;
+
+    if let Some(aggregate) = aggregate {
+        let q = sqlx::query(
+            "INSERT INTO type_aggregates (type_id, source_entity, refresh_interval_s) VALUES ($1, $2, $3)",
+        )
+        .bind(type_id)
+        .bind(&aggregate.source)
+        .bind(aggregate.refresh_interval_s.map(|s| s as i64));
+        execute(transaction, q).await?;
+    }
     Ok(())
 }
 
@@ -159,8 +511,8 @@ async fn insert_field_query(
         None => {
             let query = sqlx::query(
                 r#"
-                INSERT INTO fields (field_type, type_id, is_optional, is_unique)
-                VALUES ($1, $2, $3, $4)
+                INSERT INTO fields (field_type, type_id, is_optional, is_unique, is_version)
+                VALUES ($1, $2, $3, $4, $5)
                 RETURNING *"#,
             );
             query
@@ -168,6 +520,7 @@ async fn insert_field_query(
                 .bind(type_id)
                 .bind(field.is_optional)
                 .bind(field.is_unique)
+                .bind(field.is_version)
         }
         Some(value) => {
             let query = sqlx::query(
@@ -177,8 +530,9 @@ async fn insert_field_query(
                     type_id,
                     default_value,
                     is_optional,
-                    is_unique)
-                VALUES ($1, $2, $3, $4, $5)
+                    is_unique,
+                    is_version)
+                VALUES ($1, $2, $3, $4, $5, $6)
                 RETURNING *"#,
             );
             query
@@ -187,6 +541,7 @@ async fn insert_field_query(
                 .bind(value.to_owned())
                 .bind(field.is_optional)
                 .bind(field.is_unique)
+                .bind(field.is_version)
         }
     };
     let add_field_name = sqlx::query(
@@ -212,6 +567,13 @@ async fn insert_field_query(
             .bind(field_id);
         execute(transaction, q).await?;
     }
+    persist_field_count(transaction, field_id, &field.count).await?;
+    persist_field_aggregate(transaction, field_id, &field.aggregate).await?;
+    persist_field_timestamp(transaction, field_id, field.timestamp).await?;
+    persist_field_enum(transaction, field_id, &field.enum_type).await?;
+    persist_field_max_bytes(transaction, field_id, field.max_bytes).await?;
+    persist_field_on_delete(transaction, field_id, field.on_delete).await?;
+    persist_field_collation(transaction, field_id, field.collation).await?;
     Ok(())
 }
 
@@ -244,6 +606,14 @@ impl MetaService {
         })
     }
 
+    /// Returns the version of the schema of the database ("empty" if the database is empty).
+    pub async fn schema_version(&self) -> Result<String> {
+        let mut transaction = self.begin_transaction().await?;
+        let version = self.get_schema_version(&mut transaction).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(version)
+    }
+
     /// Try to migrate an old-style (split meta + data) sqlite layout to
     /// a single file.
     ///
@@ -445,6 +815,103 @@ impl MetaService {
         Ok(())
     }
 
+    /// Loads the hosts that the version may connect to, `None` if the version was applied before
+    /// the allowlist existed.
+    pub async fn load_allowed_hosts(&self, version_id: &str) -> Result<Option<Vec<String>>> {
+        let query = sqlx::query("SELECT allowed_hosts FROM api_info WHERE api_version = $1")
+            .bind(version_id);
+        let rows = fetch_all(&self.db.pool, query).await?;
+        let hosts: Option<String> = match rows.first() {
+            Some(row) => row.get("allowed_hosts"),
+            None => None,
+        };
+        hosts
+            .map(|hosts| serde_json::from_str(&hosts).context("Invalid allowed hosts"))
+            .transpose()
+    }
+
+    /// Stores the hosts that the version may connect to. The version info must be persisted
+    /// first (see `persist_version_info()`).
+    pub async fn persist_allowed_hosts(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+        hosts: &[String],
+    ) -> Result<()> {
+        let update = sqlx::query("UPDATE api_info SET allowed_hosts = $2 WHERE api_version = $1")
+            .bind(version_id.to_owned())
+            .bind(serde_json::to_string(hosts)?);
+        execute(transaction, update).await?;
+        Ok(())
+    }
+
+    /// Loads the worker pool settings of the version.
+    pub async fn load_worker_pool(&self, version_id: &str) -> Result<WorkerPoolOverrides> {
+        let query =
+            sqlx::query("SELECT worker_pool FROM api_info WHERE api_version = $1").bind(version_id);
+        let rows = fetch_all(&self.db.pool, query).await?;
+        let settings: Option<String> = match rows.first() {
+            Some(row) => row.get("worker_pool"),
+            None => None,
+        };
+        match settings {
+            Some(settings) => {
+                serde_json::from_str(&settings).context("Invalid worker pool settings")
+            }
+            None => Ok(WorkerPoolOverrides::default()),
+        }
+    }
+
+    /// Stores the worker pool settings of the version. The version info must be persisted first
+    /// (see `persist_version_info()`).
+    pub async fn persist_worker_pool(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+        settings: &WorkerPoolOverrides,
+    ) -> Result<()> {
+        let update = sqlx::query("UPDATE api_info SET worker_pool = $2 WHERE api_version = $1")
+            .bind(version_id.to_owned())
+            .bind(serde_json::to_string(settings)?);
+        execute(transaction, update).await?;
+        Ok(())
+    }
+
+    /// Loads the import map of the version, which is empty if the version was applied without
+    /// one.
+    pub async fn load_import_map(&self, version_id: &str) -> Result<ImportMap> {
+        let query =
+            sqlx::query("SELECT import_map FROM api_info WHERE api_version = $1").bind(version_id);
+        let rows = fetch_all(&self.db.pool, query).await?;
+        let imports: Option<String> = match rows.first() {
+            Some(row) => row.get("import_map"),
+            None => None,
+        };
+        match imports {
+            Some(imports) => {
+                let imports: HashMap<String, String> =
+                    serde_json::from_str(&imports).context("Invalid import map")?;
+                ImportMap::new(imports)
+            }
+            None => Ok(ImportMap::default()),
+        }
+    }
+
+    /// Stores the imports of the import map of the version. The version info must be persisted
+    /// first (see `persist_version_info()`).
+    pub async fn persist_import_map(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+        imports: &HashMap<String, String>,
+    ) -> Result<()> {
+        let update = sqlx::query("UPDATE api_info SET import_map = $2 WHERE api_version = $1")
+            .bind(version_id.to_owned())
+            .bind(serde_json::to_string(imports)?);
+        execute(transaction, update).await?;
+        Ok(())
+    }
+
     /// Load module source codes from metadata store.
     pub async fn load_modules(&self, version_id: &str) -> Result<HashMap<String, String>> {
         let query =
@@ -482,21 +949,72 @@ impl MetaService {
         Ok(())
     }
 
+    /// Loads the static files of a version, keyed by their path.
+    pub async fn load_static_files(&self, version_id: &str) -> Result<HashMap<String, Vec<u8>>> {
+        let query = sqlx::query("SELECT path, content FROM static_files WHERE version = $1")
+            .bind(version_id);
+        let rows = fetch_all(&self.db.pool, query).await?;
+        rows.into_iter()
+            .map(|row| {
+                let path: String = row.get("path");
+                let content: String = row.get("content");
+                let content = base64::decode(content)
+                    .with_context(|| format!("Invalid content of static file {:?}", path))?;
+                Ok((path, content))
+            })
+            .collect()
+    }
+
+    pub async fn persist_static_files(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+        files: &HashMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        let drop = sqlx::query("DELETE FROM static_files WHERE version = $1").bind(version_id);
+        execute(transaction, drop).await?;
+
+        for (path, content) in files.iter() {
+            let insert = sqlx::query(
+                "INSERT INTO static_files (version, path, content) VALUES ($1, $2, $3)",
+            )
+            .bind(version_id)
+            .bind(path)
+            .bind(base64::encode(content));
+            execute(transaction, insert).await?;
+        }
+        Ok(())
+    }
+
     /// Load the type systems for all versions from metadata store.
     pub async fn load_type_systems(
         &self,
         builtin: &Arc<BuiltinTypes>,
+    ) -> Result<HashMap<String, TypeSystem>> {
+        let mut transaction = self.begin_transaction().await?;
+        Self::load_type_systems_in(&mut transaction, builtin).await
+    }
+
+    /// Load the type systems for all versions, as seen by the given transaction.
+    ///
+    /// This is used by apply to obtain the ids of types that have been inserted in the
+    /// transaction, before it is committed.
+    pub async fn load_type_systems_in(
+        transaction: &mut Transaction<'_, Any>,
+        builtin: &Arc<BuiltinTypes>,
     ) -> Result<HashMap<String, TypeSystem>> {
         let query = sqlx::query(
             r#"
             SELECT
                 types.type_id AS type_id,
                 types.backing_table AS backing_table,
+                types.locked AS locked,
+                types.owned_by AS owned_by,
                 type_names.name AS type_name
             FROM types
             INNER JOIN type_names ON types.type_id = type_names.type_id"#,
         );
-        let rows = fetch_all(&self.db.pool, query).await?;
+        let rows = fetch_all(&mut **transaction, query).await?;
 
         let mut type_systems = HashMap::new();
         let mut failures = vec![];
@@ -510,11 +1028,16 @@ impl MetaService {
                 .entry(desc.version_id())
                 .or_insert_with(|| TypeSystem::new(builtin.clone(), desc.version_id()));
 
-            match self.load_type_fields(ts, type_id).await {
+            match Self::load_type_fields(transaction, ts, type_id).await {
                 Ok(fields) => {
-                    let indexes = self.load_type_indexes(type_id, backing_table).await?;
-
-                    let ty = ObjectType::new(&desc, fields, indexes)?;
+                    let indexes =
+                        Self::load_type_indexes(transaction, type_id, backing_table).await?;
+                    let aggregate = Self::load_type_aggregate(transaction, type_id).await?;
+
+                    let ty = ObjectType::new(&desc, fields, indexes)?
+                        .with_aggregate(aggregate)
+                        .with_locked(row.get("locked"))
+                        .with_owned_by(row.get("owned_by"));
                     ts.add_custom_type(Entity::Custom(Arc::new(ty)))?;
                 }
                 Err(_) => {
@@ -540,16 +1063,24 @@ impl MetaService {
                 .entry(desc.version_id())
                 .or_insert_with(|| TypeSystem::new(builtin.clone(), desc.version_id()));
 
-            let fields = self.load_type_fields(ts, type_id).await?;
-            let indexes = self.load_type_indexes(type_id, backing_table).await?;
-            let ty = ObjectType::new(&desc, fields, indexes)?;
+            let fields = Self::load_type_fields(transaction, ts, type_id).await?;
+            let indexes = Self::load_type_indexes(transaction, type_id, backing_table).await?;
+            let aggregate = Self::load_type_aggregate(transaction, type_id).await?;
+            let ty = ObjectType::new(&desc, fields, indexes)?
+                .with_aggregate(aggregate)
+                .with_locked(row.get("locked"))
+                .with_owned_by(row.get("owned_by"));
             ts.add_custom_type(Entity::Custom(Arc::new(ty)))?;
         }
 
         Ok(type_systems)
     }
 
-    async fn load_type_fields(&self, ts: &TypeSystem, type_id: i32) -> Result<Vec<Field>> {
+    async fn load_type_fields(
+        transaction: &mut Transaction<'_, Any>,
+        ts: &TypeSystem,
+        type_id: i32,
+    ) -> Result<Vec<Field>> {
         let query = sqlx::query(
             r#"
             SELECT
@@ -558,13 +1089,14 @@ impl MetaService {
                 fields.field_type AS field_type,
                 fields.default_value AS default_value,
                 fields.is_optional AS is_optional,
-                fields.is_unique AS is_unique
+                fields.is_unique AS is_unique,
+                fields.is_version AS is_version
             FROM field_names
             INNER JOIN fields
                 ON fields.type_id = $1 AND field_names.field_id = fields.field_id;"#,
         );
         let query = query.bind(type_id);
-        let rows = fetch_all(&self.db.pool, query).await?;
+        let rows = fetch_all(&mut **transaction, query).await?;
 
         let mut fields = Vec::new();
         for row in rows {
@@ -586,25 +1118,141 @@ impl MetaService {
             let field_def: Option<String> = row.get("default_value");
             let is_optional: bool = row.get("is_optional");
             let is_unique: bool = row.get("is_unique");
+            let is_version: bool = row.get("is_version");
 
             let labels_query =
                 sqlx::query("SELECT label_name FROM field_labels WHERE field_id = $1");
 
             let query = labels_query.bind(field_id);
 
-            let rows = fetch_all(&self.db.pool, query).await?;
+            let rows = fetch_all(&mut **transaction, query).await?;
 
             let labels = rows
                 .iter()
                 .map(|r| r.get("label_name"))
                 .collect::<Vec<String>>();
 
-            fields.push(Field::new(&desc, labels, field_def, is_optional, is_unique));
+            let count_query = sqlx::query(
+                "SELECT entity_name, counted_field FROM field_counts WHERE field_id = $1",
+            )
+            .bind(field_id);
+            let count = fetch_all(&mut **transaction, count_query)
+                .await?
+                .first()
+                .map(|r| CountSpec {
+                    entity: r.get("entity_name"),
+                    field: r.get("counted_field"),
+                });
+
+            let aggregate_query = sqlx::query(
+                "SELECT function, source_field FROM field_aggregates WHERE field_id = $1",
+            )
+            .bind(field_id);
+            let aggregate = match fetch_all(&mut **transaction, aggregate_query)
+                .await?
+                .first()
+            {
+                Some(r) => Some(AggregateFieldSpec {
+                    function: r.get::<&str, _>("function").parse()?,
+                    source_field: r.get("source_field"),
+                }),
+                None => None,
+            };
+
+            let timestamp_query =
+                sqlx::query("SELECT kind FROM field_timestamps WHERE field_id = $1").bind(field_id);
+            let timestamp = match fetch_all(&mut **transaction, timestamp_query)
+                .await?
+                .first()
+            {
+                Some(r) => Some(r.get::<&str, _>("kind").parse::<Timestamp>()?),
+                None => None,
+            };
+
+            let enum_query =
+                sqlx::query("SELECT name, enum_values FROM field_enums WHERE field_id = $1")
+                    .bind(field_id);
+            let enum_type = match fetch_all(&mut **transaction, enum_query).await?.first() {
+                Some(r) => Some(EnumSpec {
+                    name: r.get("name"),
+                    values: serde_json::from_str(r.get::<&str, _>("enum_values"))
+                        .context("Corrupted enum values")?,
+                }),
+                None => None,
+            };
+
+            let max_bytes_query =
+                sqlx::query("SELECT max_bytes FROM field_size_limits WHERE field_id = $1")
+                    .bind(field_id);
+            let max_bytes = match fetch_all(&mut **transaction, max_bytes_query)
+                .await?
+                .first()
+            {
+                Some(r) => Some(u64::try_from(r.get::<i64, _>("max_bytes"))?),
+                None => None,
+            };
+
+            let on_delete_query =
+                sqlx::query("SELECT action FROM field_on_delete WHERE field_id = $1")
+                    .bind(field_id);
+            let on_delete = match fetch_all(&mut **transaction, on_delete_query)
+                .await?
+                .first()
+            {
+                Some(r) => Some(r.get::<&str, _>("action").parse::<OnDelete>()?),
+                None => None,
+            };
+
+            let collation_query =
+                sqlx::query("SELECT collation FROM field_collations WHERE field_id = $1")
+                    .bind(field_id);
+            let collation = match fetch_all(&mut **transaction, collation_query)
+                .await?
+                .first()
+            {
+                Some(r) => Some(r.get::<&str, _>("collation").parse::<Collation>()?),
+                None => None,
+            };
+
+            fields.push(
+                Field::new(&desc, labels, field_def, is_optional, is_unique, is_version)
+                    .with_count(count)
+                    .with_aggregate(aggregate)
+                    .with_timestamp(timestamp)
+                    .with_enum_type(enum_type)
+                    .with_max_bytes(max_bytes)
+                    .with_on_delete(on_delete)
+                    .with_collation(collation),
+            );
         }
         Ok(fields)
     }
 
-    async fn load_type_indexes(&self, type_id: i32, backing_table: &str) -> Result<Vec<DbIndex>> {
+    async fn load_type_aggregate(
+        transaction: &mut Transaction<'_, Any>,
+        type_id: i32,
+    ) -> Result<Option<AggregateSpec>> {
+        let query = sqlx::query(
+            "SELECT source_entity, refresh_interval_s FROM type_aggregates WHERE type_id = $1",
+        )
+        .bind(type_id);
+        let aggregate = fetch_all(&mut **transaction, query)
+            .await?
+            .first()
+            .map(|r| AggregateSpec {
+                source: r.get("source_entity"),
+                refresh_interval_s: r
+                    .get::<Option<i64>, _>("refresh_interval_s")
+                    .map(|s| s as u64),
+            });
+        Ok(aggregate)
+    }
+
+    async fn load_type_indexes(
+        transaction: &mut Transaction<'_, Any>,
+        type_id: i32,
+        backing_table: &str,
+    ) -> Result<Vec<DbIndex>> {
         let query = sqlx::query(
             r#"
             SELECT
@@ -614,7 +1262,7 @@ impl MetaService {
             WHERE type_id = $1"#,
         )
         .bind(type_id);
-        let rows = fetch_all(&self.db.pool, query).await?;
+        let rows = fetch_all(&mut **transaction, query).await?;
 
         let mut indexes = vec![];
         for row in rows {
@@ -639,6 +1287,7 @@ impl MetaService {
         for field in ty.user_fields() {
             remove_field_query(transaction, field).await?;
         }
+        persist_type_aggregate(transaction, type_id, &None).await?;
 
         let del_type = sqlx::query("DELETE FROM types WHERE type_id = $1").bind(type_id);
         let del_type_name = sqlx::query("DELETE FROM type_names WHERE type_id = $1").bind(type_id);
@@ -649,6 +1298,65 @@ impl MetaService {
         Ok(())
     }
 
+    /// Renames the type `old` to `new_name`, keeping its id and backing table. `type_system` is
+    /// the type system after the rename (see `TypeSystem::rename_custom_type()`), the references
+    /// to the type in its fields and aggregates are persisted from it.
+    pub async fn rename_type(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        old: &ObjectType,
+        new_name: &str,
+        type_system: &TypeSystem,
+    ) -> Result<()> {
+        let type_id = old
+            .meta_id
+            .context("logical error. Trying to rename type without id")?;
+        let renamed = type_system.lookup_custom_type(new_name)?;
+
+        let query = sqlx::query("UPDATE type_names SET name = $1 WHERE type_id = $2")
+            .bind(renamed.persisted_name())
+            .bind(type_id);
+        execute(transaction, query).await?;
+        for field in renamed.user_fields() {
+            let field_id = field
+                .id
+                .context("logical error. Trying to rename field without id")?;
+            let query = sqlx::query("UPDATE field_names SET field_name = $1 WHERE field_id = $2")
+                .bind(field.persisted_name(&renamed))
+                .bind(field_id);
+            execute(transaction, query).await?;
+        }
+
+        for ty in type_system.custom_types.values() {
+            for field in ty.user_fields() {
+                let refers_to_type = match &field.type_id {
+                    TypeId::Entity { name, .. } | TypeId::EntityId(name) => name == new_name,
+                    _ => false,
+                };
+                let field_id = match field.id {
+                    Some(field_id) => field_id,
+                    None => continue,
+                };
+                if refers_to_type {
+                    let query =
+                        sqlx::query("UPDATE fields SET field_type = $1 WHERE field_id = $2")
+                            .bind(field.type_id.name())
+                            .bind(field_id);
+                    execute(transaction, query).await?;
+                }
+                if field.count.as_ref().map(|c| c.entity.as_str()) == Some(new_name) {
+                    persist_field_count(transaction, field_id, &field.count).await?;
+                }
+            }
+            if let (Some(aggregate), Some(type_id)) = (ty.aggregate(), ty.meta_id) {
+                if aggregate.source == new_name {
+                    persist_type_aggregate(transaction, type_id, &Some(aggregate.clone())).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn update_type(
         &self,
         transaction: &mut Transaction<'_, Any>,
@@ -667,20 +1375,39 @@ impl MetaService {
             update_field_query(transaction, field).await?;
         }
 
+        for rename in delta.renamed_fields.iter() {
+            let old_name = ty
+                .get_field(&rename.old_name)
+                .context("renamed field is missing from the old type")?
+                .persisted_name(ty);
+            // only the last part of `version.Type.field` changes
+            let (prefix, _) = old_name.rsplit_once('.').unwrap();
+            let new_name = format!("{}.{}", prefix, rename.new_name);
+            let query = sqlx::query("UPDATE field_names SET field_name = $1 WHERE field_id = $2")
+                .bind(new_name)
+                .bind(rename.id);
+            execute(transaction, query).await?;
+        }
+
         Self::delete_indexes(transaction, &delta.removed_indexes).await?;
 
-        Self::insert_indexes(
-            transaction,
-            ty.meta_id
-                .context("object must have an id when it's being updated")?,
-            &delta.added_indexes,
-        )
-        .await?;
+        let type_id = ty
+            .meta_id
+            .context("object must have an id when it's being updated")?;
+        Self::insert_indexes(transaction, type_id, &delta.added_indexes).await?;
+        persist_type_aggregate(transaction, type_id, &delta.aggregate).await?;
+
+        let update_locked =
+            sqlx::query("UPDATE types SET locked = $1, owned_by = $2 WHERE type_id = $3")
+                .bind(delta.locked)
+                .bind(delta.owned_by.clone())
+                .bind(type_id);
+        execute(transaction, update_locked).await?;
         Ok(())
     }
 
     pub async fn begin_transaction(&self) -> Result<Transaction<'_, Any>> {
-        Ok(self.db.pool.begin().await?)
+        self.db.begin().await
     }
 
     pub async fn commit_transaction(transaction: Transaction<'_, Any>) -> Result<()> {
@@ -711,27 +1438,20 @@ impl MetaService {
         Ok(())
     }
 
-    pub async fn delete_policy_version(
-        &self,
-        transaction: &mut Transaction<'_, Any>,
-        version_id: &str,
-    ) -> Result<()> {
-        let delete_policy =
-            sqlx::query("DELETE FROM policies WHERE version = $1").bind(version_id.to_owned());
-        execute(transaction, delete_policy).await?;
-        Ok(())
-    }
-
     /// Loads policy system for a version.
     ///
     /// Useful on startup, when we have to populate our in-memory state from the meta database.
     pub async fn load_policy_system(&self, version_id: &str) -> Result<PolicySystem> {
+        PolicySystem::from_yaml(&self.load_policy_yaml(version_id).await?)
+    }
+
+    /// Loads the YAML source of the policy system of a version.
+    pub async fn load_policy_yaml(&self, version_id: &str) -> Result<String> {
         let get_policy =
             sqlx::query("SELECT policy_str FROM policies WHERE version = $1").bind(version_id);
         let mut transaction = self.begin_transaction().await?;
         let row = fetch_one(&mut transaction, get_policy).await?;
-        let yaml: &str = row.get("policy_str");
-        PolicySystem::from_yaml(yaml)
+        Ok(row.get("policy_str"))
     }
 
     pub async fn persist_policy_sources(
@@ -772,6 +1492,545 @@ impl MetaService {
         }
     }
 
+    /// Loads the last offset committed by a Kafka consumer group for a topic partition.
+    pub async fn load_kafka_offset(
+        &self,
+        consumer_group: &str,
+        topic: &str,
+        partition: i32,
+    ) -> Result<Option<i64>> {
+        let query = sqlx::query(
+            r#"
+            SELECT "offset" FROM kafka_offsets
+            WHERE consumer_group = $1 AND topic = $2 AND partition = $3"#,
+        )
+        .bind(consumer_group)
+        .bind(topic)
+        .bind(partition);
+        let rows = fetch_all(&self.db.pool, query).await?;
+        Ok(rows.into_iter().next().map(|row| row.get("offset")))
+    }
+
+    /// Commits the offset of a Kafka event that has been handled by a consumer group.
+    ///
+    /// Offsets only move forward, committing an offset older than the current one is a no-op.
+    pub async fn persist_kafka_offset(
+        transaction: &mut Transaction<'_, Any>,
+        consumer_group: &str,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> Result<()> {
+        let upsert = sqlx::query(
+            r#"
+            INSERT INTO kafka_offsets (consumer_group, topic, partition, "offset")
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT(consumer_group, topic, partition) DO UPDATE SET "offset" = $4
+            WHERE kafka_offsets."offset" < $4"#,
+        )
+        .bind(consumer_group.to_owned())
+        .bind(topic.to_owned())
+        .bind(partition)
+        .bind(offset);
+        execute(transaction, upsert).await?;
+        Ok(())
+    }
+
+    /// Stages an apply request under `apply_id`, so that the apply can be resumed if it is
+    /// interrupted.
+    pub async fn stage_apply(
+        &self,
+        apply_id: &str,
+        version_id: &str,
+        request: &[u8],
+    ) -> Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let insert = sqlx::query(
+            r#"
+            INSERT INTO applies (apply_id, version_id, request, status, updated_at)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(apply_id.to_owned())
+        .bind(version_id.to_owned())
+        .bind(base64::encode(request))
+        .bind(ApplyStatus::Pending.as_str())
+        .bind(unix_timestamp());
+        execute(&mut transaction, insert)
+            .await
+            .with_context(|| format!("Could not stage apply {}", apply_id))?;
+        Self::commit_transaction(transaction).await
+    }
+
+    pub async fn load_staged_apply(&self, apply_id: &str) -> Result<Option<StagedApply>> {
+        let query = sqlx::query(
+            r#"
+            SELECT version_id, request, status, response, error FROM applies
+            WHERE apply_id = $1"#,
+        )
+        .bind(apply_id);
+        let rows = fetch_all(&self.db.pool, query).await?;
+        let row = match rows.into_iter().next() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let request: String = row.get("request");
+        let status: String = row.get("status");
+        let response: Option<String> = row.get("response");
+        Ok(Some(StagedApply {
+            version_id: row.get("version_id"),
+            request: base64::decode(request).context("Corrupted apply request")?,
+            status: ApplyStatus::parse(&status)?,
+            response: response
+                .map(base64::decode)
+                .transpose()
+                .context("Corrupted apply response")?,
+            error: row.get("error"),
+        }))
+    }
+
+    /// Marks a staged apply as applied, in the same transaction that applies it.
+    ///
+    /// Fails if the apply is no longer pending (i.e., if it has been finished by a concurrent
+    /// attempt), so that the apply is never performed twice.
+    pub async fn finish_staged_apply(
+        transaction: &mut Transaction<'_, Any>,
+        apply_id: &str,
+        response: &[u8],
+    ) -> Result<()> {
+        let update = sqlx::query(
+            r#"
+            UPDATE applies SET status = $1, response = $2, updated_at = $3
+            WHERE apply_id = $4 AND status = $5"#,
+        )
+        .bind(ApplyStatus::Applied.as_str())
+        .bind(base64::encode(response))
+        .bind(unix_timestamp())
+        .bind(apply_id.to_owned())
+        .bind(ApplyStatus::Pending.as_str());
+        let result = execute(transaction, update).await?;
+        anyhow::ensure!(
+            result.rows_affected() == 1,
+            "Apply {} is not pending anymore",
+            apply_id
+        );
+        Ok(())
+    }
+
+    /// Marks a staged apply as failed. Applies that are not pending are left untouched.
+    pub async fn fail_staged_apply(&self, apply_id: &str, error: &str) -> Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let update = sqlx::query(
+            r#"
+            UPDATE applies SET status = $1, error = $2, updated_at = $3
+            WHERE apply_id = $4 AND status = $5"#,
+        )
+        .bind(ApplyStatus::Failed.as_str())
+        .bind(error.to_owned())
+        .bind(unix_timestamp())
+        .bind(apply_id.to_owned())
+        .bind(ApplyStatus::Pending.as_str());
+        execute(&mut transaction, update).await?;
+        Self::commit_transaction(transaction).await
+    }
+
+    /// Removes the records of applies that have not been updated in the last `retention_s`
+    /// seconds, including pending applies that were never resumed. Returns the number of removed
+    /// applies.
+    pub async fn remove_stale_applies(&self, retention_s: u64) -> Result<u64> {
+        let mut transaction = self.begin_transaction().await?;
+        let delete = sqlx::query("DELETE FROM applies WHERE updated_at < $1")
+            .bind(unix_timestamp().saturating_sub(retention_s as i64));
+        let result = execute(&mut transaction, delete).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn persist_archived_entity(
+        transaction: &mut Transaction<'_, Any>,
+        archived: &ArchivedEntity,
+    ) -> Result<()> {
+        let insert = sqlx::query(
+            r#"
+            INSERT INTO archived_entities (table_name, version_id, entity_name, fields, archived_at)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(archived.table_name.clone())
+        .bind(archived.version_id.clone())
+        .bind(archived.entity_name.clone())
+        .bind(serde_json::to_string(&archived.fields)?)
+        .bind(archived.archived_at);
+        execute(transaction, insert).await?;
+        Ok(())
+    }
+
+    /// Loads the archived entities, the most recently archived first.
+    pub async fn load_archived_entities(&self) -> Result<Vec<ArchivedEntity>> {
+        let query = sqlx::query(
+            r#"
+            SELECT table_name, version_id, entity_name, fields, archived_at
+            FROM archived_entities
+            ORDER BY archived_at DESC"#,
+        );
+        let rows = fetch_all(&self.db.pool, query).await?;
+        rows.into_iter()
+            .map(|row| {
+                let fields: &str = row.get("fields");
+                Ok(ArchivedEntity {
+                    table_name: row.get("table_name"),
+                    version_id: row.get("version_id"),
+                    entity_name: row.get("entity_name"),
+                    fields: serde_json::from_str(fields).context("Corrupted archived entity")?,
+                    archived_at: row.get("archived_at"),
+                })
+            })
+            .collect()
+    }
+
+    pub async fn persist_audit_entry(
+        transaction: &mut Transaction<'_, Any>,
+        entry: &AuditEntry,
+    ) -> Result<()> {
+        let insert = sqlx::query(
+            r#"
+            INSERT INTO audit_log (version_id, actor, action, detail, created_at)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(entry.version_id.clone())
+        .bind(entry.actor.clone())
+        .bind(entry.action.clone())
+        .bind(entry.detail.clone())
+        .bind(entry.created_at);
+        execute(transaction, insert).await?;
+        Ok(())
+    }
+
+    /// Loads the entries of the audit log, the most recent first.
+    pub async fn load_audit_log(&self) -> Result<Vec<AuditEntry>> {
+        let query = sqlx::query(
+            r#"
+            SELECT version_id, actor, action, detail, created_at
+            FROM audit_log
+            ORDER BY id DESC"#,
+        );
+        let rows = fetch_all(&self.db.pool, query).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| AuditEntry {
+                version_id: row.get("version_id"),
+                actor: row.get("actor"),
+                action: row.get("action"),
+                detail: row.get("detail"),
+                created_at: row.get("created_at"),
+            })
+            .collect())
+    }
+
+    pub async fn remove_archived_entity(
+        transaction: &mut Transaction<'_, Any>,
+        table_name: &str,
+    ) -> Result<()> {
+        let delete = sqlx::query("DELETE FROM archived_entities WHERE table_name = $1")
+            .bind(table_name.to_owned());
+        execute(transaction, delete).await?;
+        Ok(())
+    }
+
+    /// Tries to acquire the apply lock for apply `apply_id`. Returns the current holder of the lock
+    /// if it is held by another apply.
+    ///
+    /// The lock is a single row in the `apply_lock` table, so it is shared by all servers that use
+    /// this database. It is not released automatically if the server crashes during an apply, so
+    /// it can be removed with `force_unlock_apply()`.
+    pub async fn try_lock_apply(
+        &self,
+        apply_id: &str,
+        holder: &str,
+    ) -> Result<Option<ApplyLockHolder>> {
+        loop {
+            let mut transaction = self.begin_transaction().await?;
+            let insert = sqlx::query(
+                r#"
+                INSERT INTO apply_lock (id, apply_id, holder, acquired_at)
+                VALUES (1, $1, $2, $3)
+                ON CONFLICT (id) DO NOTHING"#,
+            )
+            .bind(apply_id.to_owned())
+            .bind(holder.to_owned())
+            .bind(unix_timestamp());
+            let locked = execute(&mut transaction, insert).await?.rows_affected() == 1;
+            Self::commit_transaction(transaction).await?;
+            if locked {
+                return Ok(None);
+            }
+
+            match self.load_apply_lock().await? {
+                // the lock is reentrant, so that an apply that is resumed can lock again
+                Some(lock) if lock.apply_id == apply_id => return Ok(None),
+                Some(lock) => return Ok(Some(lock)),
+                // the lock was released in the meantime, try again
+                None => continue,
+            }
+        }
+    }
+
+    /// Locks the database for the rest of `transaction`, so that the migrations of two applies
+    /// never interleave, even if the apply lock was forcibly removed.
+    ///
+    /// On Postgres, this takes a transaction-level advisory lock, which is released when the
+    /// transaction ends. SQLite allows only one writing transaction at a time anyway.
+    pub async fn lock_apply_transaction(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+    ) -> Result<()> {
+        match self.db.pool.any_kind() {
+            AnyKind::Postgres => {
+                let lock = sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(APPLY_LOCK_KEY);
+                execute(transaction, lock).await?;
+            }
+            AnyKind::Sqlite => {}
+        }
+        Ok(())
+    }
+
+    pub async fn load_apply_lock(&self) -> Result<Option<ApplyLockHolder>> {
+        let query =
+            sqlx::query("SELECT apply_id, holder, acquired_at FROM apply_lock WHERE id = 1");
+        let rows = fetch_all(&self.db.pool, query).await?;
+        Ok(rows.into_iter().next().map(|row| ApplyLockHolder {
+            apply_id: row.get("apply_id"),
+            holder: row.get("holder"),
+            acquired_at: row.get("acquired_at"),
+        }))
+    }
+
+    pub async fn unlock_apply(&self, apply_id: &str) -> Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let delete = sqlx::query("DELETE FROM apply_lock WHERE id = 1 AND apply_id = $1")
+            .bind(apply_id.to_owned());
+        execute(&mut transaction, delete).await?;
+        Self::commit_transaction(transaction).await
+    }
+
+    /// Removes the apply lock, no matter who holds it. Returns the previous holder.
+    pub async fn force_unlock_apply(&self) -> Result<Option<ApplyLockHolder>> {
+        let current = self.load_apply_lock().await?;
+        let mut transaction = self.begin_transaction().await?;
+        execute(&mut transaction, sqlx::query("DELETE FROM apply_lock")).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(current)
+    }
+
+    /// Stores a new version of secret `name`. Unsetting a secret (`value` is `None`) is also a new
+    /// version, so that the history of the secret is kept.
+    pub async fn set_secret(&self, name: &str, value: Option<&serde_json::Value>) -> Result<i64> {
+        let mut transaction = self.begin_transaction().await?;
+        let query =
+            sqlx::query("SELECT MAX(version) AS version FROM secrets WHERE name = $1").bind(name);
+        let row = fetch_one(&mut transaction, query).await?;
+        let version = row.get::<Option<i64>, _>("version").unwrap_or(0) + 1;
+
+        let insert = sqlx::query(
+            r#"
+            INSERT INTO secrets (name, version, value, created_at)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(name.to_owned())
+        .bind(version)
+        .bind(value.map(|v| v.to_string()))
+        .bind(unix_timestamp());
+        execute(&mut transaction, insert).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(version)
+    }
+
+    /// Loads the latest version of every secret, including the unset secrets.
+    pub async fn load_secrets(&self) -> Result<Vec<StoredSecret>> {
+        let query = sqlx::query(
+            r#"
+            SELECT name, version, value, created_at FROM secrets AS s
+            WHERE version = (SELECT MAX(version) FROM secrets WHERE name = s.name)
+            ORDER BY name"#,
+        );
+        let rows = fetch_all(&self.db.pool, query).await?;
+        rows.into_iter()
+            .map(|row| {
+                let value = row
+                    .get::<Option<&str>, _>("value")
+                    .map(serde_json::from_str)
+                    .transpose()
+                    .context("Corrupted secret")?;
+                Ok(StoredSecret {
+                    name: row.get("name"),
+                    version: row.get("version"),
+                    value,
+                    updated_at: row.get("created_at"),
+                })
+            })
+            .collect()
+    }
+
+    /// Loads the names of the seed scripts that have been applied to a version.
+    pub async fn load_applied_seeds(&self, version_id: &str) -> Result<HashSet<String>> {
+        let query = sqlx::query("SELECT name FROM seeds_applied WHERE version_id = $1")
+            .bind(version_id.to_owned());
+        let rows = fetch_all(&self.db.pool, query).await?;
+        Ok(rows.into_iter().map(|row| row.get("name")).collect())
+    }
+
+    /// Records that the seed script `name` has been applied to a version.
+    pub async fn persist_applied_seed(&self, version_id: &str, name: &str) -> Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let insert = sqlx::query(
+            r#"
+            INSERT INTO seeds_applied (version_id, name, applied_at)
+            VALUES ($1, $2, $3)"#,
+        )
+        .bind(version_id.to_owned())
+        .bind(name.to_owned())
+        .bind(unix_timestamp());
+        execute(&mut transaction, insert).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(())
+    }
+
+    /// Loads the examples that were recorded from the traffic of a version (see `contract.rs`).
+    pub async fn load_route_examples(&self, version_id: &str) -> Result<Vec<RouteExample>> {
+        let query =
+            sqlx::query("SELECT example FROM route_examples WHERE version_id = $1 ORDER BY name")
+                .bind(version_id.to_owned());
+        let rows = fetch_all(&self.db.pool, query).await?;
+        rows.into_iter()
+            .map(|row| serde_json::from_str(row.get("example")).context("Corrupted route example"))
+            .collect()
+    }
+
+    /// Stores a recorded example, replacing the previous example with the same name.
+    pub async fn persist_route_example(
+        &self,
+        version_id: &str,
+        example: &RouteExample,
+    ) -> Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let upsert = sqlx::query(
+            r#"
+            INSERT INTO route_examples (version_id, name, example, recorded_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT(version_id, name) DO UPDATE SET example = $3, recorded_at = $4"#,
+        )
+        .bind(version_id.to_owned())
+        .bind(example.name.clone())
+        .bind(serde_json::to_string(example)?)
+        .bind(unix_timestamp());
+        execute(&mut transaction, upsert).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(())
+    }
+
+    /// Stores a login that was started with the OIDC provider (see `oidc.rs`), removing the
+    /// logins that were started before `expired_before` and never finished.
+    pub async fn persist_login_state(&self, login: &LoginState, expired_before: i64) -> Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let delete =
+            sqlx::query("DELETE FROM login_states WHERE created_at < $1").bind(expired_before);
+        execute(&mut transaction, delete).await?;
+        let insert = sqlx::query(
+            r#"
+            INSERT INTO login_states (state, nonce, redirect_to, created_at, code_verifier)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(login.state.clone())
+        .bind(login.nonce.clone())
+        .bind(login.redirect_to.clone())
+        .bind(login.created_at)
+        .bind(login.code_verifier.clone());
+        execute(&mut transaction, insert).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(())
+    }
+
+    /// Removes and returns the login with the given `state`, so that every login is finished at
+    /// most once.
+    pub async fn take_login_state(&self, state: &str) -> Result<Option<LoginState>> {
+        let mut transaction = self.begin_transaction().await?;
+        let query = sqlx::query(
+            r#"
+            SELECT state, nonce, redirect_to, created_at, code_verifier
+            FROM login_states WHERE state = $1"#,
+        )
+        .bind(state.to_owned());
+        let rows = fetch_all(&mut transaction, query).await?;
+        let delete =
+            sqlx::query("DELETE FROM login_states WHERE state = $1").bind(state.to_owned());
+        execute(&mut transaction, delete).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(rows.into_iter().next().map(|row| LoginState {
+            state: row.get("state"),
+            nonce: row.get("nonce"),
+            code_verifier: row
+                .get::<Option<String>, _>("code_verifier")
+                .unwrap_or_default(),
+            redirect_to: row.get("redirect_to"),
+            created_at: row.get("created_at"),
+        }))
+    }
+
+    /// Stores a session of a user that logged in with the OIDC provider, removing the sessions
+    /// that have expired. Only the hash of the session token is stored.
+    pub async fn persist_login_session(
+        &self,
+        token_hash: &str,
+        user_id: &str,
+        expires_at: i64,
+    ) -> Result<()> {
+        let now = unix_timestamp();
+        let mut transaction = self.begin_transaction().await?;
+        let delete = sqlx::query("DELETE FROM login_sessions WHERE expires_at <= $1").bind(now);
+        execute(&mut transaction, delete).await?;
+        let insert = sqlx::query(
+            r#"
+            INSERT INTO login_sessions (token_hash, user_id, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(token_hash.to_owned())
+        .bind(user_id.to_owned())
+        .bind(now)
+        .bind(expires_at);
+        execute(&mut transaction, insert).await?;
+        Self::commit_transaction(transaction).await?;
+        Ok(())
+    }
+
+    /// Returns the id of the user of the session whose token has the hash `token_hash`, `None`
+    /// if there is no such session or if it has expired.
+    pub async fn load_login_session_user(&self, token_hash: &str) -> Result<Option<String>> {
+        let query = sqlx::query(
+            "SELECT user_id FROM login_sessions WHERE token_hash = $1 AND expires_at > $2",
+        )
+        .bind(token_hash.to_owned())
+        .bind(unix_timestamp());
+        let rows = fetch_all(&self.db.pool, query).await?;
+        Ok(rows.into_iter().next().map(|row| row.get("user_id")))
+    }
+
+    /// Removes all rows of a version that was deleted, including its `api_info`, so that the
+    /// version is not started again.
+    pub async fn delete_version(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version_id: &str,
+    ) -> Result<()> {
+        for &(table, column) in gc::VERSION_TABLES.iter() {
+            let sql = format!("DELETE FROM {table} WHERE {column} = $1");
+            let delete = sqlx::query(&sql).bind(version_id.to_owned());
+            execute(transaction, delete).await?;
+        }
+        let delete =
+            sqlx::query("DELETE FROM api_info WHERE api_version = $1").bind(version_id.to_owned());
+        execute(transaction, delete).await?;
+        Ok(())
+    }
+
     pub(crate) async fn count_rows(
         &self,
         transaction: &mut Transaction<'_, Any>,
@@ -784,15 +2043,39 @@ impl MetaService {
         Ok(cnt)
     }
 
+    /// Counts the rows of `ty` by the value of the field `field_name`, skipping nulls.
+    pub(crate) async fn count_rows_by_value(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        field_name: &str,
+    ) -> anyhow::Result<Vec<(String, i64)>> {
+        let query = format!(
+            r#"SELECT "{field}" AS value, COUNT(*) AS count FROM "{table}" WHERE "{field}" IS NOT NULL GROUP BY "{field}""#,
+            field = field_name,
+            table = ty.backing_table(),
+        );
+        let rows = fetch_all(&mut **transaction, sqlx::query(&query)).await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("value"), row.get("count")))
+            .collect())
+    }
+
     pub async fn insert_type(
         &self,
         transaction: &mut Transaction<'_, Any>,
         ty: &ObjectType,
     ) -> Result<()> {
-        let add_type = sqlx::query("INSERT INTO types (backing_table) VALUES ($1) RETURNING *");
+        let add_type = sqlx::query(
+            "INSERT INTO types (backing_table, locked, owned_by) VALUES ($1, $2, $3) RETURNING *",
+        );
         let add_type_name = sqlx::query("INSERT INTO type_names (type_id, name) VALUES ($1, $2)");
 
-        let add_type = add_type.bind(ty.backing_table().to_owned());
+        let add_type = add_type
+            .bind(ty.backing_table().to_owned())
+            .bind(ty.is_locked())
+            .bind(ty.owned_by().map(ToOwned::to_owned));
         let row = fetch_one(transaction, add_type).await?;
 
         let id: i32 = row.get("type_id");
@@ -803,6 +2086,7 @@ impl MetaService {
             insert_field_query(transaction, ty, Some(id), field).await?;
         }
         Self::insert_indexes(transaction, id, ty.indexes()).await?;
+        persist_type_aggregate(transaction, id, &ty.aggregate().cloned()).await?;
         Ok(())
     }
 
@@ -977,4 +2261,312 @@ mod tests {
         fs::metadata(meta_path).await.unwrap();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn staged_apply() -> Result<()> {
+        let tmp_dir = TempDir::new("staged_apply")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        meta.stage_apply("a1", "dev", b"request").await?;
+        meta.stage_apply("a1", "dev", b"request").await.unwrap_err();
+        let staged = meta.load_staged_apply("a1").await?.unwrap();
+        assert_eq!(staged.version_id, "dev");
+        assert_eq!(staged.request, b"request");
+        assert_eq!(staged.status, ApplyStatus::Pending);
+
+        let mut transaction = meta.begin_transaction().await?;
+        MetaService::finish_staged_apply(&mut transaction, "a1", b"response").await?;
+        MetaService::commit_transaction(transaction).await?;
+
+        // the apply cannot be finished twice and finished applies cannot fail
+        let mut transaction = meta.begin_transaction().await?;
+        MetaService::finish_staged_apply(&mut transaction, "a1", b"other")
+            .await
+            .unwrap_err();
+        drop(transaction);
+        meta.fail_staged_apply("a1", "error").await?;
+
+        let staged = meta.load_staged_apply("a1").await?.unwrap();
+        assert_eq!(staged.status, ApplyStatus::Applied);
+        assert_eq!(staged.response.as_deref(), Some(&b"response"[..]));
+
+        meta.stage_apply("a2", "dev", b"request").await?;
+        meta.fail_staged_apply("a2", "error").await?;
+        let staged = meta.load_staged_apply("a2").await?.unwrap();
+        assert_eq!(staged.status, ApplyStatus::Failed);
+        assert_eq!(staged.error.as_deref(), Some("error"));
+
+        assert!(meta.load_staged_apply("a3").await?.is_none());
+        assert_eq!(meta.remove_stale_applies(3600).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archived_entities() -> Result<()> {
+        use crate::types::{NewField, NewObject, Type};
+
+        let tmp_dir = TempDir::new("archived_entities")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        let field = |name, ty, is_optional| {
+            Field::new(
+                &NewField::new(name, ty, "dev").unwrap(),
+                vec![],
+                None,
+                is_optional,
+                false,
+                false,
+            )
+        };
+        let fields = vec![
+            field("name", Type::String, false),
+            field("author", Type::EntityId("Person".into()), false),
+            field("tags", Type::Array(Box::new(Type::String)), true),
+        ];
+        let ty = ObjectType::new(&NewObject::new("Book", "dev"), fields, vec![])?;
+        let archived = ArchivedEntity::new(&ty, "__archived_books".into());
+
+        let mut transaction = meta.begin_transaction().await?;
+        MetaService::persist_archived_entity(&mut transaction, &archived).await?;
+        MetaService::commit_transaction(transaction).await?;
+
+        let loaded = meta.load_archived_entities().await?;
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].version_id, "dev");
+        assert_eq!(loaded[0].entity_name, "Book");
+        let type_names = loaded[0]
+            .fields
+            .iter()
+            .map(|f| f.type_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(type_names, ["string", "Id<Person>", "Array<string>"]);
+
+        let ty = loaded[0].object_type(&Arc::new(BuiltinTypes::new()))?;
+        assert_eq!(ty.name(), "Book");
+        assert_eq!(ty.backing_table(), "__archived_books");
+        assert_eq!(ty.user_fields().count(), 3);
+
+        let mut transaction = meta.begin_transaction().await?;
+        MetaService::remove_archived_entity(&mut transaction, "__archived_books").await?;
+        MetaService::commit_transaction(transaction).await?;
+        assert!(meta.load_archived_entities().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn audit_log() -> Result<()> {
+        let tmp_dir = TempDir::new("audit_log")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        let entry = |action: &str, created_at| AuditEntry {
+            version_id: "dev".into(),
+            actor: "alice@host".into(),
+            action: action.into(),
+            detail: "Invoice".into(),
+            created_at,
+        };
+        let mut transaction = meta.begin_transaction().await?;
+        MetaService::persist_audit_entry(&mut transaction, &entry("locked_change_rejected", 10))
+            .await?;
+        MetaService::persist_audit_entry(&mut transaction, &entry("locked_change", 10)).await?;
+        MetaService::commit_transaction(transaction).await?;
+
+        let loaded = meta.load_audit_log().await?;
+        let actions = loaded.iter().map(|e| e.action.as_str()).collect::<Vec<_>>();
+        assert_eq!(actions, ["locked_change", "locked_change_rejected"]);
+        assert_eq!(loaded[0].actor, "alice@host");
+        assert_eq!(loaded[0].detail, "Invoice");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn apply_lock() -> Result<()> {
+        let tmp_dir = TempDir::new("apply_lock")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        assert!(meta.try_lock_apply("a1", "alice@host").await?.is_none());
+        // the lock is reentrant
+        assert!(meta.try_lock_apply("a1", "alice@host").await?.is_none());
+        let holder = meta.try_lock_apply("a2", "bob@host").await?.unwrap();
+        assert_eq!(holder.apply_id, "a1");
+        assert_eq!(holder.holder, "alice@host");
+
+        // only the holder can unlock
+        meta.unlock_apply("a2").await?;
+        assert!(meta.try_lock_apply("a2", "bob@host").await?.is_some());
+        meta.unlock_apply("a1").await?;
+        assert!(meta.try_lock_apply("a2", "bob@host").await?.is_none());
+
+        let holder = meta.force_unlock_apply().await?.unwrap();
+        assert_eq!(holder.apply_id, "a2");
+        assert!(meta.load_apply_lock().await?.is_none());
+        assert!(meta.force_unlock_apply().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn secret_versions() -> Result<()> {
+        let tmp_dir = TempDir::new("secret_versions")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        assert!(meta.load_secrets().await?.is_empty());
+        let first = serde_json::json!("first");
+        let second = serde_json::json!({"key": 2});
+        assert_eq!(meta.set_secret("a", Some(&first)).await?, 1);
+        assert_eq!(meta.set_secret("a", Some(&second)).await?, 2);
+        assert_eq!(meta.set_secret("b", Some(&first)).await?, 1);
+        assert_eq!(meta.set_secret("b", None).await?, 2);
+
+        let secrets = meta.load_secrets().await?;
+        assert_eq!(secrets.len(), 2);
+        assert_eq!(secrets[0].name, "a");
+        assert_eq!(secrets[0].version, 2);
+        assert_eq!(secrets[0].value, Some(second));
+        assert_eq!(secrets[1].name, "b");
+        assert_eq!(secrets[1].version, 2);
+        assert_eq!(secrets[1].value, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn applied_seeds() -> Result<()> {
+        let tmp_dir = TempDir::new("applied_seeds")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        meta.persist_applied_seed("dev", "001_users").await?;
+        meta.persist_applied_seed("dev", "002_posts").await?;
+        meta.persist_applied_seed("prod", "001_users").await?;
+        // a seed is applied only once per version
+        assert!(meta.persist_applied_seed("dev", "001_users").await.is_err());
+
+        let applied = meta.load_applied_seeds("dev").await?;
+        assert_eq!(applied.len(), 2);
+        assert!(applied.contains("002_posts"));
+
+        let mut transaction = meta.begin_transaction().await?;
+        meta.delete_version(&mut transaction, "dev").await?;
+        MetaService::commit_transaction(transaction).await?;
+        assert!(meta.load_applied_seeds("dev").await?.is_empty());
+        assert_eq!(meta.load_applied_seeds("prod").await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn route_examples() -> Result<()> {
+        let tmp_dir = TempDir::new("route_examples")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        let example = |path: &str| RouteExample {
+            name: "GET /books/:id".into(),
+            method: "GET".into(),
+            path: path.into(),
+            headers: vec![],
+            body: None,
+            status: 200,
+            response: None,
+            recorded: true,
+        };
+        meta.persist_route_example("dev", &example("/books/1"))
+            .await?;
+        // an example replaces the previous example with the same name
+        meta.persist_route_example("dev", &example("/books/2"))
+            .await?;
+        meta.persist_route_example("prod", &example("/books/3"))
+            .await?;
+
+        let examples = meta.load_route_examples("dev").await?;
+        assert_eq!(examples, vec![example("/books/2")]);
+
+        let mut transaction = meta.begin_transaction().await?;
+        meta.delete_version(&mut transaction, "dev").await?;
+        MetaService::commit_transaction(transaction).await?;
+        assert!(meta.load_route_examples("dev").await?.is_empty());
+        assert_eq!(meta.load_route_examples("prod").await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn collect_garbage() -> Result<()> {
+        let tmp_dir = TempDir::new("collect_garbage")?;
+        let path = tmp_dir.path().join("chisel.db");
+        let conn_str = format!("sqlite://{}?mode=rwc", path.display());
+        let conn = Arc::new(DbConnection::connect(&conn_str, 1).await?);
+        let meta = MetaService::new(conn);
+        meta.migrate_schema().await?;
+
+        let info = VersionInfo {
+            name: "app".into(),
+            tag: "".into(),
+        };
+        let modules = HashMap::from([("file:///__root.ts".to_owned(), "x".repeat(10000))]);
+        let mut transaction = meta.begin_transaction().await?;
+        meta.persist_version_info(&mut transaction, "dev", &info)
+            .await?;
+        for version_id in ["dev", "running", "deleted"] {
+            meta.persist_modules(&mut transaction, version_id, &modules)
+                .await?;
+        }
+        for (version_id, created_at) in [("dev", 0), ("dev", unix_timestamp())] {
+            let entry = AuditEntry {
+                version_id: version_id.into(),
+                actor: "alice@host".into(),
+                action: "apply".into(),
+                detail: "".into(),
+                created_at,
+            };
+            MetaService::persist_audit_entry(&mut transaction, &entry).await?;
+        }
+        MetaService::commit_transaction(transaction).await?;
+
+        let live_versions = HashSet::from(["running".to_owned()]);
+        let retention = GcRetention {
+            audit_retention_s: 3600,
+            apply_retention_s: 3600,
+        };
+        let expected = vec![("modules".to_owned(), 1), ("audit_log".to_owned(), 1)];
+        let report = meta
+            .collect_garbage(&live_versions, retention, true)
+            .await?;
+        assert_eq!(report.removed_rows, expected);
+        assert_eq!(meta.load_modules("deleted").await?.len(), 1);
+
+        let report = meta
+            .collect_garbage(&live_versions, retention, false)
+            .await?;
+        assert_eq!(report.removed_rows, expected);
+        assert!(report.reclaimed_bytes.unwrap() > 0);
+        assert!(meta.load_modules("deleted").await?.is_empty());
+        assert_eq!(meta.load_modules("dev").await?.len(), 1);
+        assert_eq!(meta.load_modules("running").await?.len(), 1);
+        assert_eq!(meta.load_audit_log().await?.len(), 1);
+        Ok(())
+    }
 }
@@ -16,6 +16,12 @@ pub enum ApiInfo {
     ApiVersion,
     AppName,
     VersionTag,
+    /// JSON array of the hosts that the version may connect to.
+    AllowedHosts,
+    /// JSON object with the worker pool settings of the version (`WorkerPoolOverrides`).
+    WorkerPool,
+    /// JSON object with the imports of the import map of the version (see `ImportMap`).
+    ImportMap,
 }
 
 #[derive(Iden)]
@@ -24,6 +30,8 @@ pub enum Types {
     TypeId,
     BackingTable,
     ApiVersion,
+    Locked,
+    OwnedBy,
 }
 
 #[derive(Iden)]
@@ -42,6 +50,7 @@ pub enum Fields {
     DefaultValue,
     IsOptional,
     IsUnique,
+    IsVersion,
 }
 
 #[derive(Iden)]
@@ -58,6 +67,66 @@ pub enum FieldLabels {
     FieldId,
 }
 
+#[derive(Iden)]
+pub enum FieldCounts {
+    Table,
+    FieldId,
+    EntityName,
+    CountedField,
+}
+
+#[derive(Iden)]
+pub enum FieldAggregates {
+    Table,
+    FieldId,
+    Function,
+    SourceField,
+}
+
+#[derive(Iden)]
+pub enum FieldTimestamps {
+    Table,
+    FieldId,
+    Kind,
+}
+
+#[derive(Iden)]
+pub enum FieldEnums {
+    Table,
+    FieldId,
+    Name,
+    EnumValues,
+}
+
+#[derive(Iden)]
+pub enum FieldSizeLimits {
+    Table,
+    FieldId,
+    MaxBytes,
+}
+
+#[derive(Iden)]
+pub enum FieldOnDelete {
+    Table,
+    FieldId,
+    Action,
+}
+
+#[derive(Iden)]
+pub enum FieldCollations {
+    Table,
+    FieldId,
+    Collation,
+}
+
+#[derive(Iden)]
+pub enum TypeAggregates {
+    Table,
+    TypeId,
+    SourceEntity,
+    RefreshIntervalS,
+}
+
 #[derive(Iden)]
 pub enum Indexes {
     Table,
@@ -108,3 +177,107 @@ pub enum PolicyStore {
     Version,
     Store,
 }
+
+#[derive(Iden)]
+pub enum KafkaOffsets {
+    Table,
+    ConsumerGroup,
+    Topic,
+    Partition,
+    Offset,
+}
+
+#[derive(Iden)]
+pub enum Applies {
+    Table,
+    ApplyId,
+    VersionId,
+    Request,
+    Status,
+    Response,
+    Error,
+    UpdatedAt,
+}
+
+#[derive(Iden)]
+pub enum ArchivedEntities {
+    Table,
+    TableName,
+    VersionId,
+    EntityName,
+    Fields,
+    ArchivedAt,
+}
+
+#[derive(Iden)]
+pub enum Secrets {
+    Table,
+    Name,
+    Version,
+    Value,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+pub enum ApplyLock {
+    Table,
+    Id,
+    ApplyId,
+    Holder,
+    AcquiredAt,
+}
+
+#[derive(Iden)]
+pub enum SeedsApplied {
+    Table,
+    VersionId,
+    Name,
+    AppliedAt,
+}
+
+#[derive(Iden)]
+pub enum RouteExamples {
+    Table,
+    VersionId,
+    Name,
+    Example,
+    RecordedAt,
+}
+
+#[derive(Iden)]
+pub enum AuditLog {
+    Table,
+    Id,
+    VersionId,
+    Actor,
+    Action,
+    Detail,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+pub enum LoginStates {
+    Table,
+    State,
+    Nonce,
+    RedirectTo,
+    CreatedAt,
+    CodeVerifier,
+}
+
+#[derive(Iden)]
+pub enum StaticFiles {
+    Table,
+    Version,
+    Path,
+    Content,
+}
+
+#[derive(Iden)]
+pub enum LoginSessions {
+    Table,
+    TokenHash,
+    UserId,
+    CreatedAt,
+    ExpiresAt,
+}
@@ -52,22 +52,39 @@
 //! object instead and returns a `QueryResults` object, which represents a
 //! stream of query results with *policies applied*.
 
+pub mod aggregate;
 pub mod crud;
+pub mod db_metrics;
 mod dbconn;
+pub mod decimal;
+pub mod diff;
 pub mod engine;
 pub mod expr;
 mod filter;
+pub mod ingest;
+pub mod kv;
 pub mod meta;
 pub mod query;
+pub mod query_cache;
+pub mod query_stats;
+pub mod raw_sql;
+pub mod tenancy;
+pub mod transfer;
+pub mod txn_stats;
 pub mod value;
 
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
 use std::sync::Arc;
 
 use anyhow::Context;
-pub use dbconn::DbConnection;
+pub use dbconn::{DbConnection, DbPoolOptions};
 pub use engine::QueryEngine;
-pub use meta::MetaService;
+pub use meta::{
+    ApplyStatus, ArchivedEntity, AuditEntry, GcReport, GcRetention, LoginState, MetaService,
+    StoredSecret,
+};
 
 use crate::ops::job_context::JobInfo;
 use crate::policies::PolicySystem;
@@ -75,16 +92,30 @@ use crate::policy::PolicyContext;
 use crate::types::TypeSystem;
 
 use self::engine::TransactionStatic;
+use self::query_cache::QueryCache;
+use self::txn_stats::TxnGuard;
 
 pub struct DataContext {
     pub type_system: Arc<TypeSystem>,
     pub policy_system: Arc<PolicySystem>,
     pub job_info: Rc<JobInfo>,
     pub policy_context: Rc<PolicyContext>,
+    /// Key of the hashes of the `hash` transform of labels.
+    pub anonymization_key: Option<Arc<str>>,
     pub txn: TransactionStatic,
+    pub query_cache: Arc<QueryCache>,
+    /// Backing tables that were mutated in this transaction. Cached queries that read these tables
+    /// are invalidated when the transaction is committed.
+    pub written_tables: RefCell<HashSet<String>>,
+    /// Registration of the transaction in the statistics reported on the internal routes.
+    pub txn_guard: TxnGuard,
 }
 
 impl DataContext {
+    pub fn mark_written(&self, tables: impl IntoIterator<Item = String>) {
+        self.written_tables.borrow_mut().extend(tables);
+    }
+
     pub async fn commit(self) -> anyhow::Result<()> {
         let transaction = Arc::try_unwrap(self.txn)
             .ok()
@@ -93,7 +124,12 @@ impl DataContext {
             in progress that uses this transaction",
             )?
             .into_inner();
-        QueryEngine::commit_transaction(transaction).await?;
+        let result = QueryEngine::commit_transaction(transaction).await;
+        // if the commit failed, we don't know whether the changes were applied
+        self.query_cache
+            .invalidate(self.written_tables.borrow().iter());
+        result?;
+        self.txn_guard.commit();
 
         Ok(())
     }
@@ -148,7 +184,7 @@ mod test {
 
     pub fn make_field(name: &str, ty: Type) -> Field {
         let desc = types::NewField::new(name, ty, VERSION).unwrap();
-        Field::new(&desc, vec![], None, false, false)
+        Field::new(&desc, vec![], None, false, false, false)
     }
 
     pub static PERSON_TY: Lazy<Entity> = Lazy::new(|| {
@@ -193,6 +229,14 @@ mod test {
                 headers,
                 response_tx: Default::default(),
                 authentication: Authentication::None,
+                hook_authentication: Default::default(),
+                sandbox: false,
+                admin: false,
+                policy_trace: None,
+                body_stream: Default::default(),
+                request_ctx: Default::default(),
+                otel_cx: Default::default(),
+                cancel: Default::default(),
             });
             let policy_context = PolicyContext {
                 cache: Default::default(),
@@ -204,6 +248,7 @@ mod test {
                     Arc::new(TYPE_SYSTEM.clone()),
                     Default::default(),
                     policy_context,
+                    None,
                     job_info.clone(),
                 )
                 .await
@@ -228,6 +273,9 @@ mod test {
                     type_name: entity_name.to_owned(),
                     url_path: url.path().to_owned(),
                     url_query: url.query_pairs().into_owned().collect(),
+                    page_size: None,
+                    max_page_size: None,
+                    allow_full_scan: false,
                 },
             )
             .await
@@ -1,19 +1,27 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{self, Write};
 use std::sync::Arc;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, ensure, Context, Result};
 use enum_as_inner::EnumAsInner;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::authorization::AUTH_USER_NAME;
-use crate::datastore::expr::{BinaryExpr, Expr, PropertyAccess, Value as ExprValue};
+use crate::datastore::aggregate;
+use crate::datastore::decimal;
+use crate::datastore::expr::{BinaryExpr, BinaryOp, Expr, PropertyAccess, Value as ExprValue};
 use crate::datastore::filter;
+use crate::datastore::query_stats;
+use crate::datastore::tenancy::TenantFilter;
 use crate::feat_typescript_policies;
+use crate::ops::job_context::OwnerScope;
+use crate::policies::FieldTransform;
 use crate::policy::PolicyContext;
-use crate::types::{Entity, Field, ObjectType, Type, TypeId};
+use crate::types::{
+    Aggregator, Counter, Entity, Field, ObjectType, OnDelete, Reference, Type, TypeId,
+};
 
 use super::value::EntityValue;
 use super::DataContext;
@@ -53,7 +61,7 @@ pub enum QueryField {
         /// the database.
         column_idx: usize,
         /// Policy transformation to be applied on the resulting JSON value.
-        transform: Option<fn(EntityValue) -> EntityValue>,
+        transform: Option<FieldTransform>,
         /// Do not include field in return json
         keep_or_omit: KeepOrOmitField,
     },
@@ -62,7 +70,7 @@ pub enum QueryField {
         name: String,
         is_optional: bool,
         /// Policy transformation to be applied on the resulting JSON value.
-        transform: Option<fn(EntityValue) -> EntityValue>,
+        transform: Option<FieldTransform>,
         /// Do not include field in return json
         keep_or_omit: KeepOrOmitField,
         /// Fields to be retrieved from the entity.
@@ -70,6 +78,14 @@ pub enum QueryField {
     },
 }
 
+impl QueryField {
+    pub fn name(&self) -> &str {
+        match self {
+            QueryField::Scalar { name, .. } | QueryField::Entity { name, .. } => name,
+        }
+    }
+}
+
 /// `Query` is a structure that represents an executable query.
 ///
 /// A query represents a full query including filtering, projection, joins,
@@ -82,11 +98,6 @@ pub struct Query {
     /// Fields that are being retrieved. Contains information necessary to reconstruct
     /// the JSON response.
     pub fields: Vec<QueryField>,
-    /// Entity fields selected by the user. This field is used to post-filter fields that
-    /// shall be returned to the user in JSON.
-    /// FIXME: The post-filtering is suboptimal solution and selection should happen when
-    /// we build the raw_sql query.
-    pub allowed_fields: Option<HashSet<String>>,
 }
 
 /// QueriedEntity represents queried Entity of type `ty` which is to be aliased as
@@ -133,6 +144,67 @@ pub struct SortBy {
     pub keys: Vec<SortKey>,
 }
 
+/// Maximum nesting of the relations that `with()` loads, which bounds the number of joins for
+/// self-referential entities such as `Employee.manager: Id<Employee>`.
+pub const MAX_RELATION_DEPTH: usize = 5;
+
+/// Relations that are loaded together with the queried entity by `with()`, such as
+/// `{ author: { company: true } }`. The keys are fields of type `Id<Entity>` (whose entity is
+/// then joined instead of returning the id) or nested entity fields, and the values are the
+/// relations to load from the related entity.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "serde_json::Value")]
+pub struct RelationSelection(BTreeMap<String, RelationSelection>);
+
+impl RelationSelection {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, field_name: &str) -> Option<&RelationSelection> {
+        self.0.get(field_name)
+    }
+
+    /// Number of levels of the nested relations.
+    pub fn depth(&self) -> usize {
+        self.0
+            .values()
+            .map(|nested| nested.depth() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn merge(&mut self, other: &RelationSelection) {
+        for (field_name, nested) in other.0.iter() {
+            self.0.entry(field_name.clone()).or_default().merge(nested);
+        }
+    }
+}
+
+impl TryFrom<serde_json::Value> for RelationSelection {
+    type Error = anyhow::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self> {
+        let object = match value {
+            serde_json::Value::Object(object) => object,
+            value => anyhow::bail!("relations must be an object, got {value}"),
+        };
+        let mut relations = BTreeMap::new();
+        for (field_name, nested) in object {
+            let nested = match nested {
+                serde_json::Value::Bool(false) => continue,
+                serde_json::Value::Bool(true) => RelationSelection::default(),
+                nested @ serde_json::Value::Object(_) => nested.try_into()?,
+                nested => anyhow::bail!(
+                    "relation `{field_name}` must be `true` or an object, got {nested}"
+                ),
+            };
+            relations.insert(field_name, nested);
+        }
+        Ok(RelationSelection(relations))
+    }
+}
+
 /// Operators used to mutate the result set.
 #[cfg_attr(test, derive(PartialEq))]
 #[derive(Debug, Clone, EnumAsInner)]
@@ -149,6 +221,8 @@ pub enum QueryOp {
     SortBy(SortBy),
     /// Counts the elements.
     Count,
+    /// Loads the `relations` together with the elements.
+    With { relations: RelationSelection },
 }
 
 struct Column {
@@ -207,13 +281,18 @@ pub struct QueryPlan {
     /// Entity object representing entity that is being retrieved along with necessary joins
     /// and nested entities
     entity: QueriedEntity,
-    /// List of fields to be returned to the user.
+    /// List of fields to be returned to the user (from `select()`). Only the columns of these
+    /// fields are selected by the outermost SQL query.
     allowed_fields: Option<HashSet<String>>,
     /// Counts the total number of joins the builder encountered. It's used to
     /// uniquely identify joined tables.
     join_counter: usize,
     /// Operators used to mutate the result set.
     operators: Vec<QueryOp>,
+    /// Tenant whose rows are read from the tables of the queried and joined entities.
+    tenant: TenantFilter,
+    /// OpenTelemetry context that the span of the query is a child of.
+    otel_cx: opentelemetry::Context,
 }
 
 impl QueryPlan {
@@ -229,6 +308,8 @@ impl QueryPlan {
             allowed_fields: None,
             join_counter: 0,
             operators: vec![],
+            tenant: TenantFilter::All,
+            otel_cx: opentelemetry::Context::new(),
         }
     }
 
@@ -236,6 +317,32 @@ impl QueryPlan {
         &self.entity.ty
     }
 
+    pub fn otel_context(&self) -> &opentelemetry::Context {
+        &self.otel_cx
+    }
+
+    /// Fields of the base entity that an index could serve for this query (see
+    /// `query_stats.rs`).
+    pub fn index_fields(&self) -> Vec<String> {
+        query_stats::index_fields(&self.operators)
+    }
+
+    /// Backing tables of all entities that the query reads (the base entity and the joined
+    /// entities).
+    pub fn backing_tables(&self) -> Vec<String> {
+        fn gather(entity: &QueriedEntity, tables: &mut Vec<String>) {
+            tables.push(entity.ty.backing_table().to_owned());
+            for join in entity.joins.values() {
+                gather(&join.entity, tables);
+            }
+        }
+        let mut tables = Vec::new();
+        gather(&self.entity, &mut tables);
+        tables.sort_unstable();
+        tables.dedup();
+        tables
+    }
+
     /// Constructs a query builder ready to build an expression querying all fields of a
     /// given type `ty`. This is done in a shallow manner. Columns representing foreign
     /// key are returned as string, not as the related Entity.
@@ -254,7 +361,11 @@ impl QueryPlan {
         builder
     }
 
-    fn from_entity_name(ctx: &DataContext, entity_name: &str) -> Result<Self> {
+    fn from_entity_name(
+        ctx: &DataContext,
+        entity_name: &str,
+        relations: &RelationSelection,
+    ) -> Result<Self> {
         let ty = ctx
             .type_system
             .lookup_entity(entity_name)
@@ -263,7 +374,8 @@ impl QueryPlan {
             })?;
 
         let mut builder = Self::new(ty.clone());
-        builder.entity = builder.load_entity(ctx, &ty)?;
+        builder.entity = builder.load_entity(ctx, &ty, relations)?;
+        builder.otel_cx = ctx.job_info.otel_context();
         Ok(builder)
     }
 
@@ -271,8 +383,9 @@ impl QueryPlan {
     /// `operators.
     pub fn from_ops(ctx: &DataContext, ty: &Entity, operators: Vec<QueryOp>) -> Result<Self> {
         let mut query_plan = Self::new(ty.clone());
-        query_plan.entity = query_plan.load_entity(ctx, ty)?;
+        query_plan.entity = query_plan.load_entity(ctx, ty, &RelationSelection::default())?;
         query_plan.extend_operators(operators);
+        query_plan.otel_cx = ctx.job_info.otel_context();
         Ok(query_plan)
     }
 
@@ -280,8 +393,22 @@ impl QueryPlan {
     /// additional helper data like `ps`, `version_id`,
     /// `userid` and `path` (url path used for policy evaluation).
     pub fn from_op_chain(ctx: &DataContext, op_chain: QueryOpChain) -> Result<Self> {
-        let (entity_name, operators) = convert_ops(op_chain)?;
-        let mut builder = Self::from_entity_name(ctx, &entity_name)?;
+        let (entity_name, mut operators) = convert_ops(op_chain)?;
+        let mut relations = RelationSelection::default();
+        for op in operators.iter() {
+            if let QueryOp::With {
+                relations: selected,
+            } = op
+            {
+                relations.merge(selected);
+            }
+        }
+        operators.retain(|op| !matches!(op, QueryOp::With { .. }));
+        ensure!(
+            relations.depth() <= MAX_RELATION_DEPTH,
+            "with() can load relations at most {MAX_RELATION_DEPTH} levels deep"
+        );
+        let mut builder = Self::from_entity_name(ctx, &entity_name, &relations)?;
 
         builder.extend_operators(operators);
         Ok(builder)
@@ -314,7 +441,7 @@ impl QueryPlan {
         &mut self,
         field: &Field,
         table_name: &str,
-        transform: Option<fn(EntityValue) -> EntityValue>,
+        transform: Option<FieldTransform>,
         keep_or_omit: &KeepOrOmitField,
     ) -> QueryField {
         let column_idx = self.columns.len();
@@ -335,29 +462,60 @@ impl QueryPlan {
     }
 
     /// Prepares the retrieval of Entity of type `ty` from the database and
-    /// ensures login restrictions are respected.
-    fn load_entity(&mut self, ctx: &DataContext, ty: &Entity) -> anyhow::Result<QueriedEntity> {
+    /// ensures login, owner and tenant restrictions are respected.
+    fn load_entity(
+        &mut self,
+        ctx: &DataContext,
+        ty: &Entity,
+        relations: &RelationSelection,
+    ) -> anyhow::Result<QueriedEntity> {
         if feat_typescript_policies() {
             self.add_read_filters(&ctx.policy_context, ty.object_type())?;
         }
         self.add_login_filters_recursive(ctx, ty.object_type(), Expr::Parameter { position: 0 })?;
-        self.load_entity_recursive(ctx, ty, ty.backing_table())
+        self.add_owner_filter(ctx, ty.object_type());
+        self.tenant = ctx.job_info.tenant_scope().into();
+        self.load_entity_recursive(ctx, ty, ty.backing_table(), relations)
     }
 
     /// Loads QueriedEntity for a given type `ty` to be retrieved from the
     /// database. For fields that represent a nested Entity a join is
-    /// generated and we attempt to retrieve them recursively as well.
+    /// generated and we attempt to retrieve them recursively as well. The same
+    /// is done for `Id<Entity>` fields that are selected in `relations`.
     fn load_entity_recursive(
         &mut self,
         ctx: &DataContext,
         ty: &Entity,
         current_table: &str,
+        relations: &RelationSelection,
     ) -> anyhow::Result<QueriedEntity> {
+        for field_name in relations.0.keys() {
+            ensure!(
+                matches!(
+                    ty.get_field(field_name).map(|field| &field.type_id),
+                    Some(TypeId::Entity { .. } | TypeId::EntityId(_))
+                ),
+                "with(): entity `{}` has no relation `{field_name}`",
+                ty.name(),
+            );
+        }
+
         let field_policies = ctx.policy_system.make_field_policies(
             ctx.job_info.user_id(),
             ctx.job_info.path().unwrap_or_default(),
             ty,
+            ctx.anonymization_key.as_ref(),
         );
+        if let Some(trace) = ctx.job_info.policy_trace() {
+            trace.record(ty.name(), |trace| {
+                trace
+                    .transformed_fields
+                    .extend(field_policies.transforms.keys().cloned());
+                trace
+                    .omitted_fields
+                    .extend(field_policies.omit.iter().cloned());
+            });
+        }
 
         let mut fields = vec![];
         let mut joins = HashMap::default();
@@ -369,8 +527,16 @@ impl QueryPlan {
             };
 
             let ty = ctx.type_system.get(&field.type_id)?;
+            let nested_relations = relations.get(&field.name);
+            let nested_ty = match &ty {
+                Type::Entity(nested_ty) => Some(nested_ty.clone()),
+                Type::EntityId(entity_name) if nested_relations.is_some() => {
+                    Some(ctx.type_system.lookup_entity(entity_name)?)
+                }
+                _ => None,
+            };
 
-            let query_field = if let Type::Entity(nested_ty) = &ty {
+            let query_field = if let Some(nested_ty) = &nested_ty {
                 let nested_table = format!(
                     "JOIN{}_{}_TO_{}",
                     self.join_counter,
@@ -383,10 +549,16 @@ impl QueryPlan {
 
                 self.make_scalar_field(field, current_table, field_policy, &keep_or_omit);
 
-                let entity = self.load_entity_recursive(ctx, nested_ty, &nested_table)?;
+                let entity = self.load_entity_recursive(
+                    ctx,
+                    nested_ty,
+                    &nested_table,
+                    nested_relations.unwrap_or(&RelationSelection::default()),
+                )?;
                 let entity_field = QueryField::Entity {
                     name: field.name.clone(),
-                    is_optional: field.is_optional,
+                    // the entity that an id refers to is not guaranteed to exist
+                    is_optional: field.is_optional || matches!(ty, Type::EntityId(_)),
                     transform: field_policy,
                     keep_or_omit,
                     fields: entity.fields.clone(),
@@ -440,6 +612,7 @@ impl QueryPlan {
             ctx.job_info.user_id(),
             ctx.job_info.path().unwrap_or_default(),
             ty,
+            ctx.anonymization_key.as_ref(),
         );
         let user_id: ExprValue = match &field_policies.current_userid {
             None => "NULL",
@@ -447,6 +620,7 @@ impl QueryPlan {
         }
         .into();
 
+        let entity_name = ty.name();
         for field in ty.all_fields() {
             let ty = ctx.type_system.get(&field.type_id)?;
             if let Type::Entity(nested_ty) = &ty {
@@ -456,6 +630,11 @@ impl QueryPlan {
                 };
                 if nested_ty.name() == AUTH_USER_NAME {
                     if field_policies.match_login.contains(&field.name) {
+                        if let Some(trace) = ctx.job_info.policy_trace() {
+                            trace.record(entity_name, |trace| {
+                                trace.login_fields.insert(field.name.clone());
+                            });
+                        }
                         let expr = BinaryExpr::eq(property_access.into(), user_id.clone().into());
                         self.operators.push(QueryOp::Filter { expression: expr });
                     }
@@ -472,6 +651,28 @@ impl QueryPlan {
         Ok(())
     }
 
+    /// Adds the filter that restricts an entity marked with `@ownedBy` to the rows owned by the
+    /// current user, unless the job can access all rows. Anonymous users get no rows. Only the
+    /// queried entity is filtered: the nested entities are loaded with the rows that refer to
+    /// them.
+    fn add_owner_filter(&mut self, ctx: &DataContext, ty: &Arc<ObjectType>) {
+        let owner = match ty.owned_by() {
+            Some(owner) => owner,
+            None => return,
+        };
+        let user_id: ExprValue = match ctx.job_info.owner_scope() {
+            OwnerScope::All => return,
+            OwnerScope::User(None) => ExprValue::Null,
+            OwnerScope::User(Some(user_id)) => user_id.into(),
+        };
+        let property_access = PropertyAccess {
+            property: owner.to_owned(),
+            object: Expr::Parameter { position: 0 }.into(),
+        };
+        let expr = BinaryExpr::eq(property_access.into(), user_id.into());
+        self.operators.push(QueryOp::Filter { expression: expr });
+    }
+
     fn make_column_string(&self) -> String {
         let mut column_string = String::new();
         for c in &self.columns {
@@ -497,31 +698,46 @@ impl QueryPlan {
         column_string
     }
 
+    /// Returns the condition that restricts the rows of `entity` to the tenant of the query.
+    fn tenant_condition(&self, entity: &QueriedEntity) -> Option<String> {
+        // the auth entities are shared by all tenants
+        if entity.ty.is_auth() {
+            return None;
+        }
+        self.tenant.condition(&entity.table_alias)
+    }
+
     fn make_join_string(&self) -> String {
-        fn gather_joins(entity: &QueriedEntity) -> String {
-            let mut join_string = String::new();
-            for join in entity.joins.values() {
-                writeln!(
-                    join_string,
-                    "LEFT JOIN \"{}\" AS \"{}\" ON \"{}\".\"{}\"=\"{}\".\"{}\"",
-                    join.entity.ty.backing_table(),
-                    join.entity.table_alias,
-                    entity.table_alias,
-                    join.lkey,
-                    join.entity.table_alias,
-                    join.rkey
-                )
-                .unwrap();
-                join_string += gather_joins(&join.entity).as_str();
+        let mut join_string = String::new();
+        self.gather_joins(&self.entity, &mut join_string);
+        join_string
+    }
+
+    fn gather_joins(&self, entity: &QueriedEntity, join_string: &mut String) {
+        for join in entity.joins.values() {
+            write!(
+                join_string,
+                "LEFT JOIN \"{}\" AS \"{}\" ON \"{}\".\"{}\"=\"{}\".\"{}\"",
+                join.entity.ty.backing_table(),
+                join.entity.table_alias,
+                entity.table_alias,
+                join.lkey,
+                join.entity.table_alias,
+                join.rkey
+            )
+            .unwrap();
+            // a row of another tenant is never joined, as if it did not exist
+            if let Some(condition) = self.tenant_condition(&join.entity) {
+                write!(join_string, " AND {condition}").unwrap();
             }
-            join_string
+            join_string.push('\n');
+            self.gather_joins(&join.entity, join_string);
         }
-        gather_joins(&self.entity)
     }
 
-    fn make_filter_string(&self, expr: &Option<Expr>) -> Result<String> {
+    fn make_filter_string(&self, target: &TargetDatabase, expr: &Option<Expr>) -> Result<String> {
         let where_cond = if let Some(expr) = expr {
-            let condition = self.filter_expr_to_string(expr)?;
+            let condition = self.filter_expr_to_string(target, expr)?;
             format!("WHERE {}", condition)
         } else {
             "".to_owned()
@@ -529,7 +745,7 @@ impl QueryPlan {
         Ok(where_cond)
     }
 
-    fn filter_expr_to_string(&self, expr: &Expr) -> Result<String> {
+    fn filter_expr_to_string(&self, target: &TargetDatabase, expr: &Expr) -> Result<String> {
         let expr_str = match &expr {
             Expr::Value { value } => match &value {
                 ExprValue::Bool(value) => (if *value { "true" } else { "false" }).to_string(),
@@ -540,21 +756,151 @@ impl QueryPlan {
                 ExprValue::Null => "NULL".to_string(),
             },
             Expr::Binary(binary_exp) => {
-                format!(
-                    "({} {} {})",
-                    self.filter_expr_to_string(&binary_exp.left)?,
-                    binary_exp.op.to_sql_string(),
-                    self.filter_expr_to_string(&binary_exp.right)?,
-                )
+                let left = self.operand_to_string(target, &binary_exp.left, &binary_exp.right)?;
+                let op = binary_exp.op.to_sql_string();
+                let right = self.operand_to_string(target, &binary_exp.right, &binary_exp.left)?;
+                let is_comparison = !matches!(binary_exp.op, BinaryOp::And | BinaryOp::Or);
+                if is_comparison
+                    && (self.is_nocase_property(&binary_exp.left)?
+                        || self.is_nocase_property(&binary_exp.right)?)
+                {
+                    match target {
+                        TargetDatabase::Sqlite => format!("({left} COLLATE NOCASE {op} {right})"),
+                        TargetDatabase::Postgres => format!("(LOWER({left}) {op} LOWER({right}))"),
+                    }
+                } else {
+                    format!("({left} {op} {right})")
+                }
             }
-            Expr::Property(property) => self.property_expr_to_string(property)?,
+            Expr::Property(property) => self.property_expr_to_string(target, property)?,
             Expr::Parameter { .. } => anyhow::bail!("unexpected standalone parameter usage"),
-            Expr::Not(expr) => format!("NOT ({})", self.filter_expr_to_string(expr)?),
+            Expr::Not { expr } => format!("NOT ({})", self.filter_expr_to_string(target, expr)?),
+            Expr::Variable { name } => {
+                anyhow::bail!("expression error: variable '{name}' was not replaced by its value")
+            }
         };
         Ok(expr_str)
     }
 
-    fn property_expr_to_string(&self, prop_access: &PropertyAccess) -> Result<String> {
+    /// Converts `operand` of a binary expression whose other operand is `other`. Postgres
+    /// compares the values inside of JSON fields as `jsonb`, so the values they are compared to
+    /// must be `jsonb` too.
+    fn operand_to_string(
+        &self,
+        target: &TargetDatabase,
+        operand: &Expr,
+        other: &Expr,
+    ) -> Result<String> {
+        if let (Expr::Value { value }, Expr::Property(property)) = (operand, other) {
+            if let Some(scaled) = self.decimal_operand(value, property)? {
+                return Ok(scaled);
+            }
+        }
+        if let (TargetDatabase::Postgres, Expr::Value { value }, Expr::Property(property)) =
+            (target, operand, other)
+        {
+            if !matches!(value, ExprValue::Null) && self.resolve_property(property)?.2.is_some() {
+                let json = serde_json::to_string(value)?;
+                return Ok(format!("{}::jsonb", escape_string(&json)));
+            }
+        }
+        self.filter_expr_to_string(target, operand)
+    }
+
+    /// Returns true if `expr` reads a field that is compared case-insensitively (`@collate`).
+    fn is_nocase_property(&self, expr: &Expr) -> Result<bool> {
+        let property = match expr {
+            Expr::Property(property) => property,
+            _ => return Ok(false),
+        };
+        let (entity, field, json_path) = self.resolve_property(property)?;
+        Ok(json_path.is_none()
+            && entity
+                .ty
+                .get_field(&field)
+                .map_or(false, |field| field.is_nocase()))
+    }
+
+    /// If `property` is a `Decimal` field, converts `value` that it is compared to into the
+    /// integer that the field stores.
+    fn decimal_operand(
+        &self,
+        value: &ExprValue,
+        property: &PropertyAccess,
+    ) -> Result<Option<String>> {
+        let (entity, field, json_path) = self.resolve_property(property)?;
+        let (precision, scale) = match entity.ty.get_field(&field).map(|f| &f.type_id) {
+            Some(TypeId::Decimal { precision, scale }) if json_path.is_none() => {
+                (*precision, *scale)
+            }
+            _ => return Ok(None),
+        };
+        let text = match value {
+            ExprValue::String(text) => text.clone(),
+            ExprValue::I64(value) => value.to_string(),
+            ExprValue::U64(value) => value.to_string(),
+            ExprValue::F64(value) => value.to_string(),
+            ExprValue::Null => return Ok(None),
+            ExprValue::Bool(_) => anyhow::bail!(
+                "expression error: decimal field '{field}' can't be compared to a boolean"
+            ),
+        };
+        let scaled = decimal::parse(&text, precision, scale)
+            .map_err(|e| anyhow!("expression error: field '{field}': {e}"))?;
+        Ok(Some(scaled.to_string()))
+    }
+
+    fn property_expr_to_string(
+        &self,
+        target: &TargetDatabase,
+        prop_access: &PropertyAccess,
+    ) -> Result<String> {
+        let (entity, field, json_path) = self.resolve_property(prop_access)?;
+        let c_alias = ColumnAlias {
+            field_name: field,
+            table_name: entity.table_alias.to_owned(),
+        };
+        let column = format!("\"{}\"", c_alias);
+        let json_path = match json_path {
+            Some(json_path) => json_path,
+            None => return Ok(column),
+        };
+
+        for key in &json_path {
+            anyhow::ensure!(
+                !key.contains(['"', '\\']),
+                "expression error: JSON path key '{}' must not contain quotes or backslashes",
+                key
+            );
+        }
+        let expr = match target {
+            TargetDatabase::Sqlite => {
+                let path: String = json_path.iter().map(|key| format!(".\"{key}\"")).collect();
+                format!(
+                    "json_extract({column}, {})",
+                    escape_string(&format!("${path}"))
+                )
+            }
+            TargetDatabase::Postgres => {
+                let path = json_path
+                    .iter()
+                    .map(|key| format!("\"{key}\""))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!("({column} #> {})", escape_string(&format!("{{{path}}}")))
+            }
+        };
+        Ok(expr)
+    }
+
+    /// Resolves the chain of properties in `prop_access` to the queried entity and the name of
+    /// the field that holds it. Properties are separated either by nesting or by dots. If the
+    /// field is a JSON field, the properties that continue past it are returned as the path of
+    /// the value inside of the field.
+    fn resolve_property(
+        &self,
+        prop_access: &PropertyAccess,
+    ) -> Result<(&QueriedEntity, String, Option<Vec<String>>)> {
         fn get_property_chain(prop_access: &PropertyAccess) -> Result<Vec<String>> {
             match &*prop_access.object {
                 Expr::Property(obj) => {
@@ -566,7 +912,11 @@ impl QueryPlan {
                 _ => anyhow::bail!("unexpected expression in property chain!"),
             }
         }
-        let properties = get_property_chain(prop_access)?;
+        let properties: Vec<String> = get_property_chain(prop_access)?
+            .iter()
+            .flat_map(|property| property.split('.'))
+            .map(ToOwned::to_owned)
+            .collect();
         assert!(!properties.is_empty());
 
         let check_field = |entity: &QueriedEntity, field| {
@@ -579,11 +929,22 @@ impl QueryPlan {
             Ok(())
         };
 
+        // points are stored as JSON, so `near()` filters by their `lat` and `lng` as by paths
+        let is_json = |entity: &QueriedEntity, field| {
+            entity.ty.get_field(field).map_or(false, |field| {
+                matches!(field.type_id, TypeId::Json | TypeId::GeoPoint)
+            })
+        };
+
         let mut field = &properties[0];
         let mut entity = &self.entity;
         check_field(entity, field)?;
 
-        for next_field in &properties[1..] {
+        let mut remaining = &properties[1..];
+        while let Some((next_field, rest)) = remaining.split_first() {
+            if is_json(entity, field) {
+                break;
+            }
             entity = &entity
                 .joins
                 .get(field)
@@ -596,16 +957,13 @@ impl QueryPlan {
                 .entity;
             field = next_field;
             check_field(entity, field)?;
+            remaining = rest;
         }
-        let c_alias = ColumnAlias {
-            field_name: field.to_owned(),
-            table_name: entity.table_alias.to_owned(),
-        };
-
-        Ok(format!("\"{}\"", c_alias))
+        let json_path = is_json(entity, field).then(|| remaining.to_vec());
+        Ok((entity, field.to_owned(), json_path))
     }
 
-    fn make_sort_string(&self, sort: Option<&SortBy>) -> Result<String> {
+    fn make_sort_string(&self, target: &TargetDatabase, sort: Option<&SortBy>) -> Result<String> {
         let sort_str = if let Some(sort) = sort {
             let mut order_tokens = vec![];
             for sort_key in &sort.keys {
@@ -621,7 +979,16 @@ impl QueryPlan {
                     field_name: sort_key.field_name.to_owned(),
                     table_name: self.base_type().backing_table().to_owned(),
                 };
-                order_tokens.push(format!("\"{c_alias}\" {order}"));
+                let is_nocase = self
+                    .base_type()
+                    .get_field(&sort_key.field_name)
+                    .map_or(false, |field| field.is_nocase());
+                let column = match (is_nocase, target) {
+                    (false, _) => format!("\"{c_alias}\""),
+                    (true, TargetDatabase::Sqlite) => format!("\"{c_alias}\" COLLATE NOCASE"),
+                    (true, TargetDatabase::Postgres) => format!("LOWER(\"{c_alias}\")"),
+                };
+                order_tokens.push(format!("{column} {order}"));
             }
             format!("ORDER BY {}", order_tokens.join(", "))
         } else {
@@ -649,11 +1016,16 @@ impl QueryPlan {
     fn make_core_select(&self) -> String {
         let column_string = self.make_column_string();
         let join_string = self.make_join_string();
+        let tenant_string = self
+            .tenant_condition(&self.entity)
+            .map(|condition| format!("WHERE {condition}"))
+            .unwrap_or_default();
         format!(
-            "SELECT {} FROM \"{}\" {}",
+            "SELECT {} FROM \"{}\" {} {}",
             column_string,
             self.base_type().backing_table(),
             join_string,
+            tenant_string,
         )
     }
 
@@ -720,22 +1092,24 @@ impl QueryPlan {
         let mut sql_query = self.make_core_select();
         let mut remaining_ops: &[QueryOp] = &self.operators[..];
         let mut fields = self.entity.fields.clone();
+        let mut is_count = false;
 
         while !remaining_ops.is_empty() {
             let (ops, remainder) = self.split_on_first_take(remaining_ops);
             remaining_ops = remainder;
 
             let filter_expr = self.gather_filters(ops);
-            let filter_string = self.make_filter_string(&filter_expr)?;
+            let filter_string = self.make_filter_string(target, &filter_expr)?;
 
             let sort = self.find_last_sort_by(ops);
-            let sort_string = self.make_sort_string(sort)?;
+            let sort_string = self.make_sort_string(target, sort)?;
 
             let limit = self.find_take_count(ops);
             let offset = self.find_skip_count(ops);
             let lo_string = self.make_limit_and_offset_string(target, limit, offset);
 
-            let columns_selection = if self.contains_count(ops) {
+            is_count = self.contains_count(ops);
+            let columns_selection = if is_count {
                 assert!(remaining_ops.is_empty());
                 fields = vec![QueryField::Scalar {
                     name: "count".to_owned(),
@@ -756,20 +1130,56 @@ impl QueryPlan {
                 columns_selection, sql_query, filter_string, sort_string, lo_string
             );
         }
+
+        if let (Some(allowed_fields), false) = (&self.allowed_fields, is_count) {
+            let mut column_idxs = vec![];
+            fields = fields
+                .iter()
+                .filter(|field| allowed_fields.contains(field.name()))
+                .map(|field| project_field(field, &mut column_idxs))
+                .collect();
+            let columns = column_idxs
+                .iter()
+                .map(|idx| format!("\"{}\"", self.columns[*idx].alias()))
+                .collect::<Vec<_>>();
+            // selecting no columns is not valid SQL
+            let columns = if columns.is_empty() {
+                "NULL".to_owned()
+            } else {
+                columns.join(",")
+            };
+            sql_query = format!("SELECT {columns} FROM ({sql_query}) AS projection");
+        }
         Ok((sql_query, fields))
     }
 
     pub fn build_query(&self, target: &TargetDatabase) -> Result<Query> {
         let (raw_sql, fields) = self.make_raw_query(target)?;
-        Ok(Query {
-            raw_sql,
-            allowed_fields: self.allowed_fields.clone(),
-            fields,
-        })
+        Ok(Query { raw_sql, fields })
     }
 }
 
 // FIXME: We should use prepared statements instead
+/// Copies `field` for a projected query, whose columns are only the columns of the projected
+/// fields. The original indices of the columns of `field` are appended to `column_idxs`, and the
+/// copy refers to their positions in `column_idxs`.
+fn project_field(field: &QueryField, column_idxs: &mut Vec<usize>) -> QueryField {
+    let mut field = field.clone();
+    match &mut field {
+        QueryField::Scalar { column_idx, .. } => {
+            column_idxs.push(*column_idx);
+            *column_idx = column_idxs.len() - 1;
+        }
+        QueryField::Entity { fields, .. } => {
+            *fields = fields
+                .iter()
+                .map(|field| project_field(field, column_idxs))
+                .collect();
+        }
+    }
+    field
+}
+
 fn escape_string(s: &str) -> String {
     format!("{}", format_sql_query::QuotedData(s))
 }
@@ -830,6 +1240,11 @@ pub enum QueryOpChain {
     Count {
         inner: Box<QueryOpChain>,
     },
+    With {
+        #[serde(rename = "selection")]
+        relations: RelationSelection,
+        inner: Box<QueryOpChain>,
+    },
 }
 
 /// Converts operator chain into a tuple `(entity_name, ops)`, where
@@ -855,6 +1270,7 @@ fn convert_ops(op: QueryOpChain) -> Result<(String, Vec<QueryOp>)> {
         Op::Skip { count, inner } => (QueryOp::Skip { count }, inner),
         Op::SortBy { keys, inner } => (QueryOp::SortBy(SortBy { keys }), inner),
         Op::Count { inner } => (QueryOp::Count, inner),
+        Op::With { relations, inner } => (QueryOp::With { relations }, inner),
     };
     let (entity_name, mut ops) = convert_ops(*inner)?;
     ops.push(query_op);
@@ -866,6 +1282,25 @@ pub struct Mutation {
     base_entity: Entity,
     /// Query plan used to build mutation condition.
     filter_query_plan: QueryPlan,
+    /// `@count` fields that count the deleted entity.
+    counters: Vec<Counter>,
+    /// Materialized aggregates of the deleted entity that are updated on write.
+    aggregators: Vec<Aggregator>,
+    /// `@onDelete` references to the deleted entity and to the entities that are deleted by a
+    /// cascade, keyed by the name of the referred entity.
+    references: HashMap<String, Vec<Reference>>,
+    /// Entities that are written because they refer to a deleted entity, keyed by name.
+    dependents: HashMap<String, DependentEntity>,
+}
+
+/// Entity that is deleted by an `@onDelete("cascade")` reference or updated by an
+/// `@onDelete("setNull")` reference when a `Mutation` deletes the entity that it refers to.
+struct DependentEntity {
+    entity: Arc<ObjectType>,
+    /// `@count` fields that count the entity.
+    counters: Vec<Counter>,
+    /// Materialized aggregates of the entity that are updated on write.
+    aggregators: Vec<Aggregator>,
 }
 
 impl Mutation {
@@ -880,19 +1315,163 @@ impl Mutation {
             Ok(ty) => anyhow::bail!("Cannot delete scalar type {type_name} ({})", ty.name()),
             Err(_) => anyhow::bail!("Cannot delete from type `{type_name}`, type not found"),
         };
+        if base_entity.aggregate().is_some() {
+            anyhow::bail!("Cannot delete from type `{type_name}`, it is a materialized aggregate");
+        }
 
-        let mut query_plan = QueryPlan::from_entity_name(ctx, type_name)?;
+        let mut query_plan =
+            QueryPlan::from_entity_name(ctx, type_name, &RelationSelection::default())?;
         if let Some(expr) = filter_expr {
             query_plan.extend_operators(vec![QueryOp::Filter {
                 expression: expr.clone(),
             }]);
         }
+        // follow the cascades to find all entities that the mutation may write
+        let mut references = HashMap::new();
+        let mut dependents = HashMap::<String, DependentEntity>::new();
+        let mut pending = vec![type_name.to_owned()];
+        while let Some(name) = pending.pop() {
+            if references.contains_key(&name) {
+                continue;
+            }
+            let refs = ctx.type_system.references_to(&name);
+            for reference in refs.iter() {
+                let referring = reference.referring.name();
+                if reference.on_delete == OnDelete::Cascade {
+                    pending.push(referring.to_owned());
+                }
+                if reference.on_delete != OnDelete::Restrict
+                    && referring != type_name
+                    && !dependents.contains_key(referring)
+                {
+                    dependents.insert(
+                        referring.to_owned(),
+                        DependentEntity {
+                            entity: reference.referring.clone(),
+                            counters: ctx.type_system.counters_of(referring),
+                            aggregators: ctx.type_system.aggregators_of(referring),
+                        },
+                    );
+                }
+            }
+            references.insert(name, refs);
+        }
+
         Ok(Self {
             base_entity,
             filter_query_plan: query_plan,
+            counters: ctx.type_system.counters_of(type_name),
+            aggregators: ctx.type_system.aggregators_of(type_name),
+            references,
+            dependents,
         })
     }
 
+    /// Name of the entity that this mutation deletes.
+    pub fn base_entity_name(&self) -> &str {
+        self.base_entity.name()
+    }
+
+    /// Whether some `@onDelete` references must be followed, so the mutation must be executed
+    /// row by row instead of by a single statement.
+    pub fn has_references(&self) -> bool {
+        self.references.values().any(|refs| !refs.is_empty())
+    }
+
+    /// Returns the `@onDelete` references to the entity `name`.
+    pub fn references_to(&self, name: &str) -> &[Reference] {
+        self.references.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Returns the backing table and the `@count` fields of the entity `name` that this mutation
+    /// deletes, which is either the base entity or a dependent entity.
+    pub fn deleted_entity(&self, name: &str) -> Result<(&str, &[Counter])> {
+        if name == self.base_entity.name() {
+            return Ok((self.backing_table(), &self.counters));
+        }
+        let dependent = self
+            .dependents
+            .get(name)
+            .with_context(|| format!("Entity `{name}` is not deleted by a cascade"))?;
+        Ok((dependent.entity.backing_table(), &dependent.counters))
+    }
+
+    /// The table that this mutation mutates.
+    pub fn backing_table(&self) -> &str {
+        self.base_entity.backing_table()
+    }
+
+    pub fn otel_context(&self) -> &opentelemetry::Context {
+        self.filter_query_plan.otel_context()
+    }
+
+    /// Rows that this mutation reads and writes, which also restricts the instances that are
+    /// followed through the `@onDelete` references.
+    pub fn tenant(&self) -> &TenantFilter {
+        &self.filter_query_plan.tenant
+    }
+
+    /// The tables that this mutation writes, including the tables of the entities whose `@count`
+    /// fields count the deleted entity and the tables of its materialized aggregates.
+    pub fn written_tables(&self) -> Vec<String> {
+        let mut tables = vec![self.backing_table().to_owned()];
+        for counter in self.counters.iter() {
+            tables.push(counter.counting.backing_table().to_owned());
+        }
+        for agg in self.aggregators.iter() {
+            tables.push(agg.aggregate.backing_table().to_owned());
+        }
+        for dependent in self.dependents.values() {
+            tables.push(dependent.entity.backing_table().to_owned());
+            for counter in dependent.counters.iter() {
+                tables.push(counter.counting.backing_table().to_owned());
+            }
+            for agg in dependent.aggregators.iter() {
+                tables.push(agg.aggregate.backing_table().to_owned());
+            }
+        }
+        tables.sort();
+        tables.dedup();
+        tables
+    }
+
+    /// Builds the refreshes of the materialized aggregates of the deleted entity and of the
+    /// dependent entities, which must be executed after the mutation.
+    pub fn build_aggregate_sql(&self) -> Vec<String> {
+        let dependent_aggregators = self
+            .dependents
+            .values()
+            .flat_map(|dependent| dependent.aggregators.iter());
+        self.aggregators
+            .iter()
+            .chain(dependent_aggregators)
+            .flat_map(aggregate::refresh_sql)
+            .collect()
+    }
+
+    /// Builds a query that selects the ids of the instances of the base entity that match the
+    /// filter of the mutation.
+    pub fn build_ids_sql(&self, target: TargetDatabase) -> Result<String> {
+        let select_sql = self.filter_query_plan.build_query(&target)?.raw_sql;
+        let id_column = ColumnAlias {
+            field_name: "id".to_owned(),
+            table_name: self.base_entity.backing_table().to_owned(),
+        };
+        Ok(format!(
+            r#"SELECT "{id_column}" FROM ({select_sql}) as subquery"#
+        ))
+    }
+
+    /// Builds the updates of the `@count` fields that count the deleted entity, which must be
+    /// executed before the mutation.
+    pub fn build_count_sql(&self, target: TargetDatabase) -> Result<Vec<String>> {
+        if self.counters.is_empty() {
+            return Ok(vec![]);
+        }
+        let deleted_ids = self.build_ids_sql(target)?;
+        Ok(count_update_sql(&self.counters, &deleted_ids))
+    }
+
     pub fn build_sql(&self, target: TargetDatabase) -> Result<String> {
         let select_sql = self.filter_query_plan.build_query(&target)?.raw_sql;
         let id_column = ColumnAlias {
@@ -910,6 +1489,79 @@ impl Mutation {
     }
 }
 
+/// Builds the updates of the `@count` fields in `counters`, which decrement the counts by the
+/// instances of the counted entity whose ids are selected by `deleted_ids`.
+pub fn count_update_sql(counters: &[Counter], deleted_ids: &str) -> Vec<String> {
+    counters
+        .iter()
+        .map(|counter| {
+            format!(
+                r#"UPDATE "{counting}" SET "{field}" = "{field}" - (
+                    SELECT COUNT(*) FROM "{counted}" AS counted
+                    WHERE counted."{counted_field}" = "{counting}"."id" AND counted."id" IN ({deleted_ids})
+                )
+                WHERE "id" IN (
+                    SELECT counted."{counted_field}" FROM "{counted}" AS counted
+                    WHERE counted."id" IN ({deleted_ids})
+                )"#,
+                counting = counter.counting.backing_table(),
+                field = counter.field,
+                counted = counter.counted.backing_table(),
+                counted_field = counter.counted_field,
+            )
+        })
+        .collect()
+}
+
+/// Returns the list of `count` parameters `$1, $2, ...`.
+pub fn id_params(count: usize) -> String {
+    (1..=count)
+        .map(|i| format!("${i}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns the condition that selects the instances that refer by `reference` to one of `count`
+/// ids, which are bound as parameters, restricted to the rows of `tenant`.
+fn referring_condition(reference: &Reference, count: usize, tenant: &TenantFilter) -> String {
+    let table = reference.referring.backing_table();
+    let mut condition = format!(r#""{}" IN ({})"#, reference.field, id_params(count));
+    if let Some(tenant_condition) = tenant.condition(table) {
+        write!(condition, " AND {tenant_condition}").unwrap();
+    }
+    condition
+}
+
+/// Builds a query that selects the id and the referred id of the instances of `tenant` that refer
+/// by `reference` to one of `count` ids, which are bound as parameters.
+pub fn referring_rows_sql(reference: &Reference, count: usize, tenant: &TenantFilter) -> String {
+    format!(
+        r#"SELECT "id", "{field}" FROM "{table}" WHERE {condition}"#,
+        field = reference.field,
+        table = reference.referring.backing_table(),
+        condition = referring_condition(reference, count, tenant),
+    )
+}
+
+/// Builds an update that clears the `reference` field of the instances of `tenant` that refer to
+/// one of `count` ids, which are bound as parameters.
+pub fn set_null_sql(reference: &Reference, count: usize, tenant: &TenantFilter) -> String {
+    format!(
+        r#"UPDATE "{table}" SET "{field}" = NULL WHERE {condition}"#,
+        field = reference.field,
+        table = reference.referring.backing_table(),
+        condition = referring_condition(reference, count, tenant),
+    )
+}
+
+/// Builds a delete of the rows of `table` with one of `count` ids, which are bound as parameters.
+pub fn delete_ids_sql(table: &str, count: usize) -> String {
+    format!(
+        r#"DELETE FROM "{table}" WHERE "id" IN ({params})"#,
+        params = id_params(count)
+    )
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -1076,6 +1728,55 @@ pub mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_projection() {
+        let (qe, _db_file) = setup_clear_db(&*ENTITIES).await;
+        qe.with_dummy_ctx(Default::default(), |ctx| async {
+            add_row(
+                &qe,
+                &PERSON_TY,
+                &json!({"name": "John", "age": 20f32}),
+                &ctx,
+            )
+            .await;
+            add_row(
+                &qe,
+                &PERSON_TY,
+                &json!({"name": "Alan", "age": 30f32}),
+                &ctx,
+            )
+            .await;
+
+            let op_chain = QueryOpChain::Projection {
+                fields: vec!["name".into()],
+                inner: QueryOpChain::SortBy {
+                    keys: vec![SortKey {
+                        field_name: "age".into(),
+                        ascending: false,
+                    }],
+                    inner: QueryOpChain::BaseEntity {
+                        name: "Person".into(),
+                    }
+                    .into(),
+                }
+                .into(),
+            };
+            let query_plan = QueryPlan::from_op_chain(&ctx, op_chain).unwrap();
+            let query = query_plan.build_query(&TargetDatabase::Sqlite).unwrap();
+            let column = format!("\"{}_name\"", PERSON_TY.backing_table());
+            assert!(query.raw_sql.starts_with(&format!("SELECT {column} FROM")));
+
+            let rows = fetch_rows_with_plan(&qe, ctx.txn.clone(), query_plan).await;
+            let rows: Vec<_> = rows
+                .into_iter()
+                .map(|row| row.into_keys().collect::<Vec<_>>())
+                .collect();
+            assert_eq!(rows, vec![vec!["name"], vec!["name"]]);
+            ctx
+        })
+        .await;
+    }
+
     #[tokio::test]
     async fn test_delete_with_expr() {
         let delete_with_expr = |ctx: &DataContext, entity_name: &str, expr: Expr| {
@@ -1163,4 +1864,30 @@ pub mod tests {
             .await;
         }
     }
+
+    #[test]
+    fn relation_selection() {
+        let parse = |value: serde_json::Value| RelationSelection::try_from(value);
+
+        let relations = parse(json!({"author": {"company": true}, "editor": false})).unwrap();
+        assert_eq!(relations.depth(), 2);
+        assert!(relations.get("editor").is_none());
+        assert!(relations
+            .get("author")
+            .unwrap()
+            .get("company")
+            .unwrap()
+            .is_empty());
+        assert_eq!(parse(json!({})).unwrap().depth(), 0);
+
+        assert!(parse(json!({"author": 1})).is_err());
+        assert!(parse(json!(["author"])).is_err());
+
+        let mut merged = parse(json!({"author": true})).unwrap();
+        merged.merge(&parse(json!({"author": {"company": true}, "tags": true})).unwrap());
+        assert_eq!(
+            merged,
+            parse(json!({"author": {"company": true}, "tags": true})).unwrap()
+        );
+    }
 }
@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! In-memory cache of query results, used by `Entity.cached()` in TypeScript.
+//!
+//! Entries are keyed by the version, the SQL of the query (values of the query parameters are a
+//! part of the SQL) and the inputs of the policies (the user and the routing path), because the
+//! policies may transform the results. Every entry remembers the backing tables that the query
+//! reads, and it is invalidated when a transaction that mutated any of these tables (with
+//! `op_chisel_store` or `op_chisel_delete`) is committed.
+
+use crate::datastore::value::EntityMap;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Default for `--query-cache-size`.
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub version_id: String,
+    pub sql: String,
+    pub user_id: Option<String>,
+    pub path: String,
+}
+
+/// Generations of the tables that a query reads, taken before the query is executed.
+pub struct TableGenerations(Vec<(String, u64)>);
+
+pub struct QueryCache {
+    /// Maximum number of cached queries; the cache is disabled if this is zero.
+    max_entries: usize,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Incremented every time a table is invalidated. An entry can be inserted only if the
+    /// tables did not change while the query was executed.
+    generations: HashMap<String, u64>,
+}
+
+struct CacheEntry {
+    rows: Arc<Vec<EntityMap>>,
+    tables: Vec<String>,
+    expires_at: Instant,
+}
+
+impl QueryCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            inner: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_entries > 0
+    }
+
+    pub fn get(&self, key: &CacheKey, now: Instant) -> Option<Arc<Vec<EntityMap>>> {
+        let mut inner = self.inner.lock();
+        match inner.entries.get(key) {
+            Some(entry) if entry.expires_at > now => Some(entry.rows.clone()),
+            Some(_) => {
+                inner.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn generations(&self, tables: &[String]) -> TableGenerations {
+        let inner = self.inner.lock();
+        let generations = tables
+            .iter()
+            .map(|table| {
+                let generation = inner.generations.get(table).copied().unwrap_or(0);
+                (table.clone(), generation)
+            })
+            .collect();
+        TableGenerations(generations)
+    }
+
+    /// Caches the results of a query. `generations` must be taken before the query was executed,
+    /// so that we don't cache results that were invalidated in the meantime.
+    pub fn insert(
+        &self,
+        key: CacheKey,
+        rows: Arc<Vec<EntityMap>>,
+        generations: TableGenerations,
+        expires_at: Instant,
+    ) {
+        let mut inner = self.inner.lock();
+        let unchanged = generations.0.iter().all(|(table, generation)| {
+            inner.generations.get(table).copied().unwrap_or(0) == *generation
+        });
+        if !unchanged {
+            return;
+        }
+
+        if inner.entries.len() >= self.max_entries && !inner.entries.contains_key(&key) {
+            let now = Instant::now();
+            inner.entries.retain(|_, entry| entry.expires_at > now);
+            if inner.entries.len() >= self.max_entries {
+                // evict the entry that would expire first
+                let evicted = inner
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(evicted) = evicted {
+                    inner.entries.remove(&evicted);
+                }
+            }
+        }
+
+        let tables = generations.0.into_iter().map(|(table, _)| table).collect();
+        inner.entries.insert(
+            key,
+            CacheEntry {
+                rows,
+                tables,
+                expires_at,
+            },
+        );
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().entries.clear();
+    }
+
+    /// Removes all entries that read any of the `tables`.
+    pub fn invalidate<'a>(&self, tables: impl IntoIterator<Item = &'a String>) {
+        let mut inner = self.inner.lock();
+        let mut invalidated = Vec::new();
+        for table in tables {
+            *inner.generations.entry(table.clone()).or_insert(0) += 1;
+            invalidated.push(table);
+        }
+        if !invalidated.is_empty() {
+            inner
+                .entries
+                .retain(|_, entry| !entry.tables.iter().any(|t| invalidated.contains(&t)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::value::EntityValue;
+    use std::time::Duration;
+
+    fn key(sql: &str) -> CacheKey {
+        CacheKey {
+            version_id: "dev".into(),
+            sql: sql.into(),
+            user_id: None,
+            path: "/people".into(),
+        }
+    }
+
+    fn rows(name: &str) -> Arc<Vec<EntityMap>> {
+        let row = EntityMap::from([("name".into(), EntityValue::String(name.into()))]);
+        Arc::new(vec![row])
+    }
+
+    fn tables(tables: &[&str]) -> Vec<String> {
+        tables.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn invalidation() {
+        let cache = QueryCache::new(10);
+        let now = Instant::now();
+        let later = now + Duration::from_secs(60);
+
+        let gens = cache.generations(&tables(&["people"]));
+        cache.insert(key("a"), rows("Alice"), gens, later);
+        let gens = cache.generations(&tables(&["people", "companies"]));
+        cache.insert(key("b"), rows("Bob"), gens, later);
+        let gens = cache.generations(&tables(&["companies"]));
+        cache.insert(key("c"), rows("Carol"), gens, later);
+        assert_eq!(cache.get(&key("a"), now), Some(rows("Alice")));
+
+        cache.invalidate(&tables(&["people"]));
+        assert_eq!(cache.get(&key("a"), now), None);
+        assert_eq!(cache.get(&key("b"), now), None);
+        assert_eq!(cache.get(&key("c"), now), Some(rows("Carol")));
+
+        // results of a query that was running during the invalidation are not cached
+        let gens = cache.generations(&tables(&["people"]));
+        cache.invalidate(&tables(&["people"]));
+        cache.insert(key("a"), rows("Alice"), gens, later);
+        assert_eq!(cache.get(&key("a"), now), None);
+    }
+
+    #[test]
+    fn expiration_and_eviction() {
+        let cache = QueryCache::new(2);
+        let now = Instant::now();
+
+        let gens = cache.generations(&tables(&["people"]));
+        cache.insert(key("a"), rows("Alice"), gens, now + Duration::from_secs(10));
+        assert_eq!(cache.get(&key("a"), now), Some(rows("Alice")));
+        assert_eq!(cache.get(&key("a"), now + Duration::from_secs(10)), None);
+
+        for (sql, ttl) in [("a", 30), ("b", 10), ("c", 20)] {
+            let gens = cache.generations(&tables(&["people"]));
+            cache.insert(key(sql), rows(sql), gens, now + Duration::from_secs(ttl));
+        }
+        assert!(cache.get(&key("a"), now).is_some());
+        assert!(cache.get(&key("b"), now).is_none());
+        assert!(cache.get(&key("c"), now).is_some());
+    }
+}
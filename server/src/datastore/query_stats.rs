@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Statistics of the fields that queries filter and sort by, which `chisel index-suggestions`
+//! turns into suggestions of indexes (see `QueryEngine::index_suggestions()`).
+//!
+//! For every query, we collect the fields of the base entity that an index could serve: the
+//! fields compared for equality in the top-level conjunction of the filters, followed by one field
+//! that is compared by a range (or, if there is none, the first sort key). These are the columns
+//! of the index that would serve the query, in the order of the columns.
+
+use crate::datastore::expr::{BinaryOp, Expr};
+use crate::datastore::query::QueryOp;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    /// Number of queries, by the backing table of the base entity and by the index fields of the
+    /// query.
+    tables: Mutex<HashMap<String, HashMap<Vec<String>, u64>>>,
+}
+
+impl QueryStats {
+    /// Records a query on `table` that an index on `fields` would serve.
+    pub fn record(&self, table: &str, fields: Vec<String>) {
+        if fields.is_empty() {
+            return;
+        }
+        let mut tables = self.tables.lock();
+        let counts = tables.entry(table.to_owned()).or_default();
+        *counts.entry(fields).or_default() += 1;
+    }
+
+    /// Returns the recorded index fields of queries on `table`, with the number of queries.
+    pub fn table_counts(&self, table: &str) -> Vec<(Vec<String>, u64)> {
+        let tables = self.tables.lock();
+        let mut counts = tables
+            .get(table)
+            .map(|counts| counts.iter().map(|(f, c)| (f.clone(), *c)).collect())
+            .unwrap_or_else(Vec::new);
+        counts.sort();
+        counts
+    }
+
+    /// Forgets the statistics of `table`, which was dropped.
+    pub fn forget(&self, table: &str) {
+        self.tables.lock().remove(table);
+    }
+}
+
+/// Folds the queries whose index fields are a prefix of the index fields of other queries into
+/// the longest of them, because one index serves both.
+pub fn merge_prefixes(mut counts: Vec<(Vec<String>, u64)>) -> Vec<(Vec<String>, u64)> {
+    counts.sort_by(|(x, _), (y, _)| y.len().cmp(&x.len()).then_with(|| x.cmp(y)));
+    let mut merged: Vec<(Vec<String>, u64)> = Vec::new();
+    for (fields, count) in counts {
+        match merged
+            .iter_mut()
+            .find(|(longer, _)| longer.starts_with(&fields))
+        {
+            Some((_, longer_count)) => *longer_count += count,
+            None => merged.push((fields, count)),
+        }
+    }
+    merged
+}
+
+/// Returns the fields of the base entity that an index could serve for a query with `operators`.
+pub fn index_fields(operators: &[QueryOp]) -> Vec<String> {
+    let mut eq_fields = BTreeSet::new();
+    let mut range_fields = Vec::new();
+    let mut sort_fields = Vec::new();
+    for op in operators.iter() {
+        match op {
+            QueryOp::Filter { expression } => {
+                collect_filter_fields(expression, &mut eq_fields, &mut range_fields)
+            }
+            QueryOp::SortBy(sort) => {
+                sort_fields.extend(sort.keys.iter().map(|key| key.field_name.clone()))
+            }
+            _ => {}
+        }
+    }
+
+    let last = range_fields
+        .into_iter()
+        .chain(sort_fields)
+        .find(|field| !eq_fields.contains(field));
+    let mut fields = eq_fields.into_iter().collect::<Vec<_>>();
+    fields.extend(last);
+    fields.retain(|field| field != "id");
+    fields
+}
+
+fn collect_filter_fields(
+    expr: &Expr,
+    eq_fields: &mut BTreeSet<String>,
+    range_fields: &mut Vec<String>,
+) {
+    let binary = match expr {
+        Expr::Binary(binary) => binary,
+        _ => return,
+    };
+    if binary.op == BinaryOp::And {
+        collect_filter_fields(&binary.left, eq_fields, range_fields);
+        collect_filter_fields(&binary.right, eq_fields, range_fields);
+        return;
+    }
+    let field = match (base_property(&binary.left), base_property(&binary.right)) {
+        (Some(field), None) if is_value(&binary.right) => field,
+        (None, Some(field)) if is_value(&binary.left) => field,
+        _ => return,
+    };
+    match binary.op {
+        BinaryOp::Eq => {
+            eq_fields.insert(field.to_owned());
+        }
+        BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq | BinaryOp::Like => {
+            range_fields.push(field.to_owned())
+        }
+        _ => {}
+    }
+}
+
+/// Returns the name of the property if `expr` reads a field of the base entity (rather than a
+/// field of a related entity).
+fn base_property(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Property(access) => match *access.object {
+            Expr::Parameter { position: 0 } => Some(&access.property),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_value(expr: &Expr) -> bool {
+    matches!(expr, Expr::Value { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::expr::{BinaryExpr, PropertyAccess, Value};
+    use crate::datastore::query::{SortBy, SortKey};
+
+    fn prop(name: &str) -> Expr {
+        PropertyAccess {
+            property: name.into(),
+            object: Box::new(Expr::Parameter { position: 0 }),
+        }
+        .into()
+    }
+
+    fn filter(expression: Expr) -> QueryOp {
+        QueryOp::Filter { expression }
+    }
+
+    #[test]
+    fn equality_then_range() {
+        let expression = BinaryExpr::and(
+            BinaryExpr::gt(prop("age"), Value::from(18u64).into()),
+            BinaryExpr::and(
+                BinaryExpr::eq(prop("name"), Value::from("alice").into()),
+                BinaryExpr::eq(Value::from("x").into(), prop("city")),
+            ),
+        );
+        assert_eq!(
+            index_fields(&[filter(expression)]),
+            vec!["city", "name", "age"]
+        );
+    }
+
+    #[test]
+    fn sort_and_ignored_filters() {
+        let nested = PropertyAccess {
+            property: "name".into(),
+            object: Box::new(prop("author")),
+        };
+        let ops = vec![
+            filter(BinaryExpr::or(
+                BinaryExpr::eq(prop("a"), Value::from(1u64).into()),
+                BinaryExpr::eq(prop("b"), Value::from(2u64).into()),
+            )),
+            filter(BinaryExpr::eq(nested.into(), Value::from("x").into())),
+            filter(BinaryExpr::eq(prop("id"), Value::from("x").into())),
+            QueryOp::SortBy(SortBy {
+                keys: vec![SortKey {
+                    field_name: "createdAt".into(),
+                    ascending: false,
+                }],
+            }),
+        ];
+        assert_eq!(index_fields(&ops), vec!["createdAt"]);
+    }
+
+    #[test]
+    fn merges_prefixes() {
+        let fields = |fields: &[&str]| fields.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let merged = merge_prefixes(vec![
+            (fields(&["a"]), 1),
+            (fields(&["a", "b"]), 2),
+            (fields(&["a", "c"]), 4),
+            (fields(&["b"]), 8),
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                (fields(&["a", "b"]), 3),
+                (fields(&["a", "c"]), 4),
+                (fields(&["b"]), 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_by_table() {
+        let stats = QueryStats::default();
+        stats.record("t", vec!["name".into()]);
+        stats.record("t", vec!["name".into()]);
+        stats.record("t", vec![]);
+        stats.record("u", vec!["age".into()]);
+        assert_eq!(stats.table_counts("t"), vec![(vec!["name".into()], 2)]);
+        stats.forget("t");
+        assert!(stats.table_counts("t").is_empty());
+    }
+}
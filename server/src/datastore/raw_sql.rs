@@ -0,0 +1,398 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Raw SQL queries (`Chisel.rawQuery()`), an escape hatch for the queries that the query builder
+//! cannot express. They are available only when chiseld runs with `--allow-raw-sql`.
+//!
+//! The query runs in the transaction of the job, so it sees and is rolled back with the other
+//! writes of the job. Policies, `@ownedBy` and the other checks of the query engine are not
+//! applied, which is why raw queries are refused when the data is isolated per tenant. The query
+//! must be a single statement that reads or writes data, and it can only use the tables of its
+//! version: every name that is a table of the database must be one of those tables.
+
+use std::collections::HashSet;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value as JsonValue};
+use sqlx::any::{Any, AnyArguments, AnyKind, AnyRow};
+use sqlx::{Column, Executor, Row, Transaction, TypeInfo, ValueRef};
+
+/// Statements that a raw query can run, by their first keyword.
+const STATEMENTS: &[&str] = &[
+    "delete", "explain", "insert", "select", "update", "values", "with",
+];
+
+/// Functions that run SQL given as a string or reach outside the database, which would escape the
+/// check of the tables.
+const FUNCTIONS: &[&str] = &[
+    "cursor_to_xml",
+    "dblink",
+    "dblink_exec",
+    "load_extension",
+    "query_to_xml",
+    "query_to_xml_and_xmlschema",
+    "table_to_xml",
+    "table_to_xml_and_xmlschema",
+];
+
+/// Outcome of a raw query: the returned rows, and whether the statement may have written data.
+#[derive(Debug)]
+pub struct RawQueryResult {
+    pub rows: Vec<Map<String, JsonValue>>,
+    pub is_write: bool,
+}
+
+/// Runs the statement `sql` with the positional parameters `params` (`$1`, `$2`, ...) and
+/// returns its rows as JSON objects. The statement can only use the tables in `allowed_tables`.
+pub async fn raw_query(
+    txn: &mut Transaction<'_, Any>,
+    sql: &str,
+    params: &[JsonValue],
+    allowed_tables: &[String],
+) -> Result<RawQueryResult> {
+    let tables = list_tables(txn).await?;
+    let allowed_tables = allowed_tables
+        .iter()
+        .map(|table| table.to_lowercase())
+        .collect::<HashSet<_>>();
+    let is_write = check_statement(sql, &tables, &allowed_tables)?;
+    let mut query = sqlx::query(sql);
+    for (idx, param) in params.iter().enumerate() {
+        query = bind_param(query, param)
+            .with_context(|| format!("Cannot bind parameter ${}", idx + 1))?;
+    }
+    let rows = txn.fetch_all(query).await.context("Raw SQL query failed")?;
+    let rows = rows.iter().map(row_to_json).collect::<Result<_>>()?;
+    Ok(RawQueryResult { rows, is_write })
+}
+
+/// Returns the names of all tables and views of the database, in lowercase.
+async fn list_tables(txn: &mut Transaction<'_, Any>) -> Result<HashSet<String>> {
+    let query = match txn.kind() {
+        AnyKind::Sqlite => sqlx::query(
+            r#"
+            SELECT name
+            FROM sqlite_schema
+            WHERE type = 'table' OR type = 'view'"#,
+        ),
+        AnyKind::Postgres => sqlx::query(
+            r#"
+            SELECT tablename AS name
+            FROM pg_catalog.pg_tables
+            WHERE schemaname != 'pg_catalog' AND schemaname != 'information_schema'
+            UNION
+            SELECT viewname AS name
+            FROM pg_catalog.pg_views
+            WHERE schemaname != 'pg_catalog' AND schemaname != 'information_schema'"#,
+        ),
+    };
+    let rows = txn
+        .fetch_all(query)
+        .await
+        .context("Cannot list the tables for a raw SQL query")?;
+    Ok(rows
+        .iter()
+        .map(|row| row.get::<String, _>("name").to_lowercase())
+        .collect())
+}
+
+fn bind_param<'q>(
+    query: sqlx::query::Query<'q, Any, AnyArguments<'q>>,
+    param: &JsonValue,
+) -> Result<sqlx::query::Query<'q, Any, AnyArguments<'q>>> {
+    Ok(match param {
+        JsonValue::Null => query.bind(Option::<String>::None),
+        JsonValue::Bool(value) => query.bind(*value),
+        JsonValue::Number(value) => match value.as_i64() {
+            Some(value) => query.bind(value),
+            None => query.bind(value.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(value) => query.bind(value.clone()),
+        JsonValue::Array(_) | JsonValue::Object(_) => {
+            bail!("only null, booleans, numbers and strings can be used as parameters")
+        }
+    })
+}
+
+/// Converts a row into a JSON object. Values of types that have no obvious JSON representation
+/// must be cast in SQL, such as `CAST(price AS TEXT)`.
+fn row_to_json(row: &AnyRow) -> Result<Map<String, JsonValue>> {
+    let mut object = Map::new();
+    for column in row.columns() {
+        let idx = column.ordinal();
+        let name = column.name();
+        if row.try_get_raw(idx)?.is_null() {
+            object.insert(name.to_owned(), JsonValue::Null);
+            continue;
+        }
+        let type_name = column.type_info().name().to_uppercase();
+        let value = match type_name.as_str() {
+            "BOOL" | "BOOLEAN" => JsonValue::from(row.try_get::<bool, _>(idx)?),
+            "INT2" | "SMALLINT" => JsonValue::from(row.try_get::<i16, _>(idx)?),
+            "INT4" | "INT" => JsonValue::from(row.try_get::<i32, _>(idx)?),
+            "INT8" | "BIGINT" | "INTEGER" => JsonValue::from(row.try_get::<i64, _>(idx)?),
+            "FLOAT4" => JsonValue::from(row.try_get::<f32, _>(idx)?),
+            "FLOAT8" | "REAL" | "DOUBLE PRECISION" => JsonValue::from(row.try_get::<f64, _>(idx)?),
+            "TEXT" | "VARCHAR" | "CHAR" | "BPCHAR" | "NAME" => {
+                JsonValue::from(row.try_get::<String, _>(idx)?)
+            }
+            "BYTEA" | "BLOB" => JsonValue::from(base64::encode(row.try_get::<Vec<u8>, _>(idx)?)),
+            _ => bail!(
+                "column `{}` has type {}, which cannot be returned by a raw query; \
+                cast it to TEXT",
+                name,
+                type_name
+            ),
+        };
+        object.insert(name.to_owned(), value);
+    }
+    Ok(object)
+}
+
+/// Checks that `sql` is a single statement that reads or writes data and that every name in it
+/// that is one of `tables` (the tables of the database) is one of `allowed_tables`. Returns
+/// whether the statement may write data.
+fn check_statement(
+    sql: &str,
+    tables: &HashSet<String>,
+    allowed_tables: &HashSet<String>,
+) -> Result<bool> {
+    let tokens = tokenize(sql)?;
+    let keyword = match tokens.first() {
+        Some(Token::Word(word)) => word.clone(),
+        _ => bail!("the raw SQL query is empty"),
+    };
+    if let Some(idx) = tokens.iter().position(|t| *t == Token::Semicolon) {
+        if idx + 1 < tokens.len() {
+            bail!("a raw SQL query must be a single statement");
+        }
+    }
+    if !STATEMENTS.contains(&keyword.as_str()) {
+        bail!(
+            "raw SQL queries can only read and write data, not run `{}` statements",
+            keyword
+        );
+    }
+
+    for token in tokens.iter() {
+        let name = match token {
+            Token::Word(name) | Token::Quoted(name) => name.to_lowercase(),
+            Token::Semicolon => continue,
+        };
+        // the catalogs of the database and their functions
+        if name.starts_with("pg_")
+            || name.starts_with("sqlite_")
+            || name == "information_schema"
+            || FUNCTIONS.contains(&name.as_str())
+        {
+            bail!("raw SQL queries cannot use `{}`", name);
+        }
+        if tables.contains(&name) && !allowed_tables.contains(&name) {
+            bail!(
+                "raw SQL queries cannot use `{}`, which is not a table of this version",
+                name
+            );
+        }
+    }
+    let is_read = matches!(keyword.as_str(), "select" | "values" | "explain")
+        || (keyword == "with" && !tokens.iter().any(is_write_keyword));
+    Ok(!is_read)
+}
+
+fn is_write_keyword(token: &Token) -> bool {
+    matches!(token, Token::Word(word) if matches!(word.as_str(), "insert" | "update" | "delete"))
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// An unquoted word (keyword or identifier), in lowercase.
+    Word(String),
+    /// A quoted identifier, such as `"id"`.
+    Quoted(String),
+    Semicolon,
+}
+
+/// Splits `sql` into the tokens that `check_statement()` needs. String literals, comments,
+/// numbers, parameters and operators are skipped. The literals whose end is hard to find (the
+/// dollar-quoted and escaped strings of Postgres) are rejected, so that no name can hide in them.
+fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => skip_quoted(&mut chars, '\'').context("unterminated string literal")?,
+            '"' | '`' => {
+                let end = c;
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some(c) if c == end && chars.peek() == Some(&end) => {
+                            chars.next();
+                            name.push(c);
+                        }
+                        Some(c) if c == end => break,
+                        Some(c) => name.push(c),
+                        None => bail!("unterminated quoted identifier"),
+                    }
+                }
+                tokens.push(Token::Quoted(name));
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => prev = c,
+                        None => bail!("unterminated comment"),
+                    }
+                }
+            }
+            ';' => tokens.push(Token::Semicolon),
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_lowercase().to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '$') {
+                        break;
+                    }
+                    word.extend(c.to_lowercase());
+                    chars.next();
+                }
+                match (word.as_str(), chars.peek()) {
+                    ("e", Some('\'')) | ("u", Some('&')) => {
+                        bail!("raw SQL queries cannot use escaped strings, use parameters instead")
+                    }
+                    _ => {}
+                }
+                tokens.push(Token::Word(word));
+            }
+            '$' if !chars.peek().map_or(false, char::is_ascii_digit) => {
+                bail!("raw SQL queries cannot use dollar-quoted strings, use parameters instead")
+            }
+            c if c.is_ascii_digit() || c == '$' => {
+                let is_part = |c: &char| c.is_alphanumeric() || *c == '_' || *c == '.';
+                while chars.peek().map_or(false, is_part) {
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(tokens)
+}
+
+/// Skips the rest of a literal that is quoted by `quote`, where a doubled quote is an escaped
+/// quote.
+fn skip_quoted(chars: &mut std::iter::Peekable<std::str::Chars>, quote: char) -> Option<()> {
+    loop {
+        match chars.next()? {
+            c if c == quote && chars.peek() == Some(&quote) => {
+                chars.next();
+            }
+            c if c == quote => return Some(()),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(sql: &str) -> Result<bool> {
+        let tables = ["ent1_Book", "ent2_Book", "types", "secrets", "kv_1"]
+            .iter()
+            .map(|table| table.to_lowercase())
+            .collect();
+        let allowed_tables = ["ent1_Book", "kv_1"]
+            .iter()
+            .map(|table| table.to_lowercase())
+            .collect();
+        check_statement(sql, &tables, &allowed_tables)
+    }
+
+    #[test]
+    fn allowed_statements() {
+        assert!(!check("SELECT * FROM \"ent1_Book\" WHERE id = $1").unwrap());
+        assert!(!check("select count(*) from ent1_book; ").unwrap());
+        assert!(check("UPDATE \"ent1_Book\" SET title = 'x' WHERE id = $1").unwrap());
+        assert!(
+            check("WITH x AS (DELETE FROM \"ent1_Book\" RETURNING id) SELECT * FROM x").unwrap()
+        );
+        assert!(check("INSERT INTO kv_1 VALUES ($1, $2)").unwrap());
+        // the names of other tables in literals and comments are fine
+        assert!(!check("SELECT 'types' AS x -- FROM types").unwrap());
+    }
+
+    #[test]
+    fn rejected_statements() {
+        let error = |sql: &str| check(sql).unwrap_err().to_string();
+        assert_eq!(
+            error("SELECT * FROM types"),
+            "raw SQL queries cannot use `types`, which is not a table of this version"
+        );
+        assert_eq!(
+            error("select * from \"Secrets\""),
+            "raw SQL queries cannot use `secrets`, which is not a table of this version"
+        );
+        assert_eq!(
+            error("SELECT * FROM \"ent2_Book\""),
+            "raw SQL queries cannot use `ent2_book`, which is not a table of this version"
+        );
+        assert_eq!(
+            error("SELECT * FROM pg_catalog.pg_tables"),
+            "raw SQL queries cannot use `pg_catalog`"
+        );
+        assert_eq!(
+            error("SELECT name FROM sqlite_schema"),
+            "raw SQL queries cannot use `sqlite_schema`"
+        );
+        assert_eq!(
+            error("SELECT query_to_xml('SELECT * FROM types', true, true, '')"),
+            "raw SQL queries cannot use `query_to_xml`"
+        );
+        assert_eq!(
+            error("SELECT 1; DELETE FROM ent1_book"),
+            "a raw SQL query must be a single statement"
+        );
+        assert_eq!(
+            error("DROP TABLE ent1_book"),
+            "raw SQL queries can only read and write data, not run `drop` statements"
+        );
+        assert_eq!(
+            error("PRAGMA table_info(types)"),
+            "raw SQL queries can only read and write data, not run `pragma` statements"
+        );
+        assert_eq!(error("  -- nothing"), "the raw SQL query is empty");
+        assert_eq!(error("SELECT 'x"), "unterminated string literal");
+    }
+
+    #[test]
+    fn rejected_literals() {
+        let error = |sql: &str| check(sql).unwrap_err().to_string();
+        assert_eq!(
+            error("SELECT $$ ' $$ FROM types --'"),
+            "raw SQL queries cannot use dollar-quoted strings, use parameters instead"
+        );
+        assert_eq!(
+            error("SELECT $x$ ' $x$ FROM types --'"),
+            "raw SQL queries cannot use dollar-quoted strings, use parameters instead"
+        );
+        assert_eq!(
+            error("SELECT E'\\' FROM types --'"),
+            "raw SQL queries cannot use escaped strings, use parameters instead"
+        );
+        assert_eq!(
+            error("SELECT U&'\\0027' FROM types"),
+            "raw SQL queries cannot use escaped strings, use parameters instead"
+        );
+        // but the words that end with an `e` are fine
+        assert!(!check("SELECT title FROM \"ent1_Book\" WHERE title = 'e'").unwrap());
+    }
+}
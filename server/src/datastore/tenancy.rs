@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Isolation of the data per tenant.
+//!
+//! When chiseld is started with `--tenant-claim` or `--tenant-header`, the table of every entity
+//! gets a hidden `tenant_id` column, which is not a field of the entity. Rows are written with the
+//! tenant of the request (see `JobInfo::tenant_scope()`), and every `QueryPlan` reads only the rows
+//! of that tenant, including the rows of the joined entities. User code never names the column, so
+//! the same endpoints serve all tenants.
+//!
+//! The tenant of a request comes from a claim of its authenticated user (see `TenancyConfig`), so
+//! a client cannot pick the tenant by itself. The tenant header alone is only trusted behind a
+//! proxy that authenticates the clients.
+//!
+//! The entries of `Chisel.kv` and the blobs of `Chisel.blob` are isolated by prefixing their keys
+//! with the tenant (see `key_prefix()`).
+//!
+//! The constraints of the fields still span all tenants: a `@unique` value can only be used by
+//! one tenant. The rows of materialized aggregates are computed from the rows of all tenants and
+//! belong to no tenant, so only requests with the admin secret read them.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use serde_json::Value as JsonValue;
+
+use crate::datastore::QueryEngine;
+use crate::ops::job_context::TenantScope;
+use crate::opt::Opt;
+use crate::types::TypeSystem;
+
+/// Name of the hidden column that stores the tenant of a row.
+pub const TENANT_COLUMN: &str = "tenant_id";
+
+/// How the tenant of a request is identified.
+#[derive(Debug, Clone, Default)]
+pub struct TenancyConfig {
+    /// Claim of the authenticated user that holds the tenant (`--tenant-claim`).
+    pub claim: Option<String>,
+    /// Header that names the tenant (`--tenant-header`), in lowercase. With `claim`, it must agree
+    /// with the claim; alone, it is trusted (`--trust-tenant-header`).
+    pub header: Option<String>,
+}
+
+impl TenancyConfig {
+    /// Returns the configuration given by `opt`, `None` if the data is not isolated per tenant.
+    pub fn from_opt(opt: &Opt) -> Result<Option<Self>> {
+        let header = opt
+            .tenant_header
+            .as_ref()
+            .map(|header| header.to_lowercase());
+        if opt.tenant_claim.is_none() && header.is_some() && !opt.trust_tenant_header {
+            bail!(
+                "--tenant-header needs --tenant-claim, which checks the header against the \
+                authenticated user, or --trust-tenant-header, if chiseld is behind a proxy that \
+                authenticates the clients and sets the header"
+            );
+        }
+        if opt.tenant_claim.is_none() && header.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(TenancyConfig {
+            claim: opt.tenant_claim.clone(),
+            header,
+        }))
+    }
+
+    /// Returns the tenant of a request of the user with `claims`, whose tenant header is `header`.
+    /// With a tenant claim, the tenant is the value of the claim, and a request whose header names
+    /// another tenant is rejected. Without it, the tenant is the header.
+    pub fn tenant<'a>(
+        &self,
+        claims: Option<&'a JsonValue>,
+        header: Option<&'a str>,
+    ) -> Result<Option<&'a str>> {
+        let header = header.filter(|tenant| !tenant.is_empty());
+        let claim = match self.claim {
+            Some(ref claim) => claim,
+            None => return Ok(header),
+        };
+        let claimed = claims
+            .and_then(|claims| claims.get(claim)?.as_str())
+            .filter(|tenant| !tenant.is_empty());
+        match header {
+            Some(header) if Some(header) != claimed => {
+                bail!("the tenant header names tenant {header:?}, which is not the tenant of the user")
+            }
+            _ => Ok(claimed),
+        }
+    }
+}
+
+/// Adds the tenant column to the tables of the entities of all versions, which may have been
+/// created before the data was isolated per tenant. Their existing rows belong to no tenant.
+pub async fn add_tenant_columns(
+    query_engine: &QueryEngine,
+    type_systems: &HashMap<String, TypeSystem>,
+) -> Result<()> {
+    let mut transaction = query_engine.begin_transaction().await?;
+    for type_system in type_systems.values() {
+        for ty in type_system.custom_types.values() {
+            query_engine.add_tenant_column(&mut transaction, ty).await?;
+        }
+    }
+    QueryEngine::commit_transaction(transaction).await
+}
+
+/// Returns the tenant that the rows of `entity` written in `scope` are stored with, `None` if they
+/// are stored without a tenant (which only admins can read). Requests without a tenant cannot
+/// write.
+pub fn written_tenant<'a>(scope: TenantScope<'a>, entity: &str) -> Result<Option<&'a str>> {
+    match scope {
+        TenantScope::All => Ok(None),
+        TenantScope::Tenant(Some(tenant)) => Ok(Some(tenant)),
+        TenantScope::Tenant(None) => anyhow::bail!(
+            "Cannot write {entity}: the data is isolated per tenant, so it can only be written by requests of a tenant"
+        ),
+    }
+}
+
+/// Returns the prefix of the keys of `Chisel.kv` and `Chisel.blob` (named by `store`) that holds
+/// the entries of the tenant in `scope`, `None` if the job accesses the entries of all tenants.
+/// The tenant is hex-encoded, so no tenant is a prefix of another one and the prefix is a valid
+/// segment of a blob key. Jobs without a tenant cannot use the stores.
+pub fn key_prefix(scope: TenantScope, store: &str) -> Result<Option<String>> {
+    match scope {
+        TenantScope::All => Ok(None),
+        TenantScope::Tenant(Some(tenant)) => Ok(Some(format!("tenants/{}/", hex::encode(tenant)))),
+        TenantScope::Tenant(None) => bail!(
+            "Cannot use {store}: the data is isolated per tenant, so it can only be used by requests of a tenant"
+        ),
+    }
+}
+
+/// Rows that a `QueryPlan` reads, the owned counterpart of `TenantScope`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TenantFilter {
+    #[default]
+    All,
+    Tenant(Option<String>),
+}
+
+impl From<TenantScope<'_>> for TenantFilter {
+    fn from(scope: TenantScope) -> Self {
+        match scope {
+            TenantScope::All => TenantFilter::All,
+            TenantScope::Tenant(tenant) => TenantFilter::Tenant(tenant.map(ToOwned::to_owned)),
+        }
+    }
+}
+
+impl TenantFilter {
+    /// Returns the SQL condition that restricts the rows of the table (or table alias) `table`,
+    /// `None` if all rows are accessible.
+    pub fn condition(&self, table: &str) -> Option<String> {
+        match self {
+            TenantFilter::All => None,
+            TenantFilter::Tenant(Some(tenant)) => Some(format!(
+                r#""{table}"."{TENANT_COLUMN}" = '{}'"#,
+                tenant.replace('\'', "''")
+            )),
+            // requests without a tenant see no rows
+            TenantFilter::Tenant(None) => Some("false".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions() {
+        let condition = |scope| TenantFilter::from(scope).condition("Person");
+        assert_eq!(condition(TenantScope::All), None);
+        assert_eq!(
+            condition(TenantScope::Tenant(Some("o'reilly"))).unwrap(),
+            r#""Person"."tenant_id" = 'o''reilly'"#
+        );
+        assert_eq!(condition(TenantScope::Tenant(None)).unwrap(), "false");
+    }
+
+    #[test]
+    fn key_prefixes() {
+        let prefix = |scope| key_prefix(scope, "Chisel.kv");
+        assert_eq!(prefix(TenantScope::All).unwrap(), None);
+        assert_eq!(
+            prefix(TenantScope::Tenant(Some("a/b"))).unwrap().unwrap(),
+            "tenants/612f62/"
+        );
+        assert!(prefix(TenantScope::Tenant(None)).is_err());
+    }
+
+    #[test]
+    fn tenants() {
+        let claims = serde_json::json!({"sub": "alice", "org": "acme"});
+        let claims = Some(&claims);
+
+        let by_claim = TenancyConfig {
+            claim: Some("org".into()),
+            header: Some("x-tenant".into()),
+        };
+        assert_eq!(by_claim.tenant(claims, None).unwrap(), Some("acme"));
+        assert_eq!(by_claim.tenant(claims, Some("acme")).unwrap(), Some("acme"));
+        assert!(by_claim.tenant(claims, Some("globex")).is_err());
+        assert!(by_claim.tenant(None, Some("acme")).is_err());
+        assert_eq!(by_claim.tenant(None, None).unwrap(), None);
+
+        let by_header = TenancyConfig {
+            claim: None,
+            header: Some("x-tenant".into()),
+        };
+        assert_eq!(
+            by_header.tenant(claims, Some("globex")).unwrap(),
+            Some("globex")
+        );
+        assert_eq!(by_header.tenant(claims, Some("")).unwrap(), None);
+    }
+}
@@ -0,0 +1,439 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Export and import of the rows of entities (`chisel export` and `chisel import`).
+//!
+//! Rows are encoded as JSON objects, in the same form as they are read from the datastore.
+//! Imported rows are validated against the entity type like the rows uploaded to `ingest()`
+//! routes and they are inserted as they are, without applying the policies (a row with the id of
+//! an existing row overwrites that row). Related entities are exported separately, so a field
+//! that refers to another entity is imported as the id of that entity.
+//!
+//! For large tables, rows can also be transferred as CSV with a header, which is the format of
+//! Postgres `COPY ... WITH (FORMAT csv, HEADER true)`. On Postgres, CSV is dumped and loaded with
+//! `COPY`, which skips the validation of the values (except by the column types) and fails on
+//! rows whose id already exists; on SQLite, the rows are validated and inserted like JSON rows.
+//! Only entities whose fields are all scalars can be transferred as CSV, and the header is
+//! checked against the entity before any row is loaded.
+
+use crate::datastore::engine::QueryEngine;
+use crate::datastore::ingest::{row_to_entity, row_to_stored_entity, IngestFormat, RowParser};
+use crate::datastore::query::QueryPlan;
+use crate::datastore::value::{EntityMap, EntityValue};
+use crate::types::{Entity, Field, ObjectType, TypeId, TypeSystem};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{Future, StreamExt};
+use sqlx::{Any, Transaction};
+use std::collections::BTreeMap;
+
+/// Number of rows that are sent in a single message of the export.
+pub const EXPORT_BATCH_SIZE: usize = 500;
+
+/// Number of bytes of CSV that are sent in a single message of the export.
+pub const EXPORT_CSV_CHUNK_SIZE: usize = 1 << 20;
+
+/// Reads all rows of `entity`, passing them to `send_rows` in batches (a single empty batch if the
+/// entity has no rows). Returns the number of rows.
+pub async fn export_entity<F, Fut>(
+    engine: &QueryEngine,
+    entity: &Entity,
+    mut send_rows: F,
+) -> Result<u64>
+where
+    F: FnMut(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let txn = engine.begin_transaction_static().await?;
+    let mut row_stream = engine.query(txn.clone(), QueryPlan::from_type(entity))?;
+    let mut batch = Vec::new();
+    let mut count = 0;
+    while let Some(row) = row_stream.next().await {
+        let row = row.with_context(|| format!("Could not read rows of {}", entity.name()))?;
+        batch.push(serde_json::to_string(&row)?);
+        count += 1;
+        if batch.len() >= EXPORT_BATCH_SIZE {
+            send_rows(std::mem::take(&mut batch)).await?;
+        }
+    }
+    drop(row_stream);
+    QueryEngine::commit_transaction_static(txn).await?;
+
+    if !batch.is_empty() || count == 0 {
+        send_rows(batch).await?;
+    }
+    Ok(count)
+}
+
+/// Checks that the rows of `ty` can be transferred as CSV and returns the names of its columns.
+pub fn csv_columns(ty: &ObjectType) -> Result<Vec<String>> {
+    ty.all_fields()
+        .map(|field| {
+            check_csv_field(ty, field)?;
+            Ok(field.name.clone())
+        })
+        .collect()
+}
+
+fn check_csv_field(ty: &ObjectType, field: &Field) -> Result<()> {
+    match field.type_id {
+        TypeId::String
+        | TypeId::Id
+        | TypeId::Float
+        | TypeId::Int64
+        | TypeId::JsDate
+        | TypeId::Boolean
+        | TypeId::EntityId(_)
+        | TypeId::Entity { .. } => Ok(()),
+        _ => bail!(
+            "Field `{}` of {} has type {}, which cannot be transferred as CSV (use the jsonl format)",
+            field.name,
+            ty.name(),
+            field.type_id.name()
+        ),
+    }
+}
+
+/// Reads all rows of `entity` as CSV, passing whole records to `send_chunk` in chunks of about
+/// `EXPORT_CSV_CHUNK_SIZE` bytes, together with the number of rows in the chunk. The first chunk
+/// starts with the header. Returns the number of rows.
+pub async fn export_entity_csv<F, Fut>(
+    engine: &QueryEngine,
+    entity: &Entity,
+    mut send_chunk: F,
+) -> Result<u64>
+where
+    F: FnMut(Vec<u8>, u64) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let columns = csv_columns(entity)?;
+    let mut chunk = Vec::new();
+    let mut chunk_rows = 0;
+    let mut count = 0;
+    if engine.supports_copy() {
+        let mut txn = engine.begin_transaction().await?;
+        let mut records = engine.copy_out_csv(&mut txn, entity, &columns).await?;
+        // the first record is the header
+        let mut is_header = true;
+        while let Some(record) = records.next().await {
+            let record =
+                record.with_context(|| format!("Could not read rows of {}", entity.name()))?;
+            chunk.extend_from_slice(&record);
+            if !is_header {
+                chunk_rows += 1;
+                count += 1;
+            }
+            is_header = false;
+            if chunk.len() >= EXPORT_CSV_CHUNK_SIZE {
+                send_chunk(std::mem::take(&mut chunk), std::mem::take(&mut chunk_rows)).await?;
+            }
+        }
+        drop(records);
+        QueryEngine::commit_transaction(txn).await?;
+    } else {
+        let header = columns
+            .iter()
+            .map(|c| EntityValue::String(c.clone()))
+            .collect::<Vec<_>>();
+        write_csv_record(&mut chunk, header.iter().map(Some));
+
+        let txn = engine.begin_transaction_static().await?;
+        let mut row_stream = engine.query(txn.clone(), QueryPlan::from_type(entity))?;
+        while let Some(row) = row_stream.next().await {
+            let row = row.with_context(|| format!("Could not read rows of {}", entity.name()))?;
+            write_csv_record(&mut chunk, columns.iter().map(|c| row.get(c)));
+            chunk_rows += 1;
+            count += 1;
+            if chunk.len() >= EXPORT_CSV_CHUNK_SIZE {
+                send_chunk(std::mem::take(&mut chunk), std::mem::take(&mut chunk_rows)).await?;
+            }
+        }
+        drop(row_stream);
+        QueryEngine::commit_transaction_static(txn).await?;
+    }
+
+    if !chunk.is_empty() {
+        send_chunk(chunk, chunk_rows).await?;
+    }
+    Ok(count)
+}
+
+/// Appends a CSV record to `out`, formatting the values like Postgres does: nulls are empty and
+/// empty strings are quoted. Related entities are written as their ids.
+fn write_csv_record<'v>(out: &mut Vec<u8>, values: impl Iterator<Item = Option<&'v EntityValue>>) {
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        let text = match value {
+            Some(EntityValue::String(s)) => s.clone(),
+            Some(EntityValue::Float64(x) | EntityValue::JsDate(x)) => x.to_string(),
+            Some(EntityValue::Int64(x)) => x.to_string(),
+            Some(EntityValue::Boolean(x)) => x.to_string(),
+            Some(EntityValue::Map(related)) => match related.get("id") {
+                Some(EntityValue::String(id)) => id.clone(),
+                _ => continue,
+            },
+            // nulls, and the types rejected by `csv_columns()`
+            _ => continue,
+        };
+        let needs_quotes =
+            text.is_empty() || text.contains(|c| matches!(c, ',' | '"' | '\n' | '\r'));
+        if needs_quotes {
+            out.push(b'"');
+            out.extend_from_slice(text.replace('"', "\"\"").as_bytes());
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(text.as_bytes());
+        }
+    }
+    out.push(b'\n');
+}
+
+/// Parses the header (the first line) of CSV, returning the column names and the rest of `csv`.
+fn split_csv_header(csv: &[u8]) -> Result<(Vec<String>, &[u8])> {
+    let end = csv.iter().position(|&b| b == b'\n').unwrap_or(csv.len());
+    let header = std::str::from_utf8(&csv[..end]).context("The CSV header is not in UTF-8")?;
+    let columns = header
+        .trim_end_matches('\r')
+        .split(',')
+        .map(|column| {
+            let column = column.trim();
+            match column.strip_prefix('"').and_then(|c| c.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\"\"", "\""),
+                None => column.to_owned(),
+            }
+        })
+        .collect();
+    Ok((columns, &csv[(end + 1).min(csv.len())..]))
+}
+
+/// Checks that `columns` are distinct fields of `ty` that can be transferred as CSV and that no
+/// required field is missing.
+fn check_csv_header(ty: &ObjectType, columns: &[String]) -> Result<()> {
+    let mut seen = std::collections::HashSet::new();
+    for column in columns.iter() {
+        let field = ty
+            .get_field(column)
+            .with_context(|| format!("Entity {} has no field `{}`", ty.name(), column))?;
+        check_csv_field(ty, field)?;
+        anyhow::ensure!(seen.insert(column), "Column `{}` appears twice", column);
+    }
+    for field in ty.all_fields() {
+        anyhow::ensure!(
+            field.is_optional || seen.contains(&field.name),
+            "Column of the required field `{}` is missing",
+            field.name
+        );
+    }
+    Ok(())
+}
+
+/// Imports rows into the entities of a version in a single transaction, which is committed by
+/// `finish()`. If the importer is dropped before that, nothing is imported.
+pub struct Importer<'a> {
+    engine: &'a QueryEngine,
+    type_system: &'a TypeSystem,
+    txn: Transaction<'static, Any>,
+    /// Delete the existing rows of every entity before its first row is imported.
+    replace: bool,
+    entities: BTreeMap<String, ImportedEntity>,
+}
+
+struct ImportedEntity {
+    parser: RowParser,
+    /// Columns of the CSV header, if the rows are loaded with `COPY`.
+    copy_columns: Option<Vec<String>>,
+    rows: u64,
+}
+
+impl<'a> Importer<'a> {
+    pub async fn new(
+        engine: &'a QueryEngine,
+        type_system: &'a TypeSystem,
+        replace: bool,
+    ) -> Result<Importer<'a>> {
+        Ok(Self {
+            engine,
+            type_system,
+            txn: engine.begin_transaction().await?,
+            replace,
+            entities: BTreeMap::new(),
+        })
+    }
+
+    fn lookup_entity(&self, entity_name: &str) -> Result<Entity> {
+        self.type_system
+            .lookup_custom_type(entity_name)
+            .with_context(|| {
+                format!(
+                    "Entity {:?} does not exist in version {:?}",
+                    entity_name, self.type_system.version_id
+                )
+            })
+    }
+
+    /// Starts the import of the entity `ty`, deleting its rows if the import replaces them.
+    async fn begin_entity(&mut self, ty: &ObjectType, imported: ImportedEntity) -> Result<()> {
+        if self.replace {
+            self.engine.truncate_table(&mut self.txn, ty).await?;
+        }
+        self.entities.insert(ty.name().into(), imported);
+        Ok(())
+    }
+
+    /// Validates the JSON-encoded `rows` and inserts them into the entity `entity_name`.
+    pub async fn import_rows(&mut self, entity_name: &str, rows: &[String]) -> Result<()> {
+        let ty = self.lookup_entity(entity_name)?;
+        if !self.entities.contains_key(entity_name) {
+            let imported = ImportedEntity {
+                parser: RowParser::new(IngestFormat::Ndjson),
+                copy_columns: None,
+                rows: 0,
+            };
+            self.begin_entity(&ty, imported).await?;
+        }
+        let imported = self.entities.get_mut(entity_name).unwrap();
+
+        let mut parsed = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            imported
+                .parser
+                .push(row.as_bytes(), &mut parsed)
+                .and_then(|_| imported.parser.push(b"\n", &mut parsed))
+                .map_err(|e| anyhow!("Invalid row of {}: {}", entity_name, e))?;
+        }
+        for row in parsed {
+            let mut record = row_to_entity(&ty, row)
+                .map_err(|e| anyhow!("Invalid row of {}: {}", entity_name, e))?;
+            shallow_references(&ty, &mut record);
+            self.engine
+                .add_row_shallow(&mut self.txn, &ty, &record)
+                .await
+                .with_context(|| format!("Could not insert a row of {}", entity_name))?;
+            imported.rows += 1;
+        }
+        Ok(())
+    }
+
+    /// Imports a chunk of whole CSV records into the entity `entity_name`. The first chunk of the
+    /// entity must start with the header, which is checked before any row is loaded.
+    pub async fn import_csv(&mut self, entity_name: &str, csv: &[u8]) -> Result<()> {
+        let ty = self.lookup_entity(entity_name)?;
+        let mut csv = csv;
+        if !self.entities.contains_key(entity_name) {
+            let (columns, records) = split_csv_header(csv)?;
+            check_csv_header(&ty, &columns)
+                .with_context(|| format!("Invalid CSV header of {}", entity_name))?;
+            // without `COPY`, the header is read by the parser
+            let copy_columns = if self.engine.supports_copy() {
+                csv = records;
+                Some(columns)
+            } else {
+                None
+            };
+            let imported = ImportedEntity {
+                parser: RowParser::new(IngestFormat::Csv),
+                copy_columns,
+                rows: 0,
+            };
+            self.begin_entity(&ty, imported).await?;
+        }
+        let imported = self.entities.get_mut(entity_name).unwrap();
+        if csv.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(columns) = &imported.copy_columns {
+            imported.rows += self
+                .engine
+                .copy_in_csv(&mut self.txn, &ty, columns, csv)
+                .await
+                .with_context(|| format!("Could not load rows of {}", entity_name))?;
+            return Ok(());
+        }
+
+        let mut parsed = vec![];
+        imported
+            .parser
+            .push(csv, &mut parsed)
+            .map_err(|e| anyhow!("Invalid row of {}: {}", entity_name, e))?;
+        if !csv.ends_with(b"\n") {
+            imported
+                .parser
+                .push(b"\n", &mut parsed)
+                .map_err(|e| anyhow!("Invalid row of {}: {}", entity_name, e))?;
+        }
+        for row in parsed {
+            let record = row_to_stored_entity(&ty, row)
+                .map_err(|e| anyhow!("Invalid row of {}: {}", entity_name, e))?;
+            self.engine
+                .add_row_shallow(&mut self.txn, &ty, &record)
+                .await
+                .with_context(|| format!("Could not insert a row of {}", entity_name))?;
+            imported.rows += 1;
+        }
+        Ok(())
+    }
+
+    /// Commits the import and returns the number of rows imported into each entity.
+    pub async fn finish(self) -> Result<BTreeMap<String, u64>> {
+        QueryEngine::commit_transaction(self.txn).await?;
+        self.engine.clear_query_cache();
+        Ok(self
+            .entities
+            .into_iter()
+            .map(|(name, imported)| (name, imported.rows))
+            .collect())
+    }
+}
+
+/// Replaces the related entities in `record` (which are exported as nested objects) with their
+/// ids, which is how they are stored in the table of `ty`.
+fn shallow_references(ty: &ObjectType, record: &mut EntityMap) {
+    for field in ty.user_fields() {
+        if let TypeId::Entity { .. } = field.type_id {
+            if let Some(EntityValue::Map(related)) = record.get(&field.name) {
+                if let Some(id) = related.get("id").cloned() {
+                    record.insert(field.name.clone(), id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_records() {
+        let mut related = EntityMap::new();
+        related.insert("id".into(), EntityValue::String("x1".into()));
+        let values = [
+            Some(EntityValue::String("plain".into())),
+            Some(EntityValue::String("a, \"b\"".into())),
+            Some(EntityValue::String("".into())),
+            None,
+            Some(EntityValue::Null),
+            Some(EntityValue::Float64(1.5)),
+            Some(EntityValue::Boolean(true)),
+            Some(EntityValue::Map(related)),
+        ];
+        let mut out = vec![];
+        write_csv_record(&mut out, values.iter().map(Option::as_ref));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "plain,\"a, \"\"b\"\"\",\"\",,,1.5,true,x1\n"
+        );
+    }
+
+    #[test]
+    fn csv_header() {
+        let (columns, rest) = split_csv_header(b"id,\"name\" ,age\r\na,b,1\n").unwrap();
+        assert_eq!(columns, ["id", "name", "age"]);
+        assert_eq!(rest, b"a,b,1\n");
+
+        let (columns, rest) = split_csv_header(b"id").unwrap();
+        assert_eq!(columns, ["id"]);
+        assert!(rest.is_empty());
+    }
+}
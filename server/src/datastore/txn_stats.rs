@@ -0,0 +1,197 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Statistics of the transactions opened by user code, reported on the internal `/transactions`
+//! route (see `internal.rs`), so that operators can find out which route is holding a transaction
+//! open for too long.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Default)]
+pub struct TxnStats {
+    next_id: AtomicU64,
+    started: AtomicU64,
+    committed: AtomicU64,
+    rolled_back: AtomicU64,
+    active: Mutex<HashMap<u64, Arc<ActiveTxn>>>,
+}
+
+struct ActiveTxn {
+    version_id: String,
+    /// Description of the job that opened the transaction (such as `GET /dev/people`).
+    job: String,
+    started_at: Instant,
+    rows_read: AtomicU64,
+    rows_written: AtomicU64,
+}
+
+/// Registration of an active transaction; the transaction is counted as rolled back when this
+/// guard is dropped, unless it was committed with `TxnGuard::commit()`.
+pub struct TxnGuard {
+    stats: Arc<TxnStats>,
+    id: u64,
+    txn: Arc<ActiveTxn>,
+    committed: bool,
+}
+
+/// Counters of rows touched by an active transaction, which can be moved into `Send` futures and
+/// streams.
+#[derive(Clone)]
+pub struct TxnCounters(Arc<ActiveTxn>);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxnStatsReport {
+    pub started: u64,
+    pub committed: u64,
+    pub rolled_back: u64,
+    /// Active transactions for every version, the oldest first.
+    pub active: BTreeMap<String, Vec<ActiveTxnReport>>,
+    /// Only reported on Postgres.
+    pub lock_waits: Option<Vec<LockWait>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveTxnReport {
+    pub job: String,
+    pub age_s: f64,
+    pub rows_read: u64,
+    pub rows_written: u64,
+}
+
+/// Database session that waits for a lock held by another session, while it touches some of the
+/// backing tables of entities.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockWait {
+    pub pid: i32,
+    pub blocking_pids: Vec<i32>,
+    pub waiting_s: Option<f64>,
+    pub tables: Vec<String>,
+    pub query: String,
+}
+
+impl TxnStats {
+    pub fn begin(self: &Arc<Self>, version_id: String, job: String) -> TxnGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let txn = Arc::new(ActiveTxn {
+            version_id,
+            job,
+            started_at: Instant::now(),
+            rows_read: AtomicU64::new(0),
+            rows_written: AtomicU64::new(0),
+        });
+        self.started.fetch_add(1, Ordering::Relaxed);
+        self.active.lock().insert(id, txn.clone());
+        TxnGuard {
+            stats: self.clone(),
+            id,
+            txn,
+            committed: false,
+        }
+    }
+
+    pub fn report(&self, lock_waits: Option<Vec<LockWait>>) -> TxnStatsReport {
+        let now = Instant::now();
+        // ids are assigned in the order in which the transactions started
+        let mut active: BTreeMap<String, Vec<(u64, ActiveTxnReport)>> = BTreeMap::new();
+        for (&id, txn) in self.active.lock().iter() {
+            let report = ActiveTxnReport {
+                job: txn.job.clone(),
+                age_s: now.duration_since(txn.started_at).as_secs_f64(),
+                rows_read: txn.rows_read.load(Ordering::Relaxed),
+                rows_written: txn.rows_written.load(Ordering::Relaxed),
+            };
+            active
+                .entry(txn.version_id.clone())
+                .or_default()
+                .push((id, report));
+        }
+        let active = active
+            .into_iter()
+            .map(|(version_id, mut txns)| {
+                txns.sort_by_key(|(id, _)| *id);
+                (version_id, txns.into_iter().map(|(_, t)| t).collect())
+            })
+            .collect();
+
+        TxnStatsReport {
+            started: self.started.load(Ordering::Relaxed),
+            committed: self.committed.load(Ordering::Relaxed),
+            rolled_back: self.rolled_back.load(Ordering::Relaxed),
+            active,
+            lock_waits,
+        }
+    }
+}
+
+impl TxnGuard {
+    pub fn counters(&self) -> TxnCounters {
+        TxnCounters(self.txn.clone())
+    }
+
+    pub fn add_rows_written(&self, rows: u64) {
+        self.counters().add_rows_written(rows);
+    }
+
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl TxnCounters {
+    pub fn add_rows_read(&self, rows: u64) {
+        self.0.rows_read.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub fn add_rows_written(&self, rows: u64) {
+        self.0.rows_written.fetch_add(rows, Ordering::Relaxed);
+    }
+}
+
+impl Drop for TxnGuard {
+    fn drop(&mut self) {
+        self.stats.active.lock().remove(&self.id);
+        let counter = if self.committed {
+            &self.stats.committed
+        } else {
+            &self.stats.rolled_back
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_transactions() {
+        let stats = Arc::new(TxnStats::default());
+        let first = stats.begin("dev".into(), "GET /dev/people".into());
+        let second = stats.begin("dev".into(), "POST /dev/people".into());
+        let third = stats.begin("staging".into(), "kafka outbox".into());
+        second.counters().add_rows_read(3);
+        second.add_rows_written(1);
+
+        let report = stats.report(None);
+        assert_eq!(report.started, 3);
+        let dev = &report.active["dev"];
+        assert_eq!(dev.len(), 2);
+        assert_eq!(dev[0].job, "GET /dev/people");
+        assert_eq!((dev[1].rows_read, dev[1].rows_written), (3, 1));
+        assert_eq!(report.active["staging"].len(), 1);
+
+        first.commit();
+        drop(second);
+        third.commit();
+        let report = stats.report(None);
+        assert_eq!((report.committed, report.rolled_back), (2, 1));
+        assert!(report.active.is_empty());
+    }
+}
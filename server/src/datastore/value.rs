@@ -37,6 +37,15 @@ impl EntityValue {
             EntityValue::String(v.to_string(scope).unwrap().to_rust_string_lossy(scope))
         } else if v.is_number() {
             EntityValue::Float64(v.to_number(scope).unwrap().value())
+        } else if v.is_big_int() {
+            let big_int = v8::Local::<v8::BigInt>::try_from(*v).unwrap();
+            let (value, lossless) = big_int.i64_value();
+            anyhow::ensure!(
+                lossless,
+                "bigint {} does not fit in 64 bits",
+                v.to_rust_string_lossy(scope)
+            );
+            EntityValue::Int64(value)
         } else if v.is_boolean() {
             EntityValue::Boolean(v.boolean_value(scope))
         } else if v.is_date() {
@@ -132,7 +141,7 @@ impl EntityValue {
                 .context("failed to create v8 string when converting EntityValue to v8")?
                 .into(),
             Self::Float64(v) => v8::Number::new(scope, *v).into(),
-            Self::Int64(v) => v8::Number::new(scope, *v as f64).into(),
+            Self::Int64(v) => v8::BigInt::new_from_i64(scope, *v).into(),
             Self::Boolean(v) => v8::Boolean::new(scope, *v).into(),
             Self::JsDate(v) => v8::Date::new(scope, *v)
                 .context("failed to create v8 Date when converting EntityValue to v8")?
@@ -0,0 +1,323 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Human-readable documentation of the entities and routes of a version, served as a static HTML
+//! page under `/<version>/__docs` (see `http.rs`).
+//!
+//! The page is rendered from the same data as the OpenAPI document (see `openapi.rs`): the
+//! entities come from the type system of the version and the routes from `op_chisel_set_routes`.
+
+use crate::openapi::convert_path_pattern;
+use crate::server::Server;
+use crate::types::{Entity, Field, ObjectType, Type, TypeSystem};
+use crate::version::{RouteInfo, Version};
+use serde_json::{json, Map, Value};
+use std::fmt::Write;
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 60em; margin: 2em auto; padding: 0 1em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+code, pre { background: #f4f4f4; }
+pre { padding: 0.6em; overflow-x: auto; }
+";
+
+/// Renders the page that links to the documentation of every version.
+pub fn index_page(server: &Server) -> String {
+    let mut versions = server.trunk.list_versions();
+    versions.retain(|version| version.version_id != "__chiselstrike");
+    versions.sort_unstable_by(|x, y| x.version_id.cmp(&y.version_id));
+
+    let mut body = String::from("<h1>ChiselStrike API documentation</h1>\n<ul>\n");
+    for version in versions.iter() {
+        let id = escape(&version.version_id);
+        writeln!(body, "<li><a href=\"/{id}/__docs\">{id}</a></li>").unwrap();
+    }
+    body.push_str("</ul>\n");
+    page("ChiselStrike API documentation", &body)
+}
+
+/// Renders the documentation of a version. `base_url` (such as `http://localhost:8080`) is used
+/// in the example requests.
+pub fn version_page(version: &Version, base_url: &str) -> String {
+    let version_id = &version.version_id;
+    let ts = &version.type_system;
+    let title = format!("API documentation of version {}", version_id);
+
+    let mut entities = ts.custom_types.values().collect::<Vec<_>>();
+    entities.sort_unstable_by(|x, y| x.name().cmp(y.name()));
+    let mut routes = version.routes.read().clone();
+    routes.sort_by(|x, y| x.path_pattern.cmp(&y.path_pattern));
+
+    let mut body = String::new();
+    writeln!(body, "<h1>{}</h1>", escape(&title)).unwrap();
+    if !version.info.tag.is_empty() {
+        writeln!(
+            body,
+            "<p>Tag: <code>{}</code></p>",
+            escape(&version.info.tag)
+        )
+        .unwrap();
+    }
+    body.push_str(
+        "<p>Machine-readable descriptions are available at \
+        <a href=\"/__openapi.json\"><code>/__openapi.json</code></a> and \
+        <a href=\"/__jsonschema.json\"><code>/__jsonschema.json</code></a>.</p>\n",
+    );
+
+    body.push_str("<h2>Entities</h2>\n");
+    if entities.is_empty() {
+        body.push_str("<p>This version defines no entities.</p>\n");
+    }
+    for entity in entities.iter() {
+        write_entity(&mut body, ts, entity);
+    }
+
+    body.push_str("<h2>Routes</h2>\n");
+    if routes.is_empty() {
+        body.push_str("<p>This version defines no routes.</p>\n");
+    }
+    for route in routes.iter() {
+        write_route(&mut body, version, route, base_url);
+    }
+    page(&title, &body)
+}
+
+fn write_entity(body: &mut String, ts: &TypeSystem, entity: &ObjectType) {
+    let name = escape(entity.name());
+    writeln!(body, "<h3 id=\"entity-{name}\">{name}</h3>").unwrap();
+    body.push_str("<table>\n<tr><th>Field</th><th>Type</th><th>Notes</th></tr>\n");
+    for field in entity.all_fields() {
+        let type_name = match ts.get(&field.type_id) {
+            Ok(Type::Entity(Entity::Custom(ty))) => {
+                let name = escape(ty.name());
+                format!("<a href=\"#entity-{name}\">{name}</a>")
+            }
+            Ok(ty) => escape(&ty.name()),
+            Err(_) => escape(&field.type_id.name()),
+        };
+        writeln!(
+            body,
+            "<tr><td><code>{}</code></td><td><code>{}</code></td><td>{}</td></tr>",
+            escape(&field.name),
+            type_name,
+            escape(&field_notes(field).join(", ")),
+        )
+        .unwrap();
+    }
+    body.push_str("</table>\n");
+}
+
+fn field_notes(field: &Field) -> Vec<String> {
+    let mut notes = Vec::new();
+    if field.name == "id" {
+        notes.push("generated by the server".into());
+    }
+    if field.is_optional {
+        notes.push("optional".into());
+    }
+    if field.is_unique {
+        notes.push("unique".into());
+    }
+    if let Some(default) = field.user_provided_default() {
+        notes.push(format!("default {}", default));
+    }
+    if field.is_version {
+        notes.push("version".into());
+    }
+    if field.timestamp.is_some() || field.count.is_some() || field.aggregate.is_some() {
+        notes.push("maintained by the server".into());
+    }
+    if let Some(enum_type) = &field.enum_type {
+        notes.push(format!("one of {}", enum_type.values.join(" | ")));
+    }
+    if let Some(max_bytes) = field.max_bytes {
+        notes.push(format!("at most {} bytes", max_bytes));
+    }
+    if field.is_nocase() {
+        notes.push("case-insensitive".into());
+    }
+    if !field.labels.is_empty() {
+        notes.push(format!("labels {}", field.labels.join(" ")));
+    }
+    notes
+}
+
+fn write_route(body: &mut String, version: &Version, route: &RouteInfo, base_url: &str) {
+    let (path, _) = convert_path_pattern(&route.path_pattern);
+    let path = format!("/{}{}", version.version_id, path);
+    let methods = route
+        .methods
+        .iter()
+        .map(|method| match method.as_str() {
+            "*" => "ANY".to_string(),
+            method => method.to_uppercase(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    writeln!(
+        body,
+        "<h3><code>{} {}</code></h3>",
+        escape(&methods),
+        escape(&path)
+    )
+    .unwrap();
+
+    if let Some(crud) = &route.crud {
+        let entity = escape(&crud.entity_name);
+        writeln!(
+            body,
+            "<p>{} of <a href=\"#entity-{entity}\">{entity}</a> created by <code>crud()</code>.</p>",
+            escape(&crud_description(&crud.kind))
+        )
+        .unwrap();
+    } else if let Some(ingest) = &route.ingest {
+        let entity = escape(&ingest.entity_name);
+        writeln!(
+            body,
+            "<p>Bulk ingestion of <a href=\"#entity-{entity}\">{entity}</a> created by \
+            <code>ingest()</code>.</p>"
+        )
+        .unwrap();
+    } else if route.upload {
+        writeln!(
+            body,
+            "<p>Streamed multipart file upload created by <code>upload()</code>.</p>"
+        )
+        .unwrap();
+    }
+
+    let example = example_request(version, route, &path, base_url);
+    writeln!(body, "<pre>{}</pre>", escape(&example)).unwrap();
+}
+
+fn crud_description(kind: &str) -> String {
+    match kind {
+        "GetOne" => "Returns one instance".into(),
+        "GetMany" => "Returns a page of instances".into(),
+        "PostOne" => "Creates an instance".into(),
+        "PutOne" => "Replaces an instance".into(),
+        "PatchOne" => "Updates an instance".into(),
+        "DeleteOne" => "Deletes an instance".into(),
+        "DeleteMany" => "Deletes the matching instances".into(),
+        kind => format!("{} handler", kind),
+    }
+}
+
+/// Builds an example `curl` command for the route. The body is an example instance of the entity
+/// for the CRUD routes that accept one.
+fn example_request(version: &Version, route: &RouteInfo, path: &str, base_url: &str) -> String {
+    let method = match route.methods.first().map(String::as_str) {
+        None | Some("*") => "GET".to_string(),
+        Some(method) => method.to_uppercase(),
+    };
+    let mut example = format!("curl -X {} '{}{}'", method, base_url, path);
+    let entity = route
+        .crud
+        .as_ref()
+        .filter(|crud| matches!(crud.kind.as_str(), "PostOne" | "PutOne" | "PatchOne"))
+        .map(|crud| &crud.entity_name)
+        .and_then(|name| version.type_system.lookup_custom_type(name).ok());
+    if let Some(entity) = entity {
+        let body = example_entity(&version.type_system, &entity);
+        let body = serde_json::to_string(&body).unwrap().replace('\'', "'\\''");
+        write!(
+            example,
+            " \\\n  -H 'content-type: application/json' \\\n  -d '{}'",
+            body
+        )
+        .unwrap();
+    }
+    example
+}
+
+fn example_entity(ts: &TypeSystem, entity: &ObjectType) -> Value {
+    let mut object = Map::new();
+    for field in entity.user_fields() {
+        let managed = field.is_version
+            || field.timestamp.is_some()
+            || field.count.is_some()
+            || field.aggregate.is_some();
+        if managed || field.is_optional || field.user_provided_default().is_some() {
+            continue;
+        }
+        let value = match &field.enum_type {
+            Some(enum_type) if !enum_type.values.is_empty() => json!(enum_type.values[0]),
+            _ => match ts.get(&field.type_id) {
+                Ok(ty) => example_value(&ty),
+                Err(_) => Value::Null,
+            },
+        };
+        object.insert(field.name.clone(), value);
+    }
+    Value::Object(object)
+}
+
+fn example_value(ty: &Type) -> Value {
+    match ty {
+        Type::String => json!("string"),
+        Type::Float => json!(0),
+        Type::Int64 => json!("0"),
+        Type::Decimal { scale, .. } => json!(format!("{:.*}", *scale as usize, 0.0)),
+        Type::Boolean => json!(false),
+        Type::JsDate => json!("2022-01-01T00:00:00.000Z"),
+        Type::ArrayBuffer => json!(""),
+        Type::Json => Value::Null,
+        Type::GeoPoint => json!({ "lat": 0, "lng": 0 }),
+        Type::EntityId(_) => json!("00000000-0000-0000-0000-000000000000"),
+        Type::Entity(_) => json!({}),
+        Type::Array(_) => json!([]),
+    }
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+        <style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape("<a href=\"x\">Tom & 'Jerry'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn example_values() {
+        assert_eq!(
+            example_value(&Type::Decimal {
+                precision: 10,
+                scale: 2
+            }),
+            json!("0.00")
+        );
+        assert_eq!(
+            example_value(&Type::Array(Box::new(Type::String))),
+            json!([])
+        );
+    }
+}
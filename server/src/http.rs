@@ -1,25 +1,55 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-use crate::authentication::{authenticate, Authentication};
-use crate::authorization::authorize;
+use crate::authentication::{authenticate, claimed_roles, Authentication};
+use crate::authorization::{
+    authorize, authorize_policy_trace, authorize_sandbox, has_admin_secret, is_admin,
+    ADMIN_SECRET_HEADER, SANDBOX_HEADER,
+};
+use crate::blob;
+use crate::cancel::{cancel_pair, CancelToken};
+use crate::contract;
+use crate::docs;
 use crate::error::{Error as ChiselError, ErrorKind};
+use crate::json_schema;
+use crate::logging::log_event;
+use crate::mirror::{Mirror, MirrorOutcome};
+use crate::oidc;
+use crate::openapi;
+use crate::policies::RateLimitKey;
+use crate::rate_limit::BucketKey;
+use crate::request_log::RequestLogRecord;
 use crate::server::Server;
-use crate::version::{Version, VersionJob};
+use crate::socket::{self, SocketInfo};
+use crate::static_files::{self, STATIC_PATH};
+use crate::telemetry;
+use crate::version::{module_hash, Version, VersionJob};
 use anyhow::{Context, Error, Result};
 use deno_core::serde_v8;
 use enclose::enclose;
 use futures::stream::{FuturesUnordered, TryStreamExt};
 use futures::FutureExt;
+use hyper::server::conn::AddrStream;
 use lazy_static::lazy_static;
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt};
+use opentelemetry::KeyValue;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use std::convert::Infallible;
 use std::future::ready;
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
 use utils::TaskHandle;
+use uuid::Uuid;
+
+/// Header that carries the trace id of a request. Clients can set it to correlate the logs of
+/// chiseld with their own; otherwise, a new trace id is generated. It is always returned in the
+/// response.
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
 
 pub async fn spawn(
     server: Arc<Server>,
@@ -28,12 +58,14 @@ pub async fn spawn(
     let servers = FuturesUnordered::new();
     let mut local_addrs = Vec::new();
     for addr in tokio::net::lookup_host(listen_addr).await? {
-        let make_service = hyper::service::make_service_fn(enclose! {(server) move |_conn| {
-            let service = hyper::service::service_fn(enclose!{(server) move |request| {
-                handle_request(server.clone(), request).map(Ok::<_, Infallible>)
+        let make_service =
+            hyper::service::make_service_fn(enclose! {(server) move |conn: &AddrStream| {
+                let remote_addr = conn.remote_addr();
+                let service = hyper::service::service_fn(enclose!{(server) move |request| {
+                    handle_request(server.clone(), remote_addr, request).map(Ok::<_, Infallible>)
+                }});
+                ready(Ok::<_, Infallible>(service))
             }});
-            ready(Ok::<_, Infallible>(service))
-        }});
 
         // TODO: implement graceful shutdown?
         let incoming = hyper::server::conn::AddrIncoming::bind(&addr)?;
@@ -54,21 +86,95 @@ pub async fn spawn(
 
 async fn handle_request(
     server: Arc<Server>,
+    remote_addr: SocketAddr,
     request: hyper::Request<hyper::Body>,
 ) -> hyper::Response<hyper::Body> {
+    let start = Instant::now();
+    let received = SystemTime::now();
     let method = request.method().clone();
     let uri = request.uri().clone();
-    let mut response = try_handle_request(server, request)
+    let trace_id = get_trace_id(&request);
+    let otel_cx = telemetry::start_span(
+        &telemetry::extract_context(request.headers()),
+        format!("HTTP {}", method),
+        SpanKind::Server,
+        vec![
+            KeyValue::new("http.method", method.to_string()),
+            KeyValue::new("http.target", uri.path().to_owned()),
+            KeyValue::new("chisel.trace_id", trace_id.clone()),
+        ],
+    );
+    let request_log = server.request_log.clone();
+    let mut response = try_handle_request(server, remote_addr, request, &trace_id, &otel_cx)
         .await
-        .unwrap_or_else(|err| handle_error(&method, &uri, err));
+        .unwrap_or_else(|err| handle_error(&method, &uri, &trace_id, err));
+    let span = otel_cx.span();
+    span.set_attribute(KeyValue::new(
+        "http.status_code",
+        response.status().as_u16() as i64,
+    ));
+    if response.status().is_server_error() {
+        span.set_status(Status::error(response.status().to_string()));
+    }
+    span.end();
     add_default_headers(&mut response);
-    debug!("{} {} -> {}", method, uri, response.status());
+    if let Ok(value) = hyper::header::HeaderValue::from_str(&trace_id) {
+        response.headers_mut().insert(TRACE_ID_HEADER, value);
+    }
+    log_event(
+        log::Level::Info,
+        module_path!(),
+        format_args!("Handled request"),
+        vec![
+            ("method", method.to_string()),
+            ("path", uri.path().into()),
+            ("status", response.status().as_u16().to_string()),
+            ("duration_ms", start.elapsed().as_millis().to_string()),
+            ("trace_id", trace_id.clone()),
+        ],
+    );
+    if let Some(request_log) = request_log {
+        let version_id = get_version_path(uri.path()).map(|(version_id, _)| version_id);
+        if request_log.sample(version_id) {
+            request_log.record(RequestLogRecord::new(
+                received,
+                version_id.map(ToString::to_string),
+                method.to_string(),
+                uri.path().into(),
+                response.status().as_u16(),
+                start.elapsed(),
+                trace_id,
+            ));
+        }
+    }
     response
 }
 
+/// Returns the trace id that the client sent with the request, or generates a new one.
+fn get_trace_id(request: &hyper::Request<hyper::Body>) -> String {
+    request
+        .headers()
+        .get(TRACE_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_trace_id(id))
+        .map(ToString::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_simple().to_string())
+}
+
+fn is_valid_trace_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
 async fn try_handle_request(
     server: Arc<Server>,
+    remote_addr: SocketAddr,
     request: hyper::Request<hyper::Body>,
+    trace_id: &str,
+    otel_cx: &opentelemetry::Context,
 ) -> Result<hyper::Response<hyper::Body>> {
     let path = request.uri().path();
     let normalized_path = normalize_path(path);
@@ -80,16 +186,83 @@ async fn try_handle_request(
         return Ok(handle_index(server));
     }
 
+    if path == "/__openapi.json" {
+        return Ok(handle_openapi(&server));
+    }
+
+    if path == "/__jsonschema.json" {
+        return Ok(handle_json_schema(&server));
+    }
+
+    if path == DOCS_PATH {
+        return Ok(handle_docs(&server, None, request));
+    }
+
+    if let Some(oidc) = &server.oidc {
+        if path == oidc::LOGIN_PATH {
+            return oidc::handle_login(&server, oidc, request.uri())
+                .await
+                .or_else(handle_chisel_error);
+        }
+        if path == oidc::CALLBACK_PATH {
+            return oidc::handle_callback(&server, oidc, request.uri(), request.headers())
+                .await
+                .or_else(handle_chisel_error);
+        }
+    }
+
+    if let Some(blob_path) = path.strip_prefix(blob::SIGNED_PATH) {
+        if let Some(store) = &server.blob_store {
+            return blob::handle_signed(store.as_ref(), &request, blob_path)
+                .await
+                .or_else(handle_chisel_error);
+        }
+    }
+
     if *request.method() == hyper::Method::OPTIONS {
         return Ok(handle_options());
     }
 
     if let Some((version_id, routing_path)) = get_version_path(path) {
         if let Some(trunk_version) = server.trunk.get_trunk_version(version_id) {
+            if let Some(module_path) = routing_path.strip_prefix(MODULES_PATH) {
+                return Ok(handle_modules(
+                    &server,
+                    &trunk_version.version,
+                    request,
+                    module_path,
+                ));
+            }
+            let static_files = &trunk_version.version.static_files;
+            if !static_files.is_empty() {
+                if let Some(static_path) = static_file_path(routing_path) {
+                    return Ok(static_files::handle(static_files, &request, static_path));
+                }
+            }
+            if routing_path == EXAMPLES_PATH {
+                return handle_examples(&server, &trunk_version.version, request).await;
+            }
+            if routing_path == DOCS_PATH {
+                return Ok(handle_docs(
+                    &server,
+                    Some(trunk_version.version.as_ref()),
+                    request,
+                ));
+            }
             let version = trunk_version.version;
             let job_tx = trunk_version.job_tx;
             let routing_path = routing_path.into();
-            return handle_version_request(server, version, job_tx, request, routing_path).await;
+            return handle_version_request(
+                server,
+                version,
+                job_tx,
+                remote_addr,
+                request,
+                routing_path,
+                trace_id,
+                otel_cx,
+            )
+            .await;
         } else {
             return Ok(handle_not_found(format!(
                 "Unknown version {:?}",
@@ -105,7 +278,14 @@ async fn try_handle_request(
 pub struct HttpRequestResponse {
     pub request: HttpRequest,
     pub authentication: Authentication,
+    /// If true, the request is executed in a sandbox: its transaction is always rolled back.
+    pub sandbox: bool,
+    /// If true, the request carries the admin secret, so it can access the rows of all owners of
+    /// `@ownedBy` entities.
+    pub admin: bool,
     pub response_tx: oneshot::Sender<HttpResponse>,
+    /// Cancelled when the client disconnects or the deadline of the request expires.
+    pub cancel: CancelToken,
 }
 
 /// HTTP request that is passed to JavaScript.
@@ -117,7 +297,65 @@ pub struct HttpRequest {
     pub headers: Vec<(String, String)>,
     pub body: serde_v8::ZeroCopyBuf,
     pub routing_path: String,
+    pub ctx: RequestContext,
+    /// OpenTelemetry context of the span that the job of the request is a child of.
+    #[serde(skip)]
+    pub otel_cx: opentelemetry::Context,
+    /// Body of requests to `ingest()` and `upload()` routes, which is streamed instead of being
+    /// passed in `body`.
+    #[serde(skip)]
+    pub body_stream: Option<hyper::Body>,
+    /// If true, the policy decisions are returned in the `POLICY_TRACE_HEADER` of the response.
+    #[serde(skip)]
+    pub policy_trace: bool,
+}
+
+/// Context of an HTTP request, which is constructed once per request and passed to the handlers
+/// (and, in part, to the policies) as `ctx`.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestContext {
+    /// Id of the version that handles the request.
+    pub version_id: String,
+    /// Id of the request, which is attached to its log records and returned in the
+    /// `TRACE_ID_HEADER` header.
+    pub request_id: String,
+    /// W3C `traceparent` of the span of the request, if the request is traced.
+    pub traceparent: Option<String>,
+    /// IP address of the client that sent the request.
+    pub client_ip: String,
+    /// Id of the user. Like `claims` and `roles`, it is resolved before the authentication hook
+    /// runs, which replaces the user in JavaScript (see `JobInfo::authentication()`).
     pub user_id: Option<String>,
+    /// Tenant of the request when the data is isolated per tenant, see `JobInfo::tenant_scope()`.
+    /// It is resolved in JavaScript after the authentication hook (see `op_chisel_resolve_tenant`).
+    pub tenant_id: Option<String>,
+    /// Claims about the user, from the JWT.
+    pub claims: Option<serde_json::Value>,
+    /// Roles of the user, see `claimed_roles()`.
+    pub roles: Vec<String>,
+}
+
+impl RequestContext {
+    fn new(
+        version: &Version,
+        request_id: &str,
+        otel_cx: &opentelemetry::Context,
+        remote_addr: SocketAddr,
+        authentication: &Authentication,
+    ) -> Self {
+        let claims = authentication.claims().cloned();
+        Self {
+            version_id: version.version_id.clone(),
+            request_id: request_id.into(),
+            traceparent: telemetry::traceparent(otel_cx),
+            client_ip: remote_addr.ip().to_string(),
+            user_id: authentication.user_id().map(ToString::to_string),
+            tenant_id: None,
+            roles: claimed_roles(claims.as_ref()),
+            claims,
+        }
+    }
 }
 
 /// HTTP response that is received from JavaScript.
@@ -127,6 +365,35 @@ pub struct HttpResponse {
     pub status: u16,
     pub headers: Vec<(String, String)>,
     pub body: serde_v8::ZeroCopyBuf,
+    /// Overrides of the security headers of the version by the route; `None` removes the header.
+    #[serde(default)]
+    pub security_headers: Vec<(String, Option<String>)>,
+    /// Trailers that the handler set in `ctx.trailers` while the body was produced.
+    #[serde(default)]
+    pub trailers: Vec<(String, String)>,
+}
+
+/// Reads the whole body of a request, `None` if it is larger than `limit` bytes. Bodies that
+/// announce a larger `Content-Length` are rejected without reading them.
+async fn read_body(
+    mut body: hyper::Body,
+    headers: &hyper::HeaderMap,
+    limit: u64,
+) -> Result<Option<hyper::body::Bytes>> {
+    let content_length = headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if content_length.map_or(false, |len| len > limit) {
+        return Ok(None);
+    }
+    let mut buf = Vec::with_capacity(content_length.unwrap_or(0) as usize);
+    while let Some(chunk) = body.try_next().await? {
+        if (buf.len() + chunk.len()) as u64 > limit {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Some(buf.into()))
 }
 
 fn handle_chisel_error(error: ChiselError) -> Result<hyper::Response<hyper::Body>> {
@@ -139,33 +406,92 @@ fn handle_chisel_error(error: ChiselError) -> Result<hyper::Response<hyper::Body
 
 async fn handle_version_request(
     server: Arc<Server>,
-    version: Arc<Version>,
-    job_tx: mpsc::Sender<VersionJob>,
-    request: hyper::Request<hyper::Body>,
+    mut version: Arc<Version>,
+    mut job_tx: mpsc::Sender<VersionJob>,
+    remote_addr: SocketAddr,
+    mut request: hyper::Request<hyper::Body>,
     routing_path: String,
+    trace_id: &str,
+    otel_cx: &opentelemetry::Context,
 ) -> Result<hyper::Response<hyper::Body>> {
+    let on_upgrade = is_websocket_upgrade(&request).then(|| hyper::upgrade::on(&mut request));
     let (req_parts, req_body) = request.into_parts();
-    let req_body = hyper::body::to_bytes(req_body).await?;
 
-    let authentication = match authenticate(&req_parts, &server.secrets).await {
+    let authentication = match authenticate(&server, &req_parts).await {
         Ok(auth) => auth,
         Err(e) => return handle_chisel_error(e),
     };
 
-    if let Err(e) = authorize(
+    // if the traffic of the version is split, the request might be handled by the canary version
+    if let Some(split) = server.trunk.get_traffic_split(&version.version_id) {
+        if split.routes_to_canary(authentication.user_id()) {
+            if let Some(canary) = server.trunk.get_trunk_version(&split.canary_version_id) {
+                version = canary.version;
+                job_tx = canary.job_tx;
+            }
+        }
+    }
+
+    let (req_body, body_stream) =
+        if version.is_streamed_request(req_parts.method.as_str(), &routing_path) {
+            (hyper::body::Bytes::new(), Some(req_body))
+        } else {
+            match read_body(req_body, &req_parts.headers, server.max_body_size).await? {
+                Some(req_body) => (req_body, None),
+                None => return Ok(handle_payload_too_large(server.max_body_size)),
+            }
+        };
+
+    let authorize_cx = telemetry::start_span(otel_cx, "authorize", SpanKind::Internal, vec![]);
+    let authorized = authorize(
         &server,
         &version,
         &authentication,
         &routing_path,
         &req_parts,
     )
-    .await
-    {
+    .await;
+    authorize_cx.span().end();
+    if let Err(e) = authorized {
         return handle_chisel_error(e);
     }
 
-    let user_id = authentication.user_id().map(ToString::to_string);
-    let http_request = HttpRequest {
+    if let Err(retry_after) = check_rate_limit(
+        &server,
+        &version,
+        &authentication,
+        remote_addr,
+        &routing_path,
+    ) {
+        return Ok(handle_too_many_requests(retry_after));
+    }
+
+    if let Some(on_upgrade) = on_upgrade {
+        return handle_socket_request(
+            server,
+            &version,
+            job_tx,
+            req_parts,
+            routing_path,
+            authentication,
+            on_upgrade,
+        )
+        .await;
+    }
+
+    let sandbox = match authorize_sandbox(&server, &req_parts) {
+        Ok(sandbox) => sandbox,
+        Err(e) => return handle_chisel_error(e),
+    };
+
+    let policy_trace = match authorize_policy_trace(&server, &req_parts) {
+        Ok(policy_trace) => policy_trace,
+        Err(e) => return handle_chisel_error(e),
+    };
+
+    let admin = has_admin_secret(&server, &req_parts);
+    let ctx = RequestContext::new(&version, trace_id, otel_cx, remote_addr, &authentication);
+    let make_http_request = || HttpRequest {
         method: req_parts.method.as_str().into(),
         uri: req_parts.uri.to_string(),
         headers: req_parts
@@ -175,42 +501,359 @@ async fn handle_version_request(
             .collect(),
         // TODO: unnecessary copy from `Bytes` to `Vec<u8>`
         body: serde_v8::ZeroCopyBuf::from(req_body.to_vec()),
-        routing_path,
-        user_id,
+        routing_path: routing_path.clone(),
+        ctx: ctx.clone(),
+        otel_cx: otel_cx.clone(),
+        body_stream: None,
+        policy_trace,
     };
 
-    // send the job and wait for the response
-    let (response_tx, response_rx) = oneshot::channel();
-    let job = VersionJob::Http(HttpRequestResponse {
-        request: http_request,
+    // if the version is mirrored, send a copy of the request to the target version in the
+    // background; it will be compared with the outcome of this request once we know it
+    // (streamed bodies cannot be copied, so such requests are not mirrored)
+    let request_timeout = version
+        .route_timeout(req_parts.method.as_str(), &routing_path)
+        .or(server.request_timeout);
+    let mut mirror_outcome_tx = None;
+    let mirror = match body_stream {
+        Some(_) => None,
+        None => server.trunk.get_mirror(&version.version_id),
+    };
+    if let Some(mirror) = mirror {
+        if let Some(target) = server.trunk.get_trunk_version(&mirror.target_version_id) {
+            if mirror.sample() {
+                let (outcome_tx, outcome_rx) = oneshot::channel();
+                mirror_outcome_tx = Some(outcome_tx);
+                let mut mirrored_request = make_http_request();
+                mirrored_request.ctx.version_id = mirror.target_version_id.clone();
+                mirrored_request.policy_trace = false;
+                server.trunk.spawn_detached(run_mirror(
+                    mirror,
+                    target.job_tx,
+                    mirrored_request,
+                    authentication.clone(),
+                    admin,
+                    request_timeout,
+                    outcome_rx,
+                ));
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let mut http_request = make_http_request();
+    http_request.body_stream = body_stream;
+    let http_response = send_http_job(
+        &job_tx,
+        http_request,
         authentication,
-        response_tx,
-    });
-    // ignore the error that `send()` returns if the corresponding `mpsc::Receiver` was dropped.
-    // even if `send()` returns an `Ok`, it does not in fact guarantee that the job is received or
-    // processed, so we _still_ must handle the case when the job is dropped ...
-    let _: Result<_, _> = job_tx.send(job).await;
-    // ... which happens here: when the `job` is dropped, `job.response_tx` is also dropped, so the
-    // `.await` returns an error
-    let http_response = response_rx.await.context("Request was aborted")?;
+        sandbox,
+        admin,
+        request_timeout,
+    )
+    .await;
+    if let Some(outcome_tx) = mirror_outcome_tx {
+        let _ = outcome_tx.send(MirrorOutcome {
+            status: http_response.as_ref().ok().map(|response| response.status),
+            latency: start.elapsed(),
+        });
+    }
+    let http_response = match http_response {
+        Ok(http_response) => http_response,
+        Err(err) if err.is::<DeadlineExceeded>() => return Ok(handle_gateway_timeout()),
+        Err(err) => return Err(err),
+    };
 
+    let trailers = parse_headers(http_response.trailers, "Response trailer")?;
+    // HTTP/1.1 clients receive the trailers as headers, because hyper sends trailers only over
+    // HTTP/2 (the body is complete at this point, so the headers are not committed yet)
+    let send_trailers = req_parts.version == hyper::Version::HTTP_2 && !trailers.is_empty();
     // TODO: unnecessary copy from `ZeroCopyBuf` to `Vec<u8>`
-    let response_body = hyper::Body::from(http_response.body.to_vec());
+    let response_body = http_response.body.to_vec();
+    let response_body = if send_trailers {
+        let (mut sender, body) = hyper::Body::channel();
+        let trailers = trailers.clone();
+        tokio::task::spawn(async move {
+            if sender.send_data(response_body.into()).await.is_ok() {
+                let _ = sender.send_trailers(trailers).await;
+            }
+        });
+        body
+    } else {
+        hyper::Body::from(response_body)
+    };
     let mut response = hyper::Response::new(response_body);
 
     *response.status_mut() = hyper::StatusCode::from_u16(http_response.status)
         .context("Response specified an invalid status code")?;
-    for (name, value) in http_response.headers.into_iter() {
+    response
+        .headers_mut()
+        .extend(parse_headers(http_response.headers, "Response header")?);
+    if send_trailers {
+        let names = trailers
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>();
+        let names = hyper::header::HeaderValue::from_str(&names.join(", "))?;
+        response.headers_mut().insert(hyper::header::TRAILER, names);
+    } else {
+        response.headers_mut().extend(trailers);
+    }
+    add_security_headers(&mut response, &version, http_response.security_headers)?;
+    if sandbox {
+        // let the client know that none of the changes were committed
+        response
+            .headers_mut()
+            .insert(SANDBOX_HEADER, hyper::header::HeaderValue::from_static("1"));
+    }
+
+    Ok(response)
+}
+
+/// Converts the headers (or trailers) of a response from JavaScript. The `what` describes them in
+/// the error messages.
+fn parse_headers(headers: Vec<(String, String)>, what: &str) -> Result<hyper::HeaderMap> {
+    let mut map = hyper::HeaderMap::new();
+    for (name, value) in headers.into_iter() {
         let name = hyper::header::HeaderName::from_bytes(name.as_bytes())
-            .with_context(|| format!("Response header {:?} is not a valid header name", name))?;
+            .with_context(|| format!("{} {:?} is not a valid header name", what, name))?;
         let value = hyper::header::HeaderValue::from_str(&value)
-            .with_context(|| format!("Response header {:?} has invalid value", name))?;
-        response.headers_mut().append(name, value);
+            .with_context(|| format!("{} {:?} has invalid value", what, name))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// Adds the security headers of the version to the response, as overridden by the route. Headers
+/// that the route handler set explicitly are never replaced.
+fn add_security_headers(
+    response: &mut hyper::Response<hyper::Body>,
+    version: &Version,
+    overrides: Vec<(String, Option<String>)>,
+) -> Result<()> {
+    let mut headers = version
+        .policy_system()
+        .security_headers
+        .iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    for (name, value) in overrides.into_iter() {
+        let name = hyper::header::HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Security header {:?} is not a valid header name", name))?;
+        headers.retain(|(n, _)| *n != name);
+        if let Some(value) = value {
+            let value = hyper::header::HeaderValue::from_str(&value)
+                .with_context(|| format!("Security header {:?} has invalid value", name))?;
+            headers.push((name, value));
+        }
+    }
+
+    let response_headers = response.headers_mut();
+    for (name, value) in headers.into_iter() {
+        if !response_headers.contains_key(&name) {
+            response_headers.insert(name, value);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the rate limit of the route (if there is one). If the request is over the limit,
+/// returns how long the client should wait before retrying.
+fn check_rate_limit(
+    server: &Server,
+    version: &Version,
+    authentication: &Authentication,
+    remote_addr: SocketAddr,
+    routing_path: &str,
+) -> Result<(), Duration> {
+    let policy_system = version.policy_system();
+    let (route_path, limit) = match policy_system.rate_limits.lookup(routing_path) {
+        Some(route_limit) => route_limit,
+        None => return Ok(()),
+    };
+    let client = match (limit.key, authentication.user_id()) {
+        (RateLimitKey::Global, _) => String::new(),
+        (RateLimitKey::User, Some(user_id)) => format!("user:{}", user_id),
+        (RateLimitKey::User, None) | (RateLimitKey::Ip, _) => format!("ip:{}", remote_addr.ip()),
+    };
+    let key = BucketKey {
+        version_id: version.version_id.clone(),
+        route_path: route_path.into(),
+        client,
+    };
+    server.rate_limiter.check(key, limit, Instant::now())
+}
+
+fn is_websocket_upgrade(request: &hyper::Request<hyper::Body>) -> bool {
+    let header_contains = |name, value: &str| {
+        request.headers().get_all(name).iter().any(|header| {
+            header.to_str().map_or(false, |header| {
+                header
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(value))
+            })
+        })
+    };
+    request.method() == hyper::Method::GET
+        && header_contains(hyper::header::CONNECTION, "upgrade")
+        && header_contains(hyper::header::UPGRADE, "websocket")
+}
+
+/// Handles a WebSocket upgrade request. The connection is upgraded only if the version accepts
+/// the socket (that is, if there is a socket route for the path).
+async fn handle_socket_request(
+    server: Arc<Server>,
+    version: &Version,
+    job_tx: mpsc::Sender<VersionJob>,
+    req_parts: http::request::Parts,
+    routing_path: String,
+    authentication: Authentication,
+    on_upgrade: hyper::upgrade::OnUpgrade,
+) -> Result<hyper::Response<hyper::Body>> {
+    let accept_key = match req_parts.headers.get(hyper::header::SEC_WEBSOCKET_KEY) {
+        Some(key) => derive_accept_key(key.as_bytes()),
+        None => {
+            return Ok(handle_bad_request(
+                "Missing Sec-WebSocket-Key header".into(),
+            ))
+        }
+    };
+
+    let (socket_id, outgoing_rx) = server.sockets.register(&version.version_id);
+    let info = SocketInfo {
+        socket_id,
+        routing_path: routing_path.clone(),
+        headers: req_parts
+            .headers
+            .iter()
+            .map(|(k, v)| (k.as_str().into(), v.to_str().unwrap_or_default().into()))
+            .collect(),
+        authentication,
+    };
+    match info.open(&job_tx).await {
+        Ok(true) => {}
+        Ok(false) => {
+            server.sockets.unregister(socket_id);
+            return Ok(handle_not_found(format!(
+                "There is no socket route for {:?}",
+                routing_path
+            )));
+        }
+        Err(err) => {
+            server.sockets.unregister(socket_id);
+            return Err(err);
+        }
     }
 
+    server.trunk.spawn_detached(socket::run(
+        server.clone(),
+        info,
+        job_tx,
+        on_upgrade,
+        outgoing_rx,
+    ));
+
+    let response = hyper::Response::builder()
+        .status(hyper::StatusCode::SWITCHING_PROTOCOLS)
+        .header(hyper::header::CONNECTION, "upgrade")
+        .header(hyper::header::UPGRADE, "websocket")
+        .header(hyper::header::SEC_WEBSOCKET_ACCEPT, accept_key)
+        .body(hyper::Body::empty())
+        .unwrap();
     Ok(response)
 }
 
+/// Error of `send_http_job()` when the deadline of the request expires.
+#[derive(Debug)]
+struct DeadlineExceeded;
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Request exceeded its deadline")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Sends the HTTP request to a version and waits for the response.
+///
+/// If `sandbox` is true, all changes made by the request are rolled back, and if `admin` is true,
+/// the request is not restricted to the rows that its user owns. If the response does
+/// not arrive within `timeout`, or if the returned future is dropped before that (because the
+/// client disconnected), the job is cancelled.
+async fn send_http_job(
+    job_tx: &mpsc::Sender<VersionJob>,
+    mut request: HttpRequest,
+    authentication: Authentication,
+    sandbox: bool,
+    admin: bool,
+    timeout: Option<Duration>,
+) -> Result<HttpResponse> {
+    // the span covers both the time that the job waits for a worker and the time it runs
+    let dispatch_cx = telemetry::start_span(
+        &request.otel_cx,
+        "worker dispatch",
+        SpanKind::Internal,
+        vec![KeyValue::new("chisel.sandbox", sandbox)],
+    );
+    request.otel_cx = dispatch_cx.clone();
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let (cancel_guard, cancel) = cancel_pair(deadline);
+    let (response_tx, response_rx) = oneshot::channel();
+    let job = VersionJob::Http(HttpRequestResponse {
+        request,
+        authentication,
+        sandbox,
+        admin,
+        response_tx,
+        cancel,
+    });
+    let response = async move {
+        // ignore the error that `send()` returns if the corresponding `mpsc::Receiver` was
+        // dropped. even if `send()` returns an `Ok`, it does not in fact guarantee that the job is
+        // received or processed, so we _still_ must handle the case when the job is dropped ...
+        let _: Result<_, _> = job_tx.send(job).await;
+        // ... which happens here: when the `job` is dropped, `job.response_tx` is also dropped,
+        // so the `.await` returns an error
+        response_rx.await.context("Request was aborted")
+    };
+    let response = match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), response)
+            .await
+            .unwrap_or_else(|_| Err(DeadlineExceeded.into())),
+        None => response.await,
+    };
+    // dropping the guard cancels the job, which is what we want if we did not get the response
+    if response.is_ok() {
+        cancel_guard.disarm();
+    }
+    telemetry::end(&dispatch_cx, &response);
+    response
+}
+
+/// Handles a mirrored request in the target version and records how it compares to the request
+/// in the source version. The response is discarded.
+async fn run_mirror(
+    mirror: Arc<Mirror>,
+    job_tx: mpsc::Sender<VersionJob>,
+    request: HttpRequest,
+    authentication: Authentication,
+    admin: bool,
+    timeout: Option<Duration>,
+    source_outcome_rx: oneshot::Receiver<MirrorOutcome>,
+) {
+    let start = Instant::now();
+    let response = send_http_job(&job_tx, request, authentication, true, admin, timeout).await;
+    let target_outcome = MirrorOutcome {
+        status: response.ok().map(|response| response.status),
+        latency: start.elapsed(),
+    };
+    // if the source request was aborted, there is nothing to compare with
+    if let Ok(source_outcome) = source_outcome_rx.await {
+        mirror.record(source_outcome, target_outcome);
+    }
+}
+
 fn get_version_path(path: &str) -> Option<(&str, &str)> {
     lazy_static! {
         static ref REGEX: Regex = Regex::new(
@@ -255,6 +898,144 @@ fn handle_index(server: Arc<Server>) -> hyper::Response<hyper::Body> {
         .unwrap()
 }
 
+fn handle_openapi(server: &Server) -> hyper::Response<hyper::Body> {
+    let document = openapi::document(server);
+    let response = serde_json::to_string_pretty(&document).unwrap();
+    hyper::Response::builder()
+        .header("content-type", "application/json")
+        .body(hyper::Body::from(response))
+        .unwrap()
+}
+
+fn handle_json_schema(server: &Server) -> hyper::Response<hyper::Body> {
+    let document = json_schema::document(server);
+    let response = serde_json::to_string_pretty(&document).unwrap();
+    hyper::Response::builder()
+        .header("content-type", "application/json")
+        .body(hyper::Body::from(response))
+        .unwrap()
+}
+
+/// Path (under the version) where the modules of the version are served.
+const MODULES_PATH: &str = "/__chiselstrike/modules";
+
+/// Serves the modules that a version executes, so that the exact code can be fetched when
+/// debugging. `GET {MODULES_PATH}` lists the URL and content hash of every module, and
+/// `GET {MODULES_PATH}/<hash>` returns the code of the module with that hash. Only admins can
+/// access the modules (see [`is_admin`]).
+fn handle_modules(
+    server: &Server,
+    version: &Version,
+    request: hyper::Request<hyper::Body>,
+    module_path: &str,
+) -> hyper::Response<hyper::Body> {
+    let (req_parts, _) = request.into_parts();
+    if !is_admin(server, &req_parts) {
+        return handle_forbidden(format!(
+            "Modules are only available in debug mode or with a valid {} header",
+            ADMIN_SECRET_HEADER
+        ));
+    }
+
+    match module_path.trim_start_matches('/') {
+        "" => {
+            let mut modules = version
+                .modules
+                .iter()
+                .map(|(url, code)| serde_json::json!({ "url": url, "hash": module_hash(code) }))
+                .collect::<Vec<_>>();
+            modules.sort_unstable_by(|a, b| a["url"].as_str().cmp(&b["url"].as_str()));
+            let response = serde_json::to_string_pretty(&modules).unwrap();
+            hyper::Response::builder()
+                .header("content-type", "application/json")
+                .body(hyper::Body::from(response))
+                .unwrap()
+        }
+        hash => match version.module_by_hash(hash) {
+            Some((url, code)) => {
+                let mut response = hyper::Response::builder()
+                    .header("content-type", "application/javascript; charset=utf-8")
+                    .body(hyper::Body::from(code.to_owned()))
+                    .unwrap();
+                if let Ok(value) = hyper::header::HeaderValue::from_str(url) {
+                    response.headers_mut().insert("x-chisel-module-url", value);
+                }
+                response
+            }
+            None => handle_not_found(format!("Unknown module {:?}", hash)),
+        },
+    }
+}
+
+/// Returns the path of a static file if `routing_path` is under `STATIC_PATH`.
+fn static_file_path(routing_path: &str) -> Option<&str> {
+    let path = routing_path.strip_prefix(STATIC_PATH)?;
+    (path.is_empty() || path.starts_with('/')).then_some(path)
+}
+
+/// Path under a version where the route examples are served, see [`handle_examples`].
+const EXAMPLES_PATH: &str = "/__chiselstrike/examples";
+
+/// Serves the route examples of a version (registered and recorded, see `contract.rs`) as a JSON
+/// array, which `chisel contract verify` replays. Only admins can access the examples (see
+/// [`is_admin`]).
+async fn handle_examples(
+    server: &Server,
+    version: &Version,
+    request: hyper::Request<hyper::Body>,
+) -> Result<hyper::Response<hyper::Body>> {
+    let (req_parts, _) = request.into_parts();
+    if !is_admin(server, &req_parts) {
+        return Ok(handle_forbidden(format!(
+            "Examples are only available in debug mode or with a valid {} header",
+            ADMIN_SECRET_HEADER
+        )));
+    }
+
+    let examples = contract::version_examples(server, version).await?;
+    let response = serde_json::to_string_pretty(&examples)?;
+    Ok(hyper::Response::builder()
+        .header("content-type", "application/json")
+        .body(hyper::Body::from(response))
+        .unwrap())
+}
+
+/// Path where the documentation is served: at the top level it lists the versions, and under a
+/// version (`/<version>/__docs`) it documents the entities and routes of the version.
+const DOCS_PATH: &str = "/__docs";
+
+/// Serves the HTML documentation of a version, or the list of versions if `version` is `None`.
+/// Only admins can access the documentation (see [`is_admin`]).
+fn handle_docs(
+    server: &Server,
+    version: Option<&Version>,
+    request: hyper::Request<hyper::Body>,
+) -> hyper::Response<hyper::Body> {
+    let (req_parts, _) = request.into_parts();
+    if !is_admin(server, &req_parts) {
+        return handle_forbidden(format!(
+            "Documentation is only available in debug mode or with a valid {} header",
+            ADMIN_SECRET_HEADER
+        ));
+    }
+
+    let page = match version {
+        Some(version) => {
+            let host = req_parts
+                .headers
+                .get(hyper::header::HOST)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("localhost:8080");
+            docs::version_page(version, &format!("http://{}", host))
+        }
+        None => docs::index_page(server),
+    };
+    hyper::Response::builder()
+        .header("content-type", "text/html; charset=utf-8")
+        .body(hyper::Body::from(page))
+        .unwrap()
+}
+
 fn handle_options() -> hyper::Response<hyper::Body> {
     // Makes CORS preflights pass.
     // NOTE: This is a very heavy-handed way to handle CORS!
@@ -278,6 +1059,36 @@ fn handle_forbidden(msg: String) -> hyper::Response<hyper::Body> {
         .unwrap()
 }
 
+fn handle_too_many_requests(retry_after: Duration) -> hyper::Response<hyper::Body> {
+    // `Retry-After` only accepts whole seconds, so round up to not invite retries that would fail
+    let retry_after_s = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    hyper::Response::builder()
+        .status(hyper::StatusCode::TOO_MANY_REQUESTS)
+        .header(hyper::header::RETRY_AFTER, retry_after_s.to_string())
+        .body(hyper::Body::from(format!(
+            "Too many requests, retry after {} seconds",
+            retry_after_s
+        )))
+        .unwrap()
+}
+
+fn handle_payload_too_large(limit: u64) -> hyper::Response<hyper::Body> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::PAYLOAD_TOO_LARGE)
+        .body(hyper::Body::from(format!(
+            "Request body is larger than {} bytes",
+            limit
+        )))
+        .unwrap()
+}
+
+fn handle_gateway_timeout() -> hyper::Response<hyper::Body> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::GATEWAY_TIMEOUT)
+        .body(hyper::Body::from("Request exceeded its deadline"))
+        .unwrap()
+}
+
 fn handle_bad_request(msg: String) -> hyper::Response<hyper::Body> {
     hyper::Response::builder()
         .status(hyper::StatusCode::BAD_REQUEST)
@@ -288,9 +1099,15 @@ fn handle_bad_request(msg: String) -> hyper::Response<hyper::Body> {
 fn handle_error(
     method: &hyper::Method,
     uri: &hyper::Uri,
+    trace_id: &str,
     err: Error,
 ) -> hyper::Response<hyper::Body> {
-    log::error!("Error while handling {} {}: {:?}", method, uri, err);
+    log_event(
+        log::Level::Error,
+        module_path!(),
+        format_args!("Error while handling {} {}: {:?}", method, uri, err),
+        vec![("trace_id", trace_id.into())],
+    );
     hyper::Response::builder()
         .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
         .body(hyper::Body::empty())
@@ -341,7 +1158,10 @@ fn add_default_headers(response: &mut hyper::Response<hyper::Body>) {
             "access-control-allow-methods",
             "POST, PUT, GET, OPTIONS, DELETE, PATCH",
         ),
-        ("access-control-allow-headers", "Content-Type,ChiselUID"),
+        (
+            "access-control-allow-headers",
+            "Content-Type,ChiselUID,X-Chisel-Sandbox,X-Chisel-Admin-Secret,X-Trace-Id",
+        ),
     ];
 
     let headers = response.headers_mut();
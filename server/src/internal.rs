@@ -1,11 +1,14 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
+use crate::backup::BackupService;
+use crate::datastore::QueryEngine;
 use anyhow::{Context, Result};
 use hyper::server::conn::AddrIncoming;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server};
+use hyper::{Body, Method, Request, Response, Server};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
 use utils::TaskHandle;
 
 static HEALTH_READY: AtomicU16 = AtomicU16::new(404);
@@ -25,7 +28,11 @@ fn response(body: &str, status: u16) -> Result<Response<Body>> {
         .unwrap())
 }
 
-async fn route(req: Request<Body>) -> Result<Response<Body>> {
+async fn route(
+    req: Request<Body>,
+    query_engine: QueryEngine,
+    backups: Option<Arc<BackupService>>,
+) -> Result<Response<Body>> {
     match req.uri().path() {
         // Conceptually those checks are different and could eventually become
         // more complex functions. But for now we just return simple strings.
@@ -34,20 +41,63 @@ async fn route(req: Request<Body>) -> Result<Response<Body>> {
         "/status" => response("ok", 200),
         "/readiness" => response("ready", HEALTH_READY.load(Ordering::Relaxed)),
         "/liveness" => response("alive", 200),
+        // Active transactions and lock waits, to find the routes that are holding things up.
+        "/transactions" => transactions(&query_engine).await,
+        // Consistent snapshot of the database in the --backup-dir.
+        "/backup" => backup(&req, backups.as_deref()).await,
         _ => response("not found", 404),
     }
     .or_else(|e| response(&format!("{:?}", e), 500))
 }
 
+async fn transactions(query_engine: &QueryEngine) -> Result<Response<Body>> {
+    let report = query_engine.txn_stats_report().await?;
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string_pretty(&report)?))
+        .unwrap())
+}
+
+async fn backup(req: &Request<Body>, backups: Option<&BackupService>) -> Result<Response<Body>> {
+    if req.method() != Method::POST {
+        return response("method not allowed", 405);
+    }
+    let backups = match backups {
+        Some(backups) => backups,
+        None => return response("backups are disabled, start chiseld with --backup-dir", 404),
+    };
+    let (path, manifest) = backups.create_backup().await?;
+    let body = serde_json::json!({
+        "path": path,
+        "manifest": manifest,
+    });
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string_pretty(&body)?))
+        .unwrap())
+}
+
 /// Spawn a server that handles ChiselStrike's internal routes.
 ///
 /// Unlike the API server, it is strictly bound to 127.0.0.1. This is enough
 /// for the Kubernetes checks to work, and it is one less thing for us to secure
 /// and prevent DDoS attacks again - which is why this is a different server
-pub async fn spawn(listen_addr: SocketAddr) -> Result<(SocketAddr, TaskHandle<Result<()>>)> {
-    let make_svc = make_service_fn(|_conn| async {
-        // service_fn converts our function into a `Service`
-        Ok::<_, anyhow::Error>(service_fn(route))
+pub async fn spawn(
+    listen_addr: SocketAddr,
+    query_engine: QueryEngine,
+    backups: Option<Arc<BackupService>>,
+) -> Result<(SocketAddr, TaskHandle<Result<()>>)> {
+    let make_svc = make_service_fn(move |_conn| {
+        let query_engine = query_engine.clone();
+        let backups = backups.clone();
+        async move {
+            // service_fn converts our function into a `Service`
+            Ok::<_, anyhow::Error>(service_fn(move |req| {
+                route(req, query_engine.clone(), backups.clone())
+            }))
+        }
     });
 
     let incoming = AddrIncoming::bind(&listen_addr)?;
@@ -0,0 +1,290 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! JSON Schema documents that describe the entities of all versions, so that payloads can be
+//! validated outside of ChiselStrike.
+//!
+//! Every entity is described by three documents, because the payloads differ: `create` for new
+//! objects (the id and derived fields are generated, fields with defaults may be omitted), `update`
+//! for partial updates (every field may be omitted) and `read` for the objects that are returned
+//! (all fields are present). Materialized aggregates are only described by `read`. Values are
+//! described as they are encoded in JSON, so dates are milliseconds since the UNIX epoch, 64-bit
+//! integers are strings of digits and array buffers are strings in base64.
+
+use crate::server::Server;
+use crate::types::{Entity, Field, ObjectType, Type, TypeId, TypeSystem};
+use serde_json::{json, Map, Value};
+
+const SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shape {
+    Create,
+    Update,
+    Read,
+}
+
+impl Shape {
+    fn name(self) -> &'static str {
+        match self {
+            Shape::Create => "create",
+            Shape::Update => "update",
+            Shape::Read => "read",
+        }
+    }
+}
+
+/// Returns an object that maps version ids to the schemas of their entities (see
+/// `version_schemas()`).
+pub fn document(server: &Server) -> Value {
+    let mut versions = server.trunk.list_versions();
+    versions.retain(|version| version.version_id != "__chiselstrike");
+    versions.sort_unstable_by(|x, y| x.version_id.cmp(&y.version_id));
+
+    let mut document = Map::new();
+    for version in versions.iter() {
+        document.insert(
+            version.version_id.clone(),
+            version_schemas(&version.type_system),
+        );
+    }
+    Value::Object(document)
+}
+
+/// Returns an object that maps entity names to objects with the `create`, `update` and `read`
+/// schemas of the entity.
+pub fn version_schemas(ts: &TypeSystem) -> Value {
+    let mut entities = ts.custom_types.values().collect::<Vec<_>>();
+    entities.sort_unstable_by(|x, y| x.name().cmp(y.name()));
+
+    let mut schemas = Map::new();
+    for entity in entities {
+        let shapes: &[Shape] = match entity.aggregate() {
+            Some(_) => &[Shape::Read],
+            None => &[Shape::Create, Shape::Update, Shape::Read],
+        };
+        let entity_schemas = shapes
+            .iter()
+            .map(|&shape| (shape.name().into(), entity_document(ts, entity, shape)))
+            .collect::<Map<_, _>>();
+        schemas.insert(entity.name().into(), Value::Object(entity_schemas));
+    }
+    Value::Object(schemas)
+}
+
+fn entity_document(ts: &TypeSystem, entity: &ObjectType, shape: Shape) -> Value {
+    let mut defs = Map::new();
+    collect_defs(ts, entity, shape, &mut defs);
+
+    let mut document = json!({
+        "$schema": SCHEMA_DIALECT,
+        "title": format!("{} ({})", entity.name(), shape.name()),
+    });
+    document
+        .as_object_mut()
+        .unwrap()
+        .extend(object_schema(ts, entity, shape));
+    if !defs.is_empty() {
+        document["$defs"] = Value::Object(defs);
+    }
+    document
+}
+
+/// Adds the schemas of the entities that are related to `entity` (transitively) to `defs`.
+fn collect_defs(ts: &TypeSystem, entity: &ObjectType, shape: Shape, defs: &mut Map<String, Value>) {
+    for field in entity.user_fields() {
+        let related = match ts.get(&field.type_id) {
+            Ok(Type::Entity(Entity::Custom(related))) => related,
+            _ => continue,
+        };
+        if defs.contains_key(related.name()) {
+            continue;
+        }
+        // insert a placeholder first, so that cyclic relations terminate
+        defs.insert(related.name().into(), Value::Null);
+        let schema = Value::Object(object_schema(ts, &related, shape));
+        defs.insert(related.name().into(), schema);
+        collect_defs(ts, &related, shape, defs);
+    }
+}
+
+fn object_schema(ts: &TypeSystem, entity: &ObjectType, shape: Shape) -> Map<String, Value> {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for field in entity.all_fields() {
+        // the id, the derived fields and the timestamps are maintained by the server
+        let generated = field.type_id == TypeId::Id
+            || field.count.is_some()
+            || field.aggregate.is_some()
+            || field.timestamp.is_some();
+        if generated && shape != Shape::Read {
+            continue;
+        }
+        let ty = match ts.get(&field.type_id) {
+            Ok(ty) => ty,
+            Err(_) => continue,
+        };
+
+        let mut schema = type_schema(&ty, &field.type_id);
+        if let Some(enum_type) = &field.enum_type {
+            schema["enum"] = json!(enum_type.values);
+        }
+        if let Some(max_bytes) = field.max_bytes {
+            schema["maxLength"] = json!(base64_len(max_bytes));
+        }
+        if field.is_optional {
+            schema = nullable(schema);
+        }
+        if shape == Shape::Create {
+            if let Some(default) = default_value(&ty, field) {
+                schema["default"] = default;
+            }
+        }
+        properties.insert(field.name.clone(), schema);
+
+        let is_required = match shape {
+            Shape::Read => !field.is_optional,
+            // the `@version` field is initialized by the server, and so is the `@ownedBy` field
+            // (unless the client is an admin)
+            Shape::Create => {
+                !field.is_optional
+                    && field.user_provided_default().is_none()
+                    && !field.is_version
+                    && entity.owned_by() != Some(field.name.as_str())
+            }
+            Shape::Update => false,
+        };
+        if is_required {
+            required.push(field.name.clone());
+        }
+    }
+
+    let mut schema = Map::new();
+    schema.insert("type".into(), json!("object"));
+    schema.insert("properties".into(), Value::Object(properties));
+    if !required.is_empty() {
+        schema.insert("required".into(), json!(required));
+    }
+    schema
+}
+
+fn type_schema(ty: &Type, type_id: &TypeId) -> Value {
+    match ty {
+        Type::String if *type_id == TypeId::Id => json!({ "type": "string", "format": "uuid" }),
+        Type::EntityId(_) => json!({ "type": "string", "format": "uuid" }),
+        Type::String => json!({ "type": "string" }),
+        Type::Float => json!({ "type": "number" }),
+        Type::Int64 => json!({
+            "type": "string",
+            "pattern": "^-?[0-9]+$",
+            "description": "64-bit integer",
+        }),
+        Type::Decimal { precision, scale } => json!({
+            "type": "string",
+            "pattern": "^[-+]?[0-9]+(\\.[0-9]+)?$",
+            "description": format!(
+                "Decimal number with at most {precision} digits, {scale} of them after the decimal point"
+            ),
+        }),
+        Type::Boolean => json!({ "type": "boolean" }),
+        Type::JsDate => json!({
+            "type": "number",
+            "description": "Milliseconds since the UNIX epoch",
+        }),
+        Type::ArrayBuffer => json!({ "type": "string", "contentEncoding": "base64" }),
+        Type::Json => json!({}),
+        Type::GeoPoint => geo_point_schema(),
+        Type::Entity(Entity::Custom(entity)) => {
+            json!({ "$ref": format!("#/$defs/{}", entity.name()) })
+        }
+        Type::Entity(Entity::Auth(_)) => json!({ "type": "object" }),
+        Type::Array(element) => {
+            let element_id = match type_id {
+                TypeId::Array(element_id) => element_id.as_ref().clone(),
+                _ => TypeId::String,
+            };
+            json!({
+                "type": "array",
+                "items": type_schema(element, &element_id),
+            })
+        }
+    }
+}
+
+/// Allows `null` in addition to the values described by `schema`, which is how optional fields
+/// without a value are encoded.
+fn nullable(mut schema: Value) -> Value {
+    if let Some(Value::Array(values)) = schema.get_mut("enum") {
+        values.push(Value::Null);
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some(ty) => {
+            schema["type"] = json!([ty, "null"]);
+            schema
+        }
+        None => json!({ "anyOf": [schema, { "type": "null" }] }),
+    }
+}
+
+/// Schema of a `GeoPoint`, which is the same in JSON Schema and in OpenAPI.
+pub(crate) fn geo_point_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "lat": { "type": "number", "minimum": -90, "maximum": 90 },
+            "lng": { "type": "number", "minimum": -180, "maximum": 180 },
+        },
+        "required": ["lat", "lng"],
+        "additionalProperties": false,
+    })
+}
+
+/// Returns the length of the base64 encoding of `bytes` bytes, which is how `ArrayBuffer` fields are
+/// encoded in JSON.
+pub(crate) fn base64_len(bytes: u64) -> u64 {
+    (bytes + 2) / 3 * 4
+}
+
+/// Converts the default of the field (as written in the model) to a JSON value. Defaults that are
+/// not literals of the type of the field are omitted.
+fn default_value(ty: &Type, field: &Field) -> Option<Value> {
+    let default = field.user_provided_default().as_ref()?;
+    match ty {
+        Type::String | Type::Decimal { .. } => Some(json!(default)),
+        Type::Float => default.parse::<f64>().ok().map(|value| json!(value)),
+        Type::Int64 => default
+            .parse::<i64>()
+            .ok()
+            .map(|value| json!(value.to_string())),
+        Type::Boolean => default.parse::<bool>().ok().map(|value| json!(value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nullable_types() {
+        assert_eq!(
+            nullable(json!({ "type": "string", "format": "uuid" })),
+            json!({ "type": ["string", "null"], "format": "uuid" })
+        );
+        assert_eq!(
+            nullable(json!({ "$ref": "#/$defs/Person" })),
+            json!({ "anyOf": [{ "$ref": "#/$defs/Person" }, { "type": "null" }] })
+        );
+        assert_eq!(
+            nullable(json!({ "type": "string", "enum": ["red", "green"] })),
+            json!({ "type": ["string", "null"], "enum": ["red", "green", null] })
+        );
+    }
+
+    #[test]
+    fn base64_lengths() {
+        assert_eq!(base64_len(0), 0);
+        assert_eq!(base64_len(1), 4);
+        assert_eq!(base64_len(3), 4);
+        assert_eq!(base64_len(4), 8);
+    }
+}
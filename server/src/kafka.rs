@@ -1,12 +1,15 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+use crate::datastore::MetaService;
 use crate::nursery::{Nursery, NurseryStream};
+use crate::schema_registry::SchemaRegistry;
 use crate::server::Server;
 use crate::version::VersionJob;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use deno_core::serde_v8;
 use enclose::enclose;
 use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
+use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use rskafka::client::{
     consumer::{StartOffset, StreamConsumerBuilder},
@@ -14,24 +17,189 @@ use rskafka::client::{
     Client, ClientBuilder,
 };
 use rskafka::record::Record;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::Duration;
 use time::OffsetDateTime;
+use tokio::sync::oneshot;
 use utils::TaskHandle;
 
+/// We only consume the first partition of every topic.
+const PARTITION: i32 = 0;
+
+/// Delay before an event that was not committed in the manual commit mode is delivered again.
+const REDELIVERY_DELAY: Duration = Duration::from_secs(1);
+
+/// How many times an event that is not committed in the manual commit mode is delivered again
+/// before we give up on it and skip it, so that a single poison event cannot stall the topic.
+const MAX_REDELIVERIES: usize = 3;
+
 /// Kafka event that is passed to JavaScript.
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct KafkaEvent {
     pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
     pub key: serde_v8::ZeroCopyBuf,
     pub value: serde_v8::ZeroCopyBuf,
+    /// The value decoded with the schema registry, if the topic has a schema.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoded_value: Option<serde_json::Value>,
+    /// Indices of the handlers of the topic that accept the event (see `EventFilter`). If `None`,
+    /// the version did not report its handlers, so all of them are called.
+    pub handlers: Option<Vec<usize>>,
+    /// Dropped when the event has been handled by the worker.
+    #[serde(skip)]
+    pub done_tx: Option<oneshot::Sender<()>>,
+}
+
+/// Per-topic consumer settings, specified by the user in the `TopicMap`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicOptions {
+    /// Where to start consuming the topic if the consumer group has not committed any offset for
+    /// it yet. If not given, `--kafka-start-offset` is used.
+    pub start_offset: Option<TopicStartOffset>,
+    /// If true, the offset of an event is committed only when the event handler calls
+    /// `event.commit()`, in the same transaction as the changes made by the handler. Events that
+    /// were not committed are delivered again.
+    #[serde(default)]
+    pub manual_commit: bool,
+    /// If given, values of the topic are decoded with the schema registry before they are passed
+    /// to the handler, and objects published on the topic are encoded.
+    pub schema: Option<TopicSchema>,
+}
+
+/// Schema registry settings of a topic.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicSchema {
+    /// Subject whose latest schema is used to encode published values. Defaults to
+    /// `<topic>-value`.
+    pub value_subject: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TopicStartOffset {
+    Named(String),
+    At(i64),
+}
+
+/// Declarative filter of an event handler. Filters are evaluated in Rust, so that events are sent
+/// to JavaScript only if some handler accepts them. All conditions must match.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EventFilter {
+    /// The key must start with this prefix.
+    pub key_prefix: Option<String>,
+    /// Headers that must be present with exactly these values.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Fields of the value (parsed as JSON, or decoded with the schema registry) that must be
+    /// equal to these values. Nested fields are separated by dots, such as `"user.country"`.
+    #[serde(default)]
+    pub fields: BTreeMap<String, serde_json::Value>,
+}
+
+/// The parts of an event that filters look at.
+struct FilterInput<'a> {
+    key: &'a [u8],
+    headers: &'a BTreeMap<String, Vec<u8>>,
+    value: &'a [u8],
+    decoded_value: Option<&'a serde_json::Value>,
+    /// The value parsed as JSON, only if some filter needs it.
+    parsed_value: OnceCell<Option<serde_json::Value>>,
+}
+
+impl<'a> FilterInput<'a> {
+    fn json_value(&self) -> Option<&serde_json::Value> {
+        match self.decoded_value {
+            Some(value) => Some(value),
+            None => self
+                .parsed_value
+                .get_or_init(|| serde_json::from_slice(self.value).ok())
+                .as_ref(),
+        }
+    }
+}
+
+impl EventFilter {
+    fn matches(&self, input: &FilterInput) -> bool {
+        if let Some(ref prefix) = self.key_prefix {
+            if !input.key.starts_with(prefix.as_bytes()) {
+                return false;
+            }
+        }
+        let headers_match = self.headers.iter().all(|(name, expected)| {
+            input
+                .headers
+                .get(name)
+                .map_or(false, |value| value == expected.as_bytes())
+        });
+        if !headers_match {
+            return false;
+        }
+        self.fields.iter().all(|(path, expected)| {
+            let value = input
+                .json_value()
+                .and_then(|value| lookup_field(value, path));
+            value == Some(expected)
+        })
+    }
+}
+
+fn lookup_field<'v>(mut value: &'v serde_json::Value, path: &str) -> Option<&'v serde_json::Value> {
+    for name in path.split('.') {
+        value = match value {
+            serde_json::Value::Object(fields) => fields.get(name)?,
+            serde_json::Value::Array(elems) => elems.get(name.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Returns the indices of the handlers that accept the event (handlers without a filter accept
+/// all events).
+fn match_handlers(filters: &[Option<EventFilter>], input: &FilterInput) -> Vec<usize> {
+    filters
+        .iter()
+        .enumerate()
+        .filter(|(_, filter)| filter.as_ref().map_or(true, |filter| filter.matches(input)))
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+impl TopicStartOffset {
+    fn to_start_offset(&self) -> Result<StartOffset> {
+        Ok(match self {
+            TopicStartOffset::Named(name) => match name.as_str() {
+                "earliest" => StartOffset::Earliest,
+                "latest" => StartOffset::Latest,
+                _ => match name.parse() {
+                    Ok(offset) => StartOffset::At(offset),
+                    Err(_) => bail!(
+                        "Invalid Kafka start offset {:?}, expected \"earliest\", \"latest\" or a number",
+                        name
+                    ),
+                },
+            },
+            TopicStartOffset::At(offset) => StartOffset::At(*offset),
+        })
+    }
 }
 
 pub struct KafkaService {
     client: Client,
+    consumer_group: String,
+    default_start_offset: StartOffset,
+    schema_registry: Option<SchemaRegistry>,
     topics: Mutex<HashMap<String, Arc<PartitionClient>>>,
+    /// Schemas of the topics, as given in the `TopicOptions`.
+    topic_schemas: Mutex<HashMap<String, TopicSchema>>,
     topic_nursery: Nursery<TaskHandle<Result<()>>>,
     topic_stream: Mutex<Option<NurseryStream<TaskHandle<Result<()>>>>>,
     // The `outbox_poll_mutex` is used to serialize concurrent calls to outbox
@@ -40,7 +208,15 @@ pub struct KafkaService {
 }
 
 impl KafkaService {
-    pub async fn connect(connection: &str) -> Result<KafkaService> {
+    pub async fn connect(
+        connection: &str,
+        consumer_group: &str,
+        default_start_offset: &str,
+        schema_registry: Option<&str>,
+    ) -> Result<KafkaService> {
+        let default_start_offset =
+            TopicStartOffset::Named(default_start_offset.into()).to_start_offset()?;
+        let schema_registry = schema_registry.map(SchemaRegistry::new).transpose()?;
         let client = ClientBuilder::new(vec![connection.to_owned()])
             .build()
             .await?;
@@ -48,7 +224,11 @@ impl KafkaService {
         let (topic_nursery, topic_stream) = Nursery::new();
         Ok(KafkaService {
             client,
+            consumer_group: consumer_group.into(),
+            default_start_offset,
+            schema_registry,
             topics,
+            topic_schemas: Mutex::new(HashMap::default()),
             topic_nursery,
             topic_stream: Mutex::new(Some(topic_stream)),
             outbox_poll_mutex: async_lock::Mutex::new(()),
@@ -61,7 +241,7 @@ impl KafkaService {
         key: Option<Vec<u8>>,
         value: Option<Vec<u8>>,
     ) -> Result<()> {
-        let partition_client = Arc::new(self.client.partition_client(topic.to_owned(), 0)?);
+        let partition_client = Arc::new(self.client.partition_client(topic.to_owned(), PARTITION)?);
         let record = Record {
             key,
             value,
@@ -74,15 +254,67 @@ impl KafkaService {
         Ok(())
     }
 
-    pub fn subscribe_topic(&self, server: Arc<Server>, topic: String) {
+    /// Encodes a value that is published on `topic` with the schema registry.
+    pub async fn encode_value(&self, topic: &str, value: serde_json::Value) -> Result<Vec<u8>> {
+        let registry = match self.schema_registry {
+            Some(ref registry) => registry,
+            None => bail!(
+                "Cannot publish an object on topic {:?} without a schema registry (see \
+                `--kafka-schema-registry`)",
+                topic
+            ),
+        };
+        let subject = self
+            .topic_schemas
+            .lock()
+            .get(topic)
+            .and_then(|schema| schema.value_subject.clone())
+            .unwrap_or_else(|| format!("{}-value", topic));
+        registry.encode(&subject, value).await
+    }
+
+    pub fn subscribe_topic(
+        &self,
+        server: Arc<Server>,
+        topic: String,
+        options: TopicOptions,
+    ) -> Result<()> {
+        let start_offset = match options.start_offset {
+            Some(ref start_offset) => start_offset.to_start_offset()?,
+            None => self.default_start_offset,
+        };
+        if let Some(ref schema) = options.schema {
+            if self.schema_registry.is_none() {
+                bail!(
+                    "Kafka topic {:?} has a schema, but no schema registry is configured (see \
+                    `--kafka-schema-registry`)",
+                    topic
+                );
+            }
+            self.topic_schemas
+                .lock()
+                .insert(topic.clone(), schema.clone());
+        }
+
         let mut topics = self.topics.lock();
         if topics.contains_key(&topic) {
-            return;
+            return Ok(());
         }
-        let partition_client = Arc::new(self.client.partition_client(topic.clone(), 0).unwrap());
+        let partition_client = Arc::new(self.client.partition_client(topic.clone(), PARTITION)?);
         topics.insert(topic.clone(), partition_client.clone());
-        self.topic_nursery
-            .spawn(handle_topic(server, partition_client, topic));
+        self.topic_nursery.spawn(handle_topic(
+            server,
+            partition_client,
+            topic,
+            start_offset,
+            options.manual_commit,
+            options.schema.is_some(),
+        ));
+        Ok(())
+    }
+
+    pub fn consumer_group(&self) -> &str {
+        &self.consumer_group
     }
 
     pub async fn publish(&self, server: Arc<Server>) -> Result<()> {
@@ -104,43 +336,182 @@ async fn handle_topic(
     server: Arc<Server>,
     client: Arc<PartitionClient>,
     topic: String,
+    start_offset: StartOffset,
+    manual_commit: bool,
+    decode: bool,
 ) -> Result<()> {
-    let mut stream = StreamConsumerBuilder::new(client, StartOffset::Latest)
-        .with_max_wait_ms(100)
-        .build();
-    while let Some(event) = stream.next().await {
-        match event {
-            Ok((record_and_offset, _)) => {
-                handle_event(&server, topic.clone(), record_and_offset.record).await?;
-            }
-            Err(err) => {
-                warn!("Failed to receive Kafka event: {}", err);
+    let consumer_group = server
+        .kafka_service
+        .as_ref()
+        .map(|service| service.consumer_group().to_owned())
+        .unwrap_or_default();
+    let meta = &server.meta_service;
+    // offset of the event that is being delivered again, with the number of redeliveries
+    let mut redeliveries: Option<(i64, usize)> = None;
+
+    'subscribe: loop {
+        // resume after the last committed offset, if there is any
+        let committed = meta
+            .load_kafka_offset(&consumer_group, &topic, PARTITION)
+            .await?;
+        let start_offset = match committed {
+            Some(offset) => StartOffset::At(offset + 1),
+            None => start_offset,
+        };
+
+        let mut stream = StreamConsumerBuilder::new(client.clone(), start_offset)
+            .with_max_wait_ms(100)
+            .build();
+        while let Some(event) = stream.next().await {
+            match event {
+                Ok((record_and_offset, _)) => {
+                    let offset = record_and_offset.offset;
+                    let done_rxs = handle_event(
+                        &server,
+                        topic.clone(),
+                        offset,
+                        record_and_offset.record,
+                        decode,
+                    )
+                    .await?;
+
+                    // wait until all versions have handled the event before its offset is
+                    // persisted, so that the event is delivered again if we crash meanwhile
+                    let delivered = !done_rxs.is_empty();
+                    done_rxs.collect::<Vec<_>>().await;
+
+                    // in the manual commit mode, the handlers commit the events themselves, only
+                    // the events that were not delivered to any version (because no handler
+                    // accepted them) are committed here
+                    if manual_commit && delivered {
+                        let committed = meta
+                            .load_kafka_offset(&consumer_group, &topic, PARTITION)
+                            .await?;
+                        if committed.map_or(false, |committed| committed >= offset) {
+                            continue;
+                        }
+
+                        // subscribe again from the last committed offset to deliver the event
+                        // again, unless it has already been delivered too many times
+                        let attempts = match redeliveries {
+                            Some((redelivered, attempts)) if redelivered == offset => attempts + 1,
+                            _ => 1,
+                        };
+                        if attempts <= MAX_REDELIVERIES {
+                            warn!(
+                                "Kafka event at offset {} of topic {:?} was not committed, it \
+                                will be delivered again ({}/{})",
+                                offset, topic, attempts, MAX_REDELIVERIES
+                            );
+                            redeliveries = Some((offset, attempts));
+                            tokio::time::sleep(REDELIVERY_DELAY).await;
+                            continue 'subscribe;
+                        }
+                        error!(
+                            "Kafka event at offset {} of topic {:?} was not committed after {} \
+                            redeliveries, giving up on it",
+                            offset, topic, MAX_REDELIVERIES
+                        );
+                    }
+
+                    let mut transaction = meta.begin_transaction().await?;
+                    MetaService::persist_kafka_offset(
+                        &mut transaction,
+                        &consumer_group,
+                        &topic,
+                        PARTITION,
+                        offset,
+                    )
+                    .await?;
+                    MetaService::commit_transaction(transaction).await?;
+                }
+                Err(err) => {
+                    warn!("Failed to receive Kafka event: {}", err);
+                }
             }
         }
+        return Ok(());
     }
-    Ok(())
 }
 
-async fn handle_event(server: &Server, topic: String, record: Record) -> Result<()> {
+/// Sends the event to all versions and returns receivers that will be closed once the
+/// corresponding version has handled the event.
+async fn handle_event(
+    server: &Server,
+    topic: String,
+    offset: i64,
+    record: Record,
+    decode: bool,
+) -> Result<FuturesUnordered<oneshot::Receiver<()>>> {
     let key = record.key.unwrap_or_default();
     let value = record.value.unwrap_or_default();
+    let headers = record.headers;
 
-    // TODO: this is just a dirty proof-of-concept; in particular:
-    // - we don't know how to map events to versions, so we send the event to _all_ versions
-    // - we don't care whether the event was handled correctly or not (we simply ignore any issues
-    // with at-most-once/at-least-once semantics of event delivery)
+    // an event that cannot be decoded is still delivered, the handler can look at the raw value
+    let registry = server
+        .kafka_service
+        .as_ref()
+        .and_then(|service| service.schema_registry.as_ref());
+    let decoded_value = match registry {
+        Some(registry) if decode && !value.is_empty() => match registry.decode(&value).await {
+            Ok(decoded) => Some(decoded),
+            Err(err) => {
+                warn!(
+                    "Could not decode Kafka event at offset {} of topic {:?}: {:?}",
+                    offset, topic, err
+                );
+                None
+            }
+        },
+        _ => None,
+    };
 
-    // send the job to all versions concurrently
-    let send_futs = server
+    // TODO: this is just a dirty proof-of-concept; in particular, we don't know how to map events
+    // to versions, so we send the event to _all_ versions
+
+    let filter_input = FilterInput {
+        key: &key,
+        headers: &headers,
+        value: &value,
+        decoded_value: decoded_value.as_ref(),
+        parsed_value: OnceCell::new(),
+    };
+    let targets = server
         .trunk
         .list_trunk_versions()
         .into_iter()
-        .map(|trunk_version| {
-            enclose! {(topic, key, value) async move {
+        .filter_map(|trunk_version| {
+            let handlers = trunk_version
+                .version
+                .event_filters
+                .read()
+                .get(&topic)
+                .map(|filters| match_handlers(filters, &filter_input));
+            match handlers {
+                // no handler in this version accepts the event
+                Some(ref handlers) if handlers.is_empty() => None,
+                _ => Some((trunk_version, handlers)),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // send the job to all versions concurrently
+    let done_rxs = FuturesUnordered::new();
+    let send_futs = targets
+        .into_iter()
+        .map(|(trunk_version, handlers)| {
+            let (done_tx, done_rx) = oneshot::channel();
+            done_rxs.push(done_rx);
+            enclose! {(topic, key, value, decoded_value) async move {
                 let kafka_event = KafkaEvent {
                     topic,
+                    partition: PARTITION,
+                    offset,
                     key: key.into(),
                     value: value.into(),
+                    decoded_value,
+                    handlers,
+                    done_tx: Some(done_tx),
                 };
                 let job = VersionJob::Kafka(kafka_event);
                 let _: Result<_, _> = trunk_version.job_tx.send(job).await;
@@ -149,7 +520,7 @@ async fn handle_event(server: &Server, topic: String, record: Record) -> Result<
         .collect::<FuturesUnordered<_>>();
     send_futs.collect::<()>().await;
 
-    Ok(())
+    Ok(done_rxs)
 }
 
 async fn handle_publish(server: Arc<Server>) -> Result<()> {
@@ -168,3 +539,62 @@ async fn handle_publish(server: Arc<Server>) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filter(filter: serde_json::Value) -> EventFilter {
+        serde_json::from_value(filter).unwrap()
+    }
+
+    #[test]
+    fn event_filters() {
+        let headers = BTreeMap::from([("source".to_string(), b"web".to_vec())]);
+        let input = FilterInput {
+            key: b"order-42",
+            headers: &headers,
+            value: br#"{"kind": "created", "customer": {"country": "CZ"}, "items": [1, 2]}"#,
+            decoded_value: None,
+            parsed_value: OnceCell::new(),
+        };
+
+        assert!(filter(json!({})).matches(&input));
+        assert!(filter(json!({"keyPrefix": "order-"})).matches(&input));
+        assert!(!filter(json!({"keyPrefix": "invoice-"})).matches(&input));
+        assert!(filter(json!({"headers": {"source": "web"}})).matches(&input));
+        assert!(!filter(json!({"headers": {"source": "app"}})).matches(&input));
+        assert!(!filter(json!({"headers": {"region": "eu"}})).matches(&input));
+        assert!(
+            filter(json!({"fields": {"kind": "created", "customer.country": "CZ"}}))
+                .matches(&input)
+        );
+        assert!(filter(json!({"fields": {"items.1": 2}})).matches(&input));
+        assert!(!filter(json!({"fields": {"customer.city": "Brno"}})).matches(&input));
+        assert!(
+            !filter(json!({"keyPrefix": "order-", "fields": {"kind": "deleted"}})).matches(&input)
+        );
+
+        let filters = vec![
+            Some(filter(json!({"fields": {"kind": "deleted"}}))),
+            None,
+            Some(filter(json!({"keyPrefix": "order-"}))),
+        ];
+        assert_eq!(match_handlers(&filters, &input), vec![1, 2]);
+    }
+
+    #[test]
+    fn event_filters_decoded_value() {
+        let headers = BTreeMap::new();
+        let decoded = json!({"kind": "created"});
+        let input = FilterInput {
+            key: b"",
+            headers: &headers,
+            value: &[0, 0, 0, 0, 1, 2],
+            decoded_value: Some(&decoded),
+            parsed_value: OnceCell::new(),
+        };
+        assert!(filter(json!({"fields": {"kind": "created"}})).matches(&input));
+    }
+}
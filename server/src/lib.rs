@@ -2,8 +2,12 @@
 
 use once_cell::sync::OnceCell;
 
+use crate::datastore::tenancy::TenancyConfig;
+
+pub use crate::apply::check_policy;
 pub use crate::opt::Opt;
 pub use crate::server::run;
+pub use crate::version::module_hash;
 pub use authorization::is_auth_entity_name;
 
 pub(crate) type JsonObject = serde_json::Map<String, serde_json::Value>;
@@ -17,10 +21,17 @@ pub(crate) fn feat_typescript_policies() -> bool {
         .unwrap_or_default()
 }
 
+/// How the tenant of a request is identified, `None` if the data is not isolated per tenant, see
+/// `datastore::tenancy`.
+pub(crate) fn tenancy() -> Option<&'static TenancyConfig> {
+    FEATURES.get()?.tenancy.as_ref()
+}
+
 /// Chiseld experimental features
 #[derive(Default)]
 pub struct Features {
     typescript_policies: bool,
+    tenancy: Option<TenancyConfig>,
 }
 
 #[macro_use]
@@ -30,27 +41,48 @@ extern crate log;
 pub(crate) mod error;
 
 pub(crate) mod apply;
+pub(crate) mod apply_plan;
 pub(crate) mod authentication;
 pub(crate) mod authorization;
+pub mod backup;
+pub(crate) mod blob;
+pub(crate) mod cancel;
+pub(crate) mod contract;
 pub(crate) mod datastore;
+pub(crate) mod docs;
 pub(crate) mod http;
 pub(crate) mod internal;
+pub(crate) mod json_schema;
 pub(crate) mod kafka;
+pub mod logging;
+pub(crate) mod mirror;
 pub(crate) mod module_loader;
 mod nursery;
+pub(crate) mod oidc;
+pub(crate) mod openapi;
 pub mod ops;
 pub(crate) mod opt;
 pub(crate) mod outbox;
 pub(crate) mod policies;
 mod policy;
 pub(crate) mod prefix_map;
+pub(crate) mod rate_limit;
+pub(crate) mod request_log;
 pub(crate) mod rpc;
+pub(crate) mod schema_registry;
 pub(crate) mod secrets;
+pub(crate) mod seed;
 pub(crate) mod server;
+pub(crate) mod socket;
+pub(crate) mod static_files;
+pub mod telemetry;
+pub(crate) mod traffic_split;
 pub(crate) mod trunk;
 pub(crate) mod types;
 pub(crate) mod version;
+pub(crate) mod watchdog;
 pub(crate) mod worker;
+pub(crate) mod worker_pool;
 
 #[allow(clippy::all)]
 pub(crate) mod proto {
@@ -0,0 +1,178 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Logging of `chiseld`: all log records go through `env_logger`, which prints them either as
+//! plain text or as JSON objects (one per line), depending on `--log-format`.
+//!
+//! Events that carry structured fields (such as the trace id of a request) are logged with
+//! [`log_event()`], which attaches the fields to the record: in the text format, they are
+//! appended as `key=value` pairs, in the JSON format, they become properties of the object.
+
+use anyhow::{bail, Result};
+use env_logger::Env;
+use log::{Level, LevelFilter};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Format of the log output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("Invalid log format {:?}, expected `text` or `json`", s),
+        }
+    }
+}
+
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Fields of the record that is currently being logged by `log_event()` on this thread.
+    /// `env_logger` formats records synchronously, so the format function can pick them up.
+    static EVENT_FIELDS: RefCell<Vec<(&'static str, String)>> = RefCell::new(Vec::new());
+}
+
+/// Initializes the global logger.
+pub fn init() {
+    // the logger itself accepts everything unless `RUST_LOG` says otherwise, and we limit the
+    // output using `log::set_max_level()`, so that the level can be changed when the
+    // configuration is reloaded
+    env_logger::Builder::from_env(Env::default().default_filter_or("trace"))
+        .format(|buf, record| {
+            let timestamp = buf.timestamp();
+            EVENT_FIELDS.with(|fields| {
+                let fields = fields.borrow();
+                let line = if JSON_FORMAT.load(Ordering::Relaxed) {
+                    format_json(&timestamp, record, &fields)
+                } else {
+                    format_text(&timestamp, record, &fields)
+                };
+                writeln!(buf, "{}", line)
+            })
+        })
+        .filter_module("sqlx::query", LevelFilter::Warn)
+        .init();
+    if std::env::var_os("RUST_LOG").is_none() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+pub fn set_format(format: LogFormat) {
+    JSON_FORMAT.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+/// Logs a message with additional structured fields.
+pub fn log_event(
+    level: Level,
+    target: &str,
+    args: fmt::Arguments,
+    fields: Vec<(&'static str, String)>,
+) {
+    if level > log::max_level() {
+        return;
+    }
+    EVENT_FIELDS.with(|f| *f.borrow_mut() = fields);
+    log::logger().log(
+        &log::Record::builder()
+            .level(level)
+            .target(target)
+            .args(args)
+            .build(),
+    );
+    EVENT_FIELDS.with(|f| f.borrow_mut().clear());
+}
+
+fn format_text(
+    timestamp: &dyn fmt::Display,
+    record: &log::Record,
+    fields: &[(&'static str, String)],
+) -> String {
+    let mut line = format!("[{}] {} - {}", timestamp, record.level(), record.args());
+    for (key, value) in fields.iter() {
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    line
+}
+
+fn format_json(
+    timestamp: &dyn fmt::Display,
+    record: &log::Record,
+    fields: &[(&'static str, String)],
+) -> String {
+    let mut object = serde_json::Map::new();
+    object.insert("timestamp".into(), timestamp.to_string().into());
+    object.insert("level".into(), record.level().as_str().into());
+    object.insert("target".into(), record.target().into());
+    object.insert("message".into(), record.args().to_string().into());
+    for (key, value) in fields.iter() {
+        object.insert((*key).into(), value.clone().into());
+    }
+    serde_json::Value::Object(object).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_fields() -> Vec<(&'static str, String)> {
+        vec![("trace_id", "abc".into()), ("status", "200".into())]
+    }
+
+    #[test]
+    fn text_format_appends_fields() {
+        let line = format_text(
+            &"2022-01-01T00:00:00Z",
+            &log::Record::builder()
+                .level(Level::Info)
+                .args(format_args!("GET /dev/hello"))
+                .build(),
+            &record_fields(),
+        );
+        assert_eq!(
+            line,
+            "[2022-01-01T00:00:00Z] INFO - GET /dev/hello trace_id=abc status=200"
+        );
+    }
+
+    #[test]
+    fn json_format_includes_fields() {
+        let line = format_json(
+            &"2022-01-01T00:00:00Z",
+            &log::Record::builder()
+                .level(Level::Warn)
+                .target("chisel::http")
+                .args(format_args!("say \"hi\""))
+                .build(),
+            &record_fields(),
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "timestamp": "2022-01-01T00:00:00Z",
+                "level": "WARN",
+                "target": "chisel::http",
+                "message": "say \"hi\"",
+                "trace_id": "abc",
+                "status": "200",
+            })
+        );
+    }
+
+    #[test]
+    fn parse_log_format() {
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
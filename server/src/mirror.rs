@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use parking_lot::Mutex;
+use std::time::Duration;
+
+/// Rule for mirroring ("shadowing") HTTP requests from one version to another.
+///
+/// A sample of the requests that are handled by the source version is also sent to the target
+/// version. The responses from the target version are discarded and the request is executed in a
+/// sandbox: its transaction is always rolled back, and it does not write to `Chisel.blob` or send
+/// to sockets. However, outbound requests with `fetch()` are still made, so the target version
+/// must not call services that have side effects from mirrored requests.
+#[derive(Debug)]
+pub struct Mirror {
+    pub target_version_id: String,
+    /// Fraction of requests (between 0 and 1) that are mirrored.
+    pub fraction: f64,
+    stats: Mutex<MirrorStats>,
+}
+
+/// Statistics collected for a [`Mirror`].
+#[derive(Debug, Default, Clone)]
+pub struct MirrorStats {
+    /// Number of requests that were mirrored and completed in both versions.
+    pub mirrored: u64,
+    /// Number of mirrored requests that failed (with a 5xx status) in the source version.
+    pub source_errors: u64,
+    /// Number of mirrored requests that failed (with a 5xx status) in the target version.
+    pub target_errors: u64,
+    /// Number of mirrored requests where the versions responded with a different status.
+    pub status_mismatches: u64,
+    /// Total time spent handling the mirrored requests in the source version.
+    pub source_latency: Duration,
+    /// Total time spent handling the mirrored requests in the target version.
+    pub target_latency: Duration,
+}
+
+/// Result of handling a request in one version. The `status` is `None` if the request failed
+/// without producing a response.
+#[derive(Debug, Clone, Copy)]
+pub struct MirrorOutcome {
+    pub status: Option<u16>,
+    pub latency: Duration,
+}
+
+impl MirrorOutcome {
+    fn is_error(&self) -> bool {
+        self.status.map_or(true, |status| status >= 500)
+    }
+}
+
+impl Mirror {
+    pub fn new(target_version_id: String, fraction: f64) -> Self {
+        Self {
+            target_version_id,
+            fraction,
+            stats: Mutex::new(MirrorStats::default()),
+        }
+    }
+
+    /// Decides whether the next request should be mirrored.
+    pub fn sample(&self) -> bool {
+        rand::random::<f64>() < self.fraction
+    }
+
+    pub fn record(&self, source: MirrorOutcome, target: MirrorOutcome) {
+        let mut stats = self.stats.lock();
+        stats.mirrored += 1;
+        stats.source_errors += source.is_error() as u64;
+        stats.target_errors += target.is_error() as u64;
+        stats.status_mismatches += (source.status != target.status) as u64;
+        stats.source_latency += source.latency;
+        stats.target_latency += target.latency;
+    }
+
+    pub fn stats(&self) -> MirrorStats {
+        self.stats.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(status: Option<u16>, latency_ms: u64) -> MirrorOutcome {
+        MirrorOutcome {
+            status,
+            latency: Duration::from_millis(latency_ms),
+        }
+    }
+
+    #[test]
+    fn record_compares_outcomes() {
+        let mirror = Mirror::new("v2".into(), 1.0);
+        mirror.record(outcome(Some(200), 10), outcome(Some(200), 30));
+        mirror.record(outcome(Some(200), 10), outcome(Some(500), 20));
+        mirror.record(outcome(Some(404), 10), outcome(None, 5));
+
+        let stats = mirror.stats();
+        assert_eq!(stats.mirrored, 3);
+        assert_eq!(stats.source_errors, 0);
+        assert_eq!(stats.target_errors, 2);
+        assert_eq!(stats.status_mismatches, 2);
+        assert_eq!(stats.source_latency, Duration::from_millis(30));
+        assert_eq!(stats.target_latency, Duration::from_millis(55));
+    }
+
+    #[test]
+    fn sample_respects_fraction() {
+        assert!(!Mirror::new("v2".into(), 0.0).sample());
+        assert!(Mirror::new("v2".into(), 1.0).sample());
+    }
+}
@@ -5,17 +5,36 @@ use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
+use utils::ImportMap;
 
 /// The loader is used by Deno when V8 resolves and loads modules.
 #[derive(Debug)]
 pub struct ModuleLoader {
     /// Maps fully qualified module specifiers (absolute URLs) to transpiled JavaScript sources.
     modules: Arc<HashMap<String, String>>,
+    /// Resolves the bare specifiers (such as `@lib/util.ts`) that the compiled modules import.
+    import_map: Arc<ImportMap>,
 }
 
 impl ModuleLoader {
-    pub fn new(modules: Arc<HashMap<String, String>>) -> ModuleLoader {
-        ModuleLoader { modules }
+    pub fn new(modules: Arc<HashMap<String, String>>, import_map: Arc<ImportMap>) -> ModuleLoader {
+        ModuleLoader {
+            modules,
+            import_map,
+        }
+    }
+
+    /// Resolves `specifier` with the import map. Like the compiler with the path aliases of
+    /// `tsconfig.json`, a missing `.ts` extension is added if the module exists with it.
+    fn resolve_mapped(&self, specifier: &str) -> Option<Url> {
+        let url = self.import_map.resolve(specifier)?;
+        if !self.modules.contains_key(&url) {
+            let with_ext = format!("{}.ts", url);
+            if self.modules.contains_key(&with_ext) {
+                return Url::parse(&with_ext).ok();
+            }
+        }
+        Url::parse(&url).ok()
     }
 }
 
@@ -25,6 +44,8 @@ impl deno_core::ModuleLoader for ModuleLoader {
             Url::parse("chisel://api/api.ts").unwrap()
         } else if let Some(path) = NODE_POLYFILLS.get(specifier) {
             Url::parse(&format!("chisel://deno-std/{}", path)).unwrap()
+        } else if let Some(url) = self.resolve_mapped(specifier) {
+            url
         } else {
             deno_core::resolve_import(specifier, referrer)?
         })
@@ -0,0 +1,671 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Built-in login with an OpenID Connect provider, enabled with `--oidc-issuer`.
+//!
+//! [`LOGIN_PATH`] starts the authorization code flow by redirecting the user to the provider,
+//! which redirects back to [`CALLBACK_PATH`]. The callback exchanges the code for an ID token,
+//! finds or creates the `AuthUser` of the provider account (linked to it by an `AuthAccount`) and
+//! starts a session. The login is bound to the browser that started it by the [`LOGIN_COOKIE`]
+//! cookie, which holds the `state` of the login, so a callback URL cannot be finished in another
+//! browser. The code is exchanged with a PKCE verifier, so it is useless to whoever intercepts it,
+//! even if chiseld has no client secret. The token of the session is returned in the
+//! [`SESSION_COOKIE`] cookie, and
+//! requests authenticate with the cookie or with the token in the `Authorization: Bearer` header
+//! (see `authentication.rs`), so users can log in without an external NextAuth app.
+//!
+//! The logins in progress and the sessions are stored in the meta database, so they survive
+//! restarts and are shared by all instances of chiseld. Only the hashes of the session tokens are
+//! stored.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Context;
+use jsonwebtoken::Validation;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::authentication::{jwk_decoding_key, JwksCache};
+use crate::authorization::{AUTH_ACCOUNT_NAME, AUTH_USER_NAME};
+use crate::datastore::value::{EntityMap, EntityValue};
+use crate::datastore::{LoginState, MetaService, QueryEngine};
+use crate::error::{Result, ResultExt};
+use crate::opt::Opt;
+use crate::server::Server;
+use crate::types::{ObjectType, Type};
+
+/// Path that starts the login. The optional `redirect` query parameter is the path that the user
+/// is redirected to after the login.
+pub const LOGIN_PATH: &str = "/__chiselstrike/auth/login";
+/// Path that the provider redirects the user to, with the authorization code.
+pub const CALLBACK_PATH: &str = "/__chiselstrike/auth/callback";
+/// Cookie that carries the session token.
+pub const SESSION_COOKIE: &str = "chisel_session";
+/// Cookie that carries the `state` of the login in progress, from [`LOGIN_PATH`] to
+/// [`CALLBACK_PATH`].
+pub const LOGIN_COOKIE: &str = "chisel_login";
+
+/// Secret with the client secret of chiseld at the provider.
+const CLIENT_SECRET_NAME: &str = "CHISEL_OIDC_CLIENT_SECRET";
+/// How long the user has to finish a login at the provider.
+const LOGIN_MAX_AGE_S: i64 = 600;
+/// Scopes that are requested from the provider.
+const SCOPES: &str = "openid email profile";
+
+/// Configuration of the built-in login (from `--oidc-issuer` and the related options).
+pub struct OidcConfig {
+    issuer: String,
+    client_id: String,
+    redirect_url: String,
+    session_ttl_s: i64,
+    client: reqwest::Client,
+    /// Endpoints and keys of the provider, discovered at the first login.
+    provider: tokio::sync::OnceCell<Provider>,
+}
+
+/// Provider as described by its discovery document.
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks: JwksCache,
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// Response of the token endpoint of the provider.
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    access_token: Option<String>,
+    token_type: Option<String>,
+    refresh_token: Option<String>,
+    scope: Option<String>,
+}
+
+/// Claims of the ID token that identify the user.
+#[derive(Deserialize)]
+struct IdClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+    picture: Option<String>,
+}
+
+impl OidcConfig {
+    /// Returns the configuration of the built-in login, `None` if it is disabled.
+    pub fn from_opt(opt: &Opt) -> anyhow::Result<Option<Self>> {
+        let issuer = match &opt.oidc_issuer {
+            Some(issuer) => issuer.clone(),
+            None => return Ok(None),
+        };
+        let client_id = opt
+            .oidc_client_id
+            .clone()
+            .context("--oidc-issuer requires --oidc-client-id")?;
+        let redirect_url = opt.oidc_redirect_url.clone().with_context(|| {
+            format!("--oidc-issuer requires --oidc-redirect-url, the public URL of {CALLBACK_PATH}")
+        })?;
+        Ok(Some(Self {
+            issuer,
+            client_id,
+            redirect_url,
+            session_ttl_s: opt.oidc_session_ttl_s as i64,
+            client: reqwest::Client::new(),
+            provider: tokio::sync::OnceCell::new(),
+        }))
+    }
+
+    async fn provider(&self) -> Result<&Provider> {
+        self.provider
+            .get_or_try_init(|| async {
+                self.discover()
+                    .await
+                    .with_context(|| {
+                        format!("Could not discover the OIDC provider {}", self.issuer)
+                    })
+                    .err_internal()
+            })
+            .await
+    }
+
+    async fn discover(&self) -> anyhow::Result<Provider> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let response = self.client.get(&url).send().await?;
+        let metadata: ProviderMetadata = response.error_for_status()?.json().await?;
+        anyhow::ensure!(
+            same_issuer(&metadata.issuer, &self.issuer),
+            "the discovery document is for the issuer {}",
+            metadata.issuer
+        );
+        Ok(Provider {
+            issuer: metadata.issuer,
+            authorization_endpoint: metadata.authorization_endpoint,
+            token_endpoint: metadata.token_endpoint,
+            jwks: JwksCache::new(metadata.jwks_uri),
+        })
+    }
+
+    /// Returns the URL of the provider that the user logs in at.
+    fn authorization_url(&self, provider: &Provider, login: &LoginState) -> Result<url::Url> {
+        let code_challenge = code_challenge(&login.code_verifier);
+        url::Url::parse_with_params(
+            &provider.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", self.redirect_url.as_str()),
+                ("scope", SCOPES),
+                ("state", login.state.as_str()),
+                ("nonce", login.nonce.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .context("Invalid authorization endpoint of the OIDC provider")
+        .err_internal()
+    }
+
+    /// Exchanges the authorization `code` for the tokens of the user.
+    async fn exchange_code(
+        &self,
+        provider: &Provider,
+        code: &str,
+        code_verifier: &str,
+        client_secret: Option<&str>,
+    ) -> Result<TokenResponse> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("code_verifier", code_verifier),
+            ("redirect_uri", self.redirect_url.as_str()),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(client_secret) = client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let response = self
+            .client
+            .post(&provider.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .context("Could not reach the token endpoint of the OIDC provider")
+            .err_internal()?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            forbidden!("the OIDC provider refused the authorization code ({status}): {body}");
+        }
+        response
+            .json()
+            .await
+            .context("Invalid response of the token endpoint of the OIDC provider")
+            .err_internal()
+    }
+
+    /// Validates the ID token that the provider issued for the login with `nonce`.
+    async fn validate_id_token(
+        &self,
+        provider: &Provider,
+        token: &str,
+        nonce: &str,
+    ) -> Result<IdClaims> {
+        let header = jsonwebtoken::decode_header(token).err_forbidden()?;
+        let jwk = provider.jwks.key(header.kid.as_deref()).await?;
+        let key = jwk_decoding_key(&jwk, header.alg)?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        let claims = jsonwebtoken::decode::<IdClaims>(token, &key, &validation)
+            .err_forbidden()?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            forbidden!("the ID token was not issued for this login");
+        }
+        Ok(claims)
+    }
+
+    /// Returns the `Set-Cookie` header that stores the session `token`.
+    fn session_cookie_header(&self, token: &str) -> String {
+        self.cookie_header(SESSION_COOKIE, token, "/", self.session_ttl_s)
+    }
+
+    /// Returns the `Set-Cookie` header that stores the `state` of a login until its callback. An
+    /// empty `state` removes the cookie.
+    fn login_cookie_header(&self, state: &str) -> String {
+        let max_age = if state.is_empty() { 0 } else { LOGIN_MAX_AGE_S };
+        self.cookie_header(LOGIN_COOKIE, state, CALLBACK_PATH, max_age)
+    }
+
+    fn cookie_header(&self, name: &str, value: &str, path: &str, max_age: i64) -> String {
+        let mut cookie =
+            format!("{name}={value}; Path={path}; Max-Age={max_age}; HttpOnly; SameSite=Lax");
+        if self.redirect_url.starts_with("https://") {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+/// Starts a login: redirects the user to the provider.
+pub async fn handle_login(
+    server: &Server,
+    oidc: &OidcConfig,
+    uri: &hyper::Uri,
+) -> Result<hyper::Response<hyper::Body>> {
+    let redirect_to = query_params(uri)
+        .remove("redirect")
+        .unwrap_or_else(|| "/".into());
+    if !is_local_path(&redirect_to) {
+        bad_request!(
+            "the redirect after the login must be a path of this server, not {redirect_to:?}"
+        );
+    }
+
+    let provider = oidc.provider().await?;
+    let now = unix_timestamp();
+    let login = LoginState {
+        state: random_token(),
+        nonce: random_token(),
+        code_verifier: random_token(),
+        redirect_to,
+        created_at: now,
+    };
+    server
+        .meta_service
+        .persist_login_state(&login, now - LOGIN_MAX_AGE_S)
+        .await
+        .err_internal()?;
+    let url = oidc.authorization_url(provider, &login)?;
+    Ok(redirect(url.as_str())
+        .header(
+            hyper::header::SET_COOKIE,
+            oidc.login_cookie_header(&login.state),
+        )
+        .body(hyper::Body::empty())
+        .unwrap())
+}
+
+/// Finishes a login: the provider redirected the user back with the authorization code.
+pub async fn handle_callback(
+    server: &Server,
+    oidc: &OidcConfig,
+    uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+) -> Result<hyper::Response<hyper::Body>> {
+    let mut params = query_params(uri);
+    if let Some(error) = params.remove("error") {
+        forbidden!("the OIDC provider refused the login: {error}");
+    }
+    let (code, state) = match (params.remove("code"), params.remove("state")) {
+        (Some(code), Some(state)) => (code, state),
+        _ => bad_request!("the callback of the login requires the `code` and `state` parameters"),
+    };
+    // the login must be finished by the browser that started it, otherwise anybody could log the
+    // user in as somebody else by making them open the callback URL of another login
+    if !is_login_of_browser(headers, &state) {
+        forbidden!("the login was not started by this browser, please log in again");
+    }
+    let login = match server
+        .meta_service
+        .take_login_state(&state)
+        .await
+        .err_internal()?
+    {
+        Some(login) if login.created_at >= unix_timestamp() - LOGIN_MAX_AGE_S => login,
+        _ => forbidden!("unknown or expired login, please log in again"),
+    };
+
+    let provider = oidc.provider().await?;
+    let client_secret = server
+        .secrets
+        .read()
+        .get(CLIENT_SECRET_NAME)
+        .and_then(|secret| secret.as_str())
+        .map(ToOwned::to_owned);
+    let tokens = oidc
+        .exchange_code(
+            provider,
+            &code,
+            &login.code_verifier,
+            client_secret.as_deref(),
+        )
+        .await?;
+    let claims = oidc
+        .validate_id_token(provider, &tokens.id_token, &login.nonce)
+        .await?;
+    let user_id = find_or_create_user(server, &provider.issuer, &claims, &tokens)
+        .await
+        .context("Could not store the user that logged in")
+        .err_internal()?;
+
+    let token = random_token();
+    server
+        .meta_service
+        .persist_login_session(
+            &hash_token(&token),
+            &user_id,
+            unix_timestamp() + oidc.session_ttl_s,
+        )
+        .await
+        .err_internal()?;
+    Ok(redirect(&login.redirect_to)
+        .header(
+            hyper::header::SET_COOKIE,
+            oidc.session_cookie_header(&token),
+        )
+        .header(hyper::header::SET_COOKIE, oidc.login_cookie_header(""))
+        .body(hyper::Body::empty())
+        .unwrap())
+}
+
+/// Returns the id of the user of the session with `token`, `None` if the session does not exist
+/// or has expired.
+pub async fn session_user(meta: &MetaService, token: &str) -> anyhow::Result<Option<String>> {
+    meta.load_login_session_user(&hash_token(token)).await
+}
+
+/// Returns the session token from the session cookie of a request.
+pub fn session_cookie(headers: &hyper::HeaderMap) -> Option<&str> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// Checks whether the login cookie of a request holds `state`, so the login with `state` was
+/// started by the browser that sent the request.
+fn is_login_of_browser(headers: &hyper::HeaderMap, state: &str) -> bool {
+    match cookie(headers, LOGIN_COOKIE) {
+        Some(cookie_state) => cookie_state.as_bytes().ct_eq(state.as_bytes()).into(),
+        None => false,
+    }
+}
+
+fn cookie<'a>(headers: &'a hyper::HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+}
+
+/// Returns the user of the provider account of `claims`. At the first login of the account, the
+/// account is linked to the user with the same verified email, or to a new user.
+async fn find_or_create_user(
+    server: &Server,
+    issuer: &str,
+    claims: &IdClaims,
+    tokens: &TokenResponse,
+) -> anyhow::Result<String> {
+    let user_type = auth_entity(server, AUTH_USER_NAME)?;
+    let account_type = auth_entity(server, AUTH_ACCOUNT_NAME)?;
+    let mut transaction = server.query_engine.begin_transaction().await?;
+
+    let sql = format!(
+        r#"SELECT "userId" FROM "{}" WHERE "provider" = $1 AND "providerAccountId" = $2"#,
+        account_type.backing_table()
+    );
+    let account = sqlx::query(&sql)
+        .bind(issuer.to_owned())
+        .bind(claims.sub.clone())
+        .fetch_optional(&mut transaction)
+        .await?;
+    if let Some(account) = account {
+        return Ok(account.get("userId"));
+    }
+
+    // an unverified email could belong to somebody else, so it never links to an existing user
+    let existing_user = match &claims.email {
+        Some(email) if claims.email_verified => {
+            let sql = format!(
+                r#"SELECT "id" FROM "{}" WHERE "email" = $1"#,
+                user_type.backing_table()
+            );
+            sqlx::query(&sql)
+                .bind(email.clone())
+                .fetch_optional(&mut transaction)
+                .await?
+                .map(|user| user.get::<String, _>("id"))
+        }
+        _ => None,
+    };
+    let user_id = match existing_user {
+        Some(user_id) => user_id,
+        None => {
+            let user_id = Uuid::new_v4().to_string();
+            let mut user = EntityMap::new();
+            user.insert("id".into(), EntityValue::String(user_id.clone()));
+            insert_optional(&mut user, "email", &claims.email);
+            insert_optional(&mut user, "name", &claims.name);
+            insert_optional(&mut user, "image", &claims.picture);
+            if claims.email_verified {
+                user.insert("emailVerified".into(), EntityValue::String(iso_now()));
+            }
+            server
+                .query_engine
+                .add_row_shallow(&mut transaction, &user_type, &user)
+                .await?;
+            user_id
+        }
+    };
+
+    let mut account = EntityMap::new();
+    account.insert("id".into(), EntityValue::String(Uuid::new_v4().to_string()));
+    account.insert(
+        "providerAccountId".into(),
+        EntityValue::String(claims.sub.clone()),
+    );
+    account.insert("userId".into(), EntityValue::String(user_id.clone()));
+    account.insert("provider".into(), EntityValue::String(issuer.to_owned()));
+    account.insert("type".into(), EntityValue::String("oidc".into()));
+    insert_optional(&mut account, "access_token", &tokens.access_token);
+    insert_optional(&mut account, "token_type", &tokens.token_type);
+    insert_optional(&mut account, "id_token", &Some(tokens.id_token.clone()));
+    insert_optional(&mut account, "refresh_token", &tokens.refresh_token);
+    insert_optional(&mut account, "scope", &tokens.scope);
+    server
+        .query_engine
+        .add_row_shallow(&mut transaction, &account_type, &account)
+        .await?;
+
+    QueryEngine::commit_transaction(transaction).await?;
+    Ok(user_id)
+}
+
+fn auth_entity(server: &Server, name: &str) -> anyhow::Result<Arc<ObjectType>> {
+    match server.builtin_types.types.get(name) {
+        Some(Type::Entity(entity)) => Ok(entity.object_type().clone()),
+        _ => anyhow::bail!("type {name} not found"),
+    }
+}
+
+fn insert_optional(map: &mut EntityMap, field: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        map.insert(field.into(), EntityValue::String(value.clone()));
+    }
+}
+
+/// Checks whether the issuers are the same, ignoring a trailing slash, which providers are not
+/// consistent about.
+fn same_issuer(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+/// Checks whether `path` is a path of this server, so that the login cannot be abused to redirect
+/// users to other sites.
+fn is_local_path(path: &str) -> bool {
+    path.starts_with('/') && !path.starts_with("//") && !path.contains('\\')
+}
+
+fn query_params(uri: &hyper::Uri) -> HashMap<String, String> {
+    form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn redirect(location: &str) -> http::response::Builder {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::FOUND)
+        .header(hyper::header::LOCATION, location)
+}
+
+/// Returns the PKCE challenge of `code_verifier`, with the `S256` method.
+fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Returns a random token that cannot be guessed.
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Returns the current time in the format of `Date.toISOString()`, which is how NextAuth stores
+/// the times in the auth entities.
+fn iso_now() -> String {
+    let now = time::OffsetDateTime::now_utc();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        now.millisecond()
+    )
+}
+
+fn unix_timestamp() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> OidcConfig {
+        OidcConfig {
+            issuer: "https://issuer".into(),
+            client_id: "chisel".into(),
+            redirect_url: "https://app.example/__chiselstrike/auth/callback".into(),
+            session_ttl_s: 3600,
+            client: reqwest::Client::new(),
+            provider: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    #[test]
+    fn authorization_url() {
+        let provider = Provider {
+            issuer: "https://issuer/".into(),
+            authorization_endpoint: "https://issuer/authorize?prompt=login".into(),
+            token_endpoint: "https://issuer/token".into(),
+            jwks: JwksCache::new("https://issuer/jwks".into()),
+        };
+        let login = LoginState {
+            state: "s".into(),
+            nonce: "n".into(),
+            code_verifier: "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".into(),
+            redirect_to: "/".into(),
+            created_at: 0,
+        };
+        let url = test_config().authorization_url(&provider, &login).unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["prompt"], "login");
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], "chisel");
+        assert_eq!(
+            params["redirect_uri"],
+            "https://app.example/__chiselstrike/auth/callback"
+        );
+        assert_eq!(params["scope"], "openid email profile");
+        assert_eq!(params["state"], "s");
+        assert_eq!(params["nonce"], "n");
+        // the example of RFC 7636
+        assert_eq!(
+            params["code_challenge"],
+            "E9Melhoa2OwvFrEMTJguCHoeXUlJmJ-G9p4dUPJr3b8"
+        );
+        assert_eq!(params["code_challenge_method"], "S256");
+    }
+
+    #[test]
+    fn session_cookies() {
+        let mut headers = hyper::HeaderMap::new();
+        assert_eq!(session_cookie(&headers), None);
+        headers.insert(
+            hyper::header::COOKIE,
+            "theme=dark; chisel_session=abc".parse().unwrap(),
+        );
+        assert_eq!(session_cookie(&headers), Some("abc"));
+
+        let cookie = test_config().session_cookie_header("abc");
+        assert!(cookie.starts_with("chisel_session=abc; Path=/; Max-Age=3600; HttpOnly"));
+        assert!(cookie.ends_with("; Secure"));
+    }
+
+    #[test]
+    fn login_cookies() {
+        let cookie = test_config().login_cookie_header("s1");
+        assert!(cookie.starts_with("chisel_login=s1; Path=/__chiselstrike/auth/callback;"));
+        assert!(cookie.contains("; Max-Age=600; HttpOnly; SameSite=Lax"));
+        let cleared = test_config().login_cookie_header("");
+        assert!(cleared.starts_with("chisel_login=; Path=/__chiselstrike/auth/callback;"));
+        assert!(cleared.contains("; Max-Age=0;"));
+
+        // only the browser with the cookie of the login can finish it
+        let mut headers = hyper::HeaderMap::new();
+        assert!(!is_login_of_browser(&headers, "s1"));
+        headers.insert(
+            hyper::header::COOKIE,
+            "chisel_session=abc; chisel_login=s1".parse().unwrap(),
+        );
+        assert!(is_login_of_browser(&headers, "s1"));
+        assert!(!is_login_of_browser(&headers, "s2"));
+    }
+
+    #[test]
+    fn redirects_stay_local() {
+        assert!(is_local_path("/"));
+        assert!(is_local_path("/dev/profile?tab=1"));
+        assert!(!is_local_path("https://evil.example"));
+        assert!(!is_local_path("//evil.example"));
+        assert!(!is_local_path("/\\evil.example"));
+        assert!(same_issuer("https://issuer/", "https://issuer"));
+    }
+
+    #[test]
+    fn tokens() {
+        let token = random_token();
+        assert_eq!(token.len(), 43);
+        assert!(!token.contains('.'));
+        assert_ne!(token, random_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+    }
+}
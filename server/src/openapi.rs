@@ -0,0 +1,287 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! OpenAPI 3.0 document that describes the routes and entities of all versions.
+//!
+//! Entities are described by the type systems of the versions. Routes are defined in JavaScript,
+//! so every version reports its routes when it starts up (see `op_chisel_set_routes`).
+
+use crate::server::Server;
+use crate::types::{Entity, ObjectType, Type, TypeSystem};
+use crate::version::RouteInfo;
+use serde_json::{json, Map, Value};
+
+/// Methods that are used for routes that accept any method.
+const ANY_METHODS: &[&str] = &["get", "post", "put", "patch", "delete"];
+
+pub fn document(server: &Server) -> Value {
+    let mut versions = server.trunk.list_versions();
+    versions.retain(|version| version.version_id != "__chiselstrike");
+    versions.sort_unstable_by(|x, y| x.version_id.cmp(&y.version_id));
+
+    let mut paths = Map::new();
+    let mut schemas = Map::new();
+    for version in versions.iter() {
+        let version_id = &version.version_id;
+        let mut entities = version
+            .type_system
+            .custom_types
+            .values()
+            .collect::<Vec<_>>();
+        entities.sort_unstable_by(|x, y| x.name().cmp(y.name()));
+        for entity in entities {
+            schemas.insert(
+                schema_name(version_id, entity.name()),
+                entity_schema(&version.type_system, entity),
+            );
+        }
+
+        for route in version.routes.read().iter() {
+            let (path, params) = convert_path_pattern(&route.path_pattern);
+            let item = paths
+                .entry(format!("/{}{}", version_id, path))
+                .or_insert_with(|| json!({}));
+            let methods = route
+                .methods
+                .iter()
+                .flat_map(|method| match method.as_str() {
+                    "*" => ANY_METHODS.iter().map(|m| m.to_string()).collect(),
+                    method => vec![method.to_lowercase()],
+                })
+                .collect::<Vec<_>>();
+            for method in methods {
+                item[method] = operation(version_id, route, &params);
+            }
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "ChiselStrike all routes",
+            "version": env!("VERGEN_GIT_SEMVER_LIGHTWEIGHT"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+        },
+    })
+}
+
+fn schema_name(version_id: &str, entity_name: &str) -> String {
+    format!("{}.{}", version_id, entity_name)
+}
+
+fn schema_ref(version_id: &str, entity_name: &str) -> Value {
+    json!({
+        "$ref":
+            format!(
+                "#/components/schemas/{}",
+                schema_name(version_id, entity_name)
+            )
+    })
+}
+
+fn entity_schema(ts: &TypeSystem, entity: &ObjectType) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for field in entity.all_fields() {
+        let ty = match ts.get(&field.type_id) {
+            Ok(ty) => ty,
+            Err(_) => continue,
+        };
+        let mut schema = type_schema(&ts.version_id, &ty);
+        if let Some(enum_type) = &field.enum_type {
+            schema["enum"] = json!(enum_type.values);
+        }
+        if let Some(max_bytes) = field.max_bytes {
+            schema["maxLength"] = json!(crate::json_schema::base64_len(max_bytes));
+        }
+        properties.insert(field.name.clone(), schema);
+        // the id is generated by the server, so it is not required in requests
+        if field.name != "id" && !field.is_optional && field.user_provided_default().is_none() {
+            required.push(field.name.clone());
+        }
+    }
+
+    let mut schema = json!({
+        "type": "object",
+        "properties": properties,
+    });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+fn type_schema(version_id: &str, ty: &Type) -> Value {
+    match ty {
+        Type::String | Type::EntityId(_) => json!({ "type": "string" }),
+        Type::Float => json!({ "type": "number" }),
+        Type::Int64 => json!({ "type": "string", "format": "int64" }),
+        Type::Decimal { .. } => json!({ "type": "string", "format": "decimal" }),
+        Type::Boolean => json!({ "type": "boolean" }),
+        Type::JsDate => json!({ "type": "string", "format": "date-time" }),
+        Type::ArrayBuffer => json!({ "type": "string", "format": "byte" }),
+        Type::Json => json!({}),
+        Type::GeoPoint => crate::json_schema::geo_point_schema(),
+        Type::Entity(Entity::Custom(entity)) => schema_ref(version_id, entity.name()),
+        Type::Entity(Entity::Auth(_)) => json!({ "type": "object" }),
+        Type::Array(element) => json!({
+            "type": "array",
+            "items": type_schema(version_id, element),
+        }),
+    }
+}
+
+fn operation(version_id: &str, route: &RouteInfo, params: &[String]) -> Value {
+    let mut op = json!({
+        "tags": [version_id],
+        "responses": {
+            "200": { "description": "Successful response" },
+        },
+    });
+
+    if !params.is_empty() {
+        op["parameters"] = params
+            .iter()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                })
+            })
+            .collect();
+    }
+
+    if let Some(crud) = &route.crud {
+        let entity = schema_ref(version_id, &crud.entity_name);
+        let json_content = |schema: Value| json!({ "application/json": { "schema": schema } });
+        match crud.kind.as_str() {
+            "GetOne" => {
+                op["responses"]["200"]["content"] = json_content(entity);
+            }
+            "GetMany" => {
+                let page = json!({
+                    "type": "object",
+                    "properties": {
+                        "results": { "type": "array", "items": entity },
+                        "next_page": { "type": "string" },
+                        "prev_page": { "type": "string" },
+                    },
+                });
+                op["responses"]["200"]["content"] = json_content(page);
+            }
+            "PostOne" | "PutOne" | "PatchOne" => {
+                op["requestBody"] = json!({ "content": json_content(entity.clone()) });
+                op["responses"]["200"]["content"] = json_content(entity);
+            }
+            _ => {}
+        }
+    }
+    op
+}
+
+/// Converts a path pattern in the `URLPattern` syntax (such as `/books/:id`) into an OpenAPI path
+/// (such as `/books/{id}`), returning also the names of the path parameters.
+///
+/// Regular expressions of the parameters are dropped, unnamed groups (such as `(.*)`) are named by
+/// their index, as in `URLPattern`.
+pub(crate) fn convert_path_pattern(pattern: &str) -> (String, Vec<String>) {
+    let mut path = String::new();
+    let mut params = Vec::new();
+    let mut unnamed_idx = 0;
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        let name = match c {
+            ':' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                if chars.peek() == Some(&'(') {
+                    chars.next();
+                    skip_group(&mut chars);
+                }
+                name
+            }
+            '(' => {
+                skip_group(&mut chars);
+                let name = unnamed_idx.to_string();
+                unnamed_idx += 1;
+                name
+            }
+            '\\' => {
+                path.extend(chars.next());
+                continue;
+            }
+            c => {
+                path.push(c);
+                continue;
+            }
+        };
+        // modifiers (`?`, `*` and `+`) cannot be expressed in OpenAPI paths
+        if matches!(chars.peek(), Some('?' | '*' | '+')) {
+            chars.next();
+        }
+        path.push_str(&format!("{{{}}}", name));
+        params.push(name);
+    }
+    (path, params)
+}
+
+/// Skips the rest of a regex group, after the opening parenthesis.
+fn skip_group(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_patterns() {
+        let convert = convert_path_pattern;
+        assert_eq!(convert("/books"), ("/books".into(), vec![]));
+        assert_eq!(
+            convert("/books/:id"),
+            ("/books/{id}".into(), vec!["id".into()])
+        );
+        assert_eq!(
+            convert("/:legacyPathParams(.*)"),
+            (
+                "/{legacyPathParams}".into(),
+                vec!["legacyPathParams".into()]
+            )
+        );
+        assert_eq!(
+            convert("/a/:x([0-9]+)/b/:y?"),
+            ("/a/{x}/b/{y}".into(), vec!["x".into(), "y".into()])
+        );
+        assert_eq!(
+            convert("/files/(.*)"),
+            ("/files/{0}".into(), vec!["0".into()])
+        );
+        assert_eq!(convert("/a\\:b"), ("/a:b".into(), vec![]));
+    }
+}
@@ -0,0 +1,141 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Ops of the blob storage (`Chisel.blob`), see `blob`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use deno_core::{serde_v8, OpState};
+use serde::Serialize;
+
+use super::WorkerState;
+use crate::blob::{self, BlobStore, MAX_SIGNED_URL_EXPIRY};
+use crate::datastore::tenancy;
+use crate::ops::job_context::{JobContext, JobInfo, OwnerScope};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobJson {
+    data: serde_v8::ZeroCopyBuf,
+    content_type: Option<String>,
+}
+
+/// Everything that an op of the blob storage needs from the current job.
+struct BlobAccess {
+    store: Arc<dyn BlobStore>,
+    job_info: Rc<JobInfo>,
+    /// Key of the blob in the store, which is prefixed with the tenant of the job (see
+    /// `tenancy::key_prefix()`).
+    key: String,
+}
+
+impl BlobAccess {
+    /// Checks that the current user may access `key` of `bucket`.
+    fn new(
+        state: &OpState,
+        job_ctx_rid: deno_core::ResourceId,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Self> {
+        let worker_state = state.borrow::<WorkerState>();
+        let store = worker_state.server.blob_store.clone().context(
+            "Chisel.blob is not enabled, please start chiseld with --blob-dir or --blob-s3-endpoint",
+        )?;
+        let context = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+        blob::validate_bucket(bucket)?;
+        blob::validate_key(key)?;
+
+        // like @ownedBy, the policy does not apply to admins, Kafka events and seeds
+        if let OwnerScope::User(user_id) = context.job_info.owner_scope() {
+            let policy_system = worker_state.version.policy_system();
+            if !policy_system.blob_authorization.is_allowed(user_id, bucket) {
+                bail!(
+                    "Access to bucket {:?} of Chisel.blob is not allowed",
+                    bucket
+                );
+            }
+        }
+        let key_prefix = tenancy::key_prefix(context.job_info.tenant_scope(), "Chisel.blob")?;
+        Ok(Self {
+            store,
+            job_info: context.job_info.clone(),
+            key: format!("{}{}", key_prefix.unwrap_or_default(), key),
+        })
+    }
+}
+
+#[deno_core::op]
+pub async fn op_chisel_blob_put(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+    bucket: String,
+    key: String,
+    data: serde_v8::ZeroCopyBuf,
+    content_type: Option<String>,
+) -> Result<()> {
+    let access = BlobAccess::new(&state.borrow(), job_ctx_rid, &bucket, &key)?;
+    // the store is not transactional, so sandboxed jobs cannot write it at all
+    if access.job_info.is_sandbox() {
+        return Ok(());
+    }
+    let put = access
+        .store
+        .put(&bucket, &access.key, data.to_vec(), content_type.as_deref());
+    access.job_info.cancellable(put).await
+}
+
+#[deno_core::op]
+pub async fn op_chisel_blob_get(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+    bucket: String,
+    key: String,
+) -> Result<Option<BlobJson>> {
+    let access = BlobAccess::new(&state.borrow(), job_ctx_rid, &bucket, &key)?;
+    let blob = access
+        .job_info
+        .cancellable(access.store.get(&bucket, &access.key))
+        .await?;
+    Ok(blob.map(|blob| BlobJson {
+        data: blob.data.into(),
+        content_type: blob.content_type,
+    }))
+}
+
+#[deno_core::op]
+pub async fn op_chisel_blob_delete(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+    bucket: String,
+    key: String,
+) -> Result<bool> {
+    let access = BlobAccess::new(&state.borrow(), job_ctx_rid, &bucket, &key)?;
+    if access.job_info.is_sandbox() {
+        // report whether the blob would have been deleted, without deleting it
+        let get = access.store.get(&bucket, &access.key);
+        return Ok(access.job_info.cancellable(get).await?.is_some());
+    }
+    let delete = access.store.delete(&bucket, &access.key);
+    access.job_info.cancellable(delete).await
+}
+
+/// Returns a URL that gives read access to the blob for `expires_in_s` seconds.
+#[deno_core::op]
+pub fn op_chisel_blob_signed_url(
+    state: &mut OpState,
+    job_ctx_rid: deno_core::ResourceId,
+    bucket: String,
+    key: String,
+    expires_in_s: f64,
+) -> Result<String> {
+    let access = BlobAccess::new(state, job_ctx_rid, &bucket, &key)?;
+    let max_s = MAX_SIGNED_URL_EXPIRY.as_secs();
+    if !(1. ..=max_s as f64).contains(&expires_in_s) {
+        bail!("The expiry of a signed URL must be between 1 and {max_s} seconds");
+    }
+    let expires_in = Duration::from_secs(expires_in_s as u64);
+    access.store.signed_url(&bucket, &access.key, expires_in)
+}
@@ -5,19 +5,27 @@ use std::future::Future;
 use std::pin::Pin;
 use std::rc::{Rc, Weak};
 use std::task::{Context, Poll};
+use std::time::Duration;
 
-use anyhow::{anyhow, bail, Context as _, Result};
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
 use deno_core::serde_v8::Serializable;
 use deno_core::{serde_v8, v8, CancelFuture, OpState};
+use futures::{Stream, TryStreamExt};
+use hyper::body::Bytes;
 use serde::Deserialize;
 
 use super::WorkerState;
+use crate::cancel::CancelToken;
 use crate::datastore::crud;
 use crate::datastore::engine::{IdTree, QueryResults};
 use crate::datastore::expr::Expr;
+use crate::datastore::ingest::{row_to_entity, IngestFormat, RowParser};
+use crate::datastore::kv::kv_table;
 use crate::datastore::query::{Mutation, QueryOpChain, QueryPlan};
+use crate::datastore::raw_sql;
 use crate::datastore::value::EntityValue;
 use crate::ops::job_context::JobContext;
+use crate::policies::anonymization_key;
 use crate::policy::{PolicyContext, PolicyProcessor};
 use crate::types::{Entity, Type};
 use crate::{feat_typescript_policies, JsonObject};
@@ -33,19 +41,27 @@ pub async fn op_chisel_begin_transaction(
         .server
         .query_engine
         .clone();
-    let data_ctx = {
+    let (job_info, create_data_ctx) = {
         let state = state.borrow();
         let ctx = state.resource_table.get::<JobContext>(job_ctx_rid)?;
         let job_info = ctx.job_info.clone();
         let worker_state = state.borrow::<WorkerState>();
         let type_system = worker_state.version.type_system.clone();
-        let policy_system = worker_state.version.policy_system.clone();
+        let policy_system = worker_state.version.policy_system();
         let policy_engine = worker_state.policy_engine.clone();
         let policy_context = PolicyContext::new(policy_engine, ctx.job_info.clone());
-
-        query_engine.create_data_context(type_system, policy_system, policy_context, job_info)
-    }
-    .await?;
+        let anonymization_key = anonymization_key(&worker_state.server.secrets.read());
+
+        let create_data_ctx = query_engine.create_data_context(
+            type_system,
+            policy_system,
+            policy_context,
+            anonymization_key,
+            job_info.clone(),
+        );
+        (job_info, create_data_ctx)
+    };
+    let data_ctx = job_info.cancellable(create_data_ctx).await?;
 
     let ctx = state
         .borrow()
@@ -81,7 +97,12 @@ pub async fn op_chisel_commit_transaction(
         anyhow!("Cannot commit because a reference to the transaction is still being held.")
     })?;
 
-    data_ctx.commit().await?;
+    if data_ctx.job_info.is_sandbox() {
+        // sandboxed jobs must not have any effect on the database
+        data_ctx.rollback()?;
+    } else {
+        data_ctx.commit().await?;
+    }
 
     Ok(())
 }
@@ -140,15 +161,244 @@ pub fn op_chisel_store<'a>(
     Ok(async move {
         let data_ctx = ctx.data_context()?;
         let val = value.try_into_map()?;
-        let (_value, id_tree) = server
+        let add_row = server
             .query_engine
-            .add_row(ty.object_type().clone(), val, &data_ctx)
-            .await?;
+            .add_row(ty.object_type().clone(), val, &data_ctx);
+        let (_value, id_tree) = ctx.job_info.cancellable(add_row).await?;
 
         Ok(id_tree)
     })
 }
 
+#[derive(Deserialize)]
+pub struct UpsertParams<'a> {
+    name: String,
+    find: serde_v8::Value<'a>,
+    create: serde_v8::Value<'a>,
+    update: serde_v8::Value<'a>,
+}
+
+/// Inserts or updates a row with a single `INSERT ... ON CONFLICT` (`ChiselEntity.upsert()`) and
+/// returns the id of the row.
+#[deno_core::op(v8)]
+pub fn op_chisel_upsert<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    state: Rc<RefCell<OpState>>,
+    params: UpsertParams<'a>,
+    job_ctx_rid: deno_core::ResourceId,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<String>>> {
+    let state = state.borrow();
+    let find = EntityValue::from_v8(&params.find.v8_value, scope)?.try_into_map()?;
+    let create = EntityValue::from_v8(&params.create.v8_value, scope)?.try_into_map()?;
+    let update = EntityValue::from_v8(&params.update.v8_value, scope)?.try_into_map()?;
+    let worker_state = state.borrow::<WorkerState>();
+    let server = worker_state.server.clone();
+    let version_id = &worker_state.version.version_id;
+    let ctx = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+    let ts = &worker_state.version.type_system;
+
+    let ty = match ts.lookup_type(&params.name) {
+        Ok(Type::Entity(ty)) => ty,
+        _ => bail!("Cannot save into type {}", params.name),
+    };
+    if ty.is_auth() && !is_auth_path(version_id, ctx.job_info.path().unwrap_or("")) {
+        bail!("Cannot save into auth type {}", params.name);
+    }
+
+    Ok(async move {
+        let data_ctx = ctx.data_context()?;
+        let upsert = server.query_engine.upsert_row(
+            ty.object_type().clone(),
+            find,
+            create,
+            update,
+            &data_ctx,
+        );
+        ctx.job_info.cancellable(upsert).await
+    })
+}
+
+#[derive(Deserialize)]
+pub struct UpdateParams<'a> {
+    name: String,
+    id: String,
+    fields: serde_v8::Value<'a>,
+}
+
+/// Writes some fields of a row with a single `UPDATE ... SET` (`ChiselEntity.update()`) and
+/// returns whether the row exists.
+#[deno_core::op(v8)]
+pub fn op_chisel_update<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    state: Rc<RefCell<OpState>>,
+    params: UpdateParams<'a>,
+    job_ctx_rid: deno_core::ResourceId,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<bool>>> {
+    let state = state.borrow();
+    let fields = EntityValue::from_v8(&params.fields.v8_value, scope)?.try_into_map()?;
+    let worker_state = state.borrow::<WorkerState>();
+    let server = worker_state.server.clone();
+    let version_id = &worker_state.version.version_id;
+    let ctx = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+    let ts = &worker_state.version.type_system;
+
+    let ty = match ts.lookup_type(&params.name) {
+        Ok(Type::Entity(ty)) => ty,
+        _ => bail!("Cannot save into type {}", params.name),
+    };
+    if ty.is_auth() && !is_auth_path(version_id, ctx.job_info.path().unwrap_or("")) {
+        bail!("Cannot save into auth type {}", params.name);
+    }
+
+    Ok(async move {
+        let data_ctx = ctx.data_context()?;
+        let update =
+            server
+                .query_engine
+                .update_row(ty.object_type().clone(), params.id, fields, &data_ctx);
+        ctx.job_info.cancellable(update).await
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestParams {
+    type_name: String,
+    content_type: String,
+    batch_size: usize,
+}
+
+/// Inserts the rows of a CSV or NDJSON upload to an `ingest()` route and returns the number of
+/// inserted rows. The body is streamed from the HTTP request when the route was recognized by
+/// `Version::is_streamed_request()`, otherwise it is taken from `body`.
+#[deno_core::op]
+pub async fn op_chisel_ingest(
+    state: Rc<RefCell<OpState>>,
+    params: IngestParams,
+    body: Option<serde_v8::ZeroCopyBuf>,
+    job_ctx_rid: deno_core::ResourceId,
+) -> Result<usize> {
+    let (server, ty, ctx) = {
+        let state = state.borrow();
+        let worker_state = state.borrow::<WorkerState>();
+        let ty = match worker_state
+            .version
+            .type_system
+            .lookup_type(&params.type_name)
+        {
+            Ok(Type::Entity(ty)) if !ty.is_auth() => ty.object_type().clone(),
+            _ => bail!("Cannot ingest into type {}", params.type_name),
+        };
+        let ctx = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+        (worker_state.server.clone(), ty, ctx)
+    };
+    let format = IngestFormat::from_content_type(&params.content_type)
+        .with_context(|| format!("Cannot ingest content type {:?}", params.content_type))?;
+    let batch_size = params.batch_size.max(1);
+
+    let mut chunks: Pin<Box<dyn Stream<Item = Result<Bytes>>>> =
+        match ctx.job_info.take_body_stream() {
+            Some(body_stream) => Box::pin(body_stream.map_err(anyhow::Error::from)),
+            None => {
+                let body = body.map(|body| Ok(Bytes::from(body.to_vec())));
+                Box::pin(futures::stream::iter(body))
+            }
+        };
+    let data_ctx = ctx.data_context()?;
+    let mut parser = RowParser::new(format);
+    let mut rows = Vec::new();
+    let mut inserted = 0;
+    let mut finished = false;
+    while !finished {
+        match ctx.job_info.cancellable(chunks.try_next()).await? {
+            Some(chunk) => parser.push(&chunk, &mut rows)?,
+            None => {
+                parser.finish(&mut rows)?;
+                finished = true;
+            }
+        }
+        while rows.len() >= batch_size || (finished && !rows.is_empty()) {
+            let batch = rows
+                .drain(..batch_size.min(rows.len()))
+                .map(|row| row_to_entity(&ty, row))
+                .collect::<Result<Vec<_>, _>>()?;
+            inserted += batch.len();
+            let add_rows = server.query_engine.add_rows(ty.clone(), batch, &data_ctx);
+            ctx.job_info.cancellable(add_rows).await?;
+        }
+    }
+    Ok(inserted)
+}
+
+#[derive(Deserialize)]
+pub struct RawQueryParams {
+    sql: String,
+    #[serde(default)]
+    params: Vec<serde_json::Value>,
+}
+
+/// Runs a raw SQL statement (`Chisel.rawQuery()`) in the transaction of the job and returns its
+/// rows. Available only with `--allow-raw-sql`, and not when the data is isolated per tenant.
+#[deno_core::op]
+pub async fn op_chisel_raw_query(
+    state: Rc<RefCell<OpState>>,
+    params: RawQueryParams,
+    job_ctx_rid: deno_core::ResourceId,
+) -> Result<Vec<JsonObject>> {
+    let (ctx, tables) = {
+        let state = state.borrow();
+        let worker_state = state.borrow::<WorkerState>();
+        ensure!(
+            worker_state.server.opt.allow_raw_sql,
+            "Chisel.rawQuery() is available only when chiseld runs with --allow-raw-sql"
+        );
+        // raw queries would read and write the rows of all tenants
+        ensure!(
+            crate::tenancy().is_none(),
+            "Chisel.rawQuery() is not available when the data is isolated per tenant"
+        );
+        let version = &worker_state.version;
+        // the statement may use and write any table of the version, and only those
+        let mut tables = version
+            .type_system
+            .custom_types
+            .values()
+            .map(|entity| entity.object_type().backing_table().to_owned())
+            .collect::<Vec<_>>();
+        tables.push(kv_table(&version.version_id));
+        let ctx = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+        (ctx, tables)
+    };
+    let data_ctx = ctx.data_context()?;
+    let query = async {
+        let mut txn = data_ctx.txn.lock().await;
+        raw_sql::raw_query(&mut txn, &params.sql, &params.params, &tables).await
+    };
+    let result = ctx.job_info.cancellable(query).await?;
+    if result.is_write {
+        data_ctx.mark_written(tables);
+    }
+    Ok(result.rows)
+}
+
+/// Returns the quoted name of the table of an entity, for `Chisel.rawQuery()`.
+#[deno_core::op]
+pub fn op_chisel_raw_table_name(state: &mut OpState, name: String) -> Result<String> {
+    let worker_state = state.borrow::<WorkerState>();
+    ensure!(
+        worker_state.server.opt.allow_raw_sql,
+        "Chisel.table() is available only when chiseld runs with --allow-raw-sql"
+    );
+    ensure!(
+        crate::tenancy().is_none(),
+        "Chisel.table() is not available when the data is isolated per tenant"
+    );
+    match worker_state.version.type_system.lookup_custom_type(&name) {
+        Ok(entity) => Ok(format!(r#""{}""#, entity.backing_table())),
+        Err(_) => bail!("Unknown entity {}", name),
+    }
+}
+
 fn is_auth_path(version_id: &str, routing_path: &str) -> bool {
     version_id == "__chiselstrike" && routing_path.starts_with("/auth/")
 }
@@ -167,7 +417,7 @@ pub async fn op_chisel_delete(
     job_ctx_rid: deno_core::ResourceId,
 ) -> Result<()> {
     let server = state.borrow().borrow::<WorkerState>().server.clone();
-    let (txn, mutation) = {
+    let (job_info, txn, counters, mutation) = {
         let context = state
             .borrow()
             .resource_table
@@ -177,14 +427,21 @@ pub async fn op_chisel_delete(
             Mutation::delete_from_expr(&data_ctx, &params.type_name, &params.filter_expr).context(
                 "failed to construct delete expression from JSON passed to `op_chisel_delete`",
             )?;
-        (data_ctx.txn.clone(), mutation)
+        data_ctx.mark_written(mutation.written_tables());
+        let counters = data_ctx.txn_guard.counters();
+        let job_info = context.job_info.clone();
+        (job_info, data_ctx.txn.clone(), counters, mutation)
     };
 
     let mut txn = txn.lock().await;
-    server
-        .query_engine
-        .mutate_with_transaction(mutation, &mut txn)
+    let deleted = job_info
+        .cancellable(
+            server
+                .query_engine
+                .mutate_with_transaction(mutation, &mut txn),
+        )
         .await?;
+    counters.add_rows_written(deleted);
     Ok(())
 }
 
@@ -202,7 +459,7 @@ pub async fn op_chisel_crud_delete(
     job_ctx_rid: deno_core::ResourceId,
 ) -> Result<()> {
     let server = state.borrow().borrow::<WorkerState>().server.clone();
-    let (txn, mutation) = {
+    let (job_info, txn, counters, mutation) = {
         let context = state
             .borrow()
             .resource_table
@@ -212,14 +469,21 @@ pub async fn op_chisel_crud_delete(
             .context(
                 "failed to construct delete expression from JSON passed to `op_chisel_crud_delete`",
             )?;
-        (data_ctx.txn.clone(), mutation)
+        data_ctx.mark_written(mutation.written_tables());
+        let counters = data_ctx.txn_guard.counters();
+        let job_info = context.job_info.clone();
+        (job_info, data_ctx.txn.clone(), counters, mutation)
     };
 
     let mut txn = txn.lock().await;
-    server
-        .query_engine
-        .mutate_with_transaction(mutation, &mut txn)
+    let deleted = job_info
+        .cancellable(
+            server
+                .query_engine
+                .mutate_with_transaction(mutation, &mut txn),
+        )
         .await?;
+    counters.add_rows_written(deleted);
     Ok(())
 }
 
@@ -230,16 +494,15 @@ pub async fn op_chisel_crud_query(
     job_ctx_rid: deno_core::ResourceId,
 ) -> Result<JsonObject> {
     let server = state.borrow().borrow::<WorkerState>().server.clone();
-    {
-        let context = state
-            .borrow()
-            .resource_table
-            .get::<JobContext>(job_ctx_rid)?;
-        let context = context.current_data_ctx.borrow();
-        let context = context.as_ref().context("No transaction in this context")?;
-        server.query_engine.run_query(context, params)
-    }
-    .await
+    let context = state
+        .borrow()
+        .resource_table
+        .get::<JobContext>(job_ctx_rid)?;
+    let data_ctx = context.data_context()?;
+    context
+        .job_info
+        .cancellable(server.query_engine.run_query(&data_ctx, params))
+        .await
 }
 
 #[deno_core::op]
@@ -247,6 +510,7 @@ pub async fn op_chisel_relational_query_create(
     state: Rc<RefCell<OpState>>,
     op_chain: QueryOpChain,
     job_ctx_rid: deno_core::ResourceId,
+    cache_ttl_ms: Option<u64>,
 ) -> Result<deno_core::ResourceId> {
     let server = state.borrow().borrow::<WorkerState>().server.clone();
     let context = state
@@ -257,27 +521,88 @@ pub async fn op_chisel_relational_query_create(
     let query_plan = QueryPlan::from_op_chain(&data_ctx, op_chain)?;
     let ty = query_plan.base_type().clone();
 
-    let stream = server
-        .query_engine
-        .query(data_ctx.txn.clone(), query_plan)?;
+    let stream = match cache_ttl_ms {
+        Some(ttl_ms) => {
+            let ttl = Duration::from_millis(ttl_ms);
+            let query = server.query_engine.query_cached(&data_ctx, query_plan, ttl);
+            context.job_info.cancellable(query).await?
+        }
+        None => server
+            .query_engine
+            .query(data_ctx.txn.clone(), query_plan)?,
+    };
+    let counters = data_ctx.txn_guard.counters();
+    let stream = Box::pin(stream.inspect_ok(move |_| counters.add_rows_read(1)));
     let resource = QueryStreamResource {
         stream: RefCell::new(stream),
         cancel: Default::default(),
+        job_cancel: context.job_info.cancel_token().cloned(),
         ty,
-        next: RefCell::new(None),
+        rows: RefCell::new(Vec::new()),
     };
     let rid = state.as_ref().borrow_mut().resource_table.add(resource);
 
     Ok(rid)
 }
 
+/// Runs the queries of `op_chains` (`ChiselCursor.batch()`) together and returns a query stream
+/// resource for each of them, from which the rows are taken like from the resource of
+/// `op_chisel_relational_query_create`. See `QueryEngine::query_batch()` for how the queries are
+/// sent to the database.
+#[deno_core::op]
+pub async fn op_chisel_execute_batch(
+    state: Rc<RefCell<OpState>>,
+    op_chains: Vec<QueryOpChain>,
+    job_ctx_rid: deno_core::ResourceId,
+) -> Result<Vec<deno_core::ResourceId>> {
+    let server = state.borrow().borrow::<WorkerState>().server.clone();
+    let context = state
+        .borrow()
+        .resource_table
+        .get::<JobContext>(job_ctx_rid)?;
+    let data_ctx = context.data_context()?;
+    let query_plans = op_chains
+        .into_iter()
+        .map(|op_chain| QueryPlan::from_op_chain(&data_ctx, op_chain))
+        .collect::<Result<Vec<_>>>()?;
+    let types = query_plans
+        .iter()
+        .map(|query_plan| query_plan.base_type().clone())
+        .collect::<Vec<_>>();
+    let batch = server.query_engine.query_batch(&data_ctx, query_plans);
+    let results = context.job_info.cancellable(batch).await?;
+
+    let counters = data_ctx.txn_guard.counters();
+    let mut state = state.borrow_mut();
+    let rids = results
+        .into_iter()
+        .zip(types)
+        .map(|(rows, ty)| {
+            counters.add_rows_read(rows.len() as u64);
+            let stream: QueryResults = Box::pin(futures::stream::iter(rows.into_iter().map(Ok)));
+            state.resource_table.add(QueryStreamResource {
+                stream: RefCell::new(stream),
+                cancel: Default::default(),
+                job_cancel: None,
+                ty,
+                rows: RefCell::new(Vec::new()),
+            })
+        })
+        .collect();
+    Ok(rids)
+}
+
 type DbStream = RefCell<QueryResults>;
 
 struct QueryStreamResource {
     stream: DbStream,
     cancel: deno_core::CancelHandle,
+    /// Cancellation of the job that created the query, which aborts the query in flight.
+    job_cancel: Option<CancelToken>,
     ty: Entity,
-    next: RefCell<Option<EntityValue>>,
+    /// Rows that were fetched by `op_chisel_query_fetch` and not yet taken by
+    /// `op_chisel_query_take_rows`.
+    rows: RefCell<Vec<EntityValue>>,
 }
 
 impl deno_core::Resource for QueryStreamResource {
@@ -286,53 +611,68 @@ impl deno_core::Resource for QueryStreamResource {
     }
 }
 
-// A future that resolves when this stream next element is available.
-struct QueryNextFuture {
+// A future that resolves when `max_rows` rows of the stream were fetched (to true), or when the
+// stream ended (to false).
+struct QueryFetchFuture {
     resource: Weak<QueryStreamResource>,
+    max_rows: usize,
 }
 
-impl Future for QueryNextFuture {
-    type Output = Result<()>;
+impl Future for QueryFetchFuture {
+    type Output = Result<bool>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.resource.upgrade() {
             Some(rc) => {
                 let mut stream = rc.stream.borrow_mut();
                 let stream: &mut QueryResults = &mut stream;
-                match stream.as_mut().poll_next(cx) {
-                    Poll::Ready(Some(Ok(next))) => {
-                        *rc.next.borrow_mut() = Some(EntityValue::Map(next));
-                        Poll::Ready(Ok(()))
+                let mut rows = rc.rows.borrow_mut();
+                while rows.len() < self.max_rows {
+                    match stream.as_mut().poll_next(cx) {
+                        Poll::Ready(Some(Ok(next))) => rows.push(EntityValue::Map(next)),
+                        Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                        Poll::Ready(None) => return Poll::Ready(Ok(false)),
+                        Poll::Pending => return Poll::Pending,
                     }
-                    Poll::Ready(Some(Err(e))) => Poll::Ready(Err(e)),
-                    Poll::Ready(None) => Poll::Ready(Ok(())),
-                    Poll::Pending => Poll::Pending,
                 }
+                Poll::Ready(Ok(true))
             }
             None => Poll::Ready(Err(anyhow!("Closed resource"))),
         }
     }
 }
 
+/// Fetches the next (at most) `max_rows` rows of the query, which are then taken by
+/// `op_chisel_query_take_rows`. Fetching the rows in batches saves a roundtrip per row. Returns
+/// false if the query has no more rows.
 #[deno_core::op]
-pub async fn op_chisel_query_next(
+pub async fn op_chisel_query_fetch(
     state: Rc<RefCell<OpState>>,
     query_stream_rid: deno_core::ResourceId,
-    _job_ctx_rid: deno_core::ResourceId,
-) -> Result<()> {
-    let (resource, cancel) = {
+    max_rows: usize,
+) -> Result<bool> {
+    ensure!(max_rows > 0, "the number of fetched rows must be positive");
+    let (resource, cancel, job_cancel) = {
         let rc: Rc<QueryStreamResource> = state.borrow().resource_table.get(query_stream_rid)?;
         let cancel = deno_core::RcRef::map(&rc, |r| &r.cancel);
-        (Rc::downgrade(&rc), cancel)
+        (Rc::downgrade(&rc), cancel, rc.job_cancel.clone())
     };
 
-    let fut = QueryNextFuture { resource };
+    let fut = QueryFetchFuture { resource, max_rows };
     let fut = fut.or_cancel(cancel);
-    fut.await?
+    match job_cancel {
+        Some(job_cancel) => tokio::select! {
+            res = fut => res?,
+            _ = job_cancel.cancelled() => bail!("Request was cancelled"),
+        },
+        None => fut.await?,
+    }
 }
 
+/// Takes the rows that were fetched by `op_chisel_query_fetch` as an array. Rows that the
+/// policies filter out are skipped.
 #[deno_core::op(v8)]
-pub fn op_chisel_query_get_value<'a>(
+pub fn op_chisel_query_take_rows<'a>(
     scope: &mut v8::HandleScope<'a>,
     state: Rc<RefCell<OpState>>,
     query_stream_rid: deno_core::ResourceId,
@@ -340,28 +680,33 @@ pub fn op_chisel_query_get_value<'a>(
 ) -> Result<serde_v8::Value<'a>> {
     let query_stream: Rc<QueryStreamResource> =
         state.borrow().resource_table.get(query_stream_rid)?;
-    let ty = query_stream.ty.object_type().clone();
-    let v8_value = match query_stream.next.borrow_mut().take() {
-        Some(v) => {
-            if feat_typescript_policies() {
-                let ctx = state
-                    .borrow()
-                    .resource_table
-                    .get::<JobContext>(ctx)?
-                    .data_context()?
-                    .policy_context
-                    .clone();
-                let validator = PolicyProcessor { ty, ctx };
-                validator
-                    .process_read(v.try_into_map()?)?
-                    .map(|mut v| v.to_v8(scope))
-                    .transpose()?
-                    .unwrap_or_else(|| v8::null(scope).into())
-            } else {
-                v.to_v8(scope)?
-            }
-        }
-        None => v8::null(scope).into(),
+    let rows = query_stream.rows.take();
+    let policy_processor = if feat_typescript_policies() {
+        let ctx = state
+            .borrow()
+            .resource_table
+            .get::<JobContext>(ctx)?
+            .data_context()?
+            .policy_context
+            .clone();
+        let ty = query_stream.ty.object_type().clone();
+        Some(PolicyProcessor { ty, ctx })
+    } else {
+        None
     };
-    Ok(serde_v8::Value::from(v8_value))
+
+    let mut values = Vec::with_capacity(rows.len());
+    for row in rows.into_iter() {
+        let value = match policy_processor {
+            Some(ref validator) => match validator.process_read(row.try_into_map()?)? {
+                Some(mut row) => row.to_v8(scope)?,
+                // rows that the policies filtered out are skipped
+                None => continue,
+            },
+            None => row.to_v8(scope)?,
+        };
+        values.push(value);
+    }
+    let array = v8::Array::new_with_elements(scope, &values);
+    Ok(serde_v8::Value::from(v8::Local::<v8::Value>::from(array)))
 }
@@ -0,0 +1,44 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Ops for the route examples of `chisel contract verify` (see `contract.rs`).
+
+use crate::contract::{self, RouteExample, SampledRequest};
+use crate::worker::WorkerState;
+use anyhow::Result;
+use deno_core::OpState;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Reports the examples that are registered with `RouteMap.example()`.
+#[deno_core::op]
+pub fn op_chisel_set_examples(state: &mut OpState, examples: Vec<RouteExample>) {
+    *state.borrow::<WorkerState>().version.examples.write() = examples;
+}
+
+/// Decides whether the current request should be recorded as an example (see
+/// `--record-examples-rate`).
+#[deno_core::op]
+pub fn op_chisel_sample_example(state: &mut OpState) -> bool {
+    contract::should_record(&state.borrow::<WorkerState>().server)
+}
+
+/// Redacts a sampled request and stores it as the recorded example of its route.
+#[deno_core::op]
+pub async fn op_chisel_record_example(
+    state: Rc<RefCell<OpState>>,
+    request: SampledRequest,
+) -> Result<()> {
+    let (server, version_id) = {
+        let state = state.borrow();
+        let worker_state = state.borrow::<WorkerState>();
+        (
+            worker_state.server.clone(),
+            worker_state.version.version_id.clone(),
+        )
+    };
+    let example = contract::recorded_example(request, &server.opt.redact_example_fields);
+    server
+        .meta_service
+        .persist_route_example(&version_id, &example)
+        .await
+}
@@ -4,12 +4,20 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use anyhow::{bail, Context, Result};
+use deno_core::serde_v8;
+use futures::TryStreamExt;
 use guard::guard;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 
+use crate::authentication::Authentication;
+use crate::authorization::POLICY_TRACE_HEADER;
 use crate::http::{HttpRequest, HttpRequestResponse, HttpResponse};
 use crate::kafka::KafkaEvent;
-use crate::ops::job_context::{JobContext, JobInfo};
+use crate::ops::job_context::{JobContext, JobInfo, KafkaPosition};
+use crate::policy::trace::PolicyTrace;
+use crate::seed::SeedJob;
+use crate::socket::{SocketEvent, SocketEventKind};
 use crate::version::VersionJob;
 use crate::worker::WorkerState;
 
@@ -30,6 +38,19 @@ enum AcceptedJob {
     },
     #[serde(rename_all = "camelCase")]
     Outbox { ctx_rid: deno_core::ResourceId },
+    #[serde(rename_all = "camelCase")]
+    Socket {
+        socket_id: u64,
+        routing_path: String,
+        user_id: Option<String>,
+        event: SocketEventKind,
+        ctx_rid: deno_core::ResourceId,
+    },
+    #[serde(rename_all = "camelCase")]
+    Seed {
+        name: String,
+        ctx_rid: deno_core::ResourceId,
+    },
 }
 
 #[deno_core::op]
@@ -40,25 +61,47 @@ async fn op_chisel_accept_job(
     guard! {let Some(mut job_rx) = state.borrow_mut().borrow_mut::<WorkerState>().job_rx.take() else {
         bail!("op_chisel_accept_job cannot be called while another call is pending")
     }};
+    // the previous job (if any) is finished, so the worker is idle until it receives another job
+    {
+        let state = state.borrow();
+        let worker_state = state.borrow::<WorkerState>();
+        worker_state.watchdog.job_finished();
+        if let Some(idle_tx) = worker_state.idle_tx.as_ref() {
+            let _: Result<_, _> = idle_tx.send(worker_state.worker_idx);
+        }
+    }
     // ... wait for the job ...
     let received_job = job_rx.recv().await;
     // ... and move the `job_rx` back
     let mut state = state.borrow_mut();
-    state.borrow_mut::<WorkerState>().job_rx = Some(job_rx);
+    let worker_state = state.borrow_mut::<WorkerState>();
+    worker_state.job_rx = Some(job_rx);
+    // the policies might have been updated while we were waiting
+    worker_state.refresh_policy_engine()?;
 
+    // only the jobs of HTTP requests can be cancelled
+    let mut watched_cancel = None;
     let accepted_job = match received_job {
         Some(VersionJob::Http(request_response)) => {
             let HttpRequestResponse {
-                request,
+                mut request,
                 response_tx,
                 authentication,
+                sandbox,
+                admin,
+                cancel,
             } = request_response;
+            watched_cancel = Some(cancel.clone());
 
             let ctx_rid = {
                 let path = request.routing_path.clone();
                 let headers = request.headers.iter().cloned().collect();
                 let method = request.method.clone();
                 let response_tx = RefCell::new(Some(response_tx));
+                let body_stream = RefCell::new(request.body_stream.take());
+                let request_ctx = request.ctx.clone();
+                let otel_cx = request.otel_cx.clone();
+                let policy_trace = request.policy_trace.then(PolicyTrace::default);
 
                 let job_info = Rc::new(JobInfo::HttpRequest {
                     method,
@@ -66,6 +109,14 @@ async fn op_chisel_accept_job(
                     headers,
                     response_tx,
                     authentication,
+                    hook_authentication: Default::default(),
+                    sandbox,
+                    admin,
+                    policy_trace,
+                    body_stream,
+                    request_ctx,
+                    otel_cx,
+                    cancel,
                 });
 
                 let ctx = JobContext {
@@ -78,10 +129,19 @@ async fn op_chisel_accept_job(
 
             AcceptedJob::Http { request, ctx_rid }
         }
-        Some(VersionJob::Kafka(event)) => {
+        Some(VersionJob::Kafka(mut event)) => {
             let ctx_rid = {
+                let position = KafkaPosition {
+                    topic: event.topic.clone(),
+                    partition: event.partition,
+                    offset: event.offset,
+                };
+                let job_info = JobInfo::KafkaEvent {
+                    position: Some(position),
+                    _done_tx: event.done_tx.take(),
+                };
                 let ctx = JobContext {
-                    job_info: Rc::new(JobInfo::KafkaEvent),
+                    job_info: Rc::new(job_info),
                     current_data_ctx: None.into(),
                 };
                 state.resource_table.add(ctx)
@@ -90,16 +150,70 @@ async fn op_chisel_accept_job(
         }
         Some(VersionJob::Outbox) => {
             let ctx_rid = {
+                let job_info = JobInfo::KafkaEvent {
+                    position: None,
+                    _done_tx: None,
+                };
                 let ctx = JobContext {
-                    job_info: Rc::new(JobInfo::KafkaEvent),
+                    job_info: Rc::new(job_info),
                     current_data_ctx: None.into(),
                 };
                 state.resource_table.add(ctx)
             };
             AcceptedJob::Outbox { ctx_rid }
         }
+        Some(VersionJob::Socket(event)) => {
+            let SocketEvent {
+                socket_id,
+                routing_path,
+                headers,
+                authentication,
+                kind,
+                accept_tx,
+            } = event;
+            let user_id = authentication.user_id().map(ToString::to_string);
+
+            let ctx_rid = {
+                let job_info = JobInfo::SocketEvent {
+                    path: routing_path.clone(),
+                    headers: headers.into_iter().collect(),
+                    authentication,
+                    accept_tx: RefCell::new(accept_tx),
+                };
+                let ctx = JobContext {
+                    job_info: Rc::new(job_info),
+                    current_data_ctx: None.into(),
+                };
+                state.resource_table.add(ctx)
+            };
+            AcceptedJob::Socket {
+                socket_id,
+                routing_path,
+                user_id,
+                event: kind,
+                ctx_rid,
+            }
+        }
+        Some(VersionJob::Seed(SeedJob { name, done_tx })) => {
+            let ctx_rid = {
+                let job_info = JobInfo::Seed {
+                    name: name.clone(),
+                    done_tx: RefCell::new(Some(done_tx)),
+                };
+                let ctx = JobContext {
+                    job_info: Rc::new(job_info),
+                    current_data_ctx: None.into(),
+                };
+                state.resource_table.add(ctx)
+            };
+            AcceptedJob::Seed { name, ctx_rid }
+        }
         None => return Ok(None),
     };
+    state
+        .borrow_mut::<WorkerState>()
+        .watchdog
+        .job_started(watched_cancel);
 
     Ok(Some(accepted_job))
 }
@@ -108,17 +222,24 @@ async fn op_chisel_accept_job(
 fn op_chisel_http_respond(
     state: Rc<RefCell<deno_core::OpState>>,
     ctx: deno_core::ResourceId,
-    response: HttpResponse,
+    mut response: HttpResponse,
 ) -> Result<()> {
     let ctx = state.borrow_mut().resource_table.get::<JobContext>(ctx)?;
     match *ctx.job_info {
         JobInfo::HttpRequest {
-            ref response_tx, ..
+            ref response_tx,
+            ref policy_trace,
+            ..
         } => {
             let tx = response_tx
                 .borrow_mut()
                 .take()
                 .context("Response already send for that request")?;
+            if let Some(policy_trace) = policy_trace {
+                response
+                    .headers
+                    .push((POLICY_TRACE_HEADER.into(), policy_trace.to_json()));
+            }
             let _ = tx.send(response);
         }
         _ => bail!("invalid request type"),
@@ -126,3 +247,108 @@ fn op_chisel_http_respond(
 
     Ok(())
 }
+
+/// Waits until the job is finished and returns true if it was cancelled (because the client
+/// disconnected or the deadline of the request expired). Returns false immediately for jobs that
+/// cannot be cancelled.
+#[deno_core::op]
+async fn op_chisel_job_cancelled(
+    state: Rc<RefCell<deno_core::OpState>>,
+    ctx: deno_core::ResourceId,
+) -> Result<bool> {
+    let cancel = {
+        let ctx = state.borrow().resource_table.get::<JobContext>(ctx)?;
+        ctx.job_info.cancel_token().cloned()
+    };
+    Ok(match cancel {
+        Some(cancel) => cancel.finished().await,
+        None => false,
+    })
+}
+
+/// Reads the next chunk of the streamed body of an `upload()` request, `None` at the end of the
+/// body (or if the body was not streamed).
+#[deno_core::op]
+async fn op_chisel_read_body_chunk(
+    state: Rc<RefCell<deno_core::OpState>>,
+    ctx: deno_core::ResourceId,
+) -> Result<Option<serde_v8::ZeroCopyBuf>> {
+    let job_info = {
+        let ctx = state.borrow().resource_table.get::<JobContext>(ctx)?;
+        ctx.job_info.clone()
+    };
+    guard! {let Some(mut body_stream) = job_info.take_body_stream() else {
+        return Ok(None);
+    }};
+    let chunk = job_info
+        .cancellable(body_stream.try_next().map_err(anyhow::Error::from))
+        .await?;
+    if chunk.is_some() {
+        job_info.restore_body_stream(body_stream);
+    }
+    Ok(chunk.map(|chunk| chunk.to_vec().into()))
+}
+
+/// Reports the outcome of a seed script: `error` is the error message if the seed failed.
+#[deno_core::op]
+fn op_chisel_seed_done(
+    state: Rc<RefCell<deno_core::OpState>>,
+    ctx: deno_core::ResourceId,
+    error: Option<String>,
+) -> Result<()> {
+    let ctx = state.borrow_mut().resource_table.get::<JobContext>(ctx)?;
+    match *ctx.job_info {
+        JobInfo::Seed { ref done_tx, .. } => {
+            let tx = done_tx
+                .borrow_mut()
+                .take()
+                .context("Outcome already sent for that seed")?;
+            let _ = tx.send(match error {
+                Some(error) => Err(error),
+                None => Ok(()),
+            });
+        }
+        _ => bail!("invalid request type"),
+    }
+
+    Ok(())
+}
+
+/// User principal returned by the authentication hook of the version.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthPrincipal {
+    user_id: String,
+    #[serde(default)]
+    claims: JsonValue,
+}
+
+/// Returns the tenant of the HTTP request (see `JobInfo::request_tenant()`), which is resolved
+/// once its user is known, after the authentication hook. Fails if the tenant header of the request
+/// names another tenant than its user.
+#[deno_core::op]
+fn op_chisel_resolve_tenant(
+    state: Rc<RefCell<deno_core::OpState>>,
+    ctx: deno_core::ResourceId,
+) -> Result<Option<String>> {
+    let ctx = state.borrow().resource_table.get::<JobContext>(ctx)?;
+    ctx.job_info.request_tenant()?;
+    Ok(ctx.job_info.tenant_id().map(ToOwned::to_owned))
+}
+
+/// Records the user that the authentication hook resolved for the HTTP request, so that the
+/// policies see it instead of the user from the request headers. `None` means that the hook
+/// didn't recognize any user.
+#[deno_core::op]
+fn op_chisel_set_authentication(
+    state: Rc<RefCell<deno_core::OpState>>,
+    ctx: deno_core::ResourceId,
+    principal: Option<AuthPrincipal>,
+) -> Result<()> {
+    let ctx = state.borrow_mut().resource_table.get::<JobContext>(ctx)?;
+    let authentication = match principal {
+        Some(AuthPrincipal { user_id, claims }) => Authentication::Hook { user_id, claims },
+        None => Authentication::None,
+    };
+    ctx.job_info.set_hook_authentication(authentication)
+}
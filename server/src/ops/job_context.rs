@@ -2,13 +2,16 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+use once_cell::unsync::OnceCell;
 use serde_json::Value as JsonValue;
 use tokio::sync::oneshot;
 
 use crate::authentication::Authentication;
+use crate::cancel::CancelToken;
 use crate::datastore::DataContext;
-use crate::http::HttpResponse;
+use crate::http::{HttpResponse, RequestContext};
 use crate::policy::engine::ChiselRequestContext;
+use crate::policy::trace::PolicyTrace;
 
 #[allow(clippy::large_enum_variant)]
 pub enum JobInfo {
@@ -18,79 +21,342 @@ pub enum JobInfo {
         headers: HashMap<String, String>,
         response_tx: RefCell<Option<oneshot::Sender<HttpResponse>>>,
         authentication: Authentication,
+        /// Authentication resolved by the authentication hook of the version, which replaces
+        /// `authentication` once it is set (see `op_chisel_set_authentication`).
+        hook_authentication: OnceCell<Authentication>,
+        /// If true, the transaction of the request is always rolled back.
+        sandbox: bool,
+        /// If true, the request carries the admin secret, see `OwnerScope::All`.
+        admin: bool,
+        /// Set if the decisions of the policies are returned with the response, see
+        /// `authorize_policy_trace()`.
+        policy_trace: Option<PolicyTrace>,
+        /// Streamed body of requests to `ingest()` and `upload()` routes, taken by
+        /// `op_chisel_ingest` and `op_chisel_read_body_chunk`.
+        body_stream: RefCell<Option<hyper::Body>>,
+        /// Context of the request, which the handlers receive as `ctx`.
+        request_ctx: RequestContext,
+        /// OpenTelemetry context of the span of the job, see `telemetry`.
+        otel_cx: opentelemetry::Context,
+        /// Cancelled when the client disconnects or the deadline of the request expires.
+        cancel: CancelToken,
+    },
+    KafkaEvent {
+        /// Position of the event that is being handled, `None` if the job is not handling a
+        /// particular event (such as when polling the outbox).
+        position: Option<KafkaPosition>,
+        /// Dropped when the job is finished, to signal that the event has been handled.
+        _done_tx: Option<oneshot::Sender<()>>,
+    },
+    SocketEvent {
+        path: String,
+        /// Headers of the HTTP request that opened the socket.
+        headers: HashMap<String, String>,
+        authentication: Authentication,
+        /// Set only when the socket is being opened, see `op_chisel_socket_accept`.
+        accept_tx: RefCell<Option<oneshot::Sender<bool>>>,
+    },
+    Seed {
+        name: String,
+        /// Taken by `op_chisel_seed_done` to report the outcome of the seed.
+        done_tx: RefCell<Option<oneshot::Sender<Result<(), String>>>>,
     },
-    KafkaEvent,
+}
+
+/// Rows of the entities marked with `@ownedBy` that a job can read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnerScope<'a> {
+    /// All rows: requests with the admin secret, and jobs that are not performed on behalf of a
+    /// user (Kafka events and seeds).
+    All,
+    /// Only the rows owned by this user. Anonymous users (`None`) can read no rows and write none.
+    User(Option<&'a str>),
+}
+
+/// Rows that a job can read and write when the data is isolated per tenant (see
+/// `datastore::tenancy`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TenantScope<'a> {
+    /// All rows: the data is not isolated, or the job is not performed on behalf of a tenant
+    /// (requests with the admin secret and no tenant header, Kafka events and seeds).
+    All,
+    /// Only the rows of this tenant. Requests without the tenant header (`None`) can read no rows
+    /// and write none.
+    Tenant(Option<&'a str>),
+}
+
+/// Position of a Kafka event, used to commit the offset of the event.
+#[derive(Debug, Clone)]
+pub struct KafkaPosition {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
 }
 
 impl ChiselRequestContext for JobInfo {
     fn method(&self) -> &str {
         match self {
             JobInfo::HttpRequest { ref method, .. } => method,
-            JobInfo::KafkaEvent => todo!(),
+            JobInfo::SocketEvent { .. } => "GET",
+            JobInfo::Seed { .. } | JobInfo::KafkaEvent { .. } => "POST",
         }
     }
 
     fn path(&self) -> &str {
         match self {
-            JobInfo::HttpRequest { ref path, .. } => path,
-            JobInfo::KafkaEvent => todo!(),
+            JobInfo::HttpRequest { ref path, .. } | JobInfo::SocketEvent { ref path, .. } => path,
+            JobInfo::Seed { .. } => "/",
+            // the policies see the topic of the event as the path
+            JobInfo::KafkaEvent { ref position, .. } => position
+                .as_ref()
+                .map_or("/", |position| position.topic.as_str()),
         }
     }
 
     fn headers(&self) -> Box<dyn Iterator<Item = (&str, &str)> + '_> {
         match self {
-            JobInfo::HttpRequest { ref headers, .. } => {
+            JobInfo::HttpRequest { ref headers, .. } | JobInfo::SocketEvent { ref headers, .. } => {
                 Box::new(headers.iter().map(|(k, v)| (k.as_str(), v.as_str())))
             }
-            JobInfo::KafkaEvent => todo!(),
+            JobInfo::Seed { .. } | JobInfo::KafkaEvent { .. } => Box::new(std::iter::empty()),
         }
     }
 
     fn user_id(&self) -> Option<&str> {
-        match self {
-            JobInfo::HttpRequest {
-                authentication: Authentication::UserId(ref uid),
-                ..
-            } => Some(uid),
-            _ => None,
-        }
+        JobInfo::user_id(self)
+    }
+
+    fn tenant_id(&self) -> Option<&str> {
+        JobInfo::tenant_id(self)
     }
 
     fn token(&self) -> Option<&JsonValue> {
         match self {
-            JobInfo::HttpRequest {
-                ref authentication, ..
-            } => match authentication {
-                Authentication::Jwt(ref val) => Some(val),
-                _ => None,
-            },
-            JobInfo::KafkaEvent => todo!(),
+            JobInfo::HttpRequest { .. } | JobInfo::SocketEvent { .. } => {
+                self.authentication()?.claims()
+            }
+            JobInfo::Seed { .. } | JobInfo::KafkaEvent { .. } => None,
         }
     }
+
+    fn request_context(&self) -> Option<&RequestContext> {
+        JobInfo::request_context(self)
+    }
+
+    fn otel_context(&self) -> opentelemetry::Context {
+        JobInfo::otel_context(self)
+    }
+
+    fn policy_trace(&self) -> Option<&PolicyTrace> {
+        JobInfo::policy_trace(self)
+    }
 }
 
 impl JobInfo {
     pub fn path(&self) -> Option<&str> {
         match self {
-            JobInfo::HttpRequest { ref path, .. } => Some(path),
-            JobInfo::KafkaEvent => None,
+            JobInfo::HttpRequest { ref path, .. } | JobInfo::SocketEvent { ref path, .. } => {
+                Some(path)
+            }
+            JobInfo::KafkaEvent { .. } | JobInfo::Seed { .. } => None,
         }
     }
 
     pub fn request_headers(&self) -> Option<&HashMap<String, String>> {
         match self {
-            JobInfo::HttpRequest { ref headers, .. } => Some(headers),
-            JobInfo::KafkaEvent => None,
+            JobInfo::HttpRequest { ref headers, .. } | JobInfo::SocketEvent { ref headers, .. } => {
+                Some(headers)
+            }
+            JobInfo::KafkaEvent { .. } | JobInfo::Seed { .. } => None,
         }
     }
 
     pub fn user_id(&self) -> Option<&str> {
+        self.authentication()?.user_id()
+    }
+
+    /// Returns the authentication of the user that performs the request, preferring the one
+    /// resolved by the authentication hook.
+    pub fn authentication(&self) -> Option<&Authentication> {
+        match self {
+            JobInfo::HttpRequest {
+                ref authentication,
+                ref hook_authentication,
+                ..
+            } => Some(hook_authentication.get().unwrap_or(authentication)),
+            JobInfo::SocketEvent {
+                ref authentication, ..
+            } => Some(authentication),
+            JobInfo::KafkaEvent { .. } | JobInfo::Seed { .. } => None,
+        }
+    }
+
+    /// Sets the authentication resolved by the authentication hook. It can be set only once per
+    /// request.
+    pub fn set_hook_authentication(&self, hook: Authentication) -> anyhow::Result<()> {
+        match self {
+            JobInfo::HttpRequest {
+                ref hook_authentication,
+                ..
+            } => match hook_authentication.set(hook) {
+                Ok(()) => Ok(()),
+                Err(_) => anyhow::bail!("The user of this request has already been resolved"),
+            },
+            JobInfo::KafkaEvent { .. } | JobInfo::SocketEvent { .. } | JobInfo::Seed { .. } => {
+                anyhow::bail!("The authentication hook can only resolve users of HTTP requests")
+            }
+        }
+    }
+
+    /// Returns the trace id of the HTTP request that is handled by this job, see
+    /// `http::TRACE_ID_HEADER`.
+    pub fn trace_id(&self) -> Option<&str> {
+        Some(&self.request_context()?.request_id)
+    }
+
+    /// Returns the context of the HTTP request that is handled by this job.
+    pub fn request_context(&self) -> Option<&RequestContext> {
         match self {
             JobInfo::HttpRequest {
-                authentication: Authentication::UserId(ref uid),
+                ref request_ctx, ..
+            } => Some(request_ctx),
+            JobInfo::KafkaEvent { .. } | JobInfo::SocketEvent { .. } | JobInfo::Seed { .. } => None,
+        }
+    }
+
+    /// Returns the OpenTelemetry context that the spans of this job are children of.
+    pub fn otel_context(&self) -> opentelemetry::Context {
+        match self {
+            JobInfo::HttpRequest { ref otel_cx, .. } => otel_cx.clone(),
+            JobInfo::KafkaEvent { .. } | JobInfo::SocketEvent { .. } | JobInfo::Seed { .. } => {
+                opentelemetry::Context::new()
+            }
+        }
+    }
+
+    /// Returns the rows of `@ownedBy` entities that this job can access.
+    pub fn owner_scope(&self) -> OwnerScope<'_> {
+        match self {
+            JobInfo::HttpRequest { admin: true, .. } => OwnerScope::All,
+            JobInfo::HttpRequest { .. } | JobInfo::SocketEvent { .. } => {
+                OwnerScope::User(self.user_id())
+            }
+            JobInfo::KafkaEvent { .. } | JobInfo::Seed { .. } => OwnerScope::All,
+        }
+    }
+
+    /// Returns the tenant of the request, from the claims of its user and its tenant header (see
+    /// `TenancyConfig::tenant()`). Fails if the header names another tenant than the user.
+    pub fn request_tenant(&self) -> anyhow::Result<Option<&str>> {
+        let tenancy = match crate::tenancy() {
+            Some(tenancy) => tenancy,
+            None => return Ok(None),
+        };
+        let header = tenancy
+            .header
+            .as_deref()
+            .and_then(|header| self.request_headers()?.get(header))
+            .map(String::as_str);
+        let claims = self.authentication().and_then(Authentication::claims);
+        tenancy.tenant(claims, header)
+    }
+
+    /// Returns the tenant whose data this job can access.
+    pub fn tenant_scope(&self) -> TenantScope<'_> {
+        if crate::tenancy().is_none() {
+            return TenantScope::All;
+        }
+        // HTTP requests whose tenant header disagrees with their user are rejected before they
+        // run (see `op_chisel_resolve_tenant`), other jobs with such a header access no data
+        let tenant = self.request_tenant().unwrap_or(None);
+        match self {
+            JobInfo::HttpRequest { admin: true, .. } if tenant.is_none() => TenantScope::All,
+            JobInfo::HttpRequest { .. } | JobInfo::SocketEvent { .. } => {
+                TenantScope::Tenant(tenant)
+            }
+            JobInfo::KafkaEvent { .. } | JobInfo::Seed { .. } => TenantScope::All,
+        }
+    }
+
+    /// Returns the tenant of the request, see `tenant_scope()`.
+    pub fn tenant_id(&self) -> Option<&str> {
+        match self.tenant_scope() {
+            TenantScope::Tenant(tenant) => tenant,
+            TenantScope::All => None,
+        }
+    }
+
+    /// Returns the trace of the policy decisions, if the request asked for it.
+    pub fn policy_trace(&self) -> Option<&PolicyTrace> {
+        match self {
+            JobInfo::HttpRequest { policy_trace, .. } => policy_trace.as_ref(),
+            JobInfo::KafkaEvent { .. } | JobInfo::SocketEvent { .. } | JobInfo::Seed { .. } => None,
+        }
+    }
+
+    /// Returns true if the changes made by this job must never be committed.
+    pub fn is_sandbox(&self) -> bool {
+        match self {
+            JobInfo::HttpRequest { sandbox, .. } => *sandbox,
+            JobInfo::KafkaEvent { .. } | JobInfo::SocketEvent { .. } | JobInfo::Seed { .. } => {
+                false
+            }
+        }
+    }
+
+    /// Returns the token that is cancelled when the job is no longer needed, if the job can be
+    /// cancelled.
+    pub fn cancel_token(&self) -> Option<&CancelToken> {
+        match self {
+            JobInfo::HttpRequest { cancel, .. } => Some(cancel),
+            JobInfo::KafkaEvent { .. } | JobInfo::SocketEvent { .. } | JobInfo::Seed { .. } => None,
+        }
+    }
+
+    /// Runs `fut`, but fails as soon as the job is cancelled. Dropping the future aborts the
+    /// in-flight query (if any) that it executes.
+    pub async fn cancellable<T>(
+        &self,
+        fut: impl std::future::Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        match self.cancel_token() {
+            Some(cancel) => tokio::select! {
+                res = fut => res,
+                _ = cancel.cancelled() => anyhow::bail!("Request was cancelled"),
+            },
+            None => fut.await,
+        }
+    }
+
+    /// Takes the streamed body of the HTTP request, if any.
+    pub fn take_body_stream(&self) -> Option<hyper::Body> {
+        match self {
+            JobInfo::HttpRequest { body_stream, .. } => body_stream.borrow_mut().take(),
+            JobInfo::KafkaEvent { .. } | JobInfo::SocketEvent { .. } | JobInfo::Seed { .. } => None,
+        }
+    }
+
+    /// Puts back the streamed body that was taken by `take_body_stream()` and not read to the end.
+    pub fn restore_body_stream(&self, stream: hyper::Body) {
+        if let JobInfo::HttpRequest { body_stream, .. } = self {
+            *body_stream.borrow_mut() = Some(stream);
+        }
+    }
+
+    /// Short human-readable description of the job, used in diagnostics.
+    pub fn description(&self) -> String {
+        match self {
+            JobInfo::HttpRequest { method, path, .. } => format!("{} {}", method, path),
+            JobInfo::KafkaEvent {
+                position: Some(position),
                 ..
-            } => Some(uid),
-            _ => None,
+            } => format!(
+                "kafka {}:{}@{}",
+                position.topic, position.partition, position.offset
+            ),
+            JobInfo::KafkaEvent { position: None, .. } => "kafka outbox".into(),
+            JobInfo::SocketEvent { path, .. } => format!("socket {}", path),
+            JobInfo::Seed { name, .. } => format!("seed {}", name),
         }
     }
 }
@@ -1,30 +1,90 @@
 use super::WorkerState;
 use crate::datastore::expr::{BinaryExpr, Expr, PropertyAccess, Value};
 use crate::datastore::query::{Mutation, QueryOp, SortBy, SortKey};
-use crate::datastore::QueryEngine;
 use crate::datastore::{
     query::QueryPlan,
     value::{EntityMap, EntityValue},
 };
-use crate::ops::job_context::JobContext;
+use crate::datastore::{MetaService, QueryEngine};
+use crate::kafka::{EventFilter, TopicOptions};
+use crate::ops::job_context::{JobContext, JobInfo};
 use crate::outbox::OUTBOX_NAME;
+use crate::policies::anonymization_key;
 use crate::policy::PolicyContext;
 use crate::types::Type;
 use anyhow::Result;
-use deno_core::OpState;
+use deno_core::{serde_v8, OpState};
 use futures::StreamExt;
 use std::cell::RefCell;
 use std::rc::Rc;
 
 #[deno_core::op]
-pub fn op_chisel_subscribe_topic(op_state: Rc<RefCell<OpState>>, topic: String) -> Result<()> {
-    let server = op_state.borrow().borrow::<WorkerState>().server.clone();
+pub fn op_chisel_subscribe_topic(
+    op_state: Rc<RefCell<OpState>>,
+    topic: String,
+    options: Option<TopicOptions>,
+    filters: Vec<Option<EventFilter>>,
+) -> Result<()> {
+    let (server, version) = {
+        let op_state = op_state.borrow();
+        let worker_state = op_state.borrow::<WorkerState>();
+        (worker_state.server.clone(), worker_state.version.clone())
+    };
+    version.event_filters.write().insert(topic.clone(), filters);
     if let Some(ref service) = server.kafka_service {
-        service.subscribe_topic(server.clone(), topic);
+        service.subscribe_topic(server.clone(), topic, options.unwrap_or_default())?;
     }
     Ok(())
 }
 
+#[deno_core::op]
+pub async fn op_chisel_kafka_commit(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+) -> Result<()> {
+    let server = state.borrow().borrow::<WorkerState>().server.clone();
+    let kafka_service = match &server.kafka_service {
+        Some(kafka_service) => kafka_service.clone(),
+        None => anyhow::bail!("Kafka is not configured"),
+    };
+    let (data_ctx, position) = {
+        let state = state.borrow();
+        let ctx = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+        let position = match *ctx.job_info {
+            JobInfo::KafkaEvent {
+                position: Some(ref position),
+                ..
+            } => position.clone(),
+            _ => anyhow::bail!("Only Kafka events can be committed"),
+        };
+        (ctx.data_context()?, position)
+    };
+    let mut txn = data_ctx.txn.lock().await;
+    MetaService::persist_kafka_offset(
+        &mut *txn,
+        kafka_service.consumer_group(),
+        &position.topic,
+        position.partition,
+        position.offset,
+    )
+    .await
+}
+
+#[deno_core::op]
+pub async fn op_chisel_kafka_encode(
+    op_state: Rc<RefCell<OpState>>,
+    topic: String,
+    value: serde_json::Value,
+) -> Result<serde_v8::ZeroCopyBuf> {
+    let server = op_state.borrow().borrow::<WorkerState>().server.clone();
+    let kafka_service = match &server.kafka_service {
+        Some(kafka_service) => kafka_service.clone(),
+        None => anyhow::bail!("Kafka is not configured"),
+    };
+    let payload = kafka_service.encode_value(&topic, value).await?;
+    Ok(payload.into())
+}
+
 #[deno_core::op]
 pub async fn op_chisel_publish(op_state: Rc<RefCell<OpState>>) -> Result<()> {
     let server = op_state.borrow().borrow::<WorkerState>().server.clone();
@@ -54,15 +114,21 @@ pub async fn op_chisel_poll_outbox(
         let job_info = ctx.job_info.clone();
         let worker_state = state.borrow::<WorkerState>();
         let type_system = worker_state.version.type_system.clone();
-        let policy_system = worker_state.version.policy_system.clone();
+        let policy_system = worker_state.version.policy_system();
         let policy_engine = worker_state.policy_engine.clone();
         let policy_context = PolicyContext::new(policy_engine, ctx.job_info.clone());
         let outbox_type = match type_system.lookup_builtin_type(OUTBOX_NAME)? {
             Type::Entity(entity) => entity,
             _ => anyhow::bail!("internal error"),
         };
-        let data_ctx_future =
-            query_engine.create_data_context(type_system, policy_system, policy_context, job_info);
+        let anonymization_key = anonymization_key(&worker_state.server.secrets.read());
+        let data_ctx_future = query_engine.create_data_context(
+            type_system,
+            policy_system,
+            policy_context,
+            anonymization_key,
+            job_info,
+        );
         (data_ctx_future, outbox_type)
     };
     let data_ctx = data_ctx_future.await?;
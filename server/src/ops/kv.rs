@@ -0,0 +1,149 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Ops of the key-value store (`Chisel.kv`), see `datastore::kv`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use deno_core::OpState;
+use serde_json::Value as JsonValue;
+
+use super::WorkerState;
+use crate::datastore::engine::{now_ms, TransactionStatic};
+use crate::datastore::kv::{self, KvEntry};
+use crate::datastore::tenancy;
+use crate::ops::job_context::{JobContext, JobInfo, OwnerScope};
+use crate::policies::PolicySystem;
+
+/// Everything that an op of the key-value store needs from the current job.
+struct KvAccess {
+    version_id: String,
+    /// Prefix of the keys of the tenant of the job (see `tenancy::key_prefix()`).
+    key_prefix: String,
+    txn: TransactionStatic,
+    now_ms: f64,
+    job_info: Rc<JobInfo>,
+    policy_system: Arc<PolicySystem>,
+}
+
+impl KvAccess {
+    fn new(state: &OpState, job_ctx_rid: deno_core::ResourceId) -> Result<Self> {
+        let worker_state = state.borrow::<WorkerState>();
+        let context = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+        let data_ctx = context.data_context()?;
+        let key_prefix = tenancy::key_prefix(data_ctx.job_info.tenant_scope(), "Chisel.kv")?;
+        Ok(Self {
+            version_id: worker_state.version.version_id.clone(),
+            key_prefix: key_prefix.unwrap_or_default(),
+            txn: data_ctx.txn.clone(),
+            now_ms: worker_state
+                .server
+                .frozen_time_ms
+                .read()
+                .unwrap_or_else(now_ms),
+            job_info: data_ctx.job_info.clone(),
+            policy_system: data_ctx.policy_system.clone(),
+        })
+    }
+
+    fn is_allowed(&self, key: &str) -> bool {
+        // like @ownedBy, the policy does not apply to admins, Kafka events and seeds
+        match self.job_info.owner_scope() {
+            OwnerScope::All => true,
+            OwnerScope::User(user_id) => {
+                self.policy_system.kv_authorization.is_allowed(user_id, key)
+            }
+        }
+    }
+
+    /// Checks that the user may access `key` and returns the key that is stored.
+    fn stored_key(&self, key: &str) -> Result<String> {
+        if !self.is_allowed(key) {
+            bail!("Access to key {:?} of Chisel.kv is not allowed", key);
+        }
+        Ok(format!("{}{}", self.key_prefix, key))
+    }
+
+    /// Converts a stored entry back into the entry that the job sees.
+    fn unprefixed(&self, mut entry: KvEntry) -> KvEntry {
+        entry.key.replace_range(..self.key_prefix.len(), "");
+        entry
+    }
+}
+
+#[deno_core::op]
+pub async fn op_chisel_kv_get(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+    key: String,
+) -> Result<Option<KvEntry>> {
+    let access = KvAccess::new(&state.borrow(), job_ctx_rid)?;
+    let key = access.stored_key(&key)?;
+    let mut txn = access.txn.lock().await;
+    let entry = kv::get(&mut txn, &access.version_id, &key, access.now_ms).await?;
+    Ok(entry.map(|entry| access.unprefixed(entry)))
+}
+
+/// Sets the value of `key`, which expires after `ttl_ms` milliseconds (if given).
+#[deno_core::op]
+pub async fn op_chisel_kv_set(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+    key: String,
+    value: JsonValue,
+    ttl_ms: Option<f64>,
+) -> Result<()> {
+    let access = KvAccess::new(&state.borrow(), job_ctx_rid)?;
+    let key = access.stored_key(&key)?;
+    let expires_at = match ttl_ms {
+        Some(ttl_ms) if ttl_ms.is_nan() || ttl_ms <= 0. => {
+            bail!("The TTL of Chisel.kv must be positive")
+        }
+        Some(ttl_ms) => Some(access.now_ms + ttl_ms),
+        None => None,
+    };
+    let mut txn = access.txn.lock().await;
+    kv::set(
+        &mut txn,
+        &access.version_id,
+        &key,
+        &value,
+        expires_at,
+        access.now_ms,
+    )
+    .await
+}
+
+#[deno_core::op]
+pub async fn op_chisel_kv_delete(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+    key: String,
+) -> Result<bool> {
+    let access = KvAccess::new(&state.borrow(), job_ctx_rid)?;
+    let key = access.stored_key(&key)?;
+    let mut txn = access.txn.lock().await;
+    kv::delete(&mut txn, &access.version_id, &key).await
+}
+
+/// Lists the entries under `prefix`. The entries that the user may not access are skipped, so
+/// fewer than `limit` entries may be returned.
+#[deno_core::op]
+pub async fn op_chisel_kv_list(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+    prefix: String,
+    limit: Option<u64>,
+) -> Result<Vec<KvEntry>> {
+    let access = KvAccess::new(&state.borrow(), job_ctx_rid)?;
+    let prefix = format!("{}{}", access.key_prefix, prefix);
+    let mut txn = access.txn.lock().await;
+    let entries = kv::list(&mut txn, &access.version_id, &prefix, limit, access.now_ms).await?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| access.unprefixed(entry))
+        .filter(|entry| access.is_allowed(&entry.key))
+        .collect())
+}
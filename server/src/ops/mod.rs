@@ -1,15 +1,26 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-use crate::version::VersionInfo;
+use crate::datastore::db_metrics::DbMetricsReport;
+use crate::logging::log_event;
+use crate::ops::job_context::JobContext;
+use crate::version::{RouteInfo, VersionInfo};
 use crate::worker::WorkerState;
+use crate::worker_pool::WorkerPoolReport;
 use anyhow::{bail, Result};
 use deno_core::{serde_v8, v8};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
+mod blob;
 mod datastore;
 mod env;
+mod examples;
 mod job;
 pub mod job_context;
 mod kafka;
+mod kv;
+mod socket;
+mod testing;
 mod type_system;
 
 pub fn extension() -> deno_core::Extension {
@@ -21,27 +32,63 @@ pub fn extension() -> deno_core::Extension {
             op_chisel_get_version_info::decl(),
             op_chisel_get_worker_idx::decl(),
             op_chisel_is_debug::decl(),
+            op_chisel_get_metrics::decl(),
+            op_chisel_count_unhandled_rejection::decl(),
+            op_chisel_set_routes::decl(),
+            op_chisel_set_seeds::decl(),
+            op_chisel_log::decl(),
             op_format_file_name::decl(),
+            blob::op_chisel_blob_put::decl(),
+            blob::op_chisel_blob_get::decl(),
+            blob::op_chisel_blob_delete::decl(),
+            blob::op_chisel_blob_signed_url::decl(),
             datastore::op_chisel_begin_transaction::decl(),
             datastore::op_chisel_commit_transaction::decl(),
             datastore::op_chisel_rollback_transaction::decl(),
             datastore::op_chisel_store::decl(),
+            datastore::op_chisel_upsert::decl(),
+            datastore::op_chisel_update::decl(),
+            datastore::op_chisel_raw_query::decl(),
+            datastore::op_chisel_raw_table_name::decl(),
             datastore::op_chisel_delete::decl(),
             datastore::op_chisel_crud_delete::decl(),
             datastore::op_chisel_crud_query::decl(),
+            datastore::op_chisel_ingest::decl(),
             datastore::op_chisel_relational_query_create::decl(),
-            datastore::op_chisel_query_next::decl(),
+            datastore::op_chisel_query_fetch::decl(),
+            datastore::op_chisel_execute_batch::decl(),
             env::op_cwd::decl(),
             env::op_set_env::decl(),
             env::op_env::decl(),
             env::op_get_env::decl(),
             env::op_delete_env::decl(),
-            datastore::op_chisel_query_get_value::decl(),
+            examples::op_chisel_set_examples::decl(),
+            examples::op_chisel_sample_example::decl(),
+            examples::op_chisel_record_example::decl(),
+            datastore::op_chisel_query_take_rows::decl(),
             job::op_chisel_accept_job::decl(),
             job::op_chisel_http_respond::decl(),
+            job::op_chisel_job_cancelled::decl(),
+            job::op_chisel_read_body_chunk::decl(),
+            job::op_chisel_set_authentication::decl(),
+            job::op_chisel_resolve_tenant::decl(),
+            job::op_chisel_seed_done::decl(),
+            kafka::op_chisel_kafka_commit::decl(),
+            kafka::op_chisel_kafka_encode::decl(),
             kafka::op_chisel_poll_outbox::decl(),
             kafka::op_chisel_publish::decl(),
             kafka::op_chisel_subscribe_topic::decl(),
+            kv::op_chisel_kv_get::decl(),
+            kv::op_chisel_kv_set::decl(),
+            kv::op_chisel_kv_delete::decl(),
+            kv::op_chisel_kv_list::decl(),
+            socket::op_chisel_socket_accept::decl(),
+            socket::op_chisel_socket_close::decl(),
+            socket::op_chisel_socket_send::decl(),
+            testing::op_chisel_is_testing::decl(),
+            testing::op_chisel_testing_reset::decl(),
+            testing::op_chisel_testing_freeze_time::decl(),
+            testing::op_chisel_testing_now::decl(),
             type_system::op_chisel_get_type_system::decl(),
         ])
         .build()
@@ -57,6 +104,18 @@ fn op_chisel_ready(state: &mut deno_core::OpState) -> Result<()> {
     }
 }
 
+/// Reports the routes of the version; they are used to generate the OpenAPI document.
+#[deno_core::op]
+fn op_chisel_set_routes(state: &mut deno_core::OpState, routes: Vec<RouteInfo>) {
+    *state.borrow::<WorkerState>().version.routes.write() = routes;
+}
+
+/// Reports the names of the seed scripts of the version (see `seed::run_seeds()`).
+#[deno_core::op]
+fn op_chisel_set_seeds(state: &mut deno_core::OpState, seeds: Vec<String>) {
+    *state.borrow::<WorkerState>().version.seeds.write() = seeds;
+}
+
 #[deno_core::op(v8)]
 fn op_chisel_get_secret<'a>(
     scope: &mut v8::HandleScope<'a>,
@@ -95,7 +154,83 @@ fn op_chisel_get_worker_idx(state: &mut deno_core::OpState) -> usize {
 
 #[deno_core::op]
 fn op_chisel_is_debug(state: &mut deno_core::OpState) -> bool {
-    state.borrow::<WorkerState>().server.opt.debug
+    state
+        .borrow::<WorkerState>()
+        .server
+        .current_opt
+        .read()
+        .debug
+}
+
+/// Metrics reported on the `/__chiselstrike/metrics` route, by section.
+#[derive(Serialize)]
+struct Metrics {
+    db: DbMetricsReport,
+    /// Worker pools of the versions, by version id.
+    workers: BTreeMap<String, WorkerPoolReport>,
+}
+
+#[deno_core::op]
+fn op_chisel_get_metrics(state: &mut deno_core::OpState) -> Metrics {
+    let server = &state.borrow::<WorkerState>().server;
+    let workers = server
+        .trunk
+        .list_versions()
+        .into_iter()
+        .map(|version| {
+            let report = version.pool_metrics.report(&version.worker_pool);
+            (version.version_id.clone(), report)
+        })
+        .collect();
+    Metrics {
+        db: server.db.metrics_report(),
+        workers,
+    }
+}
+
+#[deno_core::op]
+fn op_chisel_count_unhandled_rejection(state: &mut deno_core::OpState) {
+    state
+        .borrow::<WorkerState>()
+        .version
+        .pool_metrics
+        .count_unhandled_rejection();
+}
+
+/// Logs a message from `console` in JavaScript. If the message is logged while handling a job
+/// (`ctx` is given), it is tagged with the trace id of the request.
+#[derive(Deserialize)]
+struct LogMessage {
+    level: String,
+    message: String,
+}
+
+#[deno_core::op]
+fn op_chisel_log(
+    state: &mut deno_core::OpState,
+    message: LogMessage,
+    ctx: Option<deno_core::ResourceId>,
+) -> Result<()> {
+    let LogMessage { level, message } = message;
+    let level = match level.as_str() {
+        "debug" => log::Level::Debug,
+        "info" => log::Level::Info,
+        "warn" => log::Level::Warn,
+        "error" => log::Level::Error,
+        _ => bail!("Invalid log level {:?}", level),
+    };
+    let mut fields = vec![(
+        "version",
+        state.borrow::<WorkerState>().version.version_id.clone(),
+    )];
+    if let Some(ctx) = ctx {
+        let ctx = state.resource_table.get::<JobContext>(ctx)?;
+        if let Some(trace_id) = ctx.job_info.trace_id() {
+            fields.push(("trace_id", trace_id.into()));
+        }
+    }
+    log_event(level, "console", format_args!("{}", message), fields);
+    Ok(())
 }
 
 // Used by deno to format names in errors
@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::ops::job_context::{JobContext, JobInfo};
+use crate::worker::WorkerState;
+use anyhow::{bail, Context, Result};
+use deno_core::{serde_v8, OpState};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Message that JavaScript sends to the client of a socket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum SocketMessage {
+    Text { data: String },
+    Binary { data: serde_v8::ZeroCopyBuf },
+}
+
+/// Decides whether the socket that is being opened is accepted.
+#[deno_core::op]
+pub fn op_chisel_socket_accept(
+    state: Rc<RefCell<OpState>>,
+    ctx: deno_core::ResourceId,
+    accepted: bool,
+) -> Result<()> {
+    let ctx = state.borrow_mut().resource_table.get::<JobContext>(ctx)?;
+    match *ctx.job_info {
+        JobInfo::SocketEvent { ref accept_tx, .. } => {
+            let tx = accept_tx
+                .borrow_mut()
+                .take()
+                .context("Socket has already been accepted or it is not being opened")?;
+            let _ = tx.send(accepted);
+        }
+        _ => bail!("invalid request type"),
+    }
+    Ok(())
+}
+
+/// Returns true if the current job may send to sockets. Sandboxed jobs (such as mirrored requests)
+/// must not have effects outside of their transaction, so they cannot.
+fn may_use_sockets(state: &OpState, job_ctx_rid: deno_core::ResourceId) -> Result<bool> {
+    let ctx = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+    Ok(!ctx.job_info.is_sandbox())
+}
+
+#[deno_core::op]
+pub fn op_chisel_socket_send(
+    state: &mut OpState,
+    job_ctx_rid: deno_core::ResourceId,
+    socket_id: u64,
+    message: SocketMessage,
+) -> Result<()> {
+    if !may_use_sockets(state, job_ctx_rid)? {
+        return Ok(());
+    }
+    let message = match message {
+        SocketMessage::Text { data } => Message::Text(data),
+        SocketMessage::Binary { data } => Message::Binary(data.to_vec()),
+    };
+    let worker_state = state.borrow::<WorkerState>();
+    let version_id = &worker_state.version.version_id;
+    worker_state
+        .server
+        .sockets
+        .send(version_id, socket_id, message)
+}
+
+#[deno_core::op]
+pub fn op_chisel_socket_close(
+    state: &mut OpState,
+    job_ctx_rid: deno_core::ResourceId,
+    socket_id: u64,
+    code: Option<u16>,
+    reason: Option<String>,
+) -> Result<()> {
+    if !may_use_sockets(state, job_ctx_rid)? {
+        return Ok(());
+    }
+    let worker_state = state.borrow::<WorkerState>();
+    let version_id = &worker_state.version.version_id;
+    worker_state
+        .server
+        .sockets
+        .close(version_id, socket_id, code, reason.unwrap_or_default())
+}
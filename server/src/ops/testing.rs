@@ -0,0 +1,81 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Ops of the testing-only API (`Chisel.testing`), which are available only with `--testing`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{ensure, Result};
+use deno_core::OpState;
+
+use super::WorkerState;
+use crate::ops::job_context::JobContext;
+use crate::server::Server;
+
+fn ensure_testing(server: &Server) -> Result<()> {
+    ensure!(
+        server.opt.testing,
+        "Chisel.testing is available only when chiseld runs with --testing"
+    );
+    Ok(())
+}
+
+#[deno_core::op]
+pub fn op_chisel_is_testing(state: &mut OpState) -> bool {
+    state.borrow::<WorkerState>().server.opt.testing
+}
+
+/// Deletes all rows of all entities of the version and all entries of its key-value store, in the
+/// transaction of the current job.
+#[deno_core::op]
+pub async fn op_chisel_testing_reset(
+    state: Rc<RefCell<OpState>>,
+    job_ctx_rid: deno_core::ResourceId,
+) -> Result<()> {
+    let (server, version_id, types, txn) = {
+        let state = state.borrow();
+        let worker_state = state.borrow::<WorkerState>();
+        ensure_testing(&worker_state.server)?;
+        let context = state.resource_table.get::<JobContext>(job_ctx_rid)?;
+        let data_ctx = context.data_context()?;
+        let types = worker_state
+            .version
+            .type_system
+            .custom_types
+            .values()
+            .map(|entity| entity.object_type().clone())
+            .collect::<Vec<_>>();
+        data_ctx.mark_written(types.iter().map(|ty| ty.backing_table().to_owned()));
+        (
+            worker_state.server.clone(),
+            worker_state.version.version_id.clone(),
+            types,
+            data_ctx.txn.clone(),
+        )
+    };
+
+    let mut txn = txn.lock().await;
+    for ty in types.iter() {
+        server.query_engine.truncate_table(&mut txn, ty).await?;
+    }
+    server
+        .query_engine
+        .truncate_kv_table(&mut txn, &version_id)
+        .await?;
+    Ok(())
+}
+
+/// Freezes the time that JavaScript sees in all workers at `time_ms` (milliseconds since the UNIX
+/// epoch), or unfreezes it if `time_ms` is `None`.
+#[deno_core::op]
+pub fn op_chisel_testing_freeze_time(state: &mut OpState, time_ms: Option<f64>) -> Result<()> {
+    let server = &state.borrow::<WorkerState>().server;
+    ensure_testing(server)?;
+    *server.frozen_time_ms.write() = time_ms;
+    Ok(())
+}
+
+#[deno_core::op]
+pub fn op_chisel_testing_now(state: &mut OpState) -> Option<f64> {
+    *state.borrow::<WorkerState>().server.frozen_time_ms.read()
+}
@@ -34,6 +34,8 @@ struct SimpleTypeSystem {
 struct SimpleEntity {
     name: String,
     fields: Vec<SimpleField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owned_by: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -44,6 +46,13 @@ struct SimpleField {
     field_type: SimpleTypeId,
     is_optional: bool,
     is_unique: bool,
+    is_version: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enum_values: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_bytes: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -51,9 +60,12 @@ struct SimpleField {
 enum SimpleTypeId {
     String,
     Number,
+    Bigint,
     Boolean,
     JsDate,
     ArrayBuffer,
+    Json,
+    GeoPoint,
     Entity {
         #[serde(rename = "entityName")]
         entity_name: String,
@@ -76,11 +88,19 @@ fn simplify_object_type(obj: &Arc<ObjectType>) -> SimpleEntity {
             field_type: simplify_type_id(&f.type_id),
             is_optional: f.is_optional,
             is_unique: f.is_unique,
+            is_version: f.is_version,
+            timestamp: f.timestamp.map(|timestamp| timestamp.as_str()),
+            enum_values: f
+                .enum_type
+                .as_ref()
+                .map(|enum_type| enum_type.values.clone()),
+            max_bytes: f.max_bytes,
         })
         .collect();
     SimpleEntity {
         name: obj.name().to_owned(),
         fields,
+        owned_by: obj.owned_by().map(ToOwned::to_owned),
     }
 }
 
@@ -88,11 +108,15 @@ fn simplify_type_id(ty: &TypeId) -> SimpleTypeId {
     match ty {
         TypeId::String => SimpleTypeId::String,
         TypeId::Float => SimpleTypeId::Number,
-        TypeId::Int64 => SimpleTypeId::Number,
+        TypeId::Int64 => SimpleTypeId::Bigint,
         TypeId::Boolean => SimpleTypeId::Boolean,
         TypeId::JsDate => SimpleTypeId::JsDate,
         TypeId::Id => SimpleTypeId::String,
         TypeId::ArrayBuffer => SimpleTypeId::ArrayBuffer,
+        TypeId::Json => SimpleTypeId::Json,
+        TypeId::GeoPoint => SimpleTypeId::GeoPoint,
+        // decimals are strings in TypeScript
+        TypeId::Decimal { .. } => SimpleTypeId::String,
         TypeId::Array(element_ty) => SimpleTypeId::Array {
             element_type: simplify_type_id(element_ty).into(),
         },
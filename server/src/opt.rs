@@ -1,6 +1,8 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-use anyhow::{anyhow, Result};
+use crate::logging::LogFormat;
+use anyhow::{anyhow, Context, Result};
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
@@ -30,9 +32,36 @@ pub struct Opt {
     /// Database URI.
     #[structopt(long, default_value = "sqlite://.chiseld.db?mode=rwc")]
     pub db_uri: String,
+    /// Directory where `POST /backup` on the internal routes creates consistent snapshots of the
+    /// database, which can be restored with `chisel restore`. Backups are disabled if not set.
+    #[structopt(long)]
+    pub backup_dir: Option<PathBuf>,
+    /// Directory where `Chisel.blob` stores the blobs, in a subdirectory for each bucket.
+    #[structopt(long)]
+    pub blob_dir: Option<PathBuf>,
+    /// Endpoint of an S3-compatible object store where `Chisel.blob` stores the blobs, in the
+    /// buckets of the same names (such as `https://s3.us-east-1.amazonaws.com`, or
+    /// `http://localhost:9000` for MinIO). The credentials are read from the `AWS_ACCESS_KEY_ID`
+    /// and `AWS_SECRET_ACCESS_KEY` environment variables.
+    #[structopt(long)]
+    pub blob_s3_endpoint: Option<String>,
+    /// Region of the S3-compatible object store, which is part of the signatures of requests.
+    #[structopt(long, default_value = "us-east-1")]
+    pub blob_s3_region: String,
     /// Kafka connection.
     #[structopt(long)]
     pub kafka_connection: Option<String>,
+    /// Kafka consumer group; offsets of the consumed events are committed under this id.
+    #[structopt(long, default_value = "chiseld")]
+    pub kafka_consumer_group: String,
+    /// Where to start consuming a Kafka topic that has no offset committed in the consumer group:
+    /// `earliest`, `latest` or a specific offset. Can be overridden for each topic.
+    #[structopt(long, default_value = "latest")]
+    pub kafka_start_offset: String,
+    /// URL of a Confluent Schema Registry, used to decode and encode events of the Kafka topics
+    /// that have a schema.
+    #[structopt(long)]
+    pub kafka_schema_registry: Option<String>,
     /// Activate inspector and let a debugger attach at any time.
     #[structopt(long)]
     pub inspect: bool,
@@ -42,13 +71,160 @@ pub struct Opt {
     /// Activate debug mode, it will show runtime exceptions in HTTP responses.
     #[structopt(long)]
     pub debug: bool,
-    /// size of database connection pool.
-    #[structopt(short, long, default_value = "10")]
+    /// Enable the testing-only API (`Chisel.testing`), which can delete all data and freeze the
+    /// time. Never use this in production.
+    #[structopt(long)]
+    pub testing: bool,
+    /// Allow the code of all versions to connect to any host, ignoring the `allowed_hosts` of the
+    /// projects. Meant for development.
+    #[structopt(long)]
+    pub allow_all_net: bool,
+    /// Allow raw SQL queries (`Chisel.rawQuery()`), which bypass policies and the other checks of
+    /// the query engine. They are refused when the data is isolated per tenant.
+    #[structopt(long)]
+    pub allow_raw_sql: bool,
+    /// Maximum log level (`error`, `warn`, `info`, `debug` or `trace`). If `RUST_LOG` is set, it
+    /// is used as an upper bound for this level.
+    #[structopt(long)]
+    pub log_level: Option<String>,
+    /// Format of the log output: `text` or `json` (one JSON object per line).
+    #[structopt(long, default_value = "text")]
+    pub log_format: String,
+    /// OTLP/HTTP endpoint that OpenTelemetry traces are exported to (such as
+    /// `http://localhost:4318/v1/traces`). Tracing is disabled if not set.
+    #[structopt(long)]
+    pub otlp_endpoint: Option<String>,
+    /// Service name that is reported in the exported traces.
+    #[structopt(long, default_value = "chiseld")]
+    pub otlp_service_name: String,
+    /// HTTP endpoint that sampled request logs are exported to. Request logs are not exported if
+    /// not set.
+    #[structopt(long)]
+    pub request_log_endpoint: Option<String>,
+    /// Format of the exported request logs: `json` (a JSON array of records) or `otlp` (OTLP/HTTP
+    /// JSON logs, for an endpoint such as `http://localhost:4318/v1/logs`).
+    #[structopt(long, default_value = "json")]
+    pub request_log_protocol: String,
+    /// Fraction of requests (between 0 and 1) whose logs are exported.
+    #[structopt(long, default_value = "1")]
+    pub request_log_sample_rate: f64,
+    /// Overrides `--request-log-sample-rate` for a version, in the form `version=fraction`. Can be
+    /// repeated.
+    #[structopt(long, number_of_values = 1)]
+    pub request_log_version_sample_rate: Vec<String>,
+    /// Maximum number of request log records that are exported in one batch.
+    #[structopt(long, default_value = "100")]
+    pub request_log_batch_size: usize,
+    /// How often (in milliseconds) a partial batch of request log records is exported.
+    #[structopt(long, default_value = "1000")]
+    pub request_log_flush_interval_ms: u64,
+    /// Maximum number of request log records that wait for export. Further records are dropped
+    /// until the queue drains, so a slow sink never delays requests.
+    #[structopt(long, default_value = "10000")]
+    pub request_log_queue_size: usize,
+    /// Fraction of successful requests (between 0 and 1) that are recorded as route examples for
+    /// `chisel contract verify`. Only the latest recorded request of every route is kept.
+    #[structopt(long, default_value = "0")]
+    pub record_examples_rate: f64,
+    /// Names of the JSON fields and query parameters whose values are replaced by `<redacted>` in
+    /// the recorded route examples (case-insensitive, comma-separated).
+    #[structopt(
+        long,
+        use_delimiter = true,
+        default_value = "password,token,secret,authorization"
+    )]
+    pub redact_example_fields: Vec<String>,
+    /// Maximum size of the database connection pool.
+    #[structopt(short, long, default_value = "10", alias = "db-max-connections")]
     pub nr_connections: usize,
+    /// Number of database connections that are kept open even when idle. They are opened at
+    /// startup, and the server reports readiness only after they are open.
+    #[structopt(long, default_value = "0")]
+    pub db_min_connections: u32,
+    /// How long (in seconds) to wait for a database connection from the pool before the request
+    /// fails (can be float).
+    #[structopt(long, default_value = "30")]
+    pub db_acquire_timeout_s: f64,
+    /// How long (in seconds) an idle database connection above `--db-min-connections` is kept
+    /// open (can be float). Zero keeps idle connections open forever.
+    #[structopt(long, default_value = "600")]
+    pub db_idle_timeout_s: f64,
+    /// How often (in seconds) the idle connections kept by `--db-min-connections` are pinged, so
+    /// that the database or a firewall doesn't drop them for inactivity (can be float). Zero
+    /// disables the pings.
+    #[structopt(long, default_value = "0")]
+    pub db_keepalive_interval_s: f64,
+    /// How many prepared statements every database connection caches (the least recently used
+    /// one is evicted). Queries of hot endpoints skip the preparation when their SQL is cached.
+    /// Zero disables the cache.
+    #[structopt(long, default_value = "100")]
+    pub db_statement_cache_capacity: usize,
     /// How many worker threads to create for every version.
     /// (The `executor_threads` alias is DEPRECATED)
     #[structopt(short, long, default_value = "1", alias = "executor-threads")]
     pub worker_threads: usize,
+    /// Maximum number of worker threads for every version. If it is larger than
+    /// `--worker-threads` (which becomes the minimum), workers are added when jobs queue up and
+    /// removed when they are idle.
+    #[structopt(long)]
+    pub max_worker_threads: Option<usize>,
+    /// Add a worker when this many jobs wait for an idle worker.
+    #[structopt(long, default_value = "4")]
+    pub worker_scale_up_queue_depth: usize,
+    /// Add a worker when a job waits for an idle worker for this long (in milliseconds).
+    #[structopt(long, default_value = "200")]
+    pub worker_scale_up_latency_ms: u64,
+    /// Remove a worker after it has been idle for this long (in seconds, can be float).
+    #[structopt(long, default_value = "60")]
+    pub worker_scale_down_idle_s: f64,
+    /// Pins HTTP requests to workers, so that requests with the same key are handled by the same
+    /// worker of a version and can share in-memory state. The key is either `user` (the id of the
+    /// logged-in user) or `header:<name>` (the value of a request header). Requests without the
+    /// key are distributed round-robin, as are requests whose pinned worker is busy, so the
+    /// pinning is only best-effort. Workers are also restarted on every apply, so the in-memory
+    /// state should only be used as a cache.
+    #[structopt(long)]
+    pub worker_affinity: Option<String>,
+    /// Deadline of HTTP requests to user routes (in seconds, can be float); 0 means no deadline.
+    /// When it expires, or when the client disconnects, the in-flight queries and outbound
+    /// fetches of the request are aborted, and JavaScript that keeps running is terminated.
+    /// Requests that expire are answered with status 504. Routes can set their own deadline with
+    /// `RouteMap.timeout()`.
+    #[structopt(long, default_value = "0")]
+    pub request_timeout_s: f64,
+    /// Maximum size (in bytes) of the bodies of HTTP requests to user routes; larger bodies are
+    /// rejected with status 413. The bodies of `ingest()` and `upload()` routes are streamed, so
+    /// they are not limited.
+    #[structopt(long, default_value = "10485760")]
+    pub max_body_size: u64,
+    /// How long (in seconds) to keep the records of applies, so that an interrupted
+    /// `chisel apply` can be resumed with `--resume`.
+    #[structopt(long, default_value = "86400")]
+    pub apply_retention_s: u64,
+    /// How long (in seconds) to keep the tables of models that were archived by
+    /// `chisel apply --archive`, before they are dropped.
+    #[structopt(long, default_value = "2592000")]
+    pub archive_retention_s: u64,
+    /// How long (in seconds) to keep the entries of the audit log, zero keeps them forever. Old
+    /// entries are removed by the garbage collection of the meta database.
+    #[structopt(long, default_value = "7776000")]
+    pub audit_retention_s: u64,
+    /// How often (in seconds) to collect the garbage of the meta database and compact it (see
+    /// `chisel meta gc`), zero disables the periodic collection.
+    #[structopt(long, default_value = "86400")]
+    pub meta_gc_interval_s: u64,
+    /// Maximum number of queries in the query cache (used by `Entity.cached()`), zero disables
+    /// the cache.
+    #[structopt(long, default_value = "1000")]
+    pub query_cache_size: usize,
+    /// Page size of the CRUD `GET` endpoints when the request does not specify one. Can be
+    /// overriden per entity with `crud(Entity, { pageSize })`.
+    #[structopt(long, default_value = "1000")]
+    pub crud_default_page_size: u64,
+    /// Maximum page size of the CRUD `GET` endpoints, larger page sizes are clamped. Can be
+    /// overriden per entity with `crud(Entity, { maxPageSize })`.
+    #[structopt(long, default_value = "10000")]
+    pub crud_max_page_size: u64,
     /// V8 flags.
     #[structopt(long)]
     pub v8_flags: Vec<String>,
@@ -82,6 +258,53 @@ pub struct Opt {
     #[structopt(long)]
     pub typescript_policies: bool,
 
+    /// Required issuer (`iss` claim) of the JWTs that authenticate the users.
+    #[structopt(long)]
+    pub jwt_issuer: Option<String>,
+    /// Required audience (`aud` claim) of the JWTs that authenticate the users, usually the id of
+    /// this application at the issuer.
+    #[structopt(long)]
+    pub jwt_audience: Option<String>,
+    /// URL of the JSON Web Key Set of the issuer, whose keys validate the JWTs (chosen by the
+    /// `kid` of the token). Requires `--jwt-issuer` and `--jwt-audience`. If not set, the JWTs are
+    /// validated with the RSA key in the `CHISEL_JWT_VALIDATION_KEY` secret.
+    #[structopt(long)]
+    pub jwt_jwks_url: Option<String>,
+
+    /// Isolates the data per tenant, identified by this claim of the authenticated user (from the
+    /// JWT or from the authentication hook). Every entity is stored with the tenant that wrote it,
+    /// and the requests only read and write the data of their tenant (requests with the admin
+    /// secret and no tenant access all data).
+    #[structopt(long)]
+    pub tenant_claim: Option<String>,
+    /// Header of the requests that names their tenant. With `--tenant-claim`, requests whose
+    /// header names another tenant than the claim are rejected. Without it, the header alone
+    /// identifies the tenant, which requires `--trust-tenant-header`.
+    #[structopt(long)]
+    pub tenant_header: Option<String>,
+    /// Trusts the `--tenant-header` of the requests without a `--tenant-claim`. Only use this when
+    /// chiseld is behind a proxy that authenticates the clients and sets the header, because any
+    /// client that can reach chiseld can access the data of any tenant by changing the header.
+    #[structopt(long)]
+    pub trust_tenant_header: bool,
+
+    /// Issuer of the OpenID Connect provider that users log in with, at
+    /// `/__chiselstrike/auth/login`. The provider is discovered from
+    /// `<issuer>/.well-known/openid-configuration`; its client secret, if any, is the
+    /// `CHISEL_OIDC_CLIENT_SECRET` secret.
+    #[structopt(long)]
+    pub oidc_issuer: Option<String>,
+    /// Client id of chiseld at the OpenID Connect provider.
+    #[structopt(long)]
+    pub oidc_client_id: Option<String>,
+    /// Public URL of `/__chiselstrike/auth/callback`, which must be registered as a redirect URL
+    /// at the OpenID Connect provider.
+    #[structopt(long)]
+    pub oidc_redirect_url: Option<String>,
+    /// How long the sessions of the users that logged in with the OpenID Connect provider last.
+    #[structopt(long, default_value = "604800")]
+    pub oidc_session_ttl_s: u64,
+
     /// Prints the configuration resulting from the merging of all the configuration sources,
     /// including default values, in the JSON format.
     /// This is the configuration that will be used when starting chiseld.
@@ -90,6 +313,27 @@ pub struct Opt {
     pub show_config: bool,
 }
 
+/// Options that can be changed by reloading the configuration (on SIGHUP) while chiseld is
+/// running. Changes of all other options are only reported and take effect after a restart.
+const RELOADABLE_OPTIONS: &[&str] = &[
+    "debug",
+    "log_level",
+    "log_format",
+    "chisel_secret_key_location",
+    "chisel_secret_location",
+    "secrets_polling_period_s",
+    "secrets_refresh_exponential_backoff_factor",
+    "secrets_refresh_max_exponential_backoff_s",
+];
+
+/// Outcome of a configuration reload: names of the options that changed, split by whether the
+/// change was applied to the running server.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
 impl Opt {
     pub async fn from_file(path: &Path) -> Result<Self> {
         let content = tokio::fs::read(path).await?;
@@ -97,4 +341,110 @@ impl Opt {
 
         Self::from_args_with_toml(content).map_err(|e| anyhow!(e.to_string()))
     }
+
+    /// Loads the options from the command line, merged with the default configuration file and
+    /// the file given by `--config` (if any).
+    pub async fn load() -> Result<Self> {
+        let default_path = find_default_config_path();
+        let opt = match default_path {
+            Some(ref path) => Self::from_file(path).await?,
+            None => Self::from_args(),
+        };
+
+        match opt.config {
+            Some(ref path) => Self::from_file(path)
+                .await
+                .with_context(|| format!("Could not read configuration from {}", path.display())),
+            None => Ok(opt),
+        }
+    }
+
+    pub fn log_level_filter(&self) -> Result<Option<LevelFilter>> {
+        self.log_level
+            .as_ref()
+            .map(|level| {
+                level
+                    .parse()
+                    .with_context(|| format!("Invalid log level {:?}", level))
+            })
+            .transpose()
+    }
+
+    pub fn log_format(&self) -> Result<LogFormat> {
+        self.log_format.parse()
+    }
+
+    /// Compares these options with `new_opt` and reports which of the changes can be applied
+    /// without a restart.
+    pub fn reload_report(&self, new_opt: &Opt) -> ReloadReport {
+        let old = serde_json::to_value(self).expect("Opt must serialize to JSON");
+        let new = serde_json::to_value(new_opt).expect("Opt must serialize to JSON");
+        let (old, new) = (old.as_object().unwrap(), new.as_object().unwrap());
+
+        let mut report = ReloadReport::default();
+        for (name, old_value) in old.iter() {
+            if new.get(name) == Some(old_value) {
+                continue;
+            }
+            if RELOADABLE_OPTIONS.contains(&name.as_str()) {
+                report.applied.push(name.clone());
+            } else {
+                report.restart_required.push(name.clone());
+            }
+        }
+        report
+    }
+
+    /// Copies the values of all reloadable options from `new_opt`.
+    pub fn apply_reloadable(&mut self, new_opt: &Opt) {
+        self.debug = new_opt.debug;
+        self.log_level = new_opt.log_level.clone();
+        self.log_format = new_opt.log_format.clone();
+        self.chisel_secret_key_location = new_opt.chisel_secret_key_location.clone();
+        self.chisel_secret_location = new_opt.chisel_secret_location.clone();
+        self.secrets_polling_period_s = new_opt.secrets_polling_period_s;
+        self.secrets_refresh_exponential_backoff_factor =
+            new_opt.secrets_refresh_exponential_backoff_factor;
+        self.secrets_refresh_max_exponential_backoff_s =
+            new_opt.secrets_refresh_max_exponential_backoff_s;
+    }
+}
+
+fn find_default_config_path() -> Option<PathBuf> {
+    let config_dir = dirs::config_dir()?.join("chiselstrike");
+    let config_path = config_dir.join("config.toml");
+    config_path.exists().then_some(config_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Opt {
+        Opt::from_iter(std::iter::once("chiseld").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn reload_report_splits_changes() {
+        let old = parse(&[]);
+        let new = parse(&["--debug", "--log-level", "debug", "--worker-threads", "4"]);
+        let report = old.reload_report(&new);
+        assert_eq!(report.applied, vec!["debug", "log_level"]);
+        assert_eq!(report.restart_required, vec!["worker_threads"]);
+    }
+
+    #[test]
+    fn reload_report_is_empty_without_changes() {
+        let opt = parse(&["--secrets-polling-period-s", "5"]);
+        assert_eq!(opt.reload_report(&opt.clone()), ReloadReport::default());
+    }
+
+    #[test]
+    fn apply_reloadable_keeps_restart_required() {
+        let mut opt = parse(&[]);
+        let new = parse(&["--debug", "--nr-connections", "20"]);
+        opt.apply_reloadable(&new);
+        assert!(opt.debug);
+        assert_eq!(opt.nr_connections, 10);
+    }
 }
@@ -5,15 +5,22 @@ use crate::prefix_map::PrefixMap;
 use crate::types::ObjectType;
 use crate::JsonObject;
 use anyhow::Result;
+use hmac::{Hmac, Mac};
 use hyper::http;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// Secret that keys the hashes of the `hash` transform of labels.
+pub const ANONYMIZATION_KEY_SECRET: &str = "CHISEL_ANONYMIZATION_KEY";
 
 /// Different kinds of policies.
 #[derive(Clone)]
 pub enum Kind {
     /// How this policy transforms values read from storage.
-    Transform(fn(EntityValue) -> EntityValue),
+    Transform(Transform),
     /// Field is of AuthUser type and must match the user currently logged in.
     MatchLogin,
     /// Field will not be in a query's resulting json object.
@@ -28,10 +35,98 @@ pub struct Policy {
     pub except_uri: regex::Regex,
 }
 
+/// Transformation of the values of a labeled field that are read from storage. Values of types
+/// that a transformation does not support are anonymized.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transform {
+    /// Replaces the value with `xxxxx`.
+    Anonymize,
+    /// Replaces the value with its HMAC-SHA256, keyed by the `CHISEL_ANONYMIZATION_KEY` secret,
+    /// so that equal values still have equal hashes. Without the secret, the values are
+    /// anonymized.
+    Hash,
+    /// Keeps the first `keep_start` and the last `keep_end` characters of strings and replaces
+    /// the others with `*`.
+    PartialMask { keep_start: usize, keep_end: usize },
+    /// Replaces the value with null.
+    Nullify,
+    /// Rounds numbers down to a multiple of `size`.
+    Bucket { size: f64 },
+}
+
+/// The transformation of a field, with the key of the hashes.
+#[derive(Clone)]
+pub struct FieldTransform {
+    pub transform: Transform,
+    key: Option<Arc<str>>,
+}
+
+impl fmt::Debug for FieldTransform {
+    // the key is a secret, so it is never printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.transform.fmt(f)
+    }
+}
+
+impl FieldTransform {
+    pub fn apply(&self, value: EntityValue) -> EntityValue {
+        match (&self.transform, value) {
+            (Transform::Anonymize, value) => anonymize(value),
+            (Transform::Nullify, _) | (_, EntityValue::Null) => EntityValue::Null,
+            (Transform::Hash, value) => match &self.key {
+                Some(key) => EntityValue::String(keyed_hash(key, &value)),
+                None => anonymize(value),
+            },
+            (
+                Transform::PartialMask {
+                    keep_start,
+                    keep_end,
+                },
+                EntityValue::String(s),
+            ) => EntityValue::String(partial_mask(&s, *keep_start, *keep_end)),
+            (Transform::Bucket { size }, EntityValue::Float64(x)) => {
+                EntityValue::Float64((x / size).floor() * size)
+            }
+            (Transform::Bucket { size }, EntityValue::Int64(x)) => {
+                EntityValue::Int64(((x as f64 / size).floor() * size) as i64)
+            }
+            (_, value) => anonymize(value),
+        }
+    }
+}
+
+/// Returns the hex HMAC-SHA256 of a value: of the string itself for strings, of the JSON
+/// representation for other values.
+fn keyed_hash(key: &str, value: &EntityValue) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    match value {
+        EntityValue::String(s) => mac.update(s.as_bytes()),
+        other => mac.update(&serde_json::to_vec(other).unwrap_or_default()),
+    }
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+fn partial_mask(s: &str, keep_start: usize, keep_end: usize) -> String {
+    let len = s.chars().count();
+    s.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            // a string too short to keep both ends is masked completely
+            let keep = keep_start + keep_end < len && (i < keep_start || i >= len - keep_end);
+            if keep {
+                c
+            } else {
+                '*'
+            }
+        })
+        .collect()
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct FieldPolicies {
     /// Maps a field name to the transformation we apply to that field's values.
-    pub transforms: HashMap<String, fn(EntityValue) -> EntityValue>,
+    pub transforms: HashMap<String, FieldTransform>,
     /// Names of fields that must equal the currently logged-in user.
     pub match_login: HashSet<String>,
     /// ID of the currently logged-in user.
@@ -136,12 +231,118 @@ struct RequiredHeader {
     methods: Option<Vec<hyper::Method>>,
 }
 
+/// Limits the rate of requests to the routes under a path (see `rate_limit.rs`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests_per_minute: u32,
+    pub key: RateLimitKey,
+}
+
+/// Determines which requests share a rate limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// All requests share the limit.
+    Global,
+    /// Every logged-in user has its own limit; anonymous requests are limited by their IP address.
+    User,
+    /// Every IP address has its own limit.
+    Ip,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct RateLimits {
+    /// Requests are limited by the longest path prefix present here.
+    paths: PrefixMap<RateLimit>,
+}
+
+impl RateLimits {
+    /// Returns the path of the route with the limit that applies to this path, and the limit.
+    pub fn lookup(&self, path: &str) -> Option<(&str, &RateLimit)> {
+        self.paths.longest_prefix(path)
+    }
+
+    /// Limits the requests to every endpoint under this path.  Longer paths override existing
+    /// prefixes.  Error if this same path has already been added.
+    fn add(&mut self, path: &str, limit: RateLimit) -> Result<()> {
+        if self.paths.insert(path.into(), limit).is_some() {
+            anyhow::bail!("Repeated path in rate limits: {path}");
+        }
+        Ok(())
+    }
+}
+
+/// Security headers that are added to every HTTP response of a version. Headers set by the route
+/// handler take precedence, and routes can override or remove them (see `RouteMap.securityHeaders()`).
+#[derive(Clone, Default, Debug)]
+pub struct SecurityHeaders {
+    headers: Vec<Header>,
+}
+
+type Header = (http::header::HeaderName, http::header::HeaderValue);
+
+impl SecurityHeaders {
+    pub fn iter(&self) -> impl Iterator<Item = &Header> {
+        self.headers.iter()
+    }
+
+    fn from_yaml(yaml: YamlSecurityHeaders) -> Result<Self> {
+        let mut headers = vec![];
+        if let Some(hsts) = yaml.hsts {
+            let mut value = format!("max-age={}", hsts.max_age);
+            if hsts.include_subdomains {
+                value.push_str("; includeSubDomains");
+            }
+            if hsts.preload {
+                value.push_str("; preload");
+            }
+            headers.push(("strict-transport-security".into(), value));
+        }
+        if yaml.content_type_options {
+            headers.push(("x-content-type-options".into(), "nosniff".into()));
+        }
+        if let Some(value) = yaml.frame_options {
+            headers.push(("x-frame-options".into(), value));
+        }
+        if let Some(value) = yaml.referrer_policy {
+            headers.push(("referrer-policy".into(), value));
+        }
+        if let Some(value) = yaml.content_security_policy {
+            headers.push(("content-security-policy".into(), value));
+        }
+        headers.extend(yaml.custom.into_iter());
+
+        let mut parsed_headers: Vec<Header> = vec![];
+        for (name, value) in headers {
+            let name = http::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid security header name {name:?}"))?;
+            let value = http::header::HeaderValue::from_str(&value).map_err(|_| {
+                anyhow::anyhow!("Invalid value of security header {name}: {value:?}")
+            })?;
+            anyhow::ensure!(
+                !parsed_headers.iter().any(|(n, _)| *n == name),
+                "Repeated security header: {name}"
+            );
+            parsed_headers.push((name, value));
+        }
+        Ok(Self {
+            headers: parsed_headers,
+        })
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct PolicySystem {
     /// Maps labels to their applicable policies.
     pub labels: HashMap<String, Policy>,
     pub user_authorization: UserAuthorization,
     pub secret_authorization: SecretAuthorization,
+    pub rate_limits: RateLimits,
+    pub security_headers: SecurityHeaders,
+    /// Users that may access the keys of `Chisel.kv`, by key prefix.
+    pub kv_authorization: UserAuthorization,
+    /// Users that may access the buckets of `Chisel.blob`.
+    pub blob_authorization: UserAuthorization,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -152,22 +353,95 @@ struct MandatoryHeader {
     only_for_methods: Option<Vec<String>>,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+struct YamlRateLimit {
+    #[serde(alias = "requestsPerMinute")]
+    requests_per_minute: u32,
+    key: Option<RateLimitKey>,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 struct Route {
     path: String,
     users: Option<String>,
     mandatory_header: Option<MandatoryHeader>,
+    #[serde(alias = "rateLimit")]
+    rate_limit: Option<YamlRateLimit>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 struct Label {
     name: String,
-    transform: Option<String>,
+    transform: Option<YamlTransform>,
     except_uri: Option<String>,
 }
 
+/// Transform of a label, either its name or, for the transforms with parameters, a map from the
+/// name to the parameters.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(untagged)]
+enum YamlTransform {
+    Name(String),
+    WithParams(YamlTransformParams),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum YamlTransformParams {
+    PartialMask {
+        #[serde(default)]
+        keep_start: usize,
+        #[serde(default)]
+        keep_end: usize,
+    },
+    Bucket {
+        size: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+struct YamlHsts {
+    max_age: u64,
+    #[serde(default)]
+    include_subdomains: bool,
+    #[serde(default)]
+    preload: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+struct YamlSecurityHeaders {
+    hsts: Option<YamlHsts>,
+    #[serde(default)]
+    content_type_options: bool,
+    frame_options: Option<String>,
+    referrer_policy: Option<String>,
+    content_security_policy: Option<String>,
+    /// Other headers, added verbatim.
+    #[serde(default)]
+    custom: BTreeMap<String, String>,
+}
+
+/// Restricts the keys of `Chisel.kv` under `prefix` to the users that match `users`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+struct KvRule {
+    prefix: String,
+    users: String,
+}
+
+/// Restricts the bucket `bucket` of `Chisel.blob` to the users that match `users`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+struct BlobRule {
+    bucket: String,
+    users: String,
+}
+
 type Routes = Vec<Route>;
 type Endpoints = Vec<Route>;
 type Labels = Vec<Label>;
@@ -178,15 +452,20 @@ struct YamlPolicies {
     routes: Option<Routes>,
     endpoints: Option<Endpoints>,
     labels: Option<Labels>,
+    security_headers: Option<YamlSecurityHeaders>,
+    kv: Option<Vec<KvRule>>,
+    blobs: Option<Vec<BlobRule>>,
 }
 
 impl PolicySystem {
-    /// For field of type `ty` creates field policies.
+    /// For field of type `ty` creates field policies. The hashes of the `hash` transform are
+    /// keyed by `anonymization_key`.
     pub fn make_field_policies(
         &self,
         user_id: Option<&str>,
         current_path: &str,
         ty: &ObjectType,
+        anonymization_key: Option<&Arc<str>>,
     ) -> FieldPolicies {
         let mut field_policies = FieldPolicies {
             current_userid: user_id.map(Into::into),
@@ -198,8 +477,14 @@ impl PolicySystem {
                 if let Some(p) = self.labels.get(lbl) {
                     if !p.except_uri.is_match(current_path) {
                         match p.kind {
-                            Kind::Transform(f) => {
-                                field_policies.transforms.insert(fld.name.clone(), f);
+                            Kind::Transform(ref transform) => {
+                                let transform = FieldTransform {
+                                    transform: transform.clone(),
+                                    key: anonymization_key.cloned(),
+                                };
+                                field_policies
+                                    .transforms
+                                    .insert(fld.name.clone(), transform);
                             }
                             Kind::MatchLogin => {
                                 field_policies.match_login.insert(fld.name.clone());
@@ -222,13 +507,8 @@ impl PolicySystem {
             let except_uri = regex::Regex::new(&label.except_uri.unwrap_or_else(
                 || r"^\b$".into(), // ^\b$ never matches;
             ))?;
-            let kind = match label.transform.as_deref() {
-                Some("anonymize") => Kind::Transform(crate::policies::anonymize),
-                Some("omit") => Kind::Omit,
-                Some("match_login") => Kind::MatchLogin,
-                Some(x) => {
-                    anyhow::bail!("unknown transform: {x} for label {}", label.name);
-                }
+            let kind = match label.transform {
+                Some(transform) => parse_transform(transform, &label.name)?,
                 None => continue,
             };
             policies
@@ -236,6 +516,23 @@ impl PolicySystem {
                 .insert(label.name, Policy { kind, except_uri });
         }
 
+        if let Some(security_headers) = parsed_yaml.security_headers {
+            policies.security_headers = SecurityHeaders::from_yaml(security_headers)?;
+        }
+
+        for rule in parsed_yaml.kv.unwrap_or_default() {
+            policies
+                .kv_authorization
+                .add(&rule.prefix, regex::Regex::new(&rule.users)?)?;
+        }
+
+        for rule in parsed_yaml.blobs.unwrap_or_default() {
+            crate::blob::validate_bucket(&rule.bucket)?;
+            policies
+                .blob_authorization
+                .add(&rule.bucket, regex::Regex::new(&rule.users)?)?;
+        }
+
         let routes = parsed_yaml
             .routes
             .or(parsed_yaml.endpoints)
@@ -258,11 +555,67 @@ impl PolicySystem {
                     },
                 )?;
             }
+            if let Some(limit) = route.rate_limit {
+                anyhow::ensure!(
+                    limit.requests_per_minute > 0,
+                    "Rate limit of route {} must allow at least one request per minute",
+                    route.path
+                );
+                policies.rate_limits.add(
+                    &route.path,
+                    RateLimit {
+                        requests_per_minute: limit.requests_per_minute,
+                        key: limit.key.unwrap_or(RateLimitKey::Ip),
+                    },
+                )?;
+            }
         }
         Ok(policies)
     }
 }
 
+fn parse_transform(transform: YamlTransform, label: &str) -> Result<Kind> {
+    let transform = match transform {
+        YamlTransform::Name(name) => match name.as_str() {
+            "anonymize" => Transform::Anonymize,
+            "hash" => Transform::Hash,
+            "nullify" => Transform::Nullify,
+            // by default, the end is kept, like the last digits of card numbers
+            "partial_mask" => Transform::PartialMask {
+                keep_start: 0,
+                keep_end: 4,
+            },
+            "omit" => return Ok(Kind::Omit),
+            "match_login" => return Ok(Kind::MatchLogin),
+            "bucket" => anyhow::bail!("transform bucket for label {label} requires a size"),
+            x => anyhow::bail!("unknown transform: {x} for label {label}"),
+        },
+        YamlTransform::WithParams(YamlTransformParams::PartialMask {
+            keep_start,
+            keep_end,
+        }) => Transform::PartialMask {
+            keep_start,
+            keep_end,
+        },
+        YamlTransform::WithParams(YamlTransformParams::Bucket { size }) => {
+            anyhow::ensure!(
+                size.is_finite() && size > 0.0,
+                "transform bucket for label {label} requires a positive size"
+            );
+            Transform::Bucket { size }
+        }
+    };
+    Ok(Kind::Transform(transform))
+}
+
+/// Returns the key of the `hash` transform from the secrets.
+pub fn anonymization_key(secrets: &JsonObject) -> Option<Arc<str>> {
+    secrets
+        .get(ANONYMIZATION_KEY_SECRET)
+        .and_then(|key| key.as_str())
+        .map(Into::into)
+}
+
 /// Parses v's elements into Methods. Returns Err if an element failed to parse.
 fn parse_methods(v: Vec<String>) -> Result<Vec<hyper::Method>> {
     use anyhow::Context;
@@ -276,3 +629,99 @@ pub fn anonymize(_: EntityValue) -> EntityValue {
     // TODO: use type-specific anonymization.
     EntityValue::String("xxxxx".to_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(transform: Transform, key: Option<&str>) -> FieldTransform {
+        FieldTransform {
+            transform,
+            key: key.map(Into::into),
+        }
+    }
+
+    fn label_transform(yaml: &str) -> Result<Transform> {
+        let policies = PolicySystem::from_yaml(yaml)?;
+        match &policies.labels["pii"].kind {
+            Kind::Transform(transform) => Ok(transform.clone()),
+            _ => panic!("not a transform"),
+        }
+    }
+
+    #[test]
+    fn parse_transforms() {
+        let parse = |transform: &str| {
+            label_transform(&format!(
+                "labels:\n  - name: pii\n    transform: {transform}\n"
+            ))
+        };
+        assert_eq!(parse("hash").unwrap(), Transform::Hash);
+        assert_eq!(parse("nullify").unwrap(), Transform::Nullify);
+        assert_eq!(
+            parse("{ partial_mask: { keep_start: 1 } }").unwrap(),
+            Transform::PartialMask {
+                keep_start: 1,
+                keep_end: 0
+            }
+        );
+        assert_eq!(
+            parse("{ bucket: { size: 10 } }").unwrap(),
+            Transform::Bucket { size: 10.0 }
+        );
+        assert!(parse("bucket").is_err());
+        assert!(parse("{ bucket: { size: 0 } }").is_err());
+        assert!(parse("{ scramble: {} }").is_err());
+    }
+
+    #[test]
+    fn hashes_are_keyed() {
+        let value = || EntityValue::String("alice@example.com".into());
+        let hash = transform(Transform::Hash, Some("k1")).apply(value());
+        assert_eq!(hash, transform(Transform::Hash, Some("k1")).apply(value()));
+        assert_ne!(hash, transform(Transform::Hash, Some("k2")).apply(value()));
+        assert_eq!(hash.as_str().unwrap().len(), 64);
+        assert_eq!(
+            transform(Transform::Hash, None).apply(value()),
+            EntityValue::String("xxxxx".into())
+        );
+    }
+
+    #[test]
+    fn masks_and_buckets() {
+        let mask = |s: &str, keep_start, keep_end| {
+            transform(
+                Transform::PartialMask {
+                    keep_start,
+                    keep_end,
+                },
+                None,
+            )
+            .apply(EntityValue::String(s.into()))
+        };
+        assert_eq!(
+            mask("4111111111111111", 0, 4).as_str().unwrap(),
+            "************1111"
+        );
+        assert_eq!(mask("alice", 1, 1).as_str().unwrap(), "a***e");
+        assert_eq!(mask("ab", 1, 1).as_str().unwrap(), "**");
+
+        let bucket = transform(Transform::Bucket { size: 10.0 }, None);
+        assert_eq!(
+            bucket.apply(EntityValue::Float64(37.5)),
+            EntityValue::Float64(30.0)
+        );
+        assert_eq!(
+            bucket.apply(EntityValue::Int64(-3)),
+            EntityValue::Int64(-10)
+        );
+        assert_eq!(
+            bucket.apply(EntityValue::String("old".into())),
+            EntityValue::String("xxxxx".into())
+        );
+        assert_eq!(
+            transform(Transform::Nullify, None).apply(EntityValue::Float64(1.0)),
+            EntityValue::Null
+        );
+    }
+}
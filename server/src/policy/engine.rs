@@ -11,11 +11,15 @@ use serde_json::Value as JsonValue;
 
 use super::debug::debug;
 use super::interpreter::{self, InterpreterContext, JsonResolver};
+use super::mask::MASK_JS;
 use super::store::PolicyStore;
+use super::trace::PolicyTrace;
 use super::type_policy::{GeoLocPolicy, ReadPolicy, TransformPolicy, TypePolicy, WritePolicy};
 use super::utils::json_to_js_value;
 use super::Action;
+use crate::authentication::claimed_roles;
 use crate::datastore::expr::{BinaryExpr, BinaryOp, Expr, PropertyAccess, Value};
+use crate::http::RequestContext;
 
 pub struct PolicyEngine {
     /// The boa context, used for evaluating JS with boa.
@@ -34,6 +38,26 @@ pub trait ChiselRequestContext {
     fn user_id(&self) -> Option<&str>;
     fn token(&self) -> Option<&JsonValue>;
 
+    /// Tenant of the request when the data is isolated per tenant (see `datastore::tenancy`).
+    fn tenant_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Context of the HTTP request, if the policies are evaluated in one.
+    fn request_context(&self) -> Option<&RequestContext> {
+        None
+    }
+
+    /// OpenTelemetry context that the spans of policy evaluation are children of.
+    fn otel_context(&self) -> opentelemetry::Context {
+        opentelemetry::Context::new()
+    }
+
+    /// Trace that records the policy decisions, if the request asked for it.
+    fn policy_trace(&self) -> Option<&PolicyTrace> {
+        None
+    }
+
     // TODO: need to find a way around using json here.
     fn to_value(&self) -> JsonValue {
         serde_json::json!({
@@ -41,7 +65,12 @@ pub trait ChiselRequestContext {
             "path": self.path(),
             "headers": self.headers().collect::<HashMap<_, _>>(),
             "user_id": self.user_id(),
+            "tenant_id": self.tenant_id(),
             "token": self.token(),
+            "roles": claimed_roles(self.token()),
+            "version_id": self.request_context().map(|c| &c.version_id),
+            "request_id": self.request_context().map(|c| &c.request_id),
+            "client_ip": self.request_context().map(|c| &c.client_ip),
         })
     }
 
@@ -57,6 +86,12 @@ pub trait ChiselRequestContext {
         };
         map.set("userId", user_id, false, ctx).unwrap();
 
+        let tenant_id = match self.tenant_id() {
+            Some(val) => JsValue::String(JsString::from(val)),
+            None => JsValue::Null,
+        };
+        map.set("tenantId", tenant_id, false, ctx).unwrap();
+
         let headers = JsObject::empty();
         for (key, val) in self.headers() {
             headers.set(key, val, false, ctx).unwrap();
@@ -70,6 +105,19 @@ pub trait ChiselRequestContext {
 
         map.set("token", token, false, ctx).unwrap();
 
+        let roles = serde_json::json!(claimed_roles(self.token()));
+        let roles = json_to_js_value(ctx, &roles);
+        map.set("roles", roles, false, ctx).unwrap();
+
+        if let Some(request_ctx) = self.request_context() {
+            map.set("versionId", request_ctx.version_id.as_str(), false, ctx)
+                .unwrap();
+            map.set("requestId", request_ctx.request_id.as_str(), false, ctx)
+                .unwrap();
+            map.set("clientIp", request_ctx.client_ip.as_str(), false, ctx)
+                .unwrap();
+        }
+
         JsValue::Object(map)
     }
 }
@@ -80,6 +128,9 @@ impl PolicyEngine {
         let action = Action::js_value(&mut context)?;
         context.register_global_property("Action", action, Attribute::all());
         context.register_global_function("debug", 0, debug);
+        context
+            .eval(MASK_JS)
+            .map_err(|e| boa_err_to_anyhow(e, &mut context))?;
         Ok(Self {
             boa_ctx: Rc::new(RefCell::new(context)),
             policies: Default::default(),
@@ -228,7 +279,7 @@ fn cond_to_expr(
             let left = cond_to_expr(left, preds, entity_param_name, env)?;
             BinaryExpr::or(left, right)
         }
-        Cond::Not(cond) => Expr::Not(Box::new(cond_to_expr(cond, preds, entity_param_name, env)?)),
+        Cond::Not(cond) => Expr::negate(cond_to_expr(cond, preds, entity_param_name, env)?),
         Cond::Predicate(id) => {
             let predicate = preds.get(*id);
             predicate_to_expr(predicate, entity_param_name, env)?
@@ -270,7 +321,7 @@ fn predicate_to_expr(pred: &Predicate, entity_param_name: &str, env: &Environmen
                 right,
             })
         }
-        Predicate::Not(pred) => Expr::Not(predicate_to_expr(pred, entity_param_name, env)?.into()),
+        Predicate::Not(pred) => Expr::negate(predicate_to_expr(pred, entity_param_name, env)?),
         Predicate::Lit(val) => Expr::Value {
             value: Value::from(val),
         },
@@ -462,23 +513,20 @@ mod test {
             .unwrap()
             .unwrap();
         // if name == "marin", skip, in other words, take name != "marin"
-        let expected = Expr::Not(
-            BinaryExpr::and(
-                BinaryExpr::eq(
-                    Expr::Property(PropertyAccess {
-                        property: "name".into(),
-                        object: Expr::Parameter { position: 0 }.into(),
-                    }),
-                    Expr::Value {
-                        value: Value::String("marin".into()),
-                    },
-                ),
+        let expected = Expr::negate(BinaryExpr::and(
+            BinaryExpr::eq(
+                Expr::Property(PropertyAccess {
+                    property: "name".into(),
+                    object: Expr::Parameter { position: 0 }.into(),
+                }),
                 Expr::Value {
-                    value: Value::Bool(true),
+                    value: Value::String("marin".into()),
                 },
-            )
-            .into(),
-        );
+            ),
+            Expr::Value {
+                value: Value::Bool(true),
+            },
+        ));
 
         assert_eq!(expr, expected);
     }
@@ -565,48 +613,42 @@ mod test {
 
         // sorry for what's next... :()
         // p1 = !(name == "marin" && true) = !A
-        let p1 = Expr::Not(
-            BinaryExpr::and(
-                BinaryExpr::eq(
-                    Expr::Property(PropertyAccess {
-                        property: "name".into(),
-                        object: Expr::Parameter { position: 0 }.into(),
-                    }),
-                    Expr::Value {
-                        value: Value::String("marin".into()),
-                    },
-                ),
+        let p1 = Expr::negate(BinaryExpr::and(
+            BinaryExpr::eq(
+                Expr::Property(PropertyAccess {
+                    property: "name".into(),
+                    object: Expr::Parameter { position: 0 }.into(),
+                }),
                 Expr::Value {
-                    value: Value::Bool(true),
+                    value: Value::String("marin".into()),
                 },
-            )
-            .into(),
-        );
+            ),
+            Expr::Value {
+                value: Value::Bool(true),
+            },
+        ));
 
         // p2 = !(name == Jim && age < 178) = !C
-        let p2 = Expr::Not(
-            BinaryExpr::and(
-                BinaryExpr::eq(
-                    Expr::Property(PropertyAccess {
-                        property: "name".into(),
-                        object: Expr::Parameter { position: 0 }.into(),
-                    }),
-                    Expr::Value {
-                        value: Value::String("Jim".into()),
-                    },
-                ),
-                BinaryExpr::lt(
-                    Expr::Property(PropertyAccess {
-                        property: "age".into(),
-                        object: Expr::Parameter { position: 0 }.into(),
-                    }),
-                    Expr::Value {
-                        value: Value::F64(178.0),
-                    },
-                ),
-            )
-            .into(),
-        );
+        let p2 = Expr::negate(BinaryExpr::and(
+            BinaryExpr::eq(
+                Expr::Property(PropertyAccess {
+                    property: "name".into(),
+                    object: Expr::Parameter { position: 0 }.into(),
+                }),
+                Expr::Value {
+                    value: Value::String("Jim".into()),
+                },
+            ),
+            BinaryExpr::lt(
+                Expr::Property(PropertyAccess {
+                    property: "age".into(),
+                    object: Expr::Parameter { position: 0 }.into(),
+                }),
+                Expr::Value {
+                    value: Value::F64(178.0),
+                },
+            ),
+        ));
 
         // p3 = p1 && p2 = !A && !C
         let p3 = BinaryExpr::and(p1, p2);
@@ -622,23 +664,20 @@ mod test {
                     value: Value::F64(42.0),
                 },
             ),
-            Expr::Not(
-                BinaryExpr::and(
-                    BinaryExpr::eq(
-                        Expr::Property(PropertyAccess {
-                            property: "name".into(),
-                            object: Expr::Parameter { position: 0 }.into(),
-                        }),
-                        Expr::Value {
-                            value: Value::String("marin".into()),
-                        },
-                    ),
+            Expr::negate(BinaryExpr::and(
+                BinaryExpr::eq(
+                    Expr::Property(PropertyAccess {
+                        property: "name".into(),
+                        object: Expr::Parameter { position: 0 }.into(),
+                    }),
                     Expr::Value {
-                        value: Value::Bool(true),
+                        value: Value::String("marin".into()),
                     },
-                )
-                .into(),
-            ),
+                ),
+                Expr::Value {
+                    value: Value::Bool(true),
+                },
+            )),
         );
 
         // expected = p3 || p4 = (!A && !C) || (!A && B) : SUCCESS this is what we wanted!
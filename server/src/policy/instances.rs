@@ -11,7 +11,9 @@ use paste::paste;
 use crate::datastore::expr::Expr;
 use crate::types::ObjectType;
 
+use super::mask::FieldMask;
 use super::type_policy::{GeoLocPolicy, ReadPolicy, TransformPolicy, WritePolicy};
+use super::utils::js_value_to_entity_value;
 use super::{Action, Location, PolicyContext};
 
 /// The PolicyEvalInstance contains instances of policy and cache valid for a type in a given
@@ -130,16 +132,25 @@ impl PolicyEvalInstance {
         }
     }
 
-    /// Applies the onRead transform to value.
+    /// Applies the onRead transform to value, and returns the field mask if the policy returned
+    /// one (see `mask.rs`).
     ///
     /// This mutates value! therefore value should be set as mutable.
-    pub fn transform_on_read(&mut self, ctx: &PolicyContext, val: &JsValue) -> Result<()> {
+    pub fn transform_on_read(
+        &mut self,
+        ctx: &PolicyContext,
+        val: &JsValue,
+    ) -> Result<Option<FieldMask>> {
         let chisel_ctx = self.chisel_ctx.clone();
-        self.get_or_load_on_read_policy_instance(ctx)?
+        let result = self
+            .get_or_load_on_read_policy_instance(ctx)?
             .map(|p| p.transform(ctx, val, &chisel_ctx))
             .transpose()?;
 
-        Ok(())
+        match result {
+            Some(result) => FieldMask::from_policy_result(&js_value_to_entity_value(&result)),
+            None => Ok(None),
+        }
     }
 
     /// Applies the onCreate transform to value.
@@ -254,18 +265,16 @@ pub struct TransformPolicyInstance {
 }
 
 impl TransformPolicyInstance {
-    /// applies the transform to value.
+    /// applies the transform to value, and returns the value returned by the transform.
     /// TODO: check that output type conforms to model.
     pub fn transform(
         &self,
         ctx: &PolicyContext,
         value: &JsValue,
         chisel_ctx: &JsValue,
-    ) -> Result<()> {
+    ) -> Result<JsValue> {
         ctx.engine
-            .call(self.function.clone(), &[value.clone(), chisel_ctx.clone()])?;
-
-        Ok(())
+            .call(self.function.clone(), &[value.clone(), chisel_ctx.clone()])
     }
 
     pub fn new(_ctx: &PolicyContext, p: &TransformPolicy) -> Result<Self> {
@@ -350,7 +359,7 @@ mod test {
         let function = compile(policy_ctx, code);
         let filter = TransformPolicyInstance { function };
 
-        filter.transform(policy_ctx, value, &req_js).unwrap()
+        filter.transform(policy_ctx, value, &req_js).unwrap();
     }
 
     #[test]
@@ -492,4 +501,39 @@ mod test {
 
         assert_eq!(val.as_string().unwrap().as_str(), "bob");
     }
+
+    #[test]
+    fn read_mask() {
+        let code = br#"
+            (person, ctx) => {
+                if (ctx.userId == person.ownerId) {
+                    return person;
+                }
+                return Mask.fields({ email: Mask.Redact });
+            }
+        "#;
+        let ctx = Rc::new(serde_json::json!({
+            "headers": { },
+            "method": "GET",
+            "path": "/hello",
+            "userId": "marin"
+        }));
+
+        let policy_ctx = make_context(ctx);
+        let mask = |owner: &str| {
+            let value = serde_json::json!({ "ownerId": owner, "email": "a@b.c" });
+            let value = json_to_js_value(&mut policy_ctx.engine.boa_ctx.borrow_mut(), &value);
+            let req_js = policy_ctx
+                .request
+                .to_js_value(&mut policy_ctx.engine.boa_ctx.borrow_mut());
+            let filter = TransformPolicyInstance {
+                function: compile(&policy_ctx, code),
+            };
+            let result = filter.transform(&policy_ctx, &value, &req_js).unwrap();
+            FieldMask::from_policy_result(&js_value_to_entity_value(&result)).unwrap()
+        };
+
+        assert!(mask("marin").is_none());
+        assert!(mask("jim").is_some());
+    }
 }
@@ -0,0 +1,154 @@
+//! Field masks, which `onRead` policies return to hide fields of the entities that are read.
+//!
+//! A policy builds a mask with the `Mask` global, e.g.
+//! `return Mask.fields({ email: Mask.Redact, phone: Mask.Hash })`. Redacted fields are removed
+//! from the entity, hashed fields are replaced by the SHA-256 hash of their value, so that they
+//! can still be compared without being revealed.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::datastore::value::{EntityMap, EntityValue};
+
+/// Property of the object returned by `Mask.fields()` that holds the masked fields.
+const MASK_KEY: &str = "__chiselFieldMask";
+
+/// Definition of the `Mask` global of the policies.
+pub const MASK_JS: &str = r#"
+globalThis.Mask = Object.freeze({
+    Redact: "redact",
+    Hash: "hash",
+    fields: (fields) => ({ __chiselFieldMask: fields }),
+});
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskAction {
+    /// Removes the field.
+    Redact,
+    /// Replaces the value of the field with its hash.
+    Hash,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldMask {
+    fields: HashMap<String, MaskAction>,
+}
+
+impl FieldMask {
+    /// Extracts the mask from the value returned by an `onRead` policy, `None` if the policy did
+    /// not return a mask.
+    pub fn from_policy_result(result: &EntityValue) -> Result<Option<Self>> {
+        let fields = match result {
+            EntityValue::Map(map) => match map.get(MASK_KEY) {
+                Some(EntityValue::Map(fields)) => fields,
+                Some(_) => bail!("`Mask.fields()` expects an object that maps fields to masks"),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        let fields = fields
+            .iter()
+            .map(|(field, action)| {
+                let action = match action.as_str() {
+                    Ok("redact") => MaskAction::Redact,
+                    Ok("hash") => MaskAction::Hash,
+                    _ => {
+                        bail!("invalid mask of field `{field}`: expected Mask.Redact or Mask.Hash")
+                    }
+                };
+                Ok((field.clone(), action))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(Self { fields }))
+    }
+
+    /// Masks the fields of `entity`. The id is never masked, because the entity could not be
+    /// told apart from the others without it.
+    pub fn apply(&self, entity: &mut EntityMap) {
+        for (field, action) in self.fields.iter() {
+            if field == "id" {
+                continue;
+            }
+            match action {
+                MaskAction::Redact => {
+                    entity.remove(field);
+                }
+                MaskAction::Hash => {
+                    if let Some(value) = entity.get_mut(field) {
+                        if !value.is_null() {
+                            *value = EntityValue::String(hash_value(value));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the hex SHA-256 hash of a value: of the string itself for strings, of the JSON
+/// representation for other values.
+fn hash_value(value: &EntityValue) -> String {
+    let digest = match value {
+        EntityValue::String(s) => Sha256::digest(s.as_bytes()),
+        other => Sha256::digest(serde_json::to_vec(other).unwrap_or_default()),
+    };
+    format!("{:x}", digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity() -> EntityMap {
+        let mut entity = EntityMap::new();
+        entity.insert("id".into(), EntityValue::String("1".into()));
+        entity.insert("email".into(), EntityValue::String("a@b.c".into()));
+        entity.insert("age".into(), EntityValue::Float64(27.0));
+        entity.insert("phone".into(), EntityValue::Null);
+        entity
+    }
+
+    fn mask(fields: &[(&str, &str)]) -> EntityValue {
+        let fields = fields
+            .iter()
+            .map(|(f, a)| (f.to_string(), EntityValue::String(a.to_string())))
+            .collect();
+        EntityValue::Map([(MASK_KEY.to_string(), EntityValue::Map(fields))].into())
+    }
+
+    #[test]
+    fn only_masks_are_masks() {
+        assert_eq!(
+            FieldMask::from_policy_result(&EntityValue::Map(entity())).unwrap(),
+            None
+        );
+        assert_eq!(
+            FieldMask::from_policy_result(&EntityValue::Null).unwrap(),
+            None
+        );
+        assert!(FieldMask::from_policy_result(&mask(&[("email", "hide")])).is_err());
+    }
+
+    #[test]
+    fn masks_fields() {
+        let mask = FieldMask::from_policy_result(&mask(&[
+            ("id", "redact"),
+            ("email", "redact"),
+            ("age", "hash"),
+            ("phone", "hash"),
+        ]))
+        .unwrap()
+        .unwrap();
+        let mut masked = entity();
+        mask.apply(&mut masked);
+        assert_eq!(masked.get("id"), Some(&EntityValue::String("1".into())));
+        assert_eq!(masked.get("email"), None);
+        assert_eq!(masked.get("phone"), Some(&EntityValue::Null));
+        let hashed = masked["age"].as_str().unwrap();
+        assert_eq!(hashed.len(), 64);
+        assert_eq!(hashed, hash_value(&EntityValue::Float64(27.0)));
+    }
+}
@@ -12,6 +12,7 @@ use boa_engine::JsValue;
 use futures::{Stream, StreamExt};
 
 use crate::datastore::value::{EntityMap, EntityValue};
+use crate::telemetry;
 use crate::types::ObjectType;
 
 use self::engine::{boa_err_to_anyhow, ChiselRequestContext, PolicyEngine};
@@ -21,7 +22,9 @@ mod debug;
 pub mod engine;
 mod instances;
 mod interpreter;
+pub mod mask;
 pub mod store;
+pub mod trace;
 pub mod type_policy;
 mod utils;
 
@@ -161,6 +164,39 @@ pub struct PolicyProcessor {
 
 impl PolicyProcessor {
     pub fn process_read(&self, value: EntityMap) -> anyhow::Result<Option<EntityMap>> {
+        let span_cx = self.start_span("policy read");
+        let result = self.process_read_inner(value);
+        telemetry::end(&span_cx, &result);
+        result
+    }
+
+    pub fn process_write(
+        &self,
+        value: &EntityMap,
+        write_action: WriteAction,
+    ) -> Result<(EntityMap, Option<Location>)> {
+        let span_cx = self.start_span(match write_action {
+            WriteAction::Create => "policy create",
+            WriteAction::Update => "policy update",
+        });
+        let result = self.process_write_inner(value, write_action);
+        telemetry::end(&span_cx, &result);
+        result
+    }
+
+    fn start_span(&self, name: &'static str) -> opentelemetry::Context {
+        telemetry::start_span(
+            &self.ctx.request.otel_context(),
+            name,
+            opentelemetry::trace::SpanKind::Internal,
+            vec![opentelemetry::KeyValue::new(
+                "chisel.entity",
+                self.ty.name().to_owned(),
+            )],
+        )
+    }
+
+    fn process_read_inner(&self, value: EntityMap) -> anyhow::Result<Option<EntityMap>> {
         let mut instance = self
             .ctx
             .cache
@@ -169,7 +205,11 @@ impl PolicyProcessor {
         let js_value =
             entity_map_to_js_value(&mut self.ctx.engine.boa_ctx.borrow_mut(), &value, true);
 
-        let js_value = match instance.get_read_action(&self.ctx, &js_value)? {
+        let action = instance.get_read_action(&self.ctx, &js_value)?;
+        if let Some(trace) = self.ctx.request.policy_trace() {
+            trace.record_read(self.ty.name(), action.as_ref());
+        }
+        let js_value = match action {
             Some(Action::Allow) | None => Some(js_value),
             Some(Action::Deny) => Err(PolicyError::ReadPermissionDenied(self.ty.clone()))?,
             Some(Action::Skip) => None,
@@ -181,11 +221,17 @@ impl PolicyProcessor {
 
         match js_value {
             Some(js_value) => {
-                instance.transform_on_read(&self.ctx, &js_value)?;
-                let new_val = js_value_to_entity_value(&js_value).try_into_map()?;
+                let mask = instance.transform_on_read(&self.ctx, &js_value)?;
+                let mut new_val = js_value_to_entity_value(&js_value).try_into_map()?;
+                if let Some(mask) = mask {
+                    mask.apply(&mut new_val);
+                }
 
                 if new_val != value {
                     instance.mark_dirty(value["id"].as_str().unwrap());
+                    if let Some(trace) = self.ctx.request.policy_trace() {
+                        trace.record(self.ty.name(), |trace| trace.transformed += 1);
+                    }
                 }
 
                 Ok(Some(new_val))
@@ -198,7 +244,7 @@ impl PolicyProcessor {
         }
     }
 
-    pub fn process_write(
+    fn process_write_inner(
         &self,
         value: &EntityMap,
         write_action: WriteAction,
@@ -222,6 +268,12 @@ impl PolicyProcessor {
 
         let geo_loc = instance.geo_loc(&self.ctx, &js_value)?;
 
+        if let (Some(trace), Some(action)) = (self.ctx.request.policy_trace(), &action) {
+            if action.is_restrictive() {
+                trace.record(self.ty.name(), |trace| trace.writes_denied += 1);
+            }
+        }
+
         match action {
             Some(Action::Log) => {
                 log::info!("{value:?}");
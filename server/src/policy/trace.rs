@@ -0,0 +1,102 @@
+//! Explanation of what the policies did during a request.
+//!
+//! Admins can ask for it with the `X-Chisel-Policy-Trace` header (see
+//! `authorization::authorize_policy_trace()`), and the decisions are then returned as JSON in the
+//! same header of the response, so that developers can tell why rows are missing or changed.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use super::Action;
+
+/// Policy decisions made while a request was handled, per entity.
+#[derive(Debug, Default)]
+pub struct PolicyTrace {
+    entities: RefCell<BTreeMap<String, EntityPolicyTrace>>,
+}
+
+/// Policy decisions made about the rows of one entity.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityPolicyTrace {
+    /// Rows that the read policy allowed, including the logged rows.
+    pub allowed: u64,
+    /// Rows whose read was denied, which failed the request.
+    pub denied: u64,
+    /// Rows that the read policy filtered out of the results.
+    pub skipped: u64,
+    /// Rows that the read policy logged.
+    pub logged: u64,
+    /// Rows that were changed by the `onRead` transform.
+    pub transformed: u64,
+    /// Writes that the create or update policies rejected.
+    pub writes_denied: u64,
+    /// Fields whose values were transformed by the label policies.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub transformed_fields: BTreeSet<String>,
+    /// Fields that the label policies omitted from the results.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub omitted_fields: BTreeSet<String>,
+    /// Fields that restricted the rows to the ones of the logged-in user.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub login_fields: BTreeSet<String>,
+}
+
+impl PolicyTrace {
+    /// Records the decisions about the rows of `entity` made by `f`.
+    pub fn record(&self, entity: &str, f: impl FnOnce(&mut EntityPolicyTrace)) {
+        let mut entities = self.entities.borrow_mut();
+        f(entities.entry(entity.to_owned()).or_default())
+    }
+
+    /// Records the action of the read policy of `entity` for one row.
+    pub fn record_read(&self, entity: &str, action: Option<&Action>) {
+        self.record(entity, |trace| match action {
+            Some(Action::Allow) | None => trace.allowed += 1,
+            Some(Action::Deny) => trace.denied += 1,
+            Some(Action::Skip) => trace.skipped += 1,
+            Some(Action::Log) => {
+                trace.allowed += 1;
+                trace.logged += 1;
+            }
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&*self.entities.borrow()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_per_entity() {
+        let trace = PolicyTrace::default();
+        trace.record_read("Person", None);
+        trace.record_read("Person", Some(&Action::Skip));
+        trace.record_read("Person", Some(&Action::Log));
+        trace.record("Person", |trace| {
+            trace.omitted_fields.insert("email".into());
+        });
+        trace.record("Company", |trace| trace.writes_denied += 1);
+
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "Company": {
+                    "allowed": 0, "denied": 0, "skipped": 0, "logged": 0, "transformed": 0,
+                    "writesDenied": 1,
+                },
+                "Person": {
+                    "allowed": 2, "denied": 0, "skipped": 1, "logged": 1, "transformed": 0,
+                    "writesDenied": 0, "omittedFields": ["email"],
+                },
+            })
+        );
+    }
+}
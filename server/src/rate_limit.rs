@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Rate limiting of HTTP requests.
+//!
+//! Limits are configured per route in the policies (see `RateLimit`) and they are enforced in
+//! `http.rs` before the request is dispatched to a worker. Every limit is a token bucket that holds
+//! at most `requests_per_minute` tokens and refills continuously, so short bursts are allowed as
+//! long as the average rate stays under the limit.
+
+use crate::policies::RateLimit;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// When there are more buckets than this, buckets that are full (that is, buckets of clients that
+/// have been idle for a while) are removed.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// Token buckets of all clients that were rate limited, shared by all versions.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<BucketKey, Bucket>>,
+}
+
+/// Identifies a bucket: the version, the path of the route with the limit (so that all paths under
+/// the route share the bucket), and the client (see `RateLimitKey`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BucketKey {
+    pub version_id: String,
+    pub route_path: String,
+    pub client: String,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * tokens_per_second(limit)).min(capacity(limit));
+        self.updated_at = now;
+    }
+}
+
+fn capacity(limit: &RateLimit) -> f64 {
+    limit.requests_per_minute as f64
+}
+
+fn tokens_per_second(limit: &RateLimit) -> f64 {
+    limit.requests_per_minute as f64 / 60.0
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket `key`. If the bucket is empty, the request must be rejected
+    /// and we return how long the client should wait before retrying.
+    pub fn check(&self, key: BucketKey, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| {
+                // we don't know the limit of the other buckets, but a minute always refills them
+                now.saturating_duration_since(bucket.updated_at) < Duration::from_secs(60)
+            });
+        }
+
+        let bucket = buckets.entry(key).or_insert_with(|| Bucket {
+            tokens: capacity(limit),
+            updated_at: now,
+        });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait_s = (1.0 - bucket.tokens) / tokens_per_second(limit);
+            Err(Duration::from_secs_f64(wait_s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policies::RateLimitKey;
+
+    fn key(client: &str) -> BucketKey {
+        BucketKey {
+            version_id: "dev".into(),
+            route_path: "/comments".into(),
+            client: client.into(),
+        }
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limit = RateLimit {
+            requests_per_minute: 2,
+            key: RateLimitKey::Ip,
+        };
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        assert!(limiter.check(key("a"), &limit, start).is_ok());
+        assert!(limiter.check(key("a"), &limit, start).is_ok());
+        let retry_after = limiter.check(key("a"), &limit, start).unwrap_err();
+        assert!((retry_after.as_secs_f64() - 30.0).abs() < 1e-6);
+
+        // other clients have their own buckets
+        assert!(limiter.check(key("b"), &limit, start).is_ok());
+
+        // one token is refilled every 30 seconds
+        let later = start + Duration::from_secs(20);
+        let retry_after = limiter.check(key("a"), &limit, later).unwrap_err();
+        assert!((retry_after.as_secs_f64() - 10.0).abs() < 1e-6);
+        let later = start + Duration::from_secs(31);
+        assert!(limiter.check(key("a"), &limit, later).is_ok());
+        assert!(limiter.check(key("a"), &limit, later).is_err());
+    }
+}
@@ -0,0 +1,402 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Export of sampled request logs to an external sink.
+//!
+//! Records of the handled requests are sampled (with a rate that can be configured per version)
+//! and put into a bounded queue, from which a background task sends them in batches to an HTTP
+//! endpoint, either as a JSON array or as OTLP/HTTP JSON logs. Requests never wait for the
+//! export: if the queue is full, the record is dropped and counted.
+
+use crate::opt::Opt;
+use anyhow::{bail, ensure, Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use utils::TaskHandle;
+
+/// Number of attempts to send a batch before it is dropped.
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Log record of one handled HTTP request.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestLogRecord {
+    /// Time when the request was received, in milliseconds since the UNIX epoch.
+    pub timestamp_ms: u64,
+    /// Id of the version in the URL of the request, if any.
+    pub version_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    pub trace_id: String,
+}
+
+/// Format of the batches that are sent to the sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// A JSON array of [`RequestLogRecord`]s.
+    Json,
+    /// OTLP/HTTP logs encoded as JSON (`resourceLogs`).
+    Otlp,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Protocol::Json),
+            "otlp" => Ok(Protocol::Otlp),
+            _ => bail!(
+                "Unknown request log protocol {:?}, expected `json` or `otlp`",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ExportConfig {
+    endpoint: String,
+    protocol: Protocol,
+    service_name: String,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+/// Handle that samples request log records and queues them for export.
+#[derive(Debug)]
+pub struct RequestLog {
+    sample_rate: f64,
+    version_sample_rates: HashMap<String, f64>,
+    record_tx: mpsc::Sender<RequestLogRecord>,
+    /// Number of records that were dropped because the queue was full, since the last time that
+    /// the drops were reported.
+    dropped: Arc<AtomicU64>,
+}
+
+impl RequestLog {
+    /// Creates the request log from `--request-log-*` options and spawns its export task.
+    /// Returns `None` if `--request-log-endpoint` is not set.
+    pub fn spawn(opt: &Opt) -> Result<Option<(Arc<Self>, TaskHandle<Result<()>>)>> {
+        let endpoint = match opt.request_log_endpoint {
+            Some(ref endpoint) => endpoint.clone(),
+            None => return Ok(None),
+        };
+        let sample_rate =
+            parse_rate(opt.request_log_sample_rate).context("Invalid --request-log-sample-rate")?;
+        let version_sample_rates = parse_version_sample_rates(&opt.request_log_version_sample_rate)
+            .context("Invalid --request-log-version-sample-rate")?;
+        ensure!(
+            opt.request_log_batch_size > 0,
+            "--request-log-batch-size must be positive"
+        );
+        ensure!(
+            opt.request_log_queue_size > 0,
+            "--request-log-queue-size must be positive"
+        );
+        let config = ExportConfig {
+            endpoint,
+            protocol: opt.request_log_protocol.parse()?,
+            service_name: opt.otlp_service_name.clone(),
+            batch_size: opt.request_log_batch_size,
+            flush_interval: Duration::from_millis(opt.request_log_flush_interval_ms.max(1)),
+        };
+
+        let (record_tx, record_rx) = mpsc::channel(opt.request_log_queue_size);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let client = reqwest::Client::builder()
+            .timeout(SEND_TIMEOUT)
+            .build()
+            .context("Could not create HTTP client for the request log")?;
+        let task = tokio::task::spawn(export(record_rx, client, config, dropped.clone()));
+        let request_log = Self {
+            sample_rate,
+            version_sample_rates,
+            record_tx,
+            dropped,
+        };
+        Ok(Some((Arc::new(request_log), TaskHandle(task))))
+    }
+
+    /// Decides whether the next request to the given version should be logged.
+    pub fn sample(&self, version_id: Option<&str>) -> bool {
+        let rate = version_id
+            .and_then(|version_id| self.version_sample_rates.get(version_id))
+            .copied()
+            .unwrap_or(self.sample_rate);
+        rate > 0. && rand::random::<f64>() < rate
+    }
+
+    /// Queues the record for export. This never blocks: if the queue is full, the record is
+    /// dropped.
+    pub fn record(&self, record: RequestLogRecord) {
+        if self.record_tx.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl RequestLogRecord {
+    pub fn new(
+        received: SystemTime,
+        version_id: Option<String>,
+        method: String,
+        path: String,
+        status: u16,
+        latency: Duration,
+        trace_id: String,
+    ) -> Self {
+        let timestamp_ms = received
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            timestamp_ms,
+            version_id,
+            method,
+            path,
+            status,
+            latency_ms: latency.as_secs_f64() * 1000.,
+            trace_id,
+        }
+    }
+}
+
+fn parse_rate(rate: f64) -> Result<f64> {
+    ensure!(
+        (0. ..=1.).contains(&rate),
+        "Sample rate {} must be between 0 and 1",
+        rate
+    );
+    Ok(rate)
+}
+
+/// Parses overrides of the sample rate in the form `version=rate`.
+fn parse_version_sample_rates(overrides: &[String]) -> Result<HashMap<String, f64>> {
+    let mut rates = HashMap::new();
+    for item in overrides {
+        let (version_id, rate) = item
+            .split_once('=')
+            .with_context(|| format!("Expected `version=rate`, got {:?}", item))?;
+        let rate = rate
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("Invalid sample rate in {:?}", item))?;
+        rates.insert(version_id.trim().to_owned(), parse_rate(rate)?);
+    }
+    Ok(rates)
+}
+
+async fn export(
+    mut record_rx: mpsc::Receiver<RequestLogRecord>,
+    client: reqwest::Client,
+    config: ExportConfig,
+    dropped: Arc<AtomicU64>,
+) -> Result<()> {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    let mut interval = tokio::time::interval(config.flush_interval);
+    loop {
+        tokio::select! {
+            record = record_rx.recv() => match record {
+                Some(record) => {
+                    batch.push(record);
+                    if batch.len() >= config.batch_size {
+                        flush(&client, &config, &mut batch, &dropped).await;
+                    }
+                }
+                None => {
+                    flush(&client, &config, &mut batch, &dropped).await;
+                    return Ok(());
+                }
+            },
+            _ = interval.tick() => flush(&client, &config, &mut batch, &dropped).await,
+        }
+    }
+}
+
+async fn flush(
+    client: &reqwest::Client,
+    config: &ExportConfig,
+    batch: &mut Vec<RequestLogRecord>,
+    dropped: &AtomicU64,
+) {
+    if !batch.is_empty() {
+        let body = match config.protocol {
+            Protocol::Json => json!(batch),
+            Protocol::Otlp => encode_otlp(batch, &config.service_name),
+        };
+        if let Err(err) = send(client, &config.endpoint, &body).await {
+            warn!(
+                "Could not export {} request log records to {}: {:?}",
+                batch.len(),
+                config.endpoint,
+                err
+            );
+        }
+        batch.clear();
+    }
+
+    let dropped = dropped.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        warn!(
+            "Dropped {} request log records because the export queue was full",
+            dropped
+        );
+    }
+}
+
+async fn send(client: &reqwest::Client, endpoint: &str, body: &JsonValue) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let result = client
+            .post(endpoint)
+            .json(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => return Ok(()),
+            Err(err) if attempt >= MAX_ATTEMPTS => return Err(err.into()),
+            Err(err) => {
+                debug!("Request log export failed (attempt {}): {}", attempt, err);
+                tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Encodes the records as an OTLP/HTTP JSON `ExportLogsServiceRequest`.
+fn encode_otlp(records: &[RequestLogRecord], service_name: &str) -> JsonValue {
+    let string_attr = |key: &str, value: &str| json!({"key": key, "value": {"stringValue": value}});
+    let log_records: Vec<JsonValue> = records
+        .iter()
+        .map(|record| {
+            let mut attributes = vec![
+                string_attr("http.method", &record.method),
+                string_attr("http.target", &record.path),
+                json!({"key": "http.status_code", "value": {"intValue": record.status.to_string()}}),
+                json!({"key": "http.duration_ms", "value": {"doubleValue": record.latency_ms}}),
+                string_attr("chisel.trace_id", &record.trace_id),
+            ];
+            if let Some(ref version_id) = record.version_id {
+                attributes.push(string_attr("chisel.version_id", version_id));
+            }
+            let (severity_number, severity_text) = if record.status >= 500 {
+                (17, "ERROR")
+            } else {
+                (9, "INFO")
+            };
+            json!({
+                "timeUnixNano": (record.timestamp_ms as u128 * 1_000_000).to_string(),
+                "severityNumber": severity_number,
+                "severityText": severity_text,
+                "body": {
+                    "stringValue": format!("{} {} {}", record.method, record.path, record.status),
+                },
+                "attributes": attributes,
+            })
+        })
+        .collect();
+    json!({
+        "resourceLogs": [{
+            "resource": {"attributes": [string_attr("service.name", service_name)]},
+            "scopeLogs": [{
+                "scope": {"name": "chiseld.request_log"},
+                "logRecords": log_records,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(status: u16) -> RequestLogRecord {
+        RequestLogRecord {
+            timestamp_ms: 1_600_000_000_000,
+            version_id: Some("dev".into()),
+            method: "GET".into(),
+            path: "/dev/hello".into(),
+            status,
+            latency_ms: 1.5,
+            trace_id: "abc".into(),
+        }
+    }
+
+    fn request_log(
+        sample_rate: f64,
+        overrides: &[&str],
+        queue_size: usize,
+    ) -> (RequestLog, mpsc::Receiver<RequestLogRecord>) {
+        let overrides: Vec<String> = overrides.iter().map(|s| s.to_string()).collect();
+        let (record_tx, record_rx) = mpsc::channel(queue_size);
+        let log = RequestLog {
+            sample_rate,
+            version_sample_rates: parse_version_sample_rates(&overrides).unwrap(),
+            record_tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (log, record_rx)
+    }
+
+    #[test]
+    fn version_sample_rates() {
+        let rates = parse_version_sample_rates(&["dev=0.5".into(), " prod = 0 ".into()]).unwrap();
+        assert_eq!(rates.get("dev"), Some(&0.5));
+        assert_eq!(rates.get("prod"), Some(&0.));
+        assert!(parse_version_sample_rates(&["dev".into()]).is_err());
+        assert!(parse_version_sample_rates(&["dev=x".into()]).is_err());
+        assert!(parse_version_sample_rates(&["dev=1.5".into()]).is_err());
+    }
+
+    #[test]
+    fn sample_per_version() {
+        let (log, _record_rx) = request_log(1., &["noisy=0"], 1);
+        assert!(log.sample(Some("dev")));
+        assert!(log.sample(None));
+        assert!(!log.sample(Some("noisy")));
+
+        let (log, _record_rx) = request_log(0., &["dev=1"], 1);
+        assert!(log.sample(Some("dev")));
+        assert!(!log.sample(Some("prod")));
+    }
+
+    #[test]
+    fn full_queue_drops_records() {
+        let (log, mut record_rx) = request_log(1., &[], 2);
+        for _ in 0..5 {
+            log.record(record(200));
+        }
+        assert_eq!(log.dropped.load(Ordering::Relaxed), 3);
+        assert!(record_rx.try_recv().is_ok());
+        assert!(record_rx.try_recv().is_ok());
+        assert!(record_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn otlp_encoding() {
+        let body = encode_otlp(&[record(200), record(503)], "chiseld");
+        let resource_logs = &body["resourceLogs"][0];
+        assert_eq!(
+            resource_logs["resource"]["attributes"][0]["value"]["stringValue"],
+            "chiseld"
+        );
+        let log_records = &resource_logs["scopeLogs"][0]["logRecords"];
+        assert_eq!(log_records[0]["timeUnixNano"], "1600000000000000000");
+        assert_eq!(log_records[0]["severityText"], "INFO");
+        assert_eq!(log_records[1]["severityText"], "ERROR");
+        assert_eq!(log_records[0]["body"]["stringValue"], "GET /dev/hello 200");
+        let attributes = log_records[0]["attributes"].as_array().unwrap();
+        assert!(attributes
+            .contains(&json!({"key": "chisel.version_id", "value": {"stringValue": "dev"}})));
+    }
+}
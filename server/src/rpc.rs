@@ -1,28 +1,50 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use crate::datastore::{MetaService, QueryEngine};
+use crate::datastore::diff::{self, RowDiff as DataRowDiff};
+use crate::datastore::query::QueryPlan;
+use crate::datastore::transfer::{self, Importer};
+use crate::datastore::value::EntityValue;
+use crate::datastore::{ApplyStatus, MetaService, QueryEngine};
+use crate::mirror::Mirror;
 use crate::policies::PolicySystem;
 use crate::proto::chisel_rpc_server::{ChiselRpc, ChiselRpcServer};
 use crate::proto::{
-    ApplyRequest, ApplyResponse, DeleteRequest, DeleteResponse, DescribeRequest, DescribeResponse,
-    FieldDefinition, LabelPolicyDefinition, PopulateRequest, PopulateResponse, StatusRequest,
-    StatusResponse, TypeDefinition, VersionDefinition,
+    data_diff_response, row_diff, secrets_request, AggregateDefinition, AggregateFieldDefinition,
+    ApplyPolicyOnlyRequest, ApplyPolicyOnlyResponse, ApplyRequest, ApplyResponse,
+    ArchivedEntity as ProtoArchivedEntity, AuditEntry as ProtoAuditEntry, CheckCountsRequest,
+    CheckCountsResponse, CountDefinition, CountMismatch as ProtoCountMismatch, DataDiffRequest,
+    DataDiffResponse, DataDiffSummary, DataFormat, DataQueryRequest, DataQueryResponse,
+    DeleteRequest, DeleteResponse, DescribeRequest, DescribeResponse, EnumDefinition,
+    ExportDataRequest, ExportDataResponse, FieldDefinition, FieldDiff, ImportDataRequest,
+    ImportDataResponse, ImportedEntity, IndexDefinition, IndexSuggestion as ProtoIndexSuggestion,
+    IndexSuggestionsRequest, IndexSuggestionsResponse, JsonSchemaRequest, JsonSchemaResponse,
+    LabelPolicyDefinition, ListArchivesRequest, ListArchivesResponse, ListAuditLogRequest,
+    ListAuditLogResponse, MetaGcRequest, MetaGcResponse, MetaGcTable, MirrorRequest,
+    MirrorResponse, MirrorStatus, MirrorStatusRequest, MirrorStatusResponse, Module as ProtoModule,
+    OpenApiRequest, OpenApiResponse, PopulateRequest, PopulateResponse, RowDiff, RunSeedsRequest,
+    RunSeedsResponse, SecretInfo, SecretsRequest, SecretsResponse, StatusRequest, StatusResponse,
+    TypeDefinition, VersionDefinition, VersionStateRequest, VersionStateResponse,
 };
 use crate::server::{self, Server};
-use crate::types::TypeSystem;
-use crate::version::{VersionInfo, VersionInit};
-use crate::{apply, version};
+use crate::static_files::{self, StaticFiles};
+use crate::traffic_split::TrafficSplit;
+use crate::types::{Entity, TypeSystem};
+use crate::version::{Version, VersionInfo, VersionInit};
+use crate::worker_pool::{WorkerPoolConfig, WorkerPoolOverrides};
+use crate::{apply, json_schema, openapi, seed, version, worker};
 use anyhow::{bail, ensure, Context, Result};
 use deno_core::futures;
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt};
+use prost::Message;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::panic;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::oneshot;
-use tonic::{Request, Response, Status};
-use utils::{CancellableTaskHandle, TaskHandle};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use utils::{CancellableTaskHandle, ImportMap, TaskHandle};
 use uuid::Uuid;
 
 /// RPC service for Chisel server.
@@ -78,7 +100,29 @@ impl ChiselRpc for RpcService {
         &self,
         request: Request<ApplyRequest>,
     ) -> Result<Response<ApplyResponse>, Status> {
-        apply(self.server.clone(), request.into_inner())
+        // the apply runs in a separate task, so that it is not cancelled halfway when the client
+        // disconnects; the client can then resume it (see `apply_staged()`)
+        let task = tokio::task::spawn(apply_staged(self.server.clone(), request.into_inner()));
+        let result = match task.await {
+            Ok(result) => result,
+            Err(err) => Err(anyhow::Error::new(err).context("Apply task has failed")),
+        };
+        result.map(Response::new).map_err(|e| {
+            if e.is::<MissingReusedModule>() {
+                // the client applies again with the code of all modules
+                Status::failed_precondition(e.to_string())
+            } else {
+                Status::internal(format!("{:?}", e))
+            }
+        })
+    }
+
+    /// Replace the policies of a running version, without re-applying its types and modules
+    async fn apply_policy_only(
+        &self,
+        request: Request<ApplyPolicyOnlyRequest>,
+    ) -> Result<Response<ApplyPolicyOnlyResponse>, Status> {
+        apply_policy_only(&self.server, request.into_inner())
             .await
             .map(Response::new)
             .map_err(|e| Status::internal(format!("{:?}", e)))
@@ -111,6 +155,368 @@ impl ChiselRpc for RpcService {
     ) -> Result<Response<DescribeResponse>, Status> {
         Ok(Response::new(describe(&self.server)))
     }
+
+    /// Describe all versions with an OpenAPI document
+    async fn open_api(
+        &self,
+        _request: Request<OpenApiRequest>,
+    ) -> Result<Response<OpenApiResponse>, Status> {
+        let document = openapi::document(&self.server);
+        Ok(Response::new(OpenApiResponse {
+            document: serde_json::to_string_pretty(&document).unwrap(),
+        }))
+    }
+
+    /// Describe the entities of all versions with JSON Schema documents
+    async fn json_schema(
+        &self,
+        _request: Request<JsonSchemaRequest>,
+    ) -> Result<Response<JsonSchemaResponse>, Status> {
+        let document = json_schema::document(&self.server);
+        Ok(Response::new(JsonSchemaResponse {
+            document: serde_json::to_string_pretty(&document).unwrap(),
+        }))
+    }
+
+    /// Start, update or stop mirroring of requests between versions
+    async fn mirror(
+        &self,
+        request: Request<MirrorRequest>,
+    ) -> Result<Response<MirrorResponse>, Status> {
+        mirror(&self.server, request.into_inner())
+            .map(Response::new)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn get_mirror_status(
+        &self,
+        _request: Request<MirrorStatusRequest>,
+    ) -> Result<Response<MirrorStatusResponse>, Status> {
+        Ok(Response::new(mirror_status(&self.server)))
+    }
+
+    type DataDiffStream = ReceiverStream<Result<DataDiffResponse, Status>>;
+
+    /// Compare the data of an entity between two versions, streaming the differences
+    async fn data_diff(
+        &self,
+        request: Request<DataDiffRequest>,
+    ) -> Result<Response<Self::DataDiffStream>, Status> {
+        let request = request.into_inner();
+        // validate the request eagerly, so that the errors are reported as a gRPC status
+        let (from, to, fraction) = data_diff_entities(&self.server, &request)
+            .map_err(|e| Status::invalid_argument(format!("{:?}", e)))?;
+
+        let (tx, rx) = mpsc::channel(64);
+        let server = self.server.clone();
+        tokio::task::spawn(async move {
+            let send_row = |row_diff| {
+                let tx = tx.clone();
+                async move {
+                    let event = data_diff_response::Event::Row(row_diff_to_proto(row_diff));
+                    tx.send(Ok(DataDiffResponse { event: Some(event) }))
+                        .await
+                        .context("Client stopped receiving the diff")
+                }
+            };
+            let result = diff::diff_entity(&server.query_engine, &from, &to, fraction, send_row)
+                .await
+                .map(|summary| DataDiffResponse {
+                    event: Some(data_diff_response::Event::Summary(DataDiffSummary {
+                        compared: summary.compared,
+                        changed: summary.changed,
+                        only_in_from: summary.only_in_from,
+                        only_in_to: summary.only_in_to,
+                        from_only_fields: summary.from_only_fields,
+                        to_only_fields: summary.to_only_fields,
+                    })),
+                })
+                .map_err(|e| Status::internal(format!("{:?}", e)));
+            let _ = tx.send(result).await;
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Read the rows of a live or archived entity
+    async fn data_query(
+        &self,
+        request: Request<DataQueryRequest>,
+    ) -> Result<Response<DataQueryResponse>, Status> {
+        let response = data_query(&self.server, request.into_inner())
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(response))
+    }
+
+    /// List the entities whose tables were archived
+    async fn list_archives(
+        &self,
+        _request: Request<ListArchivesRequest>,
+    ) -> Result<Response<ListArchivesResponse>, Status> {
+        let archives = self
+            .server
+            .meta_service
+            .load_archived_entities()
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        let archives = archives
+            .into_iter()
+            .map(|archived| ProtoArchivedEntity {
+                version_id: archived.version_id,
+                entity_name: archived.entity_name,
+                archived_at: archived.archived_at,
+            })
+            .collect();
+        Ok(Response::new(ListArchivesResponse { archives }))
+    }
+
+    /// List the changes of locked entities and other sensitive operations
+    async fn list_audit_log(
+        &self,
+        _request: Request<ListAuditLogRequest>,
+    ) -> Result<Response<ListAuditLogResponse>, Status> {
+        let entries = self
+            .server
+            .meta_service
+            .load_audit_log()
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        let entries = entries
+            .into_iter()
+            .map(|entry| ProtoAuditEntry {
+                version_id: entry.version_id,
+                actor: entry.actor,
+                action: entry.action,
+                detail: entry.detail,
+                created_at: entry.created_at,
+            })
+            .collect();
+        Ok(Response::new(ListAuditLogResponse { entries }))
+    }
+
+    /// Set, unset or list the secrets that are stored in the database
+    async fn secrets(
+        &self,
+        request: Request<SecretsRequest>,
+    ) -> Result<Response<SecretsResponse>, Status> {
+        let response = secrets(&self.server, request.into_inner())
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(response))
+    }
+
+    /// Check (and optionally fix) that the `@count` fields match the data
+    async fn check_counts(
+        &self,
+        request: Request<CheckCountsRequest>,
+    ) -> Result<Response<CheckCountsResponse>, Status> {
+        let response = check_counts(&self.server, request.into_inner())
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(response))
+    }
+
+    /// Describe the types, routes, modules and policies of a version, to compare them with a
+    /// local project
+    async fn get_version_state(
+        &self,
+        request: Request<VersionStateRequest>,
+    ) -> Result<Response<VersionStateResponse>, Status> {
+        let response = version_state(&self.server, request.into_inner())
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(response))
+    }
+
+    /// Suggest indexes from the fields that the queries of a version filtered and sorted by
+    async fn index_suggestions(
+        &self,
+        request: Request<IndexSuggestionsRequest>,
+    ) -> Result<Response<IndexSuggestionsResponse>, Status> {
+        let response = index_suggestions(&self.server, request.into_inner())
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(response))
+    }
+
+    /// Remove the garbage from the meta database and compact it
+    async fn meta_gc(
+        &self,
+        request: Request<MetaGcRequest>,
+    ) -> Result<Response<MetaGcResponse>, Status> {
+        let report = server::collect_meta_garbage(&self.server, request.into_inner().dry_run)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        let tables = report
+            .removed_rows
+            .into_iter()
+            .map(|(table, removed_rows)| MetaGcTable {
+                table,
+                removed_rows,
+            })
+            .collect();
+        Ok(Response::new(MetaGcResponse {
+            tables,
+            reclaimed_bytes: report.reclaimed_bytes,
+        }))
+    }
+
+    /// Run the seed scripts of a version that have not been applied yet
+    async fn run_seeds(
+        &self,
+        request: Request<RunSeedsRequest>,
+    ) -> Result<Response<RunSeedsResponse>, Status> {
+        let request = request.into_inner();
+        let outcome = seed::run_seeds(&self.server, &request.version_id)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(RunSeedsResponse {
+            applied: outcome.applied,
+            skipped: outcome.skipped,
+            error: outcome.error.unwrap_or_default(),
+        }))
+    }
+
+    type ExportDataStream = ReceiverStream<Result<ExportDataResponse, Status>>;
+
+    /// Export the rows of the entities of a version, streaming them in batches
+    async fn export_data(
+        &self,
+        request: Request<ExportDataRequest>,
+    ) -> Result<Response<Self::ExportDataStream>, Status> {
+        let request = request.into_inner();
+        // validate the request eagerly, so that the errors are reported as a gRPC status
+        let entities = export_entities(&self.server, &request)
+            .map_err(|e| Status::invalid_argument(format!("{:?}", e)))?;
+
+        let csv = request.format == DataFormat::Csv as i32;
+        if csv {
+            for entity in entities.iter() {
+                transfer::csv_columns(entity)
+                    .map_err(|e| Status::invalid_argument(format!("{:?}", e)))?;
+            }
+        }
+
+        let (tx, rx) = mpsc::channel(16);
+        let server = self.server.clone();
+        tokio::task::spawn(async move {
+            for entity in entities.iter() {
+                let send = |msg: ExportDataResponse| {
+                    let tx = tx.clone();
+                    async move {
+                        tx.send(Ok(msg))
+                            .await
+                            .context("Client stopped receiving the export")
+                    }
+                };
+                let entity_name = entity.name().to_owned();
+                let result = if csv {
+                    let send_chunk = |csv, csv_rows| {
+                        send(ExportDataResponse {
+                            entity_name: entity_name.clone(),
+                            csv,
+                            csv_rows,
+                            ..Default::default()
+                        })
+                    };
+                    transfer::export_entity_csv(&server.query_engine, entity, send_chunk).await
+                } else {
+                    let send_rows = |rows| {
+                        send(ExportDataResponse {
+                            entity_name: entity_name.clone(),
+                            rows,
+                            ..Default::default()
+                        })
+                    };
+                    transfer::export_entity(&server.query_engine, entity, send_rows).await
+                };
+                if let Err(e) = result {
+                    let _ = tx.send(Err(Status::internal(format!("{:?}", e)))).await;
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Import rows into the entities of a version in a single transaction
+    async fn import_data(
+        &self,
+        request: Request<Streaming<ImportDataRequest>>,
+    ) -> Result<Response<ImportDataResponse>, Status> {
+        let response = import_data(&self.server, request.into_inner())
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(response))
+    }
+}
+
+/// Describes the user-defined types of a version, sorted by name.
+fn type_definitions(version: &Version) -> Vec<TypeDefinition> {
+    let mut type_defs = version
+        .type_system
+        .custom_types
+        .values()
+        .map(|entity| {
+            let field_defs = entity
+                .all_fields()
+                .map(|field| {
+                    let field_type = version.type_system.get(&field.type_id).unwrap();
+                    FieldDefinition {
+                        name: field.name.to_owned(),
+                        field_type: Some(field_type.into()),
+                        labels: field.labels.clone(),
+                        default_value: field.user_provided_default().clone(),
+                        is_optional: field.is_optional,
+                        is_unique: field.is_unique,
+                        is_version: field.is_version,
+                        count: field.count.as_ref().map(|count| CountDefinition {
+                            entity_name: count.entity.clone(),
+                            field_name: count.field.clone(),
+                        }),
+                        aggregate: field.aggregate.as_ref().map(|aggregate| {
+                            AggregateFieldDefinition {
+                                function: aggregate.function.as_str().to_owned(),
+                                source_field: aggregate.source_field.clone().unwrap_or_default(),
+                            }
+                        }),
+                        timestamp: field
+                            .timestamp
+                            .map(|timestamp| timestamp.as_str().to_owned())
+                            .unwrap_or_default(),
+                        enum_type: field.enum_type.as_ref().map(|enum_type| EnumDefinition {
+                            name: enum_type.name.clone(),
+                            values: enum_type.values.clone(),
+                        }),
+                        max_bytes: field.max_bytes,
+                        on_delete: field
+                            .on_delete
+                            .map(|on_delete| on_delete.as_str().to_owned())
+                            .unwrap_or_default(),
+                        collation: field
+                            .collation
+                            .map(|collation| collation.as_str().to_owned())
+                            .unwrap_or_default(),
+                        // renames are applied, the server doesn't remember them
+                        renamed_from: String::new(),
+                    }
+                })
+                .collect();
+
+            TypeDefinition {
+                name: entity.name().to_string(),
+                field_defs,
+                aggregate: entity.aggregate().map(|aggregate| AggregateDefinition {
+                    source_entity: aggregate.source.clone(),
+                    refresh_interval_s: aggregate.refresh_interval_s,
+                }),
+                locked: entity.is_locked(),
+                owned_by: entity.owned_by().unwrap_or_default().to_owned(),
+            }
+        })
+        .collect::<Vec<_>>();
+    type_defs.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+    type_defs
 }
 
 fn describe(server: &Server) -> DescribeResponse {
@@ -119,36 +525,10 @@ fn describe(server: &Server) -> DescribeResponse {
     let version_defs = versions
         .into_iter()
         .map(|version| {
-            let mut type_defs = version
-                .type_system
-                .custom_types
-                .values()
-                .map(|entity| {
-                    let field_defs = entity
-                        .all_fields()
-                        .map(|field| {
-                            let field_type = version.type_system.get(&field.type_id).unwrap();
-                            FieldDefinition {
-                                name: field.name.to_owned(),
-                                field_type: Some(field_type.into()),
-                                labels: field.labels.clone(),
-                                default_value: field.user_provided_default().clone(),
-                                is_optional: field.is_optional,
-                                is_unique: field.is_unique,
-                            }
-                        })
-                        .collect();
-
-                    TypeDefinition {
-                        name: entity.name().to_string(),
-                        field_defs,
-                    }
-                })
-                .collect::<Vec<_>>();
-            type_defs.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+            let type_defs = type_definitions(&version);
 
             let mut label_policy_defs = version
-                .policy_system
+                .policy_system()
                 .labels
                 .keys()
                 .map(|label| LabelPolicyDefinition {
@@ -168,12 +548,252 @@ fn describe(server: &Server) -> DescribeResponse {
     DescribeResponse { version_defs }
 }
 
-async fn apply(server: Arc<Server>, request: ApplyRequest) -> Result<ApplyResponse> {
+/// Applies a request that can be resumed.
+///
+/// The request is staged under its `apply_id` before it is applied, and the outcome is recorded
+/// in the same transaction that modifies the database. If the client loses the connection, it
+/// can send the request again with `resume`: a pending apply is then applied from the staged
+/// request, and the recorded outcome is returned for an apply that has already finished.
+/// A module of `ApplyRequest.reused_modules` that the running version does not have.
+#[derive(Debug)]
+struct MissingReusedModule(String);
+
+impl std::fmt::Display for MissingReusedModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Module {} was not sent, but the running version does not have it",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for MissingReusedModule {}
+
+/// Adds the modules that the client did not send again, because they are unchanged since the last
+/// apply, to the modules of the request. They are taken from the running version, before the
+/// request is staged, so that a resumed apply does not depend on the running version.
+fn resolve_reused_modules(server: &Server, request: &mut ApplyRequest) -> Result<()> {
+    if request.reused_modules.is_empty() {
+        return Ok(());
+    }
+    let running = server.trunk.get_version(&request.version_id);
+    for module_ref in std::mem::take(&mut request.reused_modules) {
+        let code = running
+            .as_ref()
+            .and_then(|running| running.modules.get(&module_ref.url))
+            .filter(|code| version::module_hash(code) == module_ref.hash)
+            .ok_or_else(|| MissingReusedModule(module_ref.url.clone()))?;
+        request.modules.push(ProtoModule {
+            url: module_ref.url,
+            code: code.clone(),
+        });
+    }
+    Ok(())
+}
+
+async fn apply_staged(server: Arc<Server>, mut request: ApplyRequest) -> Result<ApplyResponse> {
+    resolve_reused_modules(&server, &mut request)?;
+    if request.dry_run {
+        // a dry run changes nothing, so it needs neither staging nor the apply lock
+        return dry_run_apply(server, request).await;
+    }
+    if request.apply_id.is_empty() {
+        // the client does not support resuming
+        let lock_id = Uuid::new_v4().to_string();
+        lock_apply(&server, &lock_id, &ApplyLock::of_apply(&request)).await?;
+        let result = apply(server.clone(), request, None).await;
+        server.meta_service.unlock_apply(&lock_id).await?;
+        return result;
+    }
+
+    let apply_id = request.apply_id.clone();
+    let meta = &server.meta_service;
+    if request.resume {
+        let staged = meta.load_staged_apply(&apply_id).await?.with_context(|| {
+            format!(
+                "Apply {} does not exist, it might have been removed after --apply-retention-s",
+                apply_id
+            )
+        })?;
+        match staged.status {
+            ApplyStatus::Applied => {
+                let response = staged.response.unwrap_or_default();
+                return ApplyResponse::decode(&*response).context("Corrupted apply response");
+            }
+            ApplyStatus::Failed => bail!(
+                "Apply {} has failed: {}",
+                apply_id,
+                staged.error.unwrap_or_default()
+            ),
+            ApplyStatus::Pending => {
+                log::info!(
+                    "Resuming apply {} of version {}",
+                    apply_id,
+                    staged.version_id
+                );
+                let staged_request =
+                    ApplyRequest::decode(&*staged.request).context("Corrupted apply request")?;
+                lock_apply(&server, &apply_id, &ApplyLock::of_apply(&request)).await?;
+                request = staged_request;
+            }
+        }
+    } else {
+        meta.stage_apply(&apply_id, &request.version_id, &request.encode_to_vec())
+            .await?;
+        if let Err(err) = lock_apply(&server, &apply_id, &ApplyLock::of_apply(&request)).await {
+            meta.fail_staged_apply(&apply_id, &format!("{:?}", err))
+                .await?;
+            return Err(err);
+        }
+    }
+
+    let result = apply(server.clone(), request, Some(&apply_id)).await;
+    // the apply may have changed the tables (and the policies), so all cached queries are stale
+    server.query_engine.clear_query_cache();
+    let unlocked = meta.unlock_apply(&apply_id).await;
+    if let Err(ref err) = result {
+        meta.fail_staged_apply(&apply_id, &format!("{:?}", err))
+            .await?;
+    }
+    unlocked?;
+    if let Err(err) = meta
+        .remove_stale_applies(server.opt.apply_retention_s)
+        .await
+    {
+        log::warn!("Could not remove stale applies: {:?}", err);
+    }
+    if let Err(err) = apply::remove_expired_archives(&server).await {
+        log::warn!("Could not remove expired archives: {:?}", err);
+    }
+    result
+}
+
+/// How often an apply that waits for the apply lock checks whether the lock was released.
+const APPLY_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Replaces the policies of a running version. The workers of the version pick up the new policies
+/// before they handle their next request, so the modules don't need to be recompiled and the
+/// workers don't need to be restarted.
+async fn apply_policy_only(
+    server: &Server,
+    request: ApplyPolicyOnlyRequest,
+) -> Result<ApplyPolicyOnlyResponse> {
+    let version_id = validate_version_id(&request.version_id)?;
+    let version = server.trunk.get_version(&version_id).with_context(|| {
+        format!(
+            "Version `{}` is not running, use `chisel apply`",
+            version_id
+        )
+    })?;
+
+    let lock = ApplyLock {
+        holder: &request.lock_holder,
+        timeout_s: request.lock_timeout_s,
+        force_unlock: request.force_unlock,
+    };
+    let lock_id = Uuid::new_v4().to_string();
+    lock_apply(server, &lock_id, &lock).await?;
+    let result = apply::apply_policies(server, &version_id, &request.policies).await;
+    server.meta_service.unlock_apply(&lock_id).await?;
+    let result = result?;
+
+    version.update_policies(Arc::new(result.policy_system), result.policy_sources);
+    // cached queries may have the old policies baked in
+    server.query_engine.clear_query_cache();
+    log::info!("Applied policies of version {}", version_id);
+    Ok(ApplyPolicyOnlyResponse {
+        labels: result.labels,
+    })
+}
+
+/// Parameters of the apply lock that are passed by the client.
+struct ApplyLock<'a> {
+    holder: &'a str,
+    timeout_s: u32,
+    force_unlock: bool,
+}
+
+impl<'a> ApplyLock<'a> {
+    fn of_apply(request: &'a ApplyRequest) -> Self {
+        Self {
+            holder: &request.lock_holder,
+            timeout_s: request.lock_timeout_s,
+            force_unlock: request.force_unlock,
+        }
+    }
+}
+
+/// Acquires the apply lock for apply `lock_id`, waiting up to `params.timeout_s` for concurrent
+/// applies to finish.
+async fn lock_apply(server: &Server, lock_id: &str, params: &ApplyLock<'_>) -> Result<()> {
+    let meta = &server.meta_service;
+    if params.force_unlock {
+        if let Some(lock) = meta.force_unlock_apply().await? {
+            log::warn!(
+                "Removed apply lock held by {} since {}",
+                lock.holder,
+                format_timestamp(lock.acquired_at)
+            );
+        }
+    }
+
+    let holder = match params.holder {
+        "" => "unknown client",
+        holder => holder,
+    };
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(params.timeout_s.into());
+    let mut waiting = false;
+    loop {
+        let lock = match meta.try_lock_apply(lock_id, holder).await? {
+            Some(lock) => lock,
+            None => return Ok(()),
+        };
+        if tokio::time::Instant::now() >= deadline {
+            bail!(
+                "Another apply is in progress by {} since {}. If that apply is stuck, remove its lock with `chisel apply --force-unlock`",
+                lock.holder,
+                format_timestamp(lock.acquired_at)
+            );
+        }
+        if !waiting {
+            log::info!(
+                "Apply by {} is waiting for the apply by {} to finish",
+                holder,
+                lock.holder
+            );
+            waiting = true;
+        }
+        tokio::time::sleep(APPLY_LOCK_POLL_INTERVAL).await;
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    match time::OffsetDateTime::from_unix_timestamp(timestamp) {
+        Ok(time) => time.to_string(),
+        Err(_) => timestamp.to_string(),
+    }
+}
+
+async fn apply(
+    server: Arc<Server>,
+    request: ApplyRequest,
+    apply_id: Option<&str>,
+) -> Result<ApplyResponse> {
     let version_id = validate_version_id(&request.version_id)?;
     let info = VersionInfo {
         name: request.app_name.clone(),
         tag: request.version_tag.clone(),
     };
+    let stable_version_id = match request.canary_percentage {
+        Some(percentage) if percentage != 0.0 => Some(canary_stable_version(
+            &server,
+            &version_id,
+            &request,
+            percentage,
+        )?),
+        _ => None,
+    };
 
     let modules = request
         .modules
@@ -181,11 +801,17 @@ async fn apply(server: Arc<Server>, request: ApplyRequest) -> Result<ApplyRespon
         .map(|m| (m.url.clone(), m.code.clone()))
         .collect::<HashMap<_, _>>();
     let modules = Arc::new(modules);
+    let static_files = collect_static_files(&request)?;
+    let allowed_hosts = collect_allowed_hosts(&request)?;
+    let import_map = collect_import_map(&request)?;
+    let worker_pool = collect_worker_pool(&server, &request)?;
     validate_modules(
         server.clone(),
         version_id.clone(),
         info.clone(),
         modules.clone(),
+        allowed_hosts.clone(),
+        import_map.clone(),
     )
     .await
     .context("The provided code does not seem to work")?;
@@ -201,13 +827,16 @@ async fn apply(server: Arc<Server>, request: ApplyRequest) -> Result<ApplyRespon
         apply::apply(
             server.clone(),
             &request,
+            apply_id,
             type_system,
             version_id.clone(),
             &info,
             &modules,
+            &static_files,
         )
         .await?
     };
+    let mut response = result.response();
 
     let (ready_tx, ready_rx) = oneshot::channel();
     let init = VersionInit {
@@ -215,9 +844,12 @@ async fn apply(server: Arc<Server>, request: ApplyRequest) -> Result<ApplyRespon
         info,
         server: server.clone(),
         modules,
+        static_files: Arc::new(StaticFiles::new(static_files)),
+        allowed_hosts: Some(allowed_hosts),
+        import_map,
         type_system: Arc::new(result.type_system),
         policy_system: Arc::new(result.policy_system),
-        worker_count: server.opt.worker_threads,
+        worker_pool,
         ready_tx,
         is_canary: false,
         policy_sources: result.policy_sources,
@@ -231,6 +863,22 @@ async fn apply(server: Arc<Server>, request: ApplyRequest) -> Result<ApplyRespon
         )?;
     server.trunk.add_version(version, job_tx, version_task);
 
+    if let Some(percentage) = request.canary_percentage {
+        match stable_version_id {
+            Some(stable_version_id) => {
+                let split = TrafficSplit::new(version_id.clone(), percentage / 100.0);
+                server
+                    .trunk
+                    .set_traffic_split(stable_version_id.clone(), split);
+                response.canary_of = stable_version_id;
+                response.canary_percentage = percentage;
+            }
+            None => {
+                server.trunk.remove_traffic_split_to(&version_id);
+            }
+        }
+    }
+
     // try to update the secrets, so that if the user edited `.env`, the updated version will see
     // the new secrets immediately (this is in particular importance for tests).
     //
@@ -238,11 +886,144 @@ async fn apply(server: Arc<Server>, request: ApplyRequest) -> Result<ApplyRespon
     // responsible for periodic updating of the secrets, will show the error
     let _: Result<()> = server::update_secrets(&server).await;
 
-    Ok(ApplyResponse {
-        types: result.type_names_user_order,
-        labels: result.labels,
-        event_handlers: Vec::new(),
-    })
+    Ok(response)
+}
+
+/// Computes the plan of an apply without changing anything: the code is validated like in a real
+/// apply, and the changes of the database are computed in a transaction that is rolled back.
+async fn dry_run_apply(server: Arc<Server>, request: ApplyRequest) -> Result<ApplyResponse> {
+    let version_id = validate_version_id(&request.version_id)?;
+    let info = VersionInfo {
+        name: request.app_name.clone(),
+        tag: request.version_tag.clone(),
+    };
+    let modules = request
+        .modules
+        .iter()
+        .map(|m| (m.url.clone(), m.code.clone()))
+        .collect::<HashMap<_, _>>();
+    let modules = Arc::new(modules);
+    let static_files = collect_static_files(&request)?;
+    let allowed_hosts = collect_allowed_hosts(&request)?;
+    let import_map = collect_import_map(&request)?;
+    collect_worker_pool(&server, &request)?;
+    validate_modules(
+        server.clone(),
+        version_id.clone(),
+        info.clone(),
+        modules.clone(),
+        allowed_hosts,
+        import_map,
+    )
+    .await
+    .context("The provided code does not seem to work")?;
+
+    // the apply works on a copy, so the type system of the running version is left untouched
+    let mut type_system = server
+        .type_systems
+        .lock()
+        .await
+        .get(&version_id)
+        .cloned()
+        .unwrap_or_else(|| TypeSystem::new(server.builtin_types.clone(), version_id.clone()));
+    let result = apply::apply(
+        server.clone(),
+        &request,
+        None,
+        &mut type_system,
+        version_id,
+        &info,
+        &modules,
+        &static_files,
+    )
+    .await?;
+    Ok(result.response())
+}
+
+/// Returns the static files of an apply request, keyed by their path.
+fn collect_static_files(request: &ApplyRequest) -> Result<HashMap<String, Vec<u8>>> {
+    request
+        .static_files
+        .iter()
+        .map(|file| {
+            static_files::validate_path(&file.path)?;
+            Ok((file.path.clone(), file.content.clone()))
+        })
+        .collect()
+}
+
+/// Returns the hosts that the version of an apply request may connect to.
+fn collect_allowed_hosts(request: &ApplyRequest) -> Result<Vec<String>> {
+    for host in request.allowed_hosts.iter() {
+        worker::validate_allowed_host(host)?;
+    }
+    Ok(request.allowed_hosts.clone())
+}
+
+/// Returns the import map that resolves the bare specifiers of the modules of an apply request.
+fn collect_import_map(request: &ApplyRequest) -> Result<Arc<ImportMap>> {
+    let import_map = ImportMap::new(request.import_map.clone()).context("Invalid import map")?;
+    Ok(Arc::new(import_map))
+}
+
+/// Returns the worker pool of the version of an apply request, which overrides the defaults of
+/// the server.
+fn collect_worker_pool(server: &Server, request: &ApplyRequest) -> Result<WorkerPoolConfig> {
+    let overrides = request
+        .worker_pool
+        .as_ref()
+        .map(WorkerPoolOverrides::from)
+        .unwrap_or_default();
+    server.worker_pool.with_overrides(&overrides)
+}
+
+/// Finds the version whose requests should be split with the canary version `version_id`.
+fn canary_stable_version(
+    server: &Server,
+    version_id: &str,
+    request: &ApplyRequest,
+    percentage: f64,
+) -> Result<String> {
+    ensure!(
+        (0.0..=100.0).contains(&percentage),
+        "Canary percentage must be between 0 and 100, got {}",
+        percentage
+    );
+
+    if !request.canary_of.is_empty() {
+        ensure!(
+            server.trunk.get_version(&request.canary_of).is_some(),
+            "Version {:?} does not exist",
+            request.canary_of
+        );
+        ensure!(
+            request.canary_of != version_id,
+            "Version {:?} cannot be a canary of itself",
+            version_id
+        );
+        return Ok(request.canary_of.clone());
+    }
+
+    let mut candidates = server
+        .trunk
+        .list_versions()
+        .into_iter()
+        .filter(|version| version.version_id != version_id && version.info.name == request.app_name)
+        .map(|version| version.version_id.clone())
+        .collect::<Vec<_>>();
+    candidates.sort_unstable();
+    match candidates.as_slice() {
+        [stable_version_id] => Ok(stable_version_id.clone()),
+        [] => bail!(
+            "There is no other version of {:?} to split the requests with",
+            request.app_name
+        ),
+        _ => bail!(
+            "There are multiple versions of {:?} ({}), choose one with `--canary-of`",
+            request.app_name,
+            candidates.join(", ")
+        ),
+    }
 }
 
 async fn validate_modules(
@@ -250,6 +1031,8 @@ async fn validate_modules(
     version_id: String,
     info: VersionInfo,
     modules: Arc<HashMap<String, String>>,
+    allowed_hosts: Vec<String>,
+    import_map: Arc<ImportMap>,
 ) -> Result<()> {
     let type_system = TypeSystem::new(server.builtin_types.clone(), version_id.clone());
     let policy_system = PolicySystem::default();
@@ -260,9 +1043,12 @@ async fn validate_modules(
         info,
         server: server.clone(),
         modules,
+        static_files: Default::default(),
+        allowed_hosts: Some(allowed_hosts),
+        import_map,
         type_system: Arc::new(type_system),
         policy_system: Arc::new(policy_system),
-        worker_count: 1,
+        worker_pool: WorkerPoolConfig::fixed(1),
         ready_tx,
         is_canary: true,
         policy_sources: Default::default(),
@@ -314,7 +1100,7 @@ async fn delete(server: &Server, request: DeleteRequest) -> Result<DeleteRespons
 
     let meta = &server.meta_service;
     let mut transaction = meta.begin_transaction().await?;
-    meta.delete_policy_version(&mut transaction, &version.version_id)
+    meta.delete_version(&mut transaction, &version.version_id)
         .await?;
     for &entity in entities_to_remove.iter() {
         meta.remove_type(&mut transaction, entity).await?;
@@ -326,7 +1112,11 @@ async fn delete(server: &Server, request: DeleteRequest) -> Result<DeleteRespons
     for &entity in entities_to_remove.iter() {
         query_engine.drop_table(&mut transaction, entity).await?;
     }
+    query_engine
+        .drop_kv_table(&mut transaction, &version.version_id)
+        .await?;
     QueryEngine::commit_transaction(transaction).await?;
+    query_engine.clear_query_cache();
 
     let message = format!("Deleted {:?}", version.version_id);
     Ok(DeleteResponse { message })
@@ -354,11 +1144,435 @@ async fn populate(server: &Server, request: PopulateRequest) -> Result<PopulateR
         &from_version.type_system,
     )
     .await?;
+    server.query_engine.clear_query_cache();
 
     let message = "OK".to_string();
     Ok(PopulateResponse { message })
 }
 
+fn mirror(server: &Server, request: MirrorRequest) -> Result<MirrorResponse> {
+    ensure!(
+        (0.0..=100.0).contains(&request.percentage),
+        "Percentage must be between 0 and 100, got {}",
+        request.percentage
+    );
+
+    if request.percentage == 0.0 {
+        let message = match server.trunk.remove_mirror(&request.from_version_id) {
+            Some(_) => format!("Stopped mirroring {:?}", request.from_version_id),
+            None => format!("Version {:?} is not mirrored", request.from_version_id),
+        };
+        return Ok(MirrorResponse { message });
+    }
+
+    for version_id in [&request.from_version_id, &request.to_version_id] {
+        ensure!(
+            server.trunk.get_version(version_id).is_some(),
+            "Version {:?} does not exist",
+            version_id
+        );
+    }
+    ensure!(
+        request.from_version_id != request.to_version_id,
+        "Cannot mirror version {:?} to itself",
+        request.from_version_id
+    );
+
+    let mirror = Mirror::new(request.to_version_id.clone(), request.percentage / 100.0);
+    server
+        .trunk
+        .set_mirror(request.from_version_id.clone(), mirror);
+    let message = format!(
+        "Mirroring {}% of requests from {:?} to {:?}",
+        request.percentage, request.from_version_id, request.to_version_id
+    );
+    Ok(MirrorResponse { message })
+}
+
+fn mirror_status(server: &Server) -> MirrorStatusResponse {
+    let avg_ms = |total: Duration, count: u64| {
+        if count == 0 {
+            0.0
+        } else {
+            total.as_secs_f64() * 1000.0 / count as f64
+        }
+    };
+
+    let mut mirrors = server
+        .trunk
+        .list_mirrors()
+        .into_iter()
+        .map(|(from_version_id, mirror)| {
+            let stats = mirror.stats();
+            MirrorStatus {
+                from_version_id,
+                to_version_id: mirror.target_version_id.clone(),
+                percentage: mirror.fraction * 100.0,
+                mirrored: stats.mirrored,
+                from_errors: stats.source_errors,
+                to_errors: stats.target_errors,
+                status_mismatches: stats.status_mismatches,
+                from_latency_avg_ms: avg_ms(stats.source_latency, stats.mirrored),
+                to_latency_avg_ms: avg_ms(stats.target_latency, stats.mirrored),
+            }
+        })
+        .collect::<Vec<_>>();
+    mirrors.sort_unstable_by(|x, y| x.from_version_id.cmp(&y.from_version_id));
+    MirrorStatusResponse { mirrors }
+}
+
+fn data_diff_entities(server: &Server, request: &DataDiffRequest) -> Result<(Entity, Entity, f64)> {
+    ensure!(
+        (0.0..=100.0).contains(&request.sample_percentage),
+        "Sample percentage must be between 0 and 100, got {}",
+        request.sample_percentage
+    );
+    let fraction = match request.sample_percentage {
+        p if p == 0.0 => 1.0,
+        p => p / 100.0,
+    };
+
+    let lookup = |version_id: &str| {
+        let version = server
+            .trunk
+            .get_version(version_id)
+            .with_context(|| format!("Version {:?} does not exist", version_id))?;
+        version
+            .type_system
+            .lookup_entity(&request.entity_name)
+            .with_context(|| {
+                format!(
+                    "Entity {:?} does not exist in version {:?}",
+                    request.entity_name, version_id
+                )
+            })
+    };
+    let from = lookup(&request.from_version_id)?;
+    let to = lookup(&request.to_version_id)?;
+    Ok((from, to, fraction))
+}
+
+async fn secrets(server: &Server, request: SecretsRequest) -> Result<SecretsResponse> {
+    let meta = &server.meta_service;
+    match secrets_request::Action::from_i32(request.action) {
+        Some(secrets_request::Action::List) => {}
+        Some(secrets_request::Action::Set) => {
+            ensure!(!request.name.is_empty(), "Secret name cannot be empty");
+            let value: serde_json::Value = serde_json::from_str(&request.value)
+                .context("Value of the secret is not valid JSON")?;
+            let version = meta.set_secret(&request.name, Some(&value)).await?;
+            log::info!("Secret {} was set (version {})", request.name, version);
+            server::refresh_stored_secrets(server).await?;
+        }
+        Some(secrets_request::Action::Unset) => {
+            let version = meta.set_secret(&request.name, None).await?;
+            log::info!("Secret {} was unset (version {})", request.name, version);
+            server::refresh_stored_secrets(server).await?;
+        }
+        None => bail!("Unknown secrets action {}", request.action),
+    }
+
+    let stored = meta.load_secrets().await?;
+    let file_secrets = server.file_secrets.read().clone();
+    let mut secrets = stored
+        .into_iter()
+        .map(|secret| SecretInfo {
+            in_file: file_secrets.contains_key(&secret.name),
+            name: secret.name,
+            version: secret.version,
+            updated_at: secret.updated_at,
+            is_set: secret.value.is_some(),
+        })
+        .collect::<Vec<_>>();
+    for name in file_secrets.keys() {
+        if !secrets.iter().any(|secret| secret.name == *name) {
+            secrets.push(SecretInfo {
+                name: name.clone(),
+                in_file: true,
+                ..Default::default()
+            });
+        }
+    }
+    secrets.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+    Ok(SecretsResponse { secrets })
+}
+
+async fn data_query(server: &Server, request: DataQueryRequest) -> Result<DataQueryResponse> {
+    let entity = if request.archived {
+        let archived = server
+            .meta_service
+            .load_archived_entities()
+            .await?
+            .into_iter()
+            .find(|a| a.version_id == request.version_id && a.entity_name == request.entity_name)
+            .with_context(|| {
+                format!(
+                    "Entity {:?} was not archived in version {:?}",
+                    request.entity_name, request.version_id
+                )
+            })?;
+        Entity::Custom(Arc::new(archived.object_type(&server.builtin_types)?))
+    } else {
+        let version = server
+            .trunk
+            .get_version(&request.version_id)
+            .with_context(|| format!("Version {:?} does not exist", request.version_id))?;
+        version
+            .type_system
+            .lookup_custom_type(&request.entity_name)
+            .with_context(|| {
+                format!(
+                    "Entity {:?} does not exist in version {:?}",
+                    request.entity_name, request.version_id
+                )
+            })?
+    };
+
+    let engine = &server.query_engine;
+    let txn = engine.begin_transaction_static().await?;
+    let mut rows = vec![];
+    let mut row_stream = engine.query(txn.clone(), QueryPlan::from_type(&entity))?;
+    while let Some(row) = row_stream.next().await {
+        if request.limit != 0 && rows.len() >= request.limit as usize {
+            break;
+        }
+        let row = row.with_context(|| format!("Could not read rows of {}", entity.name()))?;
+        rows.push(serde_json::to_string(&row)?);
+    }
+    drop(row_stream);
+    QueryEngine::commit_transaction_static(txn).await?;
+    Ok(DataQueryResponse { rows })
+}
+
+/// Looks up the entities to export: the requested entities, or all entities of the version sorted
+/// by name.
+fn export_entities(server: &Server, request: &ExportDataRequest) -> Result<Vec<Entity>> {
+    let version = server
+        .trunk
+        .get_version(&request.version_id)
+        .with_context(|| format!("Version {:?} does not exist", request.version_id))?;
+    let type_system = &version.type_system;
+    if request.entity_names.is_empty() {
+        let mut entities = type_system
+            .custom_types
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        entities.sort_unstable_by(|x, y| x.name().cmp(y.name()));
+        return Ok(entities);
+    }
+    request
+        .entity_names
+        .iter()
+        .map(|entity_name| {
+            type_system
+                .lookup_custom_type(entity_name)
+                .with_context(|| {
+                    format!(
+                        "Entity {:?} does not exist in version {:?}",
+                        entity_name, request.version_id
+                    )
+                })
+        })
+        .collect()
+}
+
+async fn import_data(
+    server: &Server,
+    mut stream: Streaming<ImportDataRequest>,
+) -> Result<ImportDataResponse> {
+    let first = match stream.message().await? {
+        Some(first) => first,
+        None => return Ok(ImportDataResponse::default()),
+    };
+    let version = server
+        .trunk
+        .get_version(&first.version_id)
+        .with_context(|| format!("Version {:?} does not exist", first.version_id))?;
+
+    let mut importer =
+        Importer::new(&server.query_engine, &version.type_system, first.replace).await?;
+    let mut message = Some(first);
+    while let Some(request) = message {
+        if request.format == DataFormat::Csv as i32 {
+            importer
+                .import_csv(&request.entity_name, &request.csv)
+                .await?;
+        } else {
+            importer
+                .import_rows(&request.entity_name, &request.rows)
+                .await?;
+        }
+        message = stream.message().await?;
+    }
+
+    let entities = importer
+        .finish()
+        .await?
+        .into_iter()
+        .map(|(entity_name, rows)| ImportedEntity { entity_name, rows })
+        .collect();
+    Ok(ImportDataResponse { entities })
+}
+
+async fn version_state(
+    server: &Server,
+    request: VersionStateRequest,
+) -> Result<VersionStateResponse> {
+    let version = server
+        .trunk
+        .get_version(&request.version_id)
+        .with_context(|| format!("Version {:?} does not exist", request.version_id))?;
+
+    let mut indexes = version
+        .type_system
+        .custom_types
+        .values()
+        .flat_map(|entity| {
+            entity.indexes().iter().map(|index| IndexDefinition {
+                entity_name: entity.name().to_owned(),
+                fields: index.fields.clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+    indexes.sort_unstable_by(|x, y| (&x.entity_name, &x.fields).cmp(&(&y.entity_name, &y.fields)));
+
+    let mut routes = version
+        .routes
+        .read()
+        .iter()
+        .map(|route| route.path_pattern.clone())
+        .collect::<Vec<_>>();
+    routes.sort_unstable();
+    routes.dedup();
+
+    let mut modules = version
+        .modules
+        .iter()
+        .map(|(url, code)| ProtoModule {
+            url: url.clone(),
+            code: code.clone(),
+        })
+        .collect::<Vec<_>>();
+    modules.sort_unstable_by(|x, y| x.url.cmp(&y.url));
+
+    let policy_yaml = server
+        .meta_service
+        .load_policy_yaml(&version.version_id)
+        .await?;
+    let type_policies = version
+        .policies()
+        .sources
+        .iter()
+        .map(|(entity_name, code)| {
+            (
+                entity_name.clone(),
+                String::from_utf8_lossy(code).into_owned(),
+            )
+        })
+        .collect();
+
+    Ok(VersionStateResponse {
+        type_defs: type_definitions(&version),
+        indexes,
+        routes,
+        modules,
+        policy_yaml,
+        type_policies,
+    })
+}
+
+async fn index_suggestions(
+    server: &Server,
+    request: IndexSuggestionsRequest,
+) -> Result<IndexSuggestionsResponse> {
+    let version = server
+        .trunk
+        .get_version(&request.version_id)
+        .with_context(|| format!("Version {:?} does not exist", request.version_id))?;
+    let suggestions = server
+        .query_engine
+        .index_suggestions(&version.type_system)
+        .await?
+        .into_iter()
+        .map(|suggestion| ProtoIndexSuggestion {
+            entity_name: suggestion.entity_name,
+            fields: suggestion.fields,
+            query_count: suggestion.query_count,
+            row_count: suggestion.row_count,
+            estimated_rows_saved: suggestion.estimated_rows_saved,
+        })
+        .collect();
+    Ok(IndexSuggestionsResponse { suggestions })
+}
+
+async fn check_counts(server: &Server, request: CheckCountsRequest) -> Result<CheckCountsResponse> {
+    let version = server
+        .trunk
+        .get_version(&request.version_id)
+        .with_context(|| format!("Version {:?} does not exist", request.version_id))?;
+    let counters = version.type_system.counters();
+
+    let engine = &server.query_engine;
+    let mut txn = engine.begin_transaction().await?;
+    let mut mismatches = vec![];
+    for counter in counters.iter() {
+        let counter_mismatches = engine.check_counter(&mut txn, counter).await?;
+        if request.fix && !counter_mismatches.is_empty() {
+            engine.backfill_counter(&mut txn, counter).await?;
+        }
+        mismatches.extend(
+            counter_mismatches
+                .into_iter()
+                .map(|mismatch| ProtoCountMismatch {
+                    entity_name: counter.counting.name().to_owned(),
+                    field_name: counter.field.clone(),
+                    id: mismatch.id,
+                    stored: mismatch.stored,
+                    actual: mismatch.actual,
+                }),
+        );
+    }
+    QueryEngine::commit_transaction(txn).await?;
+    if request.fix && !mismatches.is_empty() {
+        engine.clear_query_cache();
+    }
+    Ok(CheckCountsResponse {
+        checked: counters.len() as u32,
+        mismatches,
+    })
+}
+
+fn row_diff_to_proto(row_diff: DataRowDiff) -> RowDiff {
+    let to_json = |value: Option<EntityValue>| {
+        value.map(|v| serde_json::to_string(&v).unwrap_or_else(|_| format!("{:?}", v)))
+    };
+    match row_diff {
+        DataRowDiff::Changed { id, fields } => RowDiff {
+            id,
+            kind: row_diff::Kind::Changed as i32,
+            fields: fields
+                .into_iter()
+                .map(|field| FieldDiff {
+                    field_name: field.name,
+                    from_value: to_json(field.from),
+                    to_value: to_json(field.to),
+                })
+                .collect(),
+        },
+        DataRowDiff::OnlyInFrom { id } => RowDiff {
+            id,
+            kind: row_diff::Kind::OnlyInFrom as i32,
+            fields: vec![],
+        },
+        DataRowDiff::OnlyInTo { id } => RowDiff {
+            id,
+            kind: row_diff::Kind::OnlyInTo as i32,
+            fields: vec![],
+        },
+    }
+}
+
 fn validate_version_id(version_id: &str) -> Result<String> {
     ensure!(
         version_id != "__chiselstrike",
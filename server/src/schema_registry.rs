@@ -0,0 +1,271 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Client of a Confluent Schema Registry, used to decode and encode Kafka events.
+//!
+//! Payloads are in the Confluent wire format: a zero "magic" byte, the id of the schema as a 32-bit
+//! big-endian integer, and then the payload encoded with that schema. Avro payloads are decoded
+//! into JSON (and encoded from JSON) using the schema from the registry; JSON Schema payloads are
+//! plain JSON, so they are not validated against the schema.
+
+use anyhow::{bail, ensure, Context, Result};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The first byte of every payload in the Confluent wire format.
+const MAGIC_BYTE: u8 = 0;
+
+/// Length of the header of the Confluent wire format (magic byte and schema id).
+const HEADER_LEN: usize = 5;
+
+/// How long we use the latest schema of a subject before we ask the registry again. Schemas are
+/// immutable, so schemas looked up by id are cached forever.
+const SUBJECT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct RegisteredSchema {
+    pub id: u32,
+    pub kind: SchemaKind,
+}
+
+#[derive(Debug)]
+pub enum SchemaKind {
+    Avro(apache_avro::Schema),
+    Json,
+}
+
+/// Schema as returned by the registry.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SchemaResponse {
+    /// Only present when looking up the schema of a subject.
+    id: Option<u32>,
+    schema: String,
+    /// Missing for Avro schemas.
+    schema_type: Option<String>,
+}
+
+pub struct SchemaRegistry {
+    /// Base URL of the registry. Credentials for basic authentication can be given in the URL.
+    url: url::Url,
+    client: reqwest::Client,
+    by_id: Mutex<HashMap<u32, Arc<RegisteredSchema>>>,
+    by_subject: Mutex<HashMap<String, (Instant, Arc<RegisteredSchema>)>>,
+}
+
+impl SchemaRegistry {
+    pub fn new(url: &str) -> Result<SchemaRegistry> {
+        let parsed = url::Url::parse(url)
+            .with_context(|| format!("Invalid schema registry URL {:?}", url))?;
+        ensure!(
+            matches!(parsed.scheme(), "http" | "https") && !parsed.cannot_be_a_base(),
+            "Schema registry URL {:?} must be a http or https URL",
+            url
+        );
+        Ok(SchemaRegistry {
+            url: parsed,
+            client: reqwest::Client::new(),
+            by_id: Default::default(),
+            by_subject: Default::default(),
+        })
+    }
+
+    /// Decodes a payload in the Confluent wire format into JSON.
+    pub async fn decode(&self, payload: &[u8]) -> Result<JsonValue> {
+        let (id, data) = split_header(payload)?;
+        let schema = self.schema_by_id(id).await?;
+        decode_data(&schema, data)
+            .with_context(|| format!("Could not decode payload with schema {}", id))
+    }
+
+    /// Encodes a JSON value with the latest schema of `subject`, in the Confluent wire format.
+    pub async fn encode(&self, subject: &str, value: JsonValue) -> Result<Vec<u8>> {
+        let schema = self.schema_by_subject(subject).await?;
+        encode_value(&schema, value).with_context(|| {
+            format!(
+                "Could not encode value with schema {} of subject {:?}",
+                schema.id, subject
+            )
+        })
+    }
+
+    async fn schema_by_id(&self, id: u32) -> Result<Arc<RegisteredSchema>> {
+        if let Some(schema) = self.by_id.lock().get(&id) {
+            return Ok(schema.clone());
+        }
+        let response = self.fetch(&["schemas", "ids", &id.to_string()]).await?;
+        let schema = Arc::new(parse_schema(id, response)?);
+        self.by_id.lock().insert(id, schema.clone());
+        Ok(schema)
+    }
+
+    async fn schema_by_subject(&self, subject: &str) -> Result<Arc<RegisteredSchema>> {
+        if let Some((fetched_at, schema)) = self.by_subject.lock().get(subject) {
+            if fetched_at.elapsed() < SUBJECT_CACHE_TTL {
+                return Ok(schema.clone());
+            }
+        }
+        let response = self
+            .fetch(&["subjects", subject, "versions", "latest"])
+            .await?;
+        let id = match response.id {
+            Some(id) => id,
+            None => bail!(
+                "Schema registry did not return the id of subject {:?}",
+                subject
+            ),
+        };
+        let schema = Arc::new(parse_schema(id, response)?);
+        self.by_id.lock().insert(id, schema.clone());
+        self.by_subject
+            .lock()
+            .insert(subject.into(), (Instant::now(), schema.clone()));
+        Ok(schema)
+    }
+
+    async fn fetch(&self, segments: &[&str]) -> Result<SchemaResponse> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(segments);
+        let path = segments.join("/");
+        let response = self
+            .client
+            .get(url)
+            .header("Accept", "application/vnd.schemaregistry.v1+json")
+            .send()
+            .await
+            .with_context(|| format!("Could not connect to schema registry at {}", self.url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!(
+                "Schema registry request {} failed with {}: {}",
+                path,
+                status,
+                body
+            );
+        }
+        response
+            .json()
+            .await
+            .with_context(|| format!("Invalid response from schema registry for {}", path))
+    }
+}
+
+fn parse_schema(id: u32, response: SchemaResponse) -> Result<RegisteredSchema> {
+    let kind = match response.schema_type.as_deref().unwrap_or("AVRO") {
+        "AVRO" => SchemaKind::Avro(
+            apache_avro::Schema::parse_str(&response.schema)
+                .with_context(|| format!("Invalid Avro schema {}", id))?,
+        ),
+        "JSON" => SchemaKind::Json,
+        other => bail!("Schema {} has unsupported type {}", id, other),
+    };
+    Ok(RegisteredSchema { id, kind })
+}
+
+/// Splits a payload in the Confluent wire format into the schema id and the encoded data.
+fn split_header(payload: &[u8]) -> Result<(u32, &[u8])> {
+    ensure!(
+        payload.len() >= HEADER_LEN && payload[0] == MAGIC_BYTE,
+        "Payload is not in the schema registry wire format"
+    );
+    let id = u32::from_be_bytes(payload[1..HEADER_LEN].try_into().unwrap());
+    Ok((id, &payload[HEADER_LEN..]))
+}
+
+fn decode_data(schema: &RegisteredSchema, mut data: &[u8]) -> Result<JsonValue> {
+    match &schema.kind {
+        SchemaKind::Avro(avro_schema) => {
+            let value = apache_avro::from_avro_datum(avro_schema, &mut data, None)?;
+            Ok(JsonValue::try_from(value)?)
+        }
+        SchemaKind::Json => Ok(serde_json::from_slice(data)?),
+    }
+}
+
+fn encode_value(schema: &RegisteredSchema, value: JsonValue) -> Result<Vec<u8>> {
+    let mut payload = vec![MAGIC_BYTE];
+    payload.extend_from_slice(&schema.id.to_be_bytes());
+    match &schema.kind {
+        SchemaKind::Avro(avro_schema) => {
+            let value = apache_avro::types::Value::from(value).resolve(avro_schema)?;
+            payload.extend(apache_avro::to_avro_datum(avro_schema, value)?);
+        }
+        SchemaKind::Json => serde_json::to_writer(&mut payload, &value)?,
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn avro_schema(id: u32) -> RegisteredSchema {
+        let schema = r#"{
+            "type": "record",
+            "name": "Person",
+            "fields": [
+                {"name": "name", "type": "string"},
+                {"name": "age", "type": "int"},
+                {"name": "email", "type": ["null", "string"], "default": null}
+            ]
+        }"#;
+        parse_schema(
+            id,
+            SchemaResponse {
+                id: Some(id),
+                schema: schema.into(),
+                schema_type: None,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn wire_format() {
+        assert_eq!(
+            split_header(&[0, 0, 0, 1, 2, 42]).unwrap(),
+            (258, &[42u8][..])
+        );
+        assert!(split_header(&[0, 0, 0]).is_err());
+        assert!(split_header(&[1, 0, 0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn avro_roundtrip() {
+        let schema = avro_schema(7);
+        let person = json!({"name": "Alice", "age": 30, "email": "alice@example.com"});
+        let payload = encode_value(&schema, person.clone()).unwrap();
+        assert_eq!(&payload[..HEADER_LEN], &[0, 0, 0, 0, 7]);
+
+        let (id, data) = split_header(&payload).unwrap();
+        assert_eq!(id, 7);
+        assert_eq!(decode_data(&schema, data).unwrap(), person);
+
+        let payload = encode_value(&schema, json!({"name": "Bob", "age": 20})).unwrap();
+        assert_eq!(
+            decode_data(&schema, &payload[HEADER_LEN..]).unwrap(),
+            json!({"name": "Bob", "age": 20, "email": null})
+        );
+
+        assert!(encode_value(&schema, json!({"name": "Bob"})).is_err());
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let schema = RegisteredSchema {
+            id: 3,
+            kind: SchemaKind::Json,
+        };
+        let value = json!({"a": [1, 2, {"b": null}]});
+        let payload = encode_value(&schema, value.clone()).unwrap();
+        assert_eq!(decode_data(&schema, &payload[HEADER_LEN..]).unwrap(), value);
+    }
+}
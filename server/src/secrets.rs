@@ -1,5 +1,6 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+use crate::datastore::StoredSecret;
 use crate::opt::Opt;
 use crate::JsonObject;
 use aes_gcm::aead::{Aead, NewAead};
@@ -134,6 +135,18 @@ pub async fn get_secrets(opt: &Opt) -> Result<JsonObject> {
     Ok(secrets)
 }
 
+/// Merges the secrets from the database into the secrets from the file. Secrets that were unset in
+/// the database fall back to the file.
+pub fn merge_secrets(file_secrets: &JsonObject, stored: &[StoredSecret]) -> JsonObject {
+    let mut secrets = file_secrets.clone();
+    for secret in stored {
+        if let Some(value) = &secret.value {
+            secrets.insert(secret.name.clone(), value.clone());
+        }
+    }
+    secrets
+}
+
 fn extract_secrets(private_key: &RsaPrivateKey, payload: &str) -> Result<JsonObject> {
     let decoded = decode_base64(payload)?;
     let payload: Payload = serde_json::from_slice(&decoded)?;
@@ -257,4 +270,24 @@ MSFoUvsxlBCwBc/KlwzO4lJ1/V8+U6aCEAQoLdGEZXyQq7WGyJg/
 
         assert_eq!(expected.as_object().unwrap(), &actual);
     }
+
+    #[test]
+    fn test_merge_secrets() {
+        let file_secrets = json!({"a": "file a", "b": "file b"});
+        let stored = |name: &str, value: Option<serde_json::Value>| StoredSecret {
+            name: name.into(),
+            version: 1,
+            value,
+            updated_at: 0,
+        };
+        let stored = [
+            stored("a", Some(json!("stored a"))),
+            stored("b", None),
+            stored("c", Some(json!({"x": 1}))),
+        ];
+        let actual = merge_secrets(file_secrets.as_object().unwrap(), &stored);
+
+        let expected = json!({"a": "stored a", "b": "file b", "c": {"x": 1}});
+        assert_eq!(expected.as_object().unwrap(), &actual);
+    }
 }
@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::server::Server;
+use crate::version::VersionJob;
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::sync::oneshot;
+
+/// A job that runs one seed script of a version (a file under `seeds/`, see `chisel seed`).
+#[derive(Debug)]
+pub struct SeedJob {
+    pub name: String,
+    /// Receives the outcome of the seed: the error message if the seed failed.
+    pub done_tx: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Debug, Default)]
+pub struct SeedsOutcome {
+    /// Seeds that were applied by this run.
+    pub applied: Vec<String>,
+    /// Seeds that had been applied before.
+    pub skipped: Vec<String>,
+    /// Error of the seed that failed and stopped the run.
+    pub error: Option<String>,
+}
+
+/// Runs the seed scripts of a version that have not been applied to the version yet, ordered by
+/// their names, and records them as applied. Every seed runs in its own transaction; the run stops
+/// at the first seed that fails, and that seed is not recorded, so it runs again next time. The
+/// seeds after the failed seed are neither applied nor skipped.
+pub async fn run_seeds(server: &Server, version_id: &str) -> Result<SeedsOutcome> {
+    let _lock = server.seed_lock.lock().await;
+    let trunk_version = server
+        .trunk
+        .get_trunk_version(version_id)
+        .with_context(|| format!("Version {:?} does not exist", version_id))?;
+    let mut names = trunk_version.version.seeds.read().clone();
+    names.sort_unstable();
+
+    let meta = &server.meta_service;
+    let applied_seeds = meta.load_applied_seeds(version_id).await?;
+    let mut outcome = SeedsOutcome::default();
+    for name in names.into_iter() {
+        if applied_seeds.contains(&name) {
+            outcome.skipped.push(name);
+            continue;
+        }
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let job = VersionJob::Seed(SeedJob {
+            name: name.clone(),
+            done_tx,
+        });
+        trunk_version
+            .job_tx
+            .send(job)
+            .await
+            .map_err(|_| anyhow!("Version {:?} is not running", version_id))?;
+        match done_rx.await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => {
+                outcome.error = Some(format!("Seed {:?} failed: {}", name, error));
+                break;
+            }
+            Err(_) => bail!("Seed {:?} was aborted", name),
+        }
+
+        meta.persist_applied_seed(version_id, &name).await?;
+        outcome.applied.push(name);
+    }
+    Ok(outcome)
+}
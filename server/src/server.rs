@@ -1,16 +1,29 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use crate::datastore::{DbConnection, MetaService, QueryEngine};
+use crate::authentication::JwtConfig;
+use crate::backup::BackupService;
+use crate::blob::{self, BlobStore};
+use crate::datastore::crud::PageLimits;
+use crate::datastore::tenancy::{add_tenant_columns, TenancyConfig};
+use crate::datastore::{
+    DbConnection, DbPoolOptions, GcReport, GcRetention, MetaService, QueryEngine,
+};
 use crate::internal::{mark_not_ready, mark_ready};
 use crate::kafka::{self, KafkaService};
-use crate::opt::Opt;
+use crate::oidc::OidcConfig;
+use crate::opt::{Opt, ReloadReport};
 use crate::policies::PolicySystem;
+use crate::rate_limit::RateLimiter;
+use crate::request_log::RequestLog;
+use crate::socket::SocketRegistry;
+use crate::static_files::StaticFiles;
 use crate::trunk::{self, Trunk};
 use crate::types::{BuiltinTypes, TypeSystem};
-use crate::version::{self, VersionInfo, VersionInit};
+use crate::version::{self, VersionInfo, VersionInit, WorkerAffinity};
+use crate::worker_pool::WorkerPoolConfig;
 use crate::Features;
-use crate::{http, internal, rpc, secrets, worker, JsonObject, FEATURES};
-use anyhow::{bail, Context, Result};
+use crate::{apply, http, internal, rpc, secrets, tenancy, worker, JsonObject, FEATURES};
+use anyhow::{bail, ensure, Context, Result};
 use futures::future::{Fuse, FutureExt};
 use parking_lot::RwLock;
 use regex::Regex;
@@ -19,13 +32,17 @@ use std::net::SocketAddr;
 use std::panic;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 use utils::TaskHandle;
 
 /// Global state of the server.
 pub struct Server {
+    /// Options that the server was started with.
     pub opt: Opt,
+    /// Current values of the options, which differ from `opt` if the configuration was reloaded.
+    /// Only the reloadable options are ever updated (see `Opt::reload_report()`).
+    pub current_opt: RwLock<Opt>,
     pub db: Arc<DbConnection>,
     pub query_engine: QueryEngine,
     pub meta_service: MetaService,
@@ -35,12 +52,43 @@ pub struct Server {
     /// Type system for each version (key is version id), should reflect the state of the "meta"
     /// database.
     pub type_systems: tokio::sync::Mutex<HashMap<String, TypeSystem>>,
-    /// Current secrets, they are periodically refreshed and rewritten.
+    /// Current secrets, they are periodically refreshed and rewritten. Secrets that were set with
+    /// `chisel secrets set` take precedence over the secrets from the secrets file.
     pub secrets: RwLock<JsonObject>,
+    /// Secrets from the secrets file, as they were last read.
+    pub file_secrets: RwLock<JsonObject>,
     /// Handle to an inspector server that allows debugging of JavaScript code from Chrome.
     pub inspector: Option<Arc<deno_runtime::inspector_server::InspectorServer>>,
     /// Trunk with versions ("branches").
     pub trunk: Trunk,
+    /// Open WebSocket connections.
+    pub sockets: SocketRegistry,
+    /// State of the rate limits of all versions.
+    pub rate_limiter: RateLimiter,
+    /// How HTTP requests are pinned to workers (parsed from `--worker-affinity`).
+    pub worker_affinity: Option<WorkerAffinity>,
+    /// Size and autoscaling of the worker pools of user versions (from `--worker-threads` and
+    /// `--max-worker-threads`).
+    pub worker_pool: WorkerPoolConfig,
+    /// Deadline of HTTP requests to user routes (from `--request-timeout-s`).
+    pub request_timeout: Option<Duration>,
+    /// Maximum size of the buffered bodies of HTTP requests (from `--max-body-size`).
+    pub max_body_size: u64,
+    /// Exporter of sampled request logs (from `--request-log-endpoint`).
+    pub request_log: Option<Arc<RequestLog>>,
+    /// Time (in milliseconds since the UNIX epoch) that was frozen by
+    /// `Chisel.testing.freezeTime()`; only used with `--testing`.
+    pub frozen_time_ms: RwLock<Option<f64>>,
+    /// Validation of the JWTs that authenticate the users (from `--jwt-issuer` and
+    /// `--jwt-jwks-url`).
+    pub jwt: JwtConfig,
+    /// Built-in login with an OpenID Connect provider (from `--oidc-issuer`), if enabled.
+    pub oidc: Option<OidcConfig>,
+    /// Storage of `Chisel.blob` (from `--blob-dir` or `--blob-s3-endpoint`), if enabled.
+    pub blob_store: Option<Arc<dyn BlobStore>>,
+    /// Serializes the runs of seed scripts, so that concurrent `chisel seed` calls don't run the
+    /// same seed twice.
+    pub seed_lock: tokio::sync::Mutex<()>,
 }
 
 pub async fn run(opt: Opt) -> Result<()> {
@@ -53,6 +101,7 @@ pub async fn run(opt: Opt) -> Result<()> {
 
     let features = Features {
         typescript_policies: opt.typescript_policies,
+        tenancy: TenancyConfig::from_opt(&opt)?,
     };
 
     FEATURES
@@ -60,7 +109,10 @@ pub async fn run(opt: Opt) -> Result<()> {
         .map_err(|_| ())
         .expect("features set twice!");
 
-    let (server, trunk_task) = make_server(opt).await?;
+    let (server, trunk_task, request_log_task) = make_server(opt).await?;
+    apply::remove_expired_archives(&server)
+        .await
+        .context("Could not remove expired archives")?;
     start_versions(server.clone()).await?;
     start_builtin_version(server.clone()).await?;
 
@@ -72,16 +124,39 @@ pub async fn run(opt: Opt) -> Result<()> {
         .await
         .context("Could not start HTTP API server")?;
 
-    let (internal_addr, internal_task) = internal::spawn(server.opt.internal_routes_listen_addr)
-        .await
-        .context("Could not start an internal HTTP server")?;
+    let backups = server.opt.backup_dir.clone().map(|backup_dir| {
+        Arc::new(BackupService::new(
+            server.db.clone(),
+            server.opt.db_uri.clone(),
+            backup_dir,
+        ))
+    });
+    let (internal_addr, internal_task) = internal::spawn(
+        server.opt.internal_routes_listen_addr,
+        server.query_engine.clone(),
+        backups,
+    )
+    .await
+    .context("Could not start an internal HTTP server")?;
 
     let kafka_task = match server.kafka_service.clone() {
         Some(service) => kafka::spawn(service).await?.fuse(),
         None => Fuse::terminated(),
     };
 
+    let request_log_task = match request_log_task {
+        Some(task) => task.fuse(),
+        None => Fuse::terminated(),
+    };
+
     let secrets_task = TaskHandle(tokio::task::spawn(refresh_secrets(server.clone())));
+    let reload_task = TaskHandle(tokio::task::spawn(reload_on_sighup(server.clone())));
+    let aggregates_task = TaskHandle(tokio::task::spawn(refresh_scheduled_aggregates(
+        server.clone(),
+    )));
+    let meta_gc_task = TaskHandle(tokio::task::spawn(collect_meta_garbage_periodically(
+        server.clone(),
+    )));
     let signal_task = TaskHandle(tokio::task::spawn(wait_for_signals()));
 
     info!("ChiselStrike server is ready 🚀");
@@ -90,7 +165,9 @@ pub async fn run(opt: Opt) -> Result<()> {
     }
     debug!("gRPC API address: {}", rpc_addr);
     debug!("Internal address: http://{}", internal_addr);
-    mark_ready();
+    let db_keepalive_task = TaskHandle(tokio::task::spawn(warm_up_db_and_keep_alive(
+        server.clone(),
+    )));
 
     let all_tasks = async move {
         tokio::try_join!(
@@ -99,7 +176,12 @@ pub async fn run(opt: Opt) -> Result<()> {
             http_task,
             internal_task,
             kafka_task,
-            secrets_task
+            request_log_task,
+            secrets_task,
+            reload_task,
+            aggregates_task,
+            meta_gc_task,
+            db_keepalive_task
         )
     };
     tokio::select! {
@@ -108,13 +190,51 @@ pub async fn run(opt: Opt) -> Result<()> {
     }
 }
 
-async fn make_server(opt: Opt) -> Result<(Arc<Server>, TaskHandle<Result<()>>)> {
-    let db = DbConnection::connect(&opt.db_uri, opt.nr_connections).await?;
+async fn make_server(
+    opt: Opt,
+) -> Result<(
+    Arc<Server>,
+    TaskHandle<Result<()>>,
+    Option<TaskHandle<Result<()>>>,
+)> {
+    let worker_affinity = opt
+        .worker_affinity
+        .as_deref()
+        .map(WorkerAffinity::parse)
+        .transpose()?;
+    let worker_pool = WorkerPoolConfig::from_opt(&opt)?;
+    ensure!(
+        opt.request_timeout_s >= 0.,
+        "--request-timeout-s must not be negative"
+    );
+    let request_timeout =
+        (opt.request_timeout_s > 0.).then(|| Duration::from_secs_f64(opt.request_timeout_s));
+    let pool_options = DbPoolOptions {
+        min_connections: opt.db_min_connections,
+        max_connections: opt.nr_connections as u32,
+        acquire_timeout: Duration::from_secs_f64(opt.db_acquire_timeout_s),
+        idle_timeout: (opt.db_idle_timeout_s > 0.)
+            .then(|| Duration::from_secs_f64(opt.db_idle_timeout_s)),
+        statement_cache_capacity: opt.db_statement_cache_capacity,
+    };
+    let db = DbConnection::connect_with(&opt.db_uri, pool_options).await?;
     let db = Arc::new(db);
-    let query_engine = QueryEngine::new(db.clone());
+    let query_engine = QueryEngine::new(db.clone())
+        .with_query_cache_size(opt.query_cache_size)
+        .with_crud_page_limits(PageLimits {
+            default_size: opt.crud_default_page_size,
+            max_size: opt.crud_max_page_size,
+        });
     let meta_service = MetaService::new(db.clone());
     let kafka_service = if let Some(ref kafka_connection) = opt.kafka_connection {
-        Some(Arc::new(KafkaService::connect(kafka_connection).await?))
+        let service = KafkaService::connect(
+            kafka_connection,
+            &opt.kafka_consumer_group,
+            &opt.kafka_start_offset,
+            opt.kafka_schema_registry.as_deref(),
+        )
+        .await?;
+        Some(Arc::new(service))
     } else {
         None
     };
@@ -131,27 +251,55 @@ async fn make_server(opt: Opt) -> Result<(Arc<Server>, TaskHandle<Result<()>>)>
         .migrate_schema()
         .await
         .context("Could not migrate database schema to the latest version")?;
+    meta_service
+        .remove_stale_applies(opt.apply_retention_s)
+        .await
+        .context("Could not remove stale applies")?;
 
     let builtin_types = Arc::new(BuiltinTypes::new());
     builtin_types.create_backing_tables(&query_engine).await?;
 
     let type_systems = meta_service.load_type_systems(&builtin_types).await?;
+    if tenancy().is_some() {
+        add_tenant_columns(&query_engine, &type_systems)
+            .await
+            .context("Could not add the tenant column to the tables of the entities")?;
+    }
     let type_systems = tokio::sync::Mutex::new(type_systems);
 
-    let secrets = match secrets::get_secrets(&opt).await {
+    let file_secrets = match secrets::get_secrets(&opt).await {
         Ok(secrets) => secrets,
         Err(err) => {
             log::error!("Could not read secrets: {:?}", err);
             JsonObject::default()
         }
     };
-    let secrets = RwLock::new(secrets);
+    let stored_secrets = meta_service
+        .load_secrets()
+        .await
+        .context("Could not load secrets from the database")?;
+    let secrets = RwLock::new(secrets::merge_secrets(&file_secrets, &stored_secrets));
+    let file_secrets = RwLock::new(file_secrets);
 
     worker::set_v8_flags(&opt.v8_flags)?;
     let inspector = start_inspector(&opt).await?;
 
+    let (request_log, request_log_task) = match RequestLog::spawn(&opt)? {
+        Some((request_log, task)) => (Some(request_log), Some(task)),
+        None => (None, None),
+    };
+
+    let jwt = JwtConfig::new(
+        opt.jwt_issuer.clone(),
+        opt.jwt_audience.clone(),
+        opt.jwt_jwks_url.clone(),
+    )?;
+    let oidc = OidcConfig::from_opt(&opt)?;
+    let blob_store = blob::from_opt(&opt)?;
+
     let (trunk, trunk_task) = trunk::spawn().await?;
     let server = Server {
+        current_opt: RwLock::new(opt.clone()),
         opt,
         db,
         query_engine,
@@ -160,10 +308,23 @@ async fn make_server(opt: Opt) -> Result<(Arc<Server>, TaskHandle<Result<()>>)>
         builtin_types,
         type_systems,
         secrets,
+        file_secrets,
         inspector,
         trunk,
+        sockets: SocketRegistry::default(),
+        rate_limiter: RateLimiter::default(),
+        worker_affinity,
+        worker_pool,
+        request_timeout,
+        max_body_size: opt.max_body_size,
+        request_log,
+        frozen_time_ms: RwLock::new(None),
+        jwt,
+        oidc,
+        blob_store,
+        seed_lock: tokio::sync::Mutex::new(()),
     };
-    Ok((Arc::new(server), trunk_task))
+    Ok((Arc::new(server), trunk_task, request_log_task))
 }
 
 fn find_legacy_sqlite_dbs(opt: &Opt) -> Vec<PathBuf> {
@@ -177,7 +338,7 @@ fn find_legacy_sqlite_dbs(opt: &Opt) -> Vec<PathBuf> {
     sources
 }
 
-fn extract_sqlite_file(db_uri: &str) -> Option<String> {
+pub(crate) fn extract_sqlite_file(db_uri: &str) -> Option<String> {
     let regex = Regex::new("^sqlite://(?P<fname>[^?]+)").unwrap();
     regex
         .captures(db_uri)
@@ -194,6 +355,11 @@ async fn start_versions(server: Arc<Server>) -> Result<()> {
             .unwrap_or_else(|| TypeSystem::new(server.builtin_types.clone(), version_id.clone()));
         let policy_system = server.meta_service.load_policy_system(&version_id).await?;
         let modules = server.meta_service.load_modules(&version_id).await?;
+        let static_files = server.meta_service.load_static_files(&version_id).await?;
+        let allowed_hosts = server.meta_service.load_allowed_hosts(&version_id).await?;
+        let import_map = server.meta_service.load_import_map(&version_id).await?;
+        let worker_pool = server.meta_service.load_worker_pool(&version_id).await?;
+        let worker_pool = server.worker_pool.with_overrides(&worker_pool)?;
         let policy_sources = Arc::new(server.meta_service.load_policy_sources(&version_id).await?);
 
         let root_url = "file:///__root.ts";
@@ -207,6 +373,14 @@ async fn start_versions(server: Arc<Server>) -> Result<()> {
             continue;
         }
 
+        // versions applied by an older chiseld do not have the table of `Chisel.kv`
+        let mut transaction = server.query_engine.begin_transaction().await?;
+        server
+            .query_engine
+            .create_kv_table(&mut transaction, &version_id)
+            .await?;
+        QueryEngine::commit_transaction(transaction).await?;
+
         // ignore the notification that the version is ready
         let (ready_tx, _ready_rx) = oneshot::channel();
 
@@ -215,9 +389,12 @@ async fn start_versions(server: Arc<Server>) -> Result<()> {
             info,
             server: server.clone(),
             modules: Arc::new(modules),
+            static_files: Arc::new(StaticFiles::new(static_files)),
+            allowed_hosts,
+            import_map: Arc::new(import_map),
             type_system: Arc::new(type_system),
             policy_system: Arc::new(policy_system),
-            worker_count: server.opt.worker_threads,
+            worker_pool,
             ready_tx,
             is_canary: false,
             policy_sources,
@@ -254,9 +431,12 @@ async fn start_builtin_version(server: Arc<Server>) -> Result<()> {
         info,
         server: server.clone(),
         modules: Arc::new(modules),
+        static_files: Default::default(),
+        allowed_hosts: None,
+        import_map: Default::default(),
         type_system: Arc::new(type_system),
         policy_system: Arc::new(policy_system),
-        worker_count: 1,
+        worker_pool: WorkerPoolConfig::fixed(1),
         ready_tx,
         is_canary: false,
         policy_sources: Default::default(),
@@ -269,19 +449,20 @@ async fn start_builtin_version(server: Arc<Server>) -> Result<()> {
 
 async fn refresh_secrets(server: Arc<Server>) -> Result<()> {
     let mut last_try_was_failure = false;
-    let mut delay_s = server.opt.secrets_polling_period_s;
+    let mut delay_s = server.current_opt.read().secrets_polling_period_s;
     loop {
         tokio::time::sleep(Duration::from_secs_f32(delay_s)).await;
+        let opt = server.current_opt.read().clone();
         if let Err(err) = update_secrets(&server).await {
             if !last_try_was_failure {
                 log::warn!("Could not re-read secrets: {:?}", err);
             }
             last_try_was_failure = true;
-            delay_s *= server.opt.secrets_refresh_exponential_backoff_factor;
-            delay_s = delay_s.min(server.opt.secrets_refresh_max_exponential_backoff_s);
+            delay_s *= opt.secrets_refresh_exponential_backoff_factor;
+            delay_s = delay_s.min(opt.secrets_refresh_max_exponential_backoff_s);
         } else {
             last_try_was_failure = false;
-            delay_s = server.opt.secrets_polling_period_s;
+            delay_s = opt.secrets_polling_period_s;
 
             if server.opt.refresh_secrets_only_once {
                 return Ok(());
@@ -291,11 +472,202 @@ async fn refresh_secrets(server: Arc<Server>) -> Result<()> {
 }
 
 pub async fn update_secrets(server: &Server) -> Result<()> {
-    let secrets = secrets::get_secrets(&server.opt).await?;
+    let opt = server.current_opt.read().clone();
+    let secrets = secrets::get_secrets(&opt).await?;
+    *server.file_secrets.write() = secrets;
+    refresh_stored_secrets(server).await
+}
+
+/// Re-reads the secrets from the database (they might have been changed by `chisel secrets` on
+/// this or another server) and merges them with the secrets from the file.
+pub async fn refresh_stored_secrets(server: &Server) -> Result<()> {
+    let stored = server.meta_service.load_secrets().await?;
+    let secrets = secrets::merge_secrets(&server.file_secrets.read(), &stored);
     *server.secrets.write() = secrets;
     Ok(())
 }
 
+/// Periodically recomputes the materialized aggregates that are refreshed on schedule (the other
+/// aggregates are maintained on write).
+async fn refresh_scheduled_aggregates(server: Arc<Server>) -> Result<()> {
+    // when each aggregate was last refreshed, keyed by version id and aggregate name; aggregates
+    // are computed by the apply that defines them, so the first refresh is due one interval after
+    // the server has started
+    let mut last_refresh = HashMap::<(String, String), Instant>::new();
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let now = Instant::now();
+        for version in server.trunk.list_versions() {
+            for agg in version.type_system.aggregators() {
+                let interval_s = match agg.aggregate.aggregate().and_then(|a| a.refresh_interval_s)
+                {
+                    Some(interval_s) => interval_s,
+                    None => continue,
+                };
+                let key = (version.version_id.clone(), agg.aggregate.name().to_owned());
+                let last = *last_refresh.entry(key.clone()).or_insert(now);
+                if now.duration_since(last) < Duration::from_secs(interval_s) {
+                    continue;
+                }
+                last_refresh.insert(key, now);
+
+                let refreshed = async {
+                    let mut transaction = server.query_engine.begin_transaction().await?;
+                    server
+                        .query_engine
+                        .refresh_aggregate(&mut transaction, &agg)
+                        .await?;
+                    QueryEngine::commit_transaction(transaction).await
+                };
+                match refreshed.await {
+                    Ok(()) => server
+                        .query_engine
+                        .invalidate_query_cache(agg.aggregate.backing_table()),
+                    Err(err) => log::warn!(
+                        "Could not refresh aggregate `{}` of version `{}`: {:?}",
+                        agg.aggregate.name(),
+                        version.version_id,
+                        err
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Collects the garbage of the meta database (see `datastore::meta::gc`). Refuses to run while an
+/// apply holds the apply lock.
+pub async fn collect_meta_garbage(server: &Server, dry_run: bool) -> Result<GcReport> {
+    if let Some(lock) = server.meta_service.load_apply_lock().await? {
+        bail!(
+            "Apply {} by {} is in progress, try again when it finishes",
+            lock.apply_id,
+            lock.holder
+        );
+    }
+    let live_versions = server
+        .trunk
+        .list_versions()
+        .iter()
+        .map(|version| version.version_id.clone())
+        .collect();
+    let retention = GcRetention {
+        audit_retention_s: server.opt.audit_retention_s,
+        apply_retention_s: server.opt.apply_retention_s,
+    };
+    server
+        .meta_service
+        .collect_garbage(&live_versions, retention, dry_run)
+        .await
+}
+
+/// Periodically collects the garbage of the meta database, see `--meta-gc-interval-s`.
+async fn collect_meta_garbage_periodically(server: Arc<Server>) -> Result<()> {
+    let interval_s = server.opt.meta_gc_interval_s;
+    if interval_s == 0 {
+        return Ok(());
+    }
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval_s)).await;
+        match collect_meta_garbage(&server, false).await {
+            Ok(report) => debug!(
+                "Collected the garbage of the meta database: removed {:?}, reclaimed {:?} bytes",
+                report.removed_rows, report.reclaimed_bytes
+            ),
+            Err(err) => warn!(
+                "Could not collect the garbage of the meta database: {:?}",
+                err
+            ),
+        }
+    }
+}
+
+/// Opens `--db-min-connections` database connections and marks the server as ready once they are
+/// open, then pings the idle connections every `--db-keepalive-interval-s`.
+async fn warm_up_db_and_keep_alive(server: Arc<Server>) -> Result<()> {
+    loop {
+        match server.db.warm_up().await {
+            Ok(size) => {
+                debug!("Opened {} database connections", size);
+                break;
+            }
+            Err(err) => {
+                warn!("Could not warm up the database connection pool: {:?}", err);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+    mark_ready();
+
+    let interval_s = server.opt.db_keepalive_interval_s;
+    if interval_s <= 0. {
+        return Ok(());
+    }
+    loop {
+        tokio::time::sleep(Duration::from_secs_f64(interval_s)).await;
+        let count = server.db.ping_idle().await;
+        debug!("Pinged {} idle database connections", count);
+    }
+}
+
+async fn reload_on_sighup(server: Arc<Server>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = signal(SignalKind::hangup())?;
+    while sighup.recv().await.is_some() {
+        info!("Got SIGHUP, reloading configuration");
+        match reload_config(&server).await {
+            Ok(report) => {
+                if !report.applied.is_empty() {
+                    info!(
+                        "Applied configuration changes: {}",
+                        report.applied.join(", ")
+                    );
+                }
+                if !report.restart_required.is_empty() {
+                    warn!(
+                        "Configuration changes that require a restart: {}",
+                        report.restart_required.join(", ")
+                    );
+                }
+                if report.applied.is_empty() && report.restart_required.is_empty() {
+                    info!("Configuration has not changed");
+                }
+            }
+            Err(err) => error!("Could not reload configuration: {:?}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Re-reads the configuration and applies the options that can be changed at runtime.
+pub async fn reload_config(server: &Server) -> Result<ReloadReport> {
+    let new_opt = Opt::load().await?;
+    let log_level = new_opt.log_level_filter()?;
+    let log_format = new_opt.log_format()?;
+
+    let report = {
+        let mut current_opt = server.current_opt.write();
+        let report = current_opt.reload_report(&new_opt);
+        current_opt.apply_reloadable(&new_opt);
+        report
+    };
+
+    if report.applied.iter().any(|name| name == "log_level") {
+        log::set_max_level(log_level.unwrap_or(log::LevelFilter::Info));
+    }
+    if report.applied.iter().any(|name| name == "log_format") {
+        crate::logging::set_format(log_format);
+    }
+    if report
+        .applied
+        .iter()
+        .any(|name| name.starts_with("chisel_secret"))
+    {
+        update_secrets(server).await?;
+    }
+    Ok(report)
+}
+
 async fn wait_for_signals() -> Result<()> {
     let default_hook = panic::take_hook();
     panic::set_hook(Box::new(move |info| {
@@ -306,12 +678,10 @@ async fn wait_for_signals() -> Result<()> {
     use tokio::signal::unix::{signal, SignalKind};
     let mut sigterm = signal(SignalKind::terminate())?;
     let mut sigint = signal(SignalKind::interrupt())?;
-    let mut sighup = signal(SignalKind::hangup())?;
 
     tokio::select! {
         Some(_) = sigterm.recv() => { debug!("Got SIGTERM") },
         Some(_) = sigint.recv() => { debug!("Got SIGINT") },
-        Some(_) = sighup.recv() => { debug!("Got SIGHUP") },
     };
     mark_not_ready();
     Ok(())
@@ -0,0 +1,272 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! WebSocket connections.
+//!
+//! A WebSocket connection is established by an HTTP upgrade request to a version. Before we
+//! accept the upgrade, we send an "open" event to the version, and JavaScript decides whether
+//! there is a socket route for the path (see `Chisel.socket()` in the API). Once the connection
+//! is established, every frame that we receive from the client is sent to the version as a
+//! separate event. All events of a single connection are handled by the same worker, in the
+//! order in which they were received.
+//!
+//! Messages from JavaScript to the client are sent through the [`SocketRegistry`], which maps
+//! socket ids to the tasks that run the connections. A socket can only be used by the version
+//! that accepted it.
+
+use crate::authentication::Authentication;
+use crate::server::Server;
+use crate::version::VersionJob;
+use anyhow::{bail, Context, Result};
+use deno_core::serde_v8;
+use futures::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Event on a WebSocket connection that is handled by a version.
+#[derive(Debug)]
+pub struct SocketEvent {
+    pub socket_id: u64,
+    pub routing_path: String,
+    pub headers: Vec<(String, String)>,
+    pub authentication: Authentication,
+    pub kind: SocketEventKind,
+    /// Only set for the `Open` event: JavaScript sends `true` if the connection is accepted.
+    pub accept_tx: Option<oneshot::Sender<bool>>,
+}
+
+/// Kind of a [`SocketEvent`] that is passed to JavaScript.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum SocketEventKind {
+    Open,
+    Text { data: String },
+    Binary { data: serde_v8::ZeroCopyBuf },
+    Close { code: Option<u16>, reason: String },
+}
+
+/// Maximal number of outgoing messages that may wait for a single client. If a slow client
+/// lets the queue fill up, the socket is closed.
+pub const SOCKET_QUEUE_SIZE: usize = 1024;
+
+/// Registry of open WebSocket connections.
+#[derive(Default)]
+pub struct SocketRegistry {
+    next_id: AtomicU64,
+    sockets: Mutex<HashMap<u64, RegisteredSocket>>,
+}
+
+struct RegisteredSocket {
+    /// Version that accepted the socket; no other version may use it.
+    version_id: String,
+    tx: mpsc::Sender<Message>,
+}
+
+impl SocketRegistry {
+    /// Registers a new socket of the given version, returning its id and the receiver for
+    /// outgoing messages.
+    pub fn register(&self, version_id: &str) -> (u64, mpsc::Receiver<Message>) {
+        let socket_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(SOCKET_QUEUE_SIZE);
+        let socket = RegisteredSocket {
+            version_id: version_id.into(),
+            tx,
+        };
+        self.sockets.lock().insert(socket_id, socket);
+        (socket_id, rx)
+    }
+
+    pub fn unregister(&self, socket_id: u64) {
+        self.sockets.lock().remove(&socket_id);
+    }
+
+    /// Queues a message to be sent to the client of a socket that belongs to the given version.
+    ///
+    /// If the queue of the socket is full, the socket is closed: dropping our sender ends the
+    /// connection task.
+    pub fn send(&self, version_id: &str, socket_id: u64, message: Message) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        let socket = sockets
+            .get(&socket_id)
+            .filter(|socket| socket.version_id == version_id)
+            .with_context(|| format!("Socket {} is closed", socket_id))?;
+        match socket.tx.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                sockets.remove(&socket_id);
+                bail!(
+                    "Socket {} was closed because its client does not receive messages fast \
+                    enough",
+                    socket_id
+                )
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => bail!("Socket {} is closed", socket_id),
+        }
+    }
+
+    pub fn close(
+        &self,
+        version_id: &str,
+        socket_id: u64,
+        code: Option<u16>,
+        reason: String,
+    ) -> Result<()> {
+        let frame = CloseFrame {
+            code: code.map_or(CloseCode::Normal, CloseCode::from),
+            reason: reason.into(),
+        };
+        self.send(version_id, socket_id, Message::Close(Some(frame)))
+    }
+}
+
+/// Parameters of a socket that are shared by all its events.
+#[derive(Debug, Clone)]
+pub struct SocketInfo {
+    pub socket_id: u64,
+    pub routing_path: String,
+    pub headers: Vec<(String, String)>,
+    pub authentication: Authentication,
+}
+
+impl SocketInfo {
+    fn event(&self, kind: SocketEventKind) -> SocketEvent {
+        SocketEvent {
+            socket_id: self.socket_id,
+            routing_path: self.routing_path.clone(),
+            headers: self.headers.clone(),
+            authentication: self.authentication.clone(),
+            kind,
+            accept_tx: None,
+        }
+    }
+
+    /// Sends the `Open` event to the version and waits until JavaScript decides whether the
+    /// socket is accepted.
+    pub async fn open(&self, job_tx: &mpsc::Sender<VersionJob>) -> Result<bool> {
+        let (accept_tx, accept_rx) = oneshot::channel();
+        let mut event = self.event(SocketEventKind::Open);
+        event.accept_tx = Some(accept_tx);
+        // see `send_http_job()` for the explanation of the error handling
+        let _: Result<_, _> = job_tx.send(VersionJob::Socket(event)).await;
+        accept_rx.await.context("Socket was not opened")
+    }
+}
+
+/// Runs an accepted WebSocket connection until it is closed by either side.
+pub async fn run(
+    server: Arc<Server>,
+    info: SocketInfo,
+    job_tx: mpsc::Sender<VersionJob>,
+    on_upgrade: hyper::upgrade::OnUpgrade,
+    mut outgoing_rx: mpsc::Receiver<Message>,
+) {
+    let result = async {
+        let upgraded = on_upgrade.await.context("Could not upgrade to WebSocket")?;
+        let mut stream = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+        let (mut close_code, mut close_reason) = (None, String::new());
+        loop {
+            tokio::select! {
+                incoming = stream.next() => {
+                    let kind = match incoming {
+                        Some(Ok(Message::Text(data))) => SocketEventKind::Text { data },
+                        Some(Ok(Message::Binary(data))) => SocketEventKind::Binary {
+                            data: serde_v8::ZeroCopyBuf::from(data),
+                        },
+                        Some(Ok(Message::Close(frame))) => {
+                            if let Some(frame) = frame {
+                                close_code = Some(u16::from(frame.code));
+                                close_reason = frame.reason.into_owned();
+                            }
+                            break;
+                        }
+                        // pings are answered automatically
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => {
+                            log::debug!("Error on socket {}: {:?}", info.socket_id, err);
+                            break;
+                        }
+                        None => break,
+                    };
+                    if job_tx.send(VersionJob::Socket(info.event(kind))).await.is_err() {
+                        break;
+                    }
+                }
+                outgoing = outgoing_rx.recv() => match outgoing {
+                    Some(message) => {
+                        let is_close = matches!(message, Message::Close(_));
+                        stream.send(message).await?;
+                        if is_close {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+        Ok::<_, anyhow::Error>((close_code, close_reason))
+    }
+    .await;
+
+    server.sockets.unregister(info.socket_id);
+    let (code, reason) = result.unwrap_or_else(|err| {
+        log::debug!("Socket {} failed: {:?}", info.socket_id, err);
+        (None, String::new())
+    });
+    let event = info.event(SocketEventKind::Close { code, reason });
+    let _: Result<_, _> = job_tx.send(VersionJob::Socket(event)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sockets_belong_to_their_version() {
+        let registry = SocketRegistry::default();
+        let (socket_id, mut rx) = registry.register("dev");
+
+        assert!(registry
+            .send("other", socket_id, Message::Text("a".into()))
+            .is_err());
+        assert!(registry
+            .close("other", socket_id, None, String::new())
+            .is_err());
+        assert!(rx.try_recv().is_err());
+
+        registry
+            .send("dev", socket_id, Message::Text("b".into()))
+            .unwrap();
+        assert_eq!(rx.try_recv().unwrap(), Message::Text("b".into()));
+    }
+
+    #[test]
+    fn full_queue_closes_socket() {
+        let registry = SocketRegistry::default();
+        let (socket_id, mut rx) = registry.register("dev");
+        for _ in 0..SOCKET_QUEUE_SIZE {
+            registry
+                .send("dev", socket_id, Message::Text("x".into()))
+                .unwrap();
+        }
+        assert!(registry
+            .send("dev", socket_id, Message::Text("x".into()))
+            .is_err());
+        assert!(registry
+            .send("dev", socket_id, Message::Text("x".into()))
+            .is_err());
+
+        // the queued messages are still delivered, then the receiver sees that it was closed
+        for _ in 0..SOCKET_QUEUE_SIZE {
+            rx.try_recv().unwrap();
+        }
+        assert_eq!(rx.try_recv(), Err(mpsc::error::TryRecvError::Disconnected));
+    }
+}
@@ -0,0 +1,231 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Static files of a version.
+//!
+//! `chisel apply` uploads the files of the `static/` directory of the project, which are stored in
+//! the meta database and served at `/{version}/static/{path}`, so that small frontends can be
+//! deployed together with the routes. The files shadow the user routes under `/static`.
+//!
+//! The responses carry an `ETag` (the hash of the content) and `Cache-Control: no-cache`, so
+//! clients may cache the files, but they revalidate them with `If-None-Match` and get a 304
+//! response as long as the file was not changed by another apply.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+/// Path under a version where the static files are served.
+pub const STATIC_PATH: &str = "/static";
+
+/// File that is served for the paths of directories.
+const INDEX_FILE: &str = "index.html";
+
+pub struct StaticFile {
+    pub content: hyper::body::Bytes,
+    /// Quoted hash of the content.
+    pub etag: String,
+}
+
+#[derive(Default)]
+pub struct StaticFiles {
+    files: HashMap<String, StaticFile>,
+}
+
+impl StaticFiles {
+    pub fn new(files: HashMap<String, Vec<u8>>) -> Self {
+        let files = files
+            .into_iter()
+            .map(|(path, content)| {
+                let etag = format!("\"{:x}\"", Sha256::digest(&content));
+                let file = StaticFile {
+                    content: content.into(),
+                    etag,
+                };
+                (path, file)
+            })
+            .collect();
+        Self { files }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns the path and the file at `path` (relative to the static directory), or the index
+    /// file if `path` is a directory. The paths of requests never end with a slash (see
+    /// `normalize_path()` in `http`), so directories are recognized by their index file.
+    pub fn get(&self, path: &str) -> Option<(&str, &StaticFile)> {
+        let path = path.trim_matches('/');
+        let (path, file) = match path {
+            "" => self.files.get_key_value(INDEX_FILE)?,
+            _ => self
+                .files
+                .get_key_value(path)
+                .or_else(|| self.files.get_key_value(&format!("{path}/{INDEX_FILE}")))?,
+        };
+        Some((path.as_str(), file))
+    }
+}
+
+/// Checks that the path of an uploaded static file is relative and does not escape the static
+/// directory.
+pub fn validate_path(path: &str) -> Result<()> {
+    anyhow::ensure!(
+        !path.is_empty()
+            && !path.starts_with('/')
+            && !path.contains('\\')
+            && path
+                .split('/')
+                .all(|segment| !matches!(segment, "" | "." | "..")),
+        "Invalid path of static file {:?}",
+        path
+    );
+    Ok(())
+}
+
+/// Serves the static file at `path` of a version.
+pub fn handle(
+    files: &StaticFiles,
+    request: &hyper::Request<hyper::Body>,
+    path: &str,
+) -> hyper::Response<hyper::Body> {
+    let method = request.method();
+    if method != hyper::Method::GET && method != hyper::Method::HEAD {
+        return hyper::Response::builder()
+            .status(hyper::StatusCode::METHOD_NOT_ALLOWED)
+            .header(hyper::header::ALLOW, "GET, HEAD")
+            .body(hyper::Body::empty())
+            .unwrap();
+    }
+    let (path, file) = match files.get(path) {
+        Some(found) => found,
+        None => {
+            return hyper::Response::builder()
+                .status(hyper::StatusCode::NOT_FOUND)
+                .body(hyper::Body::from(format!("Unknown static file {:?}", path)))
+                .unwrap()
+        }
+    };
+
+    let builder = hyper::Response::builder()
+        .header(hyper::header::ETAG, &file.etag)
+        .header(hyper::header::CACHE_CONTROL, "no-cache");
+    let not_modified = request
+        .headers()
+        .get(hyper::header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| etag_matches(value, &file.etag));
+    if not_modified {
+        return builder
+            .status(hyper::StatusCode::NOT_MODIFIED)
+            .body(hyper::Body::empty())
+            .unwrap();
+    }
+
+    let builder = builder
+        .header(hyper::header::CONTENT_TYPE, content_type(path))
+        .header(hyper::header::CONTENT_LENGTH, file.content.len());
+    let body = match *method {
+        hyper::Method::HEAD => hyper::Body::empty(),
+        _ => hyper::Body::from(file.content.clone()),
+    };
+    builder.body(body).unwrap()
+}
+
+/// Returns true if the `If-None-Match` header matches the ETag of the file.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag.trim_start_matches("W/") == etag
+    })
+}
+
+fn content_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map_or("", |(_, ext)| ext);
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> StaticFiles {
+        StaticFiles::new(HashMap::from([
+            ("index.html".to_owned(), b"<h1>home</h1>".to_vec()),
+            ("app/main.js".to_owned(), b"console.log(1)".to_vec()),
+            ("app/index.html".to_owned(), b"<h1>app</h1>".to_vec()),
+        ]))
+    }
+
+    #[test]
+    fn paths() {
+        assert!(validate_path("app/main.js").is_ok());
+        for path in [
+            "",
+            "/etc/passwd",
+            "../secret",
+            "app/../x",
+            "app//x",
+            "app\\x",
+        ] {
+            assert!(validate_path(path).is_err(), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let files = files();
+        let content = |path: &str| &files.get(path).unwrap().1.content[..];
+        assert_eq!(content(""), b"<h1>home</h1>");
+        assert_eq!(content("/app"), b"<h1>app</h1>");
+        assert_eq!(content("/app/main.js"), b"console.log(1)");
+        assert_eq!(files.get("/app").unwrap().0, "app/index.html");
+        assert!(files.get("/app/main").is_none());
+        assert!(files.get("/missing.css").is_none());
+    }
+
+    #[test]
+    fn revalidation() {
+        let files = files();
+        let etag = files.get("/app/main.js").unwrap().1.etag.clone();
+        let request = |if_none_match: Option<&str>| {
+            let mut builder = hyper::Request::get("/dev/static/app/main.js");
+            if let Some(value) = if_none_match {
+                builder = builder.header(hyper::header::IF_NONE_MATCH, value);
+            }
+            builder.body(hyper::Body::empty()).unwrap()
+        };
+
+        let response = handle(&files, &request(None), "/app/main.js");
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(response.headers()[hyper::header::ETAG], etag.as_str());
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+
+        let matching = format!("\"other\", W/{etag}");
+        let response = handle(&files, &request(Some(&matching)), "/app/main.js");
+        assert_eq!(response.status(), hyper::StatusCode::NOT_MODIFIED);
+        let response = handle(&files, &request(Some("\"other\"")), "/app/main.js");
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+    }
+}
@@ -0,0 +1,197 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! OpenTelemetry tracing of `chiseld`.
+//!
+//! Every HTTP request is traced with a span that has child spans for the dispatch of the request
+//! to a worker, for the evaluation of policies and for each SQL statement that the `QueryEngine`
+//! executes. Spans are exported with OTLP if `--otlp-endpoint` is set; otherwise, the global
+//! tracer is a no-op and creating spans costs next to nothing.
+//!
+//! JavaScript jobs of many requests are interleaved on the same worker thread, so we cannot rely
+//! on the thread-local current context of OpenTelemetry. Instead, the context of the parent span
+//! is passed explicitly (see `JobInfo::otel_context()`).
+
+use crate::opt::Opt;
+use anyhow::{Context as _, Result};
+use futures::Stream;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use pin_project::pin_project;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::Poll;
+
+const TRACER_NAME: &str = "chiseld";
+
+/// Installs the OTLP exporter if it is configured in `opt`.
+pub fn init(opt: &Opt) -> Result<()> {
+    let endpoint = match &opt.otlp_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(()),
+    };
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                opt.otlp_service_name.clone(),
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)
+        .with_context(|| format!("Could not set up OTLP exporter to {}", endpoint))?;
+    info!("Exporting traces to {}", endpoint);
+    Ok(())
+}
+
+/// Exports the spans that have not been exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Starts a span that is a child of the span in `parent` and returns a context with the new span.
+/// The span ends when `end()` is called or when the last clone of the context is dropped.
+pub fn start_span(
+    parent: &Context,
+    name: impl Into<Cow<'static, str>>,
+    kind: SpanKind,
+    attributes: Vec<KeyValue>,
+) -> Context {
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent);
+    parent.with_span(span)
+}
+
+/// Starts a span of a SQL statement executed by the `QueryEngine`.
+pub fn start_sql_span(parent: &Context, kind: &'static str, sql: &str) -> Context {
+    start_span(
+        parent,
+        format!("SQL {}", kind),
+        SpanKind::Client,
+        vec![
+            KeyValue::new("db.statement", sql.to_owned()),
+            KeyValue::new("chisel.query_kind", kind),
+        ],
+    )
+}
+
+/// Ends the span in `cx`, recording `error` if there is one.
+pub fn end<T>(cx: &Context, result: &Result<T>) {
+    let span = cx.span();
+    if let Err(err) = result {
+        span.set_status(Status::error(format!("{:?}", err)));
+    }
+    span.end();
+}
+
+/// Returns the context of the span that the client propagated in the W3C `traceparent` header,
+/// if any.
+pub fn extract_context(headers: &hyper::HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// Returns the W3C `traceparent` of the span in `cx`, which propagates the trace to other
+/// services, or `None` if the context has no valid span.
+pub fn traceparent(cx: &Context) -> Option<String> {
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(cx, &mut headers);
+    headers.remove("traceparent")
+}
+
+struct HeaderExtractor<'a>(&'a hyper::HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Stream that ends the span in `cx` when the wrapped stream is exhausted (or when it is dropped,
+/// because spans end when they are dropped).
+#[pin_project]
+pub struct SpannedStream<S> {
+    #[pin]
+    stream: S,
+    cx: Option<Context>,
+}
+
+impl<S> SpannedStream<S> {
+    pub fn new(stream: S, cx: Context) -> Self {
+        Self {
+            stream,
+            cx: Some(cx),
+        }
+    }
+}
+
+impl<S: Stream> Stream for SpannedStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<S::Item>> {
+        let this = self.project();
+        let poll = this.stream.poll_next(cx);
+        if let Poll::Ready(None) = poll {
+            if let Some(span_cx) = this.cx.take() {
+                span_cx.span().end();
+            }
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_traceparent() {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                .parse()
+                .unwrap(),
+        );
+        let cx = extract_context(&headers);
+        let span_context = cx.span().span_context().clone();
+        assert!(span_context.is_remote());
+        assert_eq!(
+            span_context.trace_id().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+        assert_eq!(span_context.span_id().to_string(), "b7ad6b7169203331");
+    }
+
+    #[test]
+    fn missing_traceparent() {
+        let cx = extract_context(&hyper::HeaderMap::new());
+        assert!(!cx.span().span_context().is_valid());
+        assert_eq!(traceparent(&cx), None);
+    }
+
+    #[test]
+    fn propagate_traceparent() {
+        let value = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("traceparent", value.parse().unwrap());
+        let cx = extract_context(&headers);
+        assert_eq!(traceparent(&cx).as_deref(), Some(value));
+    }
+}
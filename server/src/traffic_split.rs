@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+/// Rule for splitting the traffic of a version between two versions of the same app ("blue/green"
+/// or "canary" deployment).
+///
+/// A fraction of the requests to the stable version is handled by the canary version instead.
+/// Requests of logged-in users are assigned by a hash of the user id, so a user consistently
+/// sees the same version (and a growing fraction only moves users from the stable version to the
+/// canary, never back). Anonymous requests are assigned at random.
+#[derive(Debug)]
+pub struct TrafficSplit {
+    pub canary_version_id: String,
+    /// Fraction of requests (between 0 and 1) that are handled by the canary version.
+    pub fraction: f64,
+}
+
+/// Number of buckets that the user ids are hashed into.
+const BUCKETS: u64 = 10_000;
+
+impl TrafficSplit {
+    pub fn new(canary_version_id: String, fraction: f64) -> Self {
+        Self {
+            canary_version_id,
+            fraction,
+        }
+    }
+
+    /// Decides whether a request of user `user_id` should be handled by the canary version.
+    pub fn routes_to_canary(&self, user_id: Option<&str>) -> bool {
+        match user_id {
+            Some(user_id) => (user_bucket(user_id) as f64) < self.fraction * BUCKETS as f64,
+            None => rand::random::<f64>() < self.fraction,
+        }
+    }
+}
+
+/// Hashes the user id into one of the `BUCKETS`. We use FNV-1a instead of the standard hasher,
+/// because the assignment must not change when the server is restarted or upgraded.
+fn user_bucket(user_id: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in user_id.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash % BUCKETS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_are_sticky() {
+        let split = TrafficSplit::new("v2".into(), 0.3);
+        for i in 0..100 {
+            let user_id = format!("user-{}", i);
+            let first = split.routes_to_canary(Some(&user_id));
+            assert!((0..10).all(|_| split.routes_to_canary(Some(&user_id)) == first));
+        }
+    }
+
+    #[test]
+    fn users_are_split_by_fraction() {
+        let split = TrafficSplit::new("v2".into(), 0.2);
+        let canary = (0..10_000)
+            .filter(|i| split.routes_to_canary(Some(&format!("user-{}", i))))
+            .count();
+        assert!(
+            (1_700..2_300).contains(&canary),
+            "{} users in canary",
+            canary
+        );
+    }
+
+    #[test]
+    fn growing_fraction_keeps_canary_users() {
+        let small = TrafficSplit::new("v2".into(), 0.1);
+        let large = TrafficSplit::new("v2".into(), 0.5);
+        for i in 0..1000 {
+            let user_id = format!("user-{}", i);
+            if small.routes_to_canary(Some(&user_id)) {
+                assert!(large.routes_to_canary(Some(&user_id)));
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_fractions() {
+        let none = TrafficSplit::new("v2".into(), 0.0);
+        let all = TrafficSplit::new("v2".into(), 1.0);
+        assert!(!none.routes_to_canary(None));
+        assert!(!none.routes_to_canary(Some("alice")));
+        assert!(all.routes_to_canary(None));
+        assert!(all.routes_to_canary(Some("alice")));
+    }
+}
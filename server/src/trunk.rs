@@ -1,11 +1,14 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+use crate::mirror::Mirror;
 use crate::nursery::Nursery;
+use crate::traffic_split::TrafficSplit;
 use crate::version::{Version, VersionJob};
 use anyhow::Result;
 use futures::stream::StreamExt;
 use parking_lot::RwLock;
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use utils::{CancellableTaskHandle, TaskHandle};
@@ -15,6 +18,10 @@ use utils::{CancellableTaskHandle, TaskHandle};
 /// The trunk keeps track of the active [`Version`]s and monitors the version tasks.
 pub struct Trunk {
     versions: RwLock<HashMap<String, TrunkVersion>>,
+    /// Mirroring rules, keyed by the id of the source version.
+    mirrors: RwLock<HashMap<String, Arc<Mirror>>>,
+    /// Traffic splitting rules, keyed by the id of the stable version.
+    traffic_splits: RwLock<HashMap<String, Arc<TrafficSplit>>>,
     nursery: Nursery<CancellableTaskHandle<Result<()>>>,
 }
 
@@ -68,6 +75,16 @@ impl Trunk {
     }
 
     pub fn remove_version(&self, version_id: &str) -> Option<Arc<Version>> {
+        // stop mirroring from and to the removed version
+        self.mirrors.write().retain(|source_version_id, mirror| {
+            source_version_id != version_id && mirror.target_version_id != version_id
+        });
+        // ... and stop splitting its traffic
+        self.traffic_splits
+            .write()
+            .retain(|stable_version_id, split| {
+                stable_version_id != version_id && split.canary_version_id != version_id
+            });
         self.versions
             .write()
             .remove(version_id)
@@ -75,12 +92,73 @@ impl Trunk {
         // if there is still a task in `self.nursery` for this version, we just leave it alone. it
         // should terminate on its own when its `mpsc::Sender<VersionJob>` is dropped.
     }
+
+    /// Sets (or replaces) the mirroring rule for requests to `source_version_id`.
+    pub fn set_mirror(&self, source_version_id: String, mirror: Mirror) {
+        self.mirrors
+            .write()
+            .insert(source_version_id, Arc::new(mirror));
+    }
+
+    pub fn remove_mirror(&self, source_version_id: &str) -> Option<Arc<Mirror>> {
+        self.mirrors.write().remove(source_version_id)
+    }
+
+    pub fn get_mirror(&self, source_version_id: &str) -> Option<Arc<Mirror>> {
+        self.mirrors.read().get(source_version_id).cloned()
+    }
+
+    pub fn list_mirrors(&self) -> Vec<(String, Arc<Mirror>)> {
+        self.mirrors
+            .read()
+            .iter()
+            .map(|(source_version_id, mirror)| (source_version_id.clone(), mirror.clone()))
+            .collect()
+    }
+
+    /// Sets (or replaces) the traffic splitting rule for requests to `stable_version_id`.
+    pub fn set_traffic_split(&self, stable_version_id: String, split: TrafficSplit) {
+        self.traffic_splits
+            .write()
+            .insert(stable_version_id, Arc::new(split));
+    }
+
+    /// Removes the traffic splitting rule that routes requests to `canary_version_id`, returning
+    /// the id of the stable version.
+    pub fn remove_traffic_split_to(&self, canary_version_id: &str) -> Option<String> {
+        let mut splits = self.traffic_splits.write();
+        let stable_version_id = splits
+            .iter()
+            .find(|(_, split)| split.canary_version_id == canary_version_id)
+            .map(|(stable_version_id, _)| stable_version_id.clone())?;
+        splits.remove(&stable_version_id);
+        Some(stable_version_id)
+    }
+
+    pub fn get_traffic_split(&self, stable_version_id: &str) -> Option<Arc<TrafficSplit>> {
+        self.traffic_splits.read().get(stable_version_id).cloned()
+    }
+
+    /// Spawns a background task whose result nobody waits for (such as a mirrored request). The
+    /// task is still owned by the trunk, so it is aborted when the server terminates.
+    pub fn spawn_detached<Fut>(&self, fut: Fut)
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task = tokio::task::spawn(async move {
+            fut.await;
+            Ok(())
+        });
+        self.nursery.nurse(CancellableTaskHandle(task));
+    }
 }
 
 pub async fn spawn() -> Result<(Trunk, TaskHandle<Result<()>>)> {
     let (nursery, mut nursery_stream) = Nursery::new();
     let trunk = Trunk {
         versions: RwLock::new(HashMap::new()),
+        mirrors: RwLock::new(HashMap::new()),
+        traffic_splits: RwLock::new(HashMap::new()),
         nursery,
     };
 
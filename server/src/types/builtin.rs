@@ -17,9 +17,12 @@ impl BuiltinTypes {
         let mut types = HashMap::new();
         types.insert("string".into(), Type::String);
         types.insert("number".into(), Type::Float);
+        types.insert("int64".into(), Type::Int64);
         types.insert("boolean".into(), Type::Boolean);
         types.insert("jsDate".into(), Type::JsDate);
         types.insert("ArrayBuffer".into(), Type::ArrayBuffer);
+        types.insert("json".into(), Type::Json);
+        types.insert("GeoPoint".into(), Type::GeoPoint);
         add_auth_entity(
             &mut types,
             AUTH_USER_NAME,
@@ -140,6 +143,14 @@ fn string_field(name: &str) -> Field {
         is_optional: false,
         version_id: "__chiselstrike".into(),
         is_unique: false,
+        is_version: false,
+        count: None,
+        aggregate: None,
+        timestamp: None,
+        enum_type: None,
+        max_bytes: None,
+        on_delete: None,
+        collation: None,
     }
 }
 
@@ -160,6 +171,14 @@ fn number_field(name: &str) -> Field {
         is_optional: false,
         version_id: "__chiselstrike".into(),
         is_unique: false,
+        is_version: false,
+        count: None,
+        aggregate: None,
+        timestamp: None,
+        enum_type: None,
+        max_bytes: None,
+        on_delete: None,
+        collation: None,
     }
 }
 
@@ -174,6 +193,14 @@ fn optional_number_field(name: &str) -> Field {
         is_optional: true,
         version_id: "__chiselstrike".into(),
         is_unique: false,
+        is_version: false,
+        count: None,
+        aggregate: None,
+        timestamp: None,
+        enum_type: None,
+        max_bytes: None,
+        on_delete: None,
+        collation: None,
     }
 }
 
@@ -188,6 +215,14 @@ fn date_field(name: &str) -> Field {
         is_optional: false,
         version_id: "__chiselstrike".into(),
         is_unique: false,
+        is_version: false,
+        count: None,
+        aggregate: None,
+        timestamp: None,
+        enum_type: None,
+        max_bytes: None,
+        on_delete: None,
+        collation: None,
     }
 }
 
@@ -202,5 +237,13 @@ fn optional_array_buffer_field(name: &str) -> Field {
         is_optional: true,
         version_id: "__chiselstrike".into(),
         is_unique: false,
+        is_version: false,
+        count: None,
+        aggregate: None,
+        timestamp: None,
+        enum_type: None,
+        max_bytes: None,
+        on_delete: None,
+        collation: None,
     }
 }
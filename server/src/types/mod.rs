@@ -16,9 +16,20 @@ mod type_system;
 pub enum Type {
     String,
     Float,
+    /// 64-bit integer, which is a `bigint` in JavaScript
+    Int64,
     Boolean,
     JsDate,
     ArrayBuffer,
+    /// Arbitrary JSON value, which is `unknown` in TypeScript
+    Json,
+    /// Geographic location `{ lat, lng }` in degrees, which is `GeoPoint` in TypeScript
+    GeoPoint,
+    /// Fixed-precision decimal number, which is a string in TypeScript
+    Decimal {
+        precision: u32,
+        scale: u32,
+    },
     Entity(Entity),
     /// Contains Entity name which this EntityId type refers to
     EntityId(String),
@@ -29,10 +40,14 @@ impl Type {
     pub fn name(&self) -> String {
         match self {
             Type::Float => "number".to_string(),
+            Type::Int64 => "int64".to_string(),
             Type::String => "string".to_string(),
             Type::Boolean => "boolean".to_string(),
             Type::JsDate => "jsDate".to_string(),
             Type::ArrayBuffer => "ArrayBuffer".to_string(),
+            Type::Json => "json".to_string(),
+            Type::GeoPoint => "GeoPoint".to_string(),
+            Type::Decimal { precision, scale } => format!("Decimal<{precision},{scale}>"),
             Type::Entity(ty) => ty.name.to_string(),
             Type::EntityId(entity_name) => format!("Id<{entity_name}>"),
             Type::Array(ty) => format!("Array<{}>", ty.name()),
@@ -228,6 +243,15 @@ pub enum TypeId {
     /// Represents JavaScript Date class
     JsDate,
     ArrayBuffer,
+    /// Arbitrary JSON value
+    Json,
+    /// Geographic location, stored as JSON
+    GeoPoint,
+    /// Fixed-precision decimal number, stored as an integer scaled by `10^scale`
+    Decimal {
+        precision: u32,
+        scale: u32,
+    },
     Id,
     /// Contains Entity name which this EntityId type refers to
     EntityId(String),
@@ -247,6 +271,9 @@ impl TypeId {
             TypeId::Boolean => "boolean".to_string(),
             TypeId::JsDate => "jsDate".to_string(),
             TypeId::ArrayBuffer => "ArrayBuffer".to_string(),
+            TypeId::Json => "json".to_string(),
+            TypeId::GeoPoint => "GeoPoint".to_string(),
+            TypeId::Decimal { precision, scale } => format!("Decimal<{precision},{scale}>"),
             TypeId::EntityId(entity_name) => format!("Id<{entity_name}>"),
             TypeId::Entity { ref name, .. } => name.to_string(),
             TypeId::Array(elem_type) => format!("Array<{}>", elem_type.name()),
@@ -259,9 +286,13 @@ impl From<Type> for TypeId {
         match other {
             Type::String => Self::String,
             Type::Float => Self::Float,
+            Type::Int64 => Self::Int64,
             Type::Boolean => Self::Boolean,
             Type::JsDate => Self::JsDate,
             Type::ArrayBuffer => Self::ArrayBuffer,
+            Type::Json => Self::Json,
+            Type::GeoPoint => Self::GeoPoint,
+            Type::Decimal { precision, scale } => Self::Decimal { precision, scale },
             Type::EntityId(entity_name) => Self::EntityId(entity_name),
             Type::Entity(e) => Self::Entity {
                 name: e.name().to_string(),
@@ -304,6 +335,13 @@ pub struct ObjectType {
     chisel_id: Field,
     /// Name of the backing table for this type.
     backing_table: String,
+    /// Set for materialized aggregates, whose rows are computed from the source entity.
+    aggregate: Option<AggregateSpec>,
+    /// Set for entities marked with `@locked`, which an apply may change only with `--unlock`.
+    locked: bool,
+    /// Set for entities marked with `@ownedBy`: the field that holds the id of the user that owns
+    /// the row, which restricts the rows that the users can read and write.
+    owned_by: Option<String>,
 
     pub version_id: String,
 }
@@ -348,6 +386,14 @@ impl ObjectType {
             is_optional: false,
             version_id: "__chiselstrike".into(),
             is_unique: true,
+            is_version: false,
+            count: None,
+            aggregate: None,
+            timestamp: None,
+            enum_type: None,
+            max_bytes: None,
+            on_delete: None,
+            collation: None,
         };
 
         Ok(Self {
@@ -358,9 +404,44 @@ impl ObjectType {
             fields,
             indexes,
             chisel_id,
+            aggregate: None,
+            locked: false,
+            owned_by: None,
         })
     }
 
+    pub fn with_aggregate(mut self, aggregate: Option<AggregateSpec>) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    pub fn with_locked(mut self, locked: bool) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn with_owned_by(mut self, owned_by: Option<String>) -> Self {
+        self.owned_by = owned_by;
+        self
+    }
+
+    pub fn owned_by(&self) -> Option<&str> {
+        self.owned_by.as_deref()
+    }
+
+    pub fn aggregate(&self) -> Option<&AggregateSpec> {
+        self.aggregate.as_ref()
+    }
+
+    /// Fields of a materialized aggregate that the source entity is grouped by.
+    pub fn group_by_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(|f| f.aggregate.is_none())
+    }
+
     pub fn user_fields(&self) -> impl Iterator<Item = &Field> {
         self.fields.iter()
     }
@@ -576,6 +657,29 @@ pub struct Field {
     pub labels: Vec<String>,
     pub is_optional: bool,
     pub is_unique: bool,
+    /// Set for the `@version` field that is used for optimistic concurrency control: it is
+    /// incremented on every write, and the write fails with a `ConflictError` if the stored
+    /// version does not match the version of the written object.
+    pub is_version: bool,
+    /// Set for a derived `@count` field, whose value is maintained by the query engine.
+    pub count: Option<CountSpec>,
+    /// Set for the aggregated fields of a materialized aggregate.
+    pub aggregate: Option<AggregateFieldSpec>,
+    /// Set for the `createdAt` and `updatedAt` fields of a `@timestamps` entity, whose values are
+    /// maintained by the query engine.
+    pub timestamp: Option<Timestamp>,
+    /// Set for string fields whose type is a TypeScript string enum: the written values must be
+    /// one of the values of the enum.
+    pub enum_type: Option<EnumSpec>,
+    /// Maximum size in bytes of the values of an `ArrayBuffer` field (`@maxBytes`). The limit is
+    /// checked when the field is written.
+    pub max_bytes: Option<u64>,
+    /// Set for the fields that refer to another entity with `@onDelete`: what happens to the
+    /// instance when the instance that it refers to is deleted.
+    pub on_delete: Option<OnDelete>,
+    /// Collation of a string field (`@collate`), which affects its uniqueness, the filters that
+    /// compare it and the sorting by it.
+    pub collation: Option<Collation>,
     // We want to keep the default the user gave us so we can
     // return it in `chisel describe`. That's the default that is
     // valid in typescriptland.
@@ -596,6 +700,7 @@ impl Field {
         default: Option<String>,
         is_optional: bool,
         is_unique: bool,
+        is_version: bool,
     ) -> Self {
         let effective_default = if let Type::Boolean = &desc.ty() {
             default
@@ -616,6 +721,63 @@ impl Field {
             effective_default,
             is_optional,
             is_unique,
+            is_version,
+            count: None,
+            aggregate: None,
+            timestamp: None,
+            enum_type: None,
+            max_bytes: None,
+            on_delete: None,
+            collation: None,
+        }
+    }
+
+    pub fn with_count(mut self, count: Option<CountSpec>) -> Self {
+        self.count = count;
+        self
+    }
+
+    pub fn with_aggregate(mut self, aggregate: Option<AggregateFieldSpec>) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Option<Timestamp>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_enum_type(mut self, enum_type: Option<EnumSpec>) -> Self {
+        self.enum_type = enum_type;
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn with_on_delete(mut self, on_delete: Option<OnDelete>) -> Self {
+        self.on_delete = on_delete;
+        self
+    }
+
+    pub fn with_collation(mut self, collation: Option<Collation>) -> Self {
+        self.collation = collation;
+        self
+    }
+
+    /// Returns true if the values of the field are compared case-insensitively.
+    pub fn is_nocase(&self) -> bool {
+        self.collation == Some(Collation::NoCase)
+    }
+
+    /// Returns the name of the entity that the field refers to, if it is a nested entity or an
+    /// `Id<>`.
+    pub fn referred_entity(&self) -> Option<&str> {
+        match &self.type_id {
+            TypeId::Entity { name, .. } | TypeId::EntityId(name) => Some(name),
+            _ => None,
         }
     }
 
@@ -644,12 +806,227 @@ impl Field {
     }
 }
 
+/// Definition of a `@count` field: it holds the number of instances of `entity` whose `field`
+/// refers to the instance that holds the count.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CountSpec {
+    pub entity: String,
+    pub field: String,
+}
+
+/// A `@count` field resolved against the type system, see `TypeSystem::counters()`.
+#[derive(Clone, Debug)]
+pub struct Counter {
+    /// Entity that holds the `@count` field.
+    pub counting: Arc<ObjectType>,
+    /// Name of the `@count` field.
+    pub field: String,
+    /// Entity whose instances are counted.
+    pub counted: Arc<ObjectType>,
+    /// Field of `counted` that holds the id of the counting entity.
+    pub counted_field: String,
+}
+
+/// Definition of a materialized aggregate: an entity whose rows are computed by grouping the
+/// instances of `source` by the fields that are not aggregated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggregateSpec {
+    pub source: String,
+    /// If set, the aggregate is refreshed with this period. Otherwise, it is updated in the same
+    /// transaction as the instances of `source` are written.
+    pub refresh_interval_s: Option<u64>,
+}
+
+/// Aggregate function of a field of a materialized aggregate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregateFn {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFn {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AggregateFn::Count => "count",
+            AggregateFn::Sum => "sum",
+            AggregateFn::Avg => "avg",
+            AggregateFn::Min => "min",
+            AggregateFn::Max => "max",
+        }
+    }
+}
+
+impl std::str::FromStr for AggregateFn {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "count" => AggregateFn::Count,
+            "sum" => AggregateFn::Sum,
+            "avg" => AggregateFn::Avg,
+            "min" => AggregateFn::Min,
+            "max" => AggregateFn::Max,
+            _ => anyhow::bail!("unknown aggregate function `{}`", s),
+        })
+    }
+}
+
+/// Kind of a field that is added by `@timestamps`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timestamp {
+    /// Set when the object is inserted.
+    Created,
+    /// Set whenever the object is written.
+    Updated,
+}
+
+impl Timestamp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Timestamp::Created => "created",
+            Timestamp::Updated => "updated",
+        }
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "created" => Timestamp::Created,
+            "updated" => Timestamp::Updated,
+            _ => anyhow::bail!("unknown timestamp `{}`", s),
+        })
+    }
+}
+
+/// What happens to an instance when the instance that it refers to is deleted, set by `@onDelete`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnDelete {
+    /// The instance is deleted, too.
+    Cascade,
+    /// The deletion fails.
+    Restrict,
+    /// The reference is set to null (the field must be optional).
+    SetNull,
+}
+
+impl OnDelete {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OnDelete::Cascade => "cascade",
+            OnDelete::Restrict => "restrict",
+            OnDelete::SetNull => "setNull",
+        }
+    }
+}
+
+impl std::str::FromStr for OnDelete {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "cascade" => OnDelete::Cascade,
+            "restrict" => OnDelete::Restrict,
+            "setNull" => OnDelete::SetNull,
+            _ => anyhow::bail!("unknown @onDelete action `{}`", s),
+        })
+    }
+}
+
+/// How the values of a string field are compared, set by `@collate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Collation {
+    /// Byte-wise comparison, which is the default.
+    Binary,
+    /// Case-insensitive comparison. SQLite declares the column with `COLLATE NOCASE`, Postgres
+    /// compares the lowercase values (like the `citext` extension does).
+    NoCase,
+}
+
+impl Collation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Collation::Binary => "binary",
+            Collation::NoCase => "nocase",
+        }
+    }
+}
+
+impl std::str::FromStr for Collation {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "binary" => Collation::Binary,
+            "nocase" => Collation::NoCase,
+            _ => anyhow::bail!("unknown @collate collation `{}`", s),
+        })
+    }
+}
+
+/// A field with `@onDelete` resolved against the type system, see `TypeSystem::references_to()`.
+#[derive(Clone, Debug)]
+pub struct Reference {
+    /// Entity that holds the field.
+    pub referring: Arc<ObjectType>,
+    /// Name of the field, which holds the id of the referred instance.
+    pub field: String,
+    pub on_delete: OnDelete,
+}
+
+/// TypeScript string enum that is the type of a field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumSpec {
+    pub name: String,
+    /// The values of the enum, in the order of declaration.
+    pub values: Vec<String>,
+}
+
+impl EnumSpec {
+    pub fn contains(&self, value: &str) -> bool {
+        self.values.iter().any(|v| v == value)
+    }
+}
+
+/// Definition of an aggregated field of a materialized aggregate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggregateFieldSpec {
+    pub function: AggregateFn,
+    /// Field of the source entity, `None` for `AggregateFn::Count`.
+    pub source_field: Option<String>,
+}
+
+/// A materialized aggregate resolved against the type system, see `TypeSystem::aggregators()`.
+#[derive(Clone, Debug)]
+pub struct Aggregator {
+    pub aggregate: Arc<ObjectType>,
+    pub source: Arc<ObjectType>,
+}
+
+impl Aggregator {
+    /// Returns true if the aggregate is updated on every write of the source.
+    pub fn is_on_write(&self) -> bool {
+        self.aggregate
+            .aggregate()
+            .map_or(false, |spec| spec.refresh_interval_s.is_none())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FieldAttrDelta {
     pub type_id: TypeId,
     pub default: Option<String>,
     pub is_optional: bool,
     pub is_unique: bool,
+    pub is_version: bool,
+    pub count: Option<CountSpec>,
+    pub aggregate: Option<AggregateFieldSpec>,
+    pub timestamp: Option<Timestamp>,
+    pub enum_type: Option<EnumSpec>,
+    pub max_bytes: Option<u64>,
+    pub on_delete: Option<OnDelete>,
+    pub collation: Option<Collation>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -659,13 +1036,46 @@ pub struct FieldDelta {
     pub labels: Option<Vec<String>>,
 }
 
+/// Field that is renamed with `@renamedFrom`, keeping its data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldRename {
+    pub id: i32,
+    pub old_name: String,
+    pub new_name: String,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ObjectDelta {
     pub added_fields: Vec<Field>,
     pub removed_fields: Vec<Field>,
     pub updated_fields: Vec<FieldDelta>,
+    pub renamed_fields: Vec<FieldRename>,
     pub added_indexes: Vec<DbIndex>,
     pub removed_indexes: Vec<DbIndex>,
+    /// Aggregate definition of the new type.
+    pub aggregate: Option<AggregateSpec>,
+    /// Whether the new type is locked.
+    pub locked: bool,
+    /// Owner field of the new type.
+    pub owned_by: Option<String>,
+}
+
+impl ObjectDelta {
+    /// Returns true if the delta changes the fields, the aggregate definition or the owner field
+    /// of `old_type`.
+    /// Indexes are not considered, because they don't change the data and they may be inferred
+    /// automatically.
+    pub fn changes_definition(&self, old_type: &ObjectType) -> bool {
+        !self.added_fields.is_empty()
+            || !self.removed_fields.is_empty()
+            || !self.renamed_fields.is_empty()
+            || self
+                .updated_fields
+                .iter()
+                .any(|f| f.attrs.is_some() || f.labels.is_some())
+            || self.aggregate.as_ref() != old_type.aggregate()
+            || self.owned_by.as_deref() != old_type.owned_by()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -1,8 +1,9 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 use super::{
-    BuiltinTypes, DbIndex, Entity, FieldAttrDelta, FieldDelta, FieldMap, ObjectDelta, ObjectType,
-    QueryEngine, QueryPlan, Type, TypeId, TypeSystemError,
+    Aggregator, BuiltinTypes, Counter, DbIndex, Entity, FieldAttrDelta, FieldDelta, FieldMap,
+    FieldRename, ObjectDelta, ObjectType, QueryEngine, QueryPlan, Reference, Type, TypeId,
+    TypeSystemError,
 };
 use anyhow::Context;
 use futures::StreamExt;
@@ -45,11 +46,54 @@ impl TypeSystem {
         Ok(())
     }
 
+    /// Renames the custom type `old_name` to `new_name` (for `@renamedFrom` on an entity), keeping
+    /// its id and backing table. The fields, `@count` fields and aggregates that refer to the type
+    /// are changed to refer to the new name.
+    pub fn rename_custom_type(
+        &mut self,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), TypeSystemError> {
+        let old = self
+            .custom_types
+            .remove(old_name)
+            .ok_or_else(|| TypeSystemError::NoSuchType(old_name.to_owned()))?;
+        let mut renamed = (*old).clone();
+        renamed.name = new_name.to_owned();
+        self.custom_types
+            .insert(new_name.to_owned(), Entity::Custom(Arc::new(renamed)));
+
+        for ty in self.custom_types.values_mut() {
+            let mut changed = (**ty).clone();
+            for field in changed.fields.iter_mut() {
+                match &mut field.type_id {
+                    TypeId::Entity { name, .. } | TypeId::EntityId(name) if *name == old_name => {
+                        *name = new_name.to_owned();
+                    }
+                    _ => {}
+                }
+                if let Some(count) = field.count.as_mut().filter(|c| c.entity == old_name) {
+                    count.entity = new_name.to_owned();
+                }
+            }
+            if let Some(aggregate) = changed.aggregate.as_mut().filter(|a| a.source == old_name) {
+                aggregate.source = new_name.to_owned();
+            }
+            *ty = Entity::Custom(Arc::new(changed));
+        }
+        Ok(())
+    }
+
     /// Generate an [`ObjectDelta`] with the necessary information to evolve a specific type.
+    ///
+    /// `renames` maps the new names of the fields marked with `@renamedFrom` to their old names.
+    /// A field is renamed only if the old type has the old name and not the new one, so the
+    /// hint is ignored once the rename has been applied.
     pub fn generate_type_delta(
         &self,
         old_type: &ObjectType,
         new_type: Arc<ObjectType>,
+        renames: &HashMap<String, String>,
         allow_unsafe_replacement: bool,
     ) -> Result<ObjectDelta, TypeSystemError> {
         if *old_type != *new_type {
@@ -68,11 +112,26 @@ impl TypeSystem {
         let mut added_fields = Vec::new();
         let mut removed_fields = Vec::new();
         let mut updated_fields = Vec::new();
+        let mut renamed_fields = Vec::new();
 
         for (name, field) in new_fields.map.iter() {
-            match old_fields.map.remove(name) {
+            let old = match old_fields.map.remove(name) {
+                Some(old) => Some(old),
+                None => renames
+                    .get(*name)
+                    .filter(|old_name| !new_fields.map.contains_key(old_name.as_str()))
+                    .and_then(|old_name| old_fields.map.remove(old_name.as_str())),
+            };
+            match old {
                 None => {
-                    if !allow_unsafe_replacement && field.default.is_none() && !field.is_optional {
+                    // `@count` fields are computed from the existing data and `@timestamps` fields
+                    // are backfilled with the time of the migration, so they need no default
+                    if !allow_unsafe_replacement
+                        && field.default.is_none()
+                        && !field.is_optional
+                        && field.count.is_none()
+                        && field.timestamp.is_none()
+                    {
                         return Err(TypeSystemError::UnsafeReplacement(new_type.name.clone(), format!("Trying to add a new non-optional field ({}) without a trivial default value. Consider adding a default value or making it optional to make the types compatible", field.name)));
                     }
                     added_fields.push(field.to_owned().clone());
@@ -112,16 +171,44 @@ impl TypeSystem {
                         ));
                     }
 
+                    if !allow_unsafe_replacement && field.collation != old.collation {
+                        // SQLite can't change the collation of an existing column, the table would
+                        // have to be rebuilt
+                        return Err(TypeSystemError::UnsafeReplacement(
+                            new_type.name.clone(),
+                            format!(
+                                "changing the collation of field {}. Incompatible change",
+                                field.name,
+                            ),
+                        ));
+                    }
+
                     let attrs = if field.default != old.default
                         || field_ty != old_ty
                         || field.is_optional != old.is_optional
                         || field.is_unique != old.is_unique
+                        || field.is_version != old.is_version
+                        || field.count != old.count
+                        || field.aggregate != old.aggregate
+                        || field.timestamp != old.timestamp
+                        || field.enum_type != old.enum_type
+                        || field.max_bytes != old.max_bytes
+                        || field.on_delete != old.on_delete
+                        || field.collation != old.collation
                     {
                         Some(FieldAttrDelta {
                             type_id: field.type_id.clone(),
                             default: field.default.clone(),
                             is_optional: field.is_optional,
                             is_unique: field.is_unique,
+                            is_version: field.is_version,
+                            count: field.count.clone(),
+                            aggregate: field.aggregate.clone(),
+                            timestamp: field.timestamp,
+                            enum_type: field.enum_type.clone(),
+                            max_bytes: field.max_bytes,
+                            on_delete: field.on_delete,
+                            collation: field.collation,
                         })
                     } else {
                         None
@@ -144,6 +231,13 @@ impl TypeSystem {
                             "logical error! updating field without id".to_string(),
                         )
                     })?;
+                    if old.name != field.name {
+                        renamed_fields.push(FieldRename {
+                            id,
+                            old_name: old.name.clone(),
+                            new_name: field.name.clone(),
+                        });
+                    }
                     updated_fields.push(FieldDelta { id, attrs, labels });
                 }
             }
@@ -167,8 +261,12 @@ impl TypeSystem {
             added_fields,
             removed_fields,
             updated_fields,
+            renamed_fields,
             added_indexes: Self::find_added_indexes(old_type, &new_type),
             removed_indexes: Self::find_removed_indexes(old_type, &new_type),
+            aggregate: new_type.aggregate().cloned(),
+            locked: new_type.is_locked(),
+            owned_by: new_type.owned_by().map(ToOwned::to_owned),
         })
     }
 
@@ -189,6 +287,86 @@ impl TypeSystem {
             .collect()
     }
 
+    /// Returns all `@count` fields of the custom types, sorted by the entity and field name.
+    pub fn counters(&self) -> Vec<Counter> {
+        let mut counters = vec![];
+        for ty in self.custom_types.values() {
+            for field in ty.user_fields() {
+                let count = match &field.count {
+                    Some(count) => count,
+                    None => continue,
+                };
+                // counts of entities that do not exist are rejected by apply
+                if let Ok(counted) = self.lookup_custom_type(&count.entity) {
+                    counters.push(Counter {
+                        counting: ty.object_type().clone(),
+                        field: field.name.clone(),
+                        counted: counted.object_type().clone(),
+                        counted_field: count.field.clone(),
+                    });
+                }
+            }
+        }
+        counters.sort_by(|a, b| (a.counting.name(), &a.field).cmp(&(b.counting.name(), &b.field)));
+        counters
+    }
+
+    /// Returns the `@count` fields that count the instances of the entity `counted_name`.
+    pub fn counters_of(&self, counted_name: &str) -> Vec<Counter> {
+        let mut counters = self.counters();
+        counters.retain(|counter| counter.counted.name() == counted_name);
+        counters
+    }
+
+    /// Returns the `@onDelete` fields that refer to the entity `referred_name`, sorted by name.
+    pub fn references_to(&self, referred_name: &str) -> Vec<Reference> {
+        let mut references = vec![];
+        for ty in self.custom_types.values() {
+            for field in ty.user_fields() {
+                let on_delete = match field.on_delete {
+                    Some(on_delete) => on_delete,
+                    None => continue,
+                };
+                if field.referred_entity() == Some(referred_name) {
+                    references.push(Reference {
+                        referring: ty.object_type().clone(),
+                        field: field.name.clone(),
+                        on_delete,
+                    });
+                }
+            }
+        }
+        references
+            .sort_by(|a, b| (a.referring.name(), &a.field).cmp(&(b.referring.name(), &b.field)));
+        references
+    }
+
+    /// Returns all materialized aggregates, sorted by name.
+    pub fn aggregators(&self) -> Vec<Aggregator> {
+        let mut aggregators = vec![];
+        for ty in self.custom_types.values() {
+            if let Some(spec) = ty.aggregate() {
+                // aggregates of entities that do not exist are rejected by apply
+                if let Ok(source) = self.lookup_custom_type(&spec.source) {
+                    aggregators.push(Aggregator {
+                        aggregate: ty.object_type().clone(),
+                        source: source.object_type().clone(),
+                    });
+                }
+            }
+        }
+        aggregators.sort_by(|a, b| a.aggregate.name().cmp(b.aggregate.name()));
+        aggregators
+    }
+
+    /// Returns the materialized aggregates of the entity `source_name` that are updated on every
+    /// write of the entity.
+    pub fn aggregators_of(&self, source_name: &str) -> Vec<Aggregator> {
+        let mut aggregators = self.aggregators();
+        aggregators.retain(|agg| agg.source.name() == source_name && agg.is_on_write());
+        aggregators
+    }
+
     /// Looks up a builtin type with name `type_name`.
     pub fn lookup_builtin_type(&self, type_name: &str) -> Result<Type, TypeSystemError> {
         let extract_type = |typ: &str| -> Option<&str> {
@@ -203,6 +381,13 @@ impl TypeSystem {
             return Ok(Type::Array(Box::new(element_type)));
         } else if let Some(entity_name) = extract_type("Id") {
             return Ok(Type::EntityId(entity_name.to_owned()));
+        } else if let Some(params) = extract_type("Decimal") {
+            let parse = |param: Option<&str>| param.and_then(|p| p.trim().parse::<u32>().ok());
+            let mut params = params.split(',');
+            return match (parse(params.next()), parse(params.next()), params.next()) {
+                (Some(precision), Some(scale), None) => Ok(Type::Decimal { precision, scale }),
+                _ => Err(TypeSystemError::NotABuiltinType(type_name.to_string())),
+            };
         }
         self.builtin
             .types
@@ -288,6 +473,9 @@ impl TypeSystem {
             | TypeId::Id
             | TypeId::JsDate
             | TypeId::ArrayBuffer
+            | TypeId::Json
+            | TypeId::GeoPoint
+            | TypeId::Decimal { .. }
             | TypeId::Array(_) => self.lookup_builtin_type(&ty.name()),
             TypeId::Entity { name, version_id } => {
                 if version_id == "__chiselstrike" {
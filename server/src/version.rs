@@ -1,19 +1,29 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+use crate::contract::RouteExample;
 use crate::http::HttpRequestResponse;
-use crate::kafka::KafkaEvent;
+use crate::kafka::{EventFilter, KafkaEvent};
 use crate::policies::PolicySystem;
+use crate::seed::SeedJob;
 use crate::server::Server;
+use crate::socket::SocketEvent;
+use crate::static_files::StaticFiles;
 use crate::types::TypeSystem;
-use crate::worker::{self, WorkerInit};
-use anyhow::{bail, Result};
-use futures::stream::{FuturesUnordered, TryStreamExt};
-use serde::Serialize;
+use crate::worker_pool::{self, WorkerPoolConfig, WorkerPoolMetrics, WorkerSpawner};
+use anyhow::{anyhow, bail, Result};
+use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::{mpsc, oneshot};
 use tokio::task;
-use utils::{CancellableTaskHandle, TaskHandle};
+use utils::{CancellableTaskHandle, ImportMap, TaskHandle};
 
 pub struct VersionInit {
     pub version_id: String,
@@ -21,11 +31,17 @@ pub struct VersionInit {
     pub server: Arc<Server>,
     /// Module map (see `ModuleLoader`).
     pub modules: Arc<HashMap<String, String>>,
+    /// Files of the `static/` directory of the project.
+    pub static_files: Arc<StaticFiles>,
+    /// Hosts that the workers may connect to, `None` if the network access is not restricted.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Import map that resolves the bare specifiers of the modules.
+    pub import_map: Arc<ImportMap>,
     pub type_system: Arc<TypeSystem>,
     pub policy_system: Arc<PolicySystem>,
     /// Sources for the type policies
     pub policy_sources: Arc<HashMap<String, Box<[u8]>>>,
-    pub worker_count: usize,
+    pub worker_pool: WorkerPoolConfig,
     /// We will signal you on this channel when all workers in the version are ready to accept
     /// jobs.
     pub ready_tx: oneshot::Sender<()>,
@@ -48,9 +64,155 @@ pub struct Version {
     pub version_id: String,
     pub info: VersionInfo,
     pub type_system: Arc<TypeSystem>,
-    pub policy_system: Arc<PolicySystem>,
+    /// Module map that the workers execute (see `ModuleLoader`).
+    pub modules: Arc<HashMap<String, String>>,
+    /// Static files that are served at `/{version}/static/` (see `static_files`).
+    pub static_files: Arc<StaticFiles>,
+    /// Hosts that the workers may connect to, `None` if the network access is not restricted
+    /// (see `worker::net_permission()`).
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Import map that resolves the bare specifiers of the modules (see `ModuleLoader`).
+    pub import_map: Arc<ImportMap>,
+    /// Policies of the version, which can be replaced while the version is running (see
+    /// `update_policies()`).
+    policies: RwLock<VersionPolicies>,
+    /// Routes defined by the user, as reported by JavaScript when the workers start up.
+    pub routes: RwLock<Vec<RouteInfo>>,
+    /// Filters of the event handlers of every Kafka topic, as reported by JavaScript when the
+    /// workers start up. Filters are indexed in the same way as the handlers in the `TopicMap`.
+    pub event_filters: RwLock<HashMap<String, Vec<Option<EventFilter>>>>,
+    /// Names of the seed scripts, as reported by JavaScript when the workers start up.
+    pub seeds: RwLock<Vec<String>>,
+    /// Route examples registered with `RouteMap.example()`, as reported by JavaScript when the
+    /// workers start up (see `contract.rs`).
+    pub examples: RwLock<Vec<RouteExample>>,
+    pub worker_pool: WorkerPoolConfig,
+    pub pool_metrics: WorkerPoolMetrics,
+}
+
+/// Policies of a version.
+#[derive(Clone)]
+pub struct VersionPolicies {
+    pub system: Arc<PolicySystem>,
     /// Type policies sources
-    pub policy_sources: Arc<HashMap<String, Box<[u8]>>>,
+    pub sources: Arc<HashMap<String, Box<[u8]>>>,
+    /// Incremented every time the policies are updated, so that workers know when to recompile
+    /// the type policies.
+    pub generation: u64,
+}
+
+/// Route of a version that is described in the OpenAPI document (see `openapi.rs`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteInfo {
+    pub methods: Vec<String>,
+    /// Path pattern in the `URLPattern` syntax.
+    pub path_pattern: String,
+    /// Set for routes that were created by `crud()`.
+    pub crud: Option<CrudRouteInfo>,
+    /// Set for routes that were created by `ingest()`.
+    #[serde(default)]
+    pub ingest: Option<IngestRouteInfo>,
+    /// True for routes that were created by `upload()`.
+    #[serde(default)]
+    pub upload: bool,
+    /// Deadline of the requests to the route (in seconds), set by `RouteMap.timeout()`.
+    #[serde(default)]
+    pub timeout_s: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrudRouteInfo {
+    /// Kind of the CRUD handler (`GetOne`, `GetMany`, `PostOne`, ...).
+    pub kind: String,
+    pub entity_name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestRouteInfo {
+    pub entity_name: String,
+}
+
+impl Version {
+    pub fn policy_system(&self) -> Arc<PolicySystem> {
+        self.policies.read().system.clone()
+    }
+
+    pub fn policies(&self) -> VersionPolicies {
+        self.policies.read().clone()
+    }
+
+    /// Replaces the policies of the running version. Workers pick up the new type policies before
+    /// they accept their next job.
+    pub fn update_policies(
+        &self,
+        system: Arc<PolicySystem>,
+        sources: Arc<HashMap<String, Box<[u8]>>>,
+    ) {
+        let mut policies = self.policies.write();
+        policies.system = system;
+        policies.sources = sources;
+        policies.generation += 1;
+    }
+
+    /// Returns the URL and code of the module with the given content hash (see `module_hash()`).
+    pub fn module_by_hash(&self, hash: &str) -> Option<(&str, &str)> {
+        self.modules
+            .iter()
+            .find(|(_, code)| module_hash(code) == hash)
+            .map(|(url, code)| (url.as_str(), code.as_str()))
+    }
+
+    /// Returns true if the request should be handled by an `ingest()` or `upload()` route, so its
+    /// body should be streamed to the worker instead of being read into memory.
+    pub fn is_streamed_request(&self, method: &str, routing_path: &str) -> bool {
+        self.routes.read().iter().any(|route| {
+            (route.ingest.is_some() || route.upload)
+                && route.methods.iter().any(|m| m == method)
+                && path_matches(&route.path_pattern, routing_path)
+        })
+    }
+
+    /// Returns the deadline of the requests to the first route that matches, if the route has
+    /// its own deadline (otherwise, `--request-timeout-s` applies).
+    pub fn route_timeout(&self, method: &str, routing_path: &str) -> Option<Duration> {
+        self.routes
+            .read()
+            .iter()
+            .find(|route| {
+                route.methods.iter().any(|m| m == method || m == "*")
+                    && path_matches(&route.path_pattern, routing_path)
+            })
+            .and_then(|route| route.timeout_s)
+            .filter(|timeout_s| timeout_s.is_finite() && *timeout_s > 0.)
+            .map(Duration::from_secs_f64)
+    }
+}
+
+/// Content hash of a module: the hex-encoded SHA-256 of its code.
+pub fn module_hash(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+/// Matches `path` against a `URLPattern` path pattern. Only named groups (`:name`), a trailing
+/// wildcard (`*`) and a trailing group that matches the rest of the path (`:name(.*)`, which is
+/// used by the file-based routes) are supported, which is enough for the routes created by
+/// `RouteMap.prefix()`.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern_segments = pattern.trim_end_matches('/').split('/');
+    let mut path_segments = path.trim_end_matches('/').split('/');
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (None, None) => return true,
+            (Some("*"), _) => return true,
+            (Some(p), _) if p.starts_with(':') && p.ends_with("(.*)") => return true,
+            (Some(p), Some(s)) if p.starts_with(':') && !s.is_empty() => continue,
+            (Some(p), Some(s)) if p == s => continue,
+            _ => return false,
+        }
+    }
 }
 
 /// A job that should be handled by a version (more precisely, by one of the workers in the
@@ -60,6 +222,51 @@ pub enum VersionJob {
     Http(HttpRequestResponse),
     Kafka(KafkaEvent),
     Outbox,
+    Socket(SocketEvent),
+    Seed(SeedJob),
+}
+
+/// Key that pins HTTP requests to workers (see `--worker-affinity`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkerAffinity {
+    /// Pin requests by the id of the logged-in user.
+    User,
+    /// Pin requests by the value of a header (the name is lowercase).
+    Header(String),
+}
+
+impl WorkerAffinity {
+    pub fn parse(spec: &str) -> Result<Self> {
+        match spec.split_once(':') {
+            None if spec == "user" => Ok(WorkerAffinity::User),
+            Some(("header", name)) if !name.is_empty() => {
+                Ok(WorkerAffinity::Header(name.to_ascii_lowercase()))
+            }
+            _ => Err(anyhow!(
+                "Invalid worker affinity {:?}, expected `user` or `header:<name>`",
+                spec
+            )),
+        }
+    }
+
+    /// Returns the index of the worker that the job is pinned to, if any.
+    pub(crate) fn pinned_worker(&self, job: &VersionJob, worker_count: usize) -> Option<usize> {
+        let request = match job {
+            VersionJob::Http(req) => &req.request,
+            _ => return None,
+        };
+        let key = match self {
+            WorkerAffinity::User => request.ctx.user_id.as_deref()?,
+            WorkerAffinity::Header(name) => request
+                .headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())?,
+        };
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Some((hasher.finish() % worker_count as u64) as usize)
+    }
 }
 
 pub async fn spawn(
@@ -74,8 +281,21 @@ pub async fn spawn(
         version_id: init.version_id.clone(),
         info: init.info.clone(),
         type_system: init.type_system.clone(),
-        policy_system: init.policy_system.clone(),
-        policy_sources: init.policy_sources.clone(),
+        modules: init.modules.clone(),
+        static_files: init.static_files.clone(),
+        allowed_hosts: init.allowed_hosts.clone(),
+        import_map: init.import_map.clone(),
+        policies: RwLock::new(VersionPolicies {
+            system: init.policy_system.clone(),
+            sources: init.policy_sources.clone(),
+            generation: 0,
+        }),
+        routes: RwLock::new(Vec::new()),
+        event_filters: RwLock::new(HashMap::new()),
+        seeds: RwLock::new(Vec::new()),
+        examples: RwLock::new(Vec::new()),
+        worker_pool: init.worker_pool.clone(),
+        pool_metrics: WorkerPoolMetrics::default(),
     });
     let task = CancellableTaskHandle(task::spawn(run(init, version.clone(), job_rx)));
     Ok((version, job_tx, task))
@@ -84,31 +304,37 @@ pub async fn spawn(
 async fn run(
     init: VersionInit,
     version: Arc<Version>,
-    mut job_rx: mpsc::Receiver<VersionJob>,
+    job_rx: mpsc::Receiver<VersionJob>,
 ) -> Result<()> {
     let worker_ready_rxs = FuturesUnordered::new();
     let mut worker_job_txs = Vec::new();
-    let worker_handles = FuturesUnordered::new();
-
-    // spawn all workers for this version
-    for worker_idx in 0..init.worker_count {
-        let (worker_ready_tx, worker_ready_rx) = oneshot::channel();
-        let (worker_job_tx, worker_job_rx) = mpsc::channel(1);
-        let worker_handle = worker::spawn(WorkerInit {
-            worker_idx,
-            server: init.server.clone(),
-            version: version.clone(),
-            modules: init.modules.clone(),
-            ready_tx: worker_ready_tx,
-            job_rx: worker_job_rx,
-        })
-        .await?;
+    let mut worker_handles = FuturesUnordered::new();
 
+    // workers of an autoscaled pool report when they are idle
+    let worker_pool = init.worker_pool.clone();
+    let (idle_tx, idle_rx) = if worker_pool.is_autoscaled() {
+        let (idle_tx, idle_rx) = mpsc::unbounded_channel();
+        (Some(idle_tx), Some(idle_rx))
+    } else {
+        (None, None)
+    };
+    let spawner = WorkerSpawner {
+        server: init.server.clone(),
+        version: version.clone(),
+        modules: init.modules.clone(),
+        idle_tx,
+    };
+
+    // spawn the initial workers for this version
+    for worker_idx in 0..worker_pool.min_workers {
+        let (worker_ready_rx, worker_job_tx, worker_handle) = spawner.spawn(worker_idx).await?;
         worker_ready_rxs.push(worker_ready_rx);
         worker_job_txs.push(worker_job_tx);
         worker_handles.push(worker_handle);
     }
+    version.pool_metrics.set_workers(worker_job_txs.len());
 
+    let worker_affinity = init.server.worker_affinity.clone();
     let ready_tx = init.ready_tx;
     let is_canary = init.is_canary;
     let version_id = version.version_id.clone();
@@ -131,12 +357,78 @@ async fn run(
         Ok(())
     }));
 
-    let version_id = version.version_id.clone();
-    let job_task = TaskHandle(task::spawn(async move {
+    // workers that are started by the autoscaling are joined together with the initial workers
+    let (handle_tx, mut handle_rx) = mpsc::unbounded_channel();
+    let job_task = match idle_rx {
+        Some(idle_rx) => TaskHandle(task::spawn(worker_pool::dispatch(
+            worker_pool,
+            spawner,
+            worker_job_txs,
+            job_rx,
+            idle_rx,
+            handle_tx,
+        ))),
+        None => {
+            drop(handle_tx);
+            let version_id = version.version_id.clone();
+            dispatch_round_robin(version_id, worker_affinity, worker_job_txs, job_rx)
+        }
+    };
+
+    let join_task = TaskHandle(task::spawn(async move {
+        // join all spawned workers
+        loop {
+            tokio::select! {
+                Some(worker_handle) = handle_rx.recv() => worker_handles.push(worker_handle),
+                Some(res) = worker_handles.next() => res?,
+                else => break,
+            }
+        }
+        Ok(())
+    }));
+
+    tokio::try_join!(ready_task, job_task, join_task)?;
+    Ok(())
+}
+
+/// Distributes the jobs among a fixed number of workers.
+fn dispatch_round_robin(
+    version_id: String,
+    worker_affinity: Option<WorkerAffinity>,
+    worker_job_txs: Vec<mpsc::Sender<VersionJob>>,
+    mut job_rx: mpsc::Receiver<VersionJob>,
+) -> TaskHandle<Result<()>> {
+    TaskHandle(task::spawn(async move {
         // distribute jobs among workers in a round-robin fashion
         // TODO: we should perhaps be more clever than round-robin
-        let mut worker_i = 0;
-        while let Some(job) = job_rx.recv().await {
+        let mut next_worker_i = 0;
+        while let Some(mut job) = job_rx.recv().await {
+            // requests pinned by the worker affinity go to their worker, unless the worker is
+            // busy, in which case we fall back to round-robin instead of waiting
+            let pinned_worker_i = worker_affinity
+                .as_ref()
+                .and_then(|affinity| affinity.pinned_worker(&job, worker_job_txs.len()));
+            if let Some(worker_i) = pinned_worker_i {
+                match worker_job_txs[worker_i].try_send(job) {
+                    Ok(()) => continue,
+                    Err(TrySendError::Full(returned_job)) => job = returned_job,
+                    Err(TrySendError::Closed(_)) => bail!(
+                        "Worker {:?} {} is unable to accept jobs",
+                        version_id,
+                        worker_i
+                    ),
+                }
+            }
+
+            let worker_i = match job {
+                // all events of a socket must be handled by the same worker, in order
+                VersionJob::Socket(ref event) => event.socket_id as usize % worker_job_txs.len(),
+                _ => {
+                    let worker_i = next_worker_i;
+                    next_worker_i = (next_worker_i + 1) % worker_job_txs.len();
+                    worker_i
+                }
+            };
             if worker_job_txs[worker_i].send(job).await.is_err() {
                 bail!(
                     "Worker {:?} {} is unable to accept jobs",
@@ -144,16 +436,47 @@ async fn run(
                     worker_i
                 );
             }
-            worker_i = (worker_i + 1) % worker_job_txs.len();
         }
         Ok(())
-    }));
+    }))
+}
 
-    let join_task = TaskHandle(task::spawn(async move {
-        // join all spawned workers
-        worker_handles.try_collect::<()>().await
-    }));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    tokio::try_join!(ready_task, job_task, join_task)?;
-    Ok(())
+    #[test]
+    fn match_paths() {
+        assert!(path_matches("/import/", "/import"));
+        assert!(path_matches("/import", "/import/"));
+        assert!(path_matches("/orgs/:org/import/", "/orgs/acme/import"));
+        assert!(path_matches("/files/*", "/files/a/b"));
+        assert!(!path_matches("/import/", "/import/people"));
+        assert!(!path_matches("/orgs/:org/import", "/orgs//import"));
+        assert!(!path_matches("/import/people", "/import"));
+        assert!(path_matches("/slow/:legacyPathParams(.*)", "/slow"));
+        assert!(path_matches("/slow/:legacyPathParams(.*)", "/slow/a/b"));
+        assert!(!path_matches("/slow/:legacyPathParams(.*)", "/slower"));
+    }
+
+    #[test]
+    fn hash_modules() {
+        assert_eq!(
+            module_hash(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_ne!(module_hash("a"), module_hash("b"));
+    }
+
+    #[test]
+    fn parse_worker_affinity() {
+        assert_eq!(WorkerAffinity::parse("user").unwrap(), WorkerAffinity::User);
+        assert_eq!(
+            WorkerAffinity::parse("header:X-Session").unwrap(),
+            WorkerAffinity::Header("x-session".into())
+        );
+        assert!(WorkerAffinity::parse("header:").is_err());
+        assert!(WorkerAffinity::parse("users").is_err());
+        assert!(WorkerAffinity::parse("cookie:session").is_err());
+    }
 }
@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Termination of JavaScript that keeps running after its job was cancelled.
+//!
+//! Cancelling a job (see `cancel.rs`) aborts its pending ops and the `AbortSignal` of the
+//! request, but a handler that spins in a loop never yields to the event loop, so it never
+//! notices. The watchdog of a worker waits for the cancellation of the job that the worker is
+//! running, and if the job is still running `TERMINATE_GRACE` later, it terminates the execution
+//! of the V8 isolate. The worker then starts a new JavaScript runtime (see `worker::run()`).
+
+use crate::cancel::CancelToken;
+use deno_core::v8;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a cancelled job may keep running before its execution is terminated.
+const TERMINATE_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct Watchdog {
+    isolate: Option<v8::IsolateHandle>,
+    /// Sequence number of the last job that the worker started.
+    last_job: u64,
+    /// Sequence number of the job that the worker is running, 0 when it is idle.
+    running_job: Arc<AtomicU64>,
+    terminated: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    /// Sets the isolate that runs the jobs, which changes when the worker restarts.
+    pub fn set_isolate(&mut self, isolate: v8::IsolateHandle) {
+        self.isolate = Some(isolate);
+    }
+
+    /// Called when the worker starts a job. If the job can be cancelled, its execution is
+    /// terminated when it does not finish soon enough after it was cancelled.
+    pub fn job_started(&mut self, cancel: Option<CancelToken>) {
+        self.last_job += 1;
+        let job = self.last_job;
+        self.running_job.store(job, Ordering::SeqCst);
+
+        let (cancel, isolate) = match (cancel, self.isolate.clone()) {
+            (Some(cancel), Some(isolate)) => (cancel, isolate),
+            _ => return,
+        };
+        let running_job = self.running_job.clone();
+        let terminated = self.terminated.clone();
+        // the worker thread is blocked while the job spins, so the watchdog must run elsewhere
+        tokio::spawn(async move {
+            if !cancel.finished().await {
+                return;
+            }
+            tokio::time::sleep(TERMINATE_GRACE).await;
+            if running_job.load(Ordering::SeqCst) == job {
+                terminated.store(true, Ordering::SeqCst);
+                isolate.terminate_execution();
+            }
+        });
+    }
+
+    /// Called when the worker has finished its job.
+    pub fn job_finished(&self) {
+        self.running_job.store(0, Ordering::SeqCst);
+    }
+
+    /// Returns true if the watchdog terminated the isolate since the last call.
+    pub fn take_terminated(&self) -> bool {
+        self.terminated.swap(false, Ordering::SeqCst)
+    }
+}
@@ -1,13 +1,18 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+use crate::datastore::crud::CrudQueryError;
+use crate::datastore::engine::{ConflictError, RestrictError, ValidationError};
+use crate::datastore::ingest::IngestError;
 use crate::module_loader::ModuleLoader;
 use crate::ops;
 use crate::policy::engine::PolicyEngine;
 use crate::policy::PolicyError;
 use crate::server::Server;
 use crate::version::{Version, VersionJob};
-use anyhow::{bail, Context as _, Result};
+use crate::watchdog::Watchdog;
+use anyhow::{bail, ensure, Context as _, Result};
 use deno_core::url::Url;
+use deno_runtime::permissions::{NetDescriptor, Permissions, UnaryPermission};
 use futures::ready;
 use std::collections::HashMap;
 use std::future::Future;
@@ -30,6 +35,9 @@ pub struct WorkerInit {
     pub ready_tx: oneshot::Sender<()>,
     /// The worker will receive jobs from this channel.
     pub job_rx: mpsc::Receiver<VersionJob>,
+    /// If set, the worker sends its index on this channel whenever it waits for a job (see
+    /// `worker_pool.rs`).
+    pub idle_tx: Option<mpsc::UnboundedSender<usize>>,
 }
 
 /// Handle to a worker task and thread.
@@ -61,6 +69,8 @@ pub struct WorkerState {
     ///
     /// To wait on this channel, we temporarily move out of the `Option`.
     pub job_rx: Option<mpsc::Receiver<VersionJob>>,
+    /// Channel for reporting that the worker is idle (see `WorkerInit`).
+    pub idle_tx: Option<mpsc::UnboundedSender<usize>>,
 
     /// Fake environment variables.
     ///
@@ -70,6 +80,31 @@ pub struct WorkerState {
     /// The policy engine for that worker. The policy engine is not !Send + !Sync, therefore it
     /// cannot be part of the version.
     pub policy_engine: Rc<PolicyEngine>,
+    /// Generation of the version policies that `policy_engine` was compiled from.
+    pub policy_generation: u64,
+    /// Terminates the jobs that keep running after they were cancelled.
+    pub watchdog: Watchdog,
+}
+
+impl WorkerState {
+    /// Recompiles the type policies if the policies of the version were updated since they were
+    /// compiled.
+    pub fn refresh_policy_engine(&mut self) -> Result<()> {
+        let policies = self.version.policies();
+        if policies.generation != self.policy_generation {
+            self.policy_engine = Rc::new(compile_policies(&policies.sources)?);
+            self.policy_generation = policies.generation;
+        }
+        Ok(())
+    }
+}
+
+fn compile_policies(sources: &HashMap<String, Box<[u8]>>) -> Result<PolicyEngine> {
+    let policy_engine = PolicyEngine::new()?;
+    for (ty_name, code) in sources.iter() {
+        policy_engine.register_policy_from_code(ty_name.clone(), code)?;
+    }
+    Ok(policy_engine)
 }
 
 pub async fn spawn(init: WorkerInit) -> Result<WorkerJoinHandle> {
@@ -92,6 +127,74 @@ pub async fn spawn(init: WorkerInit) -> Result<WorkerJoinHandle> {
 }
 
 async fn run(init: WorkerInit) -> Result<()> {
+    let policies = init.version.policies();
+    let policy_engine = compile_policies(&policies.sources)?;
+    let mut worker_state = WorkerState {
+        worker_idx: init.worker_idx,
+        server: init.server.clone(),
+        version: init.version.clone(),
+        ready_tx: Some(init.ready_tx),
+        job_rx: Some(init.job_rx),
+        idle_tx: init.idle_tx,
+        fake_env: HashMap::new(),
+        policy_engine: Rc::new(policy_engine),
+        policy_generation: policies.generation,
+        watchdog: Watchdog::default(),
+    };
+
+    let mut main_url = Url::parse("chisel://api/main.js").unwrap();
+    // the `main_url` is given to the Deno `InspectorServer` when registering and is visible in
+    // `chrome://inspect`, so it is useful to add version and worker index to the URL in order to
+    // distinguish between different targets on the same inspector server
+    main_url
+        .query_pairs_mut()
+        .append_pair("version", &init.version.version_id)
+        .append_pair("worker", &init.worker_idx.to_string());
+
+    loop {
+        let mut worker = bootstrap_worker(&init.server, &init.version, &init.modules, &main_url)?;
+        let isolate = worker.js_runtime.v8_isolate().thread_safe_handle();
+        worker_state.watchdog.set_isolate(isolate);
+        worker.js_runtime.op_state().borrow_mut().put(worker_state);
+
+        // start executing the JavaScript code in main.js; this will return when the worker is
+        // terminated, any futher interaction with JavaScript is done exclusively using Deno ops
+        let result = worker.execute_main_module(&main_url).await;
+        worker_state = worker
+            .js_runtime
+            .op_state()
+            .borrow_mut()
+            .take::<WorkerState>();
+        drop(worker);
+        if !worker_state.watchdog.take_terminated() {
+            return result.context(format!(
+                "Error when executing JavaScript for version {:?} in worker {}",
+                init.version.version_id, init.worker_idx
+            ));
+        }
+
+        warn!(
+            "Worker {} of version {:?} was terminated because a request did not stop after it \
+            was cancelled, restarting the worker",
+            init.worker_idx, init.version.version_id
+        );
+        ensure!(
+            worker_state.job_rx.is_some(),
+            "Worker {:?} {} was terminated while it waited for a job",
+            init.version.version_id,
+            init.worker_idx
+        );
+        // the version has been ready since the first start, nobody waits for this signal
+        worker_state.ready_tx = Some(oneshot::channel().0);
+    }
+}
+
+fn bootstrap_worker(
+    server: &Arc<Server>,
+    version: &Arc<Version>,
+    modules: &Arc<HashMap<String, String>>,
+    main_url: &Url,
+) -> Result<deno_runtime::worker::MainWorker> {
     let bootstrap = deno_runtime::BootstrapOptions {
         user_agent: "chiseld".to_string(),
         args: vec![],
@@ -99,7 +202,7 @@ async fn run(init: WorkerInit) -> Result<()> {
         debug_flag: false,
         enable_testing_features: false,
         is_tty: false,
-        inspect: init.server.opt.inspect || init.server.opt.inspect_brk,
+        inspect: server.opt.inspect || server.opt.inspect_brk,
         // FIXME: make location a configuration parameter
         location: Some(Url::parse("https://chiselstrike.com").unwrap()),
         no_color: true,
@@ -109,7 +212,10 @@ async fn run(init: WorkerInit) -> Result<()> {
     };
 
     let extensions = vec![ops::extension()];
-    let module_loader = Rc::new(ModuleLoader::new(init.modules));
+    let module_loader = Rc::new(ModuleLoader::new(
+        modules.clone(),
+        version.import_map.clone(),
+    ));
     let create_web_worker_cb = Arc::new(|_| panic!("Web workers are not supported"));
     let web_worker_preload_module_cb = Arc::new(|_| panic!("Web workers are not supported"));
     let web_worker_pre_execute_module_cb = Arc::new(|_| panic!("Web workers are not supported"));
@@ -127,8 +233,8 @@ async fn run(init: WorkerInit) -> Result<()> {
         web_worker_pre_execute_module_cb,
         format_js_error_fn: None,
         source_map_getter: None,
-        maybe_inspector_server: init.server.inspector.clone(),
-        should_break_on_first_statement: init.server.opt.inspect_brk,
+        maybe_inspector_server: server.inspector.clone(),
+        should_break_on_first_statement: server.opt.inspect_brk,
         get_error_class_fn: Some(&get_error_class_name),
         origin_storage_dir: None,
         blob_store: Default::default(),
@@ -139,51 +245,56 @@ async fn run(init: WorkerInit) -> Result<()> {
         cache_storage_dir: None,
     };
 
-    use deno_runtime::permissions::Permissions;
     let permissions = Permissions {
-        // FIXME: Temporary hack to allow easier testing for now
-        net: Permissions::new_net(&Some(vec![]), false).unwrap(),
+        net: net_permission(version.allowed_hosts.as_deref(), server.opt.allow_all_net)?,
         ..Permissions::default()
     };
 
-    let mut main_url = Url::parse("chisel://api/main.js").unwrap();
-    // the `main_url` is given to the Deno `InspectorServer` when registering and is visible in
-    // `chrome://inspect`, so it is useful to add version and worker index to the URL in order to
-    // distinguish between different targets on the same inspector server
-    main_url
-        .query_pairs_mut()
-        .append_pair("version", &init.version.version_id)
-        .append_pair("worker", &init.worker_idx.to_string());
-
-    let mut worker = deno_runtime::worker::MainWorker::bootstrap_from_options(
+    Ok(deno_runtime::worker::MainWorker::bootstrap_from_options(
         main_url.clone(),
         permissions,
         options,
-    );
-
-    let policy_engine = PolicyEngine::new()?;
-
-    for (ty_name, code) in init.version.policy_sources.iter() {
-        policy_engine.register_policy_from_code(ty_name.clone(), code)?;
-    }
+    ))
+}
 
-    let worker_state = WorkerState {
-        worker_idx: init.worker_idx,
-        server: init.server,
-        version: init.version.clone(),
-        ready_tx: Some(init.ready_tx),
-        job_rx: Some(init.job_rx),
-        fake_env: HashMap::new(),
-        policy_engine: Rc::new(policy_engine),
+/// Returns the permission of the workers to connect to `allowed_hosts` (all hosts if `None` or
+/// with `--allow-all-net`). The hosts must be valid (see `validate_allowed_host()`).
+pub fn net_permission(
+    allowed_hosts: Option<&[String]>,
+    allow_all: bool,
+) -> Result<UnaryPermission<NetDescriptor>> {
+    // Deno allows all hosts with an empty list and denies all hosts with `None`
+    let hosts = match allowed_hosts {
+        _ if allow_all => Some(vec![]),
+        None => Some(vec![]),
+        Some([]) => None,
+        Some(hosts) => Some(hosts.to_vec()),
     };
-    worker.js_runtime.op_state().borrow_mut().put(worker_state);
+    Permissions::new_net(&hosts, false)
+}
 
-    // start executing the JavaScript code in main.js; this will return when the worker is
-    // terminated, any futher interaction with JavaScript is done exclusively using Deno ops
-    worker.execute_main_module(&main_url).await.context(format!(
-        "Error when executing JavaScript for version {:?} in worker {}",
-        init.version.version_id, init.worker_idx
-    ))
+/// Checks that `host` is a host name or an IP address with an optional port, such as
+/// `api.example.com` or `127.0.0.1:8080`.
+pub fn validate_allowed_host(host: &str) -> Result<()> {
+    let url = Url::parse(&format!("http://{host}"));
+    let valid = match url {
+        Ok(url) => {
+            url.host_str().is_some()
+                && url.username().is_empty()
+                && url.password().is_none()
+                && url.path() == "/"
+                && url.query().is_none()
+                && url.fragment().is_none()
+                && !host.contains('/')
+        }
+        Err(_) => false,
+    };
+    ensure!(
+        valid,
+        "Invalid allowed host {:?}, expected a host name with an optional port",
+        host
+    );
+    Ok(())
 }
 
 impl Future for WorkerJoinHandle {
@@ -211,6 +322,14 @@ fn get_error_class_name(e: &anyhow::Error) -> &'static str {
             e.downcast_ref::<String>().map(|_| "Error")
         })
         .or_else(|| e.downcast_ref::<&'static str>().map(|_| "Error"))
+        .or_else(|| e.downcast_ref::<ConflictError>().map(|_| "ConflictError"))
+        .or_else(|| e.downcast_ref::<RestrictError>().map(|_| "ConflictError"))
+        .or_else(|| e.downcast_ref::<IngestError>().map(|_| "IngestError"))
+        .or_else(|| e.downcast_ref::<CrudQueryError>().map(|_| "CrudQueryError"))
+        .or_else(|| {
+            e.downcast_ref::<ValidationError>()
+                .map(|_| "ValidationError")
+        })
         .or_else(|| {
             match e.downcast_ref::<PolicyError>() {
                 Some(
@@ -242,3 +361,25 @@ pub fn set_v8_flags(flags: &[String]) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_hosts() {
+        for host in ["api.example.com", "localhost:8080", "127.0.0.1", "[::1]:80"] {
+            assert!(validate_allowed_host(host).is_ok(), "{host:?}");
+        }
+        for host in [
+            "",
+            "https://example.com",
+            "example.com/path",
+            "user@example.com",
+            "example.com?x",
+            "example.com:http",
+        ] {
+            assert!(validate_allowed_host(host).is_err(), "{host:?}");
+        }
+    }
+}
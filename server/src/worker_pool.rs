@@ -0,0 +1,660 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Autoscaling of the workers of a version.
+//!
+//! Every version starts with `--worker-threads` workers. If `--max-worker-threads` is larger,
+//! the version dispatches jobs only to idle workers and keeps the other jobs in a queue. When the
+//! queue gets too deep or its oldest job waits too long, the version starts another worker (one
+//! at a time), and when the last worker stays idle for long enough, it is stopped. Jobs are
+//! packed on the workers with the lowest indices, so that the workers with the highest indices
+//! become idle first.
+//!
+//! A version can override these settings in the `[workers]` section of its `Chisel.toml` (see
+//! `WorkerPoolOverrides`). The pool is saturated when all `max_workers` are busy and jobs still
+//! wait in the queue; the metrics route reports how often and for how long this happened.
+
+use crate::opt::Opt;
+use crate::proto::WorkerPoolSettings;
+use crate::server::Server;
+use crate::version::{Version, VersionJob};
+use crate::worker::{self, WorkerInit, WorkerJoinHandle};
+use anyhow::{bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+
+/// How often the dispatcher re-evaluates the scaling when no jobs arrive.
+const SCALE_TICK: Duration = Duration::from_millis(100);
+
+/// Maximum number of jobs queued in the dispatcher for every worker (at the maximum number of
+/// workers). When the queue is full, the senders of jobs wait.
+const QUEUED_JOBS_PER_WORKER: usize = 16;
+
+/// Size and autoscaling of the pool of workers of a version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerPoolConfig {
+    pub min_workers: usize,
+    pub max_workers: usize,
+    /// Start a worker when at least this many jobs wait for an idle worker...
+    pub scale_up_queue_depth: usize,
+    /// ... or when a job has waited for this long.
+    pub scale_up_latency: Duration,
+    /// Stop the last worker after it has been idle for this long.
+    pub scale_down_idle: Duration,
+}
+
+impl WorkerPoolConfig {
+    /// Pool with a fixed number of workers.
+    pub fn fixed(workers: usize) -> Self {
+        Self {
+            min_workers: workers,
+            max_workers: workers,
+            scale_up_queue_depth: 1,
+            scale_up_latency: Duration::ZERO,
+            scale_down_idle: Duration::ZERO,
+        }
+    }
+
+    pub fn from_opt(opt: &Opt) -> Result<Self> {
+        let min_workers = opt.worker_threads;
+        let max_workers = opt.max_worker_threads.unwrap_or(min_workers);
+        ensure!(min_workers > 0, "--worker-threads must be at least 1");
+        ensure!(
+            max_workers >= min_workers,
+            "--max-worker-threads ({}) must not be less than --worker-threads ({})",
+            max_workers,
+            min_workers
+        );
+        ensure!(
+            opt.worker_scale_up_queue_depth > 0,
+            "--worker-scale-up-queue-depth must be at least 1"
+        );
+        ensure!(
+            opt.worker_scale_down_idle_s >= 0.,
+            "--worker-scale-down-idle-s must not be negative"
+        );
+        Ok(Self {
+            min_workers,
+            max_workers,
+            scale_up_queue_depth: opt.worker_scale_up_queue_depth,
+            scale_up_latency: Duration::from_millis(opt.worker_scale_up_latency_ms),
+            scale_down_idle: Duration::from_secs_f64(opt.worker_scale_down_idle_s),
+        })
+    }
+
+    pub fn is_autoscaled(&self) -> bool {
+        self.max_workers > self.min_workers
+    }
+
+    /// Applies the settings of a version over the defaults of the server. If the version only
+    /// sets one of the bounds, the other one is adjusted to keep `min_workers <= max_workers`.
+    pub fn with_overrides(&self, overrides: &WorkerPoolOverrides) -> Result<Self> {
+        overrides.validate()?;
+        let (min_workers, max_workers) = match (overrides.min_workers, overrides.max_workers) {
+            (Some(min), Some(max)) => (min, max),
+            (Some(min), None) => (min, self.max_workers.max(min)),
+            (None, Some(max)) => (self.min_workers.min(max), max),
+            (None, None) => (self.min_workers, self.max_workers),
+        };
+        Ok(Self {
+            min_workers,
+            max_workers,
+            scale_up_queue_depth: overrides
+                .scale_up_queue_depth
+                .unwrap_or(self.scale_up_queue_depth),
+            scale_up_latency: overrides
+                .scale_up_latency_ms
+                .map(Duration::from_millis)
+                .unwrap_or(self.scale_up_latency),
+            scale_down_idle: overrides
+                .scale_down_idle_s
+                .map(Duration::from_secs_f64)
+                .unwrap_or(self.scale_down_idle),
+        })
+    }
+}
+
+/// Worker pool settings of a version, from the `[workers]` section of its `Chisel.toml`. The
+/// settings that are not given use the defaults of the server (`--worker-threads` and friends).
+/// They are stored as JSON in the meta database.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerPoolOverrides {
+    pub min_workers: Option<usize>,
+    pub max_workers: Option<usize>,
+    pub scale_up_queue_depth: Option<usize>,
+    pub scale_up_latency_ms: Option<u64>,
+    pub scale_down_idle_s: Option<f64>,
+}
+
+impl WorkerPoolOverrides {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(min_workers) = self.min_workers {
+            ensure!(min_workers > 0, "workers.min must be at least 1");
+        }
+        if let Some(max_workers) = self.max_workers {
+            ensure!(max_workers > 0, "workers.max must be at least 1");
+        }
+        if let (Some(min_workers), Some(max_workers)) = (self.min_workers, self.max_workers) {
+            ensure!(
+                max_workers >= min_workers,
+                "workers.max ({}) must not be less than workers.min ({})",
+                max_workers,
+                min_workers
+            );
+        }
+        if let Some(depth) = self.scale_up_queue_depth {
+            ensure!(depth > 0, "workers.scale_up_queue_depth must be at least 1");
+        }
+        if let Some(idle_s) = self.scale_down_idle_s {
+            ensure!(
+                idle_s.is_finite() && idle_s >= 0.,
+                "workers.scale_down_idle_s must not be negative"
+            );
+        }
+        Ok(())
+    }
+}
+
+impl From<&WorkerPoolSettings> for WorkerPoolOverrides {
+    fn from(settings: &WorkerPoolSettings) -> Self {
+        Self {
+            min_workers: settings.min_workers.map(|n| n as usize),
+            max_workers: settings.max_workers.map(|n| n as usize),
+            scale_up_queue_depth: settings.scale_up_queue_depth.map(|n| n as usize),
+            scale_up_latency_ms: settings.scale_up_latency_ms.map(u64::from),
+            scale_down_idle_s: settings.scale_down_idle_s,
+        }
+    }
+}
+
+/// Counters of the worker pool of a version, reported on the metrics route.
+#[derive(Debug, Default)]
+pub struct WorkerPoolMetrics {
+    workers: AtomicUsize,
+    queue_depth: AtomicUsize,
+    scale_ups: AtomicU64,
+    scale_downs: AtomicU64,
+    saturated: AtomicBool,
+    saturations: AtomicU64,
+    saturated_ms: AtomicU64,
+    unhandled_rejections: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerPoolReport {
+    pub workers: usize,
+    pub min_workers: usize,
+    pub max_workers: usize,
+    /// Jobs that wait for an idle worker (always zero without autoscaling).
+    pub queue_depth: usize,
+    pub scale_ups: u64,
+    pub scale_downs: u64,
+    /// All `max_workers` are busy and jobs wait in the queue (only measured with autoscaling).
+    pub saturated: bool,
+    /// How many times the pool became saturated.
+    pub saturations: u64,
+    /// Total time that the pool has been saturated, in milliseconds.
+    pub saturated_ms: u64,
+    /// Promises that were rejected without a handler in JavaScript.
+    pub unhandled_rejections: u64,
+}
+
+impl WorkerPoolMetrics {
+    pub fn set_workers(&self, workers: usize) {
+        self.workers.store(workers, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, config: &WorkerPoolConfig) -> WorkerPoolReport {
+        WorkerPoolReport {
+            workers: self.workers.load(Ordering::Relaxed),
+            min_workers: config.min_workers,
+            max_workers: config.max_workers,
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            scale_ups: self.scale_ups.load(Ordering::Relaxed),
+            scale_downs: self.scale_downs.load(Ordering::Relaxed),
+            saturated: self.saturated.load(Ordering::Relaxed),
+            saturations: self.saturations.load(Ordering::Relaxed),
+            saturated_ms: self.saturated_ms.load(Ordering::Relaxed),
+            unhandled_rejections: self.unhandled_rejections.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn count_unhandled_rejection(&self) {
+        self.unhandled_rejections.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Spawns the workers of a version.
+pub struct WorkerSpawner {
+    pub server: Arc<Server>,
+    pub version: Arc<Version>,
+    pub modules: Arc<HashMap<String, String>>,
+    /// Channel on which autoscaled workers report that they are idle (see `WorkerInit`).
+    pub idle_tx: Option<mpsc::UnboundedSender<usize>>,
+}
+
+impl WorkerSpawner {
+    /// Spawns a worker. Returns the receiver of its ready signal, the sender of its jobs and its
+    /// handle.
+    pub async fn spawn(
+        &self,
+        worker_idx: usize,
+    ) -> Result<(
+        oneshot::Receiver<()>,
+        mpsc::Sender<VersionJob>,
+        WorkerJoinHandle,
+    )> {
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let (job_tx, job_rx) = mpsc::channel(1);
+        let handle = worker::spawn(WorkerInit {
+            worker_idx,
+            server: self.server.clone(),
+            version: self.version.clone(),
+            modules: self.modules.clone(),
+            ready_tx,
+            job_rx,
+            idle_tx: self.idle_tx.clone(),
+        })
+        .await?;
+        Ok((ready_rx, job_tx, handle))
+    }
+}
+
+/// What the dispatcher should do with the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scale {
+    Up,
+    Down,
+    Keep,
+}
+
+/// State of the pool that the scaling decision is based on.
+#[derive(Debug, Clone, Copy)]
+struct PoolState {
+    workers: usize,
+    /// A worker was started and has not become ready yet.
+    starting: bool,
+    queue_depth: usize,
+    /// How long the oldest queued job has waited.
+    oldest_wait: Duration,
+    /// How long the last worker has been idle (`None` if it is busy).
+    last_idle: Option<Duration>,
+}
+
+fn decide_scale(config: &WorkerPoolConfig, state: PoolState) -> Scale {
+    if state.starting {
+        return Scale::Keep;
+    }
+    let congested = state.queue_depth >= config.scale_up_queue_depth
+        || (state.queue_depth > 0 && state.oldest_wait >= config.scale_up_latency);
+    if congested && state.workers < config.max_workers {
+        return Scale::Up;
+    }
+    let idle = state.queue_depth == 0
+        && matches!(state.last_idle, Some(idle) if idle >= config.scale_down_idle);
+    if idle && state.workers > config.min_workers {
+        return Scale::Down;
+    }
+    Scale::Keep
+}
+
+struct PoolWorker {
+    job_tx: mpsc::Sender<VersionJob>,
+    /// Jobs that were sent to the worker and that it has not finished. A new worker starts with
+    /// one, which is finished when the worker reports that it is idle for the first time.
+    in_flight: usize,
+    ready: bool,
+    idle_since: Instant,
+}
+
+struct QueuedJob {
+    job: VersionJob,
+    queued_at: Instant,
+}
+
+struct Dispatcher {
+    config: WorkerPoolConfig,
+    spawner: WorkerSpawner,
+    workers: Vec<PoolWorker>,
+    queue: VecDeque<QueuedJob>,
+    handle_tx: mpsc::UnboundedSender<WorkerJoinHandle>,
+    /// Whether the pool was saturated at the last check, and when that check happened.
+    saturated: bool,
+    checked_at: Instant,
+}
+
+/// Dispatches the jobs of a version to the workers of an autoscaled pool, starting and stopping
+/// the workers as needed. The handles of the started workers are sent to `handle_tx`.
+pub async fn dispatch(
+    config: WorkerPoolConfig,
+    spawner: WorkerSpawner,
+    job_txs: Vec<mpsc::Sender<VersionJob>>,
+    mut job_rx: mpsc::Receiver<VersionJob>,
+    mut idle_rx: mpsc::UnboundedReceiver<usize>,
+    handle_tx: mpsc::UnboundedSender<WorkerJoinHandle>,
+) -> Result<()> {
+    let now = Instant::now();
+    let workers = job_txs
+        .into_iter()
+        .map(|job_tx| PoolWorker {
+            job_tx,
+            in_flight: 1,
+            ready: false,
+            idle_since: now,
+        })
+        .collect();
+    let max_queued = config.max_workers * QUEUED_JOBS_PER_WORKER;
+    let mut dispatcher = Dispatcher {
+        config,
+        spawner,
+        workers,
+        queue: VecDeque::new(),
+        handle_tx,
+        saturated: false,
+        checked_at: now,
+    };
+
+    let mut tick = tokio::time::interval(SCALE_TICK);
+    let mut job_rx_open = true;
+    while job_rx_open || !dispatcher.queue.is_empty() {
+        tokio::select! {
+            job = job_rx.recv(), if job_rx_open && dispatcher.queue.len() < max_queued => match job {
+                Some(job) => dispatcher.queue.push_back(QueuedJob {
+                    job,
+                    queued_at: Instant::now(),
+                }),
+                None => job_rx_open = false,
+            },
+            Some(worker_idx) = idle_rx.recv() => dispatcher.worker_idle(worker_idx),
+            _ = tick.tick() => {},
+        }
+        dispatcher.dispatch_queued()?;
+        dispatcher.scale().await?;
+    }
+    Ok(())
+}
+
+impl Dispatcher {
+    fn version_id(&self) -> &str {
+        &self.spawner.version.version_id
+    }
+
+    fn worker_idle(&mut self, worker_idx: usize) {
+        if let Some(worker) = self.workers.get_mut(worker_idx) {
+            worker.ready = true;
+            worker.in_flight = worker.in_flight.saturating_sub(1);
+            if worker.in_flight == 0 {
+                worker.idle_since = Instant::now();
+            }
+        }
+    }
+
+    /// Sends the queued jobs to idle workers.
+    fn dispatch_queued(&mut self) -> Result<()> {
+        let mut queue_idx = 0;
+        while queue_idx < self.queue.len() {
+            match self.pick_worker(&self.queue[queue_idx].job) {
+                Some(worker_idx) => {
+                    let queued = self.queue.remove(queue_idx).unwrap();
+                    self.send(worker_idx, queued.job)?;
+                }
+                // leave the job in the queue, but later jobs might go to other workers
+                None => queue_idx += 1,
+            }
+        }
+        self.spawner
+            .version
+            .pool_metrics
+            .queue_depth
+            .store(self.queue.len(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Picks an idle worker for the job, if there is one.
+    fn pick_worker(&self, job: &VersionJob) -> Option<usize> {
+        let is_idle = |worker_idx: usize| self.workers[worker_idx].in_flight == 0;
+        match job {
+            // all events of a socket must be handled by the same worker, in order, so we only use
+            // the workers that are never stopped
+            VersionJob::Socket(event) => {
+                Some(event.socket_id as usize % self.config.min_workers).filter(|&i| is_idle(i))
+            }
+            _ => {
+                // requests pinned by the worker affinity go to their worker, unless the worker is
+                // busy. the pinning changes with the number of workers, so it is best-effort
+                let pinned = self
+                    .spawner
+                    .server
+                    .worker_affinity
+                    .as_ref()
+                    .and_then(|affinity| affinity.pinned_worker(job, self.workers.len()))
+                    .filter(|&i| is_idle(i));
+                pinned.or_else(|| (0..self.workers.len()).find(|&i| is_idle(i)))
+            }
+        }
+    }
+
+    fn send(&mut self, worker_idx: usize, job: VersionJob) -> Result<()> {
+        let worker = &mut self.workers[worker_idx];
+        match worker.job_tx.try_send(job) {
+            Ok(()) => {
+                worker.in_flight += 1;
+                Ok(())
+            }
+            // an idle worker has nothing in its channel
+            Err(TrySendError::Full(_)) => bail!(
+                "Idle worker {:?} {} did not accept a job",
+                self.spawner.version.version_id,
+                worker_idx
+            ),
+            Err(TrySendError::Closed(_)) => bail!(
+                "Worker {:?} {} is unable to accept jobs",
+                self.spawner.version.version_id,
+                worker_idx
+            ),
+        }
+    }
+
+    async fn scale(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let last = self.workers.last().unwrap();
+        let state = PoolState {
+            workers: self.workers.len(),
+            starting: self.workers.iter().any(|worker| !worker.ready),
+            queue_depth: self.queue.len(),
+            oldest_wait: self
+                .queue
+                .front()
+                .map(|queued| now - queued.queued_at)
+                .unwrap_or_default(),
+            last_idle: (last.in_flight == 0).then(|| now - last.idle_since),
+        };
+
+        self.update_saturation(state, now);
+
+        let metrics = &self.spawner.version.pool_metrics;
+        match decide_scale(&self.config, state) {
+            Scale::Up => {
+                let worker_idx = self.workers.len();
+                // the worker reports that it is idle once it is ready, we don't need the signal
+                let (_ready_rx, job_tx, handle) = self.spawner.spawn(worker_idx).await?;
+                let _: Result<_, _> = self.handle_tx.send(handle);
+                self.workers.push(PoolWorker {
+                    job_tx,
+                    in_flight: 1,
+                    ready: false,
+                    idle_since: now,
+                });
+                metrics.set_workers(self.workers.len());
+                metrics.scale_ups.fetch_add(1, Ordering::Relaxed);
+                info!(
+                    "Version {:?}: scaling up to {} workers ({} queued jobs, oldest waited {} ms)",
+                    self.version_id(),
+                    self.workers.len(),
+                    state.queue_depth,
+                    state.oldest_wait.as_millis()
+                );
+            }
+            Scale::Down => {
+                // dropping the sender stops the worker, which is idle
+                self.workers.pop();
+                metrics.set_workers(self.workers.len());
+                metrics.scale_downs.fetch_add(1, Ordering::Relaxed);
+                info!(
+                    "Version {:?}: scaling down to {} workers (idle for {} s)",
+                    self.version_id(),
+                    self.workers.len(),
+                    state.last_idle.unwrap_or_default().as_secs()
+                );
+            }
+            Scale::Keep => {}
+        }
+        Ok(())
+    }
+
+    fn update_saturation(&mut self, state: PoolState, now: Instant) {
+        let metrics = &self.spawner.version.pool_metrics;
+        if self.saturated {
+            let saturated_ms = (now - self.checked_at).as_millis() as u64;
+            metrics
+                .saturated_ms
+                .fetch_add(saturated_ms, Ordering::Relaxed);
+        }
+        self.checked_at = now;
+
+        let saturated = is_saturated(&self.config, state);
+        if saturated && !self.saturated {
+            metrics.saturations.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Version {:?}: all {} workers are busy and {} jobs are queued, consider raising \
+                the maximum number of workers",
+                self.version_id(),
+                state.workers,
+                state.queue_depth
+            );
+        }
+        self.saturated = saturated;
+        metrics.saturated.store(saturated, Ordering::Relaxed);
+    }
+}
+
+fn is_saturated(config: &WorkerPoolConfig, state: PoolState) -> bool {
+    state.workers >= config.max_workers && state.queue_depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WorkerPoolConfig {
+        WorkerPoolConfig {
+            min_workers: 1,
+            max_workers: 3,
+            scale_up_queue_depth: 4,
+            scale_up_latency: Duration::from_millis(100),
+            scale_down_idle: Duration::from_secs(60),
+        }
+    }
+
+    fn state(workers: usize, queue_depth: usize) -> PoolState {
+        PoolState {
+            workers,
+            starting: false,
+            queue_depth,
+            oldest_wait: Duration::ZERO,
+            last_idle: None,
+        }
+    }
+
+    #[test]
+    fn scale_up_on_queue_depth_or_latency() {
+        let config = config();
+        assert_eq!(decide_scale(&config, state(1, 3)), Scale::Keep);
+        assert_eq!(decide_scale(&config, state(1, 4)), Scale::Up);
+        let waited = PoolState {
+            oldest_wait: Duration::from_millis(150),
+            ..state(2, 1)
+        };
+        assert_eq!(decide_scale(&config, waited), Scale::Up);
+        // bounded by the maximum, and one worker at a time
+        assert_eq!(decide_scale(&config, state(3, 10)), Scale::Keep);
+        let starting = PoolState {
+            starting: true,
+            ..state(2, 10)
+        };
+        assert_eq!(decide_scale(&config, starting), Scale::Keep);
+    }
+
+    #[test]
+    fn scale_down_when_idle() {
+        let config = config();
+        let idle = |workers, idle_s| PoolState {
+            last_idle: Some(Duration::from_secs(idle_s)),
+            ..state(workers, 0)
+        };
+        assert_eq!(decide_scale(&config, idle(2, 30)), Scale::Keep);
+        assert_eq!(decide_scale(&config, idle(2, 60)), Scale::Down);
+        // bounded by the minimum
+        assert_eq!(decide_scale(&config, idle(1, 600)), Scale::Keep);
+        // the last worker is busy
+        assert_eq!(decide_scale(&config, state(2, 0)), Scale::Keep);
+    }
+
+    #[test]
+    fn saturated_at_max_workers_with_queue() {
+        let config = config();
+        assert!(!is_saturated(&config, state(2, 10)));
+        assert!(!is_saturated(&config, state(3, 0)));
+        assert!(is_saturated(&config, state(3, 1)));
+    }
+
+    #[test]
+    fn overrides() {
+        let server = config();
+        let overrides = |min_workers, max_workers| WorkerPoolOverrides {
+            min_workers,
+            max_workers,
+            ..Default::default()
+        };
+
+        assert_eq!(server.with_overrides(&Default::default()).unwrap(), server);
+        let both = server.with_overrides(&overrides(Some(2), Some(8))).unwrap();
+        assert_eq!((both.min_workers, both.max_workers), (2, 8));
+        // a single bound moves the other one of the server if needed
+        let min = server.with_overrides(&overrides(Some(5), None)).unwrap();
+        assert_eq!((min.min_workers, min.max_workers), (5, 5));
+        let max = server.with_overrides(&overrides(None, Some(6))).unwrap();
+        assert_eq!((max.min_workers, max.max_workers), (1, 6));
+        let fixed = WorkerPoolConfig::fixed(4)
+            .with_overrides(&overrides(None, Some(2)))
+            .unwrap();
+        assert_eq!((fixed.min_workers, fixed.max_workers), (2, 2));
+
+        let tuned = server
+            .with_overrides(&WorkerPoolOverrides {
+                scale_up_queue_depth: Some(1),
+                scale_up_latency_ms: Some(20),
+                scale_down_idle_s: Some(0.5),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(tuned.scale_up_queue_depth, 1);
+        assert_eq!(tuned.scale_up_latency, Duration::from_millis(20));
+        assert_eq!(tuned.scale_down_idle, Duration::from_millis(500));
+
+        assert!(server.with_overrides(&overrides(Some(0), None)).is_err());
+        assert!(server.with_overrides(&overrides(None, Some(0))).is_err());
+        assert!(server.with_overrides(&overrides(Some(4), Some(2))).is_err());
+        let negative_idle = WorkerPoolOverrides {
+            scale_down_idle_s: Some(-1.),
+            ..Default::default()
+        };
+        assert!(server.with_overrides(&negative_idle).is_err());
+    }
+}
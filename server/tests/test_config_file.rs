@@ -67,13 +67,36 @@ api_listen_addr = "localhost:12345"
         "_metadata_db_uri": "sqlite://chiseld.db?mode=rwc",
         "_data_db_uri": "sqlite://chiseld-data.db?mode=rwc",
         "db_uri": "sqlite://.chiseld.db?mode=rwc",
+        "backup_dir": Value::Null,
         "kafka_connection": Value::Null,
+        "kafka_consumer_group": "chiseld",
+        "kafka_start_offset": "latest",
+        "kafka_schema_registry": Value::Null,
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk": false,
         "debug": false,
+        "testing": false,
+        "log_level": Value::Null,
+        "log_format": "text",
+        "otlp_endpoint": Value::Null,
+        "otlp_service_name": "chiseld",
         "nr_connections": 10,
+        "db_min_connections": 0,
+        "db_acquire_timeout_s": 30.0,
+        "db_idle_timeout_s": 600.0,
         "worker_threads": 1,
+        "max_worker_threads": Value::Null,
+        "worker_scale_up_queue_depth": 4,
+        "worker_scale_up_latency_ms": 200,
+        "worker_scale_down_idle_s": 60.0,
+        "worker_affinity": Value::Null,
+        "request_timeout_s": 0.0,
+        "apply_retention_s": 86400,
+        "archive_retention_s": 2592000,
+        "query_cache_size": 1000,
+        "crud_default_page_size": 1000,
+        "crud_max_page_size": 10000,
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
         "secrets_polling_period_s": 1.0,
@@ -81,6 +104,35 @@ api_listen_addr = "localhost:12345"
         "secrets_refresh_exponential_backoff_factor": 2.0,
         "secrets_refresh_max_exponential_backoff_s": 600.0,
         "typescript_policies": false,
+        "request_log_endpoint": Value::Null,
+        "request_log_protocol": "json",
+        "request_log_sample_rate": 1.0,
+        "request_log_version_sample_rate": Value::Array(vec![]),
+        "request_log_batch_size": 100,
+        "request_log_flush_interval_ms": 1000,
+        "request_log_queue_size": 10000,
+        "allow_raw_sql": false,
+        "record_examples_rate": 0.0,
+        "redact_example_fields": ["password", "token", "secret", "authorization"],
+        "audit_retention_s": 7776000,
+        "meta_gc_interval_s": 86400,
+        "db_statement_cache_capacity": 100,
+        "db_keepalive_interval_s": 0.0,
+        "tenant_claim": Value::Null,
+        "tenant_header": Value::Null,
+        "trust_tenant_header": false,
+        "jwt_issuer": Value::Null,
+        "jwt_audience": Value::Null,
+        "jwt_jwks_url": Value::Null,
+        "oidc_issuer": Value::Null,
+        "oidc_client_id": Value::Null,
+        "oidc_redirect_url": Value::Null,
+        "oidc_session_ttl_s": 604800,
+        "max_body_size": 10485760,
+        "blob_dir": Value::Null,
+        "blob_s3_endpoint": Value::Null,
+        "blob_s3_region": "us-east-1",
+        "allow_all_net": false,
     });
 
     assert_eq!(out, expected);
@@ -110,13 +162,36 @@ api_listen_addr = "localhost:12345"
         "_metadata_db_uri": "sqlite://chiseld.db?mode=rwc",
         "_data_db_uri": "sqlite://chiseld-data.db?mode=rwc",
         "db_uri": "sqlite://.chiseld.db?mode=rwc",
+        "backup_dir": Value::Null,
         "kafka_connection": Value::Null,
+        "kafka_consumer_group": "chiseld",
+        "kafka_start_offset": "latest",
+        "kafka_schema_registry": Value::Null,
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk": false,
         "debug": false,
+        "testing": false,
+        "log_level": Value::Null,
+        "log_format": "text",
+        "otlp_endpoint": Value::Null,
+        "otlp_service_name": "chiseld",
         "nr_connections": 10,
+        "db_min_connections": 0,
+        "db_acquire_timeout_s": 30.0,
+        "db_idle_timeout_s": 600.0,
         "worker_threads": 1,
+        "max_worker_threads": Value::Null,
+        "worker_scale_up_queue_depth": 4,
+        "worker_scale_up_latency_ms": 200,
+        "worker_scale_down_idle_s": 60.0,
+        "worker_affinity": Value::Null,
+        "request_timeout_s": 0.0,
+        "apply_retention_s": 86400,
+        "archive_retention_s": 2592000,
+        "query_cache_size": 1000,
+        "crud_default_page_size": 1000,
+        "crud_max_page_size": 10000,
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
         "secrets_polling_period_s": 1.0,
@@ -124,6 +199,35 @@ api_listen_addr = "localhost:12345"
         "secrets_refresh_exponential_backoff_factor": 2.0,
         "secrets_refresh_max_exponential_backoff_s": 600.0,
         "typescript_policies": false,
+        "request_log_endpoint": Value::Null,
+        "request_log_protocol": "json",
+        "request_log_sample_rate": 1.0,
+        "request_log_version_sample_rate": Value::Array(vec![]),
+        "request_log_batch_size": 100,
+        "request_log_flush_interval_ms": 1000,
+        "request_log_queue_size": 10000,
+        "allow_raw_sql": false,
+        "record_examples_rate": 0.0,
+        "redact_example_fields": ["password", "token", "secret", "authorization"],
+        "audit_retention_s": 7776000,
+        "meta_gc_interval_s": 86400,
+        "db_statement_cache_capacity": 100,
+        "db_keepalive_interval_s": 0.0,
+        "tenant_claim": Value::Null,
+        "tenant_header": Value::Null,
+        "trust_tenant_header": false,
+        "jwt_issuer": Value::Null,
+        "jwt_audience": Value::Null,
+        "jwt_jwks_url": Value::Null,
+        "oidc_issuer": Value::Null,
+        "oidc_client_id": Value::Null,
+        "oidc_redirect_url": Value::Null,
+        "oidc_session_ttl_s": 604800,
+        "max_body_size": 10485760,
+        "blob_dir": Value::Null,
+        "blob_s3_endpoint": Value::Null,
+        "blob_s3_region": "us-east-1",
+        "allow_all_net": false,
     });
 
     assert_eq!(out, expected);
@@ -151,13 +255,36 @@ api_listen_addr = "localhost:12345"
         "_metadata_db_uri": "sqlite://chiseld.db?mode=rwc",
         "_data_db_uri": "sqlite://chiseld-data.db?mode=rwc",
         "db_uri": "sqlite://.chiseld.db?mode=rwc",
+        "backup_dir": Value::Null,
         "kafka_connection": Value::Null,
+        "kafka_consumer_group": "chiseld",
+        "kafka_start_offset": "latest",
+        "kafka_schema_registry": Value::Null,
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk": false,
         "debug": false,
+        "testing": false,
+        "log_level": Value::Null,
+        "log_format": "text",
+        "otlp_endpoint": Value::Null,
+        "otlp_service_name": "chiseld",
         "nr_connections": 10,
+        "db_min_connections": 0,
+        "db_acquire_timeout_s": 30.0,
+        "db_idle_timeout_s": 600.0,
         "worker_threads": 1,
+        "max_worker_threads": Value::Null,
+        "worker_scale_up_queue_depth": 4,
+        "worker_scale_up_latency_ms": 200,
+        "worker_scale_down_idle_s": 60.0,
+        "worker_affinity": Value::Null,
+        "request_timeout_s": 0.0,
+        "apply_retention_s": 86400,
+        "archive_retention_s": 2592000,
+        "query_cache_size": 1000,
+        "crud_default_page_size": 1000,
+        "crud_max_page_size": 10000,
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
         "secrets_polling_period_s": 1.0,
@@ -165,6 +292,35 @@ api_listen_addr = "localhost:12345"
         "secrets_refresh_exponential_backoff_factor": 2.0,
         "secrets_refresh_max_exponential_backoff_s": 600.0,
         "typescript_policies": false,
+        "request_log_endpoint": Value::Null,
+        "request_log_protocol": "json",
+        "request_log_sample_rate": 1.0,
+        "request_log_version_sample_rate": Value::Array(vec![]),
+        "request_log_batch_size": 100,
+        "request_log_flush_interval_ms": 1000,
+        "request_log_queue_size": 10000,
+        "allow_raw_sql": false,
+        "record_examples_rate": 0.0,
+        "redact_example_fields": ["password", "token", "secret", "authorization"],
+        "audit_retention_s": 7776000,
+        "meta_gc_interval_s": 86400,
+        "db_statement_cache_capacity": 100,
+        "db_keepalive_interval_s": 0.0,
+        "tenant_claim": Value::Null,
+        "tenant_header": Value::Null,
+        "trust_tenant_header": false,
+        "jwt_issuer": Value::Null,
+        "jwt_audience": Value::Null,
+        "jwt_jwks_url": Value::Null,
+        "oidc_issuer": Value::Null,
+        "oidc_client_id": Value::Null,
+        "oidc_redirect_url": Value::Null,
+        "oidc_session_ttl_s": 604800,
+        "max_body_size": 10485760,
+        "blob_dir": Value::Null,
+        "blob_s3_endpoint": Value::Null,
+        "blob_s3_region": "us-east-1",
+        "allow_all_net": false,
     });
 
     assert_eq!(out, expected);
@@ -201,13 +357,36 @@ api_listen_addr = "localhost:12346"
         "_metadata_db_uri":"sqlite://chiseld.db?mode=rwc",
         "_data_db_uri":"sqlite://chiseld-data.db?mode=rwc",
         "db_uri":"sqlite://.chiseld.db?mode=rwc",
+        "backup_dir": Value::Null,
         "kafka_connection": Value::Null,
+        "kafka_consumer_group": "chiseld",
+        "kafka_start_offset": "latest",
+        "kafka_schema_registry": Value::Null,
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk":false,
         "debug": false,
+        "testing": false,
+        "log_level": Value::Null,
+        "log_format": "text",
+        "otlp_endpoint": Value::Null,
+        "otlp_service_name": "chiseld",
         "nr_connections":10,
+        "db_min_connections": 0,
+        "db_acquire_timeout_s": 30.0,
+        "db_idle_timeout_s": 600.0,
         "worker_threads": 1,
+        "max_worker_threads": Value::Null,
+        "worker_scale_up_queue_depth": 4,
+        "worker_scale_up_latency_ms": 200,
+        "worker_scale_down_idle_s": 60.0,
+        "worker_affinity": Value::Null,
+        "request_timeout_s": 0.0,
+        "apply_retention_s": 86400,
+        "archive_retention_s": 2592000,
+        "query_cache_size": 1000,
+        "crud_default_page_size": 1000,
+        "crud_max_page_size": 10000,
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
         "secrets_polling_period_s": 1.0,
@@ -215,6 +394,35 @@ api_listen_addr = "localhost:12346"
         "secrets_refresh_exponential_backoff_factor": 2.0,
         "secrets_refresh_max_exponential_backoff_s": 600.0,
         "typescript_policies": false,
+        "request_log_endpoint": Value::Null,
+        "request_log_protocol": "json",
+        "request_log_sample_rate": 1.0,
+        "request_log_version_sample_rate": Value::Array(vec![]),
+        "request_log_batch_size": 100,
+        "request_log_flush_interval_ms": 1000,
+        "request_log_queue_size": 10000,
+        "allow_raw_sql": false,
+        "record_examples_rate": 0.0,
+        "redact_example_fields": ["password", "token", "secret", "authorization"],
+        "audit_retention_s": 7776000,
+        "meta_gc_interval_s": 86400,
+        "db_statement_cache_capacity": 100,
+        "db_keepalive_interval_s": 0.0,
+        "tenant_claim": Value::Null,
+        "tenant_header": Value::Null,
+        "trust_tenant_header": false,
+        "jwt_issuer": Value::Null,
+        "jwt_audience": Value::Null,
+        "jwt_jwks_url": Value::Null,
+        "oidc_issuer": Value::Null,
+        "oidc_client_id": Value::Null,
+        "oidc_redirect_url": Value::Null,
+        "oidc_session_ttl_s": 604800,
+        "max_body_size": 10485760,
+        "blob_dir": Value::Null,
+        "blob_s3_endpoint": Value::Null,
+        "blob_s3_region": "us-east-1",
+        "allow_all_net": false,
     });
 
     assert_eq!(out, expected);
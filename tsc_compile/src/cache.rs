@@ -0,0 +1,127 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Cache of the sources and of the outputs of the modules of a compilation, so that the next compilation
+//! (see `Compiler::compile_urls_incremental()`) only checks and emits the modules that changed
+//! and the modules that depend on them.
+
+use crate::CompileOptions;
+use anyhow::{Context, Result};
+use deno_core::anyhow;
+use deno_core::url::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct CompileCache {
+    /// Fingerprint of the options of the compilation that filled the cache, the cache is only
+    /// used by compilations with the same options.
+    fingerprint: u64,
+    /// Compiled modules, by URL.
+    modules: HashMap<String, CachedModule>,
+    /// Modules whose outputs were reused by the last compilation.
+    #[serde(skip)]
+    reused: HashSet<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedModule {
+    pub source: String,
+    /// Headers of a remote module, which is not downloaded again.
+    pub headers: Option<HashMap<String, String>>,
+    /// Code and declarations emitted for the module, as `(code, is_dts)`.
+    pub outputs: Vec<(String, bool)>,
+}
+
+impl CompileCache {
+    /// Loads the cache from `path`. A missing or unreadable cache is treated as empty, which just
+    /// makes the next compilation a full one.
+    pub fn load(path: &Path) -> CompileCache {
+        fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Could not create directory {}", dir.display()))?;
+        }
+        let data = serde_json::to_vec(self)?;
+        fs::write(path, data)
+            .with_context(|| format!("Could not write compile cache {}", path.display()))
+    }
+
+    /// Returns true if the outputs of the module at `url` were reused by the last compilation,
+    /// instead of being emitted again.
+    pub fn is_reused(&self, url: &str) -> bool {
+        self.reused.contains(url)
+    }
+
+    /// Empties the cache if it was filled by a compilation with different options.
+    pub(crate) fn check_options(&mut self, opts: &CompileOptions<'_>) {
+        let fingerprint = options_fingerprint(opts);
+        if self.fingerprint != fingerprint {
+            *self = CompileCache {
+                fingerprint,
+                ..Default::default()
+            };
+        }
+    }
+
+    pub(crate) fn get(&self, url: &str) -> Option<&CachedModule> {
+        self.modules.get(url)
+    }
+
+    /// Returns the remote modules, as `(source, headers)`, which are not downloaded again.
+    pub(crate) fn remote_modules(&self) -> HashMap<Url, (String, HashMap<String, String>)> {
+        let mut ret = HashMap::new();
+        for (url, module) in &self.modules {
+            if let (Ok(url), Some(headers)) = (Url::parse(url), &module.headers) {
+                ret.insert(url, (module.source.clone(), headers.clone()));
+            }
+        }
+        ret
+    }
+
+    /// Replaces the modules of the cache with the modules of the last compilation.
+    pub(crate) fn update(
+        &mut self,
+        modules: HashMap<String, CachedModule>,
+        reused: HashSet<String>,
+    ) {
+        self.modules = modules;
+        self.reused = reused;
+    }
+}
+
+fn options_fingerprint(opts: &CompileOptions<'_>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    if let Some(path) = opts.extra_default_lib {
+        path.hash(&mut hasher);
+        fs::read(path).unwrap_or_default().hash(&mut hasher);
+    }
+    let extra_libs: BTreeMap<_, _> = opts.extra_libs.iter().collect();
+    extra_libs.hash(&mut hasher);
+    serde_json::to_string(&opts.tsconfig.compiler_options)
+        .unwrap()
+        .hash(&mut hasher);
+    for (pattern, targets) in &opts.tsconfig.paths {
+        pattern.hash(&mut hasher);
+        for target in targets {
+            target.as_str().hash(&mut hasher);
+        }
+    }
+    for (key, target) in opts.import_map.imports() {
+        key.hash(&mut hasher);
+        target.hash(&mut hasher);
+    }
+    opts.emit_declarations.hash(&mut hasher);
+    opts.is_worker.hash(&mut hasher);
+    hasher.finish()
+}
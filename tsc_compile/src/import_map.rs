@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Import map of a project, read from its `import_map.json`.
+
+use anyhow::{bail, Context, Result};
+use deno_core::anyhow;
+use deno_core::url::Url;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use utils::ImportMap;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImportMapJson {
+    #[serde(default)]
+    imports: BTreeMap<String, String>,
+    scopes: Option<serde_json::Value>,
+}
+
+/// Reads `import_map.json` in the directory `dir` (which must be absolute). The targets of the
+/// imports are resolved against `dir`. Without the file, the import map is empty.
+pub fn load_import_map(dir: &Path) -> Result<ImportMap> {
+    let path = dir.join("import_map.json");
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => return Ok(ImportMap::default()),
+    };
+    parse(&text, dir).with_context(|| format!("Could not read {}", path.display()))
+}
+
+fn parse(text: &str, dir: &Path) -> Result<ImportMap> {
+    let json: ImportMapJson = serde_json::from_str(text)?;
+    if json.scopes.is_some() {
+        bail!("Scopes of import maps are not supported");
+    }
+    let base_url = Url::from_directory_path(dir)
+        .map_err(|_| anyhow::anyhow!("Invalid directory {}", dir.display()))?;
+    let mut imports = vec![];
+    for (key, target) in json.imports {
+        let target = base_url
+            .join(&target)
+            .with_context(|| format!("Invalid target {:?} of import {:?}", target, key))?;
+        imports.push((key, target.to_string()));
+    }
+    ImportMap::new(imports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use std::path::Path;
+
+    #[test]
+    fn targets() {
+        let text = r#"{
+            "imports": {
+                "@lib/": "./lib/",
+                "config": "./src/config.ts",
+                "std/": "https://deno.land/std@0.165.0/"
+            }
+        }"#;
+        let map = parse(text, Path::new("/project")).unwrap();
+        assert_eq!(
+            map.imports().collect::<Vec<_>>(),
+            vec![
+                ("config", "file:///project/src/config.ts"),
+                ("@lib/", "file:///project/lib/"),
+                ("std/", "https://deno.land/std@0.165.0/"),
+            ]
+        );
+
+        let err = parse(r#"{"imports": {}, "scopes": {}}"#, Path::new("/project")).unwrap_err();
+        assert!(err.to_string().contains("Scopes"));
+    }
+}
@@ -1,5 +1,12 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+mod cache;
+mod import_map;
+mod lockfile;
+mod remote_cache;
+mod tsconfig;
+mod watch;
+
 use anyhow::{anyhow, Context, Result};
 pub use deno_core;
 use deno_core::anyhow;
@@ -35,8 +42,16 @@ use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex;
 pub use url::Url as FixedUrl;
 use utils::without_extension;
+pub use utils::ImportMap;
+
+use crate::cache::CachedModule;
+pub use crate::cache::CompileCache;
+pub use crate::import_map::load_import_map;
+pub use crate::remote_cache::RemoteCache;
+pub use crate::tsconfig::TsConfig;
 
 #[derive(Debug)]
 struct DownloadMap {
@@ -215,15 +230,110 @@ pub struct CompileOptions<'a> {
     pub extra_libs: HashMap<String, String>,
     pub emit_declarations: bool,
     pub is_worker: bool,
+    pub remote: RemoteOptions,
+    /// Options of the `tsconfig.json` of the project.
+    pub tsconfig: TsConfig,
+    /// Import map of the project (see `load_import_map()`).
+    pub import_map: ImportMap,
+}
+
+/// How the remote modules (`https://` imports) are fetched.
+#[derive(Default, Clone, Debug)]
+pub struct RemoteOptions {
+    /// Fetch remote modules again, instead of taking them from the disk cache (see
+    /// `RemoteCache`).
+    pub reload: bool,
+    /// Never fetch remote modules, only take them from the disk cache.
+    pub offline: bool,
+    /// Lockfile with the hashes of the remote modules, which is created if it does not exist
+    /// (see `lockfile`).
+    pub lockfile: Option<PathBuf>,
 }
 
+/// Headers of remote modules, by URL.
+type RemoteHeaders = HashMap<Url, HashMap<String, String>>;
+
 struct ModuleLoader {
     extra_libs: HashMap<Url, String>,
+    /// Remote modules of the compile cache, as `(source, headers)`, which are not downloaded
+    /// again.
+    cached_remote: HashMap<Url, (String, HashMap<String, String>)>,
+    /// Headers of the remote modules that were downloaded.
+    downloaded: Arc<Mutex<RemoteHeaders>>,
+    remote_cache: Option<Arc<RemoteCache>>,
+    reload: bool,
+    offline: bool,
 }
 
 static ROOT_URL: &str = "chisel://root_domain/root.ts";
 
-fn load_url(extra_libs: &HashMap<Url, String>, specifier: Url) -> impl Future<Output = LoadResult> {
+/// Fetches a remote module, as `(headers, content)`. Unless `reload`, the module is taken from
+/// `cache` if it is still fresh there. If `offline`, the module is only taken from `cache`.
+async fn fetch_remote(
+    url: &Url,
+    cache: Option<&RemoteCache>,
+    reload: bool,
+    offline: bool,
+) -> Result<(HashMap<String, String>, String)> {
+    let now = remote_cache::now();
+    let cached = match cache {
+        Some(cache) if !reload || offline => cache.get(url),
+        _ => None,
+    };
+    if offline {
+        let (entry, content) = cached.with_context(|| {
+            format!(
+                "Cannot fetch {} in offline mode, because it is not in the cache of remote imports; compile once without --offline to cache it",
+                url
+            )
+        })?;
+        return Ok((entry.headers, content));
+    }
+    let validators = match &cached {
+        Some((entry, content)) if entry.is_fresh(now) => {
+            return Ok((entry.headers.clone(), content.clone()));
+        }
+        Some((entry, _)) => entry.validators(),
+        None => vec![],
+    };
+
+    let res = match utils::get_conditional(url.clone(), &validators).await {
+        Ok(res) => res,
+        // a stale module is better than none, for example when offline
+        Err(err) => {
+            return cached
+                .map(|(entry, content)| (entry.headers, content))
+                .ok_or(err)
+        }
+    };
+    if let (304, Some((entry, content))) = (res.status().as_u16(), cached) {
+        let headers = entry.headers.clone();
+        if let Some(cache) = cache {
+            // the cache only saves downloads, so failing to update it is not an error
+            let _ = cache.revalidated(url, entry, now);
+        }
+        return Ok((headers, content));
+    }
+
+    let mut headers = HashMap::new();
+    for (key, value) in res.headers().iter() {
+        headers.insert(key.as_str().to_string(), value.to_str()?.to_string());
+    }
+    let content = res.text().await?;
+    if let Some(cache) = cache {
+        let _ = cache.put(url, &headers, &content, now);
+    }
+    Ok((headers, content))
+}
+
+fn load_url(
+    extra_libs: &HashMap<Url, String>,
+    downloaded: Arc<Mutex<RemoteHeaders>>,
+    remote_cache: Option<Arc<RemoteCache>>,
+    reload: bool,
+    offline: bool,
+    specifier: Url,
+) -> impl Future<Output = LoadResult> {
     let sync_text: Option<Result<String>> = match specifier.scheme() {
         "file" => {
             Some(fs::read_to_string(specifier.to_file_path().unwrap()).map_err(|err| anyhow!(err)))
@@ -238,17 +348,18 @@ fn load_url(extra_libs: &HashMap<Url, String>, specifier: Url) -> impl Future<Ou
     };
     let mut maybe_headers = None;
 
-    async {
+    async move {
         let text = match sync_text {
             Some(sync_text) => sync_text?,
             None => {
-                let res = utils::get_ok(specifier.clone()).await?;
-                let mut headers = HashMap::new();
-                for (key, value) in res.headers().iter() {
-                    headers.insert(key.as_str().to_string(), value.to_str()?.to_string());
-                }
+                let (headers, text) =
+                    fetch_remote(&specifier, remote_cache.as_deref(), reload, offline).await?;
+                downloaded
+                    .lock()
+                    .unwrap()
+                    .insert(specifier.clone(), headers.clone());
                 maybe_headers = Some(headers);
-                res.text().await?
+                text
             }
         };
         let response = LoadResponse::Module {
@@ -262,13 +373,30 @@ fn load_url(extra_libs: &HashMap<Url, String>, specifier: Url) -> impl Future<Ou
 
 impl Loader for ModuleLoader {
     fn load(&mut self, specifier: &Url, _is_dynamic: bool) -> LoadFuture {
-        Box::pin(load_url(&self.extra_libs, specifier.clone()))
+        if let Some((source, headers)) = self.cached_remote.get(specifier) {
+            let response = LoadResponse::Module {
+                specifier: specifier.clone(),
+                maybe_headers: Some(headers.clone()),
+                content: Arc::new(source.clone()),
+            };
+            return Box::pin(std::future::ready(Ok(Some(response))));
+        }
+        Box::pin(load_url(
+            &self.extra_libs,
+            self.downloaded.clone(),
+            self.remote_cache.clone(),
+            self.reload,
+            self.offline,
+            specifier.clone(),
+        ))
     }
 }
 
 #[derive(Debug)]
 struct ModuleResolver {
     extra_libs: HashMap<String, Url>,
+    import_map: ImportMap,
+    tsconfig: TsConfig,
 }
 
 impl Resolver for ModuleResolver {
@@ -276,10 +404,139 @@ impl Resolver for ModuleResolver {
         if let Some(u) = self.extra_libs.get(specifier) {
             return ResolveResponse::Esm(u.clone());
         }
+        if let Some(u) = self.import_map.resolve(specifier) {
+            // the targets of the import map are valid URLs
+            return ResolveResponse::Esm(Url::parse(&u).unwrap());
+        }
+        if let Some(u) = self.tsconfig.resolve_alias(specifier) {
+            return ResolveResponse::Esm(u);
+        }
         resolve_import(specifier, referrer).into()
     }
 }
 
+/// Returns the URLs of the modules that `module` imports.
+fn module_dependencies(graph: &ModuleGraph, module: &deno_graph::Module) -> Vec<Url> {
+    let mut dependencies = vec![];
+    for specifier in module.dependencies.keys() {
+        for prefer_types in [false, true] {
+            if let Some(url) = graph.resolve_dependency(specifier, &module.specifier, prefer_types)
+            {
+                if !dependencies.contains(url) {
+                    dependencies.push(url.clone());
+                }
+            }
+        }
+    }
+    dependencies
+}
+
+/// Returns the modules of `graph` that changed since the compilation that filled `cache`, together
+/// with the modules that depend on them, because their types may have changed.
+fn dirty_modules(graph: &ModuleGraph, cache: &CompileCache) -> HashSet<Url> {
+    let mut dependents: HashMap<Url, Vec<Url>> = HashMap::new();
+    let mut dirty = vec![];
+    for m in graph.modules() {
+        for dependency in module_dependencies(graph, m) {
+            dependents
+                .entry(dependency)
+                .or_default()
+                .push(m.specifier.clone());
+        }
+        let source = m.maybe_source.as_ref().unwrap();
+        let changed = match cache.get(m.specifier.as_str()) {
+            Some(cached) => cached.source != **source,
+            None => true,
+        };
+        if changed {
+            dirty.push(m.specifier.clone());
+        }
+    }
+
+    let mut ret = HashSet::new();
+    while let Some(url) = dirty.pop() {
+        if let Some(urls) = dependents.get(&url) {
+            dirty.extend(urls.iter().filter(|u| !ret.contains(*u)).cloned());
+        }
+        ret.insert(url);
+    }
+    ret
+}
+
+/// Collects the outputs of the modules of `graph`: the outputs of the `dirty` modules are those
+/// that were just `written` by tsc, the outputs of the other modules are taken from `cache`. The
+/// cache is then updated with the modules of `graph`.
+fn collect_outputs(
+    graph: &ModuleGraph,
+    mut written: HashMap<String, String>,
+    url_set: &HashSet<&str>,
+    dirty: Option<&HashSet<Url>>,
+    cache: Option<&mut CompileCache>,
+    downloaded: &RemoteHeaders,
+) -> Vec<(FixedUrl, String, bool)> {
+    let mut prefix_map: HashMap<&str, &Url> = HashMap::default();
+    for m in graph.modules() {
+        let url = &m.specifier;
+        prefix_map.insert(without_extension(url.as_str()), url);
+        match m.media_type {
+            MediaType::JavaScript | MediaType::Mjs => {
+                let source = m.maybe_source.as_ref().unwrap().to_string();
+                written.insert(url.to_string(), source);
+            }
+            _ => {}
+        }
+    }
+    let mut emitted: HashMap<&Url, Vec<(String, bool)>> = HashMap::new();
+    for (k, v) in written {
+        if k.starts_with("chisel://") {
+            continue;
+        }
+        let prefix = without_extension(&k);
+        let is_dts = k.ends_with(".d.ts");
+        let source = prefix_map[prefix];
+        emitted.entry(source).or_default().push((v, is_dts));
+    }
+
+    let mut modules = HashMap::new();
+    let mut reused = HashSet::new();
+    let mut ret = vec![];
+    for m in graph.modules() {
+        let url = &m.specifier;
+        let is_dirty = dirty.map_or(true, |dirty| dirty.contains(url));
+        let outputs = match cache.as_deref() {
+            Some(cache) if !is_dirty => {
+                reused.insert(url.to_string());
+                cache.get(url.as_str()).unwrap().outputs.clone()
+            }
+            _ => emitted.remove(url).unwrap_or_default(),
+        };
+        for (code, is_dts) in &outputs {
+            if *is_dts && !url_set.contains(url.as_str()) {
+                continue;
+            }
+            let source = FixedUrl::parse(url.as_str()).unwrap();
+            ret.push((source, code.clone(), *is_dts));
+        }
+
+        if cache.is_some() {
+            let headers = downloaded.get(url).cloned().or_else(|| {
+                let cached = cache.as_deref()?.get(url.as_str())?;
+                cached.headers.clone()
+            });
+            let module = CachedModule {
+                source: m.maybe_source.as_ref().unwrap().to_string(),
+                headers,
+                outputs,
+            };
+            modules.insert(url.to_string(), module);
+        }
+    }
+    if let Some(cache) = cache {
+        cache.update(modules, reused);
+    }
+    ret
+}
+
 // If the given source can be made relative to one of the urls, return
 // the relative path to, and the original file name of, that url.
 fn find_relative<'a>(
@@ -337,6 +594,30 @@ impl Compiler {
         urls: Vec<Url>,
         opts: CompileOptions<'_>,
     ) -> Result<Vec<(FixedUrl, String, bool)>> {
+        self.compile_urls_impl(urls, opts, None).await
+    }
+
+    /// Like `compile_urls()`, but only checks and emits the modules that changed since the
+    /// compilation that filled `cache` (and the modules that depend on them). The outputs of the
+    /// other modules are taken from `cache`, which is updated on success.
+    pub async fn compile_urls_incremental(
+        &mut self,
+        urls: Vec<Url>,
+        opts: CompileOptions<'_>,
+        cache: &mut CompileCache,
+    ) -> Result<Vec<(FixedUrl, String, bool)>> {
+        self.compile_urls_impl(urls, opts, Some(cache)).await
+    }
+
+    async fn compile_urls_impl(
+        &mut self,
+        urls: Vec<Url>,
+        opts: CompileOptions<'_>,
+        mut cache: Option<&mut CompileCache>,
+    ) -> Result<Vec<(FixedUrl, String, bool)>> {
+        if let Some(cache) = cache.as_mut() {
+            cache.check_options(&opts);
+        }
         let urls: Vec<_> = urls.into_iter().map(|x| (x, ModuleKind::Esm)).collect();
         let url_set: HashSet<_> = urls.iter().map(|x| x.0.as_str()).collect();
         let mut extra_libs = HashMap::new();
@@ -347,8 +628,24 @@ impl Compiler {
             to_url.insert(k.clone(), url);
         }
 
-        let mut loader = ModuleLoader { extra_libs };
-        let resolver = ModuleResolver { extra_libs: to_url };
+        let cached_remote = match cache.as_deref() {
+            Some(cache) if !opts.remote.reload => cache.remote_modules(),
+            _ => HashMap::new(),
+        };
+        let downloaded = Arc::new(Mutex::new(HashMap::new()));
+        let mut loader = ModuleLoader {
+            extra_libs,
+            cached_remote,
+            downloaded: downloaded.clone(),
+            remote_cache: RemoteCache::open_default().map(Arc::new),
+            reload: opts.remote.reload,
+            offline: opts.remote.offline,
+        };
+        let resolver = ModuleResolver {
+            extra_libs: to_url,
+            import_map: opts.import_map.clone(),
+            tsconfig: opts.tsconfig.clone(),
+        };
 
         let extra_default_lib = opts
             .extra_default_lib
@@ -383,6 +680,24 @@ impl Compiler {
             }
             err => anyhow!(err),
         })?;
+        if let Some(path) = &opts.remote.lockfile {
+            lockfile::check(path, &graph)?;
+        }
+
+        // without a cache, every module is checked and emitted
+        let dirty = cache.as_deref().map(|cache| dirty_modules(&graph, cache));
+        if dirty.as_ref().map_or(false, |dirty| dirty.is_empty()) {
+            let written = HashMap::new();
+            let downloaded = downloaded.lock().unwrap();
+            return Ok(collect_outputs(
+                &graph,
+                written,
+                &url_set,
+                dirty.as_ref(),
+                cache,
+                &downloaded,
+            ));
+        }
 
         let mut root_code = "".to_string();
         for u in graph.modules() {
@@ -408,50 +723,58 @@ impl Compiler {
             let emit_declarations = v8::Boolean::new(scope, opts.emit_declarations).into();
             let is_worker = v8::Boolean::new(scope, opts.is_worker).into();
 
+            let checked_files = match &dirty {
+                Some(dirty) => {
+                    let files: Vec<v8::Local<v8::Value>> = dirty
+                        .iter()
+                        .map(|url| v8::String::new(scope, url.as_str()).unwrap().into())
+                        .collect();
+                    v8::Array::new_with_elements(scope, &files).into()
+                }
+                None => v8::undefined(scope).into(),
+            };
+
+            let compiler_options = &opts.tsconfig.compiler_options;
+            let user_options = if compiler_options.is_empty() {
+                v8::undefined(scope).into()
+            } else {
+                let json = serde_json::to_string(compiler_options).unwrap();
+                v8::String::new(scope, &json).unwrap().into()
+            };
+
             let root = v8::String::new(scope, ROOT_URL).unwrap().into();
             compile
                 .call(
                     scope,
                     global_proxy.into(),
-                    &[root, is_worker, lib, emit_declarations],
+                    &[
+                        root,
+                        is_worker,
+                        lib,
+                        emit_declarations,
+                        checked_files,
+                        user_options,
+                    ],
                 )
                 .unwrap();
         }
 
         let op_state = self.runtime.op_state();
         let mut op_state = op_state.borrow_mut();
-        let mut map = op_state.take::<DownloadMap>();
+        let map = op_state.take::<DownloadMap>();
         if !map.diagnostics.is_empty() {
             anyhow::bail!("Compilation failed:\n{}", map.diagnostics);
         }
 
-        let mut prefix_map: HashMap<&str, &Url> = HashMap::default();
-        for m in map.graph.modules() {
-            let url = &m.specifier;
-            prefix_map.insert(without_extension(url.as_str()), url);
-            match m.media_type {
-                MediaType::JavaScript | MediaType::Mjs => {
-                    let source = m.maybe_source.as_ref().unwrap().to_string();
-                    map.written.insert(url.to_string(), source);
-                }
-                _ => {}
-            }
-        }
-        let mut ret = vec![];
-        for (k, v) in map.written {
-            if k.starts_with("chisel://") {
-                continue;
-            }
-            let prefix = without_extension(&k);
-            let is_dts = k.ends_with(".d.ts");
-            let source = prefix_map[prefix];
-            if is_dts && !url_set.contains(source.as_str()) {
-                continue;
-            }
-            let source = FixedUrl::parse(source.as_str()).unwrap();
-            ret.push((source, v, is_dts));
-        }
-        Ok(ret)
+        let downloaded = downloaded.lock().unwrap();
+        Ok(collect_outputs(
+            &map.graph,
+            map.written,
+            &url_set,
+            dirty.as_ref(),
+            cache,
+            &downloaded,
+        ))
     }
 
     pub async fn compile_ts_code(
@@ -499,17 +822,76 @@ pub async fn compile_ts_code(
     compiler.compile_ts_code(file_names, opts).await
 }
 
+/// Compiles `urls` like `Compiler::compile_urls()`, but shards them across up to `jobs`
+/// compilers, each with its own runtime on its own thread, and merges their outputs. A module
+/// that is imported from the roots of several shards is compiled by each of them, so this pays
+/// off when the roots are mostly independent (such as the routes of a project).
+///
+/// This blocks the calling thread until all shards are compiled.
+pub fn compile_urls_parallel(
+    urls: Vec<Url>,
+    opts: CompileOptions<'_>,
+    jobs: usize,
+) -> Result<Vec<(FixedUrl, String, bool)>> {
+    let jobs = jobs.clamp(1, urls.len().max(1));
+    let mut shards = vec![vec![]; jobs];
+    for (i, url) in urls.into_iter().enumerate() {
+        shards[i % jobs].push(url);
+    }
+
+    let results: Vec<Result<_>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .into_iter()
+            .map(|shard| {
+                let opts = opts.clone();
+                scope.spawn(move || {
+                    // the runtime of a compiler cannot leave the thread where it was created
+                    let rt = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()?;
+                    rt.block_on(Compiler::new(true).compile_urls(shard, opts))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect()
+    });
+
+    let mut seen = HashSet::new();
+    let mut ret = vec![];
+    for result in results {
+        for (url, code, is_dts) in result? {
+            if seen.insert((url.clone(), is_dts)) {
+                ret.push((url, code, is_dts));
+            }
+        }
+    }
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod tests {
     use super::abs;
     use super::compile_ts_code;
+    use super::compile_urls_parallel;
+    use super::load_import_map;
+    use super::CompileCache;
     use super::CompileOptions;
     use super::Compiler;
+    use super::TsConfig;
     use anyhow::Result;
     use deno_core::anyhow;
     use deno_core::url::Url;
+    use std::fs;
     use std::future::Future;
     use std::io::Write;
+    use std::path::Path;
     use tempfile::Builder;
     use tempfile::NamedTempFile;
 
@@ -887,6 +1269,200 @@ export default foo;
         test_with_path_variants(check_relative_same_name, "tests/relative_a/bar.ts").await;
     }
 
+    async fn compile_incremental(path: &Path, cache: &mut CompileCache) -> Result<Vec<String>> {
+        let url = Url::from_file_path(path).unwrap();
+        // every compilation uses a new compiler, which doesn't remember the files it has read
+        let mut compiler = Compiler::new(true);
+        let compiled = compiler
+            .compile_urls_incremental(vec![url], Default::default(), cache)
+            .await?;
+        let mut urls: Vec<_> = compiled
+            .into_iter()
+            .map(|(url, _, _)| url.to_string())
+            .collect();
+        urls.sort();
+        Ok(urls)
+    }
+
+    #[tokio::test]
+    async fn incremental() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = dir.path().join("a.ts");
+        let b = dir.path().join("b.ts");
+        let c = dir.path().join("c.ts");
+        let url = |path: &Path| Url::from_file_path(path).unwrap().to_string();
+        fs::write(
+            &a,
+            "import { b } from './b.ts'; import { c } from './c.ts'; export const a: number = b + c;",
+        )?;
+        fs::write(&b, "export const b = 1;")?;
+        fs::write(&c, "export const c = 2;")?;
+
+        let mut cache = CompileCache::default();
+        let all = compile_incremental(&a, &mut cache).await?;
+        assert_eq!(all, vec![url(&a), url(&b), url(&c)]);
+        assert!(!cache.is_reused(&url(&a)));
+
+        // nothing changed, so all outputs are reused
+        assert_eq!(compile_incremental(&a, &mut cache).await?, all);
+        assert!(cache.is_reused(&url(&a)) && cache.is_reused(&url(&b)));
+
+        // the module that imports the changed module is compiled again
+        fs::write(&b, "export const b = 3;")?;
+        assert_eq!(compile_incremental(&a, &mut cache).await?, all);
+        assert!(!cache.is_reused(&url(&a)) && !cache.is_reused(&url(&b)));
+        assert!(cache.is_reused(&url(&c)));
+
+        // and it is checked again
+        fs::write(&b, "export const b = 'x';")?;
+        let err = compile_incremental(&a, &mut cache).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Type 'string' is not assignable to type 'number'"));
+
+        // the cache survives a round trip through a file
+        fs::write(&b, "export const b = 4;")?;
+        compile_incremental(&a, &mut cache).await?;
+        let cache_path = dir.path().join("cache/tsc.json");
+        cache.save(&cache_path)?;
+        let mut cache = CompileCache::load(&cache_path);
+        assert_eq!(compile_incremental(&a, &mut cache).await?, all);
+        assert!(cache.is_reused(&url(&a)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tsconfig() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("lib"))?;
+        fs::write(
+            dir.path().join("lib/util.ts"),
+            "export function id(x) { return x; }",
+        )?;
+        let main = dir.path().join("main.ts");
+        fs::write(
+            &main,
+            "import { id } from '@lib/util'; export const a = id(1);",
+        )?;
+        let main = main.to_str().unwrap();
+
+        let err = compile_ts_code(&[main], Default::default())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("failed to resolve module"));
+
+        fs::write(
+            dir.path().join("tsconfig.json"),
+            r#"{
+                // the parameter of id() has an implicit any type
+                "compilerOptions": {
+                    "noImplicitAny": false,
+                    "noEmit": true,
+                    "paths": {"@lib/*": ["./lib/*"]},
+                },
+            }"#,
+        )?;
+        let opts = CompileOptions {
+            tsconfig: TsConfig::load(dir.path())?,
+            ..Default::default()
+        };
+        compile_ts_code(&[main], opts).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn parallel() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        fs::write(path("common.ts"), "export const one = 1;")?;
+        fs::write(
+            path("a.ts"),
+            "import { one } from './common.ts'; export const a = one;",
+        )?;
+        fs::write(
+            path("b.ts"),
+            "import { one } from './common.ts'; export const b = one;",
+        )?;
+        fs::write(path("c.ts"), "export const c: number = 'c';")?;
+        let url = |name: &str| Url::from_file_path(path(name)).unwrap();
+
+        let compiled =
+            compile_urls_parallel(vec![url("a.ts"), url("b.ts")], Default::default(), 2)?;
+        let mut urls: Vec<String> = compiled.iter().map(|(url, _, _)| url.to_string()).collect();
+        urls.sort();
+        let mut expected: Vec<String> = ["a.ts", "b.ts", "common.ts"]
+            .iter()
+            .map(|name| url(name).to_string())
+            .collect();
+        expected.sort();
+        assert_eq!(urls, expected);
+
+        // an error in any shard fails the compilation
+        let err = compile_urls_parallel(vec![url("a.ts"), url("c.ts")], Default::default(), 2)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Type 'string' is not assignable to type 'number'"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tsx() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let main = dir.path().join("main.tsx");
+        fs::write(
+            &main,
+            r#"
+            declare global {
+                namespace JSX {
+                    interface IntrinsicElements { [name: string]: unknown }
+                }
+            }
+            function h(tag: string, props: unknown, ...children: unknown[]) {
+                return { tag, props, children };
+            }
+            export const greeting = <h1 class="title">Hello</h1>;
+            "#,
+        )?;
+        fs::write(
+            dir.path().join("tsconfig.json"),
+            r#"{"compilerOptions": {"jsxFactory": "h"}}"#,
+        )?;
+        let main = main.to_str().unwrap();
+        let opts = CompileOptions {
+            tsconfig: TsConfig::load(dir.path())?,
+            ..Default::default()
+        };
+        let written = compile_ts_code(&[main], opts).await?;
+        assert!(written[main].contains(r#"h("h1", { class: "title" }, "Hello")"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_map() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("lib"))?;
+        fs::write(dir.path().join("lib/util.ts"), "export const one = 1;")?;
+        let main = dir.path().join("main.ts");
+        fs::write(
+            &main,
+            "import { one } from '@lib/util.ts'; export const a = one;",
+        )?;
+        let main = main.to_str().unwrap();
+
+        fs::write(
+            dir.path().join("import_map.json"),
+            r#"{"imports": {"@lib/": "./lib/"}}"#,
+        )?;
+        let opts = CompileOptions {
+            import_map: load_import_map(dir.path())?,
+            ..Default::default()
+        };
+        let written = compile_ts_code(&[main], opts).await?;
+        assert!(written.keys().any(|name| name.ends_with("lib/util.ts")));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn import_mjs() {
         check_import("tests/import-mjs.ts".to_string(), ".ts", ".mjs").await;
@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Lockfile with the SHA-256 of every remote module (like `deno.lock`). The hash of a module is
+//! recorded the first time that the module is compiled, and the compilation fails if the module
+//! changes later, so that a project always runs the remote code that it was tested with.
+
+use crate::remote_cache::hash;
+use anyhow::{bail, Context, Result};
+use deno_core::anyhow;
+use deno_graph::ModuleGraph;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Serializes the updates of lockfiles by the compilations that run in parallel (see
+/// `compile_urls_parallel()`), which would otherwise lose each other's new modules.
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Default, Serialize, Deserialize)]
+struct Lockfile {
+    /// SHA-256 of the remote modules, by URL.
+    remote: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Checks that the module at `url` has the recorded hash, or records it if the module is new.
+    /// Returns true if the module was recorded.
+    fn verify(&mut self, url: &str, source: &str) -> Result<bool> {
+        let actual = hash(source.as_bytes());
+        match self.remote.get(url) {
+            Some(expected) if *expected == actual => Ok(false),
+            Some(expected) => bail!(
+                "Module {} has changed: its SHA-256 is {}, but the lockfile expects {}. If the change is expected, remove the module from the lockfile",
+                url,
+                actual,
+                expected
+            ),
+            None => {
+                self.remote.insert(url.to_string(), actual);
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Checks the remote modules of `graph` against the lockfile at `path`, and records the modules
+/// that are not in the lockfile yet.
+pub(crate) fn check(path: &Path, graph: &ModuleGraph) -> Result<()> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut lockfile: Lockfile = match fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .with_context(|| format!("Could not parse lockfile {}", path.display()))?,
+        Err(_) => Lockfile::default(),
+    };
+
+    let mut changed = false;
+    for m in graph.modules() {
+        if !matches!(m.specifier.scheme(), "http" | "https") {
+            continue;
+        }
+        let source = m.maybe_source.as_ref().unwrap();
+        changed |= lockfile
+            .verify(m.specifier.as_str(), source)
+            .with_context(|| format!("Integrity check with lockfile {} failed", path.display()))?;
+    }
+
+    if changed {
+        let mut data = serde_json::to_string_pretty(&lockfile)?;
+        data.push('\n');
+        fs::write(path, data)
+            .with_context(|| format!("Could not write lockfile {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lockfile;
+
+    #[test]
+    fn verify() {
+        let mut lockfile = Lockfile::default();
+        let url = "https://cdn.example.com/a.js";
+        assert!(lockfile.verify(url, "export {}").unwrap());
+        assert!(!lockfile.verify(url, "export {}").unwrap());
+        let err = lockfile.verify(url, "export const a = 1;").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Module https://cdn.example.com/a.js has changed"));
+        assert!(lockfile
+            .verify("https://cdn.example.com/b.js", "export const a = 1;")
+            .unwrap());
+    }
+}
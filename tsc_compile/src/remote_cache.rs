@@ -0,0 +1,243 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Disk cache of remote modules (`https://` imports), which is shared by all compilations of the
+//! user (`chisel dev`, `chisel apply` and the tests), so that the modules are not downloaded again
+//! by every compilation.
+//!
+//! The cache lives in `~/.cache/chiselstrike/remote`. The content of the modules is stored by its
+//! SHA-256 in `blobs/`, and `index/` maps the URL of a module to its content, its headers and the
+//! time when it was fetched. A module is fetched again when it is older than allowed by its
+//! `Cache-Control` header, with a conditional request if the server sent an `ETag` or a
+//! `Last-Modified` header.
+
+use anyhow::{Context, Result};
+use deno_core::anyhow;
+use deno_core::url::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct RemoteCache {
+    dir: PathBuf,
+}
+
+/// Entry of the index of the cache.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedRemote {
+    url: String,
+    pub headers: HashMap<String, String>,
+    /// SHA-256 of the content, which is stored in `blobs/`.
+    content_hash: String,
+    /// When the module was fetched (or revalidated), in seconds since the Unix epoch.
+    fetched_at: u64,
+    /// How long the module may be used without fetching it again, `None` if forever.
+    max_age_s: Option<u64>,
+}
+
+impl CachedRemote {
+    pub fn is_fresh(&self, now: u64) -> bool {
+        match self.max_age_s {
+            Some(max_age_s) => now < self.fetched_at.saturating_add(max_age_s),
+            None => true,
+        }
+    }
+
+    /// Headers of a conditional request, which the server answers with `304 Not Modified` if the
+    /// cached module is still valid.
+    pub fn validators(&self) -> Vec<(&'static str, String)> {
+        let mut validators = vec![];
+        if let Some(etag) = self.headers.get("etag") {
+            validators.push(("if-none-match", etag.clone()));
+        }
+        if let Some(last_modified) = self.headers.get("last-modified") {
+            validators.push(("if-modified-since", last_modified.clone()));
+        }
+        validators
+    }
+}
+
+impl RemoteCache {
+    pub fn new(dir: PathBuf) -> RemoteCache {
+        RemoteCache { dir }
+    }
+
+    /// Opens the cache in the cache directory of the user, if there is one.
+    pub fn open_default() -> Option<RemoteCache> {
+        let dir = dirs::cache_dir()?.join("chiselstrike").join("remote");
+        Some(RemoteCache::new(dir))
+    }
+
+    /// Returns the cached module at `url` with its content. A missing or corrupted entry is
+    /// treated as not cached.
+    pub(crate) fn get(&self, url: &Url) -> Option<(CachedRemote, String)> {
+        let data = fs::read(self.index_path(url)).ok()?;
+        let entry: CachedRemote = serde_json::from_slice(&data).ok()?;
+        if entry.url != url.as_str() {
+            return None;
+        }
+        let content = fs::read_to_string(self.blob_path(&entry.content_hash)).ok()?;
+        if hash(content.as_bytes()) != entry.content_hash {
+            return None;
+        }
+        Some((entry, content))
+    }
+
+    /// Stores a module that was just fetched, unless its headers forbid it.
+    pub(crate) fn put(
+        &self,
+        url: &Url,
+        headers: &HashMap<String, String>,
+        content: &str,
+        now: u64,
+    ) -> Result<()> {
+        let max_age_s = match cache_lifetime(headers) {
+            Some(max_age_s) => max_age_s,
+            None => return Ok(()),
+        };
+        let content_hash = hash(content.as_bytes());
+        let blob_path = self.blob_path(&content_hash);
+        if !blob_path.exists() {
+            write_atomic(&blob_path, content.as_bytes())?;
+        }
+        let entry = CachedRemote {
+            url: url.to_string(),
+            headers: headers.clone(),
+            content_hash,
+            fetched_at: now,
+            max_age_s,
+        };
+        write_atomic(&self.index_path(url), &serde_json::to_vec(&entry)?)
+    }
+
+    /// Marks a cached module as fetched at `now`, after the server confirmed that it is still
+    /// valid.
+    pub(crate) fn revalidated(&self, url: &Url, mut entry: CachedRemote, now: u64) -> Result<()> {
+        entry.fetched_at = now;
+        write_atomic(&self.index_path(url), &serde_json::to_vec(&entry)?)
+    }
+
+    fn index_path(&self, url: &Url) -> PathBuf {
+        self.dir
+            .join("index")
+            .join(hash(url.as_str().as_bytes()) + ".json")
+    }
+
+    fn blob_path(&self, content_hash: &str) -> PathBuf {
+        self.dir.join("blobs").join(content_hash)
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub(crate) fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Returns how long a module with `headers` may be cached (`Some(None)` if forever), or `None`
+/// if it must not be cached at all. Like deno, a module without `Cache-Control` is cached until
+/// `--reload`.
+fn cache_lifetime(headers: &HashMap<String, String>) -> Option<Option<u64>> {
+    let cache_control = match headers.get("cache-control") {
+        Some(cache_control) => cache_control.to_ascii_lowercase(),
+        None => return Some(None),
+    };
+    let mut max_age_s = None;
+    for directive in cache_control.split(',').map(str::trim) {
+        if directive == "no-store" {
+            return None;
+        } else if directive == "immutable" {
+            return Some(None);
+        } else if directive == "no-cache" {
+            max_age_s = Some(0);
+        } else if let Some(value) = directive.strip_prefix("max-age=") {
+            if max_age_s.is_none() {
+                max_age_s = Some(value.trim_matches('"').parse().unwrap_or(0));
+            }
+        }
+    }
+    Some(max_age_s)
+}
+
+/// Writes the file through a temporary file, so that concurrent compilations never read a
+/// partially written file.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+    let tmp = tempfile::NamedTempFile::new_in(dir)?;
+    fs::write(tmp.path(), data)?;
+    tmp.persist(path)
+        .with_context(|| format!("Could not write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cache_lifetime, RemoteCache};
+    use deno_core::url::Url;
+    use std::collections::HashMap;
+
+    fn headers(cache_control: Option<&str>) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        headers.insert("etag".to_string(), "\"abc\"".to_string());
+        if let Some(cache_control) = cache_control {
+            headers.insert("cache-control".to_string(), cache_control.to_string());
+        }
+        headers
+    }
+
+    #[test]
+    fn lifetime() {
+        assert_eq!(cache_lifetime(&headers(None)), Some(None));
+        assert_eq!(
+            cache_lifetime(&headers(Some("public, max-age=600"))),
+            Some(Some(600))
+        );
+        assert_eq!(
+            cache_lifetime(&headers(Some("max-age=31536000, immutable"))),
+            Some(None)
+        );
+        assert_eq!(
+            cache_lifetime(&headers(Some("no-cache, max-age=600"))),
+            Some(Some(0))
+        );
+        assert_eq!(cache_lifetime(&headers(Some("private, no-store"))), None);
+    }
+
+    #[test]
+    fn put_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RemoteCache::new(dir.path().to_path_buf());
+        let url = Url::parse("https://cdn.example.com/a.js").unwrap();
+        let other = Url::parse("https://cdn.example.com/b.js").unwrap();
+        assert!(cache.get(&url).is_none());
+
+        cache
+            .put(&url, &headers(Some("max-age=60")), "export {}", 1000)
+            .unwrap();
+        let (entry, content) = cache.get(&url).unwrap();
+        assert_eq!(content, "export {}");
+        assert!(entry.is_fresh(1059));
+        assert!(!entry.is_fresh(1060));
+        assert_eq!(
+            entry.validators(),
+            vec![("if-none-match", "\"abc\"".to_string())]
+        );
+        assert!(cache.get(&other).is_none());
+
+        cache.revalidated(&url, entry, 2000).unwrap();
+        assert!(cache.get(&url).unwrap().0.is_fresh(2059));
+
+        // modules that must not be stored are not
+        cache
+            .put(&other, &headers(Some("no-store")), "export {}", 1000)
+            .unwrap();
+        assert!(cache.get(&other).is_none());
+    }
+}
@@ -0,0 +1,299 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Compiler options of a project, read from its `tsconfig.json`.
+
+use anyhow::{Context, Result};
+use deno_core::anyhow;
+use deno_core::url::Url;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// Options of `compilerOptions` that are passed to tsc. The other options (such as `module`,
+/// `lib`, `jsx` or `noEmit`) are controlled by ChiselStrike, or they are only meant for the
+/// editor.
+const PASSED_OPTIONS: &[&str] = &[
+    "target",
+    "strict",
+    "alwaysStrict",
+    "noImplicitAny",
+    "noImplicitThis",
+    "strictNullChecks",
+    "strictFunctionTypes",
+    "strictBindCallApply",
+    "strictPropertyInitialization",
+    "useUnknownInCatchVariables",
+    "exactOptionalPropertyTypes",
+    "noUncheckedIndexedAccess",
+    "noImplicitReturns",
+    "noImplicitOverride",
+    "noFallthroughCasesInSwitch",
+    "noUnusedLocals",
+    "noUnusedParameters",
+    "experimentalDecorators",
+    "emitDecoratorMetadata",
+    "useDefineForClassFields",
+    "jsxFactory",
+    "jsxFragmentFactory",
+];
+
+#[derive(Default, Clone, Debug)]
+pub struct TsConfig {
+    /// Options that are passed to tsc, in the form of `compilerOptions`.
+    pub compiler_options: Map<String, Value>,
+    /// Path aliases (`paths`), with their targets resolved against `baseUrl`.
+    pub paths: Vec<(String, Vec<Url>)>,
+}
+
+impl TsConfig {
+    /// Reads `tsconfig.json` in the directory `dir` (which must be absolute). Without the file,
+    /// the default options are used.
+    pub fn load(dir: &Path) -> Result<TsConfig> {
+        let path = dir.join("tsconfig.json");
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return Ok(TsConfig::default()),
+        };
+        TsConfig::parse(&text, dir).with_context(|| format!("Could not read {}", path.display()))
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<TsConfig> {
+        let json: Value = serde_json::from_str(&strip_jsonc(text))?;
+        let mut options = match json.get("compilerOptions") {
+            Some(Value::Object(options)) => options.clone(),
+            Some(_) => anyhow::bail!("compilerOptions must be an object"),
+            None => Map::new(),
+        };
+
+        let base_dir = match options.get("baseUrl") {
+            Some(Value::String(base_url)) => dir.join(base_url),
+            _ => dir.to_path_buf(),
+        };
+        let base_url = Url::from_directory_path(&base_dir)
+            .map_err(|_| anyhow::anyhow!("Invalid baseUrl {}", base_dir.display()))?;
+        let mut paths = vec![];
+        if let Some(Value::Object(aliases)) = options.get("paths") {
+            for (pattern, targets) in aliases {
+                let targets: Vec<String> =
+                    serde_json::from_value(targets.clone()).with_context(|| {
+                        format!("Targets of path alias {} must be strings", pattern)
+                    })?;
+                let targets = targets
+                    .iter()
+                    .map(|target| base_url.join(target))
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(|| format!("Invalid target of path alias {}", pattern))?;
+                paths.push((pattern.clone(), targets));
+            }
+        }
+        // like tsc, prefer the alias with the longest prefix
+        paths.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern_prefix(pattern).len()));
+
+        options.retain(|name, _| PASSED_OPTIONS.contains(&name.as_str()));
+        Ok(TsConfig {
+            compiler_options: options,
+            paths,
+        })
+    }
+
+    /// Resolves `specifier` with the path aliases. Like tsc, the first target that exists is
+    /// used, and a missing `.ts` extension is added.
+    pub(crate) fn resolve_alias(&self, specifier: &str) -> Option<Url> {
+        for (pattern, targets) in &self.paths {
+            let matched = match pattern.split_once('*') {
+                Some((prefix, suffix)) => specifier
+                    .strip_prefix(prefix)
+                    .and_then(|rest| rest.strip_suffix(suffix)),
+                None if pattern == specifier => Some(""),
+                None => None,
+            };
+            let matched = match matched {
+                Some(matched) => matched,
+                None => continue,
+            };
+            let mut candidates = vec![];
+            for target in targets {
+                let url = Url::parse(&target.as_str().replacen('*', matched, 1)).ok()?;
+                let with_ext = Url::parse(&format!("{}.ts", url)).ok()?;
+                candidates.push(url);
+                candidates.push(with_ext);
+            }
+            let exists = |url: &Url| url.to_file_path().map_or(false, |path| path.is_file());
+            return candidates
+                .iter()
+                .find(|url| exists(url))
+                .or_else(|| candidates.first())
+                .cloned();
+        }
+        None
+    }
+
+    /// Returns the path aliases as the imports of an import map, so that the server can resolve
+    /// the aliases that remain in the compiled code. Only the first target of an alias is used,
+    /// and the aliases with a suffix after the `*` cannot be expressed as imports.
+    pub fn alias_imports(&self) -> Vec<(String, String)> {
+        let mut imports = vec![];
+        for (pattern, targets) in &self.paths {
+            let target = match targets.first() {
+                Some(target) => target,
+                None => continue,
+            };
+            match pattern.split_once('*') {
+                Some((prefix, "")) if prefix.ends_with('/') => {
+                    if let Some(dir) = target.as_str().strip_suffix('*') {
+                        if dir.ends_with('/') {
+                            imports.push((prefix.to_string(), dir.to_string()));
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(url) = self.resolve_alias(pattern) {
+                        imports.push((pattern.clone(), url.to_string()));
+                    }
+                }
+            }
+        }
+        imports
+    }
+}
+
+fn pattern_prefix(pattern: &str) -> &str {
+    pattern.split('*').next().unwrap()
+}
+
+/// Converts the JSON with comments and trailing commas of `tsconfig.json` to plain JSON.
+fn strip_jsonc(text: &str) -> String {
+    strip_trailing_commas(&strip_comments(text))
+}
+
+fn strip_comments(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut ret = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if in_string {
+            ret.push(c);
+            if c == '\\' {
+                ret.extend(next);
+                i += 1;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+            continue;
+        } else {
+            in_string = c == '"';
+            ret.push(c);
+        }
+        i += 1;
+    }
+    ret
+}
+
+fn strip_trailing_commas(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            let next = text[i + 1..].trim_start().chars().next();
+            if matches!(next, Some('}' | ']')) {
+                continue;
+            }
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_jsonc, TsConfig};
+    use serde_json::json;
+    use std::path::Path;
+
+    #[test]
+    fn jsonc() {
+        let text = r#"{
+            // comment
+            "a": "x // not a comment", /* block
+            comment */ "b": [1, 2,],
+            "c": "\"quoted\"",
+        }"#;
+        let json: serde_json::Value = serde_json::from_str(&strip_jsonc(text)).unwrap();
+        assert_eq!(
+            json,
+            json!({"a": "x // not a comment", "b": [1, 2], "c": "\"quoted\""})
+        );
+    }
+
+    #[test]
+    fn options() {
+        let text = r#"{
+            "compilerOptions": {
+                "target": "ES2020",
+                "strict": false,
+                "noEmit": true,
+                "module": "commonjs",
+                "baseUrl": "./src",
+                "paths": {"@lib/*": ["./lib/*"], "@lib/special/*": ["./special/*"], "cfg": ["./config.ts"]}
+            }
+        }"#;
+        let config = TsConfig::parse(text, Path::new("/project")).unwrap();
+        assert_eq!(
+            serde_json::Value::Object(config.compiler_options.clone()),
+            json!({"target": "ES2020", "strict": false})
+        );
+
+        let resolve = |specifier| config.resolve_alias(specifier).map(|url| url.to_string());
+        assert_eq!(
+            resolve("@lib/util.ts").as_deref(),
+            Some("file:///project/src/lib/util.ts")
+        );
+        assert_eq!(
+            resolve("@lib/special/a.ts").as_deref(),
+            Some("file:///project/src/special/a.ts")
+        );
+        assert_eq!(
+            resolve("cfg").as_deref(),
+            Some("file:///project/src/config.ts")
+        );
+        assert_eq!(resolve("other"), None);
+
+        let mut imports = config.alias_imports();
+        imports.sort();
+        assert_eq!(
+            imports,
+            vec![
+                ("@lib/".to_string(), "file:///project/src/lib/".to_string()),
+                (
+                    "@lib/special/".to_string(),
+                    "file:///project/src/special/".to_string()
+                ),
+                (
+                    "cfg".to_string(),
+                    "file:///project/src/config.ts".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Watch mode for embedders (see `Compiler::watch()`), which compiles the modules again whenever
+//! a file changes. The runtime of the compiler is created only once, and the unchanged modules
+//! (including the remote ones) are reused from the previous compilation.
+
+use crate::{CompileCache, CompileOptions, Compiler, FixedUrl};
+use anyhow::{bail, Result};
+use deno_core::anyhow;
+use deno_core::url::Url;
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait for more changes after a change, so that a burst of changes (such as an
+/// editor saving many files) is compiled only once.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Outputs of the last successful compilation, as `(url, is_dts) -> code`.
+type Outputs = HashMap<(FixedUrl, bool), String>;
+
+impl Compiler {
+    /// Compiles `urls`, and then compiles them again whenever a file under `paths` changes,
+    /// until `callback` breaks. The compilations are incremental (see
+    /// `compile_urls_incremental()`), and `callback` receives only the outputs that changed since
+    /// the previous successful compilation (all the outputs the first time), or the error of a
+    /// failed compilation.
+    pub async fn watch<F>(
+        &mut self,
+        urls: Vec<Url>,
+        paths: &[PathBuf],
+        opts: CompileOptions<'_>,
+        mut callback: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Result<Vec<(FixedUrl, String, bool)>>) -> ControlFlow<()>,
+    {
+        // the watcher is started before the first compilation, so that no change is missed
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = RecommendedWatcher::new(
+            move |res: notify::Result<Event>| {
+                let _ = tx.send(res);
+            },
+            Config::default(),
+        )?;
+        for path in paths {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+        }
+
+        let mut cache = CompileCache::default();
+        let mut previous = Outputs::new();
+        loop {
+            let result = self
+                .compile_urls_incremental(urls.clone(), opts.clone(), &mut cache)
+                .await
+                .map(|outputs| changed_outputs(&mut previous, outputs));
+            if callback(result).is_break() {
+                return Ok(());
+            }
+
+            loop {
+                match rx.recv().await {
+                    Some(res) if is_change(&res?) => break,
+                    Some(_) => {}
+                    None => bail!("The file watcher stopped"),
+                }
+            }
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+        }
+    }
+}
+
+fn is_change(event: &Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    )
+}
+
+/// Returns the `outputs` that differ from the `previous` outputs, which are then replaced with
+/// `outputs`. A module that is emitted again with the same code (for example, because a module
+/// that it imports changed) is not returned.
+fn changed_outputs(
+    previous: &mut Outputs,
+    outputs: Vec<(FixedUrl, String, bool)>,
+) -> Vec<(FixedUrl, String, bool)> {
+    let mut current = Outputs::new();
+    let mut changed = vec![];
+    for (url, code, is_dts) in outputs {
+        let key = (url, is_dts);
+        if previous.get(&key) != Some(&code) {
+            changed.push((key.0.clone(), code.clone(), is_dts));
+        }
+        current.insert(key, code);
+    }
+    *previous = current;
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Compiler;
+    use anyhow::Result;
+    use deno_core::anyhow;
+    use deno_core::url::Url;
+    use std::fs;
+    use std::ops::ControlFlow;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn watch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = dir.path().join("a.ts");
+        let b = dir.path().join("b.ts");
+        fs::write(&a, "import { b } from './b.ts'; export const a = b;")?;
+        fs::write(&b, "export const b = 1;")?;
+        let url = |path: &Path| Url::from_file_path(path).unwrap().to_string();
+
+        let mut compilations = vec![];
+        let mut compiler = Compiler::new(true);
+        compiler
+            .watch(
+                vec![Url::from_file_path(&a).unwrap()],
+                &[dir.path().to_path_buf()],
+                Default::default(),
+                |result| {
+                    let mut changed: Vec<String> = result
+                        .unwrap()
+                        .into_iter()
+                        .map(|(url, _, _)| url.to_string())
+                        .collect();
+                    changed.sort();
+                    compilations.push(changed);
+                    if compilations.len() == 1 {
+                        fs::write(&b, "export const b = 2;").unwrap();
+                        ControlFlow::Continue(())
+                    } else {
+                        ControlFlow::Break(())
+                    }
+                },
+            )
+            .await?;
+
+        // a.ts is compiled again, because it imports b.ts, but its output is the same
+        assert_eq!(compilations, vec![vec![url(&a), url(&b)], vec![url(&b)]]);
+        Ok(())
+    }
+}
@@ -60,7 +60,8 @@
                 // handle user libraries that don't end in .ts
                 // (like @foo/bar). We should probably get the extension
                 // from rust.
-                ret.push({ resolvedFileName: fname, extension: ".ts" });
+                const extension = fname.endsWith(".tsx") ? ".tsx" : ".ts";
+                ret.push({ resolvedFileName: fname, extension });
             }
             return ret;
         },
@@ -94,7 +95,17 @@
     };
 
     const readCache = {};
-    function compileAux(root, isWorker, lib, emitDeclarations) {
+    // If `checkedFiles` is given, only these files are checked and emitted, the outputs of the
+    // other files are reused from a previous compilation. `userOptions` are the `compilerOptions`
+    // of the tsconfig.json of the project (as JSON), which override the defaults.
+    function compileAux(
+        root,
+        isWorker,
+        lib,
+        emitDeclarations,
+        checkedFiles,
+        userOptions,
+    ) {
         const defaultLibs = [
             "lib.deno.unstable.d.ts",
             "lib.deno_core.d.ts",
@@ -115,6 +126,10 @@
             emitDecoratorMetadata: false,
             experimentalDecorators: true,
             isolatedModules: true,
+            // JSX in .tsx files is compiled to calls of `jsxFactory` (`React.createElement` by
+            // default), which the user can change in tsconfig.json (for example, to `h` for
+            // Preact)
+            jsx: ts.JsxEmit.React,
             lib: defaultLibs,
             module: ts.ModuleKind.ESNext,
             noImplicitAny: true,
@@ -124,13 +139,53 @@
             target: ts.ScriptTarget.ESNext,
             types: [],
         };
+        if (userOptions !== undefined) {
+            const converted = ts.convertCompilerOptionsFromJson(
+                JSON.parse(userOptions),
+                host.getCurrentDirectory(),
+                "tsconfig.json",
+            );
+            if (converted.errors.length != 0) {
+                const diag = ts.formatDiagnosticsWithColorAndContext(
+                    converted.errors,
+                    host,
+                );
+                Deno.core.opSync("diagnostic", diag);
+                return;
+            }
+            Object.assign(options, converted.options);
+        }
 
         const program = ts.createProgram([root], options, host);
-        const emitResult = program.emit();
-
-        let allDiagnostics = ts
-            .getPreEmitDiagnostics(program)
-            .concat(emitResult.diagnostics);
+        let allDiagnostics;
+        if (checkedFiles === undefined) {
+            const emitResult = program.emit();
+            allDiagnostics = ts
+                .getPreEmitDiagnostics(program)
+                .concat(emitResult.diagnostics);
+        } else {
+            allDiagnostics = [
+                ...program.getConfigFileParsingDiagnostics(),
+                ...program.getOptionsDiagnostics(),
+                ...program.getGlobalDiagnostics(),
+            ];
+            for (const fileName of checkedFiles) {
+                const sourceFile = program.getSourceFile(fileName);
+                if (sourceFile === undefined) {
+                    continue;
+                }
+                allDiagnostics.push(
+                    ...program.getSyntacticDiagnostics(sourceFile),
+                    ...program.getSemanticDiagnostics(sourceFile),
+                );
+                if (emitDeclarations) {
+                    allDiagnostics.push(
+                        ...program.getDeclarationDiagnostics(sourceFile),
+                    );
+                }
+                allDiagnostics.push(...program.emit(sourceFile).diagnostics);
+            }
+        }
 
         allDiagnostics = ts.sortAndDeduplicateDiagnostics(allDiagnostics);
         allDiagnostics = allDiagnostics.filter(({ code }) => {
@@ -149,9 +204,23 @@
         }
     }
 
-    function compile(root, isWorker, lib, emitDeclarations) {
+    function compile(
+        root,
+        isWorker,
+        lib,
+        emitDeclarations,
+        checkedFiles,
+        userOptions,
+    ) {
         try {
-            return compileAux(root, isWorker, lib, emitDeclarations);
+            return compileAux(
+                root,
+                isWorker,
+                lib,
+                emitDeclarations,
+                checkedFiles,
+                userOptions,
+            );
         } catch (e) {
             Deno.core.opSync("diagnostic", e.stack + "\n");
             return false;
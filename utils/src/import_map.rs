@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Import maps, which map bare specifiers (such as `@lib/util.ts`) to URLs. They are used both
+//! when the project is compiled and when its modules are loaded by the server, so that the
+//! compiled code can keep the specifiers that the user wrote.
+
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+
+/// Only the top-level `imports` of an import map are supported: a key either matches a
+/// specifier exactly, or, if it ends with `/`, it matches every specifier that starts with it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportMap {
+    /// Keys with their (absolute) target URLs, with the longest keys first, so that the most
+    /// specific key wins.
+    imports: Vec<(String, String)>,
+}
+
+impl ImportMap {
+    pub fn new(imports: impl IntoIterator<Item = (String, String)>) -> Result<ImportMap> {
+        let mut imports: Vec<(String, String)> = imports.into_iter().collect();
+        for (key, target) in &imports {
+            Url::parse(target)
+                .with_context(|| format!("Target {:?} of import {:?} is not a URL", target, key))?;
+            if key.ends_with('/') && !target.ends_with('/') {
+                bail!(
+                    "Target {:?} of import {:?} must end with '/', like the import",
+                    target,
+                    key
+                );
+            }
+        }
+        imports.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        Ok(ImportMap { imports })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.imports.is_empty()
+    }
+
+    /// Returns the imports as `(key, target)`.
+    pub fn imports(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.imports.iter().map(|(k, t)| (k.as_str(), t.as_str()))
+    }
+
+    /// Returns the URL that `specifier` is mapped to, or `None` if it is not mapped. A specifier
+    /// that would escape the target of a prefix key (with `../`) is not mapped.
+    pub fn resolve(&self, specifier: &str) -> Option<String> {
+        for (key, target) in &self.imports {
+            if key == specifier {
+                return Some(target.clone());
+            }
+            if !key.ends_with('/') {
+                continue;
+            }
+            if let Some(rest) = specifier.strip_prefix(key.as_str()) {
+                let url = Url::parse(target).ok()?.join(rest).ok()?;
+                return url.as_str().starts_with(target).then(|| url.to_string());
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImportMap;
+
+    fn import_map(imports: &[(&str, &str)]) -> anyhow::Result<ImportMap> {
+        ImportMap::new(
+            imports
+                .iter()
+                .map(|(key, target)| (key.to_string(), target.to_string())),
+        )
+    }
+
+    #[test]
+    fn resolve() {
+        let map = import_map(&[
+            ("@lib/", "file:///project/lib/"),
+            ("@lib/special/", "file:///project/special/"),
+            ("config", "file:///project/config.ts"),
+            ("std/", "https://deno.land/std@0.165.0/"),
+        ])
+        .unwrap();
+        let resolve = |specifier| map.resolve(specifier);
+        assert_eq!(
+            resolve("@lib/util.ts").as_deref(),
+            Some("file:///project/lib/util.ts")
+        );
+        assert_eq!(
+            resolve("@lib/special/a.ts").as_deref(),
+            Some("file:///project/special/a.ts")
+        );
+        assert_eq!(
+            resolve("config").as_deref(),
+            Some("file:///project/config.ts")
+        );
+        assert_eq!(
+            resolve("std/path/mod.ts").as_deref(),
+            Some("https://deno.land/std@0.165.0/path/mod.ts")
+        );
+        assert_eq!(resolve("config/a.ts"), None);
+        assert_eq!(resolve("@lib/../secret.ts"), None);
+        assert_eq!(resolve("./lib/util.ts"), None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(import_map(&[("a", "./a.ts")]).is_err());
+        assert!(import_map(&[("a/", "file:///project/a.ts")]).is_err());
+    }
+}
@@ -8,9 +8,13 @@ use std::panic;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-/// Drop the extension (.d.ts/.ts/.js) from a path
+mod import_map;
+
+pub use import_map::ImportMap;
+
+/// Drop the extension (.d.ts/.ts/.tsx/.js) from a path
 pub fn without_extension(path: &str) -> &str {
-    for suffix in [".d.ts", ".ts", ".js"] {
+    for suffix in [".d.ts", ".ts", ".tsx", ".js"] {
         if let Some(s) = path.strip_suffix(suffix) {
             return s;
         }
@@ -26,6 +30,21 @@ pub async fn get_ok(url: Url) -> Result<Response> {
     Ok(res)
 }
 
+/// Like `get_ok`, but sends `headers` with the request, and also accepts the status
+/// `304 Not Modified`, which is the answer to a conditional request.
+pub async fn get_conditional(url: Url, headers: &[(&str, String)]) -> Result<Response> {
+    let mut req = reqwest::Client::new().get(url);
+    for (name, value) in headers {
+        req = req.header(*name, value);
+    }
+    let res = req.send().await?;
+    ensure!(
+        res.status().is_success() || res.status() == reqwest::StatusCode::NOT_MODIFIED,
+        "HTTP request failed"
+    );
+    Ok(res)
+}
+
 pub fn make_signal_channel() -> (async_channel::Sender<()>, async_channel::Receiver<()>) {
     let default_hook = panic::take_hook();
     panic::set_hook(Box::new(move |info| {